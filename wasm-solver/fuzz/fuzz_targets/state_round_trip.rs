@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wasm_solver::fuzzing::check_state_round_trip;
+
+fuzz_target!(|data: &[u8]| check_state_round_trip(data));
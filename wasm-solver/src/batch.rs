@@ -1,4 +1,20 @@
-use crate::solver::NumberEntry;
+use crate::export::{ByteReader, ByteWriter, DecodeError};
+use crate::solver::{
+    DEFAULT_HYBRID_BB_BUDGET, LastPicks, NumberEntry, OriginalIndex, Precheck, SolverConfig, SortedIndex, combination_id,
+    exact_picks_reach, precheck, solve_quick, tighten_count_window,
+};
+use crate::solver::count_bounds;
+use crate::log::{Level, log};
+use crate::mitm_all::MitmAll;
+use crate::neardup::NearDuplicates;
+use crate::search_tree::{Decision, MAX_TREE_NODES, Prune, SearchTree, Skip};
+use crate::utils::{Fnv1a, OutOfMemory, StableHashMap, now_ms};
+use crate::validate::MAX_INPUT_LEN;
+use std::collections::BTreeSet;
+use std::hash::Hasher;
+use std::ops::{ControlFlow, Deref};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
 
 /// Iterative DFS state for resumable batch searching.
 /// Converts the recursive branch-and-bound into an explicit stack so we can
@@ -7,45 +23,644 @@ use crate::solver::NumberEntry;
 /// One frame of the DFS stack — mirrors what the recursive version holds per call.
 #[derive(Clone)]
 struct Frame {
-    start: usize,      // which sorted index to try next within this frame
+    start: SortedIndex, // which sorted index to try next within this frame
     current_sum: u64,
     path_len: usize,   // how many elements in path when this frame was pushed
+    /// With preferred rows: they are all tried, and `start` walks the others
+    /// from the frame's first row
+    second_pass: bool,
 }
 
 pub struct BatchSearchState {
-    // Problem data (owned, lives for the duration of the search)
-    sorted: Vec<NumberEntry>,
+    // Problem data, shared with the dataset when started on one. The suffix sums
+    // are the search's own: they run to the end of its view, which the target sets
+    sorted: SortedView,
     suffix_sum: Vec<u64>,
     target: u64,
     min_count: usize,
     max_count: usize,
     max_results: usize,
+    /// Row ids by original index; a result never holds two entries with one id
+    row_ids: Option<Vec<u32>>,
+    /// A result's original indices lie within this many of each other
+    max_index_span: Option<usize>,
+    /// Count windows searched in this one pass; empty = just [min_count, max_count]
+    windows: Vec<CountWindow>,
 
     // DFS state
     stack: Vec<Frame>,
-    path: Vec<usize>,       // indices into sorted[]
-    results: Vec<Vec<NumberEntry>>,
+    path: Vec<SortedIndex>,
+    results: Vec<Vec<NumberEntry>>, // each in DFS selection order
+    /// Parallel to `results` when windows are set: bit w = collected for windows[w]
+    window_matches: Vec<u32>,
+    /// Sequence number of `results[0]`: each collected result gets the next one
+    /// when found, and keeps it after streamed results before it are dropped
+    seq_base: u64,
+    /// When false, solutions are only counted and `results` stays empty
+    collect: bool,
+    found: usize,
     nodes_explored: u64,
     finished: bool,
+    count_window: Option<(usize, usize)>,
+    /// Per sorted index: how many collected results contain it
+    usage: Vec<usize>,
+    termination: Option<TerminationReason>,
+
+    // Sparse-tail proof: after this many nodes without a new result, try to prove
+    // that nothing is left to find. u64::MAX disables it.
+    sparse_tail_nodes: u64,
+    nodes_since_result: u64,
+    /// Consecutive batches with a wide frontier and no new result; not exported
+    wide_batches: u32,
+    /// Smoothed results per million nodes over the batches so far; not exported
+    results_rate: Option<f64>,
 
     // For progress estimation: track how much of the top-level iteration we've done.
     // The top-level loop goes from 0..n, so top_level_index / n is a rough progress measure.
     top_level_n: usize,
     top_level_done: usize,
+
+    /// Next init-time preparation step; Done before any search
+    prework: Prework,
+    /// Set when init left prework for later; handed to the next search_batch
+    prework_report: Option<PreworkReport>,
+    /// Node budget of a precheck the first search_batch still has to run
+    precheck_nodes: Option<u64>,
+    /// Set by run_precheck; handed to the next search_batch
+    precheck_report: Option<PrecheckReport>,
+
+    /// None for a plain search; otherwise the phase the next batch runs
+    phase: Option<SearchPhase>,
+    /// Node cap of the quick phase
+    quick_nodes: u64,
+    /// combination_id of each result reported before the DFS reached it: by the
+    /// quick phase, or before set_max_count restarted the walk; with streamed
+    /// results, of every result. At most `max_reported_ids` of them
+    reported_ids: BTreeSet<u64>,
+    max_reported_ids: usize,
+    /// What happens once reported_ids is full
+    dedup_overflow: DedupOverflow,
+    /// Drop results once a batch has returned them (see set_stream_results)
+    stream_results: bool,
+    /// Most results one search_batch returns (see set_max_new_results)
+    max_new_results: Option<usize>,
+    /// Collected results past that cap, at the end of `results`, for the next batch
+    held: usize,
+    /// Set by set_suppress_near_duplicates
+    near_duplicates: Option<NearDuplicates>,
+    /// Set by set_memory_limit
+    memory_limit: Option<u64>,
+    /// Rough heap bytes of `results`, kept as they come and go
+    result_bytes: usize,
+    /// Most bytes memory_estimate has shown at the end of a batch, or with the
+    /// exhaustion proof's bitset beside it; not exported
+    peak_memory: u64,
+
+    /// Paths cut only by max_count, for set_max_count to reopen; recording stops
+    /// (and set_max_count restarts instead) once MAX_FRONTIER_NODES overflow
+    frontier: Vec<CutPath>,
+    frontier_overflowed: bool,
+    /// Frontier paths set_max_count reopened, walked whenever the stack empties
+    reopened: Vec<CutPath>,
+    /// How many were reopened, for progress
+    reopened_total: usize,
+
+    /// Set by set_prefer_small_count; takes effect once the count window is known
+    prefer_small_count: bool,
+    /// Iterative deepening on result size: the first and last count of the whole
+    /// window, searched one count at a time. [min_count, max_count] is then the
+    /// count being searched, always a single one
+    deepening: Option<(usize, usize)>,
+    /// The most rows any solution can have (see count_bounds), worked out the
+    /// first time a frame could be scanned; not exported
+    count_ceiling: Option<usize>,
+    /// Set by set_search_tree; not exported
+    tree: Option<Box<SearchTree>>,
+    /// Set by set_mitm: search by meet-in-the-middle instead of the DFS
+    use_mitm: bool,
+    /// Original indices of the rows set_preferred_rows flagged, ascending
+    preferred_rows: Vec<u32>,
+    /// Their positions in `sorted`, ascending, once it is sorted; None when no
+    /// usable row is preferred
+    preferred: Option<Vec<usize>>,
+    /// Set by set_shard: the part of the search this state walks
+    shard: Option<Shard>,
+    /// The meet-in-the-middle walk, built by the first search_batch
+    mitm: Option<Box<MitmAll>>,
+}
+
+/// Entries sorted by value that fit one search's target: the first `len` of an
+/// array that may be shared with a dataset and the other searches started on it,
+/// none of which copy it. Whoever edits the array clones it first while a view
+/// still holds it (`Rc::make_mut`), so a search never sees an edit.
+#[derive(Clone)]
+pub struct SortedView {
+    entries: Rc<Vec<NumberEntry>>,
+    len: usize,
+}
+
+impl SortedView {
+    /// `entries` must be sorted by value; `len` is at most its length.
+    pub fn new(entries: Rc<Vec<NumberEntry>>, len: usize) -> Self {
+        debug_assert!(len <= entries.len());
+        SortedView { entries, len }
+    }
+
+    fn owned(entries: Vec<NumberEntry>) -> Self {
+        let len = entries.len();
+        SortedView { entries: Rc::new(entries), len }
+    }
+}
+
+impl Deref for SortedView {
+    type Target = [NumberEntry];
+
+    fn deref(&self) -> &[NumberEntry] {
+        &self.entries[..self.len]
+    }
+}
+
+/// What a batch search runs over: entries it sorts itself, or a view of entries
+/// already sorted, which skips the sort.
+pub enum BatchEntries {
+    Unsorted(Vec<NumberEntry>),
+    Sorted(SortedView),
+}
+
+impl From<Vec<NumberEntry>> for BatchEntries {
+    fn from(entries: Vec<NumberEntry>) -> Self {
+        BatchEntries::Unsorted(entries)
+    }
+}
+
+/// Rough heap cost of a collected result of `len` entries.
+fn result_bytes(len: usize) -> usize {
+    size_of::<Vec<NumberEntry>>() + len * size_of::<NumberEntry>()
+}
+
+/// Default cap on remembered result ids, 16MB or so of BTreeSet.
+pub const MAX_REPORTED_IDS: usize = 1 << 20;
+
+/// Rough heap cost of one remembered id, node overhead included.
+const REPORTED_ID_BYTES: usize = 16;
+
+/// What a search does when its set of reported result ids is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DedupOverflow {
+    /// Go on without remembering more ids; a later restart (set_max_count past
+    /// the frontier cap) may then report those results again
+    #[default]
+    AllowDuplicates,
+    /// Stop the search, with termination_reason "dedup_overflow"
+    Pause,
+}
+
+impl DedupOverflow {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "allow_duplicates" => Some(DedupOverflow::AllowDuplicates),
+            "pause" => Some(DedupOverflow::Pause),
+            _ => None,
+        }
+    }
+}
+
+/// A path the DFS stopped extending at max_count, with its sum.
+#[derive(Clone, Debug, PartialEq)]
+struct CutPath {
+    path: Vec<SortedIndex>,
+    sum: u64,
+}
+
+/// How set_max_count went on with the search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxCountChange {
+    /// Not above the current max_count (after data-derived tightening)
+    Unchanged,
+    /// Only the paths the old max_count cut are walked further
+    Resumed { reopened: usize },
+    /// The walk starts over, skipping results already reported: too many paths
+    /// were cut to remember, or the old window admitted no count at all
+    Restarted { frontier_overflowed: bool },
+}
+
+/// How a search split into shards divides the DFS between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShardMode {
+    /// By each path's first row: sorted index i goes to shard i % count. Cheap,
+    /// but the first rows' subtrees differ wildly in size, so shards can too
+    FirstRow,
+    /// By a hash of each path's first two rows, spreading every subtree over
+    /// all shards; a one-row path goes by its row. Every shard walks the first
+    /// level in full
+    FirstTwoRows,
+}
+
+impl ShardMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "first_row" => Some(ShardMode::FirstRow),
+            "first_two_rows" => Some(ShardMode::FirstTwoRows),
+            _ => None,
+        }
+    }
+}
+
+/// One of `count` disjoint parts of a search, which together cover all of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+    pub mode: ShardMode,
+}
+
+impl Shard {
+    /// Whether this shard walks `prefix` (sorted indices) extended by `i`;
+    /// `complete` when that reaches the target, ending the path.
+    fn owns(&self, prefix: &[SortedIndex], i: usize, complete: bool) -> bool {
+        match (self.mode, prefix) {
+            (ShardMode::FirstRow, []) => i % self.count == self.index,
+            (ShardMode::FirstTwoRows, []) => !complete || i % self.count == self.index,
+            (ShardMode::FirstTwoRows, &[first]) => {
+                let mut hash = Fnv1a::new();
+                hash.write_u64(first.get() as u64);
+                hash.write_u64(i as u64);
+                hash.finish() % self.count as u64 == self.index as u64
+            }
+            _ => true,
+        }
+    }
+
+    /// Path lengths below this one are where `owns` decides.
+    fn depth(&self) -> usize {
+        match self.mode {
+            ShardMode::FirstRow => 1,
+            ShardMode::FirstTwoRows => 2,
+        }
+    }
+}
+
+/// How adjust_target went on with the search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetChange {
+    /// The same target as before
+    Unchanged,
+    /// A lower target: the walk starts over at it, and the results collected
+    /// for the old one, none of which reach the new one, are dropped
+    Lowered { invalidated: usize },
+    /// A higher target: rows above the old one were never taken in, so only a
+    /// new search can look for it
+    Refused,
+}
+
+/// Cut paths remembered for set_max_count before it falls back to a restart.
+pub const MAX_FRONTIER_NODES: usize = 1 << 16;
+
+/// Default for `set_sparse_tail_nodes`.
+pub const DEFAULT_SPARSE_TAIL_NODES: u64 = 100_000;
+
+/// Open branches above which a batch counts towards `likely_long_running`.
+const WIDE_FRONTIER: u64 = 256;
+
+/// Consecutive wide batches without a new result before `likely_long_running`.
+const LONG_RUNNING_BATCHES: u32 = 5;
+
+/// Weight of the latest batch in `results_rate`; the rest is the running value.
+const RESULTS_RATE_WEIGHT: f64 = 0.3;
+
+/// Bitset words the exhaustion proof may touch (n * (max residual / 64)); past this
+/// the proof is skipped rather than slowing the search down.
+const PROOF_WORD_BUDGET: u64 = 1 << 22;
+
+/// Rough cost of sorting, per comparison (n log2 n of them), for the init budget.
+const SORT_NS_PER_COMPARISON: f64 = 4.0;
+
+/// Rough cost of one linear pass over the entries, per entry, for the init budget.
+const SCAN_NS_PER_ENTRY: f64 = 2.0;
+
+/// Preparation the search needs before its first node, in the order it runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Prework {
+    Sort,
+    SuffixSums,
+    /// Tighten [min_count, max_count] to what the data allows and seed the stack
+    CountWindow,
+    Done,
+}
+
+impl Prework {
+    fn as_str(self) -> &'static str {
+        match self {
+            Prework::Sort => "sort",
+            Prework::SuffixSums => "suffix_sums",
+            Prework::CountWindow => "count_window",
+            Prework::Done => "done",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Prework::Sort => Prework::SuffixSums,
+            Prework::SuffixSums => Prework::CountWindow,
+            Prework::CountWindow | Prework::Done => Prework::Done,
+        }
+    }
+
+    /// A guess at how long this step takes over n entries; only used to decide
+    /// whether it still fits the init budget.
+    fn estimated_ms(self, n: usize) -> f64 {
+        let n = n as f64;
+        let ns = match self {
+            Prework::Sort => n * (n + 1.0).log2() * SORT_NS_PER_COMPARISON,
+            Prework::SuffixSums | Prework::CountWindow => n * SCAN_NS_PER_ENTRY,
+            Prework::Done => 0.0,
+        };
+        ns / 1e6
+    }
+}
+
+/// Where init-time preparation went when init ran out of budget.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreworkReport {
+    /// Steps left to the first search_batch, in order
+    pub deferred: Vec<&'static str>,
+    /// Spent on prework inside init
+    pub init_ms: f64,
+    /// Spent finishing it afterwards
+    pub deferred_ms: f64,
+}
+
+/// How the feasibility check before the first batch went.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrecheckReport {
+    /// "found", "infeasible" or "unknown" (budget ran out)
+    pub outcome: &'static str,
+    pub node_budget: u64,
+    /// Nodes (or MITM subsets) it took
+    pub nodes: u64,
+}
+
+/// What a search was built to walk, read off its own state (see `info`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchInfo {
+    /// Entries the search holds
+    pub usable_count: usize,
+    /// Sum of their values
+    pub total_value: u128,
+    /// None when there are no entries
+    pub min_value: Option<u64>,
+    pub max_value: Option<u64>,
+    /// "dfs", "quick_then_dfs" (set_quick_phase) or "smallest_count_first"
+    /// (set_prefer_small_count)
+    pub strategy: &'static str,
+    /// A feasibility check is still to run before the first batch
+    pub precheck: bool,
+    /// The count window after tightening; None while that prework is pending or
+    /// when no count fits
+    pub count_window: Option<(usize, usize)>,
+}
+
+/// Most count windows one search can track (one bit each in a result's tag).
+pub const MAX_COUNT_WINDOWS: usize = 32;
+
+/// One of several count windows searched in a single pass (see `set_count_windows`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CountWindow {
+    pub min: usize,
+    pub max: usize,
+    /// Results this window takes before it is full
+    pub max_results: usize,
+    pub found: usize,
+}
+
+/// Phases of a dual-phase search (see `set_quick_phase`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchPhase {
+    /// One capped find-one run, for a first answer fast
+    Quick,
+    /// The full DFS, minus what the quick phase reported
+    Exhaustive,
+}
+
+impl SearchPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SearchPhase::Quick => "quick",
+            SearchPhase::Exhaustive => "exhaustive",
+        }
+    }
+
+    fn to_byte(phase: Option<Self>) -> u8 {
+        match phase {
+            None => 0,
+            Some(SearchPhase::Quick) => 1,
+            Some(SearchPhase::Exhaustive) => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Option<Self>, DecodeError> {
+        match byte {
+            0 => Ok(None),
+            1 => Ok(Some(SearchPhase::Quick)),
+            2 => Ok(Some(SearchPhase::Exhaustive)),
+            _ => Err(DecodeError::Corrupt),
+        }
+    }
+}
+
+/// Where a search is in its run, for a progress display that would otherwise
+/// sit still through the work before and after the walk. The stages come in
+/// this order; a search skips those it has nothing to do in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    /// The prework init deferred (see new_budgeted)
+    Preparing,
+    /// The feasibility check (see set_precheck)
+    Prechecking,
+    /// A meet-in-the-middle walk filling its left map (see set_mitm)
+    BuildingTables,
+    /// The DFS, its quick phase, or the meet-in-the-middle matching
+    Searching,
+    /// The walk is over; results held back may still have to go out
+    Finalizing,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Preparing => "preparing",
+            Stage::Prechecking => "prechecking",
+            Stage::BuildingTables => "building_tables",
+            Stage::Searching => "searching",
+            Stage::Finalizing => "finalizing",
+        }
+    }
+}
+
+/// Why a search finished.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TerminationReason {
+    /// The whole tree was walked
+    Exhausted,
+    /// max_results was reached (or every count window is full)
+    MaxResults,
+    /// The unwalked rest of the tree was proved to contain no solution
+    ProvedExhausted,
+    /// The precheck proved there is no solution at all, before any batch ran
+    ProvedInfeasible,
+    /// The set of reported ids filled up under DedupOverflow::Pause
+    DedupOverflow,
+    /// Collected results reached the memory limit, or the allocator refused more
+    OutOfMemory,
+}
+
+impl TerminationReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TerminationReason::Exhausted => "exhausted",
+            TerminationReason::MaxResults => "max_results",
+            TerminationReason::ProvedExhausted => "proved_exhausted",
+            TerminationReason::ProvedInfeasible => "proved_infeasible",
+            TerminationReason::DedupOverflow => "dedup_overflow",
+            TerminationReason::OutOfMemory => "out_of_memory",
+        }
+    }
+
+    /// Whether the whole space was searched, so every solution was found; false
+    /// when max_results, a full dedup set or memory cut the search short and more
+    /// may exist.
+    pub fn space_exhausted(self) -> bool {
+        !matches!(self, TerminationReason::MaxResults | TerminationReason::DedupOverflow | TerminationReason::OutOfMemory)
+    }
+
+    fn to_byte(reason: Option<Self>) -> u8 {
+        match reason {
+            None => 0,
+            Some(TerminationReason::Exhausted) => 1,
+            Some(TerminationReason::MaxResults) => 2,
+            Some(TerminationReason::ProvedExhausted) => 3,
+            Some(TerminationReason::ProvedInfeasible) => 4,
+            Some(TerminationReason::DedupOverflow) => 5,
+            Some(TerminationReason::OutOfMemory) => 6,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Option<Self>, DecodeError> {
+        match byte {
+            0 => Ok(None),
+            1 => Ok(Some(TerminationReason::Exhausted)),
+            2 => Ok(Some(TerminationReason::MaxResults)),
+            3 => Ok(Some(TerminationReason::ProvedExhausted)),
+            4 => Ok(Some(TerminationReason::ProvedInfeasible)),
+            5 => Ok(Some(TerminationReason::DedupOverflow)),
+            6 => Ok(Some(TerminationReason::OutOfMemory)),
+            _ => Err(DecodeError::Corrupt),
+        }
+    }
+}
+
+/// How many collected combinations one input row appears in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UsageCount {
+    pub index: OriginalIndex,
+    pub value: u64,
+    pub count: usize,
+}
+
+/// Where the DFS currently is: the top frame's path, for diagnostics only.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathSnapshot {
+    /// Original indices of the first elements of the path, in selection order
+    pub indices: Vec<OriginalIndex>,
+    /// Full path length (may exceed `indices.len()`)
+    pub len: usize,
+    /// Sum of the whole path
+    pub sum: u64,
+}
+
+/// Where the DFS is working, as a heartbeat for searches that go a long time
+/// without a result. O(depth) to compute, once per batch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurrentRegion {
+    /// Smallest and largest original index on the path
+    pub min_index: OriginalIndex,
+    pub max_index: OriginalIndex,
+    /// Sum of the path
+    pub partial_sum: u64,
+    /// Path length
+    pub depth: usize,
 }
 
 /// Result of one batch of work.
 pub struct BatchResult {
-    /// New combinations found in this batch
+    /// New combinations found in this batch (always empty when only counting)
     pub new_results: Vec<Vec<NumberEntry>>,
+    /// Sequence number of `new_results[0]`; the rest follow on from it
+    pub first_seq: u64,
     /// Total results found so far
     pub total_found: usize,
     /// Total DFS nodes explored so far
     pub nodes_explored: u64,
     /// Whether the search is completely finished
     pub finished: bool,
-    /// Rough progress estimate 0.0 .. 1.0 (based on top-level iteration)
+    /// Rough progress estimate 0.0 .. 1.0 (based on top-level iteration); 1.0
+    /// only once the space is fully explored
     pub progress: f64,
+    /// [min_count, max_count] actually searched, after data-derived tightening;
+    /// None when no count can work
+    pub count_window: Option<(usize, usize)>,
+    /// Set once finished
+    pub termination_reason: Option<TerminationReason>,
+    /// Stopped at the max_new_results cap rather than the node budget: call again
+    pub paused_on_results: bool,
+    /// Untried siblings summed over the open frames (see `open_branches`)
+    pub open_branches: u64,
+    /// The frontier has stayed wide for several batches without a new result
+    pub likely_long_running: bool,
+    /// Parallel to `new_results` when count windows are set: bit w = counted for window w
+    pub window_matches: Vec<u32>,
+    /// Results per count window so far; empty without windows
+    pub per_window_found: Vec<usize>,
+    /// Set on the first batch when init deferred part of its prework to it
+    pub prework: Option<PreworkReport>,
+    /// Set on the first batch after a precheck
+    pub precheck: Option<PrecheckReport>,
+    /// Phase this batch ran in a dual-phase search; None for a plain one
+    pub phase: Option<SearchPhase>,
+    /// Set when this batch ended its phase and the search moves on to another
+    pub next_phase: Option<SearchPhase>,
+    /// How many entries equal the target, when `new_results` holds the single
+    /// element result standing for all of them and there are several
+    pub identical_value_matches: Option<usize>,
+    /// With set_prefer_small_count, the result size the search has reached
+    /// (every smaller one is done); None once finished
+    pub current_count: Option<usize>,
+    /// The path the search is extending; None once finished or while at the root
+    pub current_region: Option<CurrentRegion>,
+    /// Results per million nodes, smoothed over recent batches while running;
+    /// once finished, the plain average over the whole search
+    pub results_rate: f64,
+    /// retained_bytes after the batch, when results are streamed
+    pub retained_bytes: Option<usize>,
+    /// Once finished, the most bytes the search held (see peak_memory_estimate)
+    pub peak_memory_estimate: Option<u64>,
+    /// Near-duplicates held back so far, when suppressing them
+    pub suppressed_near_duplicates: Option<usize>,
+    /// The stages this batch ran in, in order; the last is the one the search
+    /// is in now
+    pub stages: Vec<Stage>,
+    /// The share of the left map built, while building it; `progress` is the
+    /// searching stage's own
+    pub stage_progress: Option<f64>,
+}
+
+impl BatchResult {
+    /// Finished with every solution found. Progress is 1.0 exactly then (or at
+    /// the end of a quick phase); a search stopped by max_results keeps its
+    /// coverage estimate below 1.0.
+    pub fn space_fully_explored(&self) -> bool {
+        self.finished && self.termination_reason.is_some_and(TerminationReason::space_exhausted)
+    }
 }
 
 impl BatchSearchState {
@@ -56,256 +671,3168 @@ impl BatchSearchState {
         max_count: usize,
         max_results: usize,
     ) -> Self {
-        let mut sorted: Vec<NumberEntry> = entries.to_vec();
-        sorted.sort_unstable_by_key(|e| e.value);
+        Self::new_budgeted(entries.to_vec(), target, min_count, max_count, max_results, None)
+    }
 
+    /// Same as `new`, but init-time preparation (sort, suffix sums, count window
+    /// tightening) stops before a step that would take it past `budget_ms`; the
+    /// rest runs at the start of the first search_batch, which reports it in
+    /// `BatchResult::prework`. The deadline is soft: a step that has started
+    /// runs to the end, and whether one fits is estimated from n. Sorted entries
+    /// start at the suffix sums.
+    pub fn new_budgeted(
+        entries: impl Into<BatchEntries>,
+        target: u64,
+        min_count: usize,
+        max_count: usize,
+        max_results: usize,
+        budget_ms: Option<f64>,
+    ) -> Self {
+        let (sorted, prework) = match entries.into() {
+            BatchEntries::Unsorted(entries) => (SortedView::owned(entries), Prework::Sort),
+            BatchEntries::Sorted(view) => (view, Prework::SuffixSums),
+        };
         let n = sorted.len();
-        let mut suffix_sum = vec![0u64; n + 1];
-        for i in (0..n).rev() {
-            suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+        let mut state = BatchSearchState {
+            top_level_n: n,
+            top_level_done: 0,
+            usage: vec![0; n],
+            termination: None,
+            sparse_tail_nodes: DEFAULT_SPARSE_TAIL_NODES,
+            nodes_since_result: 0,
+            wide_batches: 0,
+            results_rate: None,
+            sorted,
+            suffix_sum: Vec::new(),
+            target,
+            min_count,
+            max_count,
+            max_results,
+            row_ids: None,
+            max_index_span: None,
+            windows: Vec::new(),
+            stack: Vec::new(),
+            path: Vec::new(),
+            results: Vec::new(),
+            window_matches: Vec::new(),
+            seq_base: 0,
+            collect: true,
+            found: 0,
+            nodes_explored: 0,
+            finished: false,
+            count_window: None,
+            prework,
+            prework_report: None,
+            precheck_nodes: None,
+            precheck_report: None,
+            phase: None,
+            quick_nodes: 0,
+            reported_ids: BTreeSet::new(),
+            max_reported_ids: MAX_REPORTED_IDS,
+            dedup_overflow: DedupOverflow::AllowDuplicates,
+            stream_results: false,
+            max_new_results: None,
+            held: 0,
+            near_duplicates: None,
+            memory_limit: None,
+            result_bytes: 0,
+            peak_memory: 0,
+            frontier: Vec::new(),
+            frontier_overflowed: false,
+            reopened: Vec::new(),
+            reopened_total: 0,
+            prefer_small_count: false,
+            deepening: None,
+            count_ceiling: None,
+            tree: None,
+            use_mitm: false,
+            mitm: None,
+            preferred_rows: Vec::new(),
+            preferred: None,
+            shard: None,
+        };
+        let init_ms = state.run_prework(budget_ms);
+        if state.prework != Prework::Done {
+            let deferred = std::iter::successors(Some(state.prework), |&step| Some(step.next()))
+                .take_while(|&step| step != Prework::Done)
+                .map(Prework::as_str)
+                .collect();
+            log!(Level::Info, "batch init: {:.1} ms budget spent, deferring {:?}", init_ms, deferred);
+            state.prework_report = Some(PreworkReport { deferred, init_ms, deferred_ms: 0.0 });
+        }
+        state
+    }
+
+    /// Run prework steps until done or, with a budget, until the next one is not
+    /// expected to fit. Returns the milliseconds spent.
+    fn run_prework(&mut self, budget_ms: Option<f64>) -> f64 {
+        let started = now_ms();
+        while self.prework != Prework::Done {
+            if let Some(budget) = budget_ms {
+                if now_ms() - started + self.prework.estimated_ms(self.sorted.len()) > budget {
+                    break;
+                }
+            }
+            match self.prework {
+                Prework::Sort => {
+                    let len = self.sorted.len;
+                    Rc::make_mut(&mut self.sorted.entries)[..len].sort_unstable_by_key(NumberEntry::value_order);
+                    self.locate_preferred();
+                }
+                Prework::SuffixSums => {
+                    let n = self.sorted.len();
+                    let mut suffix_sum = vec![0u64; n + 1];
+                    for i in (0..n).rev() {
+                        suffix_sum[i] = suffix_sum[i + 1].saturating_add(self.sorted[i].value);
+                    }
+                    self.suffix_sum = suffix_sum;
+                }
+                Prework::CountWindow => self.open_count_window(),
+                Prework::Done => {}
+            }
+            self.prework = self.prework.next();
+        }
+        (now_ms() - started).max(0.0)
+    }
+
+    /// Quick feasibility — tighten the count window to what the data allows,
+    /// and if nothing is left, mark finished immediately. Otherwise seed the
+    /// stack with the initial frame (start at index 0, sum 0, path empty).
+    fn open_count_window(&mut self) {
+        self.count_window = if self.sorted.is_empty() {
+            None
+        } else {
+            tighten_count_window(&self.sorted, &self.suffix_sum, self.target, self.min_count, self.max_count)
+        };
+        match self.count_window {
+            Some((min_count, max_count)) => {
+                self.min_count = min_count;
+                self.max_count = max_count;
+                self.stack.push(Frame {
+                    start: SortedIndex::new(0),
+                    current_sum: 0,
+                    path_len: 0,
+                    second_pass: false,
+                });
+                if self.prefer_small_count {
+                    self.start_deepening();
+                }
+            }
+            None => {
+                self.finished = true;
+                self.termination = Some(TerminationReason::Exhausted);
+            }
+        }
+    }
+
+    /// Run whatever prework init left, without a budget. search_batch does this
+    /// itself; call it before write_to. A no-op once done.
+    pub fn finish_prework(&mut self) {
+        if self.prework != Prework::Done {
+            let spent = self.run_prework(None);
+            if let Some(report) = &mut self.prework_report {
+                report.deferred_ms += spent;
+            }
+        }
+    }
+
+    /// Whether init left prework for the first search_batch.
+    pub fn prework_pending(&self) -> bool {
+        self.prework != Prework::Done
+    }
+
+    /// Same search, but solutions are only counted, never materialized, so memory
+    /// stays O(depth) however many solutions exist. There is no result cap.
+    pub fn new_counting(
+        entries: &[NumberEntry],
+        target: u64,
+        min_count: usize,
+        max_count: usize,
+    ) -> Self {
+        let mut state = Self::new(entries, target, min_count, max_count, usize::MAX);
+        state.collect = false;
+        state
+    }
+
+    /// Treat rows sharing an id (indexed by original index) as one physical row,
+    /// so no result uses two of them. Set before the first search_batch.
+    pub fn set_row_ids(&mut self, row_ids: &[u32]) {
+        self.row_ids = Some(row_ids.to_vec());
+    }
+
+    /// Only collect results whose original indices are at most `span` apart.
+    /// Set before the first search_batch.
+    pub fn set_max_index_span(&mut self, span: usize) {
+        self.max_index_span = Some(span);
+    }
+
+    /// Search several count windows in this one pass. Each `(min, max, max_results)`
+    /// window collects the results whose length it contains, until it holds
+    /// max_results; the search ends once every window is full. The state's own
+    /// [min_count, max_count] must cover them all, as it bounds the pruning. A
+    /// result whose length falls in no open window is not counted at all. Set
+    /// before the first search_batch; at most MAX_COUNT_WINDOWS.
+    pub fn set_count_windows(&mut self, windows: &[(usize, usize, usize)]) {
+        debug_assert!(windows.len() <= MAX_COUNT_WINDOWS);
+        self.windows = windows.iter()
+            .map(|&(min, max, max_results)| CountWindow { min, max, max_results, found: 0 })
+            .collect();
+    }
+
+    /// Windows still open that a result of `len` elements belongs to, as a bitmask.
+    fn open_windows(&self, len: usize) -> u32 {
+        self.windows.iter().enumerate()
+            .filter(|(_, w)| w.min <= len && len <= w.max && w.found < w.max_results)
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// max_results reached, or every count window full.
+    fn results_capped(&self) -> bool {
+        self.found >= self.max_results
+            || (!self.windows.is_empty() && self.windows.iter().all(|w| w.found >= w.max_results))
+    }
+
+    /// Count a solution given as ascending indices into `sorted`, collecting it
+    /// unless only counting. `windows` is its open_windows mask.
+    fn record_solution(&mut self, indices: &[SortedIndex], windows: u32) {
+        if let Some(near) = &mut self.near_duplicates {
+            let values: Vec<u64> = indices.iter().map(|&idx| self.sorted[idx.get()].value).collect();
+            if !near.admit(&values) {
+                return;
+            }
+        }
+        if self.collect && !self.room_for_result(indices.len()) {
+            return;
+        }
+        self.found += 1;
+        self.nodes_since_result = 0;
+        for (w, window) in self.windows.iter_mut().enumerate() {
+            if windows & 1 << w != 0 {
+                window.found += 1;
+            }
+        }
+        if self.collect {
+            let combo: Vec<NumberEntry> = indices.iter()
+                .map(|&idx| self.sorted[idx.get()].clone())
+                .collect();
+            self.result_bytes += result_bytes(combo.len());
+            self.results.push(combo);
+            if !self.windows.is_empty() {
+                self.window_matches.push(windows);
+            }
+            for &idx in indices {
+                self.usage[idx.get()] += 1;
+            }
+        }
+    }
+
+    /// Start with a quick phase: one find-one run (see `solve_quick`) capped at
+    /// `node_cap` nodes, reported as a batch of its own, before the exhaustive
+    /// DFS, which then skips what the quick phase already reported. Set before
+    /// the first search_batch.
+    pub fn set_quick_phase(&mut self, node_cap: u64) {
+        self.phase = Some(SearchPhase::Quick);
+        self.quick_nodes = node_cap;
+    }
+
+    /// Phase 1 of a dual-phase search.
+    fn run_quick_phase(&mut self) {
+        self.phase = Some(SearchPhase::Exhaustive);
+        if self.finished {
+            return;
+        }
+        let not_cancelled = AtomicBool::new(false);
+        let (hit, stats) = solve_quick(&self.sorted, &self.solver_config(&not_cancelled), self.quick_nodes);
+        let nodes = stats.phases.iter().map(|p| p.nodes).sum::<u64>();
+        self.nodes_explored += nodes;
+        log!(
+            Level::Info, "quick phase: {} after {} nodes, switching to exhaustive",
+            if hit.is_some() { "found one" } else { "nothing found" }, nodes,
+        );
+        if let Some(combo) = hit {
+            self.seed_solution(&combo);
+        }
+    }
+
+    /// Check for any solution at all (see solver::precheck) at the start of the
+    /// first batch, before its quick phase or DFS. A proof that there is none
+    /// finishes the search there as `ProvedInfeasible`; a solution found is that
+    /// batch's first result and skipped by the DFS. Either way the batch carries
+    /// the report. Set before the first search_batch.
+    pub fn set_precheck(&mut self, node_budget: u64) {
+        self.precheck_nodes = Some(node_budget);
+    }
+
+    /// Search by meet-in-the-middle instead of the DFS (see mitm_all): every
+    /// subset of either half is one node, so search_batch builds the left half's
+    /// map and then matches the right half a node budget at a time, progress
+    /// being the share of the right half matched. False, changing nothing, with
+    /// more usable entries than MitmAll takes, or with row ids, an index span,
+    /// count windows, a quick phase or smallest-count-first. Set last, before
+    /// the first search_batch. Such a search cannot be exported, and
+    /// set_max_count leaves it as it is.
+    pub fn set_mitm(&mut self) -> bool {
+        if !MitmAll::fits(self.sorted.len())
+            || self.row_ids.is_some()
+            || self.max_index_span.is_some()
+            || !self.windows.is_empty()
+            || self.phase.is_some()
+            || self.prefer_small_count
+        {
+            return false;
+        }
+        self.use_mitm = true;
+        true
+    }
+
+    /// Whether write_to can capture the search: not a meet-in-the-middle one,
+    /// nor one with preferred rows or split into shards.
+    pub fn exportable(&self) -> bool {
+        !self.use_mitm && self.preferred_rows.is_empty() && self.shard.is_none()
+    }
+
+    /// Walk only `shard` of the search: the results of all `shard.count` shards,
+    /// each searched alone over the same input, are disjoint and together make
+    /// up what one unsplit search finds. Progress is this shard's own. Leave
+    /// out the quick phase and precheck, whose answer every shard would report.
+    /// Set before the first search_batch.
+    pub fn set_shard(&mut self, shard: Shard) {
+        debug_assert!(shard.index < shard.count);
+        self.shard = Some(shard);
+    }
+
+    /// Build the meet-in-the-middle walk over the tightened count window, in
+    /// place of the DFS stack.
+    fn start_mitm(&mut self) {
+        self.stack.clear();
+        match MitmAll::new(&self.sorted, self.target, self.min_count, self.max_count, self.memory_limit) {
+            Ok(walk) => self.mitm = Some(Box::new(walk)),
+            Err(err) => {
+                log!(Level::Error, "batch: no room for the meet-in-the-middle map ({} bytes)", err.bytes);
+                self.termination = Some(TerminationReason::OutOfMemory);
+                self.finished = true;
+            }
+        }
+    }
+
+    /// Return results smallest first: search every count of the window in turn,
+    /// each to the end, with the same DFS held to that one count. Results then
+    /// come out ordered by size without sorting, at the cost of walking the
+    /// shallow levels of the tree again for every count. max_results applies to
+    /// all counts together. A quick phase or precheck answer still comes first,
+    /// whatever its size, so leave those off. Set before the first search_batch.
+    pub fn set_prefer_small_count(&mut self) {
+        self.prefer_small_count = true;
+        if self.prework == Prework::Done {
+            self.start_deepening();
+        }
+    }
+
+    /// Try the rows at these original indices first at every level of the DFS:
+    /// each frame walks its preferred children, then the others, so results
+    /// holding them tend to come out sooner. Every combination is still walked
+    /// once, so the set found is the same. The quick phase, the precheck and
+    /// the meet-in-the-middle walk keep their order. Set before the first
+    /// search_batch.
+    pub fn set_preferred_rows(&mut self, rows: &[u32]) {
+        let mut rows = rows.to_vec();
+        rows.sort_unstable();
+        rows.dedup();
+        self.preferred_rows = rows;
+        if self.prework != Prework::Sort {
+            self.locate_preferred();
+        }
+    }
+
+    /// Original indices of the preferred rows, ascending.
+    pub fn preferred_rows(&self) -> &[u32] {
+        &self.preferred_rows
+    }
+
+    fn locate_preferred(&mut self) {
+        if self.preferred_rows.is_empty() {
+            return;
+        }
+        let positions: Vec<usize> = (0..self.sorted.len())
+            .filter(|&i| self.preferred_rows.binary_search(&self.sorted[i].original_index.get()).is_ok())
+            .collect();
+        self.preferred = (!positions.is_empty()).then_some(positions);
+    }
+
+    /// The first row from `i` the frame's current pass walks: the next preferred
+    /// one in the first pass, the next other one in the second; n when none.
+    fn next_in_pass(&self, i: usize, preferred_pass: bool) -> usize {
+        let n = self.sorted.len();
+        let Some(preferred) = &self.preferred else { return i };
+        if preferred_pass {
+            return preferred.get(preferred.partition_point(|&p| p < i)).map_or(n, |&p| p.min(n));
+        }
+        (i..n).find(|k| preferred.binary_search(k).is_err()).unwrap_or(n)
+    }
+
+    /// The first sorted index whose subtree `frame` may still have to walk.
+    fn frame_from(&self, frame: &Frame) -> usize {
+        if self.preferred.is_none() || frame.second_pass {
+            return frame.start.get();
+        }
+        match frame.path_len {
+            0 => 0,
+            len => self.path[len - 1].next(),
+        }
+    }
+
+    fn start_deepening(&mut self) {
+        if self.count_window.is_some() && self.deepening.is_none() {
+            self.deepening = Some((self.min_count, self.max_count));
+            self.max_count = self.min_count;
+        }
+    }
+
+    /// Whether iterative deepening has counts left after the current one.
+    fn deepening_left(&self) -> bool {
+        self.deepening.is_some_and(|(_, last)| self.max_count < last)
+    }
+
+    /// Once the current count is done, start over from the root at the next one.
+    fn deepen_next(&mut self) -> bool {
+        if !self.deepening_left() {
+            return false;
+        }
+        self.min_count += 1;
+        self.max_count = self.min_count;
+        if let Some(tree) = &mut self.tree {
+            tree.stop();
+        }
+        log!(Level::Info, "batch: all results of {} elements found, searching {}", self.min_count - 1, self.min_count);
+        self.path.clear();
+        self.stack.push(Frame { start: SortedIndex::new(0), current_sum: 0, path_len: 0, second_pass: false });
+        self.top_level_done = 0;
+        true
+    }
+
+    fn run_precheck(&mut self, node_budget: u64) {
+        if self.finished {
+            return;
+        }
+        let not_cancelled = AtomicBool::new(false);
+        let (outcome, stats) = precheck(&self.sorted, &self.solver_config(&not_cancelled), node_budget);
+        let nodes = stats.phases.iter().map(|p| p.nodes).sum::<u64>();
+        self.nodes_explored += nodes;
+        log!(Level::Info, "precheck: {} after {} nodes", outcome.as_str(), nodes);
+        self.precheck_report = Some(PrecheckReport { outcome: outcome.as_str(), node_budget, nodes });
+        match outcome {
+            Precheck::Found(combo) => self.seed_solution(&combo),
+            Precheck::Infeasible => {
+                self.stack.clear();
+                self.finished = true;
+                self.termination = Some(TerminationReason::ProvedInfeasible);
+            }
+            Precheck::Unknown => {}
+        }
+    }
+
+    /// The search's target and count window as a find-one config.
+    fn solver_config<'a>(&'a self, cancelled: &'a AtomicBool) -> SolverConfig<'a> {
+        let (min_count, max_count) = self.deepening.unwrap_or((self.min_count, self.max_count));
+        SolverConfig {
+            target: self.target,
+            min_count,
+            max_count,
+            controller: cancelled,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: self.row_ids.as_deref(),
+            max_index_span: self.max_index_span,
+            memory_limit: self.memory_limit,
         }
+    }
+
+    /// Report a solution found outside the DFS, which then skips it. Ignored when
+    /// already reported or when it fits no count window with room left.
+    fn seed_solution(&mut self, combo: &[NumberEntry]) {
+        // A row equal to the target stands for all of them, as the DFS's first one
+        let representative;
+        let combo = match combo {
+            [single] if single.value == self.target => {
+                representative = [self.sorted[self.sorted.partition_point(|e| e.value < self.target)].clone()];
+                &representative[..]
+            }
+            _ => combo,
+        };
+        let windows = self.open_windows(combo.len());
+        if !self.windows.is_empty() && windows == 0 {
+            return;
+        }
+        if !self.remember_reported(combination_id(combo)) {
+            return;
+        }
+        let positions = self.sorted_positions();
+        let mut indices: Vec<SortedIndex> = combo.iter()
+            .filter_map(|e| positions.get(&e.original_index).copied())
+            .collect();
+        indices.sort_unstable();
+        self.record_solution(&indices, windows);
+        if self.results_capped() {
+            self.stack.clear();
+        }
+    }
+
+    /// Each row's position in `sorted`, for mapping results back to it in one
+    /// lookup per entry rather than a scan.
+    fn sorted_positions(&self) -> StableHashMap<OriginalIndex, SortedIndex> {
+        self.sorted.iter().enumerate().map(|(i, e)| (e.original_index, SortedIndex::new(i))).collect()
+    }
+
+    /// Add a result's id to reported_ids; false when it was there already. Once
+    /// the set is full, the id is not kept and the DedupOverflow policy applies.
+    fn remember_reported(&mut self, id: u64) -> bool {
+        if self.reported_ids.len() < self.max_reported_ids || self.reported_ids.contains(&id) {
+            return self.reported_ids.insert(id);
+        }
+        if self.dedup_overflow == DedupOverflow::Pause && !self.paused() {
+            log!(Level::Info, "batch: {} result ids remembered, pausing", self.reported_ids.len());
+            self.termination = Some(TerminationReason::DedupOverflow);
+            self.stack.clear();
+            self.reopened.clear();
+            self.finished = true;
+        }
+        true
+    }
+
+    /// Stopped by a full dedup set under DedupOverflow::Pause, or out of memory.
+    fn paused(&self) -> bool {
+        matches!(self.termination, Some(TerminationReason::DedupOverflow | TerminationReason::OutOfMemory))
+    }
+
+    /// Whether one more collected result of `len` entries fits memory_limit and
+    /// the allocator. If not, the search stops there, as out of memory.
+    fn room_for_result(&mut self, len: usize) -> bool {
+        let bytes = (self.result_bytes + result_bytes(len)) as u64;
+        if OutOfMemory::check(bytes, self.memory_limit).is_ok() && self.results.try_reserve(1).is_ok() {
+            return true;
+        }
+        log!(Level::Error, "batch: out of memory at {} results ({} bytes)", self.results.len(), bytes);
+        self.termination = Some(TerminationReason::OutOfMemory);
+        self.stack.clear();
+        self.reopened.clear();
+        self.finished = true;
+        false
+    }
+
+    /// Stop collecting once results would take more than `bytes` (see
+    /// SolverConfig::memory_limit, which the quick phase and precheck get too):
+    /// the search ends with TerminationReason::OutOfMemory, keeping what it has.
+    /// Streamed results count only until returned. Not kept in exported state.
+    pub fn set_memory_limit(&mut self, bytes: u64) {
+        self.memory_limit = Some(bytes);
+    }
+
+    /// Keep each batch's results only until search_batch returns them: they are
+    /// then dropped, leaving their ids in reported_ids so no later phase or
+    /// restart reports them again, and all_results() holds none. Memory for
+    /// results then stays at the ids, 16 bytes or so each, up to the dedup cap.
+    pub fn set_stream_results(&mut self) {
+        self.stream_results = true;
+    }
+
+    /// Return at most `max` results from each search_batch (0 = no cap): the
+    /// batch stops as soon as it has that many, whatever is left of its budget,
+    /// and reports `paused_on_results`. A frame finished by one scan can yield a
+    /// few more; those are held and come first from the next batch, which only
+    /// searches on once they are out. Held results keep the search unfinished.
+    pub fn set_max_new_results(&mut self, max: usize) {
+        self.max_new_results = Some(max).filter(|&max| max > 0);
+    }
+
+    /// Collected results waiting past the max_new_results cap for a later batch.
+    pub fn held_results(&self) -> usize {
+        self.held
+    }
+
+    /// Whether this batch has as many results as max_new_results allows;
+    /// `since` is where its results start in `results`.
+    fn batch_full(&self, since: usize) -> bool {
+        self.max_new_results.is_some_and(|max| self.results.len() - since >= max)
+    }
+
+    /// Record the tree of the first `max_nodes` nodes, root included, for
+    /// search_tree (see search_tree). False, changing nothing, when `max_nodes`
+    /// is 0 or over MAX_TREE_NODES, or once the search has explored a node.
+    pub fn set_search_tree(&mut self, max_nodes: usize) -> bool {
+        if !(1..=MAX_TREE_NODES).contains(&max_nodes) || self.nodes_explored > 0 {
+            return false;
+        }
+        self.tree = Some(Box::new(SearchTree::new(max_nodes)));
+        true
+    }
+
+    pub fn search_tree(&self) -> Option<&SearchTree> {
+        self.tree.as_deref()
+    }
+
+    /// The entries the search runs over, by value; search tree nodes index into it.
+    pub fn sorted_entries(&self) -> &[NumberEntry] {
+        &self.sorted
+    }
+
+    /// Record a decision of the frame `depth` rows deep, when recording the tree.
+    #[inline]
+    fn explain(&mut self, depth: usize, index: Option<usize>, path_sum: u64, decision: Decision) {
+        if let Some(tree) = &mut self.tree {
+            tree.record(depth, index, path_sum, decision);
+        }
+    }
+
+    /// Hold back results whose values are within `max_distance` of a recently
+    /// emitted one (see neardup). They are counted in suppressed_near_duplicates,
+    /// not in found, and take no room under max_results. Set before the first
+    /// search_batch.
+    pub fn set_suppress_near_duplicates(&mut self, max_distance: usize) {
+        self.near_duplicates = Some(NearDuplicates::new(max_distance));
+    }
+
+    /// Cap reported_ids at `max_ids` and pick what happens past it.
+    pub fn set_dedup_limit(&mut self, max_ids: usize, overflow: DedupOverflow) {
+        self.max_reported_ids = max_ids;
+        self.dedup_overflow = overflow;
+    }
+
+    /// Rough heap bytes held for results: combination bodies not yet dropped,
+    /// remembered ids and the paths kept for set_max_count. Grows with every
+    /// result unless they are streamed.
+    pub fn retained_bytes(&self) -> usize {
+        let combos = self.result_bytes;
+        let paths: usize = self.frontier.iter().chain(&self.reopened)
+            .map(|cut| size_of::<CutPath>() + cut.path.len() * size_of::<SortedIndex>())
+            .sum();
+        combos + paths + self.reported_ids.len() * REPORTED_ID_BYTES + self.window_matches.len() * size_of::<u32>()
+    }
+
+    /// Rough heap bytes the search holds now: retained_bytes, the DFS stack at
+    /// its deepest and the meet-in-the-middle map, counted where they grow.
+    /// The sorted entries, possibly shared with a dataset, are left out.
+    pub fn memory_estimate(&self) -> u64 {
+        let stack = self.stack.capacity() * size_of::<Frame>();
+        (self.retained_bytes() + stack) as u64 + self.mitm.as_ref().map_or(0, |walk| walk.table_bytes())
+    }
+
+    /// The most memory_estimate has shown so far: at the end of each batch, and
+    /// with an exhaustion proof's bitset beside it. An imported search starts
+    /// counting again.
+    pub fn peak_memory_estimate(&self) -> u64 {
+        self.peak_memory.max(self.memory_estimate())
+    }
+
+    /// Whether the current path is a solution already reported (see `reported_ids`).
+    fn already_reported(&self) -> bool {
+        if self.reported_ids.is_empty() {
+            return false;
+        }
+        let combo: Vec<NumberEntry> = self.path.iter().map(|&idx| self.sorted[idx.get()].clone()).collect();
+        self.reported_ids.contains(&combination_id(&combo))
+    }
+
+    /// Remember the path just cut at max_count with sorted[i] last, unless no
+    /// longer path through it could reach the target anyway.
+    fn record_cut(&mut self, i: usize, sum: u64) {
+        // Deepening cuts every path at the current count and raises it itself
+        if self.frontier_overflowed || self.deepening.is_some() {
+            return;
+        }
+        let residual = self.target - sum;
+        if i + 1 >= self.sorted.len() || self.sorted[i + 1].value > residual || self.suffix_sum[i + 1] < residual {
+            return;
+        }
+        if self.frontier.len() >= MAX_FRONTIER_NODES {
+            log!(Level::Info, "batch: over {} paths cut at max_count, raising it will restart", MAX_FRONTIER_NODES);
+            self.frontier_overflowed = true;
+            self.frontier = Vec::new();
+            return;
+        }
+        self.frontier.push(CutPath { path: self.path.clone(), sum });
+    }
+
+    /// Raise max_count on a paused or finished search. Every path the old limit
+    /// cut is reopened and walked further once the stack empties, so the work
+    /// below the old limit is not repeated and results already found are kept
+    /// and not reported again; the union equals one search at the new limit.
+    /// When more than MAX_FRONTIER_NODES paths were cut, the walk restarts from
+    /// the root instead, still skipping what was reported (or, when only
+    /// counting, counting from zero). `max_count` is tightened to what the data
+    /// allows first; a search already capped by max_results stays finished.
+    pub fn set_max_count(&mut self, max_count: usize) -> MaxCountChange {
+        if self.use_mitm {
+            return MaxCountChange::Unchanged;
+        }
+        self.finish_prework();
+        let Some((_, data_max)) = count_bounds(&self.sorted, &self.suffix_sum, self.target) else {
+            return MaxCountChange::Unchanged;
+        };
+        let max_count = max_count.min(data_max);
+        if let Some((first, last)) = self.deepening {
+            if max_count <= last {
+                return MaxCountChange::Unchanged;
+            }
+            // The larger counts are simply searched after the ones already done
+            self.deepening = Some((first, max_count));
+            self.count_window = Some((first, max_count));
+            log!(Level::Info, "batch: max_count raised to {}, deepening on", max_count);
+            if self.finished && !self.results_capped() {
+                self.finished = false;
+                self.termination = None;
+            }
+            return MaxCountChange::Resumed { reopened: 0 };
+        }
+        if max_count <= self.max_count {
+            return MaxCountChange::Unchanged;
+        }
+        self.max_count = max_count;
+
+        // No window at all before: nothing ran, so nothing was cut either
+        // Nor was anything cut when the precheck ruled the old window out
+        let change = if self.frontier_overflowed
+            || self.count_window.is_none()
+            || self.termination == Some(TerminationReason::ProvedInfeasible)
+        {
+            let frontier_overflowed = self.frontier_overflowed;
+            self.count_window = tighten_count_window(&self.sorted, &self.suffix_sum, self.target, self.min_count, max_count);
+            self.restart();
+            MaxCountChange::Restarted { frontier_overflowed }
+        } else {
+            let mut cut = std::mem::take(&mut self.frontier);
+            // Popped from the back: earliest cut first
+            cut.reverse();
+            let reopened = cut.len();
+            self.reopened.splice(0..0, cut);
+            self.reopened_total = self.reopened.len();
+            self.count_window = Some((self.min_count, max_count));
+            MaxCountChange::Resumed { reopened }
+        };
+        log!(Level::Info, "batch: max_count raised to {}, {:?}", max_count, change);
+        if !self.results_capped() && (!self.stack.is_empty() || !self.reopened.is_empty()) {
+            self.finished = false;
+            self.termination = None;
+        }
+        change
+    }
+
+    /// Search for a lower target from here on, without redoing init: the
+    /// sorted entries and settings stay, the walk starts over from the root.
+    /// The stack cannot simply go on, as the subtrees already walked may hold
+    /// combinations reaching the new target that the old one never recorded.
+    /// Every collected result summed to the old target, so all are dropped.
+    /// `min_count` is the floor asked for at init: a lower target can need
+    /// fewer rows than the window tightened for the old one allowed.
+    pub fn adjust_target(&mut self, target: u64, min_count: usize) -> TargetChange {
+        self.finish_prework();
+        if target == self.target {
+            return TargetChange::Unchanged;
+        }
+        if target > self.target {
+            return TargetChange::Refused;
+        }
+        let invalidated = self.found;
+        self.target = target;
+        // Rows past the new target are out of reach now; the suffix sums follow the view
+        self.sorted.len = self.sorted.partition_point(|e| e.value <= target);
+        let n = self.sorted.len();
+        self.suffix_sum.truncate(n + 1);
+        self.suffix_sum[n] = 0;
+        for i in (0..n).rev() {
+            self.suffix_sum[i] = self.suffix_sum[i + 1].saturating_add(self.sorted[i].value);
+        }
+        self.top_level_n = n;
+        self.usage = vec![0; n];
+        if let Some(preferred) = &mut self.preferred {
+            preferred.retain(|&i| i < n);
+        }
+        // Their sequence numbers aren't given out again
+        self.seq_base += self.results.len() as u64;
+        self.results.clear();
+        self.window_matches.clear();
+        self.found = 0;
+        for window in &mut self.windows {
+            window.found = 0;
+        }
+        self.held = 0;
+        self.result_bytes = 0;
+        self.reported_ids.clear();
+        if let Some(near) = &mut self.near_duplicates {
+            near.forget_admitted();
+        }
+        if let Some(tree) = &mut self.tree {
+            tree.stop();
+        }
+        self.mitm = None;
+        self.count_ceiling = None;
+        self.max_count = self.deepening.take().map_or(self.max_count, |(_, last)| last);
+        self.min_count = min_count;
+        self.stack.clear();
+        self.path.clear();
+        self.top_level_done = 0;
+        self.frontier = Vec::new();
+        self.frontier_overflowed = false;
+        self.reopened = Vec::new();
+        self.reopened_total = 0;
+        self.nodes_since_result = 0;
+        self.wide_batches = 0;
+        self.finished = false;
+        self.termination = None;
+        self.open_count_window();
+        log!(Level::Info, "batch: target lowered to {}, {} results invalidated, walking again", target, invalidated);
+        TargetChange::Lowered { invalidated }
+    }
+
+    /// Walk again from the root, under the current count window.
+    fn restart(&mut self) {
+        if self.collect {
+            let ids: Vec<u64> = self.results.iter().map(|combo| combination_id(combo)).collect();
+            for id in ids {
+                self.remember_reported(id);
+            }
+        } else {
+            self.found = 0;
+            for window in &mut self.windows {
+                window.found = 0;
+            }
+        }
+        if let Some((min_count, _)) = self.count_window {
+            self.min_count = min_count;
+        }
+        self.stack.clear();
+        self.path.clear();
+        if self.count_window.is_some() {
+            self.stack.push(Frame { start: SortedIndex::new(0), current_sum: 0, path_len: 0, second_pass: false });
+        }
+        self.top_level_done = 0;
+        self.frontier = Vec::new();
+        self.frontier_overflowed = false;
+        self.reopened = Vec::new();
+        self.reopened_total = 0;
+        self.nodes_since_result = 0;
+    }
+
+    /// Put the next reopened path on the empty stack; false when none is left.
+    fn reopen_next(&mut self) -> bool {
+        let Some(cut) = self.reopened.pop() else { return false };
+        if let Some(tree) = &mut self.tree {
+            tree.stop();
+        }
+        let start = SortedIndex::new(cut.path.last().map_or(0, |&i| i.next()));
+        self.stack.push(Frame { start, current_sum: cut.sum, path_len: cut.path.len(), second_pass: false });
+        self.path = cut.path;
+        true
+    }
+
+    /// Whether sorted[i] shares a row id with one of the first `path_len` path elements.
+    fn row_taken(&self, i: usize, path_len: usize) -> bool {
+        let Some(ids) = &self.row_ids else { return false };
+        let id = ids[self.sorted[i].original_index.as_usize()];
+        self.path[..path_len].iter().any(|&p| ids[self.sorted[p.get()].original_index.as_usize()] == id)
+    }
+
+    /// Whether sorted[i] lies too far, by original index, from one of the first
+    /// `path_len` path elements.
+    fn outside_span(&self, i: usize, path_len: usize) -> bool {
+        let Some(span) = self.max_index_span else { return false };
+        let index = self.sorted[i].original_index.get();
+        self.path[..path_len].iter().any(|&p| self.sorted[p.get()].original_index.get().abs_diff(index) as usize > span)
+    }
+
+    /// Nodes without a new result before the search tries to prove the rest of
+    /// the tree empty; None disables the proof.
+    pub fn set_sparse_tail_nodes(&mut self, nodes: Option<u64>) {
+        self.sparse_tail_nodes = nodes.unwrap_or(u64::MAX);
+    }
+
+    /// Run up to `node_budget` DFS nodes. Returns what was found in this batch.
+    pub fn search_batch(&mut self, node_budget: u64) -> BatchResult {
+        let mut stages = vec![self.stage()];
+        self.finish_prework();
+        self.enter_stage(&mut stages);
+        let was_finished = self.finished;
+        // Results held back by max_new_results go out first
+        let prev_found = self.results.len() - self.held;
+        let prev_found_total = self.found;
+        let prev_nodes = self.nodes_explored;
+        let mut budget = node_budget;
+
+        if let Some(nodes) = self.precheck_nodes.take() {
+            self.run_precheck(nodes);
+        }
+        self.enter_stage(&mut stages);
+        let phase = self.phase;
+        if phase == Some(SearchPhase::Quick) {
+            self.run_quick_phase();
+            budget = 0;
+        }
+        if self.use_mitm && self.mitm.is_none() && !self.finished && !self.results_capped() {
+            self.start_mitm();
+        }
+        let full = self.batch_full(prev_found);
+        if let Some(mut walk) = self.mitm.take_if(|_| !full) {
+            let steps = walk.run(budget, |indices| {
+                // The precheck's witness was reported already
+                self.path.clear();
+                self.path.extend(indices.iter().map(|&i| SortedIndex::new(i)));
+                if !self.already_reported() {
+                    let path = std::mem::take(&mut self.path);
+                    self.record_solution(&path, 0);
+                    self.path = path;
+                }
+                if self.results_capped() || self.paused() || self.batch_full(prev_found) {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            });
+            self.path.clear();
+            budget -= steps;
+            self.nodes_explored += steps;
+            self.mitm = Some(walk);
+            self.enter_stage(&mut stages);
+        }
+
+        while budget > 0
+            && !self.results_capped()
+            && !self.paused()
+            && !self.batch_full(prev_found)
+            && (!self.stack.is_empty() || self.reopen_next() || self.deepen_next())
+        {
+            if self.nodes_since_result >= self.sparse_tail_nodes {
+                self.nodes_since_result = 0;
+                if self.proved_exhausted() {
+                    self.stack.clear();
+                    self.reopened.clear();
+                    // Only this count is done; the proof says nothing about the next
+                    if self.deepening_left() {
+                        continue;
+                    }
+                    self.termination = Some(TerminationReason::ProvedExhausted);
+                    break;
+                }
+            }
+
+            budget -= 1;
+            self.nodes_explored += 1;
+            self.nodes_since_result += 1;
+
+            let frame = self.stack.last_mut().unwrap();
+            let current_sum = frame.current_sum;
+            let path_len = frame.path_len;
+            let start = frame.start.get();
+            let mut preferred_pass = self.preferred.is_some() && !frame.second_pass;
+            let n = self.sorted.len();
+
+            // Trim path back to this frame's depth (backtrack)
+            self.path.truncate(path_len);
+
+            // Find next valid child to explore from `start`
+            let remaining_budget_val = self.target - current_sum;
+            let remaining_needed = self.min_count.saturating_sub(path_len);
+
+            // One or two rows short of max_count: the whole frame in one scan,
+            // charged as the nodes it took up to what is left of the budget, so
+            // a batch still reports exactly its budget until it finishes
+            if self.max_count - path_len <= 2
+                && self.shard.is_none_or(|shard| path_len >= shard.depth())
+                && self.last_picks_scannable()
+            {
+                let found = self.found;
+                let steps = self.scan_last_picks(start, path_len, remaining_budget_val);
+                self.explain(path_len, None, current_sum, Decision::Scan { steps, found: self.found - found });
+                let steps = steps.min(budget);
+                budget -= steps;
+                self.nodes_explored += steps;
+                if !self.finished {
+                    self.pop_frame();
+                }
+                continue;
+            }
+
+            let mut found_child = false;
+            let mut i = start;
+
+            loop {
+                i = self.next_in_pass(i, preferred_pass);
+                if i >= n {
+                    if preferred_pass {
+                        // The other rows, from the frame's first one
+                        preferred_pass = false;
+                        i = if path_len == 0 { 0 } else { self.path[path_len - 1].next() };
+                        continue;
+                    }
+                    break;
+                }
+                let value = self.sorted[i].value;
+
+                let prune = if value > remaining_budget_val {
+                    // Element too large
+                    Some(Prune::TooLarge { value, remaining: remaining_budget_val })
+                } else if self.suffix_sum[i] < remaining_budget_val {
+                    // Suffix sum insufficient
+                    Some(Prune::SuffixShort { suffix_sum: self.suffix_sum[i], remaining: remaining_budget_val })
+                } else if (n - i) < remaining_needed {
+                    // Not enough elements left for min_count
+                    Some(Prune::TooFewLeft { left: n - i, needed: remaining_needed })
+                } else if path_len == 0 && value == self.target && i > 0 && self.sorted[i - 1].value == value {
+                    // One more row equal to the target: reported once, through the
+                    // first. Only alone: after zeros each copy pads a result of its own
+                    Some(Prune::DuplicateTarget)
+                } else {
+                    None
+                };
+                if let Some(prune) = prune {
+                    self.explain(path_len, Some(i), current_sum, Decision::Prune(prune));
+                    // Every later row is cut too, but only in this pass: the
+                    // preferred rows are a subset, and earlier other rows remain
+                    if preferred_pass {
+                        preferred_pass = false;
+                        i = if path_len == 0 { 0 } else { self.path[path_len - 1].next() };
+                        continue;
+                    }
+                    break;
+                }
+                // A copy of a row already on the path, one too far from it, or
+                // one another shard walks; later siblings may still fit
+                let skip = if self.row_taken(i, path_len) {
+                    Some(Skip::RowTaken)
+                } else if self.outside_span(i, path_len) {
+                    Some(Skip::OutsideSpan)
+                } else if self.shard.is_some_and(|shard| !shard.owns(&self.path[..path_len], i, current_sum + value == self.target)) {
+                    Some(Skip::OtherShard)
+                } else {
+                    None
+                };
+                if let Some(skip) = skip {
+                    self.explain(path_len, Some(i), current_sum, Decision::Skip(skip));
+                    i += 1;
+                    continue;
+                }
+
+                // This child is worth exploring. Advance frame.start past it
+                // so when we pop back, we try the next sibling.
+                let second_pass = self.preferred.is_some() && !preferred_pass;
+                let frame = self.stack.last_mut().unwrap();
+                frame.start = SortedIndex::new(i + 1);
+                frame.second_pass = second_pass;
+
+                let new_sum = current_sum + value;
+                let new_path_len = path_len + 1;
+
+                // Push this element onto path
+                self.path.truncate(path_len);
+                self.path.push(SortedIndex::new(i));
+
+                // Track top-level progress; the preferred rows' pass jumps ahead
+                if path_len == 0 && !preferred_pass {
+                    self.top_level_done = i + 1;
+                }
+
+                // Check if this is a solution
+                if new_sum == self.target && new_path_len >= self.min_count {
+                    let windows = self.open_windows(new_path_len);
+                    // No open window wants this length, or it was returned before
+                    if (!self.windows.is_empty() && windows == 0) || self.already_reported() {
+                        self.explain(path_len, Some(i), current_sum, Decision::Skip(Skip::NotWanted));
+                        i += 1;
+                        continue;
+                    }
+                    let path = std::mem::take(&mut self.path);
+                    self.record_solution(&path, windows);
+                    self.path = path;
+                    self.explain(path_len, Some(i), current_sum, Decision::Solution);
+                    if self.results_capped() || self.paused() {
+                        // Drain the stack — we're done
+                        self.stack.clear();
+                        self.reopened.clear();
+                        self.finished = true;
+                        break;
+                    }
+                    if self.batch_full(prev_found) {
+                        // The next batch picks up at the next sibling
+                        found_child = true;
+                        break;
+                    }
+                    // Nothing below a path at the target makes another: the
+                    // order is ascending, so zeros sort first and every row
+                    // after this one adds to the sum. On to the next sibling
+                    // (frame.start already advanced)
+                    i += 1;
+                    continue;
+                }
+
+                // If we can go deeper, push a new frame for the child, unless
+                // the count is pinned and no pick of exactly the rest can hit the
+                // target; that child is then cut as at max_count, so raising it
+                // later still walks what lies below
+                if new_path_len < self.max_count
+                    && (self.min_count != self.max_count
+                        || exact_picks_reach(&self.suffix_sum, i + 1, self.max_count - new_path_len, self.target - new_sum, true))
+                {
+                    self.stack.push(Frame {
+                        start: SortedIndex::new(i + 1),
+                        current_sum: new_sum,
+                        path_len: new_path_len,
+                        second_pass: false,
+                    });
+                    self.explain(path_len, Some(i), current_sum, Decision::Branch);
+                } else {
+                    self.record_cut(i, new_sum);
+                    let at_max_count = new_path_len >= self.max_count;
+                    self.explain(path_len, Some(i), current_sum, Decision::Cut { at_max_count });
+                }
+
+                found_child = true;
+                break;
+            }
+
+            if !found_child || i >= n || self.sorted[i].value > remaining_budget_val {
+                // No more children in this frame — pop it
+                // But only if we didn't just push a new child frame
+                if !found_child {
+                    self.pop_frame();
+                }
+            }
+        }
+
+        self.peak_memory = self.peak_memory_estimate();
+        let available = self.results.len() - prev_found;
+        let returned = self.max_new_results.map_or(available, |max| available.min(max));
+        self.held = available - returned;
+        let end = prev_found + returned;
+        let first_seq = self.seq_base + prev_found as u64;
+        let (new_results, window_matches) = if self.stream_results {
+            // Nothing returned is kept, so these start `results`
+            let new_results: Vec<Vec<NumberEntry>> = self.results.drain(prev_found..end).collect();
+            self.seq_base += returned as u64;
+            self.result_bytes -= new_results.iter().map(|combo| result_bytes(combo.len())).sum::<usize>();
+            for combo in &new_results {
+                self.remember_reported(combination_id(combo));
+            }
+            let matched = self.window_matches.len();
+            (new_results, self.window_matches.drain(prev_found.min(matched)..end.min(matched)).collect())
+        } else {
+            (self.results[prev_found..end].to_vec(), self.window_matches.get(prev_found..end).unwrap_or_default().to_vec())
+        };
+
+        if (self.stack.is_empty()
+            && self.reopened.is_empty()
+            && !self.deepening_left()
+            && self.mitm.as_ref().is_none_or(|walk| walk.finished()))
+            || self.results_capped()
+            || self.paused()
+        {
+            self.finished = true;
+            if self.termination.is_none() {
+                self.termination = Some(if self.results_capped() {
+                    TerminationReason::MaxResults
+                } else {
+                    TerminationReason::Exhausted
+                });
+            }
+        }
+
+        let mut progress = if self.top_level_n > 0 {
+            (self.top_level_done as f64) / (self.top_level_n as f64)
+        } else {
+            1.0
+        };
+        if self.reopened_total > 0 {
+            let walked = self.reopened_total - self.reopened.len();
+            progress = progress.min(walked as f64 / self.reopened_total as f64);
+        }
+        if let Some(walk) = &self.mitm {
+            progress = walk.progress();
+        }
+        if let Some((first, last)) = self.deepening {
+            // Counts done, plus this one's share
+            progress = ((self.min_count - first) as f64 + progress) / (last - first + 1) as f64;
+        }
+
+        let open_branches = self.open_branches();
+        if open_branches > WIDE_FRONTIER && self.found == prev_found_total {
+            self.wide_batches = self.wide_batches.saturating_add(1);
+        } else {
+            self.wide_batches = 0;
+        }
+
+        self.enter_stage(&mut stages);
+        log!(
+            Level::Debug, "batch: {} nodes, {} found, {} open frames",
+            self.nodes_explored, self.found, self.stack.len(),
+        );
+        if self.finished && !was_finished {
+            let reason = self.termination.map_or("none", TerminationReason::as_str);
+            log!(Level::Info, "batch: finished ({}) after {} nodes, {} found", reason, self.nodes_explored, self.found);
+        }
+
+        let results_rate = self.update_results_rate(self.found - prev_found_total, self.nodes_explored - prev_nodes);
+
+        let identical_value_matches = self.identical_value_matches(&new_results);
+        let quick = phase == Some(SearchPhase::Quick);
+        // A search stopped by max_results reports how far it got, not 1.0
+        // Held results still have to go out
+        let done = self.finished && self.held == 0;
+        let explored_all = done && self.termination.is_some_and(TerminationReason::space_exhausted);
+        // Per phase: the quick phase is done after its one batch
+        let progress = if explored_all || (quick && !done) { 1.0 } else { progress.min(0.999) };
+        let stage_progress = (stages.last() == Some(&Stage::BuildingTables))
+            .then(|| self.mitm.as_ref().map_or(0.0, |walk| walk.build_progress()));
+        BatchResult {
+            new_results,
+            first_seq,
+            total_found: self.found,
+            nodes_explored: self.nodes_explored,
+            finished: done,
+            progress,
+            count_window: self.count_window,
+            termination_reason: self.termination.filter(|_| done),
+            paused_on_results: !done && self.max_new_results.is_some_and(|max| returned >= max),
+            open_branches,
+            likely_long_running: self.wide_batches >= LONG_RUNNING_BATCHES,
+            window_matches,
+            per_window_found: self.windows.iter().map(|w| w.found).collect(),
+            prework: self.prework_report.take(),
+            precheck: self.precheck_report.take(),
+            phase,
+            next_phase: if quick && !self.finished { self.phase } else { None },
+            identical_value_matches,
+            current_count: self.deepening.filter(|_| !self.finished).map(|_| self.min_count),
+            current_region: if done { None } else { self.current_region() },
+            results_rate,
+            retained_bytes: self.stream_results.then(|| self.retained_bytes()),
+            peak_memory_estimate: done.then_some(self.peak_memory),
+            suppressed_near_duplicates: self.near_duplicates.as_ref().map(NearDuplicates::suppressed),
+            stages,
+            stage_progress,
+        }
+    }
+
+    /// The stage the search is in (see Stage): the one whose work comes next.
+    pub fn stage(&self) -> Stage {
+        if self.prework != Prework::Done {
+            Stage::Preparing
+        } else if self.precheck_nodes.is_some() {
+            Stage::Prechecking
+        } else if self.finished {
+            Stage::Finalizing
+        } else if self.use_mitm && self.mitm.as_ref().is_none_or(|walk| walk.building()) {
+            Stage::BuildingTables
+        } else {
+            Stage::Searching
+        }
+    }
+
+    /// Add the stage the search has moved on to, if it has, to those the batch
+    /// went through, and log the change.
+    fn enter_stage(&self, stages: &mut Vec<Stage>) {
+        let stage = self.stage();
+        if let Some(&last) = stages.last().filter(|&&last| last != stage) {
+            log!(Level::Info, "batch: stage {} -> {}", last.as_str(), stage.as_str());
+            stages.push(stage);
+        }
+    }
+
+    /// Drop the top frame, its children all walked.
+    fn pop_frame(&mut self) {
+        self.stack.pop();
+        // Update top-level progress when a top-level branch is exhausted.
+        // The root frame's start, not this frame's: a depth-1 frame's
+        // start indexes second elements and can run ahead of the root.
+        // A reopened path's frame is alone on the stack without being one.
+        if let [root] = self.stack.as_slice() {
+            if root.path_len == 0 && (root.second_pass || self.preferred.is_none()) {
+                self.top_level_done = root.start.get();
+            }
+        }
+    }
+
+    /// Whether a frame one or two rows short of max_count can be finished by one
+    /// scan: no row ids or span to check along the path, and none of its paths
+    /// cut at max_count worth remembering for set_max_count.
+    fn last_picks_scannable(&mut self) -> bool {
+        if self.row_ids.is_some() || self.max_index_span.is_some() || self.preferred.is_some() {
+            return false;
+        }
+        if self.deepening.is_some() || self.frontier_overflowed {
+            return true;
+        }
+        let (sorted, suffix_sum, target) = (&self.sorted, &self.suffix_sum, self.target);
+        let ceiling = *self.count_ceiling
+            .get_or_insert_with(|| count_bounds(sorted, suffix_sum, target).map_or(0, |(_, most)| most));
+        self.max_count >= ceiling
+    }
+
+    /// Finish the top frame, `path_len` rows deep and `budget` short of the
+    /// target, with one LastPicks scan from `start`, recording its completions as
+    /// the DFS would, in the DFS's order. Returns the nodes to count for it.
+    fn scan_last_picks(&mut self, start: usize, path_len: usize, budget: u64) -> u64 {
+        let allowed = self.max_count - path_len;
+        let needed = self.min_count.saturating_sub(path_len);
+        let n = self.sorted.len();
+        let mut picks = LastPicks::new(|i| self.sorted[i].value, start, n, budget, allowed == 2, needed <= 1);
+        let mut steps_at_result = 0;
+        while let Some((i, j)) = picks.next(|k| self.sorted[k].value) {
+            // One more row equal to the target: reported once, through the first,
+            // when alone (after zeros the sum is no sign of an empty path)
+            if j.is_none() && path_len == 0 && i > 0 && self.sorted[i - 1].value == budget {
+                break;
+            }
+            if path_len == 0 {
+                self.top_level_done = i + 1;
+            }
+            self.path.truncate(path_len);
+            self.path.push(SortedIndex::new(i));
+            self.path.extend(j.map(SortedIndex::new));
+            let windows = self.open_windows(self.path.len());
+            if (!self.windows.is_empty() && windows == 0) || self.already_reported() {
+                continue;
+            }
+            let path = std::mem::take(&mut self.path);
+            self.record_solution(&path, windows);
+            self.path = path;
+            if self.nodes_since_result == 0 {
+                steps_at_result = picks.steps;
+            }
+            if self.results_capped() || self.paused() {
+                self.stack.clear();
+                self.reopened.clear();
+                self.finished = true;
+                break;
+            }
+        }
+        self.path.truncate(path_len);
+        self.nodes_since_result += picks.steps - steps_at_result;
+        picks.steps
+    }
+
+    /// Fold one batch into the smoothed results per million nodes and return
+    /// what to report: the smoothed rate, or the overall one once finished.
+    /// Batches that walked no nodes leave it as it was.
+    fn update_results_rate(&mut self, found: usize, nodes: u64) -> f64 {
+        if nodes > 0 {
+            let rate = found as f64 * 1e6 / nodes as f64;
+            self.results_rate = Some(match self.results_rate {
+                Some(prev) => prev + RESULTS_RATE_WEIGHT * (rate - prev),
+                None => rate,
+            });
+        }
+        if self.finished {
+            return match self.nodes_explored {
+                0 => 0.0,
+                nodes => self.found as f64 * 1e6 / nodes as f64,
+            };
+        }
+        self.results_rate.unwrap_or(0.0)
+    }
+
+    /// The region the top frame extends; None when the stack is empty or the
+    /// top frame is the root.
+    pub fn current_region(&self) -> Option<CurrentRegion> {
+        let frame = self.stack.last()?;
+        let path = &self.path[..frame.path_len];
+        let indices = path.iter().map(|&i| self.sorted[i.get()].original_index);
+        Some(CurrentRegion {
+            min_index: indices.clone().min()?,
+            max_index: indices.max()?,
+            partial_sum: frame.current_sum,
+            depth: path.len(),
+        })
+    }
+
+    /// Entries equal to the target are all single-element solutions; the DFS
+    /// reports the first in sorted order and skips the rest. Their count, when
+    /// `results` holds that one and it stands for more than itself.
+    fn identical_value_matches(&self, results: &[Vec<NumberEntry>]) -> Option<usize> {
+        if !results.iter().any(|combo| combo.len() == 1 && combo[0].value == self.target) {
+            return None;
+        }
+        let count = self.sorted.len() - self.sorted.partition_point(|e| e.value < self.target);
+        (count > 1).then_some(count)
+    }
+
+    /// How much branching is left right now: for each open frame, the children it
+    /// has yet to try before the first prune point (too large, suffix too small, or
+    /// too few elements left). Cheap: sorted values and suffix sums are monotone,
+    /// so each frame is a few binary searches.
+    pub fn open_branches(&self) -> u64 {
+        if self.finished {
+            return 0;
+        }
+        let n = self.sorted.len();
+        self.stack.iter()
+            .map(|f| {
+                let residual = self.target - f.current_sum;
+                let needed = self.min_count.saturating_sub(f.path_len);
+                let too_large = self.sorted.partition_point(|e| e.value <= residual);
+                let short_suffix = self.suffix_sum[..n].partition_point(|&s| s >= residual);
+                let end = too_large.min(short_suffix).min((n + 1).saturating_sub(needed.max(1)));
+                end.saturating_sub(self.frame_from(f)) as u64
+            })
+            .sum()
+    }
+
+    /// Whether the unwalked part of the tree provably holds no solution.
+    ///
+    /// What is left is, for every frame on the stack, the subsets of
+    /// sorted[start..] added to that frame's partial sum. Dropping the count
+    /// limits only adds candidates, so if no subset of any such suffix reaches its
+    /// frame's residual, nothing is left. Cheap suffix checks first, then one
+    /// reachable-sums bitset built from the back, when small enough.
+    fn proved_exhausted(&mut self) -> bool {
+        let n = self.sorted.len();
+        let reopened = self.reopened.iter()
+            .map(|cut| (cut.path.last().map_or(0, |&i| i.next()), self.target - cut.sum));
+        let mut open: Vec<(usize, u64)> = self.stack.iter()
+            .map(|f| (self.frame_from(f), self.target - f.current_sum))
+            .chain(reopened)
+            .filter(|&(start, residual)| {
+                start < n && self.sorted[start].value <= residual && self.suffix_sum[start] >= residual
+            })
+            .collect();
+        let Some(max_residual) = open.iter().map(|&(_, r)| r).max() else {
+            return true;
+        };
+        let words = max_residual / 64 + 1;
+        if words.saturating_mul(n as u64) > PROOF_WORD_BUDGET {
+            log!(Level::Debug, "batch: exhaustion proof skipped, {} words over the bitset budget", words * n as u64);
+            return false;
+        }
+
+        self.peak_memory = self.peak_memory.max(self.memory_estimate() + words * size_of::<u64>() as u64);
+        // reachable[s] = some subset of sorted[i..] sums to s
+        let mut reachable = vec![0u64; words as usize];
+        reachable[0] = 1;
+        open.sort_unstable_by_key(|&(start, _)| std::cmp::Reverse(start));
+        let mut pending = open.iter().peekable();
+        for i in (0..n).rev() {
+            shift_or(&mut reachable, self.sorted[i].value);
+            while let Some(&&(start, residual)) = pending.peek() {
+                if start != i {
+                    break;
+                }
+                if reachable[(residual / 64) as usize] & (1 << (residual % 64)) != 0 {
+                    return false;
+                }
+                pending.next();
+            }
+        }
+        true
+    }
+
+    /// Number of usable entries being searched.
+    pub fn entry_count(&self) -> usize {
+        self.sorted.len()
+    }
+
+    /// The entries and strategy this search runs with, whatever prework is left.
+    pub fn info(&self) -> SearchInfo {
+        let strategy = if self.use_mitm {
+            "mitm"
+        } else if self.prefer_small_count {
+            "smallest_count_first"
+        } else if self.phase.is_some() {
+            "quick_then_dfs"
+        } else {
+            "dfs"
+        };
+        SearchInfo {
+            usable_count: self.sorted.len(),
+            total_value: self.sorted.iter().map(|e| e.value as u128).sum(),
+            min_value: self.sorted.iter().map(|e| e.value).min(),
+            max_value: self.sorted.iter().map(|e| e.value).max(),
+            strategy,
+            precheck: self.precheck_nodes.is_some(),
+            count_window: self.count_window,
+        }
+    }
+
+    /// Solutions found so far, collected or not.
+    pub fn found(&self) -> usize {
+        self.found
+    }
+
+    pub fn nodes_explored(&self) -> u64 {
+        self.nodes_explored
+    }
+
+    /// Done searching, with no results held for a later batch.
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.held == 0
+    }
+
+    /// Why the search finished; None while it is still running.
+    pub fn termination_reason(&self) -> Option<TerminationReason> {
+        self.termination.filter(|_| self.is_finished())
+    }
+
+    /// Read-only view of the path the top frame extends, with at most `limit`
+    /// indices. None once the stack is empty.
+    pub fn current_path_snapshot(&self, limit: usize) -> Option<PathSnapshot> {
+        let frame = self.stack.last()?;
+        let path = &self.path[..frame.path_len];
+        Some(PathSnapshot {
+            indices: path.iter().take(limit).map(|&i| self.sorted[i.get()].original_index).collect(),
+            len: path.len(),
+            sum: frame.current_sum,
+        })
+    }
+
+    /// Rows appearing in at least one collected result, most used first (ties by
+    /// index). Rows in no result are omitted. Empty when only counting.
+    pub fn usage_histogram(&self) -> Vec<UsageCount> {
+        let mut histogram: Vec<UsageCount> = self.sorted.iter()
+            .zip(&self.usage)
+            .filter(|&(_, &count)| count > 0)
+            .map(|(e, &count)| UsageCount { index: e.original_index, value: e.value, count })
+            .collect();
+        histogram.sort_unstable_by(|a, b| b.count.cmp(&a.count).then(a.index.cmp(&b.index)));
+        histogram
+    }
+
+    /// Get all results found so far.
+    #[allow(dead_code)]
+    pub fn all_results(&self) -> &[Vec<NumberEntry>] {
+        &self.results
+    }
+
+    /// Sequence number of `all_results()[0]`, or of the next result when there
+    /// are none: results found earlier were streamed out or invalidated.
+    pub fn first_seq(&self) -> u64 {
+        self.seq_base
+    }
+
+    /// The collected results numbered `from..=to`, clamped to those found so
+    /// far, each with its sequence number. None when some of them are no
+    /// longer held (see first_seq).
+    pub fn results_by_seq(&self, from: u64, to: u64) -> Option<Vec<(u64, &[NumberEntry])>> {
+        if from < self.seq_base {
+            return None;
+        }
+        let held = self.results.len() as u64;
+        let start = (from - self.seq_base).min(held);
+        let end = to.saturating_sub(self.seq_base).saturating_add(1).min(held).max(start);
+        Some((start..end).map(|i| (self.seq_base + i, self.results[i as usize].as_slice())).collect())
+    }
+
+    /// Serialize the full search state. Results are stored as indices into
+    /// `sorted`, which is written in its exact order so a resumed search walks
+    /// the same tree as an uninterrupted one. Prework must be finished first
+    /// (see `finish_prework`).
+    pub fn write_to(&self, w: &mut ByteWriter) {
+        debug_assert!(!self.prework_pending() && self.exportable());
+        w.u64(self.target);
+        w.usize(self.min_count);
+        w.usize(self.max_count);
+        w.usize(self.max_results);
+        w.u8(self.collect as u8);
+        w.usize(self.found);
+        w.u64(self.nodes_explored);
+        w.u8(self.finished as u8);
+        match self.count_window {
+            None => w.u8(0),
+            Some((lo, hi)) => {
+                w.u8(1);
+                w.usize(lo);
+                w.usize(hi);
+            }
+        }
+        w.usize(self.top_level_n);
+        w.usize(self.top_level_done);
+        w.u8(TerminationReason::to_byte(self.termination));
+        w.u64(self.sparse_tail_nodes);
+        w.u64(self.nodes_since_result);
+
+        w.usize(self.sorted.len());
+        for e in self.sorted.iter() {
+            w.u64(e.value);
+            w.usize(e.original_index.as_usize());
+        }
+        match &self.row_ids {
+            None => w.u8(0),
+            Some(ids) => {
+                w.u8(1);
+                w.usize(ids.len());
+                for &id in ids {
+                    w.u32(id);
+                }
+            }
+        }
+        match self.max_index_span {
+            None => w.u8(0),
+            Some(span) => {
+                w.u8(1);
+                w.usize(span);
+            }
+        }
+        w.usize(self.windows.len());
+        for window in &self.windows {
+            w.usize(window.min);
+            w.usize(window.max);
+            w.usize(window.max_results);
+            w.usize(window.found);
+        }
+        w.u8(SearchPhase::to_byte(self.phase));
+        w.u64(self.quick_nodes);
+        match self.deepening {
+            None => w.u8(0),
+            Some((first, last)) => {
+                w.u8(1);
+                w.usize(first);
+                w.usize(last);
+            }
+        }
+        w.usize(self.reported_ids.len());
+        for &id in &self.reported_ids {
+            w.u64(id);
+        }
+        w.usize(self.max_reported_ids);
+        w.u8((self.dedup_overflow == DedupOverflow::Pause) as u8);
+        w.u8(self.stream_results as u8);
+        w.usize(self.max_new_results.unwrap_or(0));
+        w.usize(self.held);
+        match self.memory_limit {
+            None => w.u8(0),
+            Some(bytes) => {
+                w.u8(1);
+                w.u64(bytes);
+            }
+        }
+        match &self.near_duplicates {
+            None => w.u8(0),
+            Some(near) => {
+                w.u8(1);
+                near.write_to(w);
+            }
+        }
+        w.u8(self.frontier_overflowed as u8);
+        for cuts in [&self.frontier, &self.reopened] {
+            w.usize(cuts.len());
+            for cut in cuts {
+                w.usize(cut.path.len());
+                for &idx in &cut.path {
+                    w.usize(idx.get());
+                }
+                w.u64(cut.sum);
+            }
+        }
+        w.usize(self.reopened_total);
+        w.usize(self.stack.len());
+        for f in &self.stack {
+            w.usize(f.start.get());
+            w.u64(f.current_sum);
+            w.usize(f.path_len);
+        }
+        w.usize(self.path.len());
+        for &idx in &self.path {
+            w.usize(idx.get());
+        }
+        let positions = self.sorted_positions();
+        w.usize(self.results.len());
+        for (i, combo) in self.results.iter().enumerate() {
+            w.usize(combo.len());
+            for e in combo {
+                w.usize(positions.get(&e.original_index).map_or(usize::MAX, |p| p.get()));
+            }
+            if let Some(&windows) = self.window_matches.get(i) {
+                w.u32(windows);
+            }
+        }
+        w.u64(self.seq_base);
+    }
+
+    /// Inverse of write_to. Everything the DFS later indexes or subtracts with
+    /// is bounds-checked here, so corrupt input is an error rather than a panic.
+    pub fn read_from(r: &mut ByteReader) -> Result<Self, DecodeError> {
+        let target = r.u64()?;
+        let min_count = r.usize()?;
+        let max_count = r.usize()?;
+        let max_results = r.usize()?;
+        let collect = r.bool()?;
+        let found = r.usize()?;
+        let nodes_explored = r.u64()?;
+        let finished = r.bool()?;
+        let count_window = if r.bool()? { Some((r.usize()?, r.usize()?)) } else { None };
+        let top_level_n = r.usize()?;
+        let top_level_done = r.usize()?;
+        let termination = TerminationReason::from_byte(r.u8()?)?;
+        let sparse_tail_nodes = r.u64()?;
+        let nodes_since_result = r.u64()?;
+
+        let n = r.len(16)?;
+        let mut sorted = Vec::with_capacity(n);
+        for _ in 0..n {
+            let value = r.u64()?;
+            let original_index = r.usize()?;
+            if original_index >= MAX_INPUT_LEN {
+                return Err(DecodeError::Corrupt);
+            }
+            sorted.push(NumberEntry { value, original_index: OriginalIndex::new(original_index) });
+        }
+        if sorted.windows(2).any(|w| w[0].value > w[1].value) {
+            return Err(DecodeError::Corrupt);
+        }
+        let row_ids = if r.bool()? {
+            let len = r.len(4)?;
+            let ids = (0..len).map(|_| r.u32()).collect::<Result<Vec<u32>, _>>()?;
+            // Indexed by original index
+            if sorted.iter().any(|e| e.original_index.as_usize() >= ids.len()) {
+                return Err(DecodeError::Corrupt);
+            }
+            Some(ids)
+        } else {
+            None
+        };
+        let max_index_span = if r.bool()? { Some(r.usize()?) } else { None };
+        let window_count = r.len(32)?;
+        if window_count > MAX_COUNT_WINDOWS {
+            return Err(DecodeError::Corrupt);
+        }
+        let mut windows = Vec::with_capacity(window_count);
+        for _ in 0..window_count {
+            windows.push(CountWindow { min: r.usize()?, max: r.usize()?, max_results: r.usize()?, found: r.usize()? });
+        }
+        let phase = SearchPhase::from_byte(r.u8()?)?;
+        let quick_nodes = r.u64()?;
+        let deepening = if r.bool()? { Some((r.usize()?, r.usize()?)) } else { None };
+        if deepening.is_some_and(|(first, last)| !(first <= min_count && min_count == max_count && max_count <= last)) {
+            return Err(DecodeError::Corrupt);
+        }
+        let reported_len = r.len(8)?;
+        let reported_ids = (0..reported_len).map(|_| r.u64()).collect::<Result<BTreeSet<u64>, _>>()?;
+        let max_reported_ids = r.usize()?;
+        let dedup_overflow = if r.bool()? { DedupOverflow::Pause } else { DedupOverflow::AllowDuplicates };
+        let stream_results = r.bool()?;
+        let max_new_results = Some(r.usize()?).filter(|&max| max > 0);
+        let held = r.usize()?;
+        let memory_limit = if r.bool()? { Some(r.u64()?) } else { None };
+        let near_duplicates = if r.bool()? { Some(NearDuplicates::read_from(r)?) } else { None };
+        let frontier_overflowed = r.bool()?;
+        let frontier = read_cut_paths(r, n, target)?;
+        let reopened = read_cut_paths(r, n, target)?;
+        let reopened_total = r.usize()?;
+        if reopened_total < reopened.len() {
+            return Err(DecodeError::Corrupt);
+        }
+        let mut suffix_sum = vec![0u64; n + 1];
+        for i in (0..n).rev() {
+            suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+        }
+
+        let stack_len = r.len(24)?;
+        if stack_len > n + 1 {
+            return Err(DecodeError::Corrupt);
+        }
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            let start = r.usize()?;
+            let current_sum = r.u64()?;
+            let path_len = r.usize()?;
+            if start > n || current_sum > target || path_len > n {
+                return Err(DecodeError::Corrupt);
+            }
+            stack.push(Frame { start: SortedIndex::new(start), current_sum, path_len, second_pass: false });
+        }
+
+        let path_len = r.len(8)?;
+        let mut path = Vec::with_capacity(path_len);
+        for _ in 0..path_len {
+            let idx = r.usize()?;
+            if idx >= n {
+                return Err(DecodeError::Corrupt);
+            }
+            path.push(SortedIndex::new(idx));
+        }
+
+        let result_count = r.len(8)?;
+        let mut results = Vec::with_capacity(result_count);
+        let mut window_matches = Vec::new();
+        let mut usage = vec![0; n];
+        for _ in 0..result_count {
+            let len = r.len(8)?;
+            let mut combo = Vec::with_capacity(len);
+            for _ in 0..len {
+                let idx = r.usize()?;
+                let entry = sorted.get(idx).ok_or(DecodeError::Corrupt)?;
+                combo.push(entry.clone());
+                usage[idx] += 1;
+            }
+            results.push(combo);
+            if !windows.is_empty() {
+                let matches = r.u32()?;
+                if matches == 0 || (window_count < 32 && matches >> window_count != 0) {
+                    return Err(DecodeError::Corrupt);
+                }
+                window_matches.push(matches);
+            }
+        }
+        let seq_base = r.u64()?;
+
+        // Streamed results were dropped as they were returned
+        let kept_all = if stream_results { results.len() <= found } else { results.len() == found };
+        if top_level_n != n || (collect && !kept_all) || held > results.len() {
+            return Err(DecodeError::Corrupt);
+        }
+
+        let result_bytes = results.iter().map(|combo| result_bytes(combo.len())).sum();
+        Ok(BatchSearchState {
+            sorted: SortedView::owned(sorted),
+            suffix_sum,
+            target,
+            min_count,
+            max_count,
+            max_results,
+            row_ids,
+            max_index_span,
+            windows,
+            stack,
+            path,
+            results,
+            window_matches,
+            seq_base,
+            collect,
+            found,
+            nodes_explored,
+            finished,
+            count_window,
+            usage,
+            termination,
+            sparse_tail_nodes,
+            nodes_since_result,
+            wide_batches: 0,
+            results_rate: None,
+            top_level_n,
+            top_level_done,
+            prework: Prework::Done,
+            prework_report: None,
+            precheck_nodes: None,
+            precheck_report: None,
+            phase,
+            quick_nodes,
+            reported_ids,
+            max_reported_ids,
+            dedup_overflow,
+            stream_results,
+            max_new_results,
+            held,
+            near_duplicates,
+            memory_limit,
+            result_bytes,
+            peak_memory: 0,
+            frontier,
+            frontier_overflowed,
+            reopened,
+            reopened_total,
+            prefer_small_count: deepening.is_some(),
+            deepening,
+            count_ceiling: None,
+            tree: None,
+            use_mitm: false,
+            mitm: None,
+            preferred_rows: Vec::new(),
+            preferred: None,
+            shard: None,
+        })
+    }
+}
+
+/// A list of cut paths as write_to writes them; every index must be below `n`
+/// and every sum at most `target`.
+fn read_cut_paths(r: &mut ByteReader, n: usize, target: u64) -> Result<Vec<CutPath>, DecodeError> {
+    let len = r.len(16)?;
+    let mut cuts = Vec::with_capacity(len);
+    for _ in 0..len {
+        let path_len = r.len(8)?;
+        if path_len > n {
+            return Err(DecodeError::Corrupt);
+        }
+        let path = (0..path_len).map(|_| r.usize().map(SortedIndex::new)).collect::<Result<Vec<_>, _>>()?;
+        let sum = r.u64()?;
+        if path.iter().any(|&idx| idx.get() >= n) || sum > target {
+            return Err(DecodeError::Corrupt);
+        }
+        cuts.push(CutPath { path, sum });
+    }
+    Ok(cuts)
+}
+
+/// bits |= bits << shift, dropping bits shifted past the end.
+fn shift_or(bits: &mut [u64], shift: u64) {
+    let len = bits.len();
+    let word_shift = (shift / 64) as usize;
+    if word_shift >= len {
+        return;
+    }
+    let bit_shift = shift % 64;
+    // High to low so each source word is read before it is updated
+    for dst in (word_shift..len).rev() {
+        let src = dst - word_shift;
+        let mut moved = bits[src] << bit_shift;
+        if bit_shift > 0 && src > 0 {
+            moved |= bits[src - 1] >> (64 - bit_shift);
+        }
+        bits[dst] |= moved;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, brute_force, index_sets};
+
+    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate()
+            .map(|(i, &v)| NumberEntry { value: v, original_index: OriginalIndex::new(i) })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_finds_all() {
+        let entries = make_entries(&[1, 2, 3, 4, 5]);
+        let mut state = BatchSearchState::new(&entries, 5, 1, 5, 100);
+
+        // Run in small batches
+        loop {
+            let result = state.search_batch(10);
+            if result.finished {
+                assert_eq!(result.total_found, 3); // [5], [1,4], [2,3]
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_respects_max_results() {
+        let entries = make_entries(&[1, 2, 3, 4, 5]);
+        let mut state = BatchSearchState::new(&entries, 5, 1, 5, 2);
+
+        loop {
+            let result = state.search_batch(100);
+            if result.finished {
+                assert!(result.total_found <= 2);
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_results_stop_keeps_honest_progress() {
+        let values: Vec<u64> = (1..=30).collect();
+        let entries = make_entries(&values);
+        let mut state = BatchSearchState::new(&entries, 30, 1, 30, 3);
+        let result = run_to_end(&mut state, 50);
+        assert_eq!(result.termination_reason, Some(TerminationReason::MaxResults));
+        assert!(!result.space_fully_explored());
+        // The first results all start with 1, so little of the top level is done
+        assert!(result.progress < 0.1, "{}", result.progress);
+        // And it stays put on further calls
+        assert_eq!(state.search_batch(50).progress, result.progress);
+
+        let mut state = BatchSearchState::new(&entries, 30, 1, 3, usize::MAX);
+        let result = run_to_end(&mut state, 50);
+        assert!(result.space_fully_explored() && result.progress == 1.0);
+    }
+
+    #[test]
+    fn test_count_windows_fill_independently() {
+        let nums: Vec<u64> = (1..=12).collect();
+        let entries = make_entries(&nums);
+        let mut all = BatchSearchState::new(&entries, 20, 2, 6, usize::MAX);
+        run_to_end(&mut all, 1000);
+        let by_len = |len: std::ops::RangeInclusive<usize>| all.all_results().iter().filter(|c| len.contains(&c.len())).count();
+        let (pairs, triples, larger) = (by_len(2..=2), by_len(3..=3), by_len(4..=6));
+        assert!(pairs > 0 && triples > 3 && larger > 0);
+
+        // Triples capped at 3; the other windows still collect everything
+        let mut state = BatchSearchState::new(&entries, 20, 2, 6, usize::MAX);
+        state.set_count_windows(&[(2, 2, usize::MAX), (3, 3, 3), (4, 6, usize::MAX)]);
+        let result = run_to_end(&mut state, 7);
+        assert_eq!(result.per_window_found, vec![pairs, 3, larger]);
+        assert_eq!(result.termination_reason, Some(TerminationReason::Exhausted));
+        assert_eq!(state.found(), pairs + 3 + larger);
+        for (combo, &windows) in state.all_results().iter().zip(&state.window_matches) {
+            let expected = match combo.len() { 2 => 0b001, 3 => 0b010, _ => 0b100 };
+            assert_eq!(windows, expected);
+        }
+
+        // Overlapping windows tag a result with both; all full ends the search
+        let mut state = BatchSearchState::new(&entries, 20, 2, 6, usize::MAX);
+        state.set_count_windows(&[(2, 3, 2), (3, 3, 2)]);
+        let result = run_to_end(&mut state, 1000);
+        assert_eq!(result.per_window_found, vec![2, 2]);
+        assert_eq!(result.termination_reason, Some(TerminationReason::MaxResults));
+        assert!(state.window_matches.iter().all(|&w| w != 0));
+
+        // Export keeps the windows and tags
+        let mut w = ByteWriter::new();
+        state.write_to(&mut w);
+        let bytes = w.into_bytes();
+        let restored = BatchSearchState::read_from(&mut ByteReader::new(&bytes)).unwrap();
+        assert_eq!((restored.windows.clone(), restored.window_matches.clone()), (state.windows.clone(), state.window_matches.clone()));
+    }
+
+    #[test]
+    fn test_read_from_rejects_index_past_input_cap() {
+        for (index, ok) in [(MAX_INPUT_LEN - 1, true), (MAX_INPUT_LEN, false)] {
+            let entries = vec![NumberEntry { value: 5, original_index: OriginalIndex::new(index) }];
+            let mut w = ByteWriter::new();
+            BatchSearchState::new(&entries, 5, 1, 1, 10).write_to(&mut w);
+            let restored = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes()));
+            assert_eq!(restored.is_ok(), ok, "{}", index);
+        }
+    }
+
+    #[test]
+    fn test_batch_progress_increases() {
+        let entries = make_entries(&(1..=20).collect::<Vec<u64>>());
+        let mut state = BatchSearchState::new(&entries, 30, 2, 5, 1000);
+
+        let mut last_progress = -1.0f64;
+        loop {
+            let result = state.search_batch(50);
+            assert!(result.progress >= last_progress || result.finished);
+            last_progress = result.progress;
+            if result.finished {
+                break;
+            }
+        }
+        assert_eq!(last_progress, 1.0);
+    }
+
+    #[test]
+    fn test_batch_empty_input() {
+        let entries: Vec<NumberEntry> = vec![];
+        let mut state = BatchSearchState::new(&entries, 10, 1, 5, 100);
+        let result = state.search_batch(100);
+        assert!(result.finished);
+        assert_eq!(result.total_found, 0);
+    }
+
+    #[test]
+    fn test_batch_no_solution() {
+        let entries = make_entries(&[10, 20, 30]);
+        let mut state = BatchSearchState::new(&entries, 5, 1, 3, 100);
+        let result = state.search_batch(10000);
+        assert!(result.finished);
+        assert_eq!(result.total_found, 0);
+    }
+
+    #[test]
+    fn test_batch_reports_tightened_window() {
+        let entries = make_entries(&(1..=10).collect::<Vec<u64>>());
+        let mut state = BatchSearchState::new(&entries, 20, 1, 10, 1000);
+        let result = state.search_batch(100_000);
+        assert!(result.finished);
+        assert_eq!(result.count_window, Some((3, 5)));
+        for combo in state.all_results() {
+            assert!(combo.len() >= 3 && combo.len() <= 5);
+        }
+
+        let mut state = BatchSearchState::new(&entries, 20, 1, 2, 1000);
+        let result = state.search_batch(100_000);
+        assert!(result.finished);
+        assert_eq!(result.nodes_explored, 0);
+        assert_eq!(result.count_window, None);
+    }
+
+    #[test]
+    fn test_init_budget_defers_prework_to_first_batch() {
+        // A million large values around five small ones: 6 = 1+5 = 2+4 = 1+2+3
+        let mut rng = testing::rng();
+        let mut values: Vec<u64> = (0..1_000_000).map(|_| 1_000 + rng.below(1_000_000_000)).collect();
+        let planted = [(17, 1), (250_000, 2), (500_000, 3), (750_000, 4), (999_983, 5)];
+        for &(i, v) in &planted {
+            values[i] = v;
+        }
+        let entries = make_entries(&values);
+
+        let budget_ms = 50.0;
+        let started = now_ms();
+        let mut state = BatchSearchState::new_budgeted(entries, 6, 1, 10, 100, Some(budget_ms));
+        let init_ms = now_ms() - started;
+        assert!(init_ms < budget_ms, "init took {} ms", init_ms);
+        assert!(state.prework_pending());
+
+        let first = state.search_batch(1_000);
+        assert!(!state.prework_pending());
+        let prework = first.prework.expect("first batch reports the deferred prework");
+        assert_eq!(prework.deferred, ["sort", "suffix_sums", "count_window"]);
+        assert!(prework.init_ms < budget_ms && prework.deferred_ms > 0.0, "{:?}", prework);
+        let result = run_to_end(&mut state, 1_000);
+        assert!(result.prework.is_none());
+        assert_eq!(result.count_window, Some((1, 3)));
+
+        let mut found: Vec<Vec<usize>> = state.all_results().iter()
+            .map(|combo| {
+                let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                indices
+            })
+            .collect();
+        found.sort();
+        assert_eq!(found, [vec![17, 250_000, 500_000], vec![17, 999_983], vec![250_000, 750_000]]);
+
+        // Without a budget nothing is left over
+        let mut state = BatchSearchState::new_budgeted(make_entries(&[1, 2, 3]), 3, 1, 3, 10, None);
+        assert!(!state.prework_pending());
+        assert!(state.search_batch(100).prework.is_none());
+    }
+
+    #[test]
+    fn test_quick_phase_never_repeats_a_result() {
+        let mut rng = testing::rng();
+        // MITM quick phase (n <= 40), then descending B&B (n > 40)
+        for n in [12, 30, 45, 70] {
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(60)).collect();
+            let entries = make_entries(&values);
+            let target = values.iter().take(5).sum();
+            let mut plain = BatchSearchState::new(&entries, target, 1, 5, usize::MAX);
+            run_to_end(&mut plain, u64::MAX);
+
+            let mut dual = BatchSearchState::new(&entries, target, 1, 5, usize::MAX);
+            dual.set_quick_phase(10_000);
+            let mut emitted = Vec::new();
+            let mut phases = Vec::new();
+            loop {
+                let result = dual.search_batch(37);
+                phases.push((result.phase, result.next_phase));
+                emitted.extend(result.new_results);
+                if result.finished {
+                    break;
+                }
+                if phases.len() == 2 {
+                    // Resuming from an export still remembers what the quick phase reported
+                    let mut w = ByteWriter::new();
+                    dual.write_to(&mut w);
+                    dual = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+                }
+            }
+            assert_eq!(phases[0], (Some(SearchPhase::Quick), Some(SearchPhase::Exhaustive)), "n={}", n);
+            assert!(phases[1..].iter().all(|&p| p == (Some(SearchPhase::Exhaustive), None)), "n={}", n);
+            assert_eq!(emitted.len(), plain.found(), "n={}", n);
+            assert_eq!(index_sets(&emitted), index_sets(plain.all_results()), "n={}", n);
+        }
+    }
+
+    #[test]
+    fn test_max_new_results_splits_batches() {
+        let ids = |results: &[Vec<NumberEntry>]| results.iter().map(|combo| combination_id(combo)).collect::<Vec<u64>>();
+        let mut rng = testing::rng();
+        // Dense DFS searches, pairs found by one root scan (no three rows fit, so
+        // the scan takes the root frame and the cap holds the rest), meet-in-the-
+        // middle and streamed results
+        let cases: [(Vec<u64>, u64, usize, bool, bool); 4] = [
+            ((0..16).map(|_| 1 + rng.below(20)).collect(), 40, 5, false, false),
+            ((101..=199).collect(), 300, 2, false, false),
+            ((0..16).map(|_| 1 + rng.below(30)).collect(), 60, 16, true, false),
+            ((0..16).map(|_| 1 + rng.below(20)).collect(), 35, 5, false, true),
+        ];
+        for (values, target, max_count, mitm, stream) in cases {
+            let entries = make_entries(&values);
+            let start = |cap: usize| {
+                let mut state = BatchSearchState::new(&entries, target, 1, max_count, usize::MAX);
+                if mitm {
+                    assert!(state.set_mitm());
+                }
+                if stream {
+                    state.set_stream_results();
+                }
+                state.set_max_new_results(cap);
+                state
+            };
+            let mut plain = start(0);
+            let all = run_to_end(&mut plain, u64::MAX);
+            let expected = ids(&all.new_results);
+            assert!((20..5000).contains(&expected.len()), "{} results", expected.len());
+
+            for cap in [1, 7, 50] {
+                // The cap stops every batch first, then the node budget does
+                for budget in [u64::MAX, 64] {
+                    let mut capped = start(cap);
+                    let mut emitted = Vec::new();
+                    let mut held_seen = false;
+                    let mut exports = 0;
+                    loop {
+                        let result = capped.search_batch(budget);
+                        assert!(result.new_results.len() <= cap);
+                        if budget == u64::MAX && !result.finished {
+                            assert!(result.paused_on_results && result.new_results.len() == cap);
+                        }
+                        if result.paused_on_results {
+                            assert_eq!(result.new_results.len(), cap);
+                        }
+                        held_seen |= capped.held_results() > 0;
+                        emitted.extend(ids(&result.new_results));
+                        if result.finished {
+                            assert!(!result.paused_on_results && result.termination_reason.is_some());
+                            break;
+                        }
+                        assert!(result.termination_reason.is_none() && !capped.is_finished());
+                        if !mitm && exports < 3 && emitted.len() > 5 * exports {
+                            // Held results survive an export
+                            exports += 1;
+                            let mut w = ByteWriter::new();
+                            capped.write_to(&mut w);
+                            capped = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+                        }
+                    }
+                    assert_eq!(emitted, expected, "cap {} budget {}", cap, budget);
+                    assert_eq!(capped.found(), expected.len());
+                    if max_count == 2 && budget == u64::MAX && cap < expected.len() {
+                        assert!(held_seen, "one scan finds more than {}", cap);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_adjust_target_matches_fresh_search() {
+        let mut rng = testing::rng();
+        for round in 0..24 {
+            let values: Vec<u64> = (0..18).map(|_| 1 + rng.below(40)).collect();
+            let entries = make_entries(&values);
+            let target: u64 = values.iter().take(6).sum();
+            let lowered = target - 1 - rng.below(target / 2);
+            // Plain, quick phase with precheck, smallest count first, meet-in-the-middle
+            let mode = round % 4;
+            let start = |target: u64| {
+                let mut state = BatchSearchState::new(&entries, target, 2, 6, usize::MAX);
+                match mode {
+                    1 => {
+                        state.set_precheck(1000);
+                        state.set_quick_phase(1000);
+                    }
+                    2 => state.set_prefer_small_count(),
+                    3 => assert!(state.set_mitm()),
+                    _ => {}
+                }
+                state
+            };
+            let mut fresh = start(lowered);
+            let mut expected = Vec::new();
+            loop {
+                let result = fresh.search_batch(u64::MAX);
+                expected.extend(result.new_results);
+                if result.finished {
+                    break;
+                }
+            }
+
+            // Adjusted before the first batch, part way, and once finished
+            for batches in [0, 3, usize::MAX] {
+                let mut state = start(target);
+                let mut done = 0;
+                while done < batches && !state.search_batch(50).finished {
+                    done += 1;
+                }
+                let found = state.found();
+                assert_eq!(state.adjust_target(lowered, 2), TargetChange::Lowered { invalidated: found });
+                assert_eq!(state.found(), 0);
+                let mut emitted = Vec::new();
+                loop {
+                    let result = state.search_batch(50);
+                    assert!(result.new_results.iter().all(|combo| combo.iter().map(|e| e.value).sum::<u64>() == lowered));
+                    emitted.extend(result.new_results);
+                    if result.finished {
+                        break;
+                    }
+                }
+                assert_eq!(index_sets(&emitted), index_sets(&expected), "round {} after {} batches", round, batches);
+                assert_eq!(state.found(), expected.len());
+                assert_eq!(state.adjust_target(lowered, 2), TargetChange::Unchanged);
+                assert_eq!(state.adjust_target(target, 2), TargetChange::Refused);
+            }
+        }
+
+        // A lower target can need fewer rows than the old window allowed
+        let mut state = BatchSearchState::new(&make_entries(&[1, 3, 3, 3, 3]), 12, 1, 5, usize::MAX);
+        assert_eq!(run_to_end(&mut state, u64::MAX).count_window, Some((4, 4)));
+        assert_eq!(state.adjust_target(4, 1), TargetChange::Lowered { invalidated: 1 });
+        let result = run_to_end(&mut state, u64::MAX);
+        assert_eq!((result.count_window, result.new_results.len()), (Some((2, 2)), 4));
+    }
+
+    #[test]
+    fn test_streamed_results_dedup_across_phases() {
+        let mut rng = testing::rng();
+        let values: Vec<u64> = (0..30).map(|_| 1 + rng.below(40)).collect();
+        let entries = make_entries(&values);
+        // No count limit, so no cut paths kept for set_max_count either
+        let target = 70;
+        let mut plain = BatchSearchState::new(&entries, target, 1, 30, usize::MAX);
+        run_to_end(&mut plain, u64::MAX);
+        assert!(plain.found() > 500);
+
+        let mut streamed = BatchSearchState::new(&entries, target, 1, 30, usize::MAX);
+        streamed.set_quick_phase(10_000);
+        streamed.set_stream_results();
+        let mut emitted = Vec::new();
+        loop {
+            let result = streamed.search_batch(5_000);
+            emitted.extend_from_slice(&result.new_results);
+            // Bodies are gone once returned: only the ids remain
+            assert!(streamed.all_results().is_empty());
+            assert_eq!(result.retained_bytes, Some(streamed.found() * REPORTED_ID_BYTES));
+            if result.finished {
+                assert!(result.space_fully_explored());
+                break;
+            }
+        }
+        // The quick phase's answer is not found again by the DFS
+        assert_eq!(emitted.len(), plain.found());
+        assert_eq!(index_sets(&emitted), index_sets(plain.all_results()));
+        assert!(streamed.retained_bytes() * 4 < plain.retained_bytes());
+
+        // Past the cap: no more ids kept, results unaffected without a restart
+        let mut capped = BatchSearchState::new(&entries, target, 1, 30, usize::MAX);
+        capped.set_stream_results();
+        capped.set_dedup_limit(100, DedupOverflow::AllowDuplicates);
+        let mut emitted = Vec::new();
+        loop {
+            let result = capped.search_batch(5_000);
+            emitted.extend(result.new_results);
+            assert!(result.retained_bytes.unwrap() <= 100 * REPORTED_ID_BYTES);
+            if result.finished {
+                break;
+            }
+        }
+        assert_eq!(index_sets(&emitted), index_sets(plain.all_results()));
+
+        // Or stop there
+        let mut paused = BatchSearchState::new(&entries, target, 1, 30, usize::MAX);
+        paused.set_stream_results();
+        paused.set_dedup_limit(100, DedupOverflow::Pause);
+        let result = run_to_end(&mut paused, 5_000);
+        assert_eq!(result.termination_reason, Some(TerminationReason::DedupOverflow));
+        assert!(!result.space_fully_explored() && paused.found() > 100 && paused.found() < plain.found());
+        let mut w = ByteWriter::new();
+        paused.write_to(&mut w);
+        let resumed = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+        assert_eq!(resumed.termination_reason(), Some(TerminationReason::DedupOverflow));
+        assert_eq!(resumed.retained_bytes(), paused.retained_bytes());
+    }
+
+    #[test]
+    fn test_memory_limit_stops_collecting() {
+        let mut rng = testing::rng();
+        let values: Vec<u64> = (0..30).map(|_| 1 + rng.below(40)).collect();
+        let entries = make_entries(&values);
+        let mut plain = BatchSearchState::new(&entries, 70, 1, 30, usize::MAX);
+        run_to_end(&mut plain, u64::MAX);
+        assert_eq!(plain.retained_bytes(), plain.all_results().iter().map(|combo| result_bytes(combo.len())).sum());
 
-        // Quick feasibility — if impossible, mark finished immediately
-        let mut finished = false;
-        if n == 0 || suffix_sum[0] < target || min_count > n {
-            finished = true;
-        }
-        if !finished && min_count > 0 {
-            let min_sum: u64 = sorted.iter().take(min_count).map(|e| e.value).sum();
-            if min_sum > target {
-                finished = true;
-            }
+        for limit in [0, 5_000, 20_000] {
+            let mut capped = BatchSearchState::new(&entries, 70, 1, 30, usize::MAX);
+            capped.set_memory_limit(limit);
+            let result = run_to_end(&mut capped, 1_000);
+            assert_eq!(result.termination_reason, Some(TerminationReason::OutOfMemory));
+            assert!(!result.space_fully_explored());
+            // Everything kept up to the limit, and the results found so far intact
+            assert!(capped.retained_bytes() as u64 <= limit);
+            assert!(capped.retained_bytes() as u64 + result_bytes(30) as u64 > limit);
+            assert_eq!(capped.found(), capped.all_results().len());
+            assert_eq!(index_sets(capped.all_results()), index_sets(&plain.all_results()[..capped.found()]));
         }
 
-        // Seed the stack with the initial frame (start at index 0, sum 0, path empty)
-        let mut stack = Vec::new();
-        if !finished {
-            stack.push(Frame {
-                start: 0,
-                current_sum: 0,
-                path_len: 0,
-            });
-        }
+        // The limit survives an export
+        let mut paused = BatchSearchState::new(&entries, 70, 1, 30, usize::MAX);
+        paused.set_memory_limit(5_000);
+        paused.search_batch(1);
+        let mut w = ByteWriter::new();
+        paused.write_to(&mut w);
+        let mut resumed = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+        let result = run_to_end(&mut resumed, 1_000);
+        assert_eq!(result.termination_reason, Some(TerminationReason::OutOfMemory));
+        assert!(resumed.retained_bytes() <= 5_000);
 
-        BatchSearchState {
-            top_level_n: n,
-            top_level_done: 0,
-            sorted,
-            suffix_sum,
-            target,
-            min_count,
-            max_count,
-            max_results,
-            stack,
-            path: Vec::new(),
-            results: Vec::new(),
-            nodes_explored: 0,
-            finished,
-        }
+        // Streamed results count only until returned
+        let mut streamed = BatchSearchState::new(&entries, 70, 1, 30, usize::MAX);
+        streamed.set_stream_results();
+        streamed.set_dedup_limit(usize::MAX, DedupOverflow::AllowDuplicates);
+        streamed.set_memory_limit(5_000);
+        let result = run_to_end(&mut streamed, 100);
+        assert!(result.space_fully_explored(), "{:?}", result.termination_reason);
+        assert_eq!(streamed.found(), plain.found());
     }
 
-    /// Run up to `node_budget` DFS nodes. Returns what was found in this batch.
-    pub fn search_batch(&mut self, node_budget: u64) -> BatchResult {
-        let prev_found = self.results.len();
-        let mut budget = node_budget;
-
-        while budget > 0 && !self.stack.is_empty() && self.results.len() < self.max_results {
-            budget -= 1;
-            self.nodes_explored += 1;
+    #[test]
+    fn test_suppress_near_duplicates() {
+        // Three copies of each amount: most results only trade rows for equal ones
+        let values: Vec<u64> = [3, 4, 5, 6, 7, 8].iter().flat_map(|&v| [v; 3]).collect();
+        let entries = make_entries(&values);
+        let value_sets = |results: &[Vec<NumberEntry>]| {
+            let mut sets: Vec<Vec<u64>> = results.iter()
+                .map(|combo| {
+                    let mut values: Vec<u64> = combo.iter().map(|e| e.value).collect();
+                    values.sort_unstable();
+                    values
+                })
+                .collect();
+            sets.sort();
+            sets
+        };
+        let mut plain = BatchSearchState::new(&entries, 15, 1, 18, usize::MAX);
+        run_to_end(&mut plain, u64::MAX);
 
-            let frame = self.stack.last_mut().unwrap();
-            let current_sum = frame.current_sum;
-            let path_len = frame.path_len;
-            let start = frame.start;
-            let n = self.sorted.len();
+        let mut exact = BatchSearchState::new(&entries, 15, 1, 18, usize::MAX);
+        exact.set_suppress_near_duplicates(0);
+        let result = run_to_end(&mut exact, 50);
+        // One result per multiset of amounts, every multiset still there
+        let mut distinct = value_sets(plain.all_results());
+        distinct.dedup();
+        assert_eq!(value_sets(exact.all_results()), distinct);
+        assert!(exact.found() * 5 < plain.found());
+        assert_eq!(result.suppressed_near_duplicates, Some(plain.found() - exact.found()));
+        assert_eq!(result.total_found, exact.found());
 
-            // Trim path back to this frame's depth (backtrack)
-            self.path.truncate(path_len);
+        // Exported halfway, a search holds back what it would have otherwise
+        let mut paused = BatchSearchState::new(&entries, 15, 1, 18, usize::MAX);
+        paused.set_suppress_near_duplicates(0);
+        paused.search_batch(500);
+        let mut w = ByteWriter::new();
+        paused.write_to(&mut w);
+        let mut resumed = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+        let result = run_to_end(&mut resumed, 50);
+        assert_eq!(index_sets(resumed.all_results()), index_sets(exact.all_results()));
+        assert_eq!(result.suppressed_near_duplicates, Some(plain.found() - exact.found()));
 
-            // Find next valid child to explore from `start`
-            let remaining_budget_val = self.target - current_sum;
-            let remaining_needed = self.min_count.saturating_sub(path_len);
+        // Distance 2 also drops a pair of amounts traded for another pair
+        let mut loose = BatchSearchState::new(&entries, 15, 1, 18, usize::MAX);
+        loose.set_suppress_near_duplicates(2);
+        run_to_end(&mut loose, u64::MAX);
+        assert!(loose.found() < exact.found());
+        let kept = value_sets(loose.all_results());
+        for (i, a) in kept.iter().enumerate() {
+            for b in &kept[..i] {
+                let mut rest = b.clone();
+                let common = a.iter().filter(|v| rest.iter().position(|w| w == *v).map(|at| rest.remove(at)).is_some()).count();
+                assert!(a.len().max(b.len()) - common > 2, "{:?} {:?}", a, b);
+            }
+        }
 
-            let mut found_child = false;
-            let mut i = start;
+        // Suppressed results take no room under max_results
+        let mut capped = BatchSearchState::new(&entries, 15, 1, 18, 5);
+        capped.set_suppress_near_duplicates(0);
+        let result = run_to_end(&mut capped, u64::MAX);
+        assert_eq!(value_sets(capped.all_results()).len(), 5);
+        assert_eq!(result.termination_reason, Some(TerminationReason::MaxResults));
+        assert!(result.suppressed_near_duplicates.unwrap() > 0);
+        assert_eq!(plain.search_batch(1).suppressed_near_duplicates, None);
+    }
 
-            while i < n {
-                let value = self.sorted[i].value;
+    #[test]
+    fn test_prefer_small_count_emits_by_size() {
+        let mut rng = testing::rng();
+        for round in 0..12 {
+            let n = 6 + rng.below(12) as usize;
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(30)).collect();
+            let entries = make_entries(&values);
+            let target = values[..n / 2].iter().sum();
+            let mut plain = BatchSearchState::new(&entries, target, 1, n, usize::MAX);
+            run_to_end(&mut plain, 1 << 20);
 
-                // Pruning: element too large
-                if value > remaining_budget_val {
-                    break;
-                }
-                // Pruning: suffix sum insufficient
-                if self.suffix_sum[i] < remaining_budget_val {
-                    break;
+            let mut state = BatchSearchState::new(&entries, target, 1, n, usize::MAX);
+            state.set_prefer_small_count();
+            let (mut emitted, mut progress) = (Vec::new(), 0.0);
+            loop {
+                let result = state.search_batch(1 + rng.below(40));
+                assert!(result.progress >= progress, "progress went from {} to {}", progress, result.progress);
+                progress = result.progress;
+                emitted.extend_from_slice(&result.new_results);
+                if round == 3 && !result.finished {
+                    // Resumes from an export at the same count
+                    let mut w = ByteWriter::new();
+                    state.write_to(&mut w);
+                    state = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
                 }
-                // Pruning: not enough elements left for min_count
-                if (n - i) < remaining_needed {
+                if result.finished {
+                    assert!(result.space_fully_explored() && result.progress == 1.0);
+                    assert_eq!(result.current_count, None);
                     break;
                 }
+            }
+            let sizes: Vec<usize> = emitted.iter().map(Vec::len).collect();
+            assert!(sizes.is_sorted(), "round {}: {:?}", round, sizes);
+            assert_eq!(index_sets(&emitted), index_sets(plain.all_results()), "round {}", round);
 
-                // This child is worth exploring. Advance frame.start past it
-                // so when we pop back, we try the next sibling.
-                let frame = self.stack.last_mut().unwrap();
-                frame.start = i + 1;
+            // max_results counts across sizes: the cap keeps the smallest results
+            let cap = plain.found() / 2;
+            let mut capped = BatchSearchState::new(&entries, target, 1, n, cap);
+            capped.set_prefer_small_count();
+            let result = run_to_end(&mut capped, 1 << 20);
+            assert_eq!(capped.found(), cap);
+            if cap > 0 {
+                assert_eq!(result.termination_reason, Some(TerminationReason::MaxResults));
+                let largest = capped.all_results().iter().map(Vec::len).max().unwrap();
+                assert!(sizes.iter().filter(|&&s| s < largest).count() < cap);
+                assert!(sizes[cap..].iter().all(|&s| s >= largest), "round {}", round);
+            }
+        }
+    }
 
-                let new_sum = current_sum + value;
-                let new_path_len = path_len + 1;
+    #[test]
+    fn test_preferred_rows_come_first() {
+        // Only {1, 9} holds the 9; by value order it is the last pair with the 1
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        let first_with = |state: &mut BatchSearchState| {
+            let result = run_to_end(state, 1 << 20);
+            assert!(result.space_fully_explored());
+            state.all_results().iter().position(|combo| combo.iter().any(|e| e.original_index.get() == 8))
+        };
+        let mut plain = BatchSearchState::new(&entries, 10, 1, 3, usize::MAX);
+        let mut preferred = BatchSearchState::new(&entries, 10, 1, 3, usize::MAX);
+        preferred.set_preferred_rows(&[8, 40]);
+        assert_eq!(first_with(&mut plain), Some(3));
+        assert_eq!(first_with(&mut preferred), Some(0));
+        assert_eq!(index_sets(preferred.all_results()), index_sets(plain.all_results()));
+        assert!(!preferred.exportable());
 
-                // Push this element onto path
-                self.path.truncate(path_len);
-                self.path.push(i);
+        // Same results however the walk is paused, proved empty or widened
+        let mut rng = testing::rng();
+        for round in 0..40 {
+            let n = 4 + rng.below(14) as usize;
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(25)).collect();
+            let entries = make_entries(&values);
+            let target = values[..n / 2].iter().sum::<u64>().max(1);
+            let rows: Vec<u32> = (0..1 + rng.below(4)).map(|_| rng.below(n as u64) as u32).collect();
+            let (min_count, max_count) = (1 + rng.below(2) as usize, 2 + rng.below(n as u64) as usize);
 
-                // Track top-level progress
-                if path_len == 0 {
-                    self.top_level_done = i + 1;
+            let mut plain = BatchSearchState::new(&entries, target, min_count, n, usize::MAX);
+            run_to_end(&mut plain, 1 << 20);
+            let mut state = BatchSearchState::new(&entries, target, min_count, max_count, usize::MAX);
+            state.set_preferred_rows(&rows);
+            state.set_sparse_tail_nodes(Some(1 + rng.below(20)));
+            let mut emitted = Vec::new();
+            loop {
+                let result = state.search_batch(1 + rng.below(30));
+                emitted.extend_from_slice(&result.new_results);
+                if result.finished && state.set_max_count(n) == MaxCountChange::Unchanged {
+                    break;
                 }
+            }
+            assert_eq!(index_sets(&emitted), index_sets(plain.all_results()), "round {}: {:?} preferring {:?}", round, values, rows);
+        }
+    }
 
-                // Check if this is a solution
-                if new_sum == self.target && new_path_len >= self.min_count {
-                    let combo: Vec<NumberEntry> = self.path.iter()
-                        .map(|&idx| self.sorted[idx].clone())
-                        .collect();
-                    self.results.push(combo);
-                    if self.results.len() >= self.max_results {
-                        // Drain the stack — we're done
-                        self.stack.clear();
-                        self.finished = true;
-                        break;
-                    }
-                    // With positive integers, can't extend this path further.
-                    // Continue to next sibling (frame.start already advanced).
-                    i += 1;
-                    continue;
-                }
+    #[test]
+    fn test_zeros_pad_to_min_count() {
+        use crate::solver::{SolverConfig, solve_all_combinations, DEFAULT_HYBRID_BB_BUDGET};
 
-                // If we can go deeper, push a new frame for the child
-                if new_path_len < self.max_count {
-                    self.stack.push(Frame {
-                        start: i + 1,
-                        current_sum: new_sum,
-                        path_len: new_path_len,
-                    });
-                }
+        // Only 4 + 6 reach 10; the zeros make up the rest of the count
+        let entries = make_entries(&[4, 0, 6, 9, 0]);
+        let mut four = BatchSearchState::new(&entries, 10, 4, 4, usize::MAX);
+        assert!(run_to_end(&mut four, 1 << 20).space_fully_explored());
+        assert_eq!(index_sets(four.all_results()), vec![vec![0, 1, 2, 4]]);
+        let mut three = BatchSearchState::new(&entries, 10, 3, 5, usize::MAX);
+        run_to_end(&mut three, 1 << 20);
+        assert_eq!(index_sets(three.all_results()), vec![vec![0, 1, 2], vec![0, 1, 2, 4], vec![0, 2, 4]]);
 
-                found_child = true;
-                break;
+        // A row equal to the target is reported once alone, but pads into one
+        // result per copy: with max_count 2 as with 3
+        let entries = make_entries(&[0, 5, 5, 2, 3]);
+        for max_count in [2, 3] {
+            let mut padded = BatchSearchState::new(&entries, 5, 2, max_count, usize::MAX);
+            run_to_end(&mut padded, 1 << 20);
+            assert_eq!(index_sets(padded.all_results()), brute_force(&[0, 5, 5, 2, 3], 5, 2, max_count));
+        }
+
+        // Every padded combination, however the walk is paused, and from the
+        // blocking search: both stop below a path at the target, which only
+        // zeros sorting first makes safe
+        let not_cancelled = AtomicBool::new(false);
+        let mut rng = testing::rng();
+        let mut check = |round: usize, values: &[u64], target: u64, min_count: usize, max_count: usize| {
+            let expected = brute_force(values, target, min_count, max_count);
+            let mut state = BatchSearchState::new(&make_entries(values), target, min_count, max_count, usize::MAX);
+            let mut emitted = Vec::new();
+            loop {
+                let result = state.search_batch(1 + rng.below(30));
+                emitted.extend_from_slice(&result.new_results);
+                if result.finished {
+                    break;
+                }
             }
+            assert_eq!(index_sets(&emitted), expected, "round {}: {:?} to {} from {} rows", round, values, target, min_count);
+            let config = SolverConfig {
+                target,
+                min_count,
+                max_count,
+                controller: &not_cancelled,
+                hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+                row_ids: None,
+                max_index_span: None,
+                memory_limit: None,
+            };
+            let all = solve_all_combinations(&make_entries(values), &config, usize::MAX).results;
+            assert_eq!(index_sets(&all), expected, "round {}: {:?} to {} from {} rows", round, values, target, min_count);
+        };
+        let mut draw = testing::rng();
+        for round in 0..40 {
+            let n = 4 + draw.below(9) as usize;
+            let values: Vec<u64> = (0..n).map(|_| if draw.below(3) == 0 { 0 } else { 1 + draw.below(9) }).collect();
+            let target = values[..n / 2].iter().sum::<u64>().max(10);
+            check(round, &values, target, 1 + draw.below(n as u64) as usize, n);
+        }
+        // Several rows equal to the target, padded to at least two rows
+        for round in 0..200 {
+            let n = 4 + draw.below(9) as usize;
+            let target = 1 + draw.below(6);
+            let values: Vec<u64> = (0..n)
+                .map(|_| match draw.below(4) {
+                    0 => 0,
+                    1 => target,
+                    _ => 1 + draw.below(target),
+                })
+                .collect();
+            let min_count = 2 + draw.below(3) as usize;
+            check(round, &values, target, min_count, min_count + draw.below(n as u64) as usize);
+        }
+    }
 
-            if !found_child || i >= n || self.sorted[i].value > remaining_budget_val {
-                // No more children in this frame — pop it
-                // But only if we didn't just push a new child frame
-                if !found_child {
-                    self.stack.pop();
-                    // Update top-level progress when a top-level branch is exhausted
-                    if self.stack.len() <= 1 && path_len <= 1 {
-                        self.top_level_done = start;
-                    }
+    #[test]
+    fn test_shards_partition_the_search() {
+        let mut rng = testing::rng();
+        for round in 0..30 {
+            let n = 3 + rng.below(16) as usize;
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(20)).collect();
+            let entries = make_entries(&values);
+            let target = values[..n / 2].iter().sum::<u64>().max(values[0]);
+            let (min_count, max_count) = (1, 1 + rng.below(n as u64) as usize);
+            let mut plain = BatchSearchState::new(&entries, target, min_count, max_count, usize::MAX);
+            run_to_end(&mut plain, 1 << 20);
+
+            for mode in [ShardMode::FirstRow, ShardMode::FirstTwoRows] {
+                let count = 1 + rng.below(5) as usize;
+                let mut union = Vec::new();
+                for index in 0..count {
+                    let mut state = BatchSearchState::new(&entries, target, min_count, max_count, usize::MAX);
+                    state.set_shard(Shard { index, count, mode });
+                    assert!(!state.exportable());
+                    let result = run_to_end(&mut state, 1 + rng.below(50));
+                    assert!(result.space_fully_explored() && result.progress == 1.0);
+                    union.extend_from_slice(state.all_results());
                 }
+                let (sets, expected) = (index_sets(&union), index_sets(plain.all_results()));
+                // No result in two shards
+                assert!(sets.windows(2).all(|pair| pair[0] != pair[1]), "round {} {:?}", round, mode);
+                assert_eq!(sets, expected, "round {} {:?}: {:?} to {}", round, mode, values, target);
             }
         }
+    }
 
-        if self.stack.is_empty() || self.results.len() >= self.max_results {
-            self.finished = true;
+    #[test]
+    fn test_current_region_and_results_rate() {
+        // Even values, odd target: a search that never finds anything
+        let values: Vec<u64> = (1..=40).map(|v| 2 * v).collect();
+        let entries = make_entries(&values);
+        let mut state = BatchSearchState::new(&entries, 301, 1, 6, usize::MAX);
+        let mut batches = 0;
+        loop {
+            let result = state.search_batch(5_000);
+            batches += 1;
+            if result.finished {
+                assert_eq!(result.current_region, None);
+                assert_eq!(result.results_rate, 0.0);
+                break;
+            }
+            assert_eq!(result.results_rate, 0.0);
+            let region = result.current_region.expect("a running search has a path");
+            assert!(region.min_index <= region.max_index && region.max_index.as_usize() < values.len());
+            assert!((1..6).contains(&region.depth) && region.partial_sum < 301);
+            let path = state.current_path_snapshot(usize::MAX).unwrap();
+            assert_eq!((region.depth, region.partial_sum), (path.len, path.sum));
+            assert_eq!(region.min_index, *path.indices.iter().min().unwrap());
         }
+        assert!(batches > 2);
 
-        let new_results: Vec<Vec<NumberEntry>> = self.results[prev_found..].to_vec();
+        // A dense one: smoothed while running, the overall average once finished
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        let mut state = BatchSearchState::new(&entries, 20, 1, 12, usize::MAX);
+        let first = state.search_batch(50);
+        assert_eq!(first.results_rate, first.total_found as f64 * 1e6 / 50.0);
+        let last = run_to_end(&mut state, 1 << 20);
+        assert_eq!(last.results_rate, state.found() as f64 * 1e6 / state.nodes_explored() as f64);
+        assert!(last.results_rate > 0.0 && last.current_region.is_none());
+    }
 
-        let progress = if self.top_level_n > 0 {
-            (self.top_level_done as f64) / (self.top_level_n as f64)
-        } else {
-            1.0
-        };
+    #[test]
+    fn test_prefer_small_count_raised_max_count() {
+        let values: Vec<u64> = (1..=12).collect();
+        let entries = make_entries(&values);
+        let mut state = BatchSearchState::new(&entries, 20, 1, 2, usize::MAX);
+        state.set_prefer_small_count();
+        let first = run_to_end(&mut state, 5);
+        assert!(first.space_fully_explored());
+        assert_eq!(state.set_max_count(2), MaxCountChange::Unchanged);
+        assert_eq!(state.set_max_count(4), MaxCountChange::Resumed { reopened: 0 });
+        let running = state.search_batch(1);
+        assert_eq!(running.current_count, Some(3));
+        run_to_end(&mut state, 5);
+        let sizes: Vec<usize> = state.all_results().iter().map(Vec::len).collect();
+        assert!(sizes.is_sorted() && sizes.last() == Some(&4), "{:?}", sizes);
 
-        BatchResult {
-            new_results,
-            total_found: self.results.len(),
-            nodes_explored: self.nodes_explored,
-            finished: self.finished,
-            progress: if self.finished { 1.0 } else { progress.min(0.999) },
-        }
+        let mut plain = BatchSearchState::new(&entries, 20, 1, 4, usize::MAX);
+        run_to_end(&mut plain, 1 << 20);
+        assert_eq!(index_sets(state.all_results()), index_sets(plain.all_results()));
     }
 
-    /// Get all results found so far.
-    #[allow(dead_code)]
-    pub fn all_results(&self) -> &[Vec<NumberEntry>] {
-        &self.results
+    #[test]
+    fn test_quick_phase_answers_in_first_batch() {
+        // The DFS walks ascending values, so its first answer is far away; the
+        // quick phase's descending B&B takes the three largest at once
+        let values: Vec<u64> = (1..=200).collect();
+        let entries = make_entries(&values);
+        let mut state = BatchSearchState::new(&entries, 200 + 199 + 198, 3, 3, usize::MAX);
+        state.set_quick_phase(1_000);
+        let first = state.search_batch(100);
+        assert_eq!(first.phase, Some(SearchPhase::Quick));
+        assert_eq!(index_sets(&first.new_results), [vec![197, 198, 199]]);
+        assert_eq!((first.total_found, first.progress), (1, 1.0));
+        // Exactly three picks: each root value below 198 is dropped in a node
+        let second = state.search_batch(10);
+        assert!(second.new_results.is_empty() && second.progress < 0.5);
+
+        // A quick answer can fill max_results by itself
+        let mut state = BatchSearchState::new(&entries, 200 + 199 + 198, 3, 3, 1);
+        state.set_quick_phase(1_000);
+        let first = state.search_batch(100);
+        assert!(first.finished && first.next_phase.is_none());
+        assert_eq!(first.termination_reason, Some(TerminationReason::MaxResults));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_logs_phase_change_and_finish() {
+        use crate::log::capture;
+        let entries = make_entries(&(1..=12).collect::<Vec<u64>>());
+        let run = |level| capture(level, || {
+            let mut state = BatchSearchState::new(&entries, 20, 2, 3, usize::MAX);
+            state.set_quick_phase(1_000);
+            while !state.search_batch(50).finished {}
+        });
 
-    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
-        nums.iter().enumerate()
-            .map(|(i, &v)| NumberEntry { value: v, original_index: i })
-            .collect()
+        let info = run(Level::Info);
+        assert!(info.iter().all(|(level, _)| *level == Level::Info));
+        let messages: Vec<&str> = info.iter().map(|(_, m)| m.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("quick phase: found one after "), "{}", messages[0]);
+        assert_eq!(messages[1], "batch: stage searching -> finalizing");
+        assert!(messages[2].starts_with("batch: finished (exhausted) after "), "{}", messages[2]);
+
+        // Debug adds one heartbeat per batch
+        let debug = run(Level::Debug);
+        let beats = debug.iter().filter(|(level, _)| *level == Level::Debug).count();
+        assert!(beats >= 2 && debug.len() == beats + 3, "{:?}", debug);
     }
 
     #[test]
-    fn test_batch_finds_all() {
-        let entries = make_entries(&[1, 2, 3, 4, 5]);
-        let mut state = BatchSearchState::new(&entries, 5, 1, 5, 100);
+    fn test_precheck_proves_infeasible() {
+        // Even values, odd target
+        let entries = make_entries(&[2, 4, 6, 8, 10, 12]);
+        let mut state = BatchSearchState::new(&entries, 15, 1, 6, usize::MAX);
+        state.set_precheck(1_000);
+        let first = state.search_batch(1);
+        assert!(first.finished && first.new_results.is_empty());
+        assert_eq!(first.termination_reason, Some(TerminationReason::ProvedInfeasible));
+        assert_eq!(first.precheck.unwrap().outcome, "infeasible");
 
-        // Run in small batches
-        loop {
-            let result = state.search_batch(10);
-            if result.finished {
-                assert_eq!(result.total_found, 3); // [5], [1,4], [2,3]
-                break;
+        // Only 6+1+1 reaches 8, so a raised max_count walks the tree after all
+        let entries = make_entries(&[6, 6, 1, 1, 1]);
+        let mut state = BatchSearchState::new(&entries, 8, 1, 2, usize::MAX);
+        state.set_precheck(1_000);
+        assert_eq!(state.search_batch(1).termination_reason, Some(TerminationReason::ProvedInfeasible));
+        assert_eq!(state.set_max_count(3), MaxCountChange::Restarted { frontier_overflowed: false });
+        let result = run_to_end(&mut state, 1_000);
+        assert_eq!((state.found(), result.termination_reason), (6, Some(TerminationReason::Exhausted)));
+    }
+
+    #[test]
+    fn test_precheck_witness_reported_once() {
+        let mut rng = testing::rng();
+        for n in [8, 20, 45] {
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(40)).collect();
+            let entries = make_entries(&values);
+            let target = values[..4].iter().sum();
+            let mut plain = BatchSearchState::new(&entries, target, 1, 5, usize::MAX);
+            run_to_end(&mut plain, 1 << 20);
+
+            // With an ample budget and with none at all (n > 40 runs out at once)
+            for budget in [1 << 20, 0] {
+                let mut state = BatchSearchState::new(&entries, target, 1, 5, usize::MAX);
+                state.set_precheck(budget);
+                let first = state.search_batch(1);
+                let outcome = first.precheck.as_ref().unwrap().outcome;
+                let mut emitted = first.new_results.clone();
+                if outcome == "found" {
+                    assert!(!emitted.is_empty(), "n={}", n);
+                } else {
+                    assert_eq!((n, budget, outcome), (45, 0, "unknown"));
+                }
+                while !state.is_finished() {
+                    emitted.extend(state.search_batch(10_000).new_results);
+                }
+                assert_eq!(emitted.len(), plain.found(), "n={} budget={}", n, budget);
+                assert_eq!(index_sets(&emitted), index_sets(plain.all_results()), "n={} budget={}", n, budget);
             }
         }
     }
 
     #[test]
-    fn test_batch_respects_max_results() {
-        let entries = make_entries(&[1, 2, 3, 4, 5]);
-        let mut state = BatchSearchState::new(&entries, 5, 1, 5, 2);
+    fn test_rows_equal_to_target_reported_once() {
+        let entries = make_entries(&[700; 10_000]);
+        let mut state = BatchSearchState::new(&entries, 700, 1, 10_000, 100);
+        let result = state.search_batch(1_000);
+        assert!(result.finished && result.space_fully_explored());
+        assert_eq!(result.new_results.len(), 1);
+        assert_eq!(result.identical_value_matches, Some(10_000));
+        assert!(result.nodes_explored < 5, "{}", result.nodes_explored);
+
+        // Alongside other solutions; a quick phase hit stands for the same result
+        let entries = make_entries(&[3, 10, 7, 10, 10]);
+        for quick in [false, true] {
+            let mut state = BatchSearchState::new(&entries, 10, 1, 5, usize::MAX);
+            if quick {
+                state.set_quick_phase(1_000);
+            }
+            let mut matches = None;
+            while !state.is_finished() {
+                let result = state.search_batch(1);
+                matches = matches.or(result.identical_value_matches);
+            }
+            assert_eq!(index_sets(state.all_results()), [vec![0, 2], vec![1]]);
+            assert_eq!(matches, Some(3));
+        }
+    }
 
+    /// Emitted results of a search at `first` max_count, stopped after `pause`
+    /// batches (if given), then raised to `second` and run to the end.
+    fn raised_run(entries: &[NumberEntry], target: u64, first: usize, second: usize, pause: Option<usize>)
+        -> (Vec<Vec<NumberEntry>>, MaxCountChange)
+    {
+        let mut state = BatchSearchState::new(entries, target, 1, first, usize::MAX);
+        let mut emitted = Vec::new();
+        for batch in 0.. {
+            if pause == Some(batch) {
+                break;
+            }
+            let result = state.search_batch(23);
+            emitted.extend(result.new_results);
+            if result.finished {
+                break;
+            }
+        }
+        let change = state.set_max_count(second);
+        // Survives an export with reopened paths pending
+        let mut w = ByteWriter::new();
+        state.write_to(&mut w);
+        let mut state = BatchSearchState::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+        let mut progress = 0.0;
         loop {
-            let result = state.search_batch(100);
+            let result = state.search_batch(23);
+            assert!(result.progress >= progress, "progress went from {} to {}", progress, result.progress);
+            progress = result.progress;
+            emitted.extend(result.new_results);
             if result.finished {
-                assert!(result.total_found <= 2);
+                assert_eq!(result.termination_reason, Some(TerminationReason::Exhausted));
                 break;
             }
         }
+        assert_eq!(emitted.len(), state.found());
+        (emitted, change)
     }
 
     #[test]
-    fn test_batch_progress_increases() {
-        let entries = make_entries(&(1..=20).collect::<Vec<u64>>());
-        let mut state = BatchSearchState::new(&entries, 30, 2, 5, 1000);
+    fn test_raised_max_count_matches_single_run() {
+        let mut rng = testing::rng();
+        let mut resumed = 0;
+        for round in 0..12 {
+            let n = 8 + rng.below(14) as usize;
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(30)).collect();
+            let entries = make_entries(&values);
+            let target = values.iter().take(4).sum();
+            let first = 2 + rng.below(2) as usize;
+            let second = first + 1 + rng.below(4) as usize;
+            let mut single = BatchSearchState::new(&entries, target, 1, second, usize::MAX);
+            run_to_end(&mut single, u64::MAX);
+            let expected = index_sets(single.all_results());
 
-        let mut last_progress = -1.0f64;
+            for pause in [None, Some(2)] {
+                let (emitted, change) = raised_run(&entries, target, first, second, pause);
+                // Restarted only when the first window admitted no count at all
+                assert_ne!(change, MaxCountChange::Restarted { frontier_overflowed: true }, "round {}", round);
+                resumed += matches!(change, MaxCountChange::Resumed { reopened } if reopened > 0) as usize;
+                let sets = index_sets(&emitted);
+                assert!(sets.windows(2).all(|w| w[0] != w[1]), "round {}: a result was emitted twice", round);
+                assert_eq!(sets, expected, "round {} pause {:?}", round, pause);
+            }
+        }
+        assert!(resumed >= 12, "only {} runs reopened any path", resumed);
+    }
+
+    #[test]
+    fn test_raised_max_count_fallbacks() {
+        // 10 = 4+6 = 1+3+6 = 1+4+5 = 2+3+5 = 1+2+3+4
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6]);
+        let mut state = BatchSearchState::new(&entries, 10, 1, 2, usize::MAX);
+        run_to_end(&mut state, u64::MAX);
+        assert_eq!(state.set_max_count(2), MaxCountChange::Unchanged);
+        // Pretend too many paths were cut: a full walk that skips the old result
+        state.frontier_overflowed = true;
+        assert_eq!(state.set_max_count(9), MaxCountChange::Restarted { frontier_overflowed: true });
+        let result = run_to_end(&mut state, 5);
+        assert_eq!(result.count_window, Some((2, 4)));
+        assert_eq!((state.found(), state.all_results().len()), (5, 5));
+        assert_eq!(index_sets(state.all_results()).len(), 5);
+        // The data allows at most 4, so 9 already covered everything
+        assert_eq!(state.set_max_count(20), MaxCountChange::Unchanged);
+
+        // A window no count fits never ran; raising it starts the walk
+        let mut state = BatchSearchState::new(&entries, 10, 3, 2, usize::MAX);
+        assert!(state.search_batch(100).finished);
+        assert_eq!(state.set_max_count(3), MaxCountChange::Restarted { frontier_overflowed: false });
+        assert_eq!(index_sets(&run_to_end(&mut state, 100).new_results), [vec![0, 2, 5], vec![0, 3, 4], vec![1, 2, 4]]);
+
+        // Counting restarts from zero rather than double counting
+        let mut counting = BatchSearchState::new_counting(&entries, 10, 1, 2);
+        run_to_end(&mut counting, u64::MAX);
+        counting.frontier_overflowed = true;
+        counting.set_max_count(4);
+        run_to_end(&mut counting, u64::MAX);
+        assert_eq!(counting.found(), 5);
+
+        // Capped by max_results: raising does not reopen the search
+        let mut capped = BatchSearchState::new(&entries, 10, 1, 2, 1);
+        run_to_end(&mut capped, u64::MAX);
+        assert!(matches!(capped.set_max_count(4), MaxCountChange::Resumed { .. }));
+        assert!(capped.is_finished());
+    }
+
+    fn run_to_end(state: &mut BatchSearchState, budget: u64) -> BatchResult {
         loop {
-            let result = state.search_batch(50);
-            assert!(result.progress >= last_progress || result.finished);
-            last_progress = result.progress;
+            let result = state.search_batch(budget);
             if result.finished {
-                break;
+                return result;
             }
         }
-        assert_eq!(last_progress, 1.0);
     }
 
     #[test]
-    fn test_batch_empty_input() {
-        let entries: Vec<NumberEntry> = vec![];
-        let mut state = BatchSearchState::new(&entries, 10, 1, 5, 100);
-        let result = state.search_batch(100);
-        assert!(result.finished);
-        assert_eq!(result.total_found, 0);
+    fn test_mitm_batches_match_blocking_mitm() {
+        use crate::mitm_all::solve_mitm_all;
+        use crate::solver::{SolverConfig, DEFAULT_HYBRID_BB_BUDGET};
+
+        let not_cancelled = AtomicBool::new(false);
+        let nums: Vec<u64> = (0..26).map(|i| 3 + (i * 37) % 50).collect();
+        let config = SolverConfig {
+            target: 150,
+            min_count: 2,
+            max_count: 5,
+            controller: &not_cancelled,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        };
+        let blocking = solve_mitm_all(&make_entries(&nums), &config, usize::MAX).unwrap();
+        assert!(blocking.results.len() > 100);
+
+        for budget in [1, 100, 5000] {
+            let mut state = BatchSearchState::new(&make_entries(&nums), 150, 2, 5, usize::MAX);
+            assert!(state.set_mitm());
+            assert_eq!(state.info().strategy, "mitm");
+            let (mut found, mut last_progress, mut batches) = (Vec::new(), 0.0, 0);
+            loop {
+                let result = state.search_batch(budget);
+                batches += 1;
+                assert!(result.nodes_explored <= batches * budget);
+                assert!(result.progress >= last_progress);
+                last_progress = result.progress;
+                found.extend(result.new_results);
+                if result.finished {
+                    assert_eq!(result.termination_reason, Some(TerminationReason::Exhausted));
+                    assert_eq!(result.progress, 1.0);
+                    // Every subset of both halves of 13, one node each
+                    assert_eq!(result.nodes_explored, 2 << 13);
+                    break;
+                }
+                // Still building the left map: nothing matched yet
+                if result.nodes_explored < 1 << 13 {
+                    assert_eq!(result.progress, 0.0);
+                }
+            }
+            // The same walk, so the same results in the same order
+            let rows = |results: &[Vec<NumberEntry>]| -> Vec<Vec<usize>> {
+                results.iter().map(|combo| combo.iter().map(|e| e.original_index.as_usize()).collect()).collect()
+            };
+            assert_eq!(rows(&found), rows(&blocking.results), "budget {}", budget);
+            assert_eq!(state.set_max_count(6), MaxCountChange::Unchanged);
+            assert!(!state.exportable());
+        }
+
+        // max_results ends it as with the DFS
+        let mut state = BatchSearchState::new(&make_entries(&nums), 150, 2, 5, 40);
+        assert!(state.set_mitm());
+        let result = run_to_end(&mut state, 700);
+        assert_eq!((result.total_found, result.termination_reason), (40, Some(TerminationReason::MaxResults)));
+        assert!(result.progress < 1.0);
+
+        // Not for more entries than the halves take, nor with row ids
+        let mut state = BatchSearchState::new(&make_entries(&[1; 41]), 3, 1, 3, 10);
+        assert!(!state.set_mitm());
+        let mut state = BatchSearchState::new(&make_entries(&nums), 150, 2, 5, 10);
+        state.set_row_ids(&[0; 26]);
+        assert!(!state.set_mitm());
     }
 
     #[test]
-    fn test_batch_no_solution() {
-        let entries = make_entries(&[10, 20, 30]);
-        let mut state = BatchSearchState::new(&entries, 5, 1, 3, 100);
-        let result = state.search_batch(10000);
-        assert!(result.finished);
-        assert_eq!(result.total_found, 0);
+    fn test_counting_matches_recursive() {
+        use crate::solver::{SolverConfig, solve_all_combinations, canonicalize_results, DEFAULT_HYBRID_BB_BUDGET};
+        use std::sync::atomic::AtomicBool;
+
+        let cancelled = AtomicBool::new(false);
+        let fixtures: [(&[u64], u64, usize, usize); 3] = [
+            (&[1, 2, 3, 4, 5], 5, 1, 5),
+            (&[3, 5, 7, 8, 11, 13, 14, 19, 22, 25], 40, 2, 6),
+            (&[2, 2, 2, 3, 3, 4, 6, 8], 10, 1, 8),
+        ];
+        for (nums, target, min, max) in fixtures {
+            let entries = make_entries(nums);
+            let config = SolverConfig {
+                target,
+                min_count: min,
+                max_count: max,
+                controller: &cancelled,
+                hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+                row_ids: None,
+                max_index_span: None,
+                memory_limit: None,
+            };
+            let mut expected = solve_all_combinations(&entries, &config, usize::MAX).results;
+
+            let mut state = BatchSearchState::new_counting(&entries, target, min, max);
+            let result = run_to_end(&mut state, 7);
+            assert_eq!(result.total_found, expected.len());
+
+            // Collecting mode finds the same combinations, in its own order
+            let mut state = BatchSearchState::new(&entries, target, min, max, usize::MAX);
+            run_to_end(&mut state, 7);
+            let mut collected = state.all_results().to_vec();
+            canonicalize_results(&mut expected);
+            canonicalize_results(&mut collected);
+            let indices = |results: &[Vec<NumberEntry>]| -> Vec<Vec<usize>> {
+                results.iter().map(|c| c.iter().map(|e| e.original_index.as_usize()).collect()).collect()
+            };
+            assert_eq!(indices(&collected), indices(&expected));
+        }
+    }
+
+    #[test]
+    fn test_first_result_matches_depth_first_find_one() {
+        use crate::solver::{SolverConfig, solve_depth_first_with_stats, DEFAULT_HYBRID_BB_BUDGET};
+        use std::sync::atomic::AtomicBool;
+
+        let cancelled = AtomicBool::new(false);
+        let mut rng = testing::rng();
+        for _ in 0..300 {
+            // Small value range so duplicates and ties are common
+            let n = 3 + rng.below(28) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(40)).collect();
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let min = 1 + rng.below(3) as usize;
+            let max = min + rng.below(n as u64) as usize;
+            let entries = make_entries(&nums);
+            let config = SolverConfig {
+                target,
+                min_count: min,
+                max_count: max,
+                controller: &cancelled,
+                hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+                row_ids: None,
+                max_index_span: None,
+                memory_limit: None,
+            };
+            let expected = match solve_depth_first_with_stats(&entries, &config).0 {
+                Ok(Some(found)) => Some(found.iter().map(|e| e.original_index).collect::<Vec<_>>()),
+                _ => None,
+            };
+
+            let mut state = BatchSearchState::new(&entries, target, min, max, 1);
+            run_to_end(&mut state, 13);
+            let first = state.all_results().first().map(|c| c.iter().map(|e| e.original_index).collect());
+            assert_eq!(first, expected, "{:?} target {} count {}..={}", nums, target, min, max);
+        }
+    }
+
+    #[test]
+    fn test_open_branches_wide_vs_narrow() {
+        // Wide and shallow: 300 small values, pairs only, none reaching the target
+        let wide: Vec<u64> = (1..=300).map(|v| v * 2).collect();
+        let mut state = BatchSearchState::new(&make_entries(&wide), 1001, 1, 300, usize::MAX);
+        let mut batches = Vec::new();
+        for _ in 0..LONG_RUNNING_BATCHES {
+            batches.push(state.search_batch(50));
+        }
+        assert!(batches[0].open_branches > WIDE_FRONTIER, "{}", batches[0].open_branches);
+        assert!(!batches[0].likely_long_running);
+        assert!(batches.last().unwrap().likely_long_running);
+
+        // Narrow and deep: each value just over the sum of the smaller ones, so
+        // only a chain of picks stays under the target
+        let narrow: Vec<u64> = (0..40).map(|i| 1u64 << i).collect();
+        let mut state = BatchSearchState::new(&make_entries(&narrow), (1 << 40) - 1, 1, 40, usize::MAX);
+        for _ in 0..LONG_RUNNING_BATCHES {
+            let result = state.search_batch(50);
+            assert!(result.open_branches <= WIDE_FRONTIER, "{}", result.open_branches);
+            assert!(!result.likely_long_running);
+        }
+
+        let result = run_to_end(&mut state, 1_000_000);
+        assert_eq!((result.open_branches, result.likely_long_running), (0, false));
+    }
+
+    #[test]
+    fn test_open_branches_counts_untried_children() {
+        let mut state = BatchSearchState::new(&make_entries(&[1, 2, 3, 4, 5]), 8, 1, 5, usize::MAX);
+        // Root frame: every value fits, but the suffix from 5 alone can't reach 8
+        assert_eq!(state.open_branches(), 4);
+        state.search_batch(1);
+        assert!(state.open_branches() > 0);
+    }
+
+    #[test]
+    fn test_current_path_snapshot() {
+        let nums: Vec<u64> = (1..=30).map(|v| v * 3).collect();
+        let entries = make_entries(&nums);
+        let mut state = BatchSearchState::new(&entries, 200, 1, 10, 1000);
+        for _ in 0..50 {
+            state.search_batch(17);
+            let Some(snapshot) = state.current_path_snapshot(usize::MAX) else { break };
+            let sum: u64 = snapshot.indices.iter().map(|&i| nums[i.as_usize()]).sum();
+            assert_eq!(sum, snapshot.sum);
+            assert_eq!(snapshot.indices.len(), snapshot.len);
+
+            let bounded = state.current_path_snapshot(2).unwrap();
+            assert_eq!(bounded.indices[..], snapshot.indices[..snapshot.len.min(2)]);
+            assert_eq!((bounded.len, bounded.sum), (snapshot.len, snapshot.sum));
+        }
+        run_to_end(&mut state, 1_000_000);
+        assert_eq!(state.current_path_snapshot(32), None);
+    }
+
+    #[test]
+    fn test_counting_never_stores_results() {
+        // 24 ones toward 12: C(24, 12) = 2,704,156 solutions
+        let entries = make_entries(&[1; 24]);
+        let mut state = BatchSearchState::new_counting(&entries, 12, 12, 12);
+        loop {
+            let result = state.search_batch(1_000_000);
+            assert!(result.new_results.is_empty());
+            assert!(state.all_results().is_empty());
+            if result.finished {
+                assert_eq!(result.total_found, 2_704_156);
+                break;
+            }
+        }
     }
 
     #[test]
@@ -325,4 +3852,81 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_usage_histogram() {
+        // Target 6: [6], [1,5], [2,4], [1,2,3]; 7 can't be used
+        let entries = make_entries(&[7, 1, 2, 3, 4, 5, 6]);
+        let mut state = BatchSearchState::new(&entries, 6, 1, 6, 100);
+        while !state.search_batch(3).finished {}
+
+        let counts: Vec<(usize, usize)> = state.usage_histogram().iter().map(|u| (u.index.as_usize(), u.count)).collect();
+        assert_eq!(counts, vec![(1, 2), (2, 2), (3, 1), (4, 1), (5, 1), (6, 1)]);
+        assert_eq!(state.usage_histogram()[0], UsageCount { index: OriginalIndex::new(1), value: 1, count: 2 });
+
+        let mut counting = BatchSearchState::new_counting(&entries, 6, 1, 6);
+        while !counting.search_batch(100).finished {}
+        assert!(counting.usage_histogram().is_empty());
+    }
+
+    #[test]
+    fn test_shift_or() {
+        let mut bits = vec![1u64, 0, 0];
+        shift_or(&mut bits, 70);
+        assert_eq!(bits, vec![1, 1 << 6, 0]);
+        shift_or(&mut bits, 63);
+        assert_eq!(bits, vec![1 | 1 << 63, 1 << 6, 1 << 5]);
+        shift_or(&mut bits, 500);
+        assert_eq!(bits, vec![1 | 1 << 63, 1 << 6, 1 << 5]);
+    }
+
+    #[test]
+    fn test_sparse_tail_proves_exhaustion() {
+        // The target is odd and 1 is the only odd value, so every solution uses it:
+        // all of them lie under the first top-level branch (pairs of even values),
+        // and the much larger rest of the walk (up to triples of even values) is
+        // solution-free.
+        let mut values = vec![1];
+        values.extend((1..=60).map(|v| v * 2));
+        let entries = make_entries(&values);
+
+        let mut full = BatchSearchState::new(&entries, 121, 1, 3, usize::MAX);
+        full.set_sparse_tail_nodes(None);
+        let full_result = run_to_end(&mut full, 1000);
+        assert_eq!(full_result.termination_reason, Some(TerminationReason::Exhausted));
+
+        let mut proved = BatchSearchState::new(&entries, 121, 1, 3, usize::MAX);
+        proved.set_sparse_tail_nodes(Some(100));
+        let proved_result = run_to_end(&mut proved, 1000);
+        assert_eq!(proved_result.termination_reason, Some(TerminationReason::ProvedExhausted));
+        assert_eq!(proved_result.total_found, full_result.total_found);
+        assert_eq!(proved_result.progress, 1.0);
+        assert!(
+            proved_result.nodes_explored * 3 < full_result.nodes_explored,
+            "{} vs {}", proved_result.nodes_explored, full_result.nodes_explored,
+        );
+    }
+
+    #[test]
+    fn test_sparse_tail_never_drops_solutions() {
+        // Proof attempted after every node: it must only ever end a search that
+        // has nothing left to find
+        let entries = make_entries(&[3, 5, 6, 7, 9, 10, 12, 14, 15, 17, 20, 21]);
+        for target in 1..=80 {
+            let mut plain = BatchSearchState::new(&entries, target, 1, 6, usize::MAX);
+            plain.set_sparse_tail_nodes(None);
+            let mut eager = BatchSearchState::new(&entries, target, 1, 6, usize::MAX);
+            eager.set_sparse_tail_nodes(Some(1));
+            assert_eq!(run_to_end(&mut plain, 1000).total_found, run_to_end(&mut eager, 1000).total_found, "target {}", target);
+        }
+    }
+
+    #[test]
+    fn test_termination_reasons() {
+        let entries = make_entries(&[1, 2, 3, 4, 5]);
+        let mut state = BatchSearchState::new(&entries, 5, 1, 5, 2);
+        assert_eq!(run_to_end(&mut state, 1000).termination_reason, Some(TerminationReason::MaxResults));
+        let mut state = BatchSearchState::new(&entries, 100, 1, 5, 10);
+        assert_eq!(state.search_batch(1).termination_reason, Some(TerminationReason::Exhausted));
+    }
 }
@@ -0,0 +1,476 @@
+//! Canonical form of result payloads, so two result sets can be compared (or
+//! used as cache keys) regardless of the order the search produced them in.
+//!
+//! Includes a small JSON parser, also used to read options objects (options.rs).
+//! Numbers are kept as their source text so nothing is lost to f64 (ids, u64 values).
+
+use crate::serialize::write_escaped;
+
+/// Nesting deeper than any payload we emit; guards the recursive parser.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    /// The number exactly as written
+    Number(String),
+    Str(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseError {
+    /// Byte offset in the input
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match self {
+            Value::Object(fields) => fields.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(raw) => raw.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(raw) => raw.parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn write(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(raw) => out.push_str(raw),
+            Value::Str(s) => write_escaped(s, out),
+            Value::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Value::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_escaped(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser { bytes: input.as_bytes(), pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_ws();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> ParseError {
+        ParseError { offset: self.pos, message }
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.bytes.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<(), ParseError> {
+        self.skip_ws();
+        if self.bytes.get(self.pos) != Some(&byte) {
+            return Err(self.error(message));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("invalid literal"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.skip_ws();
+        match self.bytes.get(self.pos) {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => Ok(Value::Str(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_ws();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_ws();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_ws();
+                    if self.bytes.get(self.pos) != Some(&b'"') {
+                        return Err(self.error("expected a key"));
+                    }
+                    let key = self.string()?;
+                    self.expect(b':', "expected ':'")?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_ws();
+                    match self.bytes.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        if self.bytes.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while matches!(p.bytes.get(p.pos), Some(b'0'..=b'9')) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+        if !digits(self) {
+            return Err(self.error("expected digits"));
+        }
+        if self.bytes.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("expected digits after '.'"));
+            }
+        }
+        if matches!(self.bytes.get(self.pos), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.bytes.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("expected exponent digits"));
+            }
+        }
+        // Only ASCII was consumed, so this slice is valid UTF-8
+        Ok(Value::Number(String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned()))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1; // opening quote
+        let mut out = Vec::new();
+        loop {
+            match self.bytes.get(self.pos) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.bytes.get(self.pos) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let hex = self.bytes.get(self.pos + 1..self.pos + 5)
+                                .and_then(|h| std::str::from_utf8(h).ok())
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            // Lone surrogates can't be represented; our payloads never contain them
+                            char::from_u32(hex).ok_or_else(|| self.error("unsupported \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0u8; 4];
+                    out.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                    self.pos += 1;
+                }
+                Some(&b) if b < 0x20 => return Err(self.error("control character in string")),
+                Some(&b) => {
+                    out.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Canonicalize a results payload: a bare array of combinations, a single
+/// combination, or an object holding one under `new_results` or `results`.
+/// Within each combination, `indices` are sorted ascending with `values` kept
+/// aligned; combinations are sorted by their index lists. Everything else is
+/// passed through unchanged.
+pub fn canonicalize_payload(json: &str) -> Result<String, ParseError> {
+    let mut value = parse(json)?;
+    canonicalize_value(&mut value)?;
+    let mut out = String::with_capacity(json.len());
+    value.write(&mut out);
+    Ok(out)
+}
+
+fn canonicalize_value(value: &mut Value) -> Result<(), ParseError> {
+    if let Value::Array(_) = value {
+        return canonicalize_list(value);
+    }
+    if value.get("indices").is_some() {
+        return canonicalize_combination(value).map(|_| ());
+    }
+    for key in ["new_results", "results"] {
+        if let Some(list) = value.get_mut(key) {
+            canonicalize_list(list)?;
+        }
+    }
+    Ok(())
+}
+
+fn canonicalize_list(list: &mut Value) -> Result<(), ParseError> {
+    let Value::Array(items) = list else {
+        return Err(shape_error("expected an array of combinations"));
+    };
+    let mut keyed = Vec::with_capacity(items.len());
+    for mut item in std::mem::take(items) {
+        let key = canonicalize_combination(&mut item)?;
+        keyed.push((key, item));
+    }
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    *items = keyed.into_iter().map(|(_, item)| item).collect();
+    Ok(())
+}
+
+/// Sort one combination in place; returns its sorted index list.
+fn canonicalize_combination(combo: &mut Value) -> Result<Vec<u64>, ParseError> {
+    let indices: Vec<u64> = match combo.get("indices") {
+        Some(Value::Array(items)) => items.iter()
+            .map(|v| v.as_u64().ok_or_else(|| shape_error("indices must be non-negative integers")))
+            .collect::<Result<_, _>>()?,
+        _ => return Err(shape_error("combination without an indices array")),
+    };
+    let mut order: Vec<usize> = (0..indices.len()).collect();
+    order.sort_by_key(|&i| indices[i]);
+
+    for key in ["indices", "values"] {
+        match combo.get_mut(key) {
+            Some(Value::Array(items)) if items.len() == order.len() => {
+                let mut taken: Vec<Option<Value>> = std::mem::take(items).into_iter().map(Some).collect();
+                *items = order.iter().map(|&i| taken[i].take().unwrap_or(Value::Null)).collect();
+            }
+            None => {}
+            Some(_) => return Err(shape_error("values must align with indices")),
+        }
+    }
+    Ok(order.iter().map(|&i| indices[i]).collect())
+}
+
+/// One combination of a results payload, as read back by `reported_combinations`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportedCombination {
+    /// Sorted ascending
+    pub indices: Vec<u64>,
+    /// Aligned with `indices`; None when the payload left values out
+    pub values: Option<Vec<u64>>,
+}
+
+/// The combinations of a results payload in the shapes canonicalize_payload
+/// accepts, in canonical order. Truncated combinations are rejected: their
+/// index lists are only a prefix, so they can't be compared.
+pub fn reported_combinations(json: &str) -> Result<Vec<ReportedCombination>, ParseError> {
+    let mut value = parse(json)?;
+    canonicalize_value(&mut value)?;
+    let combos: Vec<&Value> = match &value {
+        Value::Array(items) => items.iter().collect(),
+        _ if value.get("indices").is_some() => vec![&value],
+        _ => ["new_results", "results"].iter()
+            .filter_map(|key| match value.get(key) {
+                Some(Value::Array(items)) => Some(items),
+                _ => None,
+            })
+            .flatten()
+            .collect(),
+    };
+    combos.into_iter()
+        .map(|combo| {
+            if combo.get("truncated").and_then(Value::as_bool) == Some(true) {
+                return Err(shape_error("truncated combinations can't be compared"));
+            }
+            let numbers = |key| match combo.get(key) {
+                Some(Value::Array(items)) => items.iter().map(Value::as_u64).collect::<Option<Vec<u64>>>(),
+                _ => None,
+            };
+            let indices = numbers("indices").ok_or_else(|| shape_error("indices must be non-negative integers"))?;
+            Ok(ReportedCombination { indices, values: numbers("values") })
+        })
+        .collect()
+}
+
+/// Well-formed JSON that isn't a results payload; no single offset applies.
+fn shape_error(message: &'static str) -> ParseError {
+    ParseError { offset: 0, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trips_exactly() {
+        let inputs = [
+            r#"{"a":[1,-2.5,3e10,18446744073709551615],"b":{"c":null,"d":true,"e":false},"f":"x\"y\\z"}"#,
+            r#"{"progress":1.000000,"empty":[],"obj":{}}"#,
+        ];
+        for input in inputs {
+            let mut out = String::new();
+            parse(input).unwrap().write(&mut out);
+            assert_eq!(out, input);
+        }
+        // Escapes are normalized to the form our own writer uses
+        let mut out = String::new();
+        parse(r#""\u00e9\n\/""#).unwrap().write(&mut out);
+        assert_eq!(out, "\"\u{e9}\\u000a/\"");
+        assert_eq!(parse(" [ 1 , 2 ] ").unwrap(), Value::Array(vec![Value::Number("1".into()), Value::Number("2".into())]));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        for input in ["", "[", "[1,]", "{\"a\" 1}", "{\"a\":1,}", "tru", "01x", "\"abc", "1 2", "-", "1.", "[\"\u{1}\"]"] {
+            assert!(parse(input).is_err(), "{:?}", input);
+        }
+        let deep = "[".repeat(MAX_DEPTH + 2) + &"]".repeat(MAX_DEPTH + 2);
+        assert_eq!(parse(&deep).unwrap_err().message, "nesting too deep");
+        assert_eq!(parse("[1,]").unwrap_err().offset, 3);
+    }
+
+    #[test]
+    fn test_canonicalize_batch_payload() {
+        let a = r#"{"new_results":[{"id":"x","indices":[3,1],"values":[30,10],"count":2},{"id":"y","indices":[0,2],"values":[5,20],"count":2}],"total_found":2}"#;
+        let b = r#"{"new_results":[{"id":"y","indices":[2,0],"values":[20,5],"count":2},{"id":"x","indices":[1,3],"values":[10,30],"count":2}],"total_found":2}"#;
+        let expected = r#"{"new_results":[{"id":"y","indices":[0,2],"values":[5,20],"count":2},{"id":"x","indices":[1,3],"values":[10,30],"count":2}],"total_found":2}"#;
+        assert_eq!(canonicalize_payload(a).unwrap(), expected);
+        assert_eq!(canonicalize_payload(b).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_canonicalize_other_shapes() {
+        assert_eq!(
+            canonicalize_payload(r#"[{"indices":[5,4]},{"indices":[1]}]"#).unwrap(),
+            r#"[{"indices":[1]},{"indices":[4,5]}]"#,
+        );
+        assert_eq!(
+            canonicalize_payload(r#"{"status":"found","indices":[9,2],"values":[1,2]}"#).unwrap(),
+            r#"{"status":"found","indices":[2,9],"values":[2,1]}"#,
+        );
+        // Payloads without results pass through untouched
+        assert_eq!(canonicalize_payload(r#"{"status":"not_found"}"#).unwrap(), r#"{"status":"not_found"}"#);
+        // Prefix index lists sort first
+        assert_eq!(
+            canonicalize_payload(r#"[{"indices":[1,2]},{"indices":[1]}]"#).unwrap(),
+            r#"[{"indices":[1]},{"indices":[1,2]}]"#,
+        );
+    }
+
+    #[test]
+    fn test_reported_combinations() {
+        let batch = r#"{"new_results":[{"indices":[3,1],"values":[30,10]},{"indices":[2],"count":1}],"total_found":2}"#;
+        assert_eq!(reported_combinations(batch).unwrap(), vec![
+            ReportedCombination { indices: vec![1, 3], values: Some(vec![10, 30]) },
+            ReportedCombination { indices: vec![2], values: None },
+        ]);
+        assert_eq!(reported_combinations(r#"{"status":"found","indices":[4,0],"values":[1,2]}"#).unwrap().len(), 1);
+        assert!(reported_combinations(r#"{"status":"not_found"}"#).unwrap().is_empty());
+        assert!(reported_combinations(r#"[{"indices":[1,2,3],"truncated":true}]"#).is_err());
+    }
+
+    #[test]
+    fn test_canonicalize_rejects_bad_shapes() {
+        assert!(canonicalize_payload(r#"[{"values":[1]}]"#).is_err());
+        assert!(canonicalize_payload(r#"[{"indices":[1.5]}]"#).is_err());
+        assert!(canonicalize_payload(r#"[{"indices":[1,2],"values":[1]}]"#).is_err());
+        assert!(canonicalize_payload(r#"{"new_results":3}"#).is_err());
+    }
+}
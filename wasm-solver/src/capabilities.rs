@@ -0,0 +1,113 @@
+//! What this build supports, for `get_capabilities()` and the build stamp on
+//! error payloads.
+//!
+//! Frontends check `features` instead of probing for exported functions, and
+//! `api_version` instead of sniffing payload shapes. Everything here is a
+//! compile-time constant: no state, no allocation beyond the list itself.
+
+/// Bumped on any change to the shape of a payload (a field added, renamed,
+/// removed or given a new type), not on behavior changes that keep the shape.
+pub const API_VERSION: u32 = 21;
+
+/// What every build of this version has; new features go at the end.
+const API_FEATURES: &[&str] = &[
+    "scale",
+    "u64_input",
+    "include_indices",
+    "uniqueness_check",
+    "random",
+    "batch",
+    "single_search",
+    "count_batch",
+    "handles",
+    "chunked_load",
+    "full_results",
+    "batch_export",
+    "options_v2",
+    "row_ids",
+    "count_windows",
+    "init_budget",
+    "quick_phase",
+    "precheck",
+    "scoring",
+    "paranoid",
+    "prefer_small_count",
+    "self_test",
+    "log_levels",
+    "min_cost",
+    "forced_algorithm",
+    "find_sessions",
+    "heartbeat",
+    "shortcuts",
+    "stream_results",
+    "tags",
+    "adjustments",
+    "not_found_explain",
+    "suppress_near_duplicates",
+    "memory_limit",
+    "sessions",
+    "max_index_span",
+    "init_replace",
+    "target_rounding",
+    "batch_info",
+    "previously_matched",
+    "collapse_duplicates",
+    "target_analysis",
+    "search_tree",
+    "batch_mitm",
+    "csv_column",
+    "multiple_targets",
+    "fractional_inputs_warning",
+    "result_indices_chunks",
+    "max_new_results_per_batch",
+    "provably_unusable_pruning",
+    "solver_instances",
+    "adjust_target",
+    "verify_exhaustive",
+    "preferred_indices",
+    "batch_shards",
+    "include_zeros",
+    "search_stages",
+    "result_seq",
+    "max_entries",
+    "deterministic",
+    "peak_memory_estimate",
+    "matching_pair",
+    "nearest_miss_rows",
+    "bnb_nearest_misses",
+];
+
+/// Cargo features this build may be compiled with, each with whether it was.
+const CARGO_FEATURES: &[(&str, bool)] = &[
+    ("console_error_panic_hook", cfg!(feature = "console_error_panic_hook")),
+    ("console_log", cfg!(feature = "console_log")),
+    ("fuzzing", cfg!(feature = "fuzzing")),
+];
+
+/// API features followed by the enabled cargo features.
+pub fn features() -> Vec<&'static str> {
+    let enabled = CARGO_FEATURES.iter().filter(|&&(_, on)| on).map(|&(name, _)| name);
+    API_FEATURES.iter().copied().chain(enabled).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features_match_enabled_cargo_features() {
+        let features = features();
+        for (name, enabled) in [
+            ("console_error_panic_hook", cfg!(feature = "console_error_panic_hook")),
+            ("console_log", cfg!(feature = "console_log")),
+            ("fuzzing", cfg!(feature = "fuzzing")),
+        ] {
+            assert_eq!(features.contains(&name), enabled, "{}", name);
+        }
+        assert_eq!(features.len(), API_FEATURES.len() + CARGO_FEATURES.iter().filter(|f| f.1).count());
+        let mut unique = features.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), features.len());
+    }
+}
@@ -0,0 +1,336 @@
+//! Find-one over distinct values (`collapse_duplicates`): rows of equal value
+//! become one group with a multiplicity, the search decides how many rows of
+//! each group to take, and the multiset it finds is expanded back into rows,
+//! the lowest original indices of each value first.
+//!
+//! On inputs dominated by repeated amounts (a few dozen prices over thousands
+//! of rows) the search walks dozens of groups instead of thousands of rows and
+//! never tries equal rows one after another. Failed (group, remainder, count)
+//! states are remembered, so the walk is bounded by the distinct remainders
+//! rather than by the rows. Row ids and index spans depend on which rows are
+//! taken, so those searches are never collapsed.
+
+use crate::log::{Level, log};
+use crate::solver::{NumberEntry, PhaseStats, SolverConfig, SolveError, SolveOutcome, SolverStats, check_input, tighten_count_window};
+use crate::utils::{StableHashSet, try_reserve};
+
+/// Largest input the default leaves alone: meet-in-the-middle settles those
+/// as fast, with its fewest-values answer.
+pub const AUTO_MIN_N: usize = 40;
+
+/// Failed states remembered at most, about 8 MiB of them.
+const MAX_DEAD_STATES: usize = 1 << 18;
+
+/// When find_one searches over distinct values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Collapse {
+    #[default]
+    Never,
+    /// Past AUTO_MIN_N rows, when there are at most half as many distinct values
+    Auto,
+    /// Whenever two rows share a value
+    Always,
+}
+
+impl Collapse {
+    /// `collapse_duplicates` as passed: absent means Auto.
+    pub fn from_option(collapse: Option<bool>) -> Self {
+        match collapse {
+            None => Collapse::Auto,
+            Some(true) => Collapse::Always,
+            Some(false) => Collapse::Never,
+        }
+    }
+}
+
+/// Rows of one value, by original index.
+struct Group {
+    value: u64,
+    rows: Vec<NumberEntry>,
+}
+
+/// `entries` grouped by value, ascending; None when `collapse` says to search
+/// the rows themselves.
+fn groups(entries: &[NumberEntry], collapse: Collapse) -> Option<Vec<Group>> {
+    if collapse == Collapse::Never || (collapse == Collapse::Auto && entries.len() <= AUTO_MIN_N) {
+        return None;
+    }
+    let mut sorted = entries.to_vec();
+    sorted.sort_unstable_by_key(|e| (e.value, e.original_index));
+    let mut groups: Vec<Group> = Vec::new();
+    for entry in sorted {
+        match groups.last_mut() {
+            Some(group) if group.value == entry.value => group.rows.push(entry),
+            _ => groups.push(Group { value: entry.value, rows: vec![entry] }),
+        }
+    }
+    let worth = match collapse {
+        Collapse::Auto => groups.len() * 2 <= entries.len(),
+        _ => groups.len() < entries.len(),
+    };
+    worth.then_some(groups)
+}
+
+/// One match of `config`, searched over the distinct values of `entries` when
+/// `collapse` calls for it; None to search the rows as usual. Stats report the
+/// "collapsed" phase and the number of groups as `collapsed_to`.
+pub fn solve_collapsed(entries: &[NumberEntry], config: &SolverConfig, collapse: Collapse) -> Option<(SolveOutcome, SolverStats)> {
+    let groups = groups(entries, collapse)?;
+    let mut stats = SolverStats { collapsed_to: Some(groups.len()), ..SolverStats::default() };
+    if let Err(err) = check_input(entries.len(), config) {
+        return Some((Err(err), stats));
+    }
+
+    // Every row, ascending, for the count window and the bounds
+    let mut sorted = Vec::new();
+    if let Err(oom) = try_reserve(&mut sorted, entries.len(), config.memory_limit) {
+        return Some((Err(SolveError::OutOfMemory(oom)), stats));
+    }
+    sorted.extend(groups.iter().flat_map(|g| g.rows.iter().cloned()));
+    let mut suffix_sum = vec![0u64; sorted.len() + 1];
+    for i in (0..sorted.len()).rev() {
+        suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+    }
+    let Some((min_count, max_count)) = tighten_count_window(
+        &sorted, &suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return Some((Ok(None), stats));
+    };
+    stats.count_window = Some((min_count, max_count));
+    log!(
+        Level::Info, "find_one: n={} collapsed to {} values, count window [{}, {}]",
+        sorted.len(), groups.len(), min_count, max_count,
+    );
+
+    let mut starts = Vec::with_capacity(groups.len() + 1);
+    starts.push(0);
+    for group in &groups {
+        starts.push(starts.last().unwrap() + group.rows.len());
+    }
+    let mut search = GroupDfs {
+        config,
+        groups: &groups,
+        starts,
+        suffix_sum,
+        min_count,
+        max_count,
+        takes: vec![0; groups.len()],
+        dead: StableHashSet::default(),
+        nodes: 0,
+    };
+    let walk = search.dfs(0, config.target, 0);
+    stats.phases.push(PhaseStats { algorithm: "collapsed", budget: None, nodes: search.nodes });
+    let result = match walk {
+        Walk::Found => Ok(Some(search.expand())),
+        Walk::NotFound => Ok(None),
+        Walk::Cancelled => Err(SolveError::Cancelled),
+    };
+    Some((result, stats))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Walk {
+    Found,
+    NotFound,
+    Cancelled,
+}
+
+/// DFS over the groups, ascending, choosing how many rows of each to take.
+struct GroupDfs<'a> {
+    config: &'a SolverConfig<'a>,
+    groups: &'a [Group],
+    /// starts[g]: where group g's rows begin among all rows, ascending
+    starts: Vec<usize>,
+    /// Over all rows, ascending; saturating
+    suffix_sum: Vec<u64>,
+    min_count: usize,
+    max_count: usize,
+    /// Rows taken of each group on the current path
+    takes: Vec<usize>,
+    /// (group, remainder, rows taken) states known to lead nowhere
+    dead: StableHashSet<(u32, u64, u32)>,
+    nodes: u64,
+}
+
+impl GroupDfs<'_> {
+    fn dfs(&mut self, g: usize, remaining: u64, taken: usize) -> Walk {
+        self.nodes += 1;
+        if self.nodes & 0xFFF == 0 && self.config.controller.should_stop(self.nodes).is_break() {
+            return Walk::Cancelled;
+        }
+        if remaining == 0 {
+            return if taken >= self.min_count { Walk::Found } else { Walk::NotFound };
+        }
+        if g == self.groups.len() || !self.reachable(g, remaining, taken) {
+            return Walk::NotFound;
+        }
+        let key = (g as u32, remaining, taken as u32);
+        if self.dead.contains(&key) {
+            return Walk::NotFound;
+        }
+
+        // Most rows of this value first, down to none of them
+        let value = self.groups[g].value;
+        let most = (self.groups[g].rows.len() as u64).min(remaining / value).min((self.max_count - taken) as u64) as usize;
+        for take in (0..=most).rev() {
+            self.takes[g] = take;
+            match self.dfs(g + 1, remaining - take as u64 * value, taken + take) {
+                Walk::NotFound => {}
+                walk => return walk,
+            }
+        }
+        self.takes[g] = 0;
+        if self.dead.len() < MAX_DEAD_STATES {
+            self.dead.insert(key);
+        }
+        Walk::NotFound
+    }
+
+    /// Whether the rows of groups g.. can still close `remaining` within the
+    /// count window: its smallest value fits, and between the fewest and the
+    /// most rows still allowed, the smallest sum fits under it and the largest
+    /// reaches it.
+    fn reachable(&self, g: usize, remaining: u64, taken: usize) -> bool {
+        let start = self.starts[g];
+        let n = self.suffix_sum.len() - 1;
+        let needed = self.min_count.saturating_sub(taken).max(1);
+        let allowed = (self.max_count - taken).min(n - start);
+        if self.groups[g].value > remaining || needed > allowed {
+            return false;
+        }
+        if self.suffix_sum[start] == u64::MAX {
+            return true;
+        }
+        let smallest = self.suffix_sum[start] - self.suffix_sum[start + needed];
+        let largest = self.suffix_sum[n - allowed];
+        smallest <= remaining && remaining <= largest
+    }
+
+    /// The rows of the match found: each group's lowest original indices.
+    fn expand(&self) -> Vec<NumberEntry> {
+        self.groups.iter().zip(&self.takes)
+            .flat_map(|(group, &take)| group.rows[..take].iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{DEFAULT_HYBRID_BB_BUDGET, OriginalIndex, solve_depth_first_with_stats};
+    use crate::testing;
+    use std::sync::atomic::AtomicBool;
+
+    static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate().map(|(i, &v)| NumberEntry { value: v, original_index: OriginalIndex::new(i) }).collect()
+    }
+
+    fn make_config(target: u64, min: usize, max: usize) -> SolverConfig<'static> {
+        SolverConfig {
+            target,
+            min_count: min,
+            max_count: max,
+            controller: &NOT_CANCELLED,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    fn indices(found: &[NumberEntry]) -> Vec<usize> {
+        found.iter().map(|e| e.original_index.as_usize()).collect()
+    }
+
+    #[test]
+    fn test_expands_to_lowest_distinct_rows() {
+        let nums = [5, 3, 5, 3, 5, 9, 3];
+        let entries = make_entries(&nums);
+        let (result, stats) = solve_collapsed(&entries, &make_config(16, 1, 7), Collapse::Always).unwrap();
+        let Ok(Some(found)) = result else { panic!("{:?}", result) };
+        // As many 3s as still leave a match (three leave 7), then the first two 5s
+        assert_eq!(indices(&found), vec![1, 3, 0, 2]);
+        assert_eq!(stats.collapsed_to, Some(3));
+        assert_eq!(stats.phases[0].algorithm, "collapsed");
+
+        // Within the count window, and none at all when nothing fits
+        let (result, _) = solve_collapsed(&entries, &make_config(16, 2, 2), Collapse::Always).unwrap();
+        assert!(matches!(result, Ok(None)), "{:?}", result);
+        let (result, _) = solve_collapsed(&entries, &make_config(14, 2, 2), Collapse::Always).unwrap();
+        let Ok(Some(found)) = result else { panic!("{:?}", result) };
+        assert_eq!(indices(&found), vec![0, 5]);
+    }
+
+    #[test]
+    fn test_when_to_collapse() {
+        let distinct = make_entries(&(1..=60).collect::<Vec<u64>>());
+        assert!(solve_collapsed(&distinct, &make_config(10, 1, 3), Collapse::Always).is_none());
+        let repeated = make_entries(&(0..60).map(|i| 1 + i % 30).collect::<Vec<u64>>());
+        assert!(solve_collapsed(&repeated, &make_config(10, 1, 3), Collapse::Auto).is_some());
+        assert!(solve_collapsed(&repeated[..40], &make_config(10, 1, 3), Collapse::Auto).is_none());
+        assert!(solve_collapsed(&repeated[..40], &make_config(10, 1, 3), Collapse::Always).is_some());
+        assert!(solve_collapsed(&repeated, &make_config(10, 1, 3), Collapse::Never).is_none());
+        assert_eq!(Collapse::from_option(None), Collapse::Auto);
+    }
+
+    #[test]
+    fn test_matches_row_search() {
+        let mut rng = testing::rng();
+        for _ in 0..500 {
+            let n = 1 + rng.below(16) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(6) * 5).collect();
+            let max = 1 + rng.below(n as u64) as usize;
+            let min = 1 + rng.below(max as u64) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>() + 5);
+            let entries = make_entries(&nums);
+            let config = make_config(target, min, max);
+            let Some((collapsed, _)) = solve_collapsed(&entries, &config, Collapse::Always) else {
+                continue;
+            };
+            let rows = solve_depth_first_with_stats(&entries, &config).0;
+            match (collapsed, rows) {
+                (Ok(Some(found)), Ok(Some(_))) => {
+                    let mut picked = indices(&found);
+                    assert!((min..=max).contains(&picked.len()));
+                    assert_eq!(found.iter().map(|e| e.value).sum::<u64>(), target);
+                    picked.sort_unstable();
+                    picked.dedup();
+                    assert_eq!(picked.len(), found.len(), "a row taken twice");
+                    for e in &found {
+                        assert_eq!(e.value, nums[e.original_index.as_usize()]);
+                        // No unused row of the same value comes before it
+                        assert!((0..e.original_index.as_usize()).all(|i| nums[i] != e.value || picked.contains(&i)));
+                    }
+                }
+                (Ok(None), Ok(None)) => {}
+                (collapsed, rows) => panic!("{:?} [{}, {}] target {}: {:?} vs {:?}", nums, min, max, target, collapsed, rows),
+            }
+        }
+    }
+
+    /// A thousand rows of twelve amounts and up to six picks, with no match (odd
+    /// target, even values): collapsed against the row-by-row B&B, which the
+    /// node limit cuts short.
+    /// Run with `cargo test --release bench_collapse -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_collapse() {
+        let mut rng = testing::rng();
+        let amounts: Vec<u64> = (0..12).map(|_| 2 * (100 + rng.below(5_000))).collect();
+        let nums: Vec<u64> = (0..1_000).map(|_| amounts[rng.below(12) as usize]).collect();
+        let entries = make_entries(&nums);
+        let config = make_config(20_001, 1, 6);
+
+        let start = crate::utils::now_ms();
+        let (result, stats) = solve_collapsed(&entries, &config, Collapse::Auto).unwrap();
+        assert!(matches!(result, Ok(None)));
+        println!("collapsed: {:.1} ms for {} nodes", crate::utils::now_ms() - start, stats.phases[0].nodes);
+
+        let limit = crate::control::NodeLimit(5_000_000);
+        let start = crate::utils::now_ms();
+        let (result, stats) = solve_depth_first_with_stats(&entries, &SolverConfig { controller: &limit, ..config });
+        println!("rows: {:?} after {:.1} ms, {} nodes", result, crate::utils::now_ms() - start, stats.phases[0].nodes);
+    }
+}
@@ -0,0 +1,139 @@
+//! When a search stops early: the question every search loop asks at its
+//! checkpoints, answered by whatever the caller put in `SolverConfig::controller`.
+//!
+//! B&B asks every 4096 nodes and meet-in-the-middle every 65,536 subsets, so a
+//! dynamic call there costs nothing measurable (see bench_controller_dispatch);
+//! a stop ends the search as cancelled. The wasm exports pass the cancel flag;
+//! native callers can pass a node limit, a deadline or their own controller.
+
+use crate::utils::now_ms;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub trait SearchController {
+    /// `nodes` is the work the asking loop has done so far: B&B nodes, or subsets
+    /// enumerated. Once this breaks it should keep breaking, as a search may ask
+    /// again after its loops end to tell a stop from a finish.
+    fn should_stop(&self, nodes: u64) -> ControlFlow<()>;
+}
+
+/// The cancel flag: raised from anywhere, it stops the search at the next checkpoint.
+impl SearchController for AtomicBool {
+    fn should_stop(&self, _nodes: u64) -> ControlFlow<()> {
+        if self.load(Ordering::Relaxed) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+/// Stops a loop once it has done this many nodes. Checked at checkpoints only, so
+/// a loop may run up to one checkpoint interval past it, and one that ends past
+/// it reports as stopped.
+pub struct NodeLimit(pub u64);
+
+impl SearchController for NodeLimit {
+    fn should_stop(&self, nodes: u64) -> ControlFlow<()> {
+        if nodes >= self.0 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+/// Stops once the clock passes this time, in `utils::now_ms()` milliseconds.
+pub struct Deadline(pub f64);
+
+impl Deadline {
+    /// A deadline `ms` from now.
+    pub fn after_ms(ms: f64) -> Self {
+        Deadline(now_ms() + ms)
+    }
+}
+
+impl SearchController for Deadline {
+    fn should_stop(&self, _nodes: u64) -> ControlFlow<()> {
+        if now_ms() >= self.0 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{DEFAULT_HYBRID_BB_BUDGET, NumberEntry, OriginalIndex, SolverConfig, SolveError, solve_depth_first_with_stats};
+    use std::hint::black_box;
+
+    fn config(controller: &dyn SearchController) -> SolverConfig<'_> {
+        SolverConfig {
+            // Odd against even values: B&B has to walk the whole tree
+            target: 2 * 500 + 1,
+            min_count: 1,
+            max_count: 60,
+            controller,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    fn entries() -> Vec<NumberEntry> {
+        (1..=60).map(|i| NumberEntry { value: 2 * i, original_index: OriginalIndex::new(i as usize - 1) }).collect()
+    }
+
+    #[test]
+    fn test_controllers_stop_search() {
+        let flag = AtomicBool::new(true);
+        assert!(matches!(solve_depth_first_with_stats(&entries(), &config(&flag)).0, Err(SolveError::Cancelled)));
+
+        let (result, stats) = solve_depth_first_with_stats(&entries(), &config(&NodeLimit(10_000)));
+        assert!(matches!(result, Err(SolveError::Cancelled)));
+        // Asked every 4096 nodes: the first look past the limit stops it, here
+        // at the end of a scan for the last two picks
+        assert_eq!(stats.phases[0].nodes, 12_296);
+
+        let (result, stats) = solve_depth_first_with_stats(&entries(), &config(&Deadline(0.0)));
+        assert!(matches!(result, Err(SolveError::Cancelled)));
+        assert_eq!(stats.phases[0].nodes, 4109);
+
+        assert_eq!(Deadline::after_ms(60_000.0).should_stop(0), ControlFlow::Continue(()));
+        assert_eq!(NodeLimit(5).should_stop(4), ControlFlow::Continue(()));
+        assert_eq!(NodeLimit(5).should_stop(5), ControlFlow::Break(()));
+    }
+
+    /// The cost of asking through `&dyn` against loading the flag directly.
+    /// Run with `cargo test --release bench_controller_dispatch -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_controller_dispatch() {
+        const CALLS: u64 = 100_000_000;
+        let flag = AtomicBool::new(false);
+        let controller: &dyn SearchController = black_box(&flag);
+
+        let start = now_ms();
+        let mut stops = 0u64;
+        for nodes in 0..CALLS {
+            black_box(nodes);
+            stops += flag.load(Ordering::Relaxed) as u64;
+        }
+        let direct = now_ms() - start;
+
+        let start = now_ms();
+        for nodes in 0..CALLS {
+            stops += controller.should_stop(black_box(nodes)).is_break() as u64;
+        }
+        let dispatched = now_ms() - start;
+        assert_eq!(stops, 0);
+
+        let per_call = |ms: f64| ms * 1e6 / CALLS as f64;
+        println!("direct load: {:.2} ns/call, dyn should_stop: {:.2} ns/call", per_call(direct), per_call(dispatched));
+        // B&B asks once per 4096 nodes
+        println!("added per B&B node: {:.4} ns", (per_call(dispatched) - per_call(direct)).max(0.0) / 4096.0);
+    }
+}
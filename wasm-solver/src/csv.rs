@@ -0,0 +1,263 @@
+//! One numeric column read straight out of CSV text, so a large file does not
+//! have to become a JS array first.
+//!
+//! The reader follows RFC 4180: fields in double quotes may hold the delimiter,
+//! line breaks and doubled quotes, and records end in LF, CRLF or a lone CR.
+//! It is lenient where files in the wild are sloppy: text after a closing
+//! quote joins the field, and a quote never closed runs to the end of the text.
+//!
+//! Every record after the header is a row, blank lines included; only a final
+//! line break does not start one. Row k is original index k, so indices in the
+//! payloads count data rows from 0 with the header left out: spreadsheet row
+//! k + 2 when there is one. Rows whose field is missing or not a number keep
+//! their index, as a NaN the input pipeline skips, and are listed by index
+//! with the reason.
+
+use crate::input::{SkipReason, SkippedIndex};
+
+/// A delimiter the reader cannot tell apart from quoting or line breaks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidDelimiter(pub char);
+
+/// The column, one number per data row.
+#[derive(Debug, Default)]
+pub struct CsvColumn {
+    /// NaN where the row had no usable number
+    pub numbers: Vec<f64>,
+    /// The NaN rows, ascending, as NotANumber or MissingColumn
+    pub unreadable: Vec<SkippedIndex>,
+}
+
+/// The unreadable rows a search over `include_indices` (every row when None)
+/// looks at.
+pub fn considered(unreadable: &[SkippedIndex], include_indices: Option<&[u32]>) -> Vec<SkippedIndex> {
+    match include_indices {
+        None => unreadable.to_vec(),
+        Some(include) => {
+            let mut include = include.to_vec();
+            include.sort_unstable();
+            unreadable.iter().filter(|row| include.binary_search(&row.index).is_ok()).cloned().collect()
+        }
+    }
+}
+
+/// Move considered unreadable rows, which skip_counts sees as NaN, from
+/// NotFinite to their own reasons.
+pub fn recount(counts: &mut Vec<(SkipReason, usize)>, unreadable: &[SkippedIndex]) {
+    let mut by_reason = [0usize; SkipReason::ALL.len()];
+    for &(reason, n) in counts.iter() {
+        by_reason[reason as usize] += n;
+    }
+    for row in unreadable {
+        by_reason[SkipReason::NotFinite as usize] -= 1;
+        by_reason[row.reason as usize] += 1;
+    }
+    *counts = SkipReason::ALL.into_iter().zip(by_reason).filter(|&(_, n)| n > 0).collect();
+}
+
+/// Read field `column` (from 0) of every record in `text`, skipping the first
+/// record when `has_header`.
+pub fn read_column(text: &str, column: usize, delimiter: char, has_header: bool) -> Result<CsvColumn, InvalidDelimiter> {
+    if matches!(delimiter, '"' | '\r' | '\n') {
+        return Err(InvalidDelimiter(delimiter));
+    }
+    let mut out = CsvColumn::default();
+    let mut header = has_header;
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    while chars.peek().is_some() {
+        // One record: only the wanted field is kept
+        let mut index = 0;
+        let mut found = false;
+        field.clear();
+        loop {
+            let wanted = index == column;
+            let mut quoted = false;
+            let mut at_start = true;
+            let end = loop {
+                let Some(c) = chars.next() else { break None };
+                match c {
+                    '"' if quoted => {
+                        if chars.peek() == Some(&'"') {
+                            chars.next();
+                            if wanted {
+                                field.push('"');
+                            }
+                        } else {
+                            quoted = false;
+                        }
+                    }
+                    '"' if at_start => quoted = true,
+                    '\r' | '\n' if !quoted => {
+                        if c == '\r' && chars.peek() == Some(&'\n') {
+                            chars.next();
+                        }
+                        break Some(c);
+                    }
+                    c if c == delimiter && !quoted => break Some(c),
+                    c => {
+                        if wanted {
+                            field.push(c);
+                        }
+                    }
+                }
+                at_start = false;
+            };
+            found |= wanted;
+            match end {
+                Some(c) if c == delimiter => index += 1,
+                _ => break,
+            }
+        }
+        if std::mem::take(&mut header) {
+            continue;
+        }
+        let row = out.numbers.len() as u32;
+        let reason = match (found, parse_number(&field)) {
+            (true, Some(n)) => {
+                out.numbers.push(n);
+                continue;
+            }
+            (true, None) => SkipReason::NotANumber,
+            (false, _) => SkipReason::MissingColumn,
+        };
+        out.numbers.push(f64::NAN);
+        out.unreadable.push(SkippedIndex { index: row, reason });
+    }
+    Ok(out)
+}
+
+/// A number as spreadsheets in different locales write it: "1234.5",
+/// "1,234.50", "1.234,50", "1 234,5", "1'234.50", "-12", "1e3". Spaces (plain,
+/// no-break and narrow no-break) and apostrophes always group digits. With both
+/// '.' and ',' present, the last one is the decimal point. A lone '.' is one
+/// too; a lone ',' groups thousands when exactly three digits follow ("1,234"
+/// is 1234) and is the decimal point otherwise ("12,5"); a repeated one groups.
+/// None for anything else, including "NaN" and "inf".
+pub fn parse_number(field: &str) -> Option<f64> {
+    let field = field.trim();
+    if let Ok(n) = field.parse::<f64>() {
+        return field.bytes().any(|b| b.is_ascii_digit()).then_some(n);
+    }
+    let (negative, body) = match field.as_bytes().first()? {
+        b'-' => (true, &field[1..]),
+        b'+' => (false, &field[1..]),
+        _ => (false, field),
+    };
+    let (dots, commas) = (body.matches('.').count(), body.matches(',').count());
+    let decimal = match (dots, commas) {
+        (0, 0) => None,
+        (_, 0) | (0, _) => {
+            let sep = if dots > 0 { '.' } else { ',' };
+            let at = body.find(sep)?;
+            let grouped = dots + commas > 1
+                || (sep == ',' && body[at + 1..].len() == 3 && body[at + 1..].bytes().all(|b| b.is_ascii_digit()));
+            (!grouped).then_some(sep)
+        }
+        _ => body.rfind(['.', ',']).map(|at| body[at..].chars().next().unwrap()),
+    };
+    if decimal.is_some_and(|sep| body.matches(sep).count() > 1) {
+        return None;
+    }
+
+    let mut plain = String::with_capacity(body.len() + 1);
+    if negative {
+        plain.push('-');
+    }
+    let mut chars = body.chars().peekable();
+    let mut last = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '0'..='9' => plain.push(c),
+            c if Some(c) == decimal => plain.push('.'),
+            // Group separators only ever sit between two digits
+            '.' | ',' | ' ' | '\u{a0}' | '\u{202f}' | '\''
+                if last.is_some_and(|l: char| l.is_ascii_digit()) && chars.peek().is_some_and(|n| n.is_ascii_digit()) => {}
+            _ => return None,
+        }
+        last = Some(c);
+    }
+    if !plain.bytes().any(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    plain.parse::<f64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bank statement export with a header, quoted amounts holding the
+    /// delimiter, a quoted memo with a doubled quote and a line break, CRLF
+    /// endings and a row too short for the amount column.
+    const STATEMENT: &str = include_str!("../tests/fixtures/statement.csv");
+
+    #[test]
+    fn test_read_column_of_fixture() {
+        let amounts = read_column(STATEMENT, 2, ',', true).unwrap();
+        let valid: Vec<f64> = amounts.numbers.iter().copied().filter(|n| !n.is_nan()).collect();
+        assert_eq!(valid, vec![1234.5, 12.0, 99.99, 1234.5, 0.5, 250.0]);
+        assert_eq!(amounts.numbers.len(), 8);
+        assert_eq!(amounts.unreadable, vec![
+            SkippedIndex { index: 4, reason: SkipReason::NotANumber },
+            SkippedIndex { index: 6, reason: SkipReason::MissingColumn },
+        ]);
+
+        // The memo column, quoted line break and all, is one field per row
+        let dates = read_column(STATEMENT, 0, ',', true).unwrap();
+        assert_eq!(dates.numbers.len(), 8);
+        let with_header = read_column(STATEMENT, 2, ',', false).unwrap();
+        assert_eq!(with_header.numbers.len(), 9);
+        assert_eq!(with_header.unreadable[0], SkippedIndex { index: 0, reason: SkipReason::NotANumber });
+    }
+
+    #[test]
+    fn test_read_column_records() {
+        let column = |text: &str, column: usize| read_column(text, column, ';', false).unwrap().numbers;
+        assert_eq!(column("1;2\r\n3;4\n5;6\r7;8", 1), vec![2.0, 4.0, 6.0, 8.0]);
+        // A final line break ends the last row rather than starting one
+        assert_eq!(column("1\n2\n", 0), vec![1.0, 2.0]);
+        assert_eq!(column("", 0), Vec::<f64>::new());
+        // A blank line is a row, with nothing in it
+        let blank = read_column("1\n\n3", 0, ';', false).unwrap();
+        assert!(blank.numbers[1].is_nan());
+        assert_eq!(blank.unreadable, vec![SkippedIndex { index: 1, reason: SkipReason::NotANumber }]);
+        // Quoted: delimiters, line breaks and doubled quotes; a quote never closed runs on
+        assert_eq!(column("\"a;\n\"\"b\"\"\";\"1,5\"\n\"x\";2", 1), vec![1.5, 2.0]);
+        let unclosed = read_column("1;2\n3;\"4\n5;6", 1, ';', false).unwrap();
+        assert_eq!(unclosed.numbers.len(), 2);
+        assert_eq!(unclosed.unreadable, vec![SkippedIndex { index: 1, reason: SkipReason::NotANumber }]);
+        assert_eq!(read_column("1\t2", 1, '\t', false).unwrap().numbers, vec![2.0]);
+        assert_eq!(read_column("1", 0, '"', false).unwrap_err(), InvalidDelimiter('"'));
+        assert_eq!(read_column("1", 0, '\n', false).unwrap_err(), InvalidDelimiter('\n'));
+    }
+
+    #[test]
+    fn test_parse_number_locales() {
+        for (text, expected) in [
+            ("1234.5", 1234.5),
+            (" 42 ", 42.0),
+            ("-12", -12.0),
+            ("+7", 7.0),
+            ("1e3", 1000.0),
+            (".5", 0.5),
+            ("1,234.50", 1234.5),
+            ("1.234,50", 1234.5),
+            ("1 234,5", 1234.5),
+            ("1\u{a0}234,5", 1234.5),
+            ("1'234.50", 1234.5),
+            ("1,234,567", 1234567.0),
+            ("1.234.567", 1234567.0),
+            ("1,234", 1234.0),
+            ("12,5", 12.5),
+            ("1,2345", 1.2345),
+            ("1.234", 1.234),
+            ("-1.234,5", -1234.5),
+        ] {
+            assert_eq!(parse_number(text), Some(expected), "{:?}", text);
+        }
+        for text in ["", "-", "abc", "NaN", "inf", "12abc", "1,2,3.4,5", "1..2", ",", "1 ,5", "$5", "1.2.3,4,5"] {
+            assert_eq!(parse_number(text), None, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Datasets registered once and queried by handle, so repeated searches over
+//! the same numbers don't re-marshal and re-convert the whole array.
+//!
+//! A dataset keeps its usable entries sorted by value, so a query takes the
+//! prefix that fits its target instead of filtering and sorting everything.
+//! Edits (`update`, `append`) keep that order with targeted inserts and
+//! removals; the suffix sums and fingerprint are rebuilt lazily by `prepare`,
+//! once before the next query rather than after every edit. Batch searches run
+//! on a view of the sorted entries rather than a copy; an edit while one holds
+//! it copies the entries first, leaving the search as it started.
+
+use crate::batch::SortedView;
+use crate::input::{self, SkipReason, fingerprint, scale_value};
+use crate::solver::{NumberEntry, OriginalIndex};
+use crate::utils::Fnv1a;
+use std::hash::Hasher;
+use std::rc::Rc;
+
+pub struct Dataset {
+    pub scale: u32,
+    /// Rows as passed in or last edited, usable or not
+    numbers: Vec<f64>,
+    /// Usable entries in original order, not yet filtered by any target
+    pub entries: Vec<NumberEntry>,
+    /// The same entries by value, ties by original index
+    sorted: Rc<Vec<NumberEntry>>,
+    /// suffix_sum[i] = sum of sorted[i..].value, with a trailing 0
+    suffix_sum: Vec<u128>,
+    /// Fingerprint of the raw numbers, recorded in exported batch state
+    pub fingerprint: u64,
+    /// Edited since suffix_sum and fingerprint were last rebuilt
+    dirty: bool,
+}
+
+/// Aggregate view of a dataset's usable entries, in scaled units.
+#[derive(Debug, PartialEq)]
+pub struct DatasetSummary {
+    pub input_len: usize,
+    pub count: usize,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+    pub total: u128,
+    /// Most frequent repeated values as (value, occurrences), most frequent first
+    pub top_duplicates: Vec<(u64, usize)>,
+}
+
+const TOP_DUPLICATES: usize = 10;
+
+impl Dataset {
+    pub fn new(numbers: &[f64], scale: u32) -> Self {
+        let entries = numbers.iter()
+            .enumerate()
+            .filter_map(|(original_index, &n)| {
+                scale_value(n, scale).ok().map(|value| NumberEntry { value, original_index: OriginalIndex::new(original_index) })
+            })
+            .collect();
+        Self::from_parts(scale, numbers.to_vec(), entries, fingerprint(numbers))
+    }
+
+    fn from_parts(scale: u32, numbers: Vec<f64>, entries: Vec<NumberEntry>, fingerprint: u64) -> Self {
+        let mut sorted = entries.clone();
+        sorted.sort_unstable_by_key(NumberEntry::value_order);
+        let sorted = Rc::new(sorted);
+        let mut dataset = Dataset { scale, numbers, entries, sorted, suffix_sum: Vec::new(), fingerprint, dirty: false };
+        dataset.rebuild_suffix_sums();
+        dataset
+    }
+
+    /// Number of rows passed in, usable or not.
+    pub fn input_len(&self) -> usize {
+        self.numbers.len()
+    }
+
+    /// Entries usable against `target`, i.e. not larger than it, by value.
+    pub fn entries_for_target(&self, target: u64) -> Vec<NumberEntry> {
+        self.view_for_target(target).to_vec()
+    }
+
+    /// The same entries without copying them, for a batch search.
+    pub fn view_for_target(&self, target: u64) -> SortedView {
+        SortedView::new(Rc::clone(&self.sorted), self.sorted.partition_point(|e| e.value <= target))
+    }
+
+    /// Rows entries_for_target leaves out, by reason (see input::skip_counts).
+    pub fn skip_counts(&self, target: u64) -> Vec<(SkipReason, usize)> {
+        input::skip_counts(&self.numbers, target, self.scale, None)
+    }
+
+    /// Set row `index` to `n`, as if the dataset had been created with it. A row
+    /// that becomes unusable drops out, one that becomes usable joins. False,
+    /// changing nothing, when there is no such row.
+    pub fn update(&mut self, index: usize, n: f64) -> bool {
+        let Some(row) = self.numbers.get_mut(index) else { return false };
+        *row = n;
+        let value = scale_value(n, self.scale).ok();
+        match (self.entries.binary_search_by_key(&index, |e| e.original_index.as_usize()), value) {
+            (Ok(pos), Some(value)) => {
+                let old = std::mem::replace(&mut self.entries[pos].value, value);
+                self.remove_sorted(old, index);
+                self.insert_sorted(NumberEntry { value, original_index: OriginalIndex::new(index) });
+            }
+            (Ok(pos), None) => {
+                let old = self.entries.remove(pos).value;
+                self.remove_sorted(old, index);
+            }
+            (Err(pos), Some(value)) => {
+                let entry = NumberEntry { value, original_index: OriginalIndex::new(index) };
+                self.entries.insert(pos, entry.clone());
+                self.insert_sorted(entry);
+            }
+            (Err(_), None) => {}
+        }
+        self.dirty = true;
+        true
+    }
+
+    /// Add rows after the existing ones, as if the dataset had been created with them.
+    pub fn append(&mut self, numbers: &[f64]) {
+        let first = self.numbers.len();
+        self.numbers.extend_from_slice(numbers);
+        let mut added: Vec<NumberEntry> = numbers.iter()
+            .enumerate()
+            .filter_map(|(offset, &n)| {
+                scale_value(n, self.scale).ok().map(|value| NumberEntry { value, original_index: OriginalIndex::new(first + offset) })
+            })
+            .collect();
+        self.entries.extend_from_slice(&added);
+        // One merge instead of an insert per row
+        added.sort_unstable_by_key(NumberEntry::value_order);
+        let old = Rc::unwrap_or_clone(std::mem::take(&mut self.sorted));
+        let mut sorted = Vec::with_capacity(old.len() + added.len());
+        let (mut old, mut added) = (old.into_iter().peekable(), added.into_iter().peekable());
+        while let (Some(a), Some(b)) = (old.peek(), added.peek()) {
+            let next = if a.value_order() <= b.value_order() { old.next() } else { added.next() };
+            sorted.extend(next);
+        }
+        sorted.extend(old.chain(added));
+        self.sorted = Rc::new(sorted);
+        self.dirty = true;
+    }
+
+    fn remove_sorted(&mut self, value: u64, index: usize) {
+        if let Ok(pos) = self.sorted.binary_search_by_key(&(value, OriginalIndex::new(index)), NumberEntry::value_order) {
+            Rc::make_mut(&mut self.sorted).remove(pos);
+        }
+    }
+
+    fn insert_sorted(&mut self, entry: NumberEntry) {
+        let pos = self.sorted.binary_search_by_key(&entry.value_order(), NumberEntry::value_order).unwrap_or_else(|pos| pos);
+        Rc::make_mut(&mut self.sorted).insert(pos, entry);
+    }
+
+    fn rebuild_suffix_sums(&mut self) {
+        let mut suffix_sum = vec![0u128; self.sorted.len() + 1];
+        for (i, e) in self.sorted.iter().enumerate().rev() {
+            suffix_sum[i] = suffix_sum[i + 1] + e.value as u128;
+        }
+        self.suffix_sum = suffix_sum;
+    }
+
+    /// Bring the suffix sums and fingerprint up to date after edits; cheap when
+    /// there were none. Call before reading either.
+    pub fn prepare(&mut self) {
+        if self.dirty {
+            self.rebuild_suffix_sums();
+            self.fingerprint = fingerprint(&self.numbers);
+            self.dirty = false;
+        }
+    }
+
+    pub fn summary(&self) -> DatasetSummary {
+        debug_assert!(!self.dirty, "summary of an unprepared dataset");
+        let mut top_duplicates: Vec<(u64, usize)> = self.sorted.chunk_by(|a, b| a.value == b.value)
+            .filter(|run| run.len() > 1)
+            .map(|run| (run[0].value, run.len()))
+            .collect();
+        // Ties broken by value so the list is deterministic
+        top_duplicates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        top_duplicates.truncate(TOP_DUPLICATES);
+
+        DatasetSummary {
+            input_len: self.input_len(),
+            count: self.sorted.len(),
+            min: self.sorted.first().map(|e| e.value),
+            max: self.sorted.last().map(|e| e.value),
+            total: self.suffix_sum[0],
+            top_duplicates,
+        }
+    }
+}
+
+/// A dataset arriving in chunks, so JS can hand over a huge array between
+/// animation frames. Each chunk is scaled and hashed as it arrives, leaving
+/// finishing only the sort of the usable entries.
+pub struct DatasetLoader {
+    scale: u32,
+    expected_len: usize,
+    numbers: Vec<f64>,
+    entries: Vec<NumberEntry>,
+    loaded: usize,
+    /// Same hash as `fingerprint`, fed incrementally
+    hash: Fnv1a,
+}
+
+impl DatasetLoader {
+    pub fn new(expected_len: usize, scale: u32) -> Self {
+        let mut hash = Fnv1a::new();
+        hash.write_u64(expected_len as u64);
+        DatasetLoader {
+            scale,
+            expected_len,
+            numbers: Vec::with_capacity(expected_len),
+            entries: Vec::with_capacity(expected_len),
+            loaded: 0,
+            hash,
+        }
+    }
+
+    /// Add the next rows. False, adding nothing, when they would overrun the
+    /// expected length.
+    pub fn append(&mut self, chunk: &[f64]) -> bool {
+        if chunk.len() > self.expected_len - self.loaded {
+            return false;
+        }
+        self.numbers.extend_from_slice(chunk);
+        for (offset, &n) in chunk.iter().enumerate() {
+            self.hash.write_u64(n.to_bits());
+            if let Ok(value) = scale_value(n, self.scale) {
+                self.entries.push(NumberEntry { value, original_index: OriginalIndex::new(self.loaded + offset) });
+            }
+        }
+        self.loaded += chunk.len();
+        true
+    }
+
+    /// The dataset, or None (dropping the rows) while fewer than the expected
+    /// rows have arrived.
+    pub fn finish(self) -> Option<Dataset> {
+        (self.loaded == self.expected_len)
+            .then(|| Dataset::from_parts(self.scale, self.numbers, self.entries, self.hash.finish()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_dataset_keeps_original_indices() {
+        let ds = Dataset::new(&[5.0, 0.0, 3.0, f64::NAN, 12.0], 0);
+        let indices: Vec<usize> = ds.entries.iter().map(|e| e.original_index.as_usize()).collect();
+        assert_eq!(indices, vec![0, 2, 4]);
+
+        let filtered: Vec<usize> = ds.entries_for_target(10).iter().map(|e| e.original_index.as_usize()).collect();
+        // By value
+        assert_eq!(filtered, vec![2, 0]);
+    }
+
+    #[test]
+    fn test_chunked_load_matches_single_shot() {
+        let numbers: Vec<f64> = (0..100_000u32)
+            .map(|i| match i % 7 {
+                0 => f64::NAN,
+                1 => -(i as f64),
+                _ => (i % 1000) as f64 * 0.37,
+            })
+            .collect();
+        let expected = Dataset::new(&numbers, 2);
+
+        let mut loader = DatasetLoader::new(numbers.len(), 2);
+        let mut rest = &numbers[..];
+        for size in [1, 0, 777, 31_000, 5].iter().cycle() {
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            assert!(loader.append(chunk));
+            rest = tail;
+            if rest.is_empty() {
+                break;
+            }
+        }
+        let loaded = loader.finish().unwrap();
+        let rows = |ds: &Dataset| -> Vec<(usize, u64)> { ds.entries.iter().map(|e| (e.original_index.as_usize(), e.value)).collect() };
+        assert_eq!(rows(&loaded), rows(&expected));
+        assert_eq!(loaded.input_len(), expected.input_len());
+        assert_eq!(loaded.fingerprint, expected.fingerprint);
+        assert_eq!(loaded.summary(), expected.summary());
+    }
+
+    #[test]
+    fn test_chunked_load_checks_length() {
+        let mut loader = DatasetLoader::new(3, 0);
+        assert!(loader.append(&[1.0, 2.0]));
+        assert!(!loader.append(&[3.0, 4.0]));
+        assert!(loader.append(&[]));
+        assert!(loader.append(&[3.0]));
+        assert!(!loader.append(&[4.0]));
+        assert_eq!(loader.finish().unwrap().entries.len(), 3);
+
+        let mut loader = DatasetLoader::new(3, 0);
+        loader.append(&[1.0]);
+        assert!(loader.finish().is_none());
+    }
+
+    #[test]
+    fn test_batch_searches_share_the_sorted_entries() {
+        use crate::batch::{BatchEntries, BatchSearchState};
+
+        let numbers: Vec<f64> = (0..40u32).map(|i| f64::from(i * 7 % 23 + 1)).collect();
+        let mut ds = Dataset::new(&numbers, 0);
+        let on = |ds: &Dataset, target: u64| {
+            BatchSearchState::new_budgeted(BatchEntries::Sorted(ds.view_for_target(target)), target, 1, 3, 10_000, None)
+        };
+        let copied = |ds: &Dataset, target: u64| BatchSearchState::new(&ds.entries_for_target(target), target, 1, 3, 10_000);
+        let combos = |batches: &mut dyn FnMut() -> Option<Vec<Vec<NumberEntry>>>| {
+            let mut combos: Vec<Vec<usize>> = std::iter::from_fn(batches).flatten()
+                .map(|combo| {
+                    let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+                    indices.sort_unstable();
+                    indices
+                })
+                .collect();
+            combos.sort_unstable();
+            combos
+        };
+        let run = |state: &mut BatchSearchState| {
+            let mut finished = false;
+            combos(&mut || (!finished).then(|| {
+                let result = state.search_batch(1000);
+                finished = result.finished;
+                result.new_results
+            }))
+        };
+        let expected = (run(&mut copied(&ds, 12)), run(&mut copied(&ds, 30)));
+        assert!(!expected.0.is_empty() && !expected.1.is_empty());
+
+        // Two searches with different targets over one array, taking turns
+        let (mut small, mut large) = (on(&ds, 12), on(&ds, 30));
+        assert_eq!(Rc::strong_count(&ds.sorted), 3);
+        let mut turns = [(&mut small, false), (&mut large, false)];
+        let mut found = [Vec::new(), Vec::new()];
+        while turns.iter().any(|(_, finished)| !finished) {
+            for ((state, finished), found) in turns.iter_mut().zip(&mut found) {
+                if !*finished {
+                    let result = state.search_batch(500);
+                    *finished = result.finished;
+                    found.extend(result.new_results);
+                }
+            }
+        }
+        let [small_found, large_found] = found;
+        let mut small_found = small_found.into_iter();
+        let mut large_found = large_found.into_iter();
+        assert_eq!(combos(&mut || small_found.next().map(|c| vec![c])), expected.0);
+        assert_eq!(combos(&mut || large_found.next().map(|c| vec![c])), expected.1);
+
+        // An edit mid-search copies the entries, and the search carries on over
+        // the numbers it started with
+        let mut search = on(&ds, 12);
+        let first = search.search_batch(100);
+        assert!(!first.finished);
+        ds.update(0, 11.0);
+        ds.append(&[1.0, 2.0]);
+        ds.prepare();
+        assert_eq!(Rc::strong_count(&ds.sorted), 1);
+        let mut found = run(&mut search);
+        found.extend(combos(&mut { let mut first = Some(first.new_results); move || first.take() }));
+        found.sort_unstable();
+        assert_eq!(found, expected.0);
+    }
+
+    #[test]
+    fn test_dataset_summary() {
+        let ds = Dataset::new(&[1.5, 2.0, 1.5, -1.0, 7.25, 2.0, 1.5], 2);
+        let summary = ds.summary();
+        assert_eq!(summary.input_len, 7);
+        assert_eq!(summary.count, 6);
+        assert_eq!(summary.min, Some(150));
+        assert_eq!(summary.max, Some(725));
+        assert_eq!(summary.total, 150 * 3 + 200 * 2 + 725);
+        assert_eq!(summary.top_duplicates, vec![(150, 3), (200, 2)]);
+    }
+
+    #[test]
+    fn test_dataset_summary_caps_duplicates() {
+        let numbers: Vec<f64> = (1..=15).flat_map(|v| [v as f64, v as f64]).collect();
+        let summary = Dataset::new(&numbers, 0).summary();
+        assert_eq!(summary.top_duplicates.len(), TOP_DUPLICATES);
+        assert_eq!(summary.top_duplicates[0], (1, 2));
+    }
+
+    type Rows = Vec<(usize, u64)>;
+
+    /// Everything a query or summary reads, to compare against a fresh build.
+    fn observable(ds: &mut Dataset) -> (Rows, Rows, DatasetSummary, u64) {
+        ds.prepare();
+        let rows = |entries: &[NumberEntry]| entries.iter().map(|e| (e.original_index.as_usize(), e.value)).collect();
+        (rows(&ds.entries), rows(&ds.entries_for_target(u64::MAX)), ds.summary(), ds.fingerprint)
+    }
+
+    #[test]
+    fn test_edits_match_rebuild() {
+        let mut rng = testing::rng();
+        let mut numbers: Vec<f64> = (0..300).map(|i| (i % 40) as f64 * 0.5).collect();
+        let mut ds = Dataset::new(&numbers, 1);
+        for step in 0..400 {
+            if step % 25 == 0 {
+                let added: Vec<f64> = (0..rng.below(30)).map(|_| rng.below(50) as f64 * 0.5).collect();
+                numbers.extend_from_slice(&added);
+                ds.append(&added);
+            } else {
+                let index = rng.below(numbers.len() as u64) as usize;
+                // Mostly usable values, sometimes one that drops the row out
+                let n = match rng.below(8) {
+                    0 => f64::NAN,
+                    1 => -1.0,
+                    2 => 0.01,
+                    _ => rng.below(50) as f64 * 0.5,
+                };
+                numbers[index] = n;
+                assert!(ds.update(index, n));
+            }
+            if step % 10 == 0 {
+                assert_eq!(observable(&mut ds), observable(&mut Dataset::new(&numbers, 1)), "step {}", step);
+            }
+        }
+        assert_eq!(observable(&mut ds), observable(&mut Dataset::new(&numbers, 1)));
+        assert!(!ds.update(numbers.len(), 1.0));
+    }
+
+    #[test]
+    fn test_entries_for_target_is_sorted_prefix() {
+        let ds = Dataset::new(&[5.0, 3.0, 9.0, 3.0, 1.0], 0);
+        let rows: Vec<(usize, u64)> = ds.entries_for_target(5).iter().map(|e| (e.original_index.as_usize(), e.value)).collect();
+        assert_eq!(rows, vec![(4, 1), (1, 3), (3, 3), (0, 5)]);
+    }
+
+    #[test]
+    fn test_empty_dataset_summary() {
+        let summary = Dataset::new(&[], 0).summary();
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.min, None);
+        assert_eq!(summary.total, 0);
+    }
+}
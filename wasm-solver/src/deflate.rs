@@ -0,0 +1,300 @@
+//! A tiny raw DEFLATE (RFC 1951): greedy LZ77 matching coded with the fixed
+//! Huffman tables, one block. Enough to shrink exported sessions, which are
+//! mostly repeated little-endian integers, without a compression dependency.
+//!
+//! inflate reads stored and fixed-Huffman blocks, so it takes anything deflate
+//! writes; dynamic-Huffman blocks from other encoders are rejected as corrupt.
+
+use crate::export::DecodeError;
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Candidates tried per position; more compresses a little better, slower
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// `len` bits of `value`, least significant first
+    fn bits(&mut self, value: u32, len: u32) {
+        self.acc |= (value as u64) << self.bits;
+        self.bits += len;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// A Huffman code, which DEFLATE packs most significant bit first
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(w: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => w.code(0x30 + symbol, 8),
+        144..=255 => w.code(0x190 + symbol - 144, 9),
+        256..=279 => w.code(symbol - 256, 7),
+        _ => w.code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal(w, 257 + code as u32);
+    w.bits((len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    w.code(code as u32, 5);
+    w.bits((dist - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code] as u32);
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(&data[pos..]);
+        prev[pos % WINDOW] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// Compress `data` into a single fixed-Huffman DEFLATE block.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter { out: Vec::with_capacity(data.len() / 2), acc: 0, bits: 0 };
+    // BFINAL, then BTYPE 01 (fixed Huffman)
+    w.bits(1, 1);
+    w.bits(1, 2);
+    // Most recent position for each hash, and the previous one with the same
+    // hash for each position in the window
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[pos..pos + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let older = prev[candidate % WINDOW];
+                // Slots are reused once the window moves on; stop at a stale one
+                if older == usize::MAX || older >= candidate {
+                    break;
+                }
+                candidate = older;
+                chain += 1;
+            }
+        }
+        if best.0 >= MIN_MATCH {
+            write_match(&mut w, best.0, best.1);
+            for p in pos..pos + best.0 {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += best.0;
+        } else {
+            write_literal(&mut w, data[pos] as u32);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    write_literal(&mut w, 256);
+    w.finish()
+}
+
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32, DecodeError> {
+        let byte = *self.buf.get(self.pos).ok_or(DecodeError::Truncated)?;
+        let bit = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(bit as u32)
+    }
+
+    /// `len` bits, least significant first
+    fn bits(&mut self, len: u32) -> Result<u32, DecodeError> {
+        let mut v = 0;
+        for i in 0..len {
+            v |= self.bit()? << i;
+        }
+        Ok(v)
+    }
+
+    /// A Huffman code of `len` bits, most significant first
+    fn code(&mut self, len: u32) -> Result<u32, DecodeError> {
+        let mut v = 0;
+        for _ in 0..len {
+            v = (v << 1) | self.bit()?;
+        }
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+fn read_literal(r: &mut BitReader) -> Result<u32, DecodeError> {
+    let mut code = r.code(7)?;
+    if code <= 0x17 {
+        return Ok(256 + code);
+    }
+    code = (code << 1) | r.bit()?;
+    match code {
+        0x30..=0xbf => return Ok(code - 0x30),
+        0xc0..=0xc7 => return Ok(280 + code - 0xc0),
+        _ => {}
+    }
+    code = (code << 1) | r.bit()?;
+    match code {
+        0x190..=0x1ff => Ok(144 + code - 0x190),
+        _ => Err(DecodeError::Corrupt),
+    }
+}
+
+/// Decompress raw DEFLATE data, refusing to produce more than `max_len` bytes.
+pub fn inflate(data: &[u8], max_len: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut r = BitReader { buf: data, pos: 0, bit: 0 };
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = r.bit()? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let header = data.get(r.pos..r.pos + 4).ok_or(DecodeError::Truncated)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(DecodeError::Corrupt);
+                }
+                r.pos += 4;
+                let bytes = data.get(r.pos..r.pos + len as usize).ok_or(DecodeError::Truncated)?;
+                if out.len() + bytes.len() > max_len {
+                    return Err(DecodeError::Corrupt);
+                }
+                out.extend_from_slice(bytes);
+                r.pos += len as usize;
+            }
+            1 => loop {
+                let symbol = read_literal(&mut r)?;
+                if symbol == 256 {
+                    break;
+                }
+                if symbol < 256 {
+                    if out.len() >= max_len {
+                        return Err(DecodeError::Corrupt);
+                    }
+                    out.push(symbol as u8);
+                    continue;
+                }
+                let code = (symbol - 257) as usize;
+                if code >= LENGTH_BASE.len() {
+                    return Err(DecodeError::Corrupt);
+                }
+                let len = LENGTH_BASE[code] as usize + r.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                let code = r.code(5)? as usize;
+                if code >= DIST_BASE.len() {
+                    return Err(DecodeError::Corrupt);
+                }
+                let dist = DIST_BASE[code] as usize + r.bits(DIST_EXTRA[code] as u32)? as usize;
+                if dist > out.len() || out.len() + len > max_len {
+                    return Err(DecodeError::Corrupt);
+                }
+                // Byte by byte: a match may overlap the bytes it produces
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            },
+            _ => return Err(DecodeError::Corrupt),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = testing::rng();
+        let random: Vec<u8> = (0..5000).map(|_| rng.next_u64() as u8).collect();
+        let repetitive: Vec<u8> = (0..20_000u64).flat_map(|i| (i % 37).to_le_bytes()).collect();
+        for data in [Vec::new(), b"a".to_vec(), b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(), random, repetitive.clone()] {
+            let packed = deflate(&data);
+            assert_eq!(inflate(&packed, data.len()).unwrap(), data);
+        }
+        assert!(deflate(&repetitive).len() < repetitive.len() / 20);
+    }
+
+    #[test]
+    fn test_reads_stored_blocks() {
+        // Two stored blocks, the second final: "ab" then "c"
+        let data = [0x00, 2, 0, 0xfd, 0xff, b'a', b'b', 0x01, 1, 0, 0xfe, 0xff, b'c'];
+        assert_eq!(inflate(&data, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let packed = deflate(&[9u8; 1000]);
+        assert_eq!(inflate(&packed, 999), Err(DecodeError::Corrupt));
+        assert_eq!(inflate(&packed[..packed.len() - 1], 1000), Err(DecodeError::Truncated));
+        // Dynamic-Huffman block
+        assert_eq!(inflate(&[0x05], 10), Err(DecodeError::Corrupt));
+        // A match reaching back before the start
+        let mut w = BitWriter { out: Vec::new(), acc: 0, bits: 0 };
+        w.bits(1, 1);
+        w.bits(1, 2);
+        write_match(&mut w, 3, 1);
+        assert_eq!(inflate(&w.finish(), 10), Err(DecodeError::Corrupt));
+    }
+}
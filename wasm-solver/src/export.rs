@@ -0,0 +1,446 @@
+//! Binary export/import of a batch search, so it can be paused, persisted and
+//! resumed, or attached to a bug report and replayed.
+//!
+//! Layout (little-endian):
+//!   magic "TSBS" | format_version u16 | header | BatchSearchState body
+//!
+//! The header is self-contained so it can be inspected without decoding or
+//! activating the state behind it.
+
+use crate::batch::BatchSearchState;
+use crate::input::SkipReason;
+use crate::score::ScoreMode;
+
+const MAGIC: &[u8; 4] = b"TSBS";
+pub const FORMAT_VERSION: u16 = 16;
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeError {
+    BadMagic,
+    /// The bytes end before the structure does
+    Truncated,
+    /// Structurally readable, but inconsistent (indices out of bounds, bad strings, ...)
+    Corrupt,
+    UnsupportedFormat(u16),
+}
+
+impl DecodeError {
+    pub fn code(self) -> &'static str {
+        match self {
+            DecodeError::BadMagic => "not_a_batch_state",
+            DecodeError::Truncated => "truncated_state",
+            DecodeError::Corrupt => "corrupt_state",
+            DecodeError::UnsupportedFormat(_) => "unsupported_format_version",
+        }
+    }
+}
+
+pub struct ByteWriter {
+    buf: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn new() -> Self {
+        ByteWriter { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, v: usize) {
+        self.u64(v as u64);
+    }
+
+    pub fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        ByteReader { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos.checked_add(len).ok_or(DecodeError::Truncated)?;
+        let bytes = self.buf.get(self.pos..end).ok_or(DecodeError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, DecodeError> {
+        usize::try_from(self.u64()?).map_err(|_| DecodeError::Corrupt)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Corrupt),
+        }
+    }
+
+    /// Read a length prefix for items of at least `min_item_size` bytes each,
+    /// rejecting lengths the remaining input can't possibly hold, so corrupt
+    /// input can't trigger a huge allocation.
+    pub fn len(&mut self, min_item_size: usize) -> Result<usize, DecodeError> {
+        let len = self.usize()?;
+        let remaining = self.buf.len() - self.pos;
+        if len.saturating_mul(min_item_size.max(1)) > remaining {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(len)
+    }
+
+    pub fn str(&mut self) -> Result<String, DecodeError> {
+        let len = self.len(1)?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::Corrupt)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+}
+
+/// The options a batch search was started with, as the caller passed them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchOptions {
+    /// In scaled units
+    pub target: u64,
+    pub scale: u32,
+    pub min_count: u32,
+    pub max_count: u32,
+    pub max_results: u32,
+    pub include_indices: Option<Vec<u32>>,
+}
+
+/// What an exported search keeps beyond BatchOptions: the settings that shape
+/// its payloads, restored on import so a resumed search reports as before.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CarriedOptions {
+    /// `score_results`
+    pub score: ScoreMode,
+    /// init_batch_search_v2's `tag`
+    pub tag: Option<String>,
+    /// `paranoid`: the import checks results against the numbers it is given
+    pub paranoid: bool,
+    /// Started by init_batch_search_u64: payloads keep `exact_integers: true`
+    pub exact_integers: bool,
+    /// Rows init left out, by reason, for get_batch_info
+    pub skip_counts: Option<Vec<(SkipReason, usize)>>,
+}
+
+impl CarriedOptions {
+    fn write(&self, w: &mut ByteWriter) {
+        w.u8(match self.score {
+            ScoreMode::None => 0,
+            ScoreMode::PreferRound => 1,
+            ScoreMode::PreferOrganic => 2,
+        });
+        match &self.tag {
+            None => w.u8(0),
+            Some(tag) => {
+                w.u8(1);
+                w.str(tag);
+            }
+        }
+        w.u8(self.paranoid as u8);
+        w.u8(self.exact_integers as u8);
+        match &self.skip_counts {
+            None => w.u8(0),
+            Some(counts) => {
+                w.u8(1);
+                w.usize(counts.len());
+                for &(reason, rows) in counts {
+                    w.u8(reason as u8);
+                    w.usize(rows);
+                }
+            }
+        }
+    }
+
+    fn read(r: &mut ByteReader, format_version: u16) -> Result<Self, DecodeError> {
+        let score = match r.u8()? {
+            0 => ScoreMode::None,
+            1 => ScoreMode::PreferRound,
+            2 => ScoreMode::PreferOrganic,
+            _ => return Err(DecodeError::Corrupt),
+        };
+        let tag = if r.bool()? { Some(r.str()?) } else { None };
+        let paranoid = format_version >= 13 && r.bool()?;
+        let exact_integers = format_version >= 15 && r.bool()?;
+        let skip_counts = if format_version >= 16 && r.bool()? {
+            let len = r.len(9)?;
+            let mut counts = Vec::with_capacity(len);
+            for _ in 0..len {
+                let reason = *SkipReason::ALL.get(r.u8()? as usize).ok_or(DecodeError::Corrupt)?;
+                counts.push((reason, r.usize()?));
+            }
+            Some(counts)
+        } else {
+            None
+        };
+        Ok(CarriedOptions { score, tag, paranoid, exact_integers, skip_counts })
+    }
+}
+
+/// Everything needed to identify and reproduce an exported search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateHeader {
+    pub format_version: u16,
+    pub crate_version: String,
+    pub options: BatchOptions,
+    /// Fingerprint of the raw numbers the search was built from
+    pub fingerprint: u64,
+    /// Usable entries in the search
+    pub n: u64,
+    pub results_so_far: u64,
+    pub carried: CarriedOptions,
+}
+
+impl StateHeader {
+    pub fn write(&self, w: &mut ByteWriter) {
+        w.str(&self.crate_version);
+        w.u64(self.options.target);
+        w.u32(self.options.scale);
+        w.u32(self.options.min_count);
+        w.u32(self.options.max_count);
+        w.u32(self.options.max_results);
+        match &self.options.include_indices {
+            None => w.u8(0),
+            Some(indices) => {
+                w.u8(1);
+                w.usize(indices.len());
+                for &i in indices {
+                    w.u32(i);
+                }
+            }
+        }
+        w.u64(self.fingerprint);
+        w.u64(self.n);
+        w.u64(self.results_so_far);
+        self.carried.write(w);
+    }
+
+    pub fn read(r: &mut ByteReader, format_version: u16) -> Result<Self, DecodeError> {
+        let crate_version = r.str()?;
+        let target = r.u64()?;
+        let scale = r.u32()?;
+        let min_count = r.u32()?;
+        let max_count = r.u32()?;
+        let max_results = r.u32()?;
+        let include_indices = if r.bool()? {
+            let len = r.len(4)?;
+            Some((0..len).map(|_| r.u32()).collect::<Result<Vec<u32>, _>>()?)
+        } else {
+            None
+        };
+        Ok(StateHeader {
+            format_version,
+            crate_version,
+            options: BatchOptions { target, scale, min_count, max_count, max_results, include_indices },
+            fingerprint: r.u64()?,
+            n: r.u64()?,
+            results_so_far: r.u64()?,
+            // Sessions keep headers of earlier formats, written before these were
+            carried: if format_version >= 12 { CarriedOptions::read(r, format_version)? } else { CarriedOptions::default() },
+        })
+    }
+}
+
+/// Whether a state written by `found` can be read by this build: same major
+/// version, and same minor while still at 0.x.
+pub fn version_compatible(found: &str) -> bool {
+    let major_minor = |v: &str| {
+        let mut parts = v.split('.');
+        (parts.next().map(str::to_string), parts.next().map(str::to_string))
+    };
+    let (found_major, found_minor) = major_minor(found);
+    let (ours_major, ours_minor) = major_minor(CRATE_VERSION);
+    found_major == ours_major && (ours_major.as_deref() != Some("0") || found_minor == ours_minor)
+}
+
+pub fn encode(header: &StateHeader, state: &BatchSearchState) -> Vec<u8> {
+    let mut w = ByteWriter::new();
+    for &b in MAGIC {
+        w.u8(b);
+    }
+    w.u16(FORMAT_VERSION);
+    header.write(&mut w);
+    state.write_to(&mut w);
+    w.into_bytes()
+}
+
+fn read_prelude(r: &mut ByteReader) -> Result<StateHeader, DecodeError> {
+    let mut magic = [0u8; 4];
+    for b in magic.iter_mut() {
+        *b = r.u8().map_err(|_| DecodeError::BadMagic)?;
+    }
+    if &magic != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let format_version = r.u16()?;
+    if format_version != FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedFormat(format_version));
+    }
+    StateHeader::read(r, format_version)
+}
+
+/// Decode only the header; the state body is not touched.
+pub fn decode_header(bytes: &[u8]) -> Result<StateHeader, DecodeError> {
+    read_prelude(&mut ByteReader::new(bytes))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(StateHeader, BatchSearchState), DecodeError> {
+    let mut r = ByteReader::new(bytes);
+    let header = read_prelude(&mut r)?;
+    let state = BatchSearchState::read_from(&mut r)?;
+    if !r.is_empty() {
+        return Err(DecodeError::Corrupt);
+    }
+    Ok((header, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{NumberEntry, OriginalIndex};
+
+    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate()
+            .map(|(i, &v)| NumberEntry { value: v, original_index: OriginalIndex::new(i) })
+            .collect()
+    }
+
+    fn header(results_so_far: u64) -> StateHeader {
+        StateHeader {
+            format_version: FORMAT_VERSION,
+            crate_version: CRATE_VERSION.to_string(),
+            options: BatchOptions {
+                target: 30,
+                scale: 0,
+                min_count: 2,
+                max_count: 5,
+                max_results: 1000,
+                include_indices: Some(vec![1, 4, 9]),
+            },
+            fingerprint: 0xdead_beef,
+            n: 20,
+            results_so_far,
+            carried: CarriedOptions {
+                score: ScoreMode::PreferRound,
+                tag: Some("t".to_string()),
+                paranoid: true,
+                exact_integers: true,
+                skip_counts: Some(vec![(SkipReason::NonPositive, 2), (SkipReason::ProvablyUnusable, 1)]),
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip_resumes_identically() {
+        let entries = make_entries(&(1..=20).collect::<Vec<u64>>());
+        let mut reference = BatchSearchState::new(&entries, 30, 2, 5, 1000);
+        let mut paused = BatchSearchState::new(&entries, 30, 2, 5, 1000);
+        reference.search_batch(137);
+        let first = paused.search_batch(137);
+
+        let bytes = encode(&header(first.total_found as u64), &paused);
+        let (decoded_header, mut resumed) = decode(&bytes).unwrap();
+        assert_eq!(decoded_header, header(first.total_found as u64));
+
+        loop {
+            let a = reference.search_batch(50);
+            let b = resumed.search_batch(50);
+            assert_eq!(a.total_found, b.total_found);
+            assert_eq!(a.nodes_explored, b.nodes_explored);
+            assert_eq!(a.progress, b.progress);
+            let ids = |r: &[Vec<NumberEntry>]| -> Vec<u64> {
+                r.iter().map(|c| crate::solver::combination_id(c)).collect()
+            };
+            assert_eq!(ids(&a.new_results), ids(&b.new_results));
+            if a.finished {
+                assert!(b.finished);
+                break;
+            }
+        }
+        let ids = |s: &BatchSearchState| -> Vec<u64> {
+            s.all_results().iter().map(|c| crate::solver::combination_id(c)).collect()
+        };
+        assert_eq!(ids(&reference), ids(&resumed));
+        assert_eq!(reference.usage_histogram(), resumed.usage_histogram());
+    }
+
+    #[test]
+    fn test_header_decodes_without_body() {
+        let entries = make_entries(&[1, 2, 3]);
+        let state = BatchSearchState::new(&entries, 3, 1, 3, 10);
+        let bytes = encode(&header(0), &state);
+        // Chop off the body entirely: the header is still readable
+        let header_only = &bytes[..bytes.len() - 40];
+        assert_eq!(decode_header(header_only).unwrap(), header(0));
+        assert!(decode(header_only).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_garbage() {
+        assert_eq!(decode_header(b"nope").unwrap_err(), DecodeError::BadMagic);
+        assert_eq!(decode_header(b"").unwrap_err(), DecodeError::BadMagic);
+        assert_eq!(decode_header(b"TSBS\x11\x00").unwrap_err(), DecodeError::UnsupportedFormat(17));
+
+        let entries = make_entries(&[1, 2, 3]);
+        let state = BatchSearchState::new(&entries, 3, 1, 3, 10);
+        let bytes = encode(&header(0), &state);
+        for cut in 0..bytes.len() {
+            assert!(decode(&bytes[..cut]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_version_compatibility() {
+        assert!(version_compatible(CRATE_VERSION));
+        assert!(!version_compatible("99.0.0"));
+        assert!(!version_compatible("garbage"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Invariant checks driven by arbitrary bytes, for the cargo-fuzz targets in
+//! `fuzz/` (built with the `fuzzing` feature) and the randomized tests below.
+//!
+//! Every check panics on a violated invariant, which is what the fuzzer reports.
+
+use crate::batch::BatchSearchState;
+use crate::export::{self, BatchOptions, CarriedOptions, StateHeader, CRATE_VERSION, FORMAT_VERSION};
+use crate::input::unusable_rows;
+use crate::solver::{
+    DEFAULT_HYBRID_BB_BUDGET, NumberEntry, OriginalIndex, SolverConfig, SolveError,
+    solve_all_combinations, solve_depth_first_wide, solve_depth_first_with_stats, solve_subset_sum,
+};
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicBool;
+
+/// Most entries an instance holds, keeping the recursive reference affordable.
+pub const MAX_FUZZ_N: usize = 24;
+
+/// Past this many solutions only validity is checked, not set equality.
+const RESULT_LIMIT: usize = 20_000;
+
+/// Up to this n the solvers are also compared against plain enumeration.
+const BRUTE_FORCE_N: usize = 12;
+
+/// Budget for searching a state decoded from raw fuzz bytes, which may be huge.
+const RAW_STATE_NODES: u64 = 100_000;
+
+/// A search instance decoded from fuzz bytes. Missing bytes read as 0, so every
+/// input decodes to something.
+///
+/// Layout: `n % 25`, `shift % 41`, n value bytes (`(b + 1) << shift`), a target
+/// byte (255 = the total, otherwise `total * b / 254`, at least 1), a min byte
+/// (`b % (n + 2)`), a max byte (`min + b % (n + 2)`), then per-batch node budgets
+/// (`b + 1` each, cycled).
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub values: Vec<u64>,
+    pub target: u64,
+    pub min_count: usize,
+    pub max_count: usize,
+    pub budgets: Vec<u64>,
+}
+
+impl Instance {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut it = bytes.iter().copied();
+        let mut next = || it.next().unwrap_or(0);
+        let n = next() as usize % (MAX_FUZZ_N + 1);
+        let shift = next() % 41;
+        let values: Vec<u64> = (0..n).map(|_| (next() as u64 + 1) << shift).collect();
+        let total: u64 = values.iter().sum();
+        let target = match next() {
+            255 => total,
+            b => (total * b as u64 / 254).max(1),
+        };
+        let min_count = next() as usize % (n + 2);
+        let max_count = min_count + next() as usize % (n + 2);
+        let mut budgets: Vec<u64> = bytes.iter().skip(n + 5).map(|&b| b as u64 + 1).collect();
+        if budgets.is_empty() {
+            budgets.push(64);
+        }
+        Instance { values, target, min_count, max_count, budgets }
+    }
+
+    fn entries(&self) -> Vec<NumberEntry> {
+        self.values.iter().enumerate()
+            .map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) })
+            .collect()
+    }
+
+    fn new_state(&self, max_results: usize) -> BatchSearchState {
+        BatchSearchState::new(&self.entries(), self.target, self.min_count, self.max_count, max_results)
+    }
+
+    /// Panics unless `combo` is a valid solution: right sum and count, distinct
+    /// rows, values matching the input.
+    fn assert_valid(&self, combo: &[NumberEntry]) -> Vec<usize> {
+        assert!(
+            combo.len() >= self.min_count.max(1) && combo.len() <= self.max_count,
+            "{} entries outside [{}, {}]", combo.len(), self.min_count, self.max_count,
+        );
+        assert_eq!(combo.iter().map(|e| e.value as u128).sum::<u128>(), self.target as u128);
+        for e in combo {
+            assert_eq!(self.values.get(e.original_index.as_usize()), Some(&e.value), "entry {:?} not in the input", e);
+        }
+        let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+        indices.sort_unstable();
+        let len = indices.len();
+        indices.dedup();
+        assert_eq!(indices.len(), len, "a row used twice");
+        indices
+    }
+
+    /// Every solution as sorted index lists, by plain enumeration.
+    fn brute_force(&self) -> BTreeSet<Vec<usize>> {
+        let n = self.values.len();
+        (1u32..1 << n)
+            .map(|mask| (0..n).filter(|&i| mask & 1 << i != 0).collect::<Vec<usize>>())
+            .filter(|indices| {
+                indices.len() >= self.min_count && indices.len() <= self.max_count
+                    && indices.iter().map(|&i| self.values[i]).sum::<u64>() == self.target
+            })
+            .collect()
+    }
+
+    /// `combos` without the single rows equal to the target, and how many those were.
+    fn split_target_rows(&self, combos: &BTreeSet<Vec<usize>>) -> (BTreeSet<Vec<usize>>, usize) {
+        let (rows, rest): (BTreeSet<_>, BTreeSet<_>) = combos.iter().cloned()
+            .partition(|combo| combo.len() == 1 && self.values[combo[0]] == self.target);
+        (rest, rows.len())
+    }
+}
+
+/// Solver and batch invariants for the instance `bytes` decode to: results are
+/// valid, rows proved unusable are in none of them, find-one agrees with find-all
+/// on whether a solution exists, the batch
+/// search (run with the instance's budgets) finds exactly the recursive search's
+/// solutions, and its counters and progress never go backwards.
+pub fn check_instance(bytes: &[u8]) {
+    static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+    let instance = Instance::from_bytes(bytes);
+    let entries = instance.entries();
+    let config = SolverConfig {
+        target: instance.target,
+        min_count: instance.min_count,
+        max_count: instance.max_count,
+        controller: &NOT_CANCELLED,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    };
+
+    let find_all = solve_all_combinations(&entries, &config, RESULT_LIMIT);
+    assert!(!find_all.cancelled, "cancelled without a cancel");
+    let complete = !find_all.truncated_by_max_results;
+    let all = find_all.results;
+    let all_set: BTreeSet<Vec<usize>> = all.iter().map(|combo| instance.assert_valid(combo)).collect();
+    assert_eq!(all_set.len(), all.len(), "find-all returned a combination twice");
+    if complete && instance.values.len() <= BRUTE_FORCE_N {
+        // Rows equal to the target are reported once, through one of them
+        let (rest, rows) = instance.split_target_rows(&instance.brute_force());
+        assert_eq!(instance.split_target_rows(&all_set), (rest, rows.min(1)));
+    }
+
+    // Rows proved unusable are in no solution, and without them find-all finds the same ones
+    let unusable = unusable_rows(&entries, instance.target, instance.min_count, instance.max_count);
+    assert!(
+        all_set.iter().flatten().all(|&i| unusable.binary_search(&(i as u32)).is_err()),
+        "a solution uses a row proved unusable",
+    );
+    if complete && !unusable.is_empty() {
+        let pruned: Vec<NumberEntry> = entries.iter().filter(|e| unusable.binary_search(&e.index_u32()).is_err()).cloned().collect();
+        let kept = solve_all_combinations(&pruned, &config, RESULT_LIMIT);
+        let kept_set: BTreeSet<Vec<usize>> = kept.results.iter().map(|combo| instance.assert_valid(combo)).collect();
+        assert_eq!(kept_set, all_set, "pruning lost or added a solution");
+    }
+
+    for found in [solve_subset_sum(&entries, &config), solve_depth_first_with_stats(&entries, &config).0] {
+        match found {
+            Ok(Some(combo)) => {
+                instance.assert_valid(&combo);
+            }
+            Ok(None) | Err(SolveError::EmptyInput) => assert!(all.is_empty(), "find-one missed a solution"),
+            Err(SolveError::Cancelled) => panic!("cancelled without a cancel"),
+            Err(SolveError::OutOfMemory(oom)) => panic!("out of memory without a limit: {:?}", oom),
+            Err(err @ (SolveError::InvalidBounds { .. } | SolveError::TooLarge { .. })) => panic!("refused a valid call: {:?}", err),
+        }
+    }
+
+    // B&B in u32 lanes walks exactly the tree it walks in u64
+    let (narrow, narrow_stats) = solve_depth_first_with_stats(&entries, &config);
+    let (wide, wide_stats) = solve_depth_first_wide(&entries, &config);
+    let fits = instance.target <= u32::MAX as u64 && instance.values.iter().all(|&v| v <= u32::MAX as u64);
+    if narrow_stats.value_bits.is_some() {
+        assert_eq!(narrow_stats.value_bits, Some(if fits { 32 } else { 64 }));
+        assert_eq!(wide_stats.value_bits, Some(64));
+    }
+    assert_eq!(narrow_stats.phases, wide_stats.phases);
+    match (narrow, wide) {
+        (Ok(Some(a)), Ok(Some(b))) => assert_eq!(keys(&[a]), keys(&[b])),
+        (Ok(None), Ok(None)) | (Err(SolveError::EmptyInput), Err(SolveError::EmptyInput)) => {}
+        (a, b) => panic!("u32 lanes gave {:?}, u64 lanes {:?}", a, b),
+    }
+
+    let max_results = if complete { usize::MAX } else { RESULT_LIMIT };
+    let mut state = instance.new_state(max_results);
+    let batch = run_batches(&mut state, &instance.budgets);
+    assert_eq!(batch.len(), state.found());
+    let batch_set: BTreeSet<Vec<usize>> = batch.iter().map(|combo| instance.assert_valid(combo)).collect();
+    assert_eq!(batch_set.len(), batch.len(), "the batch search returned a combination twice");
+    if complete {
+        assert_eq!(batch_set, all_set);
+    } else {
+        assert_eq!(batch.len(), RESULT_LIMIT);
+    }
+}
+
+/// Drive `state` to completion with budgets cycled from `budgets`, checking the
+/// per-batch invariants; returns every new result in delivery order.
+fn run_batches(state: &mut BatchSearchState, budgets: &[u64]) -> Vec<Vec<NumberEntry>> {
+    let mut delivered = state.all_results().to_vec();
+    let (mut nodes, mut found, mut progress) = (state.nodes_explored(), state.found(), 0.0);
+    for &budget in budgets.iter().cycle() {
+        let result = state.search_batch(budget);
+        assert!(result.nodes_explored >= nodes && result.nodes_explored - nodes <= budget);
+        assert_eq!(result.total_found, found + result.new_results.len());
+        assert!(result.progress >= progress && result.progress <= 1.0, "progress {} after {}", result.progress, progress);
+        if !result.finished {
+            assert_eq!(result.nodes_explored - nodes, budget, "an unfinished batch stopped early");
+        }
+        nodes = result.nodes_explored;
+        found = result.total_found;
+        progress = result.progress;
+        delivered.extend_from_slice(&result.new_results);
+        if result.finished {
+            // Below 1.0 only when max_results cut the search short
+            assert_eq!(result.progress == 1.0, result.space_fully_explored());
+            assert!(result.termination_reason.is_some());
+            assert_eq!(result.open_branches, 0);
+            break;
+        }
+    }
+    assert_eq!(keys(&delivered), keys(state.all_results()));
+    delivered
+}
+
+/// Combinations as comparable (value, original index) lists.
+fn keys(combos: &[Vec<NumberEntry>]) -> Vec<Vec<(u64, usize)>> {
+    combos.iter().map(|combo| combo.iter().map(|e| (e.value, e.original_index.as_usize())).collect()).collect()
+}
+
+/// State serialization: arbitrary bytes decode to an error or to a state that can
+/// be searched without panicking; and a search of the instance `bytes` decode to,
+/// exported after its first batch, re-encodes byte for byte and resumes to the
+/// same results as the original.
+pub fn check_state_round_trip(bytes: &[u8]) {
+    if let Ok((_, mut state)) = export::decode(bytes) {
+        let mut nodes = 0;
+        while !state.is_finished() && nodes < RAW_STATE_NODES {
+            state.search_batch(1000);
+            nodes += 1000;
+        }
+    }
+
+    let instance = Instance::from_bytes(bytes);
+    let mut state = instance.new_state(RESULT_LIMIT);
+    state.search_batch(instance.budgets[0]);
+    let header = StateHeader {
+        format_version: FORMAT_VERSION,
+        crate_version: CRATE_VERSION.to_string(),
+        options: BatchOptions {
+            target: instance.target,
+            scale: 0,
+            min_count: instance.min_count as u32,
+            max_count: instance.max_count as u32,
+            max_results: RESULT_LIMIT as u32,
+            include_indices: None,
+        },
+        fingerprint: 0,
+        n: state.entry_count() as u64,
+        results_so_far: state.found() as u64,
+        carried: CarriedOptions::default(),
+    };
+    let encoded = export::encode(&header, &state);
+    let (decoded_header, mut restored) = export::decode(&encoded).expect("an encoded state decodes");
+    assert_eq!(decoded_header, header);
+    assert_eq!(export::encode(&decoded_header, &restored), encoded);
+
+    let budgets = &instance.budgets[1.min(instance.budgets.len() - 1)..];
+    let original = run_batches(&mut state, budgets);
+    let resumed = run_batches(&mut restored, budgets);
+    assert_eq!(keys(&resumed), keys(&original));
+    assert_eq!(restored.nodes_explored(), state.nodes_explored());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use crate::utils::SplitMix64;
+
+    /// The seeds under `fuzz/seeds`: duplicates, target = total, min = max, large
+    /// values, empty input.
+    const SEEDS: &[&[u8]] = &[
+        &[8, 0, 2, 2, 2, 2, 2, 2, 2, 2, 127, 0, 8, 1, 7, 200],
+        &[6, 0, 0, 3, 8, 15, 24, 35, 255, 0, 6, 3],
+        &[10, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 127, 3, 0, 50],
+        &[5, 40, 200, 100, 50, 25, 12, 127, 1, 5, 13],
+        &[0],
+    ];
+
+    fn random_bytes(rng: &mut SplitMix64) -> Vec<u8> {
+        let n = rng.below(15) as u8;
+        let mut bytes = vec![n, if rng.below(4) == 0 { rng.below(41) as u8 } else { 0 }];
+        // Small values collide often, which is where duplicates bite
+        let spread = [4, 16, 256][rng.below(3) as usize];
+        bytes.extend((0..n).map(|_| rng.below(spread) as u8));
+        bytes.extend((0..3 + rng.below(6)).map(|_| rng.below(256) as u8));
+        bytes
+    }
+
+    #[test]
+    fn test_seed_instances() {
+        let dups = Instance::from_bytes(SEEDS[0]);
+        assert_eq!((dups.values, dups.target, dups.min_count, dups.max_count), (vec![3; 8], 12, 0, 8));
+        let total = Instance::from_bytes(SEEDS[1]);
+        assert_eq!(total.target, total.values.iter().sum::<u64>());
+        let fixed = Instance::from_bytes(SEEDS[2]);
+        assert_eq!((fixed.min_count, fixed.max_count, fixed.target), (3, 3, 27));
+        for seed in SEEDS {
+            check_instance(seed);
+            check_state_round_trip(seed);
+        }
+    }
+
+    #[test]
+    fn test_random_instances() {
+        let mut rng = testing::rng();
+        for _ in 0..400 {
+            let bytes = random_bytes(&mut rng);
+            check_instance(&bytes);
+            check_state_round_trip(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_state_bytes_never_panic() {
+        let mut rng = testing::rng();
+        let mut state = Instance::from_bytes(SEEDS[2]).new_state(usize::MAX);
+        state.search_batch(20);
+        let header = StateHeader {
+            format_version: FORMAT_VERSION,
+            crate_version: CRATE_VERSION.to_string(),
+            options: BatchOptions { target: 27, scale: 0, min_count: 3, max_count: 3, max_results: 0, include_indices: None },
+            fingerprint: 0,
+            n: 10,
+            results_so_far: 0,
+            carried: CarriedOptions::default(),
+        };
+        let valid = export::encode(&header, &state);
+        for _ in 0..2000 {
+            let mut bytes = valid.clone();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(bytes.len() as u64) as usize;
+                bytes[at] = rng.below(256) as u8;
+            }
+            bytes.truncate(bytes.len() - rng.below(3) as usize);
+            check_state_round_trip(&bytes);
+        }
+    }
+}
@@ -0,0 +1,921 @@
+//! Converts JS numbers into the integer entries the solver works on.
+//!
+//! `scale` is the number of decimal places kept: values are multiplied by
+//! 10^scale before conversion, so scale 2 turns dollars into cents.
+
+use crate::solver::{NumberEntry, OriginalIndex};
+use crate::utils::Fnv1a;
+use crate::validate::InvalidInput;
+use std::hash::Hasher;
+
+/// Why an input row (or requested index) didn't become a solver entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkipReason {
+    /// NaN or ±Infinity
+    NotFinite,
+    /// Zero or negative before scaling
+    NonPositive,
+    /// Positive, but below one unit at this scale (e.g. 0.4 at scale 0)
+    ZeroAfterScaling,
+    /// Larger than the (scaled) target, so it can never be part of a match
+    ExceedsTarget,
+    /// An include index past the end of the numbers array
+    OutOfRange,
+    /// Too large to hold in u64 units at this scale
+    ScaleOverflow,
+    /// Usable, but listed in the caller's ledger of rows matched in earlier runs
+    PreviouslyMatched,
+    /// A CSV field that doesn't read as a number (see csv.rs)
+    NotANumber,
+    /// A CSV row with fewer fields than the column read
+    MissingColumn,
+    /// Usable, but too large or too small for any count in the window to reach
+    /// the target with it (see unusable_rows)
+    ProvablyUnusable,
+}
+
+impl SkipReason {
+    /// In declaration order, so `reason as usize` indexes it
+    pub const ALL: [SkipReason; 10] = [
+        SkipReason::NotFinite,
+        SkipReason::NonPositive,
+        SkipReason::ZeroAfterScaling,
+        SkipReason::ExceedsTarget,
+        SkipReason::OutOfRange,
+        SkipReason::ScaleOverflow,
+        SkipReason::PreviouslyMatched,
+        SkipReason::NotANumber,
+        SkipReason::MissingColumn,
+        SkipReason::ProvablyUnusable,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::NotFinite => "not_finite",
+            SkipReason::NonPositive => "non_positive",
+            SkipReason::ZeroAfterScaling => "zero_after_scaling",
+            SkipReason::ExceedsTarget => "exceeds_target",
+            SkipReason::OutOfRange => "out_of_range",
+            SkipReason::ScaleOverflow => "scale_overflow",
+            SkipReason::PreviouslyMatched => "previously_matched",
+            SkipReason::NotANumber => "not_a_number",
+            SkipReason::MissingColumn => "missing_column",
+            SkipReason::ProvablyUnusable => "provably_unusable",
+        }
+    }
+}
+
+/// A requested index that could not be used, with the reason.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippedIndex {
+    pub index: u32,
+    pub reason: SkipReason,
+}
+
+/// Convert one input number to integer units at `scale`.
+///
+/// Scale 0 keeps the historical truncating cast. With decimals the caller opted
+/// into exact minor units, so float noise such as 0.29 * 100 = 28.999… is rounded.
+pub fn scale_value(n: f64, scale: u32) -> Result<u64, SkipReason> {
+    scale_by(n, 10f64.powi(scale as i32), scale)
+}
+
+/// scale_value with `factor` = 10^scale worked out by the caller, once per sweep.
+fn scale_by(n: f64, factor: f64, scale: u32) -> Result<u64, SkipReason> {
+    if !n.is_finite() {
+        return Err(SkipReason::NotFinite);
+    }
+    if n <= 0.0 {
+        return Err(SkipReason::NonPositive);
+    }
+    // Below one unit at any scale; a subnormal multiply is slow on most hardware
+    if n < f64::MIN_POSITIVE {
+        return Err(SkipReason::ZeroAfterScaling);
+    }
+    let v = units(n * factor, scale).ok_or(SkipReason::ScaleOverflow)?;
+    if v == 0 {
+        return Err(SkipReason::ZeroAfterScaling);
+    }
+    Ok(v)
+}
+
+/// A target too large for u64 units at the requested scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetOverflow {
+    pub scale: u32,
+    /// Largest smaller scale at which the target fits, if any
+    pub max_scale: Option<u32>,
+}
+
+/// Convert the target to integer units, with the same rounding as scale_value.
+/// Non-finite or negative targets become 0, which nothing can match.
+pub fn scale_target(target: f64, scale: u32) -> Result<u64, TargetOverflow> {
+    if !target.is_finite() || target <= 0.0 {
+        return Ok(0);
+    }
+    to_units(target, scale).ok_or_else(|| TargetOverflow {
+        scale,
+        max_scale: (0..scale).rev().find(|&s| to_units(target, s).is_some()),
+    })
+}
+
+/// A target that falls between two units at its scale, e.g. 100.005 at scale 2,
+/// and the unit scale_target rounded it to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetRounding {
+    pub units: u64,
+    /// Rounded up rather than down
+    pub up: bool,
+}
+
+/// A target refused under `strict_target` for not being a whole number of units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetNotRepresentable {
+    pub target: f64,
+    pub scale: u32,
+    /// Where the target would have been rounded to
+    pub nearest: TargetRounding,
+}
+
+/// How far from a whole unit a scaled target may land and still count as on it:
+/// float noise such as 0.1 + 0.2 = 0.30000000000000004, 30.000000000000004 cents.
+const UNIT_NOISE: f64 = 1e-6;
+
+/// How scale_target rounds a target that is not a whole number of units, beyond
+/// float noise. None at scale 0, which truncates by design like scale_value, and
+/// for targets scale_target maps to 0 or rejects.
+pub fn target_rounding(target: f64, scale: u32) -> Option<TargetRounding> {
+    if scale == 0 || !target.is_finite() || target <= 0.0 {
+        return None;
+    }
+    let scaled = target * 10f64.powi(scale as i32);
+    let units = scaled.round();
+    // Past 2^52 every f64 is a whole or half unit, and the input's own error is larger
+    let noise = UNIT_NOISE.max(scaled * 4.0 * f64::EPSILON);
+    if (scaled - units).abs() <= noise || units >= TWO_POW_64 {
+        return None;
+    }
+    Some(TargetRounding { units: units as u64, up: units > scaled })
+}
+
+/// target_rounding, or the refusal when `strict` asks for an exact target.
+pub fn check_target_rounding(target: f64, scale: u32, strict: bool) -> Result<Option<TargetRounding>, TargetNotRepresentable> {
+    match target_rounding(target, scale) {
+        Some(nearest) if strict => Err(TargetNotRepresentable { target, scale, nearest }),
+        rounding => Ok(rounding),
+    }
+}
+
+/// Share of the positive rows that must lose decimals to their scale before a
+/// payload warns about it: past that, a miss is more likely the cast than the data.
+const FRACTIONAL_WARN_SHARE: f64 = 0.1;
+
+/// Largest scale fractional_inputs suggests; more decimals than this are not cents.
+const MAX_SUGGESTED_SCALE: u32 = 6;
+
+/// Rows whose decimals their scale dropped (truncated at scale 0, rounded past
+/// the last kept place otherwise), e.g. dollars-and-cents floats passed without
+/// a scale, where a target that needs the cents can no longer be reached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FractionalInputs {
+    /// Rows that had decimals past their scale
+    pub fractional: usize,
+    /// Positive finite rows looked at
+    pub considered: usize,
+    /// Smallest scale keeping all of them whole, when one up to 6 does
+    pub suggested_scale: Option<u32>,
+}
+
+impl FractionalInputs {
+    /// `fractional` as a whole percentage of `considered`.
+    pub fn percent(&self) -> u64 {
+        (self.fractional as f64 * 100.0 / self.considered as f64).round() as u64
+    }
+
+    /// The `warnings` entry: "fractional_inputs_truncated: 83% of values had
+    /// decimals; consider scale=2".
+    pub fn warning(&self) -> String {
+        let mut warning = format!("fractional_inputs_truncated: {}% of values had decimals", self.percent());
+        if let Some(scale) = self.suggested_scale {
+            warning.push_str(&format!("; consider scale={}", scale));
+        }
+        warning
+    }
+}
+
+/// Whether `n` has decimals past `scale`, beyond float noise (see target_rounding).
+fn loses_decimals(n: f64, scale: u32) -> bool {
+    let scaled = n * 10f64.powi(scale as i32);
+    let noise = UNIT_NOISE.max(scaled * 4.0 * f64::EPSILON);
+    (scaled - scaled.round()).abs() > noise && scaled < TWO_POW_64
+}
+
+/// One pass over the rows build_entries would look at, counting the positive
+/// ones with decimals past `scale`; Some when at least 10% of them do.
+pub fn fractional_inputs(numbers: &[f64], scale: u32, include_indices: Option<&[u32]>) -> Option<FractionalInputs> {
+    let mut found = FractionalInputs { fractional: 0, considered: 0, suggested_scale: Some(scale) };
+    let mut count = |n: f64| {
+        if !n.is_finite() || n <= 0.0 {
+            return;
+        }
+        found.considered += 1;
+        if loses_decimals(n, scale) {
+            found.fractional += 1;
+            let needed = (scale + 1..=MAX_SUGGESTED_SCALE).find(|&s| !loses_decimals(n, s));
+            found.suggested_scale = found.suggested_scale.zip(needed).map(|(a, b)| a.max(b));
+        }
+    };
+    match include_indices {
+        None => numbers.iter().for_each(|&n| count(n)),
+        Some(indices) => {
+            let mut indices = indices.to_vec();
+            indices.sort_unstable();
+            indices.dedup();
+            indices.iter().filter_map(|&i| numbers.get(i as usize)).for_each(|&n| count(n));
+        }
+    }
+    let significant = found.fractional > 0 && found.fractional as f64 >= FRACTIONAL_WARN_SHARE * found.considered as f64;
+    significant.then_some(found)
+}
+
+/// 2^64, the first f64 that doesn't fit in u64 (u64::MAX itself rounds up to it).
+const TWO_POW_64: f64 = 18_446_744_073_709_551_616.0;
+
+/// None when the units don't fit in u64; a plain `as` cast would saturate silently.
+fn to_units(n: f64, scale: u32) -> Option<u64> {
+    units(n * 10f64.powi(scale as i32), scale)
+}
+
+fn units(scaled: f64, scale: u32) -> Option<u64> {
+    let units = if scale == 0 { scaled.trunc() } else { scaled.round() };
+    (units < TWO_POW_64).then_some(units as u64)
+}
+
+/// Largest value that still converts at `scale`: about u64::MAX / 10^scale,
+/// exactly the last f64 below the overflow.
+pub fn max_safe_value(scale: u32) -> f64 {
+    let mut v = TWO_POW_64 / 10f64.powi(scale as i32);
+    while v > 0.0 && to_units(v, scale).is_none() {
+        v = f64::from_bits(v.to_bits() - 1);
+    }
+    v
+}
+
+/// Fingerprint of the raw input array (length plus exact f64 bits), used to check
+/// that an exported search is being resumed against the same dataset.
+pub fn fingerprint(numbers: &[f64]) -> u64 {
+    fingerprint_bits(numbers.len(), numbers.iter().map(|n| n.to_bits()))
+}
+
+/// fingerprint for integer-unit input: the values themselves stand in for the bits.
+pub fn fingerprint_u64(values: &[u64]) -> u64 {
+    fingerprint_bits(values.len(), values.iter().copied())
+}
+
+fn fingerprint_bits(len: usize, bits: impl Iterator<Item = u64>) -> u64 {
+    let mut hash = Fnv1a::new();
+    hash.write_u64(len as u64);
+    for b in bits {
+        hash.write_u64(b);
+    }
+    hash.finish()
+}
+
+/// Why a set of target rows couldn't be summed into a target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowSumError {
+    /// No target rows were given
+    Empty,
+    /// A target row doesn't exist, or isn't a usable positive number
+    InvalidIndex { index: u32, reason: SkipReason },
+    /// The exact sum doesn't fit in u64
+    Overflow,
+}
+
+/// Exact integer sum of the given rows at `scale`, as a target.
+///
+/// Summing the converted integers rather than the floats avoids the round-off
+/// between a JS-computed target and the wasm-side cast. Repeated indices count once.
+pub fn sum_rows(numbers: &[f64], indices: &[u32], scale: u32) -> Result<u64, RowSumError> {
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.is_empty() {
+        return Err(RowSumError::Empty);
+    }
+
+    let mut total = 0u64;
+    for index in indices {
+        let n = *numbers.get(index as usize).ok_or(RowSumError::InvalidIndex {
+            index,
+            reason: SkipReason::OutOfRange,
+        })?;
+        let v = scale_value(n, scale).map_err(|reason| RowSumError::InvalidIndex { index, reason })?;
+        total = total.checked_add(v).ok_or(RowSumError::Overflow)?;
+    }
+    Ok(total)
+}
+
+/// classify for a sweep over many rows: the same decisions, with 10^scale
+/// worked out once.
+fn row_classifier(scale: u32, target: u64) -> impl Fn(f64) -> Result<u64, SkipReason> {
+    let factor = 10f64.powi(scale as i32);
+    move |n| match scale_by(n, factor, scale)? {
+        v if v > target => Err(SkipReason::ExceedsTarget),
+        v => Ok(v),
+    }
+}
+
+/// scale_value plus the target bound: the full per-row decision build_entries makes.
+pub fn classify(n: f64, scale: u32, target: u64) -> Result<u64, SkipReason> {
+    let v = scale_value(n, scale)?;
+    if v > target {
+        return Err(SkipReason::ExceedsTarget);
+    }
+    Ok(v)
+}
+
+/// classify for values already in integer units (e.g. cents from a backend): no
+/// float conversion or rounding, so only zero and oversized rows are dropped.
+pub fn classify_u64(v: u64, target: u64) -> Result<u64, SkipReason> {
+    match v {
+        0 => Err(SkipReason::NonPositive),
+        v if v > target => Err(SkipReason::ExceedsTarget),
+        v => Ok(v),
+    }
+}
+
+/// Build input entries, preserving original CSV row indices.
+///
+/// When `include_indices` is given, only those original positions are considered.
+/// Duplicates are ignored; out-of-range indices are returned in the skipped list.
+pub fn build_entries(
+    numbers: &[f64],
+    target: u64,
+    scale: u32,
+    include_indices: Option<&[u32]>,
+) -> (Vec<NumberEntry>, Vec<SkippedIndex>) {
+    let rows = uncapped(sweep(numbers, include_indices, usize::MAX, row_classifier(scale, target)));
+    (rows.entries, rows.skipped)
+}
+
+/// How many of the rows build_entries considers it leaves out, by reason: the
+/// reasons with at least one row, in SkipReason::ALL order.
+pub fn skip_counts(numbers: &[f64], target: u64, scale: u32, include_indices: Option<&[u32]>) -> Vec<(SkipReason, usize)> {
+    uncapped(sweep(numbers, include_indices, usize::MAX, row_classifier(scale, target))).skip_counts
+}
+
+/// What build_entries and skip_counts make of the rows, from one sweep.
+#[derive(Debug)]
+pub struct Classified {
+    pub entries: Vec<NumberEntry>,
+    pub skipped: Vec<SkippedIndex>,
+    pub skip_counts: Vec<(SkipReason, usize)>,
+}
+
+/// build_entries and skip_counts in one sweep, for the search entry points:
+/// more than `max_entries` usable rows is a `too_many_entries` error, returned
+/// as soon as the sweep meets one row too many. However hostile the input, it
+/// then costs one pass with no sort, and nothing past the cap is kept.
+pub fn classify_rows(
+    numbers: &[f64],
+    target: u64,
+    scale: u32,
+    include_indices: Option<&[u32]>,
+    max_entries: usize,
+) -> Result<Classified, InvalidInput> {
+    sweep(numbers, include_indices, max_entries, row_classifier(scale, target))
+}
+
+/// classify_rows for values already in integer units; see classify_u64.
+pub fn classify_rows_u64(
+    values: &[u64],
+    target: u64,
+    include_indices: Option<&[u32]>,
+    max_entries: usize,
+) -> Result<Classified, InvalidInput> {
+    sweep(values, include_indices, max_entries, |v| classify_u64(v, target))
+}
+
+/// A sweep with no cap, which cannot fail.
+fn uncapped(rows: Result<Classified, InvalidInput>) -> Classified {
+    rows.unwrap_or_else(|_| unreachable!("no cap to exceed"))
+}
+
+fn sweep<T: Copy>(
+    rows: &[T],
+    include_indices: Option<&[u32]>,
+    max_entries: usize,
+    classify: impl Fn(T) -> Result<u64, SkipReason>,
+) -> Result<Classified, InvalidInput> {
+    let indices = include_indices.map(|indices| {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    });
+    let considered = indices.as_ref().map_or(rows.len(), Vec::len);
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    let mut counts = [0usize; SkipReason::ALL.len()];
+    for k in 0..considered {
+        let (idx, row) = match &indices {
+            None => (k, Some(rows[k])),
+            Some(indices) => (indices[k] as usize, rows.get(indices[k] as usize).copied()),
+        };
+        match row.map(&classify) {
+            Some(Ok(value)) => {
+                if entries.len() == max_entries {
+                    return Err(InvalidInput::TooManyEntries { max: max_entries, scanned: k + 1 });
+                }
+                entries.push(NumberEntry { value, original_index: OriginalIndex::new(idx) });
+            }
+            Some(Err(reason)) => counts[reason as usize] += 1,
+            None => {
+                counts[SkipReason::OutOfRange as usize] += 1;
+                skipped.push(SkippedIndex { index: idx as u32, reason: SkipReason::OutOfRange });
+            }
+        }
+    }
+    let skip_counts = SkipReason::ALL.into_iter().zip(counts).filter(|&(_, n)| n > 0).collect();
+    Ok(Classified { entries, skipped, skip_counts })
+}
+
+/// Add the rows of exactly zero among those build_entries considered, as entries
+/// of value 0, to what it built, in original index order, and take them out of
+/// the NonPositive count of `counts` (see skip_counts). Returns how many came in.
+/// A zero only pads a combination's count, so it is kept only when asked for.
+pub fn keep_zeros(
+    entries: &mut Vec<NumberEntry>,
+    counts: &mut Vec<(SkipReason, usize)>,
+    numbers: &[f64],
+    include_indices: Option<&[u32]>,
+) -> usize {
+    let zeros = uncapped(sweep(numbers, include_indices, usize::MAX, |n| if n == 0.0 { Ok(0) } else { Err(SkipReason::NonPositive) })).entries;
+    let added = zeros.len();
+    if added == 0 {
+        return 0;
+    }
+    entries.extend(zeros);
+    entries.sort_unstable_by_key(|e| e.original_index);
+    if let Some(position) = counts.iter().position(|&(reason, _)| reason == SkipReason::NonPositive) {
+        counts[position].1 -= added;
+        if counts[position].1 == 0 {
+            counts.remove(position);
+        }
+    }
+    added
+}
+
+/// The rows a ledger of earlier matches takes out of a search: each of `indices`,
+/// then for each of `values`, in units at `scale`, one row of that value not yet
+/// taken, smallest original index first. Returns the rows, ascending, and the
+/// values no row was left for, in ledger order. Indices past the end take nothing.
+pub fn previously_matched_rows(numbers: &[f64], scale: u32, indices: &[u32], values: &[f64]) -> (Vec<u32>, Vec<f64>) {
+    let mut taken = vec![false; numbers.len()];
+    for &index in indices {
+        if let Some(row) = taken.get_mut(index as usize) {
+            *row = true;
+        }
+    }
+    // By value, then index: the next free row of a value is always the next in line
+    let mut free: Vec<(u64, usize)> = numbers.iter().enumerate()
+        .filter(|&(i, _)| !taken[i])
+        .filter_map(|(i, &n)| scale_value(n, scale).ok().map(|units| (units, i)))
+        .collect();
+    free.sort_unstable();
+    let mut wanted: Vec<(u64, usize)> = Vec::with_capacity(values.len());
+    let mut unmatched = Vec::new();
+    for (position, &value) in values.iter().enumerate() {
+        match scale_value(value, scale) {
+            Ok(units) => wanted.push((units, position)),
+            Err(_) => unmatched.push(position),
+        }
+    }
+    wanted.sort_unstable();
+
+    let mut next = 0;
+    for (units, position) in wanted {
+        while next < free.len() && free[next].0 < units {
+            next += 1;
+        }
+        if next < free.len() && free[next].0 == units {
+            taken[free[next].1] = true;
+            next += 1;
+        } else {
+            unmatched.push(position);
+        }
+    }
+    unmatched.sort_unstable();
+    let rows = taken.iter().enumerate().filter(|&(_, &t)| t).map(|(i, _)| i as u32).collect();
+    (rows, unmatched.into_iter().map(|position| values[position]).collect())
+}
+
+/// Take `rows` (ascending) out of what build_entries returned, listing each
+/// removed entry in `skipped` as PreviouslyMatched. Rows left out for a reason of
+/// their own keep it. Returns how many entries were removed.
+pub fn exclude_rows(entries: &mut Vec<NumberEntry>, skipped: &mut Vec<SkippedIndex>, rows: &[u32]) -> usize {
+    take_rows(entries, skipped, rows, SkipReason::PreviouslyMatched)
+}
+
+fn take_rows(entries: &mut Vec<NumberEntry>, skipped: &mut Vec<SkippedIndex>, rows: &[u32], reason: SkipReason) -> usize {
+    let before = entries.len();
+    entries.retain(|e| {
+        let listed = rows.binary_search(&e.index_u32()).is_ok();
+        if listed {
+            skipped.push(SkippedIndex { index: e.index_u32(), reason });
+        }
+        !listed
+    });
+    skipped.sort_by_key(|s| s.index);
+    before - entries.len()
+}
+
+/// Rows of `entries` no combination of min_count..=max_count of them summing to
+/// `target` can use, ascending: a value v is out when v plus the min_count - 1
+/// smallest other values already passes the target, or v plus the max_count - 1
+/// largest others still falls short. Each removal can tighten the bounds of the
+/// rest, so this repeats to a fixpoint. Both tests are monotone in v, so the
+/// survivors are a window of the sorted values, shrunk one end at a time.
+///
+/// Nothing is removed when the window itself is empty or cannot be filled, or
+/// when no row would be left: the solvers prove and report those not-founds
+/// as they always have.
+pub fn unusable_rows(entries: &[NumberEntry], target: u64, min_count: usize, max_count: usize) -> Vec<u32> {
+    if max_count == 0 || min_count > max_count {
+        return Vec::new();
+    }
+    let mut sorted: Vec<(u64, u32)> = entries.iter().map(|e| (e.value, e.index_u32())).collect();
+    sorted.sort_unstable();
+    // prefix[i] = sum of sorted[..i]; u128, as a long input can pass u64
+    let mut prefix = Vec::with_capacity(sorted.len() + 1);
+    prefix.push(0u128);
+    for &(value, _) in &sorted {
+        prefix.push(prefix[prefix.len() - 1] + value as u128);
+    }
+    let target = target as u128;
+    let (mut lo, mut hi) = (0, sorted.len());
+    while lo < hi && min_count <= hi - lo {
+        let others = hi - lo - 1;
+        // The largest value with the smallest others
+        let fewest = min_count.saturating_sub(1);
+        if sorted[hi - 1].0 as u128 + prefix[lo + fewest] - prefix[lo] > target {
+            hi -= 1;
+            continue;
+        }
+        // The smallest value with the largest others
+        let most = (max_count - 1).min(others);
+        if (sorted[lo].0 as u128) + prefix[hi] - prefix[hi - most] < target {
+            lo += 1;
+            continue;
+        }
+        break;
+    }
+    if lo == hi {
+        return Vec::new();
+    }
+    let mut rows: Vec<u32> = sorted[..lo].iter().chain(&sorted[hi..]).map(|&(_, index)| index).collect();
+    rows.sort_unstable();
+    rows
+}
+
+/// Take unusable_rows out of built entries, listing each in `skipped` as
+/// ProvablyUnusable; returns how many went.
+pub fn prune_unusable(
+    entries: &mut Vec<NumberEntry>,
+    skipped: &mut Vec<SkippedIndex>,
+    target: u64,
+    min_count: usize,
+    max_count: usize,
+) -> usize {
+    let rows = unusable_rows(entries, target, min_count, max_count);
+    if rows.is_empty() {
+        return 0;
+    }
+    take_rows(entries, skipped, &rows, SkipReason::ProvablyUnusable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fractional_inputs() {
+        // All integral, including float noise and rows never considered
+        assert_eq!(fractional_inputs(&[1.0, 25.0, 0.1 + 0.2 - 0.3 + 7.0, -2.5, f64::NAN], 0, None), None);
+        assert_eq!(fractional_inputs(&[12.34, 0.5, 3.0], 2, None), None);
+
+        // All cents
+        let cents = [12.34, 0.99, 5.5, 100.01];
+        let found = fractional_inputs(&cents, 0, None).unwrap();
+        assert_eq!(found, FractionalInputs { fractional: 4, considered: 4, suggested_scale: Some(2) });
+        assert_eq!(found.warning(), "fractional_inputs_truncated: 100% of values had decimals; consider scale=2");
+        assert_eq!(fractional_inputs(&cents, 1, None).unwrap().fractional, 3);
+
+        // Mixed: five of six with decimals, one past six places
+        let mixed = [10.0, 2.5, 3.25, 1.1, 7.75, 0.1234567];
+        let found = fractional_inputs(&mixed, 0, None).unwrap();
+        assert_eq!((found.fractional, found.considered, found.suggested_scale), (5, 6, None));
+        assert_eq!(found.warning(), "fractional_inputs_truncated: 83% of values had decimals");
+        assert_eq!(fractional_inputs(&mixed[..5], 0, None).unwrap().suggested_scale, Some(2));
+
+        // Under a tenth is noise in the data, not a missing scale
+        let mut mostly = vec![5.0; 9];
+        mostly.push(0.5);
+        assert!(fractional_inputs(&mostly, 0, None).is_some());
+        mostly.push(6.0);
+        assert_eq!(fractional_inputs(&mostly, 0, None), None);
+        // Only the included rows count
+        assert_eq!(fractional_inputs(&mixed, 0, Some(&[0, 0, 9])), None);
+        assert_eq!(fractional_inputs(&mixed, 0, Some(&[1, 0])).unwrap().percent(), 50);
+    }
+
+    #[test]
+    fn test_build_entries_include_dedupes_and_reports_out_of_range() {
+        let numbers = [5.0, 3.0, 7.0, 2.0];
+        let (entries, skipped) = build_entries(&numbers, 10, 0, Some(&[3, 1, 1, 9, 3, 4]));
+        let indices: Vec<usize> = entries.iter().map(|e| e.original_index.as_usize()).collect();
+        assert_eq!(indices, vec![1, 3]);
+        assert_eq!(skipped, vec![
+            SkippedIndex { index: 4, reason: SkipReason::OutOfRange },
+            SkippedIndex { index: 9, reason: SkipReason::OutOfRange },
+        ]);
+    }
+
+    #[test]
+    fn test_skip_counts_match_build_entries() {
+        let numbers = [5.0, -1.0, f64::NAN, 0.4, 12.0, 3.0, 0.0, 11.0];
+        assert_eq!(skip_counts(&numbers, 10, 0, None), vec![
+            (SkipReason::NotFinite, 1),
+            (SkipReason::NonPositive, 2),
+            (SkipReason::ZeroAfterScaling, 1),
+            (SkipReason::ExceedsTarget, 2),
+        ]);
+        let include = [0, 4, 4, 9, 5];
+        let (entries, _) = build_entries(&numbers, 10, 0, Some(&include));
+        let counts = skip_counts(&numbers, 10, 0, Some(&include));
+        assert_eq!(counts, vec![(SkipReason::ExceedsTarget, 1), (SkipReason::OutOfRange, 1)]);
+        assert_eq!(entries.len() + counts.iter().map(|&(_, n)| n).sum::<usize>(), 4);
+        assert_eq!(classify_rows_u64(&[0, 3, 20], 10, None, usize::MAX).unwrap().skip_counts, vec![(SkipReason::NonPositive, 1), (SkipReason::ExceedsTarget, 1)]);
+    }
+
+    #[test]
+    fn test_keep_zeros() {
+        let numbers = [0.0, 5.0, -0.0, -2.0, 0.004, 0.0];
+        let (mut entries, _) = build_entries(&numbers, 1000, 2, None);
+        let mut counts = skip_counts(&numbers, 1000, 2, None);
+        assert_eq!(counts, vec![(SkipReason::NonPositive, 4), (SkipReason::ZeroAfterScaling, 1)]);
+        assert_eq!(keep_zeros(&mut entries, &mut counts, &numbers, None), 3);
+        let rows: Vec<(u64, u32)> = entries.iter().map(|e| (e.value, e.index_u32())).collect();
+        assert_eq!(rows, vec![(0, 0), (500, 1), (0, 2), (0, 5)]);
+        assert_eq!(counts, vec![(SkipReason::NonPositive, 1), (SkipReason::ZeroAfterScaling, 1)]);
+
+        // Only the rows asked for, and the count goes once all were zeros
+        let (mut entries, _) = build_entries(&numbers, 1000, 2, Some(&[5, 1]));
+        let mut counts = skip_counts(&numbers, 1000, 2, Some(&[5, 1]));
+        assert_eq!(keep_zeros(&mut entries, &mut counts, &numbers, Some(&[5, 1])), 1);
+        assert_eq!(entries.iter().map(|e| e.index_u32()).collect::<Vec<_>>(), vec![1, 5]);
+        assert!(counts.is_empty());
+    }
+
+    #[test]
+    fn test_previously_matched_rows_by_index() {
+        let numbers = [5.0, 3.0, 7.0, -2.0];
+        let (rows, unmatched) = previously_matched_rows(&numbers, 0, &[2, 0, 2, 9, 3], &[]);
+        assert_eq!(rows, vec![0, 2, 3]);
+        assert!(unmatched.is_empty());
+
+        // Only rows that would have been entries are reported as previously matched
+        let (mut entries, mut skipped) = build_entries(&numbers, 10, 0, Some(&[0, 1, 3, 8]));
+        assert_eq!(exclude_rows(&mut entries, &mut skipped, &rows), 1);
+        assert_eq!(entries.iter().map(|e| e.original_index.as_usize()).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(skipped, vec![
+            SkippedIndex { index: 0, reason: SkipReason::PreviouslyMatched },
+            SkippedIndex { index: 8, reason: SkipReason::OutOfRange },
+        ]);
+    }
+
+    #[test]
+    fn test_unusable_rows() {
+        let rows = |values: &[u64], target, min, max| {
+            let entries = classify_rows_u64(values, u64::MAX, None, usize::MAX).unwrap().entries;
+            unusable_rows(&entries, target, min, max)
+        };
+        // Too large: 8 plus the smallest other, 2, passes 9
+        assert_eq!(rows(&[2, 3, 4, 8], 9, 2, 4), vec![3]);
+        // Too small: 1 and 5 fall short even with the largest other, 7
+        assert_eq!(rows(&[1, 5, 6, 7], 13, 1, 2), vec![0, 1]);
+
+        // To a fixpoint: only once 10 is gone is 2 too small, and then 4
+        assert_eq!(rows(&[2, 4, 5, 6, 10], 11, 2, 2), vec![0, 1, 4]);
+        // and the other way: only once 1 is gone is 8 too large
+        assert_eq!(rows(&[1, 5, 6, 8], 11, 2, 2), vec![0, 3]);
+
+        // A window no count fits, or none at all, is left to the solvers
+        assert!(rows(&[1, 2], 3, 3, 4).is_empty());
+        assert!(rows(&[1, 2], 3, 0, 0).is_empty());
+        assert!(rows(&[1, 2], 3, 2, 1).is_empty());
+        // Nor is everything: no pair reaches 50, which the solvers report
+        assert!(rows(&[1, 2], 50, 1, 2).is_empty());
+        // Equal values are kept or dropped together
+        assert_eq!(rows(&[5, 5, 5, 1], 10, 2, 2), vec![3]);
+
+        let (mut entries, mut skipped) = build_entries(&[2.0, 4.0, 5.0, 6.0, 10.0], 11, 0, Some(&[0, 1, 2, 3, 4, 7]));
+        assert_eq!(prune_unusable(&mut entries, &mut skipped, 11, 2, 2), 3);
+        assert_eq!(entries.iter().map(|e| e.value).collect::<Vec<_>>(), vec![5, 6]);
+        assert_eq!(skipped.iter().map(|s| (s.index, s.reason.as_str())).collect::<Vec<_>>(), vec![
+            (0, "provably_unusable"),
+            (1, "provably_unusable"),
+            (4, "provably_unusable"),
+            (7, "out_of_range"),
+        ]);
+    }
+
+    #[test]
+    fn test_previously_matched_values_take_one_row_each() {
+        let numbers = [4.0, 2.5, 4.0, 1.0, 4.0, 2.5, 0.1 + 0.2];
+        // Two 4s take rows 0 and 2, leaving row 4; one 2.5 takes row 1
+        let (rows, unmatched) = previously_matched_rows(&numbers, 2, &[], &[4.0, 2.5, 4.0]);
+        assert_eq!(rows, vec![0, 1, 2]);
+        assert!(unmatched.is_empty());
+
+        // More copies than rows: the extra one is unmatched, in ledger order
+        let (rows, unmatched) = previously_matched_rows(&numbers, 2, &[], &[9.0, 4.0, 4.0, 4.0, 4.0, -1.0]);
+        assert_eq!(rows, vec![0, 2, 4]);
+        assert_eq!(unmatched, vec![9.0, 4.0, -1.0]);
+
+        // Compared in units, so float noise still matches
+        let (rows, _) = previously_matched_rows(&numbers, 2, &[], &[0.3]);
+        assert_eq!(rows, vec![6]);
+
+        // Rows taken by index are not taken again by value
+        let (rows, unmatched) = previously_matched_rows(&numbers, 2, &[0, 5], &[4.0, 2.5, 2.5]);
+        assert_eq!(rows, vec![0, 1, 2, 5]);
+        assert_eq!(unmatched, vec![2.5]);
+
+        // Scale 0 truncates the ledger values as it does the rows
+        let (rows, _) = previously_matched_rows(&numbers, 0, &[], &[2.9, 2.0]);
+        assert_eq!(rows, vec![1, 5]);
+    }
+
+    #[test]
+    fn test_classify_rows_cap() {
+        let numbers = [f64::NAN, 3.0, -1.0, 4.0, f64::INFINITY, 5.0, 6.0];
+        let rows = classify_rows(&numbers, 10, 0, None, 4).unwrap();
+        assert_eq!(rows.entries.len(), 4);
+        assert_eq!(rows.skip_counts, vec![(SkipReason::NotFinite, 2), (SkipReason::NonPositive, 1)]);
+        // The fourth usable row is one too many; the sweep stops right there
+        let err = classify_rows(&numbers, 10, 0, None, 3).unwrap_err();
+        assert_eq!(err, InvalidInput::TooManyEntries { max: 3, scanned: 7 });
+        assert_eq!(classify_rows(&numbers, 10, 0, None, 1).unwrap_err(), InvalidInput::TooManyEntries { max: 1, scanned: 4 });
+        // Only what the search considers counts: included rows, below the target
+        assert_eq!(classify_rows(&numbers, 10, 0, Some(&[6, 1, 1, 9]), 2).unwrap().entries.len(), 2);
+        assert_eq!(classify_rows(&numbers, 4, 0, None, 2).unwrap().entries.len(), 2);
+        assert!(classify_rows(&numbers, 10, 0, None, 0).is_err());
+        assert!(classify_rows(&[f64::NAN; 64], 10, 0, None, 0).unwrap().entries.is_empty());
+
+        let err = classify_rows_u64(&[0, 1, 2, 3], 10, None, 2).unwrap_err();
+        assert_eq!(err, InvalidInput::TooManyEntries { max: 2, scanned: 4 });
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_classify_rows() {
+        use std::time::Instant;
+
+        const N: usize = 10_000_000;
+        let usable: Vec<f64> = (0..N).map(|i| (i % 100_000) as f64 / 100.0 + 0.01).collect();
+        let garbage: Vec<f64> = (0..N)
+            .map(|i| [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e-310, -0.0, -5.0][i % 6])
+            .collect();
+        let mixed: Vec<f64> = (0..N).map(|i| if i % 3 == 0 { garbage[i] } else { usable[i] }).collect();
+        for (name, numbers) in [("all usable", &usable), ("all garbage", &garbage), ("mixed", &mixed)] {
+            let start = Instant::now();
+            let rows = classify_rows(numbers, u64::MAX, 2, None, usize::MAX).unwrap();
+            let full = start.elapsed();
+            let start = Instant::now();
+            let capped = classify_rows(numbers, u64::MAX, 2, None, 1 << 20);
+            let early = start.elapsed();
+            println!(
+                "{:>12}: {} entries in {:.1} ms; cap of 2^20 {} in {:.1} ms",
+                name, rows.entries.len(), full.as_secs_f64() * 1e3,
+                if capped.is_err() { "hit" } else { "not hit" }, early.as_secs_f64() * 1e3,
+            );
+        }
+    }
+
+    #[test]
+    fn test_classify_rows_u64_keeps_exact_values() {
+        // 2^53 + 1 has no f64; 2^63 + 1 is past i64
+        let values = [(1 << 53) + 1, 0, (1 << 63) + 1, u64::MAX];
+        let Classified { entries, skipped, .. } = classify_rows_u64(&values, (1 << 63) + 1, None, usize::MAX).unwrap();
+        let kept: Vec<(usize, u64)> = entries.iter().map(|e| (e.original_index.as_usize(), e.value)).collect();
+        assert_eq!(kept, vec![(0, (1 << 53) + 1), (2, (1 << 63) + 1)]);
+        assert!(skipped.is_empty());
+        assert_eq!(classify_u64(0, 5), Err(SkipReason::NonPositive));
+        assert_eq!(classify_u64(6, 5), Err(SkipReason::ExceedsTarget));
+        let Classified { entries, skipped, .. } = classify_rows_u64(&values, u64::MAX, Some(&[3, 7]), usize::MAX).unwrap();
+        assert_eq!(entries.iter().map(|e| e.value).collect::<Vec<_>>(), vec![u64::MAX]);
+        assert_eq!(skipped, vec![SkippedIndex { index: 7, reason: SkipReason::OutOfRange }]);
+    }
+
+    #[test]
+    fn test_classify_tricky_inputs() {
+        // Just under one dollar: rounds up to 100 cents, truncates to 0 whole units
+        assert_eq!(classify(0.9999999, 2, 1000), Ok(100));
+        assert_eq!(classify(0.9999999, 0, 1000), Err(SkipReason::ZeroAfterScaling));
+        // Float noise in cents
+        assert_eq!(classify(0.29, 2, 1000), Ok(29));
+        // Exactly the target stays usable; one unit over does not
+        assert_eq!(classify(10.0, 2, 1000), Ok(1000));
+        assert_eq!(classify(10.01, 2, 1000), Err(SkipReason::ExceedsTarget));
+        // Scale 0 keeps the legacy truncation
+        assert_eq!(classify(5.7, 0, 10), Ok(5));
+        assert_eq!(classify(0.004, 2, 1000), Err(SkipReason::ZeroAfterScaling));
+        assert_eq!(classify(0.0, 2, 1000), Err(SkipReason::NonPositive));
+        assert_eq!(classify(-3.0, 0, 1000), Err(SkipReason::NonPositive));
+        assert_eq!(classify(f64::NAN, 0, 1000), Err(SkipReason::NotFinite));
+        assert_eq!(classify(f64::INFINITY, 0, 1000), Err(SkipReason::NotFinite));
+    }
+
+    #[test]
+    fn test_sum_rows() {
+        let numbers = [0.1, 0.2, 5.0, -1.0];
+        // 0.1 + 0.2 in floats is 0.30000000000000004; in cents it is exactly 30
+        assert_eq!(sum_rows(&numbers, &[0, 1, 1], 2), Ok(30));
+        assert_eq!(sum_rows(&numbers, &[], 2), Err(RowSumError::Empty));
+        assert_eq!(
+            sum_rows(&numbers, &[0, 9], 2),
+            Err(RowSumError::InvalidIndex { index: 9, reason: SkipReason::OutOfRange }),
+        );
+        assert_eq!(
+            sum_rows(&numbers, &[3], 2),
+            Err(RowSumError::InvalidIndex { index: 3, reason: SkipReason::NonPositive }),
+        );
+        assert_eq!(sum_rows(&[1.8e19, 1.8e19], &[0, 1], 0), Err(RowSumError::Overflow));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint(&[1.0, 2.0]), fingerprint(&[1.0, 2.0]));
+        assert_ne!(fingerprint(&[1.0, 2.0]), fingerprint(&[2.0, 1.0]));
+        assert_ne!(fingerprint(&[]), fingerprint(&[0.0]));
+        assert_eq!(fingerprint_u64(&[1.0f64.to_bits()]), fingerprint(&[1.0]));
+        assert_ne!(fingerprint_u64(&[1, 2]), fingerprint_u64(&[2, 1]));
+    }
+
+    #[test]
+    fn test_scale_target() {
+        assert_eq!(scale_target(12.34, 2), Ok(1234));
+        assert_eq!(scale_target(12.9, 0), Ok(12));
+        assert_eq!(scale_target(f64::NAN, 2), Ok(0));
+        assert_eq!(scale_target(-5.0, 0), Ok(0));
+        // 5e15 fits at scale 3 (5e18) but not at scale 4 (5e19)
+        assert_eq!(scale_target(5e15, 4), Err(TargetOverflow { scale: 4, max_scale: Some(3) }));
+        assert_eq!(scale_target(2e19, 2), Err(TargetOverflow { scale: 2, max_scale: None }));
+        assert_eq!(scale_target(f64::MAX, 0), Err(TargetOverflow { scale: 0, max_scale: None }));
+    }
+
+    #[test]
+    fn test_target_rounding() {
+        // Float noise is not rounding
+        assert_eq!(target_rounding(0.1 + 0.2, 2), None);
+        assert_eq!(scale_target(0.1 + 0.2, 2), Ok(30));
+        assert_eq!(target_rounding(0.29, 2), None);
+        assert_eq!(target_rounding(1234.56, 2), None);
+        // 100.005 * 100 lands on 10000.5: half a cent, rounded away from zero
+        assert_eq!(target_rounding(100.005, 2), Some(TargetRounding { units: 10001, up: true }));
+        assert_eq!(scale_target(100.005, 2), Ok(10001));
+        assert_eq!(target_rounding(100.004, 2), Some(TargetRounding { units: 10000, up: false }));
+        assert_eq!(target_rounding(12.347, 2), Some(TargetRounding { units: 1235, up: true }));
+        assert_eq!(scale_target(12.347, 2), Ok(1235));
+        assert_eq!(target_rounding(0.004, 2), Some(TargetRounding { units: 0, up: false }));
+        // Scale 0 truncates by design; nothing to report for targets that match nothing
+        assert_eq!(target_rounding(12.9, 0), None);
+        assert_eq!(target_rounding(-0.5, 2), None);
+        assert_eq!(target_rounding(f64::NAN, 2), None);
+        assert_eq!(target_rounding(2e19, 2), None);
+
+        let nearest = TargetRounding { units: 10001, up: true };
+        assert_eq!(check_target_rounding(100.005, 2, false), Ok(Some(nearest)));
+        assert_eq!(check_target_rounding(100.005, 2, true), Err(TargetNotRepresentable { target: 100.005, scale: 2, nearest }));
+        assert_eq!(check_target_rounding(0.1 + 0.2, 2, true), Ok(None));
+    }
+
+    #[test]
+    fn test_scale_overflow_boundary() {
+        for scale in 0..=9 {
+            let max = max_safe_value(scale);
+            let next = f64::from_bits(max.to_bits() + 1);
+            assert!(scale_value(max, scale).is_ok(), "scale {}", scale);
+            assert_eq!(scale_value(next, scale), Err(SkipReason::ScaleOverflow), "scale {}", scale);
+            assert!(scale_target(max, scale).is_ok());
+            assert!(scale_target(next, scale).is_err());
+            let expected = u64::MAX as f64 / 10f64.powi(scale as i32);
+            assert!((max - expected).abs() / expected < 1e-15, "scale {}: {}", scale, max);
+        }
+        // 2^64 - 2048 is the largest f64 below 2^64
+        assert_eq!(max_safe_value(0), 18_446_744_073_709_549_568.0);
+        assert_eq!(scale_value(5e15, 4), Err(SkipReason::ScaleOverflow));
+    }
+}
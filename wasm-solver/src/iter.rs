@@ -0,0 +1,159 @@
+//! Solutions as a plain Rust iterator, for native consumers (the CLI, servers).
+//!
+//! `SolutionIter` drives the same DFS as the batch API, one result at a time, so
+//! callers can `take`, `filter` or `collect` without choosing a max_results or a
+//! node budget. It owns its copy of the entries and the search state; dropping
+//! it frees everything.
+
+use crate::batch::BatchSearchState;
+use crate::control::SearchController;
+
+pub use crate::solver::{DEFAULT_HYBRID_BB_BUDGET, NumberEntry, SolverConfig};
+
+/// Nodes in the first search chunk after a result; each chunk that finds nothing
+/// doubles the next, so a dense instance does little work past the item asked for.
+const FIRST_CHUNK_NODES: u64 = 64;
+
+/// Largest search chunk, and so most nodes between looks at the controller.
+const NODES_PER_CHECK: u64 = 1 << 16;
+
+/// Every combination of `entries` that sums to the target, in DFS order (each
+/// in selection order, like `BatchSearchState` results).
+///
+/// A stop from `config.controller` ends the iteration at the next `next()` call, or
+/// within NODES_PER_CHECK nodes when a search for the next item is under way.
+/// Results already found in the same node chunk are dropped with the rest.
+pub struct SolutionIter<'a> {
+    state: BatchSearchState,
+    controller: &'a dyn SearchController,
+    /// How many of the state's results were handed out
+    yielded: usize,
+    /// Node budget of the next search chunk
+    chunk: u64,
+}
+
+impl<'a> SolutionIter<'a> {
+    pub fn new(entries: &[NumberEntry], config: &SolverConfig<'a>) -> Self {
+        let mut state =
+            BatchSearchState::new(entries, config.target, config.min_count, config.max_count, usize::MAX);
+        if let Some(row_ids) = config.row_ids {
+            state.set_row_ids(row_ids);
+        }
+        if let Some(span) = config.max_index_span {
+            state.set_max_index_span(span);
+        }
+        SolutionIter { state, controller: config.controller, yielded: 0, chunk: FIRST_CHUNK_NODES }
+    }
+
+    /// Search nodes explored so far.
+    pub fn nodes_explored(&self) -> u64 {
+        self.state.nodes_explored()
+    }
+}
+
+impl Iterator for SolutionIter<'_> {
+    type Item = Vec<NumberEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.controller.should_stop(self.state.nodes_explored()).is_break() {
+                return None;
+            }
+            if self.state.all_results().len() > self.yielded {
+                break;
+            }
+            if self.state.is_finished() {
+                return None;
+            }
+            if self.state.search_batch(self.chunk).new_results.is_empty() {
+                self.chunk = (self.chunk * 2).min(NODES_PER_CHECK);
+            } else {
+                self.chunk = FIRST_CHUNK_NODES;
+            }
+        }
+        self.yielded += 1;
+        Some(self.state.all_results()[self.yielded - 1].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{OriginalIndex, canonicalize_results, solve_all_combinations};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn make_entries(values: &[u64]) -> Vec<NumberEntry> {
+        values.iter().enumerate().map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) }).collect()
+    }
+
+    fn config(target: u64, min_count: usize, max_count: usize, cancelled: &AtomicBool) -> SolverConfig<'_> {
+        SolverConfig {
+            target,
+            min_count,
+            max_count,
+            controller: cancelled,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    fn indices(results: &[Vec<NumberEntry>]) -> Vec<Vec<usize>> {
+        results.iter().map(|c| c.iter().map(|e| e.original_index.as_usize()).collect()).collect()
+    }
+
+    #[test]
+    fn test_take_is_lazy() {
+        let values: Vec<u64> = (1..=40).collect();
+        let entries = make_entries(&values);
+        let cancelled = AtomicBool::new(false);
+        let mut iter = SolutionIter::new(&entries, &config(100, 1, 40, &cancelled));
+        assert_eq!(iter.by_ref().take(3).count(), 3);
+        // Far fewer nodes than the millions this dense instance has solutions
+        assert!(iter.nodes_explored() < 10_000, "{}", iter.nodes_explored());
+    }
+
+    #[test]
+    fn test_collect_matches_solve_all() {
+        let cancelled = AtomicBool::new(false);
+        let fixtures: [(&[u64], u64, usize, usize); 3] = [
+            (&[1, 2, 3, 4, 5], 5, 1, 5),
+            (&[3, 5, 7, 8, 11, 13, 14, 19, 22, 25], 40, 2, 6),
+            (&[2, 2, 2, 3, 3, 4, 6, 8], 10, 1, 8),
+        ];
+        for (values, target, min, max) in fixtures {
+            let entries = make_entries(values);
+            let config = config(target, min, max, &cancelled);
+            let mut expected = solve_all_combinations(&entries, &config, usize::MAX).results;
+            let mut collected: Vec<_> = SolutionIter::new(&entries, &config).collect();
+            canonicalize_results(&mut expected);
+            canonicalize_results(&mut collected);
+            assert_eq!(indices(&collected), indices(&expected));
+        }
+    }
+
+    #[test]
+    fn test_row_ids_are_honored() {
+        let entries = make_entries(&[5, 5, 3, 2]);
+        let cancelled = AtomicBool::new(false);
+        let row_ids = [0, 0, 1, 2];
+        let config = SolverConfig { row_ids: Some(&row_ids), ..config(10, 1, 4, &cancelled) };
+        let mut collected: Vec<_> = SolutionIter::new(&entries, &config).collect();
+        canonicalize_results(&mut collected);
+        assert_eq!(indices(&collected), vec![vec![0, 2, 3], vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_cancel_between_items_ends_iteration() {
+        let values: Vec<u64> = (1..=20).collect();
+        let entries = make_entries(&values);
+        let cancelled = AtomicBool::new(false);
+        let mut iter = SolutionIter::new(&entries, &config(30, 1, 20, &cancelled));
+        assert!(iter.next().is_some());
+        assert!(iter.next().is_some());
+        cancelled.store(true, Ordering::Relaxed);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+}
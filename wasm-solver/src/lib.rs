@@ -1,19 +1,191 @@
 mod utils;
 mod solver;
 mod batch;
+mod input;
+mod dataset;
+mod export;
+mod serialize;
+mod validate;
+mod canonical;
+mod options;
+mod selftest;
+mod log;
+mod score;
+mod verify;
+mod capabilities;
+mod mincost;
+mod mitm_all;
+mod collapse;
+pub mod control;
+mod neardup;
+mod search_tree;
+mod deflate;
+mod session;
+mod csv;
+mod multi;
+mod pairs;
+pub mod iter;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+#[cfg(test)]
+mod testing;
 
 use wasm_bindgen::prelude::*;
-use solver::{SolverConfig, SolverResult, NumberEntry, solve_subset_sum};
-use batch::BatchSearchState;
+use solver::{
+    Algorithm, SolverConfig, SolveError, SolveOutcome, SolverStats, NumberEntry, combination_id, solve_subset_sum_with_stats, DEFAULT_HYBRID_BB_BUDGET,
+    DEFAULT_PRECHECK_NODES, DEFAULT_QUICK_PHASE_NODES,
+};
+use batch::{BatchEntries, BatchSearchState, DedupOverflow, MaxCountChange, Shard, ShardMode, TargetChange};
+use input::{Classified, FractionalInputs, SkipReason, SkippedIndex, TargetRounding, build_entries, check_target_rounding, classify, fingerprint, fingerprint_u64, scale_target, sum_rows};
+use dataset::{Dataset, DatasetLoader};
+use mitm_all::MitmAll;
+use utils::SplitMix64;
+use serialize::{
+    BatchStepPayload, CapabilitiesPayload, CountStepPayload, SingleStepPayload, DestroyedPayload, UsageHistogramPayload, DatasetSummaryPayload,
+    EffectiveEntriesPayload, ErrorPayload, PrognosisPayload, SelfTestPayload, FindOnePayload, ResultFormat, ImportedPayload, Json, LegacyErrorPayload, MaxCountPayload, Payload, ResultsPayload, SeqRangePayload, StateHeaderPayload,
+    SessionImportedPayload, SessionPayload, StatsPayload, ActiveSearchPayload, ReplacedPayload, BatchInfoPayload, TargetAnalysisPayload, MultiTargetPayload, ResultMetaPayload,
+    MatchingPairPayload, SearchTreePayload, TargetChangePayload, ExhaustiveCheckPayload,
+};
+use export::{BatchOptions, CarriedOptions, StateHeader};
+use session::{Session, SessionData, SessionStats};
+use validate::{CallArgs, DEFAULT_MAX_ENTRIES, InvalidInput, validate};
+use options::SearchOptions;
+use collapse::Collapse;
+use score::ScoreMode;
+use verify::{EXHAUSTIVE_MAX_ROWS, Reference};
+use mincost::CostSummary;
+use log::{Level, log};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
 
-static CANCELLED: AtomicBool = AtomicBool::new(false);
+/// Node budget for the post-hit uniqueness search, separate from the primary search.
+const UNIQUENESS_NODE_BUDGET: u64 = 1_000_000;
+
+/// Rows listed in `top_usage` on the finished search_batch payload.
+const TOP_USAGE: usize = 10;
+
+/// Most undelivered results destroy_batch_search hands back.
+const UNDELIVERED_PAGE: usize = 1000;
+
+/// Path indices listed in step_single_search diagnostics.
+const PATH_SNAPSHOT_LIMIT: usize = 32;
+
+/// Default for set_max_elements_per_result.
+const DEFAULT_MAX_ELEMENTS_PER_RESULT: usize = 10_000;
 
 // Hold the batch search state across calls in thread-local storage.
 // WASM is single-threaded so this is safe.
+// No export calls into JS while it holds one of these borrows (there are no
+// callback-taking APIs; results go back as return values), so a nested borrow
+// cannot happen. An API that takes a JS callback must release its borrow before
+// invoking it, or the callback calling back in panics on the RefCell.
 thread_local! {
-    static BATCH_STATE: RefCell<Option<BatchSearchState>> = RefCell::new(None);
+    /// The batch search of the instance being called: the default one's for the
+    /// free functions, a SolverInstance's own inside its methods
+    static BATCH_STATE: RefCell<Option<ActiveBatch>> = RefCell::new(None);
+    /// The cancellation flag of the instance being called, like BATCH_STATE
+    static CANCELLED: RefCell<Rc<AtomicBool>> = RefCell::new(Rc::new(AtomicBool::new(false)));
+    static COUNT_STATE: RefCell<Option<BatchSearchState>> = const { RefCell::new(None) };
+    static SINGLE_STATE: RefCell<Option<SingleSearch>> = const { RefCell::new(None) };
+    static DATASETS: RefCell<HashMap<u32, Dataset>> = RefCell::new(HashMap::new());
+    static NEXT_DATASET_HANDLE: Cell<u32> = const { Cell::new(1) };
+    static FIND_SESSIONS: RefCell<HashMap<u32, FindSessionState>> = RefCell::new(HashMap::new());
+    static NEXT_FIND_SESSION_HANDLE: Cell<u32> = const { Cell::new(1) };
+    static DATASET_LOAD: RefCell<Option<DatasetLoader>> = const { RefCell::new(None) };
+    static DISCOVERY_ORDER: Cell<bool> = const { Cell::new(false) };
+    static MAX_ELEMENTS: Cell<usize> = const { Cell::new(DEFAULT_MAX_ELEMENTS_PER_RESULT) };
+    /// Full index lists of combinations sent truncated, by combination id
+    static FULL_RESULTS: RefCell<HashMap<u64, FullResult>> = RefCell::new(HashMap::new());
+}
+
+/// A combination sent truncated, kept whole for get_full_result.
+struct FullResult {
+    /// Original indices, in the order the payload listed them
+    indices: Vec<u32>,
+    sum: u64,
+}
+
+/// Batch search state plus input-level bookkeeping that isn't the solver's concern.
+struct ActiveBatch {
+    state: BatchSearchState,
+    /// Reported on the first search_batch payload only, then cleared.
+    skipped: Vec<SkippedIndex>,
+    /// Recorded in exported state so a bug report can be replayed exactly
+    options: BatchOptions,
+    fingerprint: u64,
+    /// Collected results already returned by search_batch
+    delivered: usize,
+    /// utils::now_ms() at init or import; None under `deterministic`, whose
+    /// payloads carry no wall-clock times. Not kept in exported state
+    started_ms: Option<f64>,
+    /// Started by init_batch_search_u64
+    exact_integers: bool,
+    /// Scores on the result payloads
+    score: ScoreMode,
+    /// Set by `paranoid`: every new result is checked against it before it is
+    /// returned. Rebuilt on import from the numbers passed there
+    verify: Option<Reference>,
+    /// init_batch_search_v2's `tag`, first in every payload about this search
+    tag: Option<String>,
+    /// Reported on the first search_batch payload only, like `skipped`
+    target_rounding: Option<TargetRounding>,
+    /// Reported on the first search_batch payload only, like `skipped`
+    fractional_inputs: Option<FractionalInputs>,
+    /// Rows init left out, by reason, for get_batch_info
+    skip_counts: Option<Vec<(SkipReason, usize)>>,
+}
+
+impl ActiveBatch {
+    /// What export_batch_state keeps beside the options.
+    fn carried(&self) -> CarriedOptions {
+        CarriedOptions {
+            score: self.score,
+            tag: self.tag.clone(),
+            paranoid: self.verify.is_some(),
+            exact_integers: self.exact_integers,
+            skip_counts: self.skip_counts.clone(),
+        }
+    }
+
+    /// Where this search stands, for an init that would replace it.
+    fn summary(&self) -> ActiveSearchPayload {
+        ActiveSearchPayload {
+            tag: self.tag.clone(),
+            total_found: self.state.found(),
+            nodes_explored: self.state.nodes_explored(),
+            finished: self.state.is_finished(),
+            undelivered: self.state.all_results().len().saturating_sub(self.delivered),
+        }
+    }
+}
+
+/// A find session: the input prepared for find_with, and what find_one would
+/// report about it on every call.
+struct FindSessionState {
+    session: solver::FindSession,
+    target: u64,
+    skipped: Vec<SkippedIndex>,
+}
+
+/// Resumable find-one: a batch search stopped at the first result.
+struct SingleSearch {
+    state: BatchSearchState,
+    /// Whether running steps carry the current path
+    diagnostics: bool,
+}
+
+/// What this build supports. Returns JSON:
+/// { api_version, crate_version, features: ["scale", "batch_export", "handles", ...] }
+/// `api_version` is an integer bumped on any change to a payload's shape;
+/// `features` lists the API features of this version, then the cargo features
+/// the module was built with (e.g. "console_log"). Touches no state, so it can
+/// be called first thing, before `init_panic_hook`. Every error payload carries
+/// the same `api_version` and `crate_version`.
+#[wasm_bindgen]
+pub fn get_capabilities() -> String {
+    CapabilitiesPayload.to_json()
 }
 
 #[wasm_bindgen]
@@ -21,53 +193,791 @@ pub fn init_panic_hook() {
     utils::set_panic_hook();
 }
 
-/// Cancel a running computation.
+/// Cancel a running computation of the default instance (see SolverInstance).
 #[wasm_bindgen]
 pub fn cancel_search() {
-    CANCELLED.store(true, Ordering::Relaxed);
+    current_cancel_flag().store(true, Ordering::Relaxed);
 }
 
-/// Build input entries, preserving original CSV row indices.
-fn build_entries(numbers: &[f64], target: u64) -> Vec<NumberEntry> {
-    numbers.iter()
-        .enumerate()
-        .filter_map(|(original_idx, &n)| {
-            let v = n as u64;
-            if v > 0 && v <= target {
-                Some(NumberEntry { value: v, original_index: original_idx })
-            } else {
-                None
-            }
+/// The cancellation flag of the instance being called.
+fn current_cancel_flag() -> Rc<AtomicBool> {
+    CANCELLED.with(|cell| Rc::clone(&cell.borrow()))
+}
+
+/// current_cancel_flag, cleared for a new computation.
+fn cancel_flag() -> Rc<AtomicBool> {
+    let cancelled = current_cancel_flag();
+    cancelled.store(false, Ordering::Relaxed);
+    cancelled
+}
+
+/// Report each combination's elements in the order the solver selected them
+/// (ascending by value for the DFS) instead of by index, for explaining how a
+/// result was reached. Applies to every result payload until changed. When off
+/// (the default), combinations carry a `selection_order` array parallel to
+/// `indices` giving the step each element was selected at, so both views are
+/// available either way.
+#[wasm_bindgen]
+pub fn set_preserve_discovery_order(enabled: bool) {
+    DISCOVERY_ORDER.with(|cell| cell.set(enabled));
+}
+
+/// Log the solver's decisions: 0 = off (the default), 1 = errors, 2 = also
+/// algorithm choices, fallbacks, phase changes and caps hit, 3 = also periodic
+/// node-count heartbeats. Lines go to the browser console, and only in builds
+/// with the `console_log` feature; other wasm builds compile the logging out.
+#[wasm_bindgen]
+pub fn set_log_level(level: u8) {
+    log::set_level(Level::from_u8(level));
+}
+
+/// Combinations with more than `max_elements` rows are sent as
+/// `{ id, count, sum, truncated: true, indices }` with only the first indices;
+/// fetch the rest with get_full_result(id) or get_result_indices_chunk. Default
+/// 10,000; 0 means no limit.
+#[wasm_bindgen]
+pub fn set_max_elements_per_result(max_elements: u32) {
+    let max_elements = if max_elements == 0 { usize::MAX } else { max_elements as usize };
+    MAX_ELEMENTS.with(|cell| cell.set(max_elements));
+}
+
+/// A chunk of the full index list of a truncated combination: up to `len`
+/// indices starting at `offset`, in the order the payload listed them. Empty
+/// past the end; None when the id is unknown or was released.
+#[wasm_bindgen]
+pub fn get_full_result(result_id: &str, offset: u32, len: u32) -> Option<Vec<u32>> {
+    let id = u64::from_str_radix(result_id, 16).ok()?;
+    FULL_RESULTS.with(|cell| {
+        cell.borrow().get(&id).map(|FullResult { indices, .. }| {
+            let start = (offset as usize).min(indices.len());
+            let end = start.saturating_add(len as usize).min(indices.len());
+            indices[start..end].to_vec()
         })
-        .collect()
+    })
+}
+
+/// Free the full index list kept for a truncated combination. Returns false when
+/// there was none.
+#[wasm_bindgen]
+pub fn release_full_result(result_id: &str) -> bool {
+    let Ok(id) = u64::from_str_radix(result_id, 16) else { return false };
+    FULL_RESULTS.with(|cell| cell.borrow_mut().remove(&id).is_some())
+}
+
+/// What is known of one result: `{ id, count, sum }`, the sum in scaled units.
+/// `result_id` is a combination id as the payloads give it (16 hex digits),
+/// found among the truncated combinations kept for get_full_result and then
+/// among the active batch search's collected results, or the decimal position
+/// of a result in get_results' discovery order. Anything else, or a result that
+/// is no longer kept, is an `unknown_result` error naming the `result_id`.
+#[wasm_bindgen]
+pub fn get_result_meta(result_id: &str) -> String {
+    match find_result(result_id) {
+        Ok((id, indices, sum)) => ResultMetaPayload { id, count: indices.len(), sum }.to_json(),
+        Err(err) => err.to_json(),
+    }
+}
+
+/// Up to `limit` original indices of one result starting at `offset`, in the
+/// order its payload lists them, as a Uint32Array with no JSON in between: a
+/// combination of any size, truncated or not, comes over in chunks. Result ids
+/// are as in get_result_meta. Empty at the end (`offset` equal to the count);
+/// throws the `unknown_result` error object, or `offset_out_of_range` (with
+/// `offset` and `count`) past the end.
+#[wasm_bindgen]
+pub fn get_result_indices_chunk(result_id: &str, offset: u32, limit: u32) -> Result<Vec<u32>, JsValue> {
+    result_indices_chunk(result_id, offset, limit).map_err(|err| err.to_js_value())
+}
+
+fn result_indices_chunk(result_id: &str, offset: u32, limit: u32) -> Result<Vec<u32>, ErrorPayload> {
+    let (_, indices, _) = find_result(result_id)?;
+    let offset = offset as usize;
+    if offset > indices.len() {
+        return Err(ErrorPayload::new("offset_out_of_range")
+            .with("offset", Json::usize(offset))
+            .with("count", Json::usize(indices.len())));
+    }
+    let end = offset.saturating_add(limit as usize).min(indices.len());
+    Ok(indices[offset..end].to_vec())
+}
+
+/// The result `result_id` names (see get_result_meta): its id, its indices in
+/// payload order and its sum.
+fn find_result(result_id: &str) -> Result<(u64, Vec<u32>, u64), ErrorPayload> {
+    let id = u64::from_str_radix(result_id, 16).ok().filter(|_| result_id.len() == 16);
+    let kept = id.and_then(|id| FULL_RESULTS.with(|cell| cell.borrow().get(&id).map(|full| (id, full.indices.clone(), full.sum))));
+    if let Some(found) = kept {
+        return Ok(found);
+    }
+    let format = result_format();
+    BATCH_STATE.with(|cell| {
+        let borrow = cell.borrow();
+        let results = borrow.as_ref().map_or(&[][..], |active| active.state.all_results());
+        let combo = match id {
+            Some(id) => results.iter().find(|combo| combination_id(combo) == id),
+            None => result_id.parse::<usize>().ok().and_then(|position| results.get(position)),
+        };
+        match combo {
+            Some(combo) => Ok((combination_id(combo), payload_indices(format, combo), combination_sum(combo))),
+            None => Err(ErrorPayload::new("unknown_result").with("result_id", Json::str(result_id))),
+        }
+    })
+}
+
+/// A combination's original indices in the order `format` reports them.
+fn payload_indices(format: ResultFormat, combo: &[NumberEntry]) -> Vec<u32> {
+    format.report_order(combo).iter().map(|&i| combo[i].index_u32()).collect()
+}
+
+/// A combination's sum, saturating as the truncated payload's.
+fn combination_sum(combo: &[NumberEntry]) -> u64 {
+    combo.iter().fold(0u64, |sum, e| sum.saturating_add(e.value))
+}
+
+fn result_format() -> ResultFormat {
+    ResultFormat {
+        discovery_order: DISCOVERY_ORDER.with(Cell::get),
+        max_elements: MAX_ELEMENTS.with(Cell::get),
+        score: ScoreMode::None,
+    }
+}
+
+/// Keep the full index lists of combinations the payload is about to truncate.
+fn keep_full_results<'a>(format: ResultFormat, combos: impl IntoIterator<Item = &'a [NumberEntry]>) {
+    for combo in combos.into_iter().filter(|c| format.truncates(c)) {
+        let full = FullResult { indices: payload_indices(format, combo), sum: combination_sum(combo) };
+        FULL_RESULTS.with(|cell| cell.borrow_mut().insert(combination_id(combo), full));
+    }
+}
+
+fn found_entries(result: &SolveOutcome) -> Option<&[NumberEntry]> {
+    result.as_ref().ok()?.as_deref()
+}
+
+/// The error a find-one answers with in place of its result payload. An empty
+/// input is a miss and a cancel a status of its own, so neither is one.
+fn solve_error(result: &SolveOutcome) -> Option<ErrorPayload> {
+    match *result {
+        Ok(_) | Err(SolveError::EmptyInput | SolveError::Cancelled) => None,
+        Err(err @ (SolveError::InvalidBounds { .. } | SolveError::TooLarge { .. } | SolveError::OutOfMemory(_))) => {
+            Some(ErrorPayload::from(err))
+        }
+    }
 }
 
 /// Find ONE valid combination. Returns a JSON string.
+///
+/// `include_indices` optionally restricts the search to those original positions;
+/// results still report indices into `numbers`.
+///
+/// `scale` keeps that many decimal places (2 = cents); values and target in the
+/// payload are in those scaled units. Defaults to 0, truncating to whole numbers.
+/// A target between two units at a positive scale (100.005 at scale 2) is rounded
+/// to the nearest, and the payload says so: `target_rounded_to: { units,
+/// direction }`, "up" or "down". Float noise such as 0.1 + 0.2 is not rounding.
+/// When at least 10% of the positive rows have decimals the scale drops (cents
+/// passed without `scale: 2`, say), the payload carries `warnings:
+/// ["fractional_inputs_truncated: 83% of values had decimals; consider scale=2"]`,
+/// the scale being the smallest up to 6 that keeps them all.
+///
+/// The payload's `stats.phases` lists the algorithms that ran with their budgets.
+/// Before any of them, a lookup checks for a single value equal to the target and
+/// for a pair summing to it (the pair with the smallest row indices), when the
+/// count window allows one or two; `stats.shortcut` says which answered
+/// ("single_element" or "pair", with a lone "direct" phase) or "none".
+/// A single-element result equal to the target that stands for several such rows
+/// carries `identical_value_matches` with their count (see search_batch).
+/// With `check_uniqueness`, a found payload also carries `unique` (true, false or
+/// "unknown" when the uniqueness budget runs out) and the budget spent in `stats`.
+/// `stats.peak_memory_estimate_bytes` is the most the search's big tables held
+/// at once (the sorted input with a MITM map, sum lists or reordered copy),
+/// counted where they are allocated, for tuning the size cutoffs from the field.
+///
+/// When MITM proves there is no match among at most 40 usable entries (and no
+/// `row_ids` or `max_index_span`), the not-found payload carries `explain`: `nearest_below` and
+/// `nearest_above`, the closest achievable sums on either side of the target as
+/// `{sum, indices, diff}` (null when there is none), and `achievable_within`, every achievable sum
+/// within `radius` (1% of the target) of it, at most 32, the nearest ones when
+/// there are more (then with `truncated: true`). All are in scaled units and
+/// count only combinations inside the requested count window; 39.95 against a
+/// target of 40.00 points at a rounding difference rather than a missing row.
+/// When B&B proves it instead, `explain` is `{nearest_below, nearest_above,
+/// exhaustive: false}`: the nearest misses its walk met, inside the tightened
+/// count window (`stats.count_window`). A walk over ascending values meets the
+/// nearest there are; the restarts over other orders may not. No `explain` when
+/// it met none.
 #[wasm_bindgen]
 pub fn find_one(
     numbers: &[f64],
     target: f64,
     min_count: u32,
     max_count: u32,
+    include_indices: Option<Vec<u32>>,
+    check_uniqueness: Option<bool>,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target_rounding = input::target_rounding(target, scale);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let rows = match capped_rows(numbers, target, scale, include_indices.as_deref(), DEFAULT_MAX_ENTRIES as usize) {
+        Ok(rows) => rows,
+        Err(json) => return json,
+    };
+    let mode = FindOneMode {
+        check_uniqueness: check_uniqueness.unwrap_or(false),
+        target_rounding,
+        fractional_inputs: input::fractional_inputs(numbers, scale, include_indices.as_deref()),
+        ..Default::default()
+    };
+    run_find_one(&rows.entries, &rows.skipped, target, min_count, max_count, mode)
+}
+
+/// find_one with its arguments in one JSON options object:
+/// { target, min_count?, max_count?, include_indices?, scale?, check_uniqueness?,
+///   consistent_with_find_one?, row_ids?, score_results? }
+///
+/// `row_ids`, parallel to `numbers`, marks rows that are copies of one physical
+/// row (e.g. after a faulty merge) by giving them the same id; no combination uses
+/// two of them. A length other than `numbers.len()` is a `length_mismatch` error.
+///
+/// find_one picks its algorithm by input size (see solve_subset_sum), so with
+/// meet-in-the-middle its answer can differ from the batch search's first result.
+/// With `consistent_with_find_one: true` it runs the batch search's DFS instead and
+/// returns exactly that first result (`stats.phases` shows a single "bnb" phase);
+/// it can be slower on inputs MITM handles well. The batch search always runs
+/// that DFS, so the same options object can be passed to init_batch_search_v2.
+///
+/// `score_results: "prefer_round"` (or `"prefer_organic"`) adds a `score` in
+/// [0, 1] to the combination, higher for round, repetitive amounts (or for
+/// organic, distinct ones); `"none"` is the default. It never changes the search.
+///
+/// `paranoid: true` re-checks the combination against `numbers` themselves before
+/// returning it (see verify.rs): distinct in-range indices, each row re-scaling to
+/// the value used, the exact target and a count in bounds. A failure returns an
+/// `internal_consistency_error` with the `reason`, the offending `indices` and
+/// `values`, and the `target` and `count_window` it was checked against.
+///
+/// `algorithm: "mitm"` or `"bnb"` overrides the choice by input size (`"auto"`,
+/// the default): "bnb" is the depth-first search above, and "mitm" runs for at
+/// most 50 usable entries, answering `algorithm_not_applicable` (with `n` and
+/// `max_n`) past that instead of building tables that cannot fit.
+///
+/// `objective: "min_cost"` with `costs` (parallel to `numbers`) returns the match
+/// with the smallest total cost instead of the first one found, e.g. days
+/// outstanding to clear the oldest invoices. The payload adds `total_cost` and
+/// `optimal`: false when the B&B used past 40 usable entries ran out of its
+/// `cost_nodes` budget (default 20,000,000) first, so the result is the cheapest
+/// seen rather than a proven optimum. `stats.phases` shows "mitm_min_cost" or
+/// "bnb_min_cost"; `consistent_with_find_one` has no effect.
+///
+/// Unlike the positional calls, `max_count` of 0 (or absent) means unlimited and
+/// `min_count` defaults to 1. A malformed object is rejected with `invalid_json`,
+/// `missing_option` or `invalid_option`.
+///
+/// `tag`, any string of at most 256 UTF-8 bytes, comes back verbatim as the first
+/// field of the payload, errors included, so a worker serving several callers
+/// can route it; a longer one is rejected with `tag_too_long` (`max`, `actual`).
+///
+/// `adjustments` (1 to 16 amounts, e.g. `[-2.5, 0, 2.5]` for a known fee either
+/// way) searches `target + adjustment` for each in the order given, over one
+/// prepared input, and answers with the first that has a match: the payload
+/// adds the `adjustment` used and the adjusted `target` in scaled units (the
+/// last one tried when none matched). Adjustments that leave no positive target
+/// are skipped and listed in `skipped_adjustments`. Rows are kept up to the
+/// largest adjusted target; with `objective: "min_cost"` the cheapest match of
+/// the first matching target is returned.
+///
+/// `memory_limit_mb` caps each large table the search builds (the sorted copy
+/// of the input, a meet-in-the-middle half), for browsers that cannot grow wasm
+/// memory far. A MITM table over the cap, or one the allocator refuses even
+/// without it, hands over to B&B (`stats.memory_fallback` gives the bytes it
+/// needed); when nothing smaller can take over (forced "mitm", or the input
+/// itself) the answer is an `out_of_memory` error with those `bytes`, instead
+/// of a trap.
+///
+/// `max_index_span: k` only accepts combinations whose rows lie within k of
+/// each other by original index (max - min <= k, so 0 allows single rows only),
+/// for chronological statements where related transactions cluster. Every
+/// algorithm skips a row once it would stretch the rows chosen so far past k.
+///
+/// `strict_target: true` answers `target_not_representable` (with the `target`,
+/// `scale` and the `nearest` rounding) instead of rounding a target, or an
+/// adjusted target, that falls between two units. `strict_inputs: true` answers
+/// `fractional_inputs_truncated` (with `percent`, `fractional`, `considered` and
+/// `suggested_scale`) where find_one would warn about rows losing decimals.
+///
+/// `previously_matched` lists rows reconciled in earlier runs, kept out of the
+/// search without editing the input; `previously_matched_values` does the same
+/// by amount for files whose row order changed, each value taking out one row of
+/// that value (in units at `scale`), the lowest index first. Rows taken out that
+/// were otherwise usable are listed in `skipped` as `"previously_matched"`.
+///
+/// `collapse_duplicates` searches over distinct values instead of rows (see
+/// collapse.rs), expanding the match back into the lowest-indexed rows of each
+/// value; `stats.phases` then shows a "collapsed" phase and `stats.collapsed_to`
+/// the number of distinct values. Absent, it applies past 40 usable entries when
+/// they hold at most half as many distinct values; `true` applies it whenever
+/// two share a value, `false` never. It is off with `row_ids`, `max_index_span`,
+/// `costs`, `adjustments`, a forced `algorithm` or `consistent_with_find_one`.
+/// Batch searches ignore it, as they list every combination of rows.
+///
+/// `max_entries` caps the usable rows (default 4,194,304, 0 = the default): the
+/// rows are read in one pass that stops at the first row past the cap, with a
+/// `too_many_entries` error giving `max_entries` and the rows `scanned`, before
+/// anything is sorted. The positional calls, and the batch ones, apply the
+/// default cap the same way.
+///
+/// `deterministic: true` promises the same payload for the same input and
+/// options on every run and machine. find_one_v2 already reads no clock and
+/// breaks ties by original index, so here it only locks that in; any later
+/// option that draws random numbers must take an explicit seed under it.
+#[wasm_bindgen]
+pub fn find_one_v2(numbers: &[f64], options: &str) -> String {
+    find_one_v2_with(numbers, &[], options)
+}
+
+/// find_one_v2 over column `column` (from 0) of CSV `text`, read in the crate
+/// instead of from a numbers array (see csv.rs for the dialect): `delimiter`
+/// separates fields and `has_header` drops the first record. Row k is the k-th
+/// record after the header, so payload indices (and `include_indices`,
+/// `previously_matched` and `row_ids`) count data rows from 0, the header left
+/// out. Fields are read locale-tolerantly ("1,234.50", "1.234,50", "1 234,5");
+/// rows whose field does not read as a number, or that are too short to have
+/// one, are listed in `skipped` as `not_a_number` or `missing_column`. A
+/// delimiter of `"`, CR or LF is an `invalid_delimiter` error.
+#[wasm_bindgen]
+pub fn find_one_from_csv_column(text: &str, column: u32, delimiter: char, has_header: bool, options: &str) -> String {
+    match csv::read_column(text, column as usize, delimiter, has_header) {
+        Ok(csv) => find_one_v2_with(&csv.numbers, &csv.unreadable, options),
+        Err(err) => ErrorPayload::from(err).tagged(options_tag(options).as_deref()).to_json(),
+    }
+}
+
+/// find_one_v2 with rows the caller could not read (NaN in `numbers`) listed
+/// among the skipped.
+fn find_one_v2_with(numbers: &[f64], unreadable: &[SkippedIndex], options: &str) -> String {
+    let opts = match SearchOptions::parse(options) {
+        Ok(opts) => opts,
+        Err(err) => return ErrorPayload::from(err).to_json(),
+    };
+    let tag = opts.tag.as_deref();
+    let parallel = row_ids_len(&opts);
+    let args = CallArgs { numbers_len: numbers.len(), scale: opts.scale, parallel: &parallel, ..Default::default() };
+    if let Err(err) = validate(&args) {
+        return ErrorPayload::from(err).tagged(tag).to_json();
+    }
+    let scale = opts.scale.unwrap_or(0);
+    let target = match scale_target(opts.target, scale) {
+        Ok(target) => target,
+        Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+    };
+    let target_rounding = match check_target_rounding(opts.target, scale, opts.strict_target) {
+        Ok(rounding) => rounding,
+        Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+    };
+    let fractional_inputs = input::fractional_inputs(numbers, scale, opts.include_indices.as_deref());
+    if let (Some(found), true) = (fractional_inputs, opts.strict_inputs) {
+        return ErrorPayload::from(found).tagged(tag).to_json();
+    }
+    let mut adjusted = Vec::new();
+    let mut skipped_adjustments = Vec::new();
+    for &adjustment in opts.adjustments.as_deref().unwrap_or_default() {
+        let adjusted_target = opts.target + adjustment;
+        match scale_target(adjusted_target, scale) {
+            Ok(0) => {
+                log!(Level::Info, "find_one: adjustment {} leaves no positive target, skipped", adjustment);
+                skipped_adjustments.push(adjustment);
+            }
+            Ok(units) => match check_target_rounding(adjusted_target, scale, opts.strict_target) {
+                Ok(rounding) => adjusted.push((adjustment, units, rounding)),
+                Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+            },
+            Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+        }
+    }
+    // With adjustments, rows are kept up to the largest adjusted target
+    let entries_target = match opts.adjustments {
+        Some(_) => adjusted.iter().map(|&(_, units, _)| units).max().unwrap_or(0),
+        None => target,
+    };
+    let rows = input::classify_rows(numbers, entries_target, scale, opts.include_indices.as_deref(), opts.resolved_max_entries());
+    let Classified { mut entries, mut skipped, .. } = match rows {
+        Ok(rows) => rows,
+        Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+    };
+    add_unreadable(&mut skipped, unreadable, opts.include_indices.as_deref());
+    exclude_previously_matched(numbers, scale, &opts, &mut entries, &mut skipped);
+    let algorithm = if opts.consistent_with_find_one { Algorithm::Bnb } else { opts.algorithm };
+    let max_count = opts.resolved_max_count(entries.len());
+    let reference = opts.paranoid.then(|| Reference {
+        numbers: numbers.to_vec(),
+        scale,
+        target,
+        min_count: opts.min_count as usize,
+        max_count: max_count as usize,
+    });
+    let mode = FindOneMode {
+        check_uniqueness: opts.check_uniqueness,
+        algorithm,
+        row_ids: opts.row_ids.as_deref(),
+        score: opts.score_results,
+        verify: reference.as_ref(),
+        costs: opts.costs.as_deref(),
+        cost_nodes: opts.cost_nodes.map_or(mincost::DEFAULT_MIN_COST_NODES, u64::from),
+        tag,
+        skipped_adjustments: &skipped_adjustments,
+        memory_limit: opts.memory_limit(),
+        max_index_span: opts.max_index_span.map(|span| span as usize),
+        target_rounding,
+        fractional_inputs,
+        // Which rows a match uses matters to these, or the caller chose the search
+        collapse: if opts.row_ids.is_some() || opts.max_index_span.is_some() || algorithm != Algorithm::Auto {
+            Collapse::Never
+        } else {
+            Collapse::from_option(opts.collapse_duplicates)
+        },
+        ..Default::default()
+    };
+    if opts.adjustments.is_some() {
+        return run_adjusted_find_one(&entries, &skipped, &adjusted, opts.min_count, max_count, mode);
+    }
+    run_find_one(&entries, &skipped, target, opts.min_count, max_count, mode)
+}
+
+/// find_one over values already in integer units (e.g. cents from a backend),
+/// passed as a `BigUint64Array` with a `BigInt` target. Nothing goes through f64,
+/// so values past 2^53 stay exact; zero rows are skipped. The payload carries
+/// `exact_integers: true`, and its values are exact in the JSON text (a plain
+/// JSON.parse rounds those past 2^53, so read them with a BigInt-aware parser).
+#[wasm_bindgen]
+pub fn find_one_u64(
+    values: &[u64],
+    target: u64,
+    min_count: u32,
+    max_count: u32,
+    include_indices: Option<Vec<u32>>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: values.len(), ..Default::default() }) {
+        return json;
+    }
+    let rows = match capped_rows_u64(values, target, include_indices.as_deref()) {
+        Ok(rows) => rows,
+        Err(json) => return json,
+    };
+    let mode = FindOneMode { exact_integers: true, ..Default::default() };
+    run_find_one(&rows.entries, &rows.skipped, target, min_count, max_count, mode)
+}
+
+/// Find a combination of the other rows matching the exact sum of `target_indices`
+/// (e.g. the payments making up a deposit). The target rows themselves are never
+/// candidates. The found payload echoes the computed `target` in scaled units.
+#[wasm_bindgen]
+pub fn find_matching_for_rows(
+    numbers: &[f64],
+    target_indices: &[u32],
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match sum_rows(numbers, target_indices, scale) {
+        Ok(target) => target,
+        Err(err) => return ErrorPayload::from(err).to_json(),
+    };
+
+    let (mut entries, skipped) = build_entries(numbers, target, scale, None);
+    entries.retain(|e| !target_indices.contains(&e.index_u32()));
+    let mode = FindOneMode { echo_target: true, ..Default::default() };
+    run_find_one(&entries, &skipped, target, min_count, max_count, mode)
+}
+
+/// Pick ONE solution uniformly at random among all solutions; the same seed
+/// always picks the same one. Returns the find_one payload, or a
+/// `too_many_entries` error when more than 40 entries are usable, since exact
+/// sampling needs the full meet-in-the-middle enumeration.
+#[wasm_bindgen]
+pub fn find_random(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    seed: u32,
+    scale: Option<u32>,
 ) -> String {
-    CANCELLED.store(false, Ordering::Relaxed);
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+
+    let cancelled = cancel_flag();
+    let config = SolverConfig {
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+        controller: &*cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    };
+    let mut rng = SplitMix64::new(seed as u64);
+    let (result, stats) = solver::sample_uniform_with_stats(&entries, &config, &mut rng);
+    if let Err(SolveError::TooLarge { n, max_n, .. }) = result {
+        return ErrorPayload::new("too_many_entries").with("n", Json::usize(n)).with("max", Json::usize(max_n)).to_json();
+    }
+    if let Some(error) = solve_error(&result) {
+        return error.to_json();
+    }
+    let format = result_format();
+    keep_full_results(format, found_entries(&result));
+    FindOnePayload {
+        result: &result,
+        stats: StatsPayload { stats: &stats, uniqueness: None },
+        target: None,
+        skipped: &[],
+        format,
+        exact_integers: false,
+        identical_value_matches: None,
+        cost: None,
+        adjustment: None,
+        skipped_adjustments: &[],
+        target_rounding: None,
+        fractional_inputs: None,
+    }
+    .to_json()
+}
 
-    let target = target as u64;
-    let entries = build_entries(numbers, target);
+/// How run_find_one searches and what its payload carries.
+#[derive(Clone, Copy, Default)]
+struct FindOneMode<'a> {
+    check_uniqueness: bool,
+    /// Echo the computed target (find_matching_for_rows)
+    echo_target: bool,
+    /// Auto: the hybrid strategy; Bnb: the batch search's DFS
+    algorithm: Algorithm,
+    /// See SolverConfig::row_ids
+    row_ids: Option<&'a [u32]>,
+    /// Values came in as integer units (find_one_u64)
+    exact_integers: bool,
+    score: ScoreMode,
+    /// Check the result against the input before returning it
+    verify: Option<&'a Reference>,
+    /// Return the cheapest match by these costs (parallel to the input) instead
+    costs: Option<&'a [f64]>,
+    /// Node budget for that search
+    cost_nodes: u64,
+    /// Echoed as the payload's first field (find_one_v2's `tag`)
+    tag: Option<&'a str>,
+    /// The adjustment whose target was searched (see run_adjusted_find_one)
+    adjustment: Option<f64>,
+    skipped_adjustments: &'a [f64],
+    /// See SolverConfig::memory_limit
+    memory_limit: Option<u64>,
+    /// See SolverConfig::max_index_span
+    max_index_span: Option<usize>,
+    /// How the searched target was rounded to a whole unit
+    target_rounding: Option<TargetRounding>,
+    /// Rows that lost decimals to the scale (see input::fractional_inputs)
+    fractional_inputs: Option<FractionalInputs>,
+    /// Search over distinct values when this says so (see collapse.rs)
+    collapse: Collapse,
+}
+
+/// Solve and answer with the find_one payload. Rows input::unusable_rows proves
+/// no count in the window can use are kept from the solvers and listed in
+/// `skipped` as `provably_unusable`.
+fn run_find_one(
+    entries: &[NumberEntry],
+    skipped: &[SkippedIndex],
+    target: u64,
+    min_count: u32,
+    max_count: u32,
+    mode: FindOneMode<'_>,
+) -> String {
+    let (mut entries, mut skipped) = (entries.to_vec(), skipped.to_vec());
+    match input::prune_unusable(&mut entries, &mut skipped, target, min_count as usize, max_count as usize) {
+        0 => {}
+        pruned => log!(Level::Info, "find_one: {} provably unusable rows left out", pruned),
+    }
+    let entries = &entries[..];
+    run_find_one_with(entries, &skipped, target, min_count, max_count, mode, |config| {
+        if let Some(costs) = mode.costs {
+            let (result, summary, stats) = mincost::solve_min_cost(entries, costs, config, mode.cost_nodes);
+            return (result, stats, Some(summary));
+        }
+        if let Some((result, stats)) = collapse::solve_collapsed(entries, config, mode.collapse) {
+            return (result, stats, None);
+        }
+        let (result, stats) = match mode.algorithm {
+            Algorithm::Auto => solve_subset_sum_with_stats(entries, config),
+            Algorithm::Mitm => solver::solve_mitm_with_stats(entries, config),
+            Algorithm::Bnb => solver::solve_depth_first_with_stats(entries, config),
+        };
+        (result, stats, None)
+    })
+}
 
+/// run_find_one with the search itself left to `solve`.
+fn run_find_one_with(
+    entries: &[NumberEntry],
+    skipped: &[SkippedIndex],
+    target: u64,
+    min_count: u32,
+    max_count: u32,
+    mode: FindOneMode<'_>,
+    solve: impl FnOnce(&SolverConfig) -> (SolveOutcome, SolverStats, Option<CostSummary>),
+) -> String {
+    let cancelled = cancel_flag();
     let config = SolverConfig {
         target,
         min_count: min_count as usize,
         max_count: max_count as usize,
-        cancelled: &CANCELLED,
+        controller: &*cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: mode.row_ids,
+        max_index_span: mode.max_index_span,
+        memory_limit: mode.memory_limit,
+    };
+
+    let (mut result, stats, cost) = solve(&config);
+    if let Err(SolveError::OutOfMemory(oom)) = result {
+        log!(Level::Error, "find_one: out of memory ({} bytes)", oom.bytes);
+    }
+    if let Some(error) = solve_error(&result) {
+        return error.tagged(mode.tag).to_json();
+    }
+    if let (Some(reference), Ok(Some(found))) = (mode.verify, &mut result) {
+        if let Err(failure) = reference.check_all([found]) {
+            log!(Level::Error, "find_one: result failed verification ({})", failure.inconsistency.reason());
+            return ErrorPayload::from(failure).tagged(mode.tag).to_json();
+        }
+    }
+    let uniqueness = found_entries(&result)
+        .filter(|_| mode.check_uniqueness)
+        .map(|found| solver::check_uniqueness(entries, &config, found, UNIQUENESS_NODE_BUDGET));
+    let format = ResultFormat { score: mode.score, ..result_format() };
+    keep_full_results(format, found_entries(&result));
+    FindOnePayload {
+        result: &result,
+        stats: StatsPayload {
+            stats: &stats,
+            uniqueness: uniqueness.as_ref().map(|check| (check, UNIQUENESS_NODE_BUDGET)),
+        },
+        target: mode.echo_target.then_some(target),
+        skipped,
+        format,
+        exact_integers: mode.exact_integers,
+        identical_value_matches: identical_value_matches(&result, entries, target),
+        cost,
+        adjustment: mode.adjustment,
+        skipped_adjustments: mode.skipped_adjustments,
+        target_rounding: mode.target_rounding,
+        fractional_inputs: mode.fractional_inputs,
+    }
+    .tagged(mode.tag)
+    .to_json()
+}
+
+/// find_one_v2 with `adjustments`: each adjusted target in turn, in the order
+/// given, over one sorted input (a FindSession, so later targets also skip the
+/// subtrees earlier ones proved empty). The payload is run_find_one's for the
+/// first target with a match, or the last one tried, naming its adjustment.
+fn run_adjusted_find_one(
+    entries: &[NumberEntry],
+    skipped: &[SkippedIndex],
+    adjusted: &[(f64, u64, Option<TargetRounding>)],
+    min_count: u32,
+    max_count: u32,
+    mode: FindOneMode<'_>,
+) -> String {
+    let cancelled = cancel_flag();
+    let session = match solver::FindSession::new(entries, mode.memory_limit) {
+        Ok(session) => session,
+        Err(oom) => return ErrorPayload::from(oom).tagged(mode.tag).to_json(),
+    };
+    let mut outcome = None;
+    for &(adjustment, target, rounding) in adjusted {
+        let config = SolverConfig {
+            target,
+            min_count: min_count as usize,
+            max_count: max_count as usize,
+            controller: &*cancelled,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: mode.row_ids,
+            max_index_span: mode.max_index_span,
+            memory_limit: mode.memory_limit,
+        };
+        let solved = match mode.costs {
+            Some(costs) => {
+                let (result, summary, stats) = mincost::solve_min_cost(entries, costs, &config, mode.cost_nodes);
+                (result, stats, Some(summary))
+            }
+            None => {
+                let (result, stats) = session.solve(&config, mode.algorithm);
+                (result, stats, None)
+            }
+        };
+        // Anything but a proven miss ends the series
+        let done = !matches!(solved.0, Ok(None));
+        outcome = Some((adjustment, target, rounding, solved));
+        if done {
+            break;
+        }
+    }
+    let Some((adjustment, target, target_rounding, solved)) = outcome else {
+        // Every adjustment was skipped
+        let nothing = (Ok(None), SolverStats::default(), None);
+        return run_find_one_with(entries, skipped, 0, min_count, max_count, mode, |_| nothing);
     };
+    let reference = mode.verify.map(|reference| Reference { target, ..reference.clone() });
+    let mode = FindOneMode {
+        echo_target: true,
+        adjustment: Some(adjustment),
+        verify: reference.as_ref(),
+        target_rounding,
+        ..mode
+    };
+    run_find_one_with(entries, skipped, target, min_count, max_count, mode, |_| solved)
+}
 
-    let result = solve_subset_sum(&entries, &config);
-    result_to_json(&result)
+/// How many entries equal the target, when the result is one of them and has company.
+fn identical_value_matches(result: &SolveOutcome, entries: &[NumberEntry], target: u64) -> Option<usize> {
+    match found_entries(result)? {
+        [found] if found.value == target => {
+            Some(solver::identical_target_matches(entries, target)).filter(|&count| count > 1)
+        }
+        _ => None,
+    }
 }
 
 /// Initialize a batch search for ALL combinations.
 /// Call search_batch() repeatedly until it returns finished=true.
+///
+/// `include_indices` and `scale` behave as in find_one; out-of-range indices are
+/// reported in the `skipped` field of the first search_batch payload, a rounded
+/// target in its `target_rounded_to` and rows losing decimals in its `warnings`.
+/// get_batch_info describes the search right away: usable and skipped rows, their
+/// values and the strategy.
+///
+/// Returns nothing on success, or a structured error (e.g. `max_results_out_of_range`)
+/// when the arguments are rejected; the active search is then left untouched. A
+/// search still active (even finished, until destroy_batch_search) is replaced,
+/// and the answer is then `{ status: "ok", replaced_active: true, replaced: { tag?,
+/// total_found, nodes_explored, finished, undelivered } }` describing it.
 #[wasm_bindgen]
 pub fn init_batch_search(
     numbers: &[f64],
@@ -75,86 +985,4264 @@ pub fn init_batch_search(
     min_count: u32,
     max_count: u32,
     max_results: u32,
-) {
-    let target = target as u64;
-    let entries = build_entries(numbers, target);
+    include_indices: Option<Vec<u32>>,
+    scale: Option<u32>,
+) -> Option<String> {
+    let args = CallArgs { numbers_len: numbers.len(), scale, max_results: Some(max_results), ..Default::default() };
+    if let Err(json) = check_args(args) {
+        return Some(json);
+    }
+    let scale = scale.unwrap_or(0);
+    let target_rounding = input::target_rounding(target, scale);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return Some(json),
+    };
+    let rows = match capped_rows(numbers, target, scale, include_indices.as_deref(), DEFAULT_MAX_ENTRIES as usize) {
+        Ok(rows) => rows,
+        Err(json) => return Some(json),
+    };
+    let options = BatchOptions { target, scale, min_count, max_count, max_results, include_indices };
+    let extras = BatchExtras {
+        target_rounding,
+        fractional_inputs: input::fractional_inputs(numbers, scale, options.include_indices.as_deref()),
+        skip_counts: Some(rows.skip_counts),
+        ..Default::default()
+    };
+    start_batch(rows.entries, rows.skipped, options, fingerprint(numbers), extras)
+}
 
-    let state = BatchSearchState::new(
-        &entries,
-        target,
-        min_count as usize,
-        max_count as usize,
-        max_results as usize,
-    );
+/// init_batch_search for one of `shard_count` workers: this search walks only
+/// part `shard_index` of the DFS, so each worker runs its own shard over the same
+/// input with no coordination. The shards' results are disjoint and together are
+/// exactly what one init_batch_search finds, max_results aside, which applies to
+/// each shard alone. `progress` and `total_found` are the shard's own.
+///
+/// `shard_mode` picks how the DFS is split:
+/// - `"first_row"` (the default): by the first row of each combination, in value
+///   order, every `shard_count`-th row to a shard. Cheap, but the load can be
+///   very uneven, as the rows with the smallest values head most combinations.
+/// - `"first_two_rows"`: by a hash of the first two rows, which spreads each
+///   row's combinations over all shards for a more even load; every shard walks
+///   the first level in full to get there.
+///
+/// An unknown mode is an `invalid_shard_mode` error, and `shard_index` not below
+/// `shard_count` an `invalid_shard` one. A sharded search cannot be exported.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn init_batch_search_shard(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    max_results: u32,
+    shard_index: u32,
+    shard_count: u32,
+    shard_mode: Option<String>,
+) -> Option<String> {
+    let mode = match shard_mode.as_deref() {
+        None => ShardMode::FirstRow,
+        Some(name) => match ShardMode::parse(name) {
+            Some(mode) => mode,
+            None => return Some(ErrorPayload::new("invalid_shard_mode").with("found", Json::str(name)).to_json()),
+        },
+    };
+    if shard_index >= shard_count {
+        let err = ErrorPayload::new("invalid_shard")
+            .with("shard_index", Json::U64(shard_index.into()))
+            .with("shard_count", Json::U64(shard_count.into()));
+        return Some(err.to_json());
+    }
+    let args = CallArgs { numbers_len: numbers.len(), max_results: Some(max_results), ..Default::default() };
+    if let Err(json) = check_args(args) {
+        return Some(json);
+    }
+    let target_rounding = input::target_rounding(target, 0);
+    let target = match scaled_target(target, 0) {
+        Ok(target) => target,
+        Err(json) => return Some(json),
+    };
+    let rows = match capped_rows(numbers, target, 0, None, DEFAULT_MAX_ENTRIES as usize) {
+        Ok(rows) => rows,
+        Err(json) => return Some(json),
+    };
+    let options = BatchOptions { target, scale: 0, min_count, max_count, max_results, include_indices: None };
+    let extras = BatchExtras {
+        target_rounding,
+        fractional_inputs: input::fractional_inputs(numbers, 0, None),
+        skip_counts: Some(rows.skip_counts),
+        shard: Some(Shard { index: shard_index as usize, count: shard_count as usize, mode }),
+        ..Default::default()
+    };
+    start_batch(rows.entries, rows.skipped, options, fingerprint(numbers), extras)
+}
 
-    BATCH_STATE.with(|cell| {
-        *cell.borrow_mut() = Some(state);
-    });
+/// init_batch_search over integer units, as find_one_u64. The search_batch payloads
+/// carry `exact_integers: true`, an imported one's too. An exported state records
+/// a fingerprint of the values themselves, so pass no numbers when importing it.
+#[wasm_bindgen]
+pub fn init_batch_search_u64(
+    values: &[u64],
+    target: u64,
+    min_count: u32,
+    max_count: u32,
+    max_results: u32,
+    include_indices: Option<Vec<u32>>,
+) -> Option<String> {
+    let args = CallArgs { numbers_len: values.len(), max_results: Some(max_results), ..Default::default() };
+    if let Err(json) = check_args(args) {
+        return Some(json);
+    }
+    let rows = match capped_rows_u64(values, target, include_indices.as_deref()) {
+        Ok(rows) => rows,
+        Err(json) => return Some(json),
+    };
+    let options = BatchOptions { target, scale: 0, min_count, max_count, max_results, include_indices };
+    let extras = BatchExtras { exact_integers: true, skip_counts: Some(rows.skip_counts), ..Default::default() };
+    start_batch(rows.entries, rows.skipped, options, fingerprint_u64(values), extras)
 }
 
-/// Run one batch of DFS work (node_budget nodes).
-/// Returns JSON: { new_results: [...], total_found, nodes_explored, finished, progress }
+/// init_batch_search with an options object (see find_one_v2); `max_results` of
+/// 0 or absent means no limit beyond the global cap of 10,000,000.
+///
+/// `count_windows: [[2,2],[3,3],[4,6,50]]` searches several count windows in one
+/// pass instead of min_count/max_count: the DFS covers their union once, each
+/// result is tagged with the windows it was counted for, and each window stops
+/// taking results at its own cap (the optional third element; otherwise
+/// `max_results`). The search ends when every window is full. A result whose
+/// length fits no window with room left is skipped.
+///
+/// `init_budget_ms` keeps init short on large inputs: preparation steps (sort,
+/// suffix sums, count window tightening) that are not expected to fit run at
+/// the start of the first search_batch instead, whose payload then carries
+/// `prework_pending: true` and `prework: {deferred, init_ms, deferred_ms}`.
+///
+/// `quick_phase: true` gets a first answer fast: the first search_batch runs
+/// find_one's algorithm (MITM, or B&B over descending values under a node cap)
+/// and returns what it found with `phase: "quick"` and `next_phase:
+/// "exhaustive"`; later batches run the full DFS with `phase: "exhaustive"`,
+/// never repeating the quick result. Progress restarts from 0 with each phase.
+///
+/// `score_results` scores every combination in the result payloads as in
+/// find_one_v2, and lets get_results order them by that score.
+///
+/// `precheck` first asks whether there is any solution at all, at the start of
+/// the first search_batch: MITM for up to 40 usable entries, otherwise B&B
+/// under `precheck_nodes` (default 200,000). A proof that there is none ends
+/// that batch with `termination_reason: "proved_infeasible"`; a solution found
+/// is its first result, never repeated later. The batch reports `precheck:
+/// {outcome, node_budget, nodes}` with outcome "found", "infeasible" or
+/// "unknown" (budget ran out; the search just goes on). On by default for up to
+/// 60 usable entries unless `init_budget_ms` or `consistent_with_find_one` is
+/// set (its witness need not be the DFS's first result); the positional
+/// init_batch_search never runs it.
+///
+/// `prefer_small_count: true` returns results smallest first: every count from
+/// min_count up is searched to the end in turn, by the same DFS held to that one
+/// count, so results come out ordered by size with no sorting. Each count walks
+/// the shallow levels of the tree again, so the whole search costs up to about
+/// as many times the default one as there are counts (less in practice, as
+/// small counts prune early). Payloads carry `current_count` while running and
+/// `progress` covers all counts; max_results applies across them, and
+/// `quick_phase` and `precheck` are ignored, as their answer would come first.
+/// set_batch_max_count just adds the larger counts after the ones done.
+///
+/// `paranoid: true` checks every new result as in find_one_v2 before search_batch
+/// returns it; a failure returns the `internal_consistency_error` instead of that
+/// batch and ends the search.
+///
+/// `stream_results: true` drops each result once search_batch has returned it,
+/// keeping only its 64-bit combination id so no later phase, restart or reopened
+/// frontier reports it again; get_results and exports then hold none of them.
+/// Payloads carry `retained_bytes`, a rough size of what the search still holds
+/// for results. At most 2^20 ids are kept: past that `dedup_overflow:
+/// "allow_duplicates"` (the default) goes on without remembering more, so a
+/// restart may repeat those results, while `"pause"` ends the search with
+/// `termination_reason: "dedup_overflow"`.
+///
+/// `max_new_results_per_batch: k` bounds the payload a dense search can build
+/// in one call: search_batch stops once it has k new results, however much of
+/// its node budget is left, and says so with `paused_on_results: true`, a cue to
+/// call again right away. The rare extra results of a frame finished in one
+/// scan are held for the next call, which returns them first; none is lost or
+/// repeated, and the search is not `finished` while any are held. Exported
+/// states keep the cap and the held results.
+///
+/// `tag` (at most 256 UTF-8 bytes, as in find_one_v2) is stored with the search
+/// and comes back as the first field of this call's error and of every
+/// search_batch, set_batch_max_count, get_results and destroy_batch_search
+/// payload until the search is destroyed; get_usage_histogram's bare array
+/// stays as it is, and an imported search has no tag.
+///
+/// `suppress_near_duplicates: k` holds back a result whose multiset of values
+/// differs from one of the last 256 emitted in at most k positions (a value
+/// swapped, added or dropped each count one), so 0 drops results that only
+/// trade a row for another of the same amount. Held-back results are not
+/// counted in `total_found` nor against max_results; payloads carry
+/// `suppressed_near_duplicates` with how many so far. An imported search does
+/// not suppress.
+///
+/// `memory_limit_mb` (as in find_one_v2) also caps the results a search holds:
+/// once the next one would take them past it, or the allocator refuses it, the
+/// search ends there with `termination_reason: "out_of_memory"`, keeping those
+/// found so far. Streamed results count only until returned. An imported search
+/// has no limit.
+///
+/// `max_index_span` (as in find_one_v2) holds every result's rows within that
+/// many of each other; exported states keep it.
+///
+/// `preferred_indices` lists rows the user suspects belong to the match: at
+/// every level the DFS tries them before the other rows, so results holding
+/// them tend to come first, while the results found overall stay the same.
+/// Each new result carries `contains_preferred` with how many it holds. Indices
+/// that name no usable row are ignored. The quick phase and precheck answers
+/// keep their order, and such a search cannot be exported.
+///
+/// `include_zeros: true` keeps the rows of exactly 0, which build_entries
+/// otherwise drops as non-positive, for when zero-valued rows (credit memos
+/// netting out, placeholder lines) are fair padding towards min_count: a result
+/// may then hold any of them, with their original indices, beside rows summing
+/// to the target. They are not counted in `skipped_by_reason`. No quick phase or
+/// precheck runs, as the find-one solvers take positive values only.
+///
+/// `refuse_if_active: true` answers `search_active`, with the active search's
+/// summary as `active`, instead of replacing it; that search is left as it was.
+///
+/// A rounded target is reported as `target_rounded_to` on the first search_batch
+/// payload, and `strict_target: true` refuses it, both as in find_one_v2; so are
+/// the `warnings` about rows losing decimals, and `strict_inputs: true`.
+///
+/// `previously_matched` and `previously_matched_values` leave out earlier runs'
+/// rows as in find_one_v2; get_batch_info counts them as `previously_matched`.
+/// Exported states keep the rows left, not the ledger.
+///
+/// `algorithm: "mitm"` searches by meet-in-the-middle instead of the DFS, for up
+/// to 40 usable entries: each subset of either half is one node, so the first
+/// batches build the left half's map (at `progress` 0) and later ones match the
+/// right half against it, `progress` being the share of it done. max_results,
+/// `stream_results` and the other result options apply as with the DFS. It
+/// answers `algorithm_not_applicable` with `n` and `max_n` past 40 entries, or
+/// with `conflicts_with` alongside `row_ids`, `max_index_span`, `count_windows`,
+/// `quick_phase`, `prefer_small_count`, `preferred_indices` or `include_zeros`. Such a search cannot be exported,
+/// set_batch_max_count leaves it unchanged, and get_batch_info reports
+/// `strategy: "mitm"`. `"auto"` and `"bnb"` mean the DFS.
+///
+/// `deterministic: true` (as in find_one_v2) makes every payload a function of
+/// the input, options and batch sizes alone: the last batch leaves out
+/// `elapsed_ms`, and `init_budget_ms`, which splits the preparation by the
+/// clock, is rejected. An imported search times itself again.
 #[wasm_bindgen]
-pub fn search_batch(node_budget: u32) -> String {
-    BATCH_STATE.with(|cell| {
-        let mut borrow = cell.borrow_mut();
-        match borrow.as_mut() {
-            None => r#"{"error":"no search initialized"}"#.to_string(),
-            Some(state) => {
-                let result = state.search_batch(node_budget as u64);
-                batch_result_to_json(&result)
-            }
-        }
-    })
+pub fn init_batch_search_v2(numbers: &[f64], options: &str) -> Option<String> {
+    init_batch_search_v2_with(numbers, &[], options)
 }
 
-/// Clean up batch search state to free memory.
+/// init_batch_search_v2 over a CSV column, read as in find_one_from_csv_column.
+/// Unreadable rows are listed in the first search_batch payload's `skipped` and
+/// counted in get_batch_info's `skipped_by_reason`. An exported state records a
+/// fingerprint of the column as read, so import it with that column as numbers.
 #[wasm_bindgen]
-pub fn destroy_batch_search() {
-    BATCH_STATE.with(|cell| {
-        *cell.borrow_mut() = None;
-    });
+pub fn init_batch_search_from_csv_column(
+    text: &str,
+    column: u32,
+    delimiter: char,
+    has_header: bool,
+    options: &str,
+) -> Option<String> {
+    match csv::read_column(text, column as usize, delimiter, has_header) {
+        Ok(csv) => init_batch_search_v2_with(&csv.numbers, &csv.unreadable, options),
+        Err(err) => Some(ErrorPayload::from(err).tagged(options_tag(options).as_deref()).to_json()),
+    }
 }
 
-fn result_to_json(result: &SolverResult) -> String {
-    match result {
-        SolverResult::Found(entries) => {
-            let indices_str: Vec<String> = entries.iter().map(|e| e.original_index.to_string()).collect();
-            let values_str: Vec<String> = entries.iter().map(|e| e.value.to_string()).collect();
-            format!(
-                r#"{{"status":"found","indices":[{}],"values":[{}],"count":{}}}"#,
-                indices_str.join(","),
-                values_str.join(","),
-                entries.len()
-            )
+fn init_batch_search_v2_with(numbers: &[f64], unreadable: &[SkippedIndex], options: &str) -> Option<String> {
+    let opts = match SearchOptions::parse(options) {
+        Ok(opts) => opts,
+        Err(err) => return Some(ErrorPayload::from(err).to_json()),
+    };
+    let tag = opts.tag.as_deref();
+    let parallel = row_ids_len(&opts);
+    let args = CallArgs {
+        numbers_len: numbers.len(),
+        scale: opts.scale,
+        max_results: Some(opts.max_results),
+        parallel: &parallel,
+    };
+    if let Err(err) = validate(&args) {
+        return Some(ErrorPayload::from(err).tagged(tag).to_json());
+    }
+    let scale = opts.scale.unwrap_or(0);
+    let target = match scale_target(opts.target, scale) {
+        Ok(target) => target,
+        Err(err) => return Some(ErrorPayload::from(err).tagged(tag).to_json()),
+    };
+    let target_rounding = match check_target_rounding(opts.target, scale, opts.strict_target) {
+        Ok(rounding) => rounding,
+        Err(err) => return Some(ErrorPayload::from(err).tagged(tag).to_json()),
+    };
+    let fractional_inputs = input::fractional_inputs(numbers, scale, opts.include_indices.as_deref());
+    if let (Some(found), true) = (fractional_inputs, opts.strict_inputs) {
+        return Some(ErrorPayload::from(found).tagged(tag).to_json());
+    }
+    let rows = input::classify_rows(numbers, target, scale, opts.include_indices.as_deref(), opts.resolved_max_entries());
+    let Classified { mut entries, mut skipped, mut skip_counts } = match rows {
+        Ok(rows) => rows,
+        Err(err) => return Some(ErrorPayload::from(err).tagged(tag).to_json()),
+    };
+    csv::recount(&mut skip_counts, &add_unreadable(&mut skipped, unreadable, opts.include_indices.as_deref()));
+    if opts.include_zeros {
+        input::keep_zeros(&mut entries, &mut skip_counts, numbers, opts.include_indices.as_deref());
+    }
+    match exclude_previously_matched(numbers, scale, &opts, &mut entries, &mut skipped) {
+        0 => {}
+        excluded => skip_counts.push((SkipReason::PreviouslyMatched, excluded)),
+    }
+    let windows: Vec<(usize, usize, usize)> = opts.resolved_count_windows().unwrap_or_default()
+        .into_iter()
+        .map(|(min, max, cap)| (min as usize, max as usize, cap as usize))
+        .collect();
+    let (min_count, max_count, max_results) = if windows.is_empty() {
+        (opts.min_count, opts.resolved_max_count(entries.len()), opts.resolved_max_results())
+    } else {
+        // The union bounds the pruning; the windows' own caps bound the results
+        (
+            windows.iter().map(|w| w.0 as u32).min().unwrap_or(0),
+            windows.iter().map(|w| w.1 as u32).max().unwrap_or(0),
+            validate::MAX_RESULTS_CAP,
+        )
+    };
+    let mitm = opts.algorithm == Algorithm::Mitm;
+    if mitm {
+        let not_applicable = ErrorPayload::new("algorithm_not_applicable").with("algorithm", Json::str("mitm"));
+        if !MitmAll::fits(entries.len()) {
+            let max_n = 2 * mitm_all::MAX_MITM_ALL_HALF;
+            return Some(not_applicable.with("n", Json::usize(entries.len())).with("max_n", Json::usize(max_n)).tagged(tag).to_json());
+        }
+        let conflicts = [
+            ("row_ids", opts.row_ids.is_some()),
+            ("max_index_span", opts.max_index_span.is_some()),
+            ("count_windows", !windows.is_empty()),
+            ("quick_phase", opts.quick_phase),
+            ("prefer_small_count", opts.prefer_small_count),
+            ("preferred_indices", opts.preferred_indices.is_some()),
+            ("include_zeros", opts.include_zeros),
+        ];
+        if let Some((option, _)) = conflicts.into_iter().find(|&(_, set)| set) {
+            return Some(not_applicable.with("conflicts_with", Json::str(option)).tagged(tag).to_json());
         }
-        SolverResult::NotFound => r#"{"status":"not_found"}"#.to_string(),
-        SolverResult::Cancelled => r#"{"status":"cancelled"}"#.to_string(),
     }
+    let precheck_nodes = opts.resolved_precheck(entries.len())
+        .then(|| opts.precheck_nodes.map_or(DEFAULT_PRECHECK_NODES, u64::from));
+    let verify = opts.paranoid.then(|| Reference {
+        numbers: numbers.to_vec(),
+        scale,
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+    });
+    let memory_limit = opts.memory_limit();
+    let options = BatchOptions {
+        target,
+        scale,
+        min_count,
+        max_count,
+        max_results,
+        include_indices: opts.include_indices,
+    };
+    let extras = BatchExtras {
+        row_ids: opts.row_ids.as_deref(),
+        count_windows: &windows,
+        init_budget_ms: opts.init_budget_ms.map(f64::from),
+        quick_phase: opts.quick_phase && !opts.prefer_small_count && !opts.include_zeros,
+        score: opts.score_results,
+        precheck_nodes,
+        verify,
+        prefer_small_count: opts.prefer_small_count,
+        stream_results: opts.stream_results,
+        max_new_results: opts.max_new_results_per_batch,
+        dedup_overflow: opts.dedup_overflow,
+        suppress_near_duplicates: opts.suppress_near_duplicates,
+        memory_limit,
+        max_index_span: opts.max_index_span,
+        refuse_if_active: opts.refuse_if_active,
+        tag: opts.tag,
+        target_rounding,
+        fractional_inputs,
+        skip_counts: Some(skip_counts),
+        mitm,
+        preferred_indices: opts.preferred_indices.as_deref(),
+        deterministic: opts.deterministic,
+        ..Default::default()
+    };
+    start_batch(entries, skipped, options, fingerprint(numbers), extras)
+}
+
+/// Take the rows of the `previously_matched` ledger, by index and by value, out
+/// of built entries; returns how many went. Ledger values left without a row are
+/// only logged, as rows already gone from the file are the ledger's normal state.
+fn exclude_previously_matched(
+    numbers: &[f64],
+    scale: u32,
+    opts: &SearchOptions,
+    entries: &mut Vec<NumberEntry>,
+    skipped: &mut Vec<SkippedIndex>,
+) -> usize {
+    if opts.previously_matched.is_none() && opts.previously_matched_values.is_none() {
+        return 0;
+    }
+    let indices = opts.previously_matched.as_deref().unwrap_or_default();
+    let values = opts.previously_matched_values.as_deref().unwrap_or_default();
+    let (rows, unmatched) = input::previously_matched_rows(numbers, scale, indices, values);
+    if !unmatched.is_empty() {
+        log!(Level::Info, "previously_matched_values: no row left for {} value(s): {:?}", unmatched.len(), unmatched);
+    }
+    input::exclude_rows(entries, skipped, &rows)
 }
 
-fn entries_to_json(entries: &[NumberEntry]) -> String {
-    let indices_str: Vec<String> = entries.iter().map(|e| e.original_index.to_string()).collect();
-    let values_str: Vec<String> = entries.iter().map(|e| e.value.to_string()).collect();
-    format!(
-        r#"{{"indices":[{}],"values":[{}],"count":{}}}"#,
-        indices_str.join(","),
-        values_str.join(","),
-        entries.len()
-    )
+/// List the unreadable rows of a CSV column that the search considers among the
+/// skipped, in index order; returns them.
+fn add_unreadable(skipped: &mut Vec<SkippedIndex>, unreadable: &[SkippedIndex], include_indices: Option<&[u32]>) -> Vec<SkippedIndex> {
+    let considered = csv::considered(unreadable, include_indices);
+    if !considered.is_empty() {
+        skipped.extend(considered.iter().cloned());
+        skipped.sort_by_key(|s| s.index);
+    }
+    considered
 }
 
-fn batch_result_to_json(result: &batch::BatchResult) -> String {
-    let new_combos: Vec<String> = result.new_results.iter()
-        .map(|entries| entries_to_json(entries))
-        .collect();
+/// The tag of an options object, for an error found before it is otherwise read.
+fn options_tag(options: &str) -> Option<String> {
+    SearchOptions::parse(options).ok()?.tag
+}
+
+/// The `row_ids` entry of CallArgs::parallel, when given.
+fn row_ids_len(opts: &SearchOptions) -> Vec<(&'static str, usize)> {
+    let row_ids = opts.row_ids.iter().map(|ids| ("row_ids", ids.len()));
+    row_ids.chain(opts.costs.iter().map(|costs| ("costs", costs.len()))).collect()
+}
+
+/// Run the shared argument checks, mapping a failure to its error payload.
+fn check_args(args: CallArgs) -> Result<(), String> {
+    validate(&args).map_err(|err| ErrorPayload::from(err).to_json())
+}
+
+/// scale_target, mapping an overflow to its `target_scale_overflow` payload.
+fn scaled_target(target: f64, scale: u32) -> Result<u64, String> {
+    scale_target(target, scale).map_err(|err| ErrorPayload::from(err).to_json())
+}
+
+/// input::classify_rows, mapping too many usable rows to its `too_many_entries` payload.
+fn capped_rows(numbers: &[f64], target: u64, scale: u32, include_indices: Option<&[u32]>, max_entries: usize) -> Result<Classified, String> {
+    input::classify_rows(numbers, target, scale, include_indices, max_entries).map_err(|err| ErrorPayload::from(err).to_json())
+}
 
-    format!(
-        r#"{{"new_results":[{}],"total_found":{},"nodes_explored":{},"finished":{},"progress":{:.6}}}"#,
-        new_combos.join(","),
-        result.total_found,
-        result.nodes_explored,
-        result.finished,
-        result.progress,
-    )
+/// input::classify_rows_u64, as capped_rows.
+fn capped_rows_u64(values: &[u64], target: u64, include_indices: Option<&[u32]>) -> Result<Classified, String> {
+    input::classify_rows_u64(values, target, include_indices, DEFAULT_MAX_ENTRIES as usize).map_err(|err| ErrorPayload::from(err).to_json())
+}
+
+/// The less common start_batch settings; the default is a plain search.
+#[derive(Default)]
+struct BatchExtras<'a> {
+    row_ids: Option<&'a [u32]>,
+    count_windows: &'a [(usize, usize, usize)],
+    /// Values came in as u64 and stay exact in the payloads
+    exact_integers: bool,
+    /// Soft limit on init-time prework; the rest runs in the first search_batch
+    init_budget_ms: Option<f64>,
+    /// Run the quick phase before the exhaustive DFS
+    quick_phase: bool,
+    score: ScoreMode,
+    /// Node budget of a feasibility check run first
+    precheck_nodes: Option<u64>,
+    /// See ActiveBatch::verify
+    verify: Option<Reference>,
+    /// Results smallest first (see BatchSearchState::set_prefer_small_count)
+    prefer_small_count: bool,
+    /// Drop results once returned (see BatchSearchState::set_stream_results)
+    stream_results: bool,
+    /// See BatchSearchState::set_max_new_results
+    max_new_results: Option<u32>,
+    dedup_overflow: DedupOverflow,
+    /// See ActiveBatch::tag
+    tag: Option<String>,
+    /// See BatchSearchState::set_suppress_near_duplicates
+    suppress_near_duplicates: Option<u32>,
+    /// See BatchSearchState::set_memory_limit
+    memory_limit: Option<u64>,
+    /// See BatchSearchState::set_max_index_span
+    max_index_span: Option<u32>,
+    /// Leave an active search in place and answer `search_active` instead
+    refuse_if_active: bool,
+    /// See ActiveBatch::target_rounding
+    target_rounding: Option<TargetRounding>,
+    /// See ActiveBatch::fractional_inputs
+    fractional_inputs: Option<FractionalInputs>,
+    /// See ActiveBatch::skip_counts
+    skip_counts: Option<Vec<(SkipReason, usize)>>,
+    /// Search by meet-in-the-middle; init has checked that it applies
+    mitm: bool,
+    /// See BatchSearchState::set_preferred_rows
+    preferred_indices: Option<&'a [u32]>,
+    /// See BatchSearchState::set_shard
+    shard: Option<Shard>,
+    /// See ActiveBatch::started_ms
+    deterministic: bool,
+}
+
+/// Install a new batch search. Returns what init should answer: nothing when no
+/// search was active, the `replaced_active` payload when one was, or the
+/// `search_active` error (before any work) when `refuse_if_active` keeps it.
+fn start_batch(
+    entries: impl Into<BatchEntries>,
+    mut skipped: Vec<SkippedIndex>,
+    options: BatchOptions,
+    fingerprint: u64,
+    mut extras: BatchExtras,
+) -> Option<String> {
+    let active = BATCH_STATE.with(|cell| cell.borrow().as_ref().map(ActiveBatch::summary));
+    if let (Some(active), true) = (&active, extras.refuse_if_active) {
+        let tree = active.to_tree();
+        return Some(ErrorPayload::new("search_active").with("active", tree).tagged(extras.tag.as_deref()).to_json());
+    }
+    let reply = active.map(|active| ReplacedPayload(active).tagged(extras.tag.as_deref()).to_json());
+    // An already sorted view is shared with its dataset, so only owned entries are
+    // pruned; set_batch_max_count may raise max_count later, so only min_count bounds it
+    let mut entries = entries.into();
+    if let BatchEntries::Unsorted(list) = &mut entries {
+        match input::prune_unusable(list, &mut skipped, options.target, options.min_count as usize, usize::MAX) {
+            0 => {}
+            pruned => {
+                log!(Level::Info, "init_batch_search: {} provably unusable rows left out", pruned);
+                extras.skip_counts.iter_mut().for_each(|counts| counts.push((SkipReason::ProvablyUnusable, pruned)));
+            }
+        }
+    }
+    let mut state = BatchSearchState::new_budgeted(
+        entries,
+        options.target,
+        options.min_count as usize,
+        options.max_count as usize,
+        options.max_results as usize,
+        extras.init_budget_ms,
+    );
+    if let Some(ids) = extras.row_ids {
+        state.set_row_ids(ids);
+    }
+    if !extras.count_windows.is_empty() {
+        state.set_count_windows(extras.count_windows);
+    }
+    if extras.quick_phase {
+        state.set_quick_phase(DEFAULT_QUICK_PHASE_NODES);
+    }
+    if let Some(nodes) = extras.precheck_nodes {
+        state.set_precheck(nodes);
+    }
+    if extras.prefer_small_count {
+        state.set_prefer_small_count();
+    }
+    if extras.stream_results {
+        state.set_stream_results();
+    }
+    if let Some(max) = extras.max_new_results {
+        state.set_max_new_results(max as usize);
+    }
+    state.set_dedup_limit(batch::MAX_REPORTED_IDS, extras.dedup_overflow);
+    if let Some(max_distance) = extras.suppress_near_duplicates {
+        state.set_suppress_near_duplicates(max_distance as usize);
+    }
+    if let Some(bytes) = extras.memory_limit {
+        state.set_memory_limit(bytes);
+    }
+    if let Some(span) = extras.max_index_span {
+        state.set_max_index_span(span as usize);
+    }
+    if let Some(rows) = extras.preferred_indices {
+        state.set_preferred_rows(rows);
+    }
+    if let Some(shard) = extras.shard {
+        state.set_shard(shard);
+    }
+    if extras.mitm {
+        let applies = state.set_mitm();
+        debug_assert!(applies);
+    }
+
+    BATCH_STATE.with(|cell| {
+        *cell.borrow_mut() = Some(ActiveBatch {
+            state,
+            skipped,
+            options,
+            fingerprint,
+            delivered: 0,
+            started_ms: (!extras.deterministic).then(utils::now_ms),
+            exact_integers: extras.exact_integers,
+            score: extras.score,
+            verify: extras.verify,
+            tag: extras.tag,
+            target_rounding: extras.target_rounding,
+            fractional_inputs: extras.fractional_inputs,
+            skip_counts: extras.skip_counts,
+        });
+    });
+    reply
+}
+
+/// Run one batch of DFS work (node_budget nodes).
+/// Returns JSON: { new_results: [...], total_found, nodes_explored, finished, progress,
+///   count_window, open_branches, results_rate, first_seq?, last_seq?, stage,
+///   stage_progress?, stages?, likely_long_running?, paused_on_results?,
+///   retained_bytes?,
+///   suppressed_near_duplicates?, current_region?,
+///   window_matches?, per_window_found? }
+/// `open_branches` is how many untried siblings the open DFS frames still hold, a
+/// cheap gauge of remaining branching; `likely_long_running: true` appears once it
+/// has stayed above 256 for five batches in a row with no new result, a cue to
+/// suggest tighter constraints. `results_rate` is results per million nodes,
+/// smoothed over recent batches (the overall average once finished), for an
+/// honest "this looks sparse" hint. While running, `current_region`
+/// ({ min_index, max_index, partial_sum, depth }) describes the path being
+/// extended, e.g. "now exploring combinations starting near row 4,812", so a long
+/// stretch without results still shows movement. With count windows, `window_matches` lists for each
+/// new result the indices of the windows it counted for, and `per_window_found`
+/// the results per window so far. After an init that ran out of `init_budget_ms`,
+/// the first batch also carries `prework_pending` and `prework` (see
+/// init_batch_search_v2).
+/// Each result carries `seq`, numbering results in the order they were found
+/// from 0, and `first_seq`/`last_seq` give the range a batch returned (left out
+/// when it returned none). A consumer that sees `first_seq` skip past the last
+/// number it holds lost a payload, and fetches the gap with get_results_by_seq.
+/// `stage` says what the search is busy with, so a UI can name a stretch where
+/// `progress` stands still: "preparing" (prework deferred from init),
+/// "prechecking", "building_tables" (a meet-in-the-middle search's lookup map,
+/// with `stage_progress` the share built), "searching" (`progress` is this
+/// stage's own) or "finalizing" (the walk is over; held results may remain).
+/// Stages come in that order and a search skips those it has no work in;
+/// `stages` lists each one a batch went through when there was more than one.
+/// plus, once finished, `termination_reason` ("exhausted", "max_results",
+/// "proved_exhausted", "proved_infeasible", "dedup_overflow" or "out_of_memory"), `space_exhausted` and `top_usage` (the ten most used rows,
+/// as in get_usage_histogram). `space_exhausted: true` means every solution was
+/// found, and comes with `elapsed_ms` since init (or import) alongside the total
+/// `nodes_explored`; a search cut short by max_results instead carries
+/// `more_may_exist: true`. Every finished payload carries
+/// `peak_memory_estimate_bytes`, the most get_current_memory_estimate showed at
+/// the end of a batch or with the exhaustion proof's bitset beside it.
+/// Rows equal to the target are one solution between them: the first is reported,
+/// with `identical_value_matches` giving how many there are, and the rest are
+/// neither returned nor counted in `total_found`.
+///
+/// There is no timed or callback-driven variant: the caller paces the work with
+/// `node_budget` and gets each batch's results together in one payload, so a
+/// fast search never calls into JS more than once per call.
+#[wasm_bindgen]
+pub fn search_batch(node_budget: u32) -> String {
+    BATCH_STATE.with(|cell| {
+        let mut borrow = cell.borrow_mut();
+        match borrow.as_mut() {
+            None => LegacyErrorPayload("no search initialized").to_json(),
+            Some(active) => {
+                let mut result = active.state.search_batch(node_budget as u64);
+                if let Some(Err(failure)) = active.verify.as_ref().map(|r| r.check_all(&mut result.new_results)) {
+                    log!(Level::Error, "search_batch: result failed verification ({}), aborting", failure.inconsistency.reason());
+                    let json = ErrorPayload::from(failure).tagged(active.tag.as_deref()).to_json();
+                    *borrow = None;
+                    return json;
+                }
+                active.delivered = active.state.all_results().len() - active.state.held_results();
+                let skipped = std::mem::take(&mut active.skipped);
+                let target_rounding = active.target_rounding.take();
+                let fractional_inputs = active.fractional_inputs.take();
+                let top_usage = result.finished.then(|| {
+                    let mut usage = active.state.usage_histogram();
+                    usage.truncate(TOP_USAGE);
+                    usage
+                });
+                let format = ResultFormat { score: active.score, ..result_format() };
+                keep_full_results(format, result.new_results.iter().map(Vec::as_slice));
+                BatchStepPayload {
+                    result: &result,
+                    skipped: &skipped,
+                    top_usage: top_usage.as_deref(),
+                    format,
+                    elapsed_ms: active.started_ms
+                        .filter(|_| result.finished)
+                        .map(|started| (utils::now_ms() - started).max(0.0) as u64),
+                    exact_integers: active.exact_integers,
+                    target_rounding,
+                    fractional_inputs,
+                    preferred: active.state.preferred_rows(),
+                }
+                .tagged(active.tag.as_deref())
+                .to_json()
+            }
+        }
+    })
+}
+
+/// Tune when the active batch search tries to prove that the rest of its tree holds
+/// no solution: after `nodes` DFS nodes without a new result (default 100,000; 0
+/// disables it). A successful proof finishes the search with
+/// `termination_reason: "proved_exhausted"`. Returns false when no search is active.
+#[wasm_bindgen]
+pub fn set_batch_sparse_tail(nodes: u32) -> bool {
+    BATCH_STATE.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(active) => {
+            active.state.set_sparse_tail_nodes((nodes > 0).then_some(nodes as u64));
+            true
+        }
+        None => false,
+    })
+}
+
+/// Record the tree the active batch search walks in its first `max_nodes` nodes
+/// (root included, at most 10,000), for get_search_tree: every row picked or
+/// passed over and every prune with the bound values behind it, e.g. to draw
+/// branch and bound for a class. Off by default, and costs nothing then. Call
+/// after init, before the first search_batch. Returns false, recording nothing,
+/// when no search is active, it has already searched, or `max_nodes` is 0 or
+/// over 10,000.
+#[wasm_bindgen]
+pub fn set_batch_search_tree(max_nodes: u32) -> bool {
+    BATCH_STATE.with(|cell| {
+        cell.borrow_mut().as_mut().is_some_and(|active| active.state.set_search_tree(max_nodes as usize))
+    })
+}
+
+/// The tree recorded since set_batch_search_tree, as JSON `{ target, max_nodes,
+/// truncated, nodes: [{ id, parent, depth, path_sum, index?, row?, value?,
+/// decision, ... }] }`. Nodes come in the order the search decided on them, each
+/// with its parent's id (null for the root). `index` is the row's position among
+/// the search's entries sorted by value, `row` its original index, and
+/// `path_sum` the sum of the rows on the path above it, in scaled units.
+/// `decision` is one of:
+/// - `"root"`
+/// - `"branch"`: picked, and the search went deeper
+/// - `"solution"`: picked, completing a solution
+/// - `"cut"`: picked, going no deeper; `at_max_count` false when no pick of
+///   exactly the rows min_count still needs can make up the rest
+/// - `"skip"`: this row only, `reason` `"row_taken"`, `"outside_span"` or
+///   `"not_wanted"` (no open count window wants the solution, or it was returned before)
+/// - `"prune"`: this row and every later sibling, `reason` `"too_large"`
+///   (`value` over `remaining`), `"suffix_short"` (`suffix_sum` of this row and
+///   all after it under `remaining`), `"too_few_left"` (`left` rows, `needed`
+///   more) or `"duplicate_target"`
+/// - `"scan"`: the frame's last one or two picks found in one scan, with the
+///   `steps` it was charged and the solutions it `found`
+///
+/// `truncated` is true once `max_nodes` was reached, or once set_batch_max_count
+/// or a smallest-count-first search moved the walk elsewhere. Errors when no
+/// search is active, or `search_tree_off` when it is not being recorded.
+#[wasm_bindgen]
+pub fn get_search_tree() -> String {
+    BATCH_STATE.with(|cell| match cell.borrow().as_ref() {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => match active.state.search_tree() {
+            None => ErrorPayload::new("search_tree_off").tagged(active.tag.as_deref()).to_json(),
+            Some(tree) => SearchTreePayload { tree, sorted: active.state.sorted_entries(), target: active.options.target }
+                .tagged(active.tag.as_deref())
+                .to_json(),
+        },
+    })
+}
+
+/// Raise max_count on the active batch search, paused or finished, without
+/// redoing the work below the old limit: the paths it cut are walked further by
+/// the next search_batch calls, and results already returned are not returned
+/// again. Returns `{"status":"ok","change":"resumed","reopened":N}`; or
+/// `"change":"restarted"` with `"warning":"frontier_overflow"` when too many
+/// paths were cut to remember and the walk starts over (still skipping returned
+/// results); or `"change":"unchanged"` when `max_count` is not above the current
+/// one after tightening to what the data allows.
+#[wasm_bindgen]
+pub fn set_batch_max_count(max_count: u32) -> String {
+    BATCH_STATE.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(active) => {
+            let change = active.state.set_max_count(max_count as usize);
+            if change != MaxCountChange::Unchanged {
+                active.options.max_count = max_count;
+            }
+            MaxCountPayload(change).tagged(active.tag.as_deref()).to_json()
+        }
+        None => LegacyErrorPayload("no search initialized").to_json(),
+    })
+}
+
+/// Search for a lower target from here on, e.g. once a forgotten fee turns up,
+/// without a new init: `new_target` is in input units, scaled as at init.
+/// Returns `{"status":"ok","change":"lowered","invalidated_count":N,"target":T}`,
+/// T in scaled units: the walk starts over at the new target, as the part of
+/// the tree already walked can hold matches for it, and the N results found so
+/// far, all for the old target, are dropped, returned ones included. So the
+/// search goes on to find exactly what a new one at T would. `"change":
+/// "unchanged"` when T is the current target. A higher target is a
+/// `target_increase_requires_restart` error (with `target` and `current_target`),
+/// since rows above the old one were left out at init; start a new search.
+#[wasm_bindgen]
+pub fn adjust_target(new_target: f64) -> String {
+    BATCH_STATE.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(active) => {
+            let tag = active.tag.as_deref();
+            let target = match scale_target(new_target, active.options.scale) {
+                Ok(0) => {
+                    let err = ErrorPayload::new("invalid_target").with("target", Json::F64(new_target));
+                    return err.tagged(tag).to_json();
+                }
+                Ok(target) => target,
+                Err(err) => return ErrorPayload::from(err).tagged(tag).to_json(),
+            };
+            let min_count = active.options.min_count as usize;
+            let change = active.state.adjust_target(target, min_count);
+            if change == TargetChange::Refused {
+                return ErrorPayload::new("target_increase_requires_restart")
+                    .with("target", Json::U64(target))
+                    .with("current_target", Json::U64(active.options.target))
+                    .tagged(tag)
+                    .to_json();
+            }
+            active.options.target = target;
+            active.delivered = 0;
+            if let Some(reference) = &mut active.verify {
+                reference.target = target;
+            }
+            TargetChangePayload { change, target }.tagged(tag).to_json()
+        }
+        None => LegacyErrorPayload("no search initialized").to_json(),
+    })
+}
+
+/// Clean up batch search state to free memory, returning where it stood so a
+/// teardown before the last read loses nothing. Returns JSON:
+/// { total_found, nodes_explored, finished, termination_reason?, undelivered_results,
+///   undelivered_omitted? }
+/// where `undelivered_results` are collected results no search_batch payload has
+/// returned yet (e.g. restored by import_batch_state), at most 1000.
+#[wasm_bindgen]
+pub fn destroy_batch_search() -> String {
+    match BATCH_STATE.with(|cell| cell.borrow_mut().take()) {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => {
+            let state = &active.state;
+            let undelivered = &state.all_results()[active.delivered..];
+            let page = &undelivered[..undelivered.len().min(UNDELIVERED_PAGE)];
+            let format = ResultFormat { score: active.score, ..result_format() };
+            keep_full_results(format, page.iter().map(Vec::as_slice));
+            DestroyedPayload {
+                total_found: state.found(),
+                nodes_explored: state.nodes_explored(),
+                finished: state.is_finished(),
+                termination_reason: state.termination_reason(),
+                undelivered: page,
+                undelivered_omitted: undelivered.len() - page.len(),
+                format,
+            }
+            .tagged(active.tag.as_deref())
+            .to_json()
+        }
+    }
+}
+
+/// destroy_batch_search without the summary, for callers that already have everything.
+#[wasm_bindgen]
+pub fn destroy_batch_search_quiet() {
+    BATCH_STATE.with(|cell| {
+        *cell.borrow_mut() = None;
+    });
+}
+
+/// A solver with its own batch search and cancellation flag, for running
+/// several searches side by side (e.g. one per worker) whether the bundler
+/// shares the module instance between them or not. Each `new SolverInstance()`
+/// starts empty and never sees another's search; its init_batch does not
+/// replace one started elsewhere, and its cancel stops only its own work.
+///
+/// The free functions (find_one_v2, init_batch_search_v2, search_batch,
+/// cancel_search, destroy_batch_search, ...) act on a default instance of their
+/// own. Datasets, find sessions, kept full results and the result format
+/// settings stay module-wide, addressed by handle or id as before.
+#[wasm_bindgen]
+pub struct SolverInstance {
+    cancelled: Rc<AtomicBool>,
+    batch: RefCell<Option<ActiveBatch>>,
+}
+
+impl Default for SolverInstance {
+    fn default() -> Self {
+        SolverInstance { cancelled: Rc::new(AtomicBool::new(false)), batch: RefCell::new(None) }
+    }
+}
+
+#[wasm_bindgen]
+impl SolverInstance {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SolverInstance {
+        SolverInstance::default()
+    }
+
+    /// find_one_v2 with this instance's cancellation flag.
+    pub fn find_one(&self, numbers: &[f64], options: &str) -> String {
+        self.enter(|| find_one_v2(numbers, options))
+    }
+
+    /// init_batch_search_v2 for this instance's batch search.
+    pub fn init_batch(&self, numbers: &[f64], options: &str) -> Option<String> {
+        self.enter(|| init_batch_search_v2(numbers, options))
+    }
+
+    /// search_batch on this instance's batch search.
+    pub fn search_batch(&self, node_budget: u32) -> String {
+        self.enter(|| search_batch(node_budget))
+    }
+
+    /// cancel_search for this instance only.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// destroy_batch_search for this instance's batch search.
+    pub fn destroy(&self) -> String {
+        self.enter(destroy_batch_search)
+    }
+}
+
+impl SolverInstance {
+    /// Run a free function against this instance: its batch search and flag take
+    /// the default instance's place for the call, which gets them back after.
+    fn enter<R>(&self, call: impl FnOnce() -> R) -> R {
+        let swap = || BATCH_STATE.with(|cell| std::mem::swap(&mut *cell.borrow_mut(), &mut *self.batch.borrow_mut()));
+        let default_flag = CANCELLED.with(|cell| cell.replace(Rc::clone(&self.cancelled)));
+        swap();
+        let result = call();
+        swap();
+        CANCELLED.with(|cell| *cell.borrow_mut() = default_flag);
+        result
+    }
+}
+
+/// Initialize a resumable search for ONE combination, stepped with
+/// step_single_search so a UI can show progress and stop at any point.
+/// Arguments behave as in find_one. `diagnostics` (default true) adds the current
+/// DFS path to each running step; see step_single_search.
+///
+/// Returns nothing on success, or a structured error when the arguments are rejected.
+#[wasm_bindgen]
+pub fn init_single_search(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+    diagnostics: Option<bool>,
+) -> Option<String> {
+    let args = CallArgs { numbers_len: numbers.len(), scale, ..Default::default() };
+    if let Err(json) = check_args(args) {
+        return Some(json);
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return Some(json),
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+    let state = BatchSearchState::new(&entries, target, min_count as usize, max_count as usize, 1);
+    let diagnostics = diagnostics.unwrap_or(true);
+    SINGLE_STATE.with(|cell| *cell.borrow_mut() = Some(SingleSearch { state, diagnostics }));
+    None
+}
+
+/// Run one step of the single search (node_budget nodes). Returns JSON:
+/// { status: "running" | "found" | "not_found", ...combination, nodes_explored, progress,
+///   stage, diagnostics? }
+/// where the combination fields (as in find_one) are present once found, `stage`
+/// is "searching" or, once finished, "finalizing" (as in search_batch), and
+/// `diagnostics` — `{ unstable: true, path, path_len, partial_sum }`, the original
+/// indices of the first 32 elements of the path the DFS is extending and its full
+/// length and sum — is present while running unless disabled. Diagnostics are
+/// for debugging slow searches only; their shape may change.
+#[wasm_bindgen]
+pub fn step_single_search(node_budget: u32) -> String {
+    SINGLE_STATE.with(|cell| {
+        let mut borrow = cell.borrow_mut();
+        let Some(single) = borrow.as_mut() else {
+            return LegacyErrorPayload("no search initialized").to_json();
+        };
+        let state = &mut single.state;
+        let step = if state.is_finished() { None } else { Some(state.search_batch(node_budget as u64)) };
+        let found = state.all_results().first().map(Vec::as_slice);
+        let finished = state.is_finished();
+        let path = (single.diagnostics && !finished)
+            .then(|| state.current_path_snapshot(PATH_SNAPSHOT_LIMIT))
+            .flatten();
+        let format = result_format();
+        keep_full_results(format, found);
+        SingleStepPayload {
+            found,
+            finished,
+            nodes_explored: state.nodes_explored(),
+            // Finding the one result completes the task, unlike a batch's max_results stop
+            progress: step.filter(|_| !finished).map_or(1.0, |s| s.progress),
+            stage: state.stage(),
+            path,
+            format,
+        }
+        .to_json()
+    })
+}
+
+/// Drop the single search state.
+#[wasm_bindgen]
+pub fn destroy_single_search() {
+    SINGLE_STATE.with(|cell| *cell.borrow_mut() = None);
+}
+
+/// For each row, in how many collected results of the active batch search it
+/// appears. Returns JSON `[{index, count, value}]`, most used first; rows that
+/// appear in no result are omitted. Kept up to date as results are collected.
+#[wasm_bindgen]
+pub fn get_usage_histogram() -> String {
+    BATCH_STATE.with(|cell| match cell.borrow().as_ref() {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => UsageHistogramPayload(&active.state.usage_histogram()).to_json(),
+    })
+}
+
+/// What the active batch search was built from, read off its state so it always
+/// agrees with what the search does, e.g. for "searching 1,243 of your 5,000
+/// rows" right after init. Returns JSON `{ tag?, usable_count, skipped_count,
+/// skipped_by_reason, total_value, min_value, max_value, strategy, precheck,
+/// count_window, finished, termination_reason? }`: values in scaled units,
+/// `skipped_by_reason` as `{ reason: rows }` (reasons as in get_effective_entries),
+/// `strategy` "dfs", "quick_then_dfs" or "smallest_count_first", `precheck` true
+/// while a feasibility check is still to run, and `count_window` the window
+/// after tightening to what the data allows (null while init's prework is
+/// pending, or when no count fits). A search with nothing to find is finished
+/// from init, with `termination_reason: "exhausted"`. An imported search reports
+/// the skip counts of the init it was exported from.
+#[wasm_bindgen]
+pub fn get_batch_info() -> String {
+    BATCH_STATE.with(|cell| match cell.borrow().as_ref() {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => BatchInfoPayload {
+            info: &active.state.info(),
+            skip_counts: active.skip_counts.as_deref(),
+            finished: active.state.is_finished(),
+            termination_reason: active.state.termination_reason(),
+        }
+        .tagged(active.tag.as_deref())
+        .to_json(),
+    })
+}
+
+/// Rough heap bytes the active batch search holds now: its results, remembered
+/// ids and cut paths (as `retained_bytes`), the DFS stack at its deepest and a
+/// meet-in-the-middle map, each counted where it grows. The sorted input, which
+/// may be shared with a dataset, is left out. None when no search is active.
+#[wasm_bindgen]
+pub fn get_current_memory_estimate() -> Option<f64> {
+    BATCH_STATE.with(|cell| cell.borrow().as_ref().map(|active| active.state.memory_estimate() as f64))
+}
+
+/// Every result the active batch search has collected so far, returned or not:
+/// `{"order_by","total","results":[...]}` with combinations as in search_batch,
+/// `seq` included. `order_by` is `"discovery"` (the default, by `seq`) or
+/// `"score"`, best first by the search's `score_results` with ties by `seq`;
+/// asking for `"score"` on a search without scoring is a `not_scored`
+/// error, and any other value an `invalid_order_by` error.
+#[wasm_bindgen]
+pub fn get_results(order_by: Option<String>) -> String {
+    let order_by = match order_by.as_deref() {
+        None | Some("discovery") => "discovery",
+        Some("score") => "score",
+        Some(other) => return ErrorPayload::new("invalid_order_by").with("found", Json::str(other)).to_json(),
+    };
+    BATCH_STATE.with(|cell| match cell.borrow().as_ref() {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => {
+            let format = ResultFormat { score: active.score, ..result_format() };
+            let mut results: Vec<(u64, &[NumberEntry])> = (active.state.first_seq()..)
+                .zip(active.state.all_results().iter().map(Vec::as_slice))
+                .collect();
+            if order_by == "score" {
+                if active.score == ScoreMode::None {
+                    return ErrorPayload::new("not_scored").tagged(active.tag.as_deref()).to_json();
+                }
+                let score = |combo: &[NumberEntry]| active.score.score(combo.iter().map(|e| e.value)).unwrap_or(0.0);
+                results.sort_by(|a, b| score(b.1).total_cmp(&score(a.1)).then(a.0.cmp(&b.0)));
+            }
+            keep_full_results(format, results.iter().map(|&(_, combo)| combo));
+            ResultsPayload { order_by, results: &results, format }.tagged(active.tag.as_deref()).to_json()
+        }
+    })
+}
+
+/// The results of the active batch search numbered `from` to `to` (inclusive,
+/// by `seq`), to fetch again what a lost search_batch payload carried:
+/// `{"from","to","total","results":[...]}` with combinations as in get_results,
+/// in `seq` order. Numbers past the last result found are left out. Errors:
+/// `invalid_seq_range` when `from` is past `to`, and `seq_unavailable` (with
+/// `first_available`) when some of them are no longer held: streamed out under
+/// `stream_results`, or dropped by a lowered target.
+#[wasm_bindgen]
+pub fn get_results_by_seq(from: u32, to: u32) -> String {
+    let (from, to) = (u64::from(from), u64::from(to));
+    if from > to {
+        return ErrorPayload::new("invalid_seq_range")
+            .with("from", Json::U64(from))
+            .with("to", Json::U64(to))
+            .to_json();
+    }
+    BATCH_STATE.with(|cell| match cell.borrow().as_ref() {
+        None => LegacyErrorPayload("no search initialized").to_json(),
+        Some(active) => {
+            let tag = active.tag.as_deref();
+            let Some(results) = active.state.results_by_seq(from, to) else {
+                return ErrorPayload::new("seq_unavailable")
+                    .with("first_available", Json::U64(active.state.first_seq()))
+                    .tagged(tag)
+                    .to_json();
+            };
+            let format = ResultFormat { score: active.score, ..result_format() };
+            keep_full_results(format, results.iter().map(|&(_, combo)| combo));
+            SeqRangePayload { from, to, results: &results, format }.tagged(tag).to_json()
+        }
+    })
+}
+
+/// Snapshot the active batch search so it can be resumed later with
+/// import_batch_state. The blob carries a header with the options, the input
+/// fingerprint and the crate version; `score_results`, `tag`, `paranoid`,
+/// `memory_limit_mb`, `suppress_near_duplicates` (with the results it compares
+/// against) and a u64 search's `exact_integers` come back with the search. Returns None when no search is
+/// active, or when it searches by meet-in-the-middle, with preferred rows or as a shard.
+#[wasm_bindgen]
+pub fn export_batch_state() -> Option<Vec<u8>> {
+    BATCH_STATE.with(|cell| {
+        cell.borrow_mut().as_mut().filter(|active| active.state.exportable()).map(|active| {
+            active.state.finish_prework();
+            let header = StateHeader {
+                format_version: export::FORMAT_VERSION,
+                crate_version: export::CRATE_VERSION.to_string(),
+                options: active.options.clone(),
+                fingerprint: active.fingerprint,
+                n: active.state.entry_count() as u64,
+                results_so_far: active.state.found() as u64,
+                carried: active.carried(),
+            };
+            export::encode(&header, &active.state)
+        })
+    })
+}
+
+/// Replace the active batch search with an exported one; continue with search_batch.
+///
+/// When `numbers` is given, it must be the dataset the search was exported from.
+/// A `paranoid` search needs it, to check its results against: without it the
+/// import fails with `numbers_required`.
+/// Returns `{"status":"ok","total_found":N}` or a structured error with code
+/// `incompatible_version`, `fingerprint_mismatch`, `corrupt_state`, ... On error
+/// the active search is left untouched.
+#[wasm_bindgen]
+pub fn import_batch_state(bytes: &[u8], numbers: Option<Vec<f64>>) -> String {
+    match activate_batch_state(bytes, numbers.as_deref()) {
+        Ok(total_found) => ImportedPayload { total_found }.to_json(),
+        Err(err) => err.to_json(),
+    }
+}
+
+/// import_batch_state's work: the active search is replaced only on success,
+/// which returns how many results the restored search holds.
+fn activate_batch_state(bytes: &[u8], numbers: Option<&[f64]>) -> Result<usize, ErrorPayload> {
+    // Check the header first so a version or dataset mismatch is reported as such,
+    // not as whatever decoding error a changed body layout happens to produce
+    let header = export::decode_header(bytes).inspect_err(|err| {
+        log!(Level::Error, "import_batch_state: header rejected ({})", err.code());
+    })?;
+    check_crate_version(&header.crate_version)?;
+    if let Some(numbers) = numbers {
+        check_fingerprint(header.fingerprint, numbers)?;
+    }
+    // A paranoid search checks its results against the caller's rows, which
+    // the blob doesn't hold
+    let verify = match numbers {
+        Some(numbers) if header.carried.paranoid => Some(Reference {
+            numbers: numbers.to_vec(),
+            scale: header.options.scale,
+            target: header.options.target,
+            min_count: header.options.min_count as usize,
+            max_count: header.options.max_count as usize,
+        }),
+        None if header.carried.paranoid => {
+            return Err(ErrorPayload::new("numbers_required").with("option", Json::str("paranoid")));
+        }
+        _ => None,
+    };
+    let (header, state) = export::decode(bytes).inspect_err(|err| {
+        log!(Level::Error, "import_batch_state: body rejected ({})", err.code());
+    })?;
+    let total_found = state.found();
+    BATCH_STATE.with(|cell| {
+        *cell.borrow_mut() = Some(ActiveBatch {
+            state,
+            skipped: Vec::new(),
+            options: header.options,
+            fingerprint: header.fingerprint,
+            // Restored results haven't been returned to this caller yet
+            delivered: 0,
+            started_ms: Some(utils::now_ms()),
+            exact_integers: header.carried.exact_integers,
+            score: header.carried.score,
+            verify,
+            tag: header.carried.tag,
+            target_rounding: None,
+            fractional_inputs: None,
+            skip_counts: header.carried.skip_counts,
+        });
+    });
+    Ok(total_found)
+}
+
+fn check_crate_version(found: &str) -> Result<(), ErrorPayload> {
+    if export::version_compatible(found) {
+        return Ok(());
+    }
+    Err(ErrorPayload::new("incompatible_version")
+        .with("found", Json::str(found))
+        .with("expected", Json::str(export::CRATE_VERSION)))
+}
+
+/// `numbers` must be the dataset whose fingerprint was recorded.
+fn check_fingerprint(expected: u64, numbers: &[f64]) -> Result<(), ErrorPayload> {
+    let actual = fingerprint(numbers);
+    if actual == expected {
+        return Ok(());
+    }
+    Err(ErrorPayload::new("fingerprint_mismatch")
+        .with("expected", Json::hex(expected))
+        .with("found", Json::hex(actual)))
+}
+
+/// Describe an exported batch state without activating it. Returns JSON:
+/// { format_version, crate_version, compatible, fingerprint, n, results_so_far,
+///   options: { target, scale, min_count, max_count, max_results, include_indices } }
+#[wasm_bindgen]
+pub fn inspect_batch_state(bytes: &[u8]) -> String {
+    match export::decode_header(bytes) {
+        Ok(header) => StateHeaderPayload(&header).to_json(),
+        Err(err) => ErrorPayload::from(err).to_json(),
+    }
+}
+
+/// Bundle the active batch search for a support ticket: its options, stats and
+/// the rows of its results in discovery order (at most 10,000). The input
+/// values go in only with `include_data`, as the exported batch state, which
+/// also holds every result; otherwise the input is known only by its
+/// fingerprint. `compress` deflates the bundle. `max_bytes` (default 16 MiB,
+/// counted before compression) caps it: the state is dropped first, then
+/// results from the end. Returns None when no search is active, or with
+/// `include_data` when export_batch_state would.
+#[wasm_bindgen]
+pub fn export_session(include_data: bool, compress: bool, max_bytes: Option<u32>) -> Option<Vec<u8>> {
+    let state = if include_data { Some(export_batch_state()?) } else { None };
+    let mut session = BATCH_STATE.with(|cell| {
+        cell.borrow().as_ref().map(|active| Session {
+            session_version: session::SESSION_VERSION,
+            compressed: compress,
+            header: StateHeader {
+                format_version: export::FORMAT_VERSION,
+                crate_version: export::CRATE_VERSION.to_string(),
+                options: active.options.clone(),
+                fingerprint: active.fingerprint,
+                n: active.state.entry_count() as u64,
+                results_so_far: active.state.found() as u64,
+                carried: active.carried(),
+            },
+            stats: SessionStats {
+                nodes_explored: active.state.nodes_explored(),
+                finished: active.state.is_finished(),
+                termination_reason: active.state.termination_reason().map(|reason| reason.as_str().to_string()),
+                elapsed_ms: active.started_ms.map_or(0.0, |started| utils::now_ms() - started),
+            },
+            results: active.state.all_results().iter()
+                .take(session::MAX_SESSION_RESULTS)
+                .map(|combo| combo.iter().map(|e| e.index_u32()).collect())
+                .collect(),
+            data: state.map_or(SessionData::NotConsented, SessionData::State),
+        })
+    })?;
+    session.fit(max_bytes.map_or(session::DEFAULT_MAX_SESSION_BYTES, |max| max as usize));
+    Some(session.encode())
+}
+
+/// Make an exported session the active batch search, to reproduce it locally.
+///
+/// A session with data resumes its exported state as import_batch_state does,
+/// checking `numbers` against it when given. One without starts the recorded
+/// search over again on `numbers`, which must match its fingerprint. Returns
+/// `{"status":"ok","total_found":N,"replayed":bool}` or a structured error:
+/// `not_a_session`, `incompatible_version`, `fingerprint_mismatch`,
+/// `session_has_no_data` (no data and no numbers), ... On error the active
+/// search is left untouched.
+#[wasm_bindgen]
+pub fn import_session(bytes: &[u8], numbers: Option<Vec<f64>>) -> String {
+    let session = match decode_session(bytes) {
+        Ok(session) => session,
+        Err(err) => return err.to_json(),
+    };
+    if let Err(err) = check_crate_version(&session.header.crate_version) {
+        return err.to_json();
+    }
+    let imported = match (&session.data, numbers) {
+        (SessionData::State(state), numbers) => {
+            activate_batch_state(state, numbers.as_deref()).map(|total_found| (total_found, false))
+        }
+        (_, None) => Err(ErrorPayload::new("session_has_no_data").with("data", Json::str(session.data.as_str()))),
+        (_, Some(numbers)) => check_fingerprint(session.header.fingerprint, &numbers).map(|()| {
+            let options = session.header.options;
+            let (entries, skipped) = build_entries(&numbers, options.target, options.scale, options.include_indices.as_deref());
+            start_batch(entries, skipped, options, session.header.fingerprint, BatchExtras::default());
+            (0, true)
+        }),
+    };
+    match imported {
+        Ok((total_found, replayed)) => SessionImportedPayload { total_found, replayed }.to_json(),
+        Err(err) => err.to_json(),
+    }
+}
+
+/// Summarize an exported session without importing it: inspect_batch_state's
+/// fields plus `session_version`, `compressed`, `data` ("included",
+/// "not_consented" or "omitted_for_size"), `stats: {nodes_explored, finished,
+/// termination_reason, elapsed_ms}` and `results`, the rows of each listed result.
+#[wasm_bindgen]
+pub fn describe_session(bytes: &[u8]) -> String {
+    match decode_session(bytes) {
+        Ok(session) => SessionPayload(&session).to_json(),
+        Err(err) => err.to_json(),
+    }
+}
+
+fn decode_session(bytes: &[u8]) -> Result<Session, ErrorPayload> {
+    Session::decode(bytes).map_err(|err| match err {
+        export::DecodeError::BadMagic => ErrorPayload::new("not_a_session"),
+        err => ErrorPayload::from(err),
+    })
+}
+
+/// Initialize a count-only batch search: like init_batch_search, but solutions are
+/// counted instead of collected, so memory stays flat however many exist.
+/// Call count_batch() repeatedly until it returns finished=true.
+/// Returns nothing on success, or a structured error as init_batch_search does.
+#[wasm_bindgen]
+pub fn init_batch_count(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+) -> Option<String> {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return Some(json);
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return Some(json),
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+
+    let state = BatchSearchState::new_counting(
+        &entries,
+        target,
+        min_count as usize,
+        max_count as usize,
+    );
+
+    COUNT_STATE.with(|cell| {
+        *cell.borrow_mut() = Some(state);
+    });
+    None
+}
+
+/// Run one batch of counting work (node_budget nodes).
+/// Returns JSON: { found_so_far, nodes_explored, finished, progress, stage,
+/// stage_progress?, stages? }, `stage` as in search_batch
+#[wasm_bindgen]
+pub fn count_batch(node_budget: u32) -> String {
+    COUNT_STATE.with(|cell| {
+        let mut borrow = cell.borrow_mut();
+        match borrow.as_mut() {
+            None => LegacyErrorPayload("no count initialized").to_json(),
+            Some(state) => {
+                let result = state.search_batch(node_budget as u64);
+                CountStepPayload(&result).to_json()
+            }
+        }
+    })
+}
+
+/// Clean up count state.
+#[wasm_bindgen]
+pub fn destroy_batch_count() {
+    COUNT_STATE.with(|cell| {
+        *cell.borrow_mut() = None;
+    });
+}
+
+/// Register a dataset for repeated queries. Returns a handle for the `*_on` functions.
+/// Values are scaled once here; targets passed to queries use the same scale.
+/// Returns 0, never a valid handle, when `scale` is out of range or `numbers`
+/// has more than 16M (MAX_INPUT_LEN) rows.
+#[wasm_bindgen]
+pub fn create_dataset(numbers: &[f64], scale: u32) -> u32 {
+    if check_args(CallArgs { numbers_len: numbers.len(), scale: Some(scale), ..Default::default() }).is_err() {
+        return 0;
+    }
+    register_dataset(Dataset::new(numbers, scale))
+}
+
+fn register_dataset(dataset: Dataset) -> u32 {
+    let handle = NEXT_DATASET_HANDLE.with(|next| {
+        let handle = next.get();
+        next.set(handle.wrapping_add(1).max(1));
+        handle
+    });
+    DATASETS.with(|cell| {
+        cell.borrow_mut().insert(handle, dataset);
+    });
+    handle
+}
+
+/// Start registering a dataset of `expected_len` rows in chunks, for arrays too
+/// large to pass in one call without janking the page. Feed it with
+/// append_dataset_chunk, then call finish_dataset_load for the handle. Replaces
+/// any load in progress. Returns false when `scale` is out of range or
+/// `expected_len` is past MAX_INPUT_LEN.
+#[wasm_bindgen]
+pub fn begin_dataset_load(expected_len: u32, scale: u32) -> bool {
+    let args = CallArgs { numbers_len: expected_len as usize, scale: Some(scale), ..Default::default() };
+    if check_args(args).is_err() {
+        return false;
+    }
+    let loader = DatasetLoader::new(expected_len as usize, scale);
+    DATASET_LOAD.with(|cell| *cell.borrow_mut() = Some(loader));
+    true
+}
+
+/// Append the next rows of the dataset being loaded; they are scaled right away.
+/// Returns false, keeping the load as it was, when no load is in progress or the
+/// chunk would go past `expected_len`.
+#[wasm_bindgen]
+pub fn append_dataset_chunk(chunk: &[f64]) -> bool {
+    DATASET_LOAD.with(|cell| cell.borrow_mut().as_mut().is_some_and(|loader| loader.append(chunk)))
+}
+
+/// Register the loaded dataset, exactly as create_dataset would have with the
+/// whole array, and return its handle. Returns 0 when no load is in progress or
+/// fewer than `expected_len` rows arrived; either way the load is over.
+#[wasm_bindgen]
+pub fn finish_dataset_load() -> u32 {
+    match DATASET_LOAD.with(|cell| cell.borrow_mut().take()).and_then(DatasetLoader::finish) {
+        Some(dataset) => register_dataset(dataset),
+        None => 0,
+    }
+}
+
+/// Free a dataset. Unknown handles are ignored.
+#[wasm_bindgen]
+pub fn destroy_dataset(handle: u32) {
+    DATASETS.with(|cell| {
+        cell.borrow_mut().remove(&handle);
+    });
+}
+
+/// Set row `index` of a dataset to `value`, so the next query sees the edit
+/// without re-sending the array. Applied as if the dataset had been created
+/// with the new value: a row that becomes unusable (NaN, negative, zero at the
+/// dataset's scale) drops out, one that becomes usable joins. Returns false for
+/// an unknown handle or an index past the end.
+#[wasm_bindgen]
+pub fn update_dataset_value(handle: u32, index: u32, value: f64) -> bool {
+    DATASETS.with(|cell| {
+        cell.borrow_mut().get_mut(&handle).is_some_and(|ds| ds.update(index as usize, value))
+    })
+}
+
+/// Add rows to the end of a dataset, as if it had been created with them; they
+/// get the next row indices. Returns false for an unknown handle, or when the
+/// dataset would grow past MAX_INPUT_LEN rows (it is left as it was).
+#[wasm_bindgen]
+pub fn append_dataset_values(handle: u32, values: &[f64]) -> bool {
+    DATASETS.with(|cell| {
+        let mut datasets = cell.borrow_mut();
+        let Some(ds) = datasets.get_mut(&handle) else { return false };
+        let numbers_len = ds.input_len() + values.len();
+        if check_args(CallArgs { numbers_len, ..Default::default() }).is_err() {
+            return false;
+        }
+        ds.append(values);
+        true
+    })
+}
+
+/// find_one against a registered dataset.
+#[wasm_bindgen]
+pub fn find_one_on(handle: u32, target: f64, min_count: u32, max_count: u32) -> String {
+    let prepared = with_dataset(handle, |ds| {
+        let rounding = input::target_rounding(target, ds.scale);
+        scaled_target(target, ds.scale).map(|target| (ds.entries_for_target(target), target, rounding))
+    });
+    match prepared {
+        Some(Ok((entries, target, target_rounding))) => {
+            let mode = FindOneMode { target_rounding, ..Default::default() };
+            run_find_one(&entries, &[], target, min_count, max_count, mode)
+        }
+        Some(Err(json)) => json,
+        None => unknown_dataset_json(),
+    }
+}
+
+/// init_batch_search against a registered dataset, answering as init_batch_search
+/// does. An unknown handle, a max_results over the cap or a target too large for
+/// the dataset's scale is an error that leaves any active batch search untouched.
+#[wasm_bindgen]
+pub fn init_batch_search_on(
+    handle: u32,
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    max_results: u32,
+) -> Option<String> {
+    if let Err(json) = check_args(CallArgs { max_results: Some(max_results), ..Default::default() }) {
+        return Some(json);
+    }
+    let prepared = with_dataset(handle, |ds| {
+        let target_rounding = input::target_rounding(target, ds.scale);
+        let target = scaled_target(target, ds.scale)?;
+        let options = BatchOptions { target, scale: ds.scale, min_count, max_count, max_results, include_indices: None };
+        let extras = BatchExtras { target_rounding, skip_counts: Some(ds.skip_counts(target)), ..Default::default() };
+        Ok((ds.view_for_target(target), options, ds.fingerprint, extras))
+    });
+    match prepared {
+        Some(Ok((view, options, fingerprint, extras))) => {
+            start_batch(BatchEntries::Sorted(view), Vec::new(), options, fingerprint, extras)
+        }
+        Some(Err(json)) => Some(json),
+        None => Some(unknown_dataset_json()),
+    }
+}
+
+/// Count, min, max, total and most frequent repeated values of a dataset's usable
+/// entries, in scaled units.
+#[wasm_bindgen]
+pub fn get_dataset_summary(handle: u32) -> String {
+    with_dataset(handle, |ds| DatasetSummaryPayload { scale: ds.scale, summary: &ds.summary() }.to_json())
+    .unwrap_or_else(unknown_dataset_json)
+}
+
+/// Run `f` on a dataset brought up to date after any edits.
+fn with_dataset<R>(handle: u32, f: impl FnOnce(&Dataset) -> R) -> Option<R> {
+    DATASETS.with(|cell| {
+        cell.borrow_mut().get_mut(&handle).map(|ds| {
+            ds.prepare();
+            f(ds)
+        })
+    })
+}
+
+fn unknown_dataset_json() -> String {
+    LegacyErrorPayload("unknown dataset handle").to_json()
+}
+
+/// Prepare `numbers` and `target` for a series of find_with calls that vary only
+/// the count window, e.g. tightening max_count until a match is small enough to
+/// review. Returns a handle for find_with, or 0 (never a valid handle) when
+/// `scale` is out of range, `numbers` has more than 16M (MAX_INPUT_LEN) rows,
+/// the target does not fit the scale or the prepared input could not be
+/// allocated. Free it with destroy_find_session.
+#[wasm_bindgen]
+pub fn init_find_session(numbers: &[f64], target: f64, scale: Option<u32>) -> u32 {
+    if check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }).is_err() {
+        return 0;
+    }
+    let scale = scale.unwrap_or(0);
+    let Ok(target) = scale_target(target, scale) else { return 0 };
+    let (entries, skipped) = build_entries(numbers, target, scale, None);
+    let Ok(session) = solver::FindSession::new(&entries, None) else { return 0 };
+    let state = FindSessionState { session, target, skipped };
+    let handle = NEXT_FIND_SESSION_HANDLE.with(|next| {
+        let handle = next.get();
+        next.set(handle.wrapping_add(1).max(1));
+        handle
+    });
+    FIND_SESSIONS.with(|cell| {
+        cell.borrow_mut().insert(handle, state);
+    });
+    handle
+}
+
+/// find_one over a find session with this count window; the payload is find_one's
+/// for the session's numbers and target, except that the session keeps the rows
+/// find_one would leave out as provably unusable for this window (see
+/// run_find_one), so the match found and the stats can differ. Calls after the first skip
+/// sorting the input, and past 60 usable entries the B&B skips the subtrees
+/// earlier calls proved hold no match, so a sequence of tightening windows costs
+/// little more than its hardest member.
+#[wasm_bindgen]
+pub fn find_with(handle: u32, min_count: u32, max_count: u32) -> String {
+    FIND_SESSIONS.with(|cell| {
+        let sessions = cell.borrow();
+        let Some(state) = sessions.get(&handle) else {
+            return LegacyErrorPayload("unknown find session").to_json();
+        };
+        let entries = state.session.entries();
+        let mode = FindOneMode::default();
+        run_find_one_with(entries, &state.skipped, state.target, min_count, max_count, mode, |config| {
+            let (result, stats) = state.session.solve(config, Algorithm::Auto);
+            (result, stats, None)
+        })
+    })
+}
+
+/// Free a find session and everything it remembered. Unknown handles are ignored.
+#[wasm_bindgen]
+pub fn destroy_find_session(handle: u32) {
+    FIND_SESSIONS.with(|cell| {
+        cell.borrow_mut().remove(&handle);
+    });
+}
+
+/// Show exactly what the solver would see: for every input row, either its scaled
+/// value or why it was skipped. Returns JSON:
+/// { target, scale, entries: [{index, value, scaled} | {index, skipped_reason}] }
+#[wasm_bindgen]
+pub fn get_effective_entries(numbers: &[f64], target: f64, scale: u32) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale: Some(scale), ..Default::default() }) {
+        return json;
+    }
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let rows = numbers.iter().map(|&n| (n, classify(n, scale, target))).collect();
+    EffectiveEntriesPayload { target, scale, rows }.to_json()
+}
+
+/// Health check: run embedded fixtures through find_one (MITM and B&B), find_all,
+/// a batch search in small steps and a pre-cancelled search. Returns JSON:
+/// { passed, cases: [{ name, passed, detail }], version }
+/// where `detail` says what each case observed, and on failure what it expected.
+/// Takes a few milliseconds, so it can run at page load.
+#[wasm_bindgen]
+pub fn self_test() -> String {
+    SelfTestPayload(&selftest::run()).to_json()
+}
+
+/// A cheap prognosis for a find_one search, without running it. Returns JSON:
+/// { n, skipped, algorithms, count_window, infeasible, worst_case_nodes, difficulty,
+///   mitm_memory_bytes, mitm_memory_over_budget }
+/// `infeasible` is "no_entries", "count_window" or "gcd" when no solution can exist,
+/// otherwise null. `difficulty` ("instant", "seconds", "minutes", "intractable")
+/// classifies `worst_case_nodes`; a search that finds a solution usually stops far
+/// sooner, so treat it as an upper bound.
+#[wasm_bindgen]
+pub fn estimate_difficulty(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+    let cancelled = current_cancel_flag();
+    let config = SolverConfig {
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+        controller: &*cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    };
+    let prognosis = solver::prognosis(&entries, &config);
+    PrognosisPayload { prognosis: &prognosis, skipped: numbers.len() - entries.len() }.to_json()
+}
+
+/// How much choice a target leaves, before searching, for UI hints. Returns JSON:
+/// { usable_count, total_value, target, target_fraction, count_window, mandatory,
+///   irrelevant }
+/// `total_value` sums the usable rows and `target_fraction` is the target's share
+/// of it (null with no usable rows), both in scaled units. `mandatory` lists the
+/// rows every solution must include: without one, even the largest rows the count
+/// window allows fall short. `irrelevant` lists rows no solution can include: with
+/// one in, the rest of the target is out of reach of the smallest or largest
+/// other rows allowed. Both come from bounds, so what they list is certain, but
+/// rows whose irrelevance only a search could show are not listed. Both are empty
+/// when no count can work (`count_window` null).
+#[wasm_bindgen]
+pub fn analyze_target(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+    let cancelled = current_cancel_flag();
+    let config = SolverConfig {
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+        controller: &*cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    };
+    let analysis = solver::analyze_target(&entries, &config);
+    TargetAnalysisPayload { analysis: &analysis, usable_count: entries.len(), target }.to_json()
+}
+
+/// Several targets at once: one combination per target, with no row in two of
+/// them, e.g. deposits reconciled together. Targets with the fewest candidate
+/// combinations choose first, and a target left without room sends the search
+/// back to try the earlier ones' other combinations. Returns JSON:
+/// { status: "solved" | "partial", targets, order, stats: { nodes_explored,
+///   backtracks } }
+/// `targets` follows the order given, each { target, ...combination } or
+/// { target, unsatisfied } with the reason: "no_combination" (none exists even
+/// with every row free), "conflict" (the rows it needs are taken) or
+/// "budget_exhausted". A partial result keeps the most targets the search
+/// satisfied together. `order` lists the satisfied targets' positions in solving
+/// order. Targets are in units like `numbers`; at most 64 of them.
+/// cancel_search() stops it with { status: "cancelled" }.
+#[wasm_bindgen]
+pub fn solve_multiple_targets(
+    numbers: &[f64],
+    targets: &[f64],
+    min_count: u32,
+    max_count: u32,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    if targets.len() > multi::MAX_TARGETS {
+        let err = InvalidInput::OutOfRange { field: "targets", max: multi::MAX_TARGETS as u64, actual: targets.len() as u64 };
+        return ErrorPayload::from(err).to_json();
+    }
+    let scale = scale.unwrap_or(0);
+    let targets = match targets.iter().map(|&t| scaled_target(t, scale)).collect::<Result<Vec<u64>, String>>() {
+        Ok(targets) => targets,
+        Err(json) => return json,
+    };
+    let largest = targets.iter().copied().max().unwrap_or(0);
+    let (entries, _) = build_entries(numbers, largest, scale, None);
+
+    let cancelled = cancel_flag();
+    let result = multi::solve_multiple_targets(
+        &entries,
+        &targets,
+        min_count as usize,
+        max_count as usize,
+        &*cancelled,
+        multi::DEFAULT_MULTI_TARGET_NODES,
+    );
+    let format = result_format();
+    if let Ok(result) = &result {
+        keep_full_results(format, result.combinations.iter().flatten().map(Vec::as_slice));
+    }
+    MultiTargetPayload { result: &result, targets: &targets, format }.to_json()
+}
+
+/// Offsetting groups between two columns, debits and credits or two accounts:
+/// a combination of `numbers_a` and one of `numbers_b`, each within its own
+/// count window, with the same total. With `hint_sum` the total lies within
+/// `tolerance` of it (default 0) and the nearest totals are tried first;
+/// without one, the smallest. The column with fewer usable rows has its
+/// achievable totals enumerated and the other is searched for each in turn
+/// (see pairs.rs), all under one node budget. Returns JSON:
+/// { status: "found", sum, a: {...combination}, b: {...combination}, stats }
+/// or { status: "not_found", more_may_exist?, stats }, with stats
+/// { enumerated: "a" | "b", sums, probes, nodes_explored }. Indices are rows of
+/// each column; values, `sum` and the hint in units at `scale`.
+/// `more_may_exist: true` means the budget, or the cap of 65,536 totals, cut
+/// the search short. cancel_search() stops it with { status: "cancelled" }.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn find_matching_pair(
+    numbers_a: &[f64],
+    numbers_b: &[f64],
+    min_count_a: u32,
+    max_count_a: u32,
+    min_count_b: u32,
+    max_count_b: u32,
+    hint_sum: Option<f64>,
+    tolerance: Option<f64>,
+    scale: Option<u32>,
+) -> String {
+    for numbers in [numbers_a, numbers_b] {
+        if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+            return json;
+        }
+    }
+    let scale = scale.unwrap_or(0);
+    let hint = match hint_sum.map(|hint| scaled_target(hint, scale)).transpose() {
+        Ok(hint) => hint.filter(|&hint| hint > 0),
+        Err(json) => return json,
+    };
+    let tolerance = match scaled_target(tolerance.unwrap_or(0.0), scale) {
+        Ok(tolerance) => tolerance,
+        Err(json) => return json,
+    };
+    // No row larger than the band's top can be in either combination
+    let largest = hint.map_or(u64::MAX, |hint| hint.saturating_add(tolerance));
+    let mut columns = Vec::with_capacity(2);
+    for numbers in [numbers_a, numbers_b] {
+        match capped_rows(numbers, largest, scale, None, DEFAULT_MAX_ENTRIES as usize) {
+            Ok(rows) => columns.push(rows.entries),
+            Err(json) => return json,
+        }
+    }
+
+    let cancelled = cancel_flag();
+    let result = pairs::solve_matching_pair(
+        pairs::Column { entries: &columns[0], min_count: min_count_a as usize, max_count: max_count_a as usize },
+        pairs::Column { entries: &columns[1], min_count: min_count_b as usize, max_count: max_count_b as usize },
+        hint,
+        tolerance,
+        &*cancelled,
+        pairs::DEFAULT_PAIR_NODES,
+    );
+    let format = result_format();
+    if let Ok(pairs::PairSearch { pair: Some(pair), .. }) = &result {
+        keep_full_results(format, [pair.a.as_slice(), pair.b.as_slice()]);
+    }
+    MatchingPairPayload { result: &result, format }.to_json()
+}
+
+/// Largest number that still converts to integer units at `scale` (about
+/// u64::MAX / 10^scale), so the frontend can warn before calling. Larger rows are
+/// skipped as `scale_overflow`; a larger target is a `target_scale_overflow` error.
+#[wasm_bindgen]
+pub fn max_safe_value_for_scale(scale: u32) -> f64 {
+    input::max_safe_value(scale)
+}
+
+/// Canonical form of a results payload, for comparing or caching result sets
+/// regardless of search order. Accepts a search_batch / find_one payload, a bare
+/// array of combinations, or a single combination; indices are sorted within
+/// each combination (values kept aligned) and combinations by index list.
+/// Returns the canonical JSON, or { error: "invalid_json", offset, message }.
+#[wasm_bindgen]
+pub fn canonicalize_results(json: &str) -> String {
+    match canonical::canonicalize_payload(json) {
+        Ok(canonical) => canonical,
+        Err(err) => ErrorPayload::from(err).to_json(),
+    }
+}
+
+/// Check that a result list is complete, for small inputs: enumerates every
+/// combination of `numbers` (at most 24 usable rows) and compares it with the
+/// combinations in `results_json`, in any shape canonicalize_results accepts.
+/// Returns JSON:
+/// { status: "verified" | "mismatch", usable_count, expected_count,
+///   reported_count, missing: [{ indices, values, count }],
+///   extra: [{ indices, values?, reason, ... }] }
+/// `missing` lists the combinations that exist but weren't reported. `extra`
+/// lists reported ones that don't belong, each with a reason: "repeated" (the
+/// same indices were reported before), "not_enumerated" (valid, yet not found
+/// by the enumeration) or why it isn't valid ("sum_mismatch",
+/// "index_out_of_range", ... with the same detail as internal_consistency_error).
+/// Values and the target are in scaled units. With more usable rows it returns
+/// { status: "not_verifiable", reason: "too_many_rows", usable_count, max_rows };
+/// a payload with truncated combinations is an invalid_json error.
+/// cancel_search() stops it with { status: "cancelled" }.
+#[wasm_bindgen]
+pub fn verify_exhaustive(
+    numbers: &[f64],
+    target: f64,
+    min_count: u32,
+    max_count: u32,
+    results_json: &str,
+    scale: Option<u32>,
+) -> String {
+    if let Err(json) = check_args(CallArgs { numbers_len: numbers.len(), scale, ..Default::default() }) {
+        return json;
+    }
+    let scale = scale.unwrap_or(0);
+    let target = match scaled_target(target, scale) {
+        Ok(target) => target,
+        Err(json) => return json,
+    };
+    let reported = match canonical::reported_combinations(results_json) {
+        Ok(reported) => reported,
+        Err(err) => return ErrorPayload::from(err).to_json(),
+    };
+    let (entries, _) = build_entries(numbers, target, scale, None);
+    if entries.len() > EXHAUSTIVE_MAX_ROWS {
+        return ExhaustiveCheckPayload::NotVerifiable { usable_count: entries.len() }.to_json();
+    }
+
+    let cancelled = cancel_flag();
+    let config = SolverConfig {
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+        controller: &*cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    };
+    let all = solver::solve_all_combinations(&entries, &config, usize::MAX);
+    if all.cancelled {
+        return ExhaustiveCheckPayload::Cancelled.to_json();
+    }
+    let reference = Reference {
+        numbers: numbers.to_vec(),
+        scale,
+        target,
+        min_count: min_count as usize,
+        max_count: max_count as usize,
+    };
+    let check = reference.compare_exhaustive(all.results, reported);
+    ExhaustiveCheckPayload::Checked { check: &check, usable_count: entries.len() }.to_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialize::stamped;
+    use solver::OriginalIndex;
+
+    fn values_of(combos: &[Vec<NumberEntry>]) -> Vec<Vec<u64>> {
+        let mut out: Vec<Vec<u64>> = combos.iter()
+            .map(|c| {
+                let mut v: Vec<u64> = c.iter().map(|e| e.value).collect();
+                v.sort_unstable();
+                v
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
+    fn run_batch(entries: &[NumberEntry], target: u64) -> Vec<Vec<NumberEntry>> {
+        let mut state = BatchSearchState::new(entries, target, 1, 10, 1000);
+        while !state.search_batch(1000).finished {}
+        state.all_results().to_vec()
+    }
+
+    #[test]
+    fn test_include_indices_results_only_reference_included() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let include = [0u32, 2, 3, 5, 7];
+        let (entries, _) = build_entries(&numbers, 10, 0, Some(&include));
+        let results = run_batch(&entries, 10);
+        assert!(!results.is_empty());
+        for combo in &results {
+            for e in combo {
+                assert!(include.contains(&(e.index_u32())));
+                assert_eq!(e.value, numbers[e.original_index.as_usize()] as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_include_indices_matches_standalone_subset() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let include = [0u32, 2, 3, 5, 7];
+        let (included, _) = build_entries(&numbers, 10, 0, Some(&include));
+
+        let subset: Vec<f64> = include.iter().map(|&i| numbers[i as usize]).collect();
+        let (standalone, _) = build_entries(&subset, 10, 0, None);
+
+        assert_eq!(values_of(&run_batch(&included, 10)), values_of(&run_batch(&standalone, 10)));
+    }
+
+    #[test]
+    fn test_skipped_reported_in_find_one_payload() {
+        let json = find_one(&[4.0, 6.0], 10.0, 1, 2, Some(vec![0, 1, 5]), None, None);
+        assert!(json.contains(r#""status":"found""#));
+        assert!(json.ends_with(r#","skipped":[{"index":5,"reason":"out_of_range"}]}"#));
+    }
+
+    #[test]
+    fn test_payload_ids_match_between_find_one_and_batch() {
+        let numbers = [2.0, 9.0, 4.0];
+        let json = find_one(&numbers, 9.0, 1, 1, None, None, None);
+        let id = format!("{:016x}", combination_id(&[NumberEntry { value: 9, original_index: OriginalIndex::new(1) }]));
+        assert!(json.contains(&format!(r#""id":"{}""#, id)));
+
+        init_batch_search(&numbers, 9.0, 1, 1, 10, None, None);
+        let batch = search_batch(100);
+        destroy_batch_search();
+        assert!(batch.contains(&format!(r#""id":"{}""#, id)));
+    }
+
+    /// (indices, values, selection_order) of one reported combination.
+    type Reported = (Vec<u64>, Vec<u64>, Option<Vec<u64>>);
+
+    fn combinations_of(json: &str) -> Vec<Reported> {
+        let payload = canonical::parse(json).unwrap();
+        let list = |v: &canonical::Value| match v {
+            canonical::Value::Array(items) => items.iter().map(|i| i.as_u64().unwrap()).collect::<Vec<u64>>(),
+            _ => panic!("expected an array in {}", json),
+        };
+        let combos = match payload.get("new_results") {
+            Some(canonical::Value::Array(items)) => items.clone(),
+            _ => vec![payload],
+        };
+        combos.iter()
+            .map(|c| (list(c.get("indices").unwrap()), list(c.get("values").unwrap()), c.get("selection_order").map(list)))
+            .collect()
+    }
+
+    #[test]
+    fn test_preserve_discovery_order_is_a_permutation() {
+        let numbers = [9.0, 1.0, 5.0, 3.0, 4.0, 2.0];
+        let run = |discovery: bool| {
+            set_preserve_discovery_order(discovery);
+            let one = find_one(&numbers, 9.0, 3, 3, None, None, None);
+            init_batch_search(&numbers, 9.0, 1, 6, 1000, None, None);
+            let batch = search_batch(1_000_000);
+            destroy_batch_search();
+            let mut combos = combinations_of(&one);
+            combos.extend(combinations_of(&batch));
+            combos
+        };
+        let by_index = run(false);
+        let by_discovery = run(true);
+        set_preserve_discovery_order(false);
+
+        assert_eq!(by_index.len(), by_discovery.len());
+        assert!(by_index.len() > 2);
+        for ((indices, values, selection), (found_indices, found_values, none)) in by_index.iter().zip(&by_discovery) {
+            assert!(none.is_none());
+            assert!(indices.windows(2).all(|w| w[0] < w[1]));
+            // Selected smallest value first
+            assert!(found_values.windows(2).all(|w| w[0] <= w[1]));
+            // selection_order maps the index view onto the discovery view
+            let selection = selection.as_ref().unwrap();
+            for k in 0..indices.len() {
+                let step = selection[k] as usize;
+                assert_eq!(found_indices[step], indices[k]);
+                assert_eq!(found_values[step], values[k]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_one_uniqueness_payload() {
+        let json = find_one(&[1.0, 2.0, 3.0, 4.0], 5.0, 2, 2, None, Some(true), None);
+        assert!(json.contains(r#""unique":false"#));
+        assert!(json.contains(r#""uniqueness_budget":1000000"#));
+
+        let json = find_one(&[1.0, 2.0, 3.0, 4.0], 5.0, 2, 2, None, None, None);
+        assert!(!json.contains("unique"));
+    }
+
+    #[test]
+    fn test_find_one_stats_phases() {
+        let json = find_one(&[1.0, 2.0, 3.0, 4.0], 9.0, 3, 3, None, None, None);
+        assert!(json.contains(r#""stats":{"phases":[{"algorithm":"mitm","budget":null,"nodes":"#));
+        assert!(json.contains(r#""count_window":[3,3],"shortcut":"none","peak_memory_estimate_bytes":"#), "{}", json);
+
+        let json = find_one(&[1.0, 2.0], 50.0, 1, 2, None, None, None);
+        assert_eq!(json, r#"{"status":"not_found","stats":{"phases":[],"count_window":null,"peak_memory_estimate_bytes":56}}"#);
+    }
+
+    #[test]
+    fn test_scale_applies_to_values_and_target() {
+        let json = find_one(&[1.25, 0.75, 3.10], 2.0, 2, 2, None, None, Some(2));
+        assert!(json.contains(r#""indices":[0,1],"values":[125,75]"#), "{}", json);
+
+        // Without scale the cents are truncated away and 1 + 0 can't make 2
+        let json = find_one(&[1.25, 0.75, 3.10], 2.0, 2, 2, None, None, None);
+        assert!(json.starts_with(r#"{"status":"not_found""#));
+    }
+
+    #[test]
+    fn test_payload_indices_are_raw_input_rows() {
+        // Rows 0 and 1 aren't included, 2 went in an earlier run and 4 can't be
+        // reached, so the one answer lives in rows 3, 6 and 7 of the raw input
+        let numbers = [1.25, 9.99, 2.50, 0.75, 4.10, 2.50, 3.00, 1.25];
+        let options = r#"{"target":5,"scale":2,"include_indices":[2,3,4,5,6,7],"previously_matched":[2]}"#;
+        let json = find_one_v2(&numbers, options);
+        assert!(json.contains(r#""indices":[3,6,7],"values":[75,300,125]"#), "{}", json);
+
+        let mut rng = testing::rng();
+        for round in 0..200 {
+            let n = 2 + rng.below(12) as usize;
+            let numbers: Vec<f64> = (0..n).map(|_| (1 + rng.below(2000)) as f64 / 100.0).collect();
+            let include: Vec<usize> = (0..n).filter(|_| rng.below(4) != 0).collect();
+            let matched: Vec<usize> = include.iter().copied().filter(|_| rng.below(4) == 0).collect();
+            let usable: Vec<usize> = include.iter().copied().filter(|i| !matched.contains(i)).collect();
+            if usable.is_empty() {
+                continue;
+            }
+            let cents: u64 = usable.iter()
+                .filter(|_| rng.below(2) == 0)
+                .map(|&i| input::scale_value(numbers[i], 2).unwrap())
+                .sum::<u64>()
+                .max(input::scale_value(numbers[usable[0]], 2).unwrap());
+            let options = format!(
+                r#"{{"target":{}.{:02},"scale":2,"max_count":{},"include_indices":{:?},"previously_matched":{:?}}}"#,
+                cents / 100, cents % 100, n, include, matched,
+            );
+            let one = find_one_v2(&numbers, &options);
+            assert!(one.starts_with(r#"{"status":"found""#), "round {}: {}", round, one);
+            assert_eq!(init_batch_search_v2(&numbers, &options), None);
+            let batch = search_batch(1_000_000);
+            destroy_batch_search();
+
+            let mut combos = combinations_of(&one);
+            combos.extend(combinations_of(&batch));
+            for (indices, values, _) in combos {
+                assert_eq!(values.iter().sum::<u64>(), cents);
+                for (&i, &value) in indices.iter().zip(&values) {
+                    let i = i as usize;
+                    assert!(usable.contains(&i), "round {}: row {} in {}", round, i, options);
+                    assert_eq!(input::scale_value(numbers[i], 2), Ok(value), "round {}: row {}", round, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_effective_entries_classification() {
+        let json = get_effective_entries(&[0.9999999, 10.0, 10.01, 0.0, f64::NAN], 10.0, 2);
+        assert_eq!(json, concat!(
+            r#"{"target":1000,"scale":2,"entries":["#,
+            r#"{"index":0,"value":0.9999999,"scaled":100},"#,
+            r#"{"index":1,"value":10,"scaled":1000},"#,
+            r#"{"index":2,"skipped_reason":"exceeds_target"},"#,
+            r#"{"index":3,"skipped_reason":"non_positive"},"#,
+            r#"{"index":4,"skipped_reason":"not_finite"}]}"#,
+        ));
+
+        let json = get_effective_entries(&[0.9999999], 10.0, 0);
+        assert!(json.contains(r#""skipped_reason":"zero_after_scaling""#));
+    }
+
+    #[test]
+    fn test_dataset_handle_lifecycle() {
+        let handle = create_dataset(&[2.5, 1.5, 4.0, 1.5], 1);
+        assert_eq!(
+            get_dataset_summary(handle),
+            r#"{"scale":1,"input_len":4,"count":4,"min":15,"max":40,"total":95,"top_duplicates":[{"value":15,"count":2}]}"#,
+        );
+
+        let json = find_one_on(handle, 4.0, 2, 2);
+        assert!(json.contains(r#""indices":[0,1],"values":[25,15]"#) || json.contains(r#""indices":[0,3]"#), "{}", json);
+
+        assert_eq!(init_batch_search_on(handle, 4.0, 1, 3, 10), None);
+        let batch = search_batch(1000);
+        assert!(batch.contains(r#""total_found":3"#), "{}", batch);
+        destroy_batch_search();
+
+        destroy_dataset(handle);
+        assert_eq!(get_dataset_summary(handle), stamped(r#"{"error":"unknown dataset handle"}"#));
+        assert_eq!(find_one_on(handle, 4.0, 2, 2), stamped(r#"{"error":"unknown dataset handle"}"#));
+        assert_eq!(init_batch_search_on(handle, 4.0, 1, 3, 10), Some(stamped(r#"{"error":"unknown dataset handle"}"#)));
+    }
+
+    #[test]
+    fn test_find_session_lifecycle() {
+        let numbers: Vec<f64> = (0..30).map(|i| ((i * 37) % 101 + 1) as f64 / 10.0).chain([-1.0, f64::NAN]).collect();
+        let handle = init_find_session(&numbers, 25.3, Some(1));
+        assert_ne!(handle, 0);
+        for (min, max) in [(1, 0), (1, 8), (1, 4), (2, 3), (3, 3), (6, 6), (1, 2), (1, 8)] {
+            let max = if max == 0 { numbers.len() as u32 } else { max };
+            // find_one leaves provably unusable rows out first; a match is found either way
+            let status = |json: String| json.split(r#","id""#).next().unwrap().split(r#","stats""#).next().unwrap().to_string();
+            let with = find_with(handle, min, max);
+            assert_eq!(status(with.clone()), status(find_one(&numbers, 25.3, min, max, None, None, Some(1))), "[{}, {}]", min, max);
+            assert!(!with.contains("provably_unusable"), "{}", with);
+        }
+
+        destroy_find_session(handle);
+        assert!(FIND_SESSIONS.with(|cell| !cell.borrow().contains_key(&handle)));
+        assert_eq!(find_with(handle, 1, 4), stamped(r#"{"error":"unknown find session"}"#));
+        destroy_find_session(handle);
+        assert_eq!(init_find_session(&numbers, 25.3, Some(99)), 0);
+        assert_eq!(init_find_session(&numbers, 1e30, Some(6)), 0);
+    }
+
+    #[test]
+    fn test_dataset_edits_between_queries() {
+        let mut rng = testing::rng();
+        let mut numbers: Vec<f64> = (0..12).map(|i| ((i * 7) % 23 + 1) as f64).collect();
+        let handle = create_dataset(&numbers, 0);
+        for round in 0..30 {
+            if round % 6 == 5 {
+                let added = [rng.below(30) as f64, rng.below(30) as f64];
+                numbers.extend_from_slice(&added);
+                assert!(append_dataset_values(handle, &added));
+            } else {
+                let index = rng.below(numbers.len() as u64) as usize;
+                numbers[index] = rng.below(30) as f64;
+                assert!(update_dataset_value(handle, index as u32, numbers[index]));
+            }
+            let fresh = create_dataset(&numbers, 0);
+            let target = 20.0 + rng.below(40) as f64;
+            assert_eq!(find_one_on(handle, target, 2, 4), find_one_on(fresh, target, 2, 4), "round {}", round);
+            assert_eq!(get_dataset_summary(handle), get_dataset_summary(fresh));
+
+            let mut batches = Vec::new();
+            for h in [handle, fresh] {
+                assert_eq!(init_batch_search_on(h, target, 1, 3, 1000), None);
+                batches.push(search_batch(1_000_000));
+                let bytes = export_batch_state().unwrap();
+                // The fingerprint follows the edits
+                assert!(import_batch_state(&bytes, Some(numbers.clone())).starts_with(r#"{"status":"ok""#));
+                destroy_batch_search_quiet();
+            }
+            let strip_elapsed = |json: &str| json.split(r#","elapsed_ms""#).next().unwrap().to_string();
+            assert_eq!(strip_elapsed(&batches[0]), strip_elapsed(&batches[1]));
+            destroy_dataset(fresh);
+        }
+        assert!(!update_dataset_value(handle, numbers.len() as u32, 1.0));
+        destroy_dataset(handle);
+        assert!(!update_dataset_value(handle, 0, 1.0));
+        assert!(!append_dataset_values(handle, &[1.0]));
+    }
+
+    #[test]
+    fn test_chunked_dataset_load() {
+        let numbers: Vec<f64> = (0..5000).map(|i| ((i * 37) % 101) as f64 * 0.5).collect();
+        let single = create_dataset(&numbers, 1);
+
+        assert!(!append_dataset_chunk(&[1.0]));
+        assert!(!begin_dataset_load(numbers.len() as u32, 10));
+        assert!(begin_dataset_load(numbers.len() as u32, 1));
+        for chunk in numbers.chunks(999) {
+            assert!(append_dataset_chunk(chunk));
+        }
+        assert!(!append_dataset_chunk(&[1.0]));
+        let chunked = finish_dataset_load();
+        assert_ne!(chunked, 0);
+        assert_eq!(finish_dataset_load(), 0);
+
+        assert_eq!(get_dataset_summary(chunked), get_dataset_summary(single));
+        assert_eq!(find_one_on(chunked, 50.0, 1, 1), find_one_on(single, 50.0, 1, 1));
+        // Same fingerprint: state exported from one imports against the other's numbers
+        assert_eq!(init_batch_search_on(chunked, 60.0, 2, 2, 50), None);
+        let bytes = export_batch_state().unwrap();
+        assert!(import_batch_state(&bytes, Some(numbers.clone())).starts_with(r#"{"status":"ok""#));
+        destroy_batch_search_quiet();
+
+        // A short load yields no handle
+        begin_dataset_load(10, 0);
+        append_dataset_chunk(&[1.0; 9]);
+        assert_eq!(finish_dataset_load(), 0);
+        destroy_dataset(single);
+        destroy_dataset(chunked);
+    }
+
+    #[test]
+    fn test_estimate_difficulty() {
+        let json = estimate_difficulty(&[3.0, 5.0, 7.0, 11.0, -1.0, 40.0], 20.0, 1, 6, None);
+        assert!(json.starts_with(r#"{"n":4,"skipped":2,"algorithms":["mitm"],"count_window":[3,3],"infeasible":null,"#), "{}", json);
+        assert!(json.contains(r#""difficulty":"instant""#), "{}", json);
+        let json = estimate_difficulty(&[0.2, 0.4, 0.6], 0.7, 1, 3, Some(1));
+        assert!(json.contains(r#""infeasible":"gcd""#), "{}", json);
+        assert!(estimate_difficulty(&[1.0], 1.0, 1, 1, Some(10)).contains("scale_out_of_range"));
+    }
+
+    #[test]
+    fn test_count_batch_lifecycle() {
+        assert_eq!(count_batch(10), stamped(r#"{"error":"no count initialized"}"#));
+        init_batch_count(&[1.0, 2.0, 3.0, 4.0, 5.0], 5.0, 1, 5, None);
+        let mut last = String::new();
+        for _ in 0..100 {
+            last = count_batch(3);
+            if last.contains(r#""finished":true"#) {
+                break;
+            }
+        }
+        assert!(last.starts_with(r#"{"found_so_far":3,"#), "{}", last);
+        destroy_batch_count();
+        assert_eq!(count_batch(10), stamped(r#"{"error":"no count initialized"}"#));
+    }
+
+    #[test]
+    fn test_find_matching_for_rows_excludes_target_rows() {
+        // Row 0 (3.50) is the deposit. Row 3 has the same value and would match alone,
+        // but must not be confused with the deposit row itself; rows 1 + 2 also match.
+        let numbers = [3.5, 1.25, 2.25, 3.5];
+        let json = find_matching_for_rows(&numbers, &[0], 1, 1, Some(2));
+        assert!(json.contains(r#""indices":[3]"#), "{}", json);
+        assert!(json.contains(r#""target":350"#), "{}", json);
+
+        let json = find_matching_for_rows(&numbers, &[0, 3], 1, 4, Some(2));
+        assert!(json.contains(r#""status":"not_found""#), "{}", json);
+        assert!(json.contains(r#""target":700"#), "{}", json);
+
+        let json = find_matching_for_rows(&numbers, &[0], 2, 2, Some(2));
+        assert!(json.contains(r#""indices":[1,2]"#), "{}", json);
+    }
+
+    #[test]
+    fn test_find_matching_for_rows_invalid_index() {
+        assert_eq!(
+            find_matching_for_rows(&[1.0, 2.0], &[0, 5], 1, 2, None),
+            stamped(r#"{"status":"error","code":"invalid_target_index","index":5,"reason":"out_of_range"}"#),
+        );
+        assert_eq!(
+            find_matching_for_rows(&[1.0, 2.0], &[], 1, 2, None),
+            stamped(r#"{"status":"error","code":"empty_target_rows"}"#),
+        );
+    }
+
+    #[test]
+    fn test_export_import_batch_state() {
+        let numbers: Vec<f64> = (1..=20).map(|v| v as f64).collect();
+        init_batch_search(&numbers, 30.0, 2, 5, 1000, None, None);
+        search_batch(137);
+        let bytes = export_batch_state().unwrap();
+
+        let info = inspect_batch_state(&bytes);
+        assert!(info.contains(r#""compatible":true"#), "{}", info);
+        assert!(info.contains(r#""n":20"#), "{}", info);
+        assert!(info.contains(r#""target":30,"scale":0,"min_count":2,"max_count":5"#), "{}", info);
+        assert!(info.contains(&format!(r#""fingerprint":"{:016x}""#, fingerprint(&numbers))), "{}", info);
+
+        let rest_uninterrupted = search_batch(1_000_000);
+        let json = import_batch_state(&bytes, Some(numbers.clone()));
+        assert!(json.starts_with(r#"{"status":"ok","total_found":"#), "{}", json);
+        let rest_resumed = search_batch(1_000_000);
+        // Timed from init and from import respectively
+        let strip_elapsed = |json: &str| json.split(r#","elapsed_ms""#).next().unwrap().to_string();
+        assert_eq!(strip_elapsed(&rest_uninterrupted), strip_elapsed(&rest_resumed));
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_export_keeps_payload_options() {
+        let numbers: Vec<f64> = (1..=12).map(f64::from).collect();
+        let options = r#"{"target":15,"max_count":3,"score_results":"prefer_round","tag":"t","memory_limit_mb":1}"#;
+        init_batch_search_v2(&numbers, options);
+        let first = search_batch(20);
+        let bytes = export_batch_state().unwrap();
+        destroy_batch_search_quiet();
+
+        let json = import_batch_state(&bytes, None);
+        assert!(json.starts_with(r#"{"status":"ok","#), "{}", json);
+        let rest = search_batch(1_000_000);
+        assert!(first.starts_with(r#"{"tag":"t","#) && rest.starts_with(r#"{"tag":"t","#), "{}", rest);
+        assert!(first.contains(r#""score":"#) && rest.contains(r#""score":"#), "{}", rest);
+        let header = export::decode_header(&bytes).unwrap();
+        assert_eq!(header.carried, CarriedOptions { score: ScoreMode::PreferRound, tag: Some("t".to_string()), paranoid: false, exact_integers: false, skip_counts: Some(Vec::new()) });
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_import_batch_state_rejects_mismatches() {
+        let numbers = [1.0, 2.0, 3.0];
+        init_batch_search(&numbers, 3.0, 1, 3, 10, None, None);
+        let bytes = export_batch_state().unwrap();
+
+        let json = import_batch_state(&bytes, Some(vec![1.0, 2.0, 4.0]));
+        assert!(json.starts_with(r#"{"status":"error","code":"fingerprint_mismatch""#), "{}", json);
+
+        let entries = build_entries(&numbers, 3, 0, None).0;
+        let state = BatchSearchState::new(&entries, 3, 1, 3, 10);
+        let mut header = export::decode_header(&bytes).unwrap();
+        header.crate_version = "99.0.0".to_string();
+        let json = import_batch_state(&export::encode(&header, &state), None);
+        assert!(json.starts_with(r#"{"status":"error","code":"incompatible_version","found":"99.0.0""#), "{}", json);
+        assert!(inspect_batch_state(&export::encode(&header, &state)).contains(r#""compatible":false"#));
+
+        assert_eq!(import_batch_state(b"junk", None), stamped(r#"{"status":"error","code":"not_a_batch_state"}"#));
+        destroy_batch_search();
+        assert!(export_batch_state().is_none());
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let numbers: Vec<f64> = (1..=30).map(|v| v as f64 * 1.5).collect();
+        init_batch_search(&numbers, 60.0, 2, 6, 100_000, None, Some(1));
+        search_batch(5000);
+        let with_data = export_session(true, true, None).unwrap();
+        let without_data = export_session(false, false, None).unwrap();
+        let rest_uninterrupted = search_batch(1_000_000);
+
+        let info = describe_session(&with_data);
+        assert!(info.starts_with(r#"{"session_version":1,"compressed":true,"data":"included","#), "{}", info);
+        assert!(info.contains(r#""target":600,"scale":1,"min_count":2,"max_count":6,"max_results":100000"#), "{}", info);
+        assert!(info.contains(r#""stats":{"nodes_explored":5000,"finished":false,"termination_reason":null,"#), "{}", info);
+        let info = describe_session(&without_data);
+        assert!(info.contains(r#""data":"not_consented""#), "{}", info);
+        // Rows only: no value in the session is anywhere but in the options
+        assert!(!without_data.windows(8).any(|w| w == 15u64.to_le_bytes()), "value 1.5 leaked");
+
+        let json = import_session(&with_data, None);
+        assert!(json.starts_with(r#"{"status":"ok","total_found":"#) && json.ends_with(r#","replayed":false}"#), "{}", json);
+        // Restored results are returned again, so compare where both end up
+        let total_found = |json: &str| json.split(r#""total_found":"#).nth(1).map(|rest| rest.split(',').next().unwrap().to_string());
+        assert_eq!(total_found(&search_batch(1_000_000)), total_found(&rest_uninterrupted));
+        assert!(total_found(&rest_uninterrupted).is_some(), "{}", rest_uninterrupted);
+
+        assert_eq!(
+            import_session(&without_data, None),
+            stamped(r#"{"status":"error","code":"session_has_no_data","data":"not_consented"}"#),
+        );
+        let json = import_session(&without_data, Some(vec![1.0, 2.0]));
+        assert!(json.starts_with(r#"{"status":"error","code":"fingerprint_mismatch""#), "{}", json);
+        assert_eq!(import_session(&without_data, Some(numbers.clone())), r#"{"status":"ok","total_found":0,"replayed":true}"#);
+        let replayed = search_batch(1_000_000);
+        let full = export_session(false, false, None).unwrap();
+        let info = describe_session(&full);
+        assert!(info.contains(r#""finished":true,"termination_reason":"exhausted""#), "{}", info);
+        // The replay found what the original did, in the same order
+        assert!(replayed.contains(r#""finished":true"#), "{}", replayed);
+        let session = Session::decode(&without_data).unwrap();
+        assert!(Session::decode(&full).unwrap().results.starts_with(&session.results));
+
+        assert_eq!(import_session(b"TSBS", None), stamped(r#"{"status":"error","code":"not_a_session"}"#));
+        destroy_batch_search();
+        assert!(export_session(false, false, None).is_none());
+    }
+
+    #[test]
+    fn test_session_size_cap() {
+        let numbers: Vec<f64> = (1..=24).map(|v| v as f64).collect();
+        init_batch_search(&numbers, 40.0, 1, 24, 100_000, None, None);
+        search_batch(1_000_000);
+        let capped = Session::decode(&export_session(true, false, Some(2000)).unwrap()).unwrap();
+        assert_eq!(capped.data, SessionData::OmittedForSize);
+        assert!(!capped.results.is_empty() && (capped.results.len() as u64) < capped.header.results_so_far);
+        let json = import_session(&capped.encode(), None);
+        assert!(json.contains(r#""data":"omitted_for_size""#), "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_find_random() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let json = find_random(&numbers, 5.0, 1, 5, 9, None);
+        assert!(json.contains(r#""status":"found""#), "{}", json);
+        assert!(json.contains(r#""algorithm":"mitm_sample""#), "{}", json);
+        assert_eq!(json, find_random(&numbers, 5.0, 1, 5, 9, None));
+
+        let json = find_random(&numbers, 50.0, 1, 5, 9, None);
+        assert!(json.starts_with(r#"{"status":"not_found""#), "{}", json);
+
+        let many: Vec<f64> = (1..=41).map(|v| v as f64).collect();
+        assert_eq!(
+            find_random(&many, 1000.0, 1, 41, 0, None),
+            stamped(r#"{"status":"error","code":"too_many_entries","n":41,"max":40}"#),
+        );
+    }
+
+    #[test]
+    fn test_usage_histogram_payload() {
+        destroy_batch_search();
+        assert_eq!(get_usage_histogram(), stamped(r#"{"error":"no search initialized"}"#));
+
+        init_batch_search(&[7.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 6.0, 1, 6, 100, None, None);
+        let json = search_batch(1_000_000);
+        assert!(json.ends_with(concat!(
+            r#""top_usage":[{"index":1,"count":2,"value":1},{"index":2,"count":2,"value":2},"#,
+            r#"{"index":3,"count":1,"value":3},{"index":4,"count":1,"value":4},{"index":5,"count":1,"value":5},"#,
+            r#"{"index":6,"count":1,"value":6}]}"#,
+        )), "{}", json);
+        assert!(get_usage_histogram().starts_with(r#"[{"index":1,"count":2,"value":1},"#));
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_entry_points_validate_scale() {
+        let numbers = [1.0, 2.0];
+        let expected = stamped(r#"{"status":"error","code":"scale_out_of_range","field":"scale","max":9,"actual":10}"#);
+        assert_eq!(find_one(&numbers, 3.0, 1, 2, None, None, Some(10)), expected);
+        assert_eq!(find_matching_for_rows(&numbers, &[0], 1, 2, Some(10)), expected);
+        assert_eq!(find_random(&numbers, 3.0, 1, 2, 0, Some(10)), expected);
+        assert_eq!(get_effective_entries(&numbers, 3.0, 10), expected);
+        assert_eq!(init_batch_search(&numbers, 3.0, 1, 2, 10, None, Some(10)).as_deref(), Some(expected.as_str()));
+        assert_eq!(init_batch_count(&numbers, 3.0, 1, 2, Some(10)).as_deref(), Some(expected.as_str()));
+        assert_eq!(create_dataset(&numbers, 10), 0);
+        assert_eq!(init_batch_count(&numbers, 3.0, 1, 2, Some(9)), None);
+        destroy_batch_count();
+    }
+
+    #[test]
+    fn test_batch_entry_points_validate_max_results() {
+        let numbers = [1.0, 2.0];
+        destroy_batch_search();
+        assert_eq!(
+            init_batch_search(&numbers, 3.0, 1, 2, validate::MAX_RESULTS_CAP + 1, None, None),
+            Some(stamped(r#"{"status":"error","code":"max_results_out_of_range","field":"max_results","max":10000000,"actual":10000001}"#)),
+        );
+        // Rejected calls leave no search behind
+        assert_eq!(search_batch(10), stamped(r#"{"error":"no search initialized"}"#));
+
+        let handle = create_dataset(&numbers, 0);
+        assert!(init_batch_search_on(handle, 3.0, 1, 2, validate::MAX_RESULTS_CAP + 1).is_some());
+        assert_eq!(init_batch_search_on(handle, 3.0, 1, 2, validate::MAX_RESULTS_CAP), None);
+        destroy_dataset(handle);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_batch_sparse_tail_proof() {
+        destroy_batch_search();
+        assert!(!set_batch_sparse_tail(10));
+
+        let mut numbers = vec![1.0];
+        numbers.extend((1..=60).map(|v| (v * 2) as f64));
+        init_batch_search(&numbers, 121.0, 1, 3, 1000, None, None);
+        assert!(set_batch_sparse_tail(100));
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""termination_reason":"proved_exhausted""#), "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_destroy_batch_search_mid_search() {
+        let numbers: Vec<f64> = (1..=20).map(|v| v as f64).collect();
+        init_batch_search(&numbers, 30.0, 2, 5, 1000, None, None);
+        let step = search_batch(137);
+        let found = step.matches(r#""id":"#).count();
+        let json = destroy_batch_search();
+        assert!(json.starts_with(&format!(r#"{{"total_found":{},"nodes_explored":137,"finished":false,"#, found)), "{}", json);
+        // Everything found was already returned by search_batch
+        assert!(json.ends_with(r#""undelivered_results":[]}"#), "{}", json);
+        assert_eq!(search_batch(10), stamped(r#"{"error":"no search initialized"}"#));
+        assert_eq!(destroy_batch_search(), stamped(r#"{"error":"no search initialized"}"#));
+    }
+
+    #[test]
+    fn test_destroy_batch_search_after_finish() {
+        init_batch_search(&[1.0, 2.0, 3.0], 3.0, 1, 3, 1000, None, None);
+        assert!(search_batch(1_000_000).contains(r#""finished":true"#));
+        assert_eq!(
+            destroy_batch_search(),
+            r#"{"total_found":2,"nodes_explored":5,"finished":true,"termination_reason":"exhausted","undelivered_results":[]}"#,
+        );
+        init_batch_search(&[1.0, 2.0, 3.0], 3.0, 1, 3, 1000, None, None);
+        destroy_batch_search_quiet();
+        assert_eq!(export_batch_state(), None);
+    }
+
+    #[test]
+    fn test_destroy_batch_search_returns_undelivered() {
+        // Results restored by an import were never returned to this caller
+        let numbers: Vec<f64> = vec![1.0; 12];
+        init_batch_search(&numbers, 2.0, 2, 2, 10_000, None, None);
+        search_batch(1_000_000);
+        let bytes = export_batch_state().unwrap();
+        import_batch_state(&bytes, None);
+        let json = destroy_batch_search();
+        // C(12, 2) = 66 pairs, all under the page size
+        assert_eq!(json.matches(r#""id":"#).count(), 66, "{}", json);
+        assert!(!json.contains("undelivered_omitted"), "{}", json);
+
+        let numbers: Vec<f64> = vec![1.0; 50];
+        init_batch_search(&numbers, 2.0, 2, 2, 10_000, None, None);
+        search_batch(1_000_000);
+        import_batch_state(&export_batch_state().unwrap(), None);
+        // C(50, 2) = 1225 pairs: one page, the rest counted
+        let json = destroy_batch_search();
+        assert_eq!(json.matches(r#""id":"#).count(), UNDELIVERED_PAGE, "{}", json);
+        assert!(json.ends_with(r#""undelivered_omitted":225}"#), "{}", json);
+    }
+
+    #[test]
+    fn test_single_search_diagnostics() {
+        // All even, odd target: nothing matches, so the search keeps running
+        let numbers: Vec<f64> = (1..=40).map(|v| (v * 2) as f64).collect();
+        init_single_search(&numbers, 301.0, 1, 40, None, None);
+        let json = step_single_search(500);
+        assert!(json.starts_with(r#"{"status":"running""#), "{}", json);
+        let step = canonical::parse(&json).unwrap();
+        let diagnostics = step.get("diagnostics").expect(&json);
+        assert_eq!(diagnostics.get("unstable").and_then(|v| v.as_bool()), Some(true));
+        let Some(canonical::Value::Array(path)) = diagnostics.get("path") else { panic!("{}", json) };
+        let sum: u64 = path.iter().map(|i| (i.as_u64().unwrap() + 1) * 2).sum();
+        assert!(!path.is_empty());
+        assert_eq!(diagnostics.get("path_len").and_then(|v| v.as_u64()), Some(path.len() as u64));
+        assert_eq!(diagnostics.get("partial_sum").and_then(|v| v.as_u64()), Some(sum));
+
+        init_single_search(&numbers, 301.0, 1, 40, None, Some(false));
+        let json = step_single_search(500);
+        assert!(json.starts_with(r#"{"status":"running""#), "{}", json);
+        assert!(!json.contains("diagnostics"), "{}", json);
+        destroy_single_search();
+        assert_eq!(step_single_search(10), stamped(r#"{"error":"no search initialized"}"#));
+    }
+
+    #[test]
+    fn test_search_stages() {
+        // The stage each payload ends in, after those it went through
+        let stages_of = |json: &str| -> Vec<String> {
+            let list = match json.split_once(r#""stages":["#) {
+                Some((_, rest)) => &rest[..rest.find(']').unwrap()],
+                None => {
+                    let rest = json.split_once(r#""stage":"#).unwrap().1;
+                    &rest[..rest.find([',', '}']).unwrap()]
+                }
+            };
+            list.split(',').map(|stage| stage.trim_matches('"').to_string()).collect()
+        };
+        let numbers: Vec<f64> = (1..=30).map(f64::from).collect();
+        let instance = SolverInstance::new();
+        let options = r#"{"target":30,"init_budget_ms":0,"precheck":true,"algorithm":"mitm"}"#;
+        assert_eq!(instance.init_batch(&numbers, options), None);
+        let mut seen: Vec<String> = Vec::new();
+        let mut building_shares = Vec::new();
+        loop {
+            let json = instance.search_batch(4_000);
+            for stage in stages_of(&json) {
+                if seen.last() != Some(&stage) {
+                    seen.push(stage);
+                }
+            }
+            if let Some((_, rest)) = json.split_once(r#""stage_progress":"#) {
+                building_shares.push(rest[..rest.find([',', '}']).unwrap()].parse::<f64>().unwrap());
+            }
+            if json.contains(r#""finished":true"#) {
+                break;
+            }
+        }
+        assert_eq!(seen, ["preparing", "prechecking", "building_tables", "searching", "finalizing"]);
+        assert!(building_shares.len() >= 2 && building_shares.windows(2).all(|w| w[0] < w[1]), "{:?}", building_shares);
+        assert!(building_shares.iter().all(|&share| share > 0.0 && share < 1.0), "{:?}", building_shares);
+
+        // The resumable find-one searches, then ends in finalizing
+        init_single_search(&numbers, 30.0, 5, 5, None, Some(false));
+        let running = step_single_search(1);
+        assert!(running.ends_with(r#""stage":"searching"}"#), "{}", running);
+        let found = step_single_search(1_000_000);
+        assert!(found.starts_with(r#"{"status":"found""#) && found.ends_with(r#""stage":"finalizing"}"#), "{}", found);
+        destroy_single_search();
+    }
+
+    #[test]
+    fn test_single_search_matches_find_one() {
+        let numbers = [4.0, 1.0, 3.0, 2.0, 5.0];
+        init_single_search(&numbers, 5.0, 1, 5, None, None);
+        let mut json = step_single_search(1);
+        while json.starts_with(r#"{"status":"running""#) {
+            json = step_single_search(1);
+        }
+        assert!(json.starts_with(r#"{"status":"found""#), "{}", json);
+        assert!(!json.contains("diagnostics"), "{}", json);
+        // Stays found on further steps
+        assert_eq!(step_single_search(1), json);
+        init_single_search(&numbers, 100.0, 1, 5, None, None);
+        assert!(step_single_search(1_000_000).starts_with(r#"{"status":"not_found""#));
+        destroy_single_search();
+    }
+
+    #[test]
+    fn test_consistent_find_one_matches_batch_first_result() {
+        let mut rng = testing::rng();
+        let mut diverged = 0;
+        for _ in 0..100 {
+            // Small enough that plain find_one uses MITM
+            let n = 2 + rng.below(23) as usize;
+            let numbers: Vec<f64> = (0..n).map(|_| (1 + rng.below(60)) as f64).collect();
+            let target = 1 + rng.below(numbers.iter().sum::<f64>() as u64);
+            let options = format!(r#"{{"target":{},"max_results":1,"consistent_with_find_one":true}}"#, target);
+
+            let found = canonical::parse(&find_one_v2(&numbers, &options)).unwrap();
+            init_batch_search_v2(&numbers, &options);
+            let step = canonical::parse(&search_batch(u32::MAX)).unwrap();
+            let first = match step.get("new_results") {
+                Some(canonical::Value::Array(results)) => results.first().and_then(|r| r.get("indices")),
+                _ => panic!("no new_results"),
+            };
+            assert_eq!(found.get("indices"), first, "{:?} target {}", numbers, target);
+
+            let plain = canonical::parse(&find_one(&numbers, target as f64, 1, n as u32, None, None, None)).unwrap();
+            if plain.get("indices") != first {
+                diverged += 1;
+            }
+        }
+        destroy_batch_search_quiet();
+        // Without the option MITM does pick other solutions, which is the point
+        assert!(diverged > 0);
+    }
+
+    #[test]
+    fn test_v2_row_ids() {
+        // The CSV merge listed row 0 twice; 5 + 5 would use it twice
+        let numbers = [5.0, 5.0, 3.0, 2.0];
+        let options = r#"{"target":10,"row_ids":[0,0,1,2]}"#;
+        assert!(find_one_v2(&numbers, options).contains(r#""indices":[0,2,3]"#));
+
+        init_batch_search_v2(&numbers, options);
+        let bytes = export_batch_state().unwrap();
+        import_batch_state(&bytes, None);
+        let json = search_batch(1000);
+        // [0,2,3] and [1,2,3], but never [0,1]
+        assert_eq!(json.matches(r#""id":"#).count(), 2, "{}", json);
+        assert!(!json.contains(r#""indices":[0,1]"#), "{}", json);
+        destroy_batch_search_quiet();
+
+        let json = find_one_v2(&numbers, r#"{"target":10,"row_ids":[0,1]}"#);
+        assert!(json.contains(r#""code":"length_mismatch""#), "{}", json);
+        assert!(init_batch_search_v2(&numbers, r#"{"target":10,"row_ids":[0]}"#).unwrap().contains("length_mismatch"));
+    }
+
+    #[test]
+    fn test_v2_count_windows() {
+        // 10 = 4+6 = 1+3+6 = 1+4+5 = 2+3+5 = 1+2+3+4
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let options = r#"{"target":10,"count_windows":[[2,2],[3,3,1],[4,6]]}"#;
+        assert_eq!(init_batch_search_v2(&numbers, options), None);
+        let bytes = export_batch_state().unwrap();
+        import_batch_state(&bytes, None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":3,"#), "{}", json);
+        assert!(json.contains(r#""window_matches":[[2],[1],[0]],"per_window_found":[1,1,1]"#), "{}", json);
+        destroy_batch_search_quiet();
+
+        let json = init_batch_search_v2(&numbers, r#"{"target":10,"count_windows":[[3,2]]}"#).unwrap();
+        assert!(json.contains(r#""code":"invalid_option""#), "{}", json);
+    }
+
+    #[test]
+    fn test_v2_init_budget_defers_prework() {
+        // 10 = 4+6 = 1+3+6 = 1+4+5 = 2+3+5 = 1+2+3+4
+        let numbers = [6.0, 2.0, 5.0, 1.0, 4.0, 3.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"init_budget_ms":0}"#), None);
+        let json = search_batch(1);
+        assert!(json.contains(r#""prework_pending":true,"prework":{"deferred":["sort","suffix_sums","count_window"],"#), "{}", json);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":5,"#) && !json.contains("prework"), "{}", json);
+        destroy_batch_search_quiet();
+
+        // Export finishes the prework so the state decodes
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"init_budget_ms":0}"#), None);
+        let bytes = export_batch_state().unwrap();
+        assert_eq!(import_batch_state(&bytes, None), r#"{"status":"ok","total_found":0}"#);
+        assert!(search_batch(1_000_000).contains(r#""total_found":5,"#));
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_quick_phase() {
+        let numbers = [6.0, 2.0, 5.0, 1.0, 4.0, 3.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"quick_phase":true}"#), None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":1,"#), "{}", json);
+        assert!(json.contains(r#""phase":"quick","next_phase":"exhaustive""#), "{}", json);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":5,"#) && json.contains(r#""phase":"exhaustive""#), "{}", json);
+        assert_eq!(json.matches(r#""id":"#).count(), 4, "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_min_cost() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 6.0];
+        let json = find_one_v2(&numbers, r#"{"target":10,"consistent_with_find_one":true}"#);
+        assert!(json.contains(r#""indices":[0,1,2,3],"#), "{}", json);
+        let options = r#"{"target":10,"objective":"min_cost","costs":[5,5,5,5,1.5]}"#;
+        let json = find_one_v2(&numbers, options);
+        assert!(json.contains(r#""indices":[3,4],"#) && json.contains(r#""total_cost":6.5,"optimal":true,"#), "{}", json);
+        assert!(json.contains(r#""algorithm":"mitm_min_cost""#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":10,"objective":"min_cost","costs":[1]}"#);
+        assert!(json.starts_with(r#"{"status":"error","code":"length_mismatch","field":"costs""#), "{}", json);
+
+        // Past MITM's size a node budget can stop the search before it proves anything
+        let numbers: Vec<f64> = (1..=60).map(f64::from).collect();
+        let costs: Vec<String> = (1..=60).map(|v| (100 - v).to_string()).collect();
+        let options = format!(r#"{{"target":200,"objective":"min_cost","costs":[{}],"cost_nodes":50}}"#, costs.join(","));
+        let json = find_one_v2(&numbers, &options);
+        assert!(json.contains(r#""optimal":false"#), "{}", json);
+        assert!(json.contains(r#""algorithm":"bnb_min_cost","budget":50,"nodes":50"#), "{}", json);
+    }
+
+    #[test]
+    fn test_v2_prefer_small_count() {
+        let numbers = [6.0, 2.0, 5.0, 1.0, 4.0, 3.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"prefer_small_count":true}"#), None);
+        let json = search_batch(1);
+        // The feasible window starts at two numbers, and all pairs take one scan
+        assert!(json.starts_with(r#"{"new_results":[{"id":"0c35039535423be1","indices":[0,4],"#), "{}", json);
+        assert!(json.contains(r#""results_rate":1000000.000,"first_seq":0,"last_seq":0,"stage":"searching","current_count":2"#), "{}", json);
+        let mut json = search_batch(1_000_000);
+        while !json.contains(r#""finished":true"#) {
+            json = search_batch(1_000_000);
+        }
+        assert!(json.contains(r#""total_found":5,"#) && !json.contains("current_count"), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_memory_limit() {
+        let numbers: Vec<f64> = (1..=45).map(|i| (i * 7 % 101) as f64).collect();
+        // Forced MITM over 45 entries wants 2^22 subsets at 48 bytes: an error
+        let json = find_one_v2(&numbers, r#"{"target":200,"algorithm":"mitm","memory_limit_mb":1,"tag":"t"}"#);
+        assert!(json.starts_with(r#"{"tag":"t","status":"error","code":"out_of_memory","bytes":201326592,"#), "{}", json);
+        // Under auto, 30 entries fall back to B&B
+        let json = find_one_v2(&numbers[..30], r#"{"target":150,"memory_limit_mb":1}"#);
+        assert!(json.starts_with(r#"{"status":"found""#), "{}", json);
+        assert!(json.contains(r#""algorithm":"bnb""#) && json.contains(r#""memory_fallback":1572864"#), "{}", json);
+        let json = find_one_v2(&numbers[..30], r#"{"target":150,"memory_limit_mb":2}"#);
+        assert!(json.contains(r#""algorithm":"mitm""#) && !json.contains("memory_fallback"), "{}", json);
+
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":150,"memory_limit_mb":1,"precheck":false}"#), None);
+        let mut json = search_batch(1_000_000);
+        while !json.contains(r#""finished":true"#) {
+            json = search_batch(1_000_000);
+        }
+        assert!(json.contains(r#""termination_reason":"out_of_memory""#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_batch_mitm() {
+        let numbers: Vec<f64> = (1..=24).map(|i| (i * 7 % 31) as f64).collect();
+        let run = |options: &str| {
+            assert_eq!(init_batch_search_v2(&numbers, options), None);
+            let mut json = search_batch(5000);
+            while !json.contains(r#""finished":true"#) {
+                json = search_batch(5000);
+            }
+            json
+        };
+        let json = run(r#"{"target":60,"max_results":100000}"#);
+        let dfs_total = json.split(r#""total_found":"#).nth(1).unwrap().split(',').next().unwrap().to_string();
+        destroy_batch_search_quiet();
+        let json = run(r#"{"target":60,"max_results":100000,"algorithm":"mitm"}"#);
+        assert!(json.contains(&format!(r#""total_found":{},"#, dfs_total)), "{} vs {}", json, dfs_total);
+        assert!(get_batch_info().contains(r#""strategy":"mitm""#), "{}", get_batch_info());
+        assert!(export_batch_state().is_none());
+        destroy_batch_search_quiet();
+
+        let many: Vec<f64> = (1..=41).map(f64::from).collect();
+        let json = init_batch_search_v2(&many, r#"{"target":60,"algorithm":"mitm"}"#).unwrap();
+        assert!(json.contains(r#""code":"algorithm_not_applicable","algorithm":"mitm","n":41,"max_n":40"#), "{}", json);
+        let json = init_batch_search_v2(&numbers, r#"{"target":60,"algorithm":"mitm","prefer_small_count":true}"#).unwrap();
+        assert!(json.contains(r#""conflicts_with":"prefer_small_count""#), "{}", json);
+        let json = init_batch_search_v2(&numbers, r#"{"target":60,"algorithm":"mitm","max_index_span":3}"#).unwrap();
+        assert!(json.contains(r#""conflicts_with":"max_index_span""#), "{}", json);
+    }
+
+    #[test]
+    fn test_v2_max_index_span() {
+        // 10 + 20 sit 9 rows apart; 12 + 18 are neighbours
+        let numbers = [10.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 20.0, 12.0, 18.0];
+        let json = find_one_v2(&numbers, r#"{"target":30}"#);
+        assert!(json.contains(r#""indices":[0,9]"#), "{}", json);
+        for algorithm in ["auto", "mitm", "bnb"] {
+            let json = find_one_v2(&numbers, &format!(r#"{{"target":30,"max_index_span":5,"algorithm":"{}"}}"#, algorithm));
+            assert!(json.contains(r#""indices":[10,11]"#), "{}: {}", algorithm, json);
+        }
+        let json = find_one_v2(&numbers, r#"{"target":30,"max_index_span":0}"#);
+        assert!(json.starts_with(r#"{"status":"not_found""#) && !json.contains("explain"), "{}", json);
+
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":30,"max_index_span":5}"#), None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":1"#) && json.contains(r#""indices":[10,11]"#), "{}", json);
+        destroy_batch_search_quiet();
+        // Kept across export and import: without it 10 + 20 would be found too
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":30,"max_index_span":5}"#), None);
+        let bytes = export_batch_state().unwrap();
+        destroy_batch_search_quiet();
+        import_batch_state(&bytes, None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":1"#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_init_replaces_or_refuses_active_search() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":5,"tag":"first"}"#), None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":3"#) && json.contains(r#""finished":true"#), "{}", json);
+
+        // Refused: the first search stays, results and all
+        let json = init_batch_search_v2(&numbers, r#"{"target":3,"tag":"second","refuse_if_active":true}"#).unwrap();
+        assert!(json.starts_with(r#"{"tag":"second","status":"error","code":"search_active","active":{"tag":"first","total_found":3,"#), "{}", json);
+        let json = get_results(None);
+        assert!(json.starts_with(r#"{"tag":"first","order_by":"discovery","total":3,"#), "{}", json);
+
+        // Replaced: the answer describes the search that was dropped
+        let json = init_batch_search(&numbers, 3.0, 1, 3, 10, None, None).unwrap();
+        assert!(json.starts_with(r#"{"status":"ok","replaced_active":true,"replaced":{"tag":"first","total_found":3,"#), "{}", json);
+        assert!(json.ends_with(r#""finished":true,"undelivered":0}}"#), "{}", json);
+
+        // The handle-based init reports the same way
+        let handle = create_dataset(&numbers, 0);
+        let json = init_batch_search_on(handle, 4.0, 1, 3, 10).unwrap();
+        assert_eq!(json, r#"{"status":"ok","replaced_active":true,"replaced":{"total_found":0,"nodes_explored":0,"finished":false,"undelivered":0}}"#);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":2"#), "{}", json);
+
+        destroy_batch_search_quiet();
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":5,"refuse_if_active":true}"#), None);
+        destroy_batch_search_quiet();
+        destroy_dataset(handle);
+    }
+
+    #[test]
+    fn test_get_search_tree() {
+        destroy_batch_search_quiet();
+        assert!(!set_batch_search_tree(100));
+        assert_eq!(get_search_tree(), stamped(r#"{"error":"no search initialized"}"#));
+
+        assert_eq!(init_batch_search(&[4.0, 1.0, 9.0, 3.0, 2.0], 6.0, 1, 4, 100, None, None), None);
+        assert!(get_search_tree().starts_with(r#"{"status":"error","code":"search_tree_off""#));
+        assert!(set_batch_search_tree(100));
+        search_batch(1000);
+        assert!(!set_batch_search_tree(100));
+        // 9 is over the target, and the count window tightens to [2, 3]: after a
+        // first row two picks are left, so one scan finishes its frame
+        assert_eq!(get_search_tree(), concat!(
+            r#"{"target":6,"max_nodes":100,"truncated":false,"nodes":["#,
+            r#"{"id":0,"parent":null,"depth":0,"path_sum":0,"decision":"root"},"#,
+            r#"{"id":1,"parent":0,"depth":1,"path_sum":0,"index":0,"row":1,"value":1,"decision":"branch"},"#,
+            r#"{"id":2,"parent":1,"depth":2,"path_sum":1,"decision":"scan","steps":3,"found":1},"#,
+            r#"{"id":3,"parent":0,"depth":1,"path_sum":0,"index":1,"row":4,"value":2,"decision":"branch"},"#,
+            r#"{"id":4,"parent":3,"depth":2,"path_sum":2,"decision":"scan","steps":2,"found":1},"#,
+            r#"{"id":5,"parent":0,"depth":1,"path_sum":0,"index":2,"row":3,"value":3,"decision":"branch"},"#,
+            r#"{"id":6,"parent":5,"depth":2,"path_sum":3,"decision":"scan","steps":0,"found":0},"#,
+            r#"{"id":7,"parent":0,"depth":1,"path_sum":0,"index":3,"row":0,"value":4,"decision":"prune","#,
+            r#""reason":"suffix_short","suffix_sum":4,"remaining":6}]}"#,
+        ));
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_get_batch_info() {
+        destroy_batch_search_quiet();
+        assert_eq!(get_batch_info(), stamped(r#"{"error":"no search initialized"}"#));
+
+        let numbers = [5.0, -1.0, f64::NAN, 0.4, 12.0, 3.0, 0.0, 11.0, 2.0];
+        assert_eq!(init_batch_search(&numbers, 10.0, 1, 5, 100, None, None), None);
+        assert_eq!(get_batch_info(), concat!(
+            r#"{"usable_count":3,"skipped_count":6,"#,
+            r#""skipped_by_reason":{"not_finite":1,"non_positive":2,"zero_after_scaling":1,"exceeds_target":2},"#,
+            r#""total_value":10,"min_value":2,"max_value":5,"strategy":"dfs","precheck":false,"count_window":[3,3],"finished":false}"#,
+        ));
+        // The same counts once exported and imported
+        let info = get_batch_info();
+        let bytes = export_batch_state().unwrap();
+        destroy_batch_search_quiet();
+        assert!(import_batch_state(&bytes, None).starts_with(r#"{"status":"ok","#));
+        assert_eq!(get_batch_info(), info);
+        let options = r#"{"target":10,"include_indices":[0,4,20],"quick_phase":true,"tag":"q"}"#;
+        assert!(init_batch_search_v2(&numbers, options).is_some());
+        assert_eq!(get_batch_info(), concat!(
+            r#"{"tag":"q","usable_count":1,"skipped_count":2,"skipped_by_reason":{"exceeds_target":1,"out_of_range":1},"#,
+            r#""total_value":5,"min_value":5,"max_value":5,"strategy":"quick_then_dfs","precheck":true,"count_window":null,"#,
+            r#""finished":true,"termination_reason":"exhausted"}"#,
+        ));
+
+        // Finished at init: the usable rows fall short of the target, or there are none
+        assert!(init_batch_search(&numbers, 100.0, 1, 5, 100, None, None).is_some());
+        let json = get_batch_info();
+        assert!(json.starts_with(r#"{"usable_count":5,"skipped_count":4,"#), "{}", json);
+        assert!(json.ends_with(r#""total_value":33,"min_value":2,"max_value":12,"strategy":"dfs","precheck":false,"count_window":null,"finished":true,"termination_reason":"exhausted"}"#), "{}", json);
+        assert!(init_batch_search(&[], 10.0, 1, 5, 100, None, None).is_some());
+        assert_eq!(get_batch_info(), concat!(
+            r#"{"usable_count":0,"skipped_count":0,"skipped_by_reason":{},"total_value":0,"min_value":null,"max_value":null,"#,
+            r#""strategy":"dfs","precheck":false,"count_window":null,"finished":true,"termination_reason":"exhausted"}"#,
+        ));
+
+        // The same numbers by handle
+        let handle = create_dataset(&numbers, 0);
+        assert!(init_batch_search_on(handle, 10.0, 1, 5, 100).is_some());
+        assert!(get_batch_info().starts_with(r#"{"usable_count":3,"skipped_count":6,"#));
+        destroy_dataset(handle);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_solve_multiple_targets() {
+        // Alone, 5 would take the first rows summing to it, 1 and 4, leaving 10
+        // nothing; solved together, 5 takes 2 and 3 and 10 takes 4 and 6
+        let json = solve_multiple_targets(&[1.0, 2.0, 3.0, 4.0, 6.0], &[5.0, 10.0], 1, 5, None);
+        assert!(json.starts_with(r#"{"status":"solved","targets":[{"target":5,"#), "{}", json);
+        assert!(json.contains(r#""indices":[1,2],"values":[2,3]"#), "{}", json);
+        assert!(json.contains(r#"{"target":10,"#) && json.contains(r#""indices":[3,4],"values":[4,6]"#), "{}", json);
+        assert!(json.contains(r#""order":[0,1],"stats":{"nodes_explored":"#), "{}", json);
+
+        let json = solve_multiple_targets(&[0.7, 0.1, 0.2], &[0.7, 9.0, 0.8], 1, 3, Some(1));
+        assert!(json.starts_with(r#"{"status":"partial","targets":[{"target":7,"#), "{}", json);
+        assert!(json.contains(r#"{"target":90,"unsatisfied":"no_combination"}"#), "{}", json);
+        assert!(json.contains(r#"{"target":8,"unsatisfied":"conflict"}"#), "{}", json);
+
+        assert!(solve_multiple_targets(&[1.0], &[1.0], 2, 1, None).contains(r#""code":"invalid_bounds""#));
+        let json = solve_multiple_targets(&[1.0], &[1.0; 65], 1, 1, None);
+        assert!(json.contains(r#""field":"targets""#), "{}", json);
+    }
+
+    #[test]
+    fn test_find_matching_pair() {
+        // Debits and credits in cents: 1.00 + 2.10 offsets 0.55 + 2.55, and
+        // nothing else does; the -4.00 and NaN rows are skipped
+        let debits = [1.0, 2.1, 3.7, -4.0];
+        let credits = [0.55, 1.25, 2.55, f64::NAN, 0.05];
+        let json = find_matching_pair(&debits, &credits, 1, 4, 1, 4, None, None, Some(2));
+        assert_eq!(json, concat!(
+            r#"{"status":"found","sum":310,"#,
+            r#""a":{"id":"692558b056101a44","indices":[0,1],"values":[100,210],"selection_order":[0,1],"count":2},"#,
+            r#""b":{"id":"c615adcb76ddf8a7","indices":[0,2],"values":[55,255],"selection_order":[0,1],"count":2},"#,
+            r#""stats":{"enumerated":"a","sums":4,"probes":3,"nodes_explored":12}}"#,
+        ));
+
+        // A hint away from 3.10 finds nothing within its tolerance
+        let json = find_matching_pair(&debits, &credits, 1, 4, 1, 4, Some(4.0), Some(0.5), Some(2));
+        assert!(json.starts_with(r#"{"status":"not_found","stats":{"enumerated":"a","#), "{}", json);
+        assert!(find_matching_pair(&debits, &credits, 1, 4, 1, 4, Some(3.0), Some(0.1), Some(2)).contains(r#""sum":310"#));
+
+        let json = find_matching_pair(&debits, &credits, 3, 2, 1, 4, None, None, None);
+        assert!(json.contains(r#""code":"invalid_bounds""#), "{}", json);
+    }
+
+    #[test]
+    fn test_analyze_target() {
+        // Two rows for 130: only 90 and 40 together get there
+        let json = analyze_target(&[90.0, 20.0, 40.0, 10.0, -5.0, 500.0], 130.0, 1, 2, None);
+        assert_eq!(json, concat!(
+            r#"{"usable_count":4,"total_value":160,"target":130,"target_fraction":0.812500,"count_window":[2,2],"#,
+            r#""mandatory":[0,2],"irrelevant":[1,3]}"#,
+        ));
+        let json = analyze_target(&[1.5], 10.0, 1, 3, Some(2));
+        assert_eq!(json, concat!(
+            r#"{"usable_count":1,"total_value":150,"target":1000,"target_fraction":6.666667,"count_window":null,"#,
+            r#""mandatory":[],"irrelevant":[]}"#,
+        ));
+        assert!(analyze_target(&[], 10.0, 1, 3, None).contains(r#""target_fraction":null"#));
+    }
+
+    #[test]
+    fn test_collapse_duplicates() {
+        // 60 rows of three amounts: collapsed by default
+        let numbers: Vec<f64> = (0..60).map(|i| [25.0, 40.0, 10.0][i % 3]).collect();
+        let json = find_one_v2(&numbers, r#"{"target":100,"max_count":4}"#);
+        // Two 10s and two 40s, from the first rows holding them
+        assert!(json.contains(r#""indices":[1,2,4,5],"values":[40,10,40,10]"#), "{}", json);
+        assert!(json.contains(r#""phases":[{"algorithm":"collapsed","budget":null,"nodes":"#), "{}", json);
+        assert!(json.contains(r#""collapsed_to":3"#), "{}", json);
+
+        let off = find_one_v2(&numbers, r#"{"target":100,"max_count":4,"collapse_duplicates":false}"#);
+        assert!(off.starts_with(r#"{"status":"found""#) && !off.contains("collapsed"), "{}", off);
+        // Forced on a small input, off whenever the rows themselves matter
+        let small = find_one_v2(&numbers[..6], r#"{"target":100,"collapse_duplicates":true}"#);
+        assert!(small.contains(r#""collapsed_to":3"#), "{}", small);
+        let spanned = find_one_v2(&numbers, r#"{"target":100,"max_index_span":10}"#);
+        assert!(spanned.starts_with(r#"{"status":"found""#) && !spanned.contains("collapsed"), "{}", spanned);
+    }
+
+    #[test]
+    fn test_previously_matched() {
+        // Rows 0 and 1 went in an earlier run; by value, each 3 and 7 takes the first free copy
+        let numbers = [3.0, 7.0, 3.0, 7.0, 10.0, 12.0];
+        let by_index = find_one_v2(&numbers, r#"{"target":10,"max_count":2,"previously_matched":[0,1,4,5]}"#);
+        let by_value = find_one_v2(&numbers, r#"{"target":10,"max_count":2,"previously_matched_values":[7,10,3,10,12]}"#);
+        assert!(by_index.contains(r#""indices":[2,3]"#), "{}", by_index);
+        // Row 5 exceeds the target anyway, so only the usable rows are listed
+        assert!(by_index.ends_with(concat!(
+            r#""skipped":[{"index":0,"reason":"previously_matched"},{"index":1,"reason":"previously_matched"},"#,
+            r#"{"index":4,"reason":"previously_matched"}]}"#,
+        )), "{}", by_index);
+        assert_eq!(by_value, by_index);
+
+        let options = r#"{"target":10,"max_count":2,"previously_matched":[4],"previously_matched_values":[3]}"#;
+        assert_eq!(init_batch_search_v2(&numbers, options), None);
+        assert!(get_batch_info().starts_with(concat!(
+            r#"{"usable_count":3,"skipped_count":3,"#,
+            r#""skipped_by_reason":{"exceeds_target":1,"previously_matched":2},"#,
+        )), "{}", get_batch_info());
+        let json = search_batch(1000);
+        assert!(json.contains(r#""indices":[1,2]"#) && json.contains(r#""indices":[2,3]"#), "{}", json);
+        assert!(json.contains(r#""total_found":2,"#), "{}", json);
+        assert!(json.contains(r#""skipped":[{"index":0,"reason":"previously_matched"},{"index":4,"reason":"previously_matched"}]"#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_csv_column_searches() {
+        // Amounts in column 2: 1,234.50 | 12 | 99.99 | 1.234,50 | n/a | 0,50 | (missing) | 250
+        let text = include_str!("../tests/fixtures/statement.csv");
+        let json = find_one_from_csv_column(text, 2, ',', true, r#"{"target":112.49,"scale":2}"#);
+        assert!(json.contains(r#""indices":[1,2,5],"values":[1200,9999,50]"#), "{}", json);
+        assert!(json.ends_with(r#""skipped":[{"index":4,"reason":"not_a_number"},{"index":6,"reason":"missing_column"}]}"#), "{}", json);
+        // Indices are data rows, as the same column passed as numbers gives them
+        let numbers = csv::read_column(text, 2, ',', true).unwrap().numbers;
+        assert!(find_one_v2(&numbers, r#"{"target":112.49,"scale":2}"#).contains(r#""indices":[1,2,5]"#));
+        // Unreadable rows left out by include_indices are not reported
+        let json = find_one_from_csv_column(text, 2, ',', true, r#"{"target":112.49,"scale":2,"include_indices":[1,2,5,6]}"#);
+        assert!(json.ends_with(r#""skipped":[{"index":6,"reason":"missing_column"}]}"#), "{}", json);
+
+        assert_eq!(init_batch_search_from_csv_column(text, 2, ',', true, r#"{"target":1246.5,"scale":2}"#), None);
+        assert!(get_batch_info().starts_with(concat!(
+            r#"{"usable_count":6,"skipped_count":2,"#,
+            r#""skipped_by_reason":{"not_a_number":1,"missing_column":1},"#,
+        )), "{}", get_batch_info());
+        let json = search_batch(1000);
+        assert!(json.contains(r#""indices":[0,1]"#) && json.contains(r#""indices":[1,3]"#), "{}", json);
+        assert!(json.contains(r#""skipped":[{"index":4,"reason":"not_a_number"},{"index":6,"reason":"missing_column"}]"#), "{}", json);
+        destroy_batch_search_quiet();
+
+        let json = find_one_from_csv_column(text, 2, '"', true, r#"{"target":1,"tag":"t"}"#);
+        assert!(json.starts_with(r#"{"tag":"t","status":"error","code":"invalid_delimiter","delimiter":"\""#), "{}", json);
+        let json = init_batch_search_from_csv_column(text, 2, '\n', true, r#"{"target":1}"#).unwrap();
+        assert!(json.contains(r#""code":"invalid_delimiter""#), "{}", json);
+    }
+
+    #[test]
+    fn test_solver_instances_are_isolated() {
+        let numbers: Vec<f64> = (1..=14).map(f64::from).collect();
+        let total = |json: &str| json.split(r#""total_found":"#).nth(1).unwrap().split(',').next().unwrap().parse::<usize>().unwrap();
+        // Each search alone, with the free functions
+        let alone: Vec<usize> = [r#"{"target":20}"#, r#"{"target":33,"max_count":4}"#].iter().map(|options| {
+            assert_eq!(init_batch_search_v2(&numbers, options), None);
+            let found = total(&search_batch(u32::MAX));
+            destroy_batch_search_quiet();
+            found
+        }).collect();
+
+        // A default search in progress, then two instances interleaved around it
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":7,"tag":"default"}"#), None);
+        let (a, b) = (SolverInstance::new(), SolverInstance::new());
+        assert_eq!(a.init_batch(&numbers, r#"{"target":20}"#), None);
+        assert_eq!(b.init_batch(&numbers, r#"{"target":33,"max_count":4}"#), None);
+        let (mut a_json, mut b_json) = (String::new(), String::new());
+        while !a_json.contains(r#""finished":true"#) || !b_json.contains(r#""finished":true"#) {
+            a_json = a.search_batch(50);
+            // A cancel aimed at b's last call reaches neither a nor the default instance
+            b.cancel();
+            b_json = b.search_batch(50);
+            assert!(!a_json.contains(r#""tag""#) && !b_json.contains(r#""tag""#));
+        }
+        assert_eq!(vec![total(&a_json), total(&b_json)], alone);
+        assert!(a.destroy().contains(&format!(r#""total_found":{},"#, alone[0])));
+        assert!(a.search_batch(10).contains("no search initialized"));
+        assert!(b.search_batch(10).contains(r#""finished":true"#));
+
+        // The default search was left alone, and so was its flag
+        let json = search_batch(u32::MAX);
+        assert!(json.starts_with(r#"{"tag":"default","#) && json.contains(r#""total_found":"#), "{}", json);
+        assert!(!current_cancel_flag().load(Ordering::Relaxed));
+        destroy_batch_search_quiet();
+
+        // A cancelled instance still answers its next call: each call starts afresh
+        b.cancel();
+        assert!(b.find_one(&numbers, r#"{"target":20,"max_count":2}"#).contains(r#""indices":[5,13]"#));
+        assert!(b.destroy().contains(r#""finished":true"#));
+    }
+
+    #[test]
+    fn test_provably_unusable_rows_left_out() {
+        // 10 is too large for any pair with 2, then 2 and 4 too small for one with 6
+        let numbers = [2.0, 4.0, 5.0, 6.0, 10.0];
+        let json = find_one(&numbers, 11.0, 2, 2, None, None, None);
+        assert!(json.contains(r#""indices":[2,3]"#), "{}", json);
+        assert!(json.contains(concat!(
+            r#""skipped":[{"index":0,"reason":"provably_unusable"},{"index":1,"reason":"provably_unusable"},"#,
+            r#"{"index":4,"reason":"provably_unusable"}]"#,
+        )), "{}", json);
+
+        // The batch search may have max_count raised later, so only min_count prunes
+        destroy_batch_search_quiet();
+        assert_eq!(init_batch_search(&numbers, 11.0, 2, 2, 100, None, None), None);
+        let info = get_batch_info();
+        assert!(info.starts_with(r#"{"usable_count":4,"skipped_count":1,"skipped_by_reason":{"provably_unusable":1},"#), "{}", info);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""skipped":[{"index":4,"reason":"provably_unusable"}]"#), "{}", json);
+        assert!(set_batch_max_count(3).contains(r#""change":"resumed""#));
+        // 2+4+5 is reachable once three rows are allowed
+        assert!(search_batch(1_000_000).contains(r#""total_found":2,"#));
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_find_one_explains_not_found() {
+        // 40.00 is out of reach, 39.95 is not: a rounding difference, likely
+        let json = find_one(&[10.05, 20.10, 29.90], 40.0, 1, 3, None, None, Some(2));
+        assert!(json.starts_with(concat!(
+            r#"{"status":"not_found","explain":{"nearest_below":{"sum":3995,"indices":[0,2],"diff":5},"#,
+            r#""nearest_above":{"sum":5000,"indices":[1,2],"diff":1000},"radius":40,"#,
+            r#""achievable_within":[3995]},"stats":{"phases":[{"algorithm":"mitm""#,
+        )), "{}", json);
+        // Two cents over beats fifty under: the rows to look at are the ones that overshoot
+        let json = find_one(&[6.0, 4.02, 3.5], 10.0, 1, 3, None, None, Some(2));
+        assert!(json.starts_with(concat!(
+            r#"{"status":"not_found","explain":{"nearest_below":{"sum":950,"indices":[0,2],"diff":50},"#,
+            r#""nearest_above":{"sum":1002,"indices":[0,1],"diff":2},"#,
+        )), "{}", json);
+        // No summary when the count window alone rules the target out
+        let json = find_one(&[10.05, 20.10, 29.90], 40.0, 1, 1, None, None, Some(2));
+        assert!(json.starts_with(r#"{"status":"not_found","stats""#), "{}", json);
+
+        // Past 40 rows B&B proves it and names the misses it met: a row
+        // entered twice overshoots by two cents
+        let mut numbers = vec![50.01, 50.01];
+        numbers.extend((0..43).map(|i| 7.0 * (1 + i % 14) as f64));
+        let json = find_one(&numbers, 100.0, 1, 3, None, None, Some(2));
+        assert!(json.starts_with(concat!(
+            r#"{"status":"not_found","explain":{"nearest_below":{"sum":9901,"indices":[1,2,7],"diff":99},"#,
+            r#""nearest_above":{"sum":10002,"indices":[0,1],"diff":2},"exhaustive":false},"#,
+            r#""stats":{"phases":[{"algorithm":"bnb","#,
+        )), "{}", json);
+    }
+
+    #[test]
+    fn test_non_integral_target() {
+        // 100.005 is half a cent: rounded up to 100.01, not truncated to 100.00
+        let numbers = [50.0, 50.01, 49.99];
+        let json = find_one(&numbers, 100.005, 2, 2, None, None, Some(2));
+        assert!(json.starts_with(r#"{"status":"found","id":"#), "{}", json);
+        assert!(json.contains(r#""indices":[0,1],"#), "{}", json);
+        assert!(json.contains(r#""target_rounded_to":{"units":10001,"direction":"up"},"stats""#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":100.004,"scale":2,"tag":"t"}"#);
+        assert!(json.contains(r#""indices":[1,2],"#) && json.contains(r#""direction":"down""#), "{}", json);
+
+        let json = find_one_v2(&numbers, r#"{"target":100.005,"scale":2,"strict_target":true,"tag":"t"}"#);
+        assert_eq!(json, stamped(r#"{"tag":"t","status":"error","code":"target_not_representable","target":100.005,"scale":2,"nearest":{"units":10001,"direction":"up"}}"#));
+        // An adjusted target is checked the same way
+        let json = find_one_v2(&numbers, r#"{"target":100,"scale":2,"adjustments":[0.005],"strict_target":true}"#);
+        assert!(json.contains(r#""code":"target_not_representable","target":100.005,"#), "{}", json);
+
+        // 0.1 + 0.2 is 0.30000000000000004: float noise, neither reported nor refused
+        let json = find_one_v2(&[0.1, 0.2], &format!(r#"{{"target":{},"scale":2,"strict_target":true}}"#, 0.1 + 0.2));
+        assert!(json.starts_with(r#"{"status":"found","#) && !json.contains("target_rounded_to"), "{}", json);
+
+        // Batch searches report it on the first step only, and refuse before replacing anything
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":100.005,"scale":2}"#), None);
+        let json = init_batch_search_v2(&numbers, r#"{"target":100.005,"scale":2,"strict_target":true}"#).unwrap();
+        assert!(json.contains(r#""code":"target_not_representable""#), "{}", json);
+        let json = search_batch(1);
+        assert!(json.contains(r#""target_rounded_to":{"units":10001,"direction":"up"}"#), "{}", json);
+        let json = search_batch(1_000_000);
+        assert!(!json.contains("target_rounded_to") && json.contains(r#""total_found":1"#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_adjustments() {
+        // Only target + fee has a match: 30.00 + 47.50
+        let numbers = [12.5, 30.0, 47.5];
+        let json = find_one_v2(&numbers, r#"{"target":75,"scale":2,"adjustments":[-100,-2.5,0,2.5],"paranoid":true}"#);
+        assert!(json.starts_with(r#"{"status":"found","id":"#), "{}", json);
+        assert!(json.contains(r#""indices":[1,2],"values":[3000,4750],"#), "{}", json);
+        assert!(json.contains(r#""target":7750,"adjustment":2.5,"skipped_adjustments":[-100],"stats""#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":75,"scale":2}"#);
+        assert!(json.starts_with(r#"{"status":"not_found""#), "{}", json);
+
+        // The first adjustment with a match wins, whatever its size
+        let json = find_one_v2(&numbers, r#"{"target":75,"scale":2,"adjustments":[-15,2.5],"algorithm":"bnb"}"#);
+        assert!(json.contains(r#""indices":[0,2],"#) && json.contains(r#""target":6000,"adjustment":-15,"#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":75,"scale":2,"adjustments":[2.5],"objective":"min_cost","costs":[1,2,3]}"#);
+        assert!(json.contains(r#""total_cost":5,"optimal":true,"target":7750,"adjustment":2.5,"#), "{}", json);
+
+        // Nothing matches: the last adjustment tried is named
+        let json = find_one_v2(&numbers, r#"{"target":75,"scale":2,"adjustments":[0,1]}"#);
+        assert!(json.starts_with(r#"{"status":"not_found","explain":{"nearest_below":{"sum":6000,"indices":[0,2],"diff":1600},"#), "{}", json);
+        assert!(json.contains(r#""nearest_above":{"sum":7750,"indices":[1,2],"diff":150},"#), "{}", json);
+        assert!(json.contains(r#""achievable_within":[]},"target":7600,"adjustment":1,"stats""#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":75,"adjustments":[-75,-80]}"#);
+        assert!(json.starts_with(r#"{"status":"not_found","warnings":["fractional_inputs_truncated: "#), "{}", json);
+        assert!(json.contains(r#"consider scale=1"],"skipped_adjustments":[-75,-80],"stats""#), "{}", json);
+    }
+
+    #[test]
+    fn test_fractional_inputs_warning() {
+        let cents = [12.34, 7.66, 5.5, 20.0];
+        let json = find_one(&cents, 20.0, 1, 4, None, None, None);
+        assert!(json.contains(r#""warnings":["fractional_inputs_truncated: 75% of values had decimals; consider scale=2"]"#), "{}", json);
+        let json = find_one(&cents, 20.0, 1, 4, None, None, Some(2));
+        assert!(json.starts_with(r#"{"status":"found","#) && !json.contains("warnings"), "{}", json);
+        assert!(!find_one(&[3.0, 5.0, 12.0], 8.0, 1, 3, None, None, None).contains("warnings"));
+
+        let json = find_one_v2(&cents, r#"{"target":20,"strict_inputs":true,"tag":"t"}"#);
+        assert!(json.starts_with(concat!(
+            r#"{"tag":"t","status":"error","code":"fractional_inputs_truncated","percent":75,"fractional":3,"considered":4,"#,
+            r#""suggested_scale":2,"#,
+        )), "{}", json);
+        assert!(find_one_v2(&cents, r#"{"target":20,"scale":2,"strict_inputs":true}"#).starts_with(r#"{"status":"found","#));
+        assert!(find_one_v2(&cents, r#"{"target":20,"include_indices":[3]}"#).starts_with(r#"{"status":"found","#));
+
+        // Batch searches carry it on the first step only
+        assert_eq!(init_batch_search(&cents, 20.0, 1, 4, 0, None, None), None);
+        assert!(search_batch(1).contains(r#""warnings":["fractional_inputs_truncated: 75%"#));
+        assert!(!search_batch(1000).contains("warnings"));
+        destroy_batch_search_quiet();
+        let json = init_batch_search_v2(&cents, r#"{"target":20,"strict_inputs":true}"#).unwrap();
+        assert!(json.contains(r#""code":"fractional_inputs_truncated""#), "{}", json);
+        assert_eq!(init_batch_search_v2(&cents, r#"{"target":20,"scale":2}"#), None);
+        assert!(!search_batch(1000).contains("warnings"));
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_v2_tag() {
+        let numbers = [6.0, 2.0, 5.0, 1.0, 4.0, 3.0];
+        let json = find_one_v2(&numbers, r#"{"target":10,"tag":"левая панель #3 ✓"}"#);
+        assert!(json.starts_with(r#"{"tag":"левая панель #3 ✓","status":"found","#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":10,"scale":12,"tag":"x\"y"}"#);
+        assert!(json.starts_with(r#"{"tag":"x\"y","status":"error","code":"scale_out_of_range""#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":10}"#);
+        assert!(!json.contains("tag"), "{}", json);
+        let long = format!(r#"{{"target":10,"tag":"{}"}}"#, "t".repeat(options::MAX_TAG_LEN + 1));
+        assert_eq!(find_one_v2(&numbers, &long), stamped(r#"{"status":"error","code":"tag_too_long","max":256,"actual":257}"#));
+        assert_eq!(init_batch_search_v2(&numbers, &long), Some(stamped(r#"{"status":"error","code":"tag_too_long","max":256,"actual":257}"#)));
+
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"max_count":2,"tag":"worker-7"}"#), None);
+        let json = search_batch(1);
+        assert!(json.starts_with(r#"{"tag":"worker-7","new_results":"#), "{}", json);
+        assert!(set_batch_max_count(3).starts_with(r#"{"tag":"worker-7","status":"ok""#));
+        assert!(get_results(None).starts_with(r#"{"tag":"worker-7","order_by":"discovery""#));
+        assert_eq!(
+            get_results(Some("score".to_string())),
+            stamped(r#"{"tag":"worker-7","status":"error","code":"not_scored"}"#),
+        );
+        assert!(destroy_batch_search().starts_with(r#"{"tag":"worker-7","total_found":"#));
+    }
+
+    #[test]
+    fn test_v2_precheck() {
+        // Even values, odd target: on by default at this size
+        let numbers = [2.0, 4.0, 6.0, 8.0, 10.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":9}"#), None);
+        let json = search_batch(1);
+        assert!(json.contains(r#""finished":true,"#), "{}", json);
+        assert!(json.contains(r#""precheck":{"outcome":"infeasible","node_budget":200000,"#), "{}", json);
+        assert!(json.contains(r#""termination_reason":"proved_infeasible","space_exhausted":true"#), "{}", json);
+        destroy_batch_search_quiet();
+
+        // Without it, the walk itself finds nothing: one scan of the pairs
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":9,"precheck":false}"#), None);
+        let json = search_batch(1);
+        assert!(!json.contains("precheck") && json.contains(r#""termination_reason":"exhausted","#), "{}", json);
+        destroy_batch_search_quiet();
+
+        // 10 = 4+6 = 2+8 = 10: the witness comes first and only once
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"precheck_nodes":5}"#), None);
+        // (the pairs then take a single scan)
+        let json = search_batch(1);
+        assert!(json.starts_with(r#"{"new_results":[{"id":"2cdcdc0dfc5d1141","indices":[4],"#), "{}", json);
+        assert!(json.contains(r#""total_found":3,"#) && json.matches(r#""id":"#).count() == 3, "{}", json);
+        assert!(json.contains(r#""outcome":"found","node_budget":5,"#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_rows_equal_to_target() {
+        let numbers = vec![12.5; 10_000];
+        let json = find_one(&numbers, 12.5, 1, 5, None, None, Some(2));
+        assert!(json.contains(r#""count":1,"identical_value_matches":10000,"#), "{}", json);
+        assert!(json.contains(r#""algorithm":"direct""#), "{}", json);
+        // A lone match needs no count
+        let json = find_one(&[12.5, 3.0], 12.5, 1, 5, None, None, Some(2));
+        assert!(json.contains(r#""status":"found""#) && !json.contains("identical_value_matches"), "{}", json);
+
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":12.5,"scale":2,"max_results":100}"#), None);
+        let json = search_batch(1_000);
+        assert!(json.contains(r#""total_found":1,"#), "{}", json);
+        assert!(json.contains(r#""identical_value_matches":10000"#), "{}", json);
+        assert!(json.contains(r#""finished":true,"space_fully_explored":true,"#), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_paranoid_catches_corrupted_results() {
+        let numbers = [1.25, 2.5, 3.75, 5.0];
+        let options = r#"{"target":5,"scale":2,"paranoid":true}"#;
+        let clean = find_one_v2(&numbers, options);
+        assert!(clean.starts_with(r#"{"status":"found""#), "{}", clean);
+
+        verify::tamper_with_next(|combo| combo[0].value += 1);
+        let json = find_one_v2(&numbers, options);
+        assert!(json.starts_with(r#"{"status":"error","code":"internal_consistency_error","reason":"value_mismatch","#), "{}", json);
+        assert!(json.contains(r#""target":500,"count_window":[1,4]"#), "{}", json);
+
+        // A batch search aborts at the bad result, returning none of that batch
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":5,"scale":2,"paranoid":true,"precheck":false}"#), None);
+        verify::tamper_with_next(|combo| combo.push(combo[0].clone()));
+        let json = search_batch(1_000);
+        assert!(json.contains(r#""reason":"duplicate_index","index":0,"indices":[0,2,0],"#), "{}", json);
+        assert!(!json.contains("new_results"), "{}", json);
+        assert_eq!(search_batch(1), stamped(r#"{"error":"no search initialized"}"#));
+
+        // An imported search goes on checking, against the numbers it is given
+        init_batch_search_v2(&numbers, r#"{"target":5,"scale":2,"paranoid":true,"precheck":false}"#);
+        let bytes = export_batch_state().unwrap();
+        let json = import_batch_state(&bytes, None);
+        assert_eq!(json, stamped(r#"{"status":"error","code":"numbers_required","option":"paranoid"}"#));
+        let json = import_batch_state(&bytes, Some(numbers.to_vec()));
+        assert!(json.starts_with(r#"{"status":"ok","#), "{}", json);
+        verify::tamper_with_next(|combo| combo[0].value += 1);
+        let json = search_batch(1_000);
+        assert!(json.contains(r#""code":"internal_consistency_error","reason":"value_mismatch","#), "{}", json);
+    }
+
+    #[test]
+    fn test_get_results_ordered_by_score() {
+        // 3000 = 1000+1000+1000 = 1234+1766 = 500+2500
+        let numbers = [1000.0, 1000.0, 1000.0, 1234.0, 1766.0, 500.0, 2500.0];
+        let indices = |json: &str| -> Vec<String> {
+            json.split(r#""indices":"#).skip(1).map(|s| s[..s.find(']').unwrap() + 1].to_string()).collect()
+        };
+        // No precheck, so discovery order is the DFS's alone
+        let options = r#"{"target":3000,"score_results":"prefer_round","precheck":false}"#;
+        assert_eq!(init_batch_search_v2(&numbers, options), None);
+        let json = search_batch(1_000_000);
+        assert_eq!(json.matches(r#","score":0."#).count(), 3, "{}", json);
+
+        let by_score = get_results(Some("score".into()));
+        assert!(by_score.starts_with(r#"{"order_by":"score","total":3,"results":["#), "{}", by_score);
+        assert_eq!(indices(&by_score), ["[0,1,2]", "[5,6]", "[3,4]"]);
+        assert!(by_score.contains(r#""count":3,"score":0.5833,"seq":1}"#), "{}", by_score);
+        let by_discovery = get_results(None);
+        let mut sorted = indices(&by_discovery);
+        assert_ne!(sorted, indices(&by_score));
+        sorted.sort();
+        assert_eq!(sorted, ["[0,1,2]", "[3,4]", "[5,6]"]);
+        assert_eq!(get_results(Some("value".into())), stamped(r#"{"status":"error","code":"invalid_order_by","found":"value"}"#));
+        destroy_batch_search_quiet();
+
+        // prefer_organic reverses it
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":3000,"score_results":"prefer_organic"}"#), None);
+        search_batch(1_000_000);
+        assert_eq!(indices(&get_results(Some("score".into()))), ["[3,4]", "[5,6]", "[0,1,2]"]);
+        destroy_batch_search_quiet();
+
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":3000}"#), None);
+        assert!(!search_batch(1_000_000).contains("score"));
+        assert_eq!(get_results(Some("score".into())), stamped(r#"{"status":"error","code":"not_scored"}"#));
+        destroy_batch_search_quiet();
+        assert_eq!(get_results(None), stamped(r#"{"error":"no search initialized"}"#));
+    }
+
+    #[test]
+    fn test_set_batch_max_count() {
+        assert_eq!(set_batch_max_count(4), stamped(r#"{"error":"no search initialized"}"#));
+        // 10 = 4+6 = 1+3+6 = 1+4+5 = 2+3+5 = 1+2+3+4
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(init_batch_search(&numbers, 10.0, 1, 2, 100, None, None), None);
+        assert!(search_batch(1_000_000).contains(r#""total_found":1,"#));
+        let json = set_batch_max_count(4);
+        assert!(json.starts_with(r#"{"status":"ok","change":"resumed","reopened":"#), "{}", json);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":5,"#) && json.contains(r#""finished":true"#), "{}", json);
+        assert_eq!(json.matches(r#""id":"#).count(), 4, "{}", json);
+        assert_eq!(set_batch_max_count(4), r#"{"status":"ok","change":"unchanged"}"#);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_adjust_target() {
+        destroy_batch_search_quiet();
+        assert_eq!(adjust_target(5.0), stamped(r#"{"error":"no search initialized"}"#));
+        // 10 = 4+6 = 1+3+6 = 1+4+5 = 2+3+5; 9 = 3+6 = 4+5 = 1+2+6 = 1+3+5 = 2+3+4
+        let tenths = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        assert_eq!(init_batch_search_v2(&tenths, r#"{"target":1.0,"scale":1,"max_count":3}"#), None);
+        search_batch(1_000_000);
+        let json = adjust_target(1.2);
+        assert!(json.starts_with(r#"{"status":"error","code":"target_increase_requires_restart","target":12,"current_target":10,"#), "{}", json);
+        let json = adjust_target(0.9);
+        assert_eq!(json, r#"{"status":"ok","change":"lowered","invalidated_count":4,"target":9}"#);
+        assert_eq!(adjust_target(0.9), r#"{"status":"ok","change":"unchanged","target":9}"#);
+        let lowered = search_batch(1_000_000);
+        destroy_batch_search_quiet();
+
+        assert_eq!(init_batch_search_v2(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], r#"{"target":9,"max_count":3}"#), None);
+        let fresh = search_batch(1_000_000);
+        // The same results, though the fresh search's precheck reports its witness first
+        let results = |json: &str| {
+            let mut ids: Vec<String> = json.split(r#""id":"#).skip(1).map(|id| id[..18].to_string()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(results(&lowered), results(&fresh));
+        assert!(fresh.contains(r#""total_found":5,"#), "{}", fresh);
+        assert!(adjust_target(0.0).starts_with(r#"{"status":"error","code":"invalid_target","target":0"#));
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_u64_entry_points_stay_exact() {
+        const P53: u64 = 1 << 53;
+        const P63: u64 = 1 << 63;
+        // Only (2^53 + 1) + (2^63 - 3) hits the target; 2^63 + 3 plus 2^63 - 3 overflows u64
+        let values = [P53 + 1, P53 - 1, 5, P63 + 3, P63 - 3, 0];
+        let target = P63 + P53 - 2;
+        let json = find_one_u64(&values, target, 1, 6, None);
+        assert!(json.starts_with(r#"{"status":"found","#), "{}", json);
+        assert!(json.contains(r#""indices":[0,4],"values":[9007199254740993,9223372036854775805]"#), "{}", json);
+        assert!(json.contains(r#""exact_integers":true"#), "{}", json);
+        // The f64 calls never claim exactness
+        assert!(!find_one(&[(P53 + 1) as f64, 1.0], (P53 + 2) as f64, 1, 2, None, None, None).contains(r#""exact_integers""#));
+
+        assert_eq!(init_batch_search_u64(&values, target, 1, 6, 10, Some(vec![0, 3, 4, 9])), None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""values":[9007199254740993,9223372036854775805]"#), "{}", json);
+        assert!(json.contains(r#""total_found":1,"#) && json.contains(r#""exact_integers":true"#), "{}", json);
+        assert!(json.contains(r#""skipped":[{"index":9,"reason":"out_of_range"}]"#), "{}", json);
+        destroy_batch_search_quiet();
+
+        // An exported u64 search stays exact once imported
+        let values: Vec<u64> = (1..=16).map(|i| P53 + i).collect();
+        assert_eq!(init_batch_search_u64(&values, 3 * P53 + 12, 3, 3, 1000, None), None);
+        let first = search_batch(3);
+        let bytes = export_batch_state().unwrap();
+        destroy_batch_search_quiet();
+        assert!(export::decode_header(&bytes).unwrap().carried.exact_integers);
+        let json = import_batch_state(&bytes, None);
+        assert!(json.starts_with(r#"{"status":"ok","#), "{}", json);
+        let rest = search_batch(1_000_000);
+        assert!(first.contains(r#""exact_integers":true"#) && rest.contains(r#""exact_integers":true"#), "{}", rest);
+        assert!(rest.contains(r#""values":[9007199254740995,9007199254740996,9007199254740997]"#), "{}", rest);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_self_test_passes() {
+        let json = self_test();
+        assert!(json.starts_with(r#"{"passed":true,"cases":[{"name":"mitm_find_one","passed":true,"#), "{}", json);
+        assert!(json.ends_with(&format!(r#""version":"{}"}}"#, export::CRATE_VERSION)), "{}", json);
+    }
+
+    #[test]
+    fn test_batch_reports_whether_space_exhausted() {
+        // 5 = 1+4 = 2+3: asking for 500 finds both and proves there are no more
+        let numbers = [1.0, 2.0, 3.0, 4.0];
+        init_batch_search(&numbers, 5.0, 1, 4, 500, None, None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":2,"nodes_explored":"#), "{}", json);
+        assert!(json.contains(r#""space_exhausted":true,"elapsed_ms":"#), "{}", json);
+        assert!(!json.contains("more_may_exist"), "{}", json);
+        destroy_batch_search_quiet();
+
+        init_batch_search(&numbers, 5.0, 1, 4, 1, None, None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""space_exhausted":false,"more_may_exist":true"#), "{}", json);
+        assert!(!json.contains("elapsed_ms"), "{}", json);
+        destroy_batch_search_quiet();
+    }
+
+    #[test]
+    fn test_v2_zero_means_unlimited() {
+        let numbers = [1.0, 2.0, 3.0, 4.0];
+        // 10 needs all four rows
+        let json = find_one_v2(&numbers, r#"{"target":10,"max_count":0}"#);
+        assert!(json.contains(r#""count":4"#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":10}"#);
+        assert!(json.contains(r#""count":4"#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":10,"max_count":3}"#);
+        assert!(json.starts_with(r#"{"status":"not_found""#), "{}", json);
+
+        // 5 = 1+4 = 2+3: both found with no result limit
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":5,"max_results":0}"#), None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":2,"#) && json.contains(r#""termination_reason":"exhausted""#), "{}", json);
+        let header = inspect_batch_state(&export_batch_state().unwrap());
+        assert!(header.contains(&format!(r#""max_count":4,"max_results":{}"#, validate::MAX_RESULTS_CAP)), "{}", header);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_v1_zero_keeps_literal_meaning() {
+        let numbers = [1.0, 2.0, 3.0, 4.0];
+        let json = find_one(&numbers, 10.0, 1, 0, None, None, None);
+        assert!(json.starts_with(r#"{"status":"error","code":"invalid_bounds","min_count":1,"max_count":0,"#), "{}", json);
+        init_batch_search(&numbers, 5.0, 1, 4, 0, None, None);
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""total_found":0,"#) && json.contains(r#""termination_reason":"max_results""#), "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_v2_options_errors() {
+        assert_eq!(
+            find_one_v2(&[1.0], r#"{"min_count":1}"#),
+            stamped(r#"{"status":"error","code":"missing_option","field":"target"}"#),
+        );
+        assert!(find_one_v2(&[1.0], "{").starts_with(r#"{"status":"error","code":"invalid_json","offset":1"#));
+        assert_eq!(
+            init_batch_search_v2(&[1.0], r#"{"target":1,"scale":12}"#).unwrap(),
+            stamped(r#"{"status":"error","code":"scale_out_of_range","field":"scale","max":9,"actual":12}"#),
+        );
+        assert!(init_batch_search_v2(&[1.0], r#"{"target":1,"max_results":10000001}"#).is_some());
+        assert_eq!(export_batch_state(), None);
+    }
+
+    #[test]
+    fn test_result_indices_in_chunks() {
+        // Every row is needed: one 50,000-row combination
+        let numbers = vec![1.0; 50_000];
+        set_max_elements_per_result(1000);
+        let json = find_one(&numbers, 50_000.0, 1, 50_000, None, None, None);
+        set_max_elements_per_result(0);
+        assert!(json.contains(r#""count":50000,"sum":50000,"truncated":true"#));
+        let id = format!("{:016x}", combination_id(&build_entries(&numbers, 50_000, 0, None).0));
+        assert_eq!(get_result_meta(&id), format!(r#"{{"id":"{}","count":50000,"sum":50000}}"#, id));
+
+        let reassemble = |result_id: &str, limit: u32| {
+            let mut indices = Vec::new();
+            loop {
+                let chunk = result_indices_chunk(result_id, indices.len() as u32, limit).unwrap();
+                if chunk.is_empty() {
+                    break indices;
+                }
+                indices.extend(chunk);
+            }
+        };
+        let stored = FULL_RESULTS.with(|cell| cell.borrow()[&u64::from_str_radix(&id, 16).unwrap()].indices.clone());
+        assert_eq!(stored.len(), 50_000);
+        assert_eq!(reassemble(&id, 4096), stored);
+        assert_eq!(result_indices_chunk(&id, 49_999, 10).unwrap(), vec![49_999]);
+        assert_eq!(result_indices_chunk(&id, 50_000, 10).unwrap(), Vec::<u32>::new());
+        let past = result_indices_chunk(&id, 50_001, 10).unwrap_err().to_json();
+        assert!(past.starts_with(r#"{"status":"error","code":"offset_out_of_range","offset":50001,"count":50000,"#), "{}", past);
+        assert!(release_full_result(&id));
+
+        // Untruncated batch results, by id or by position
+        init_batch_search(&[3.0, 5.0, 2.0, 8.0], 10.0, 1, 4, 10, None, None);
+        search_batch(1000);
+        let results = BATCH_STATE.with(|cell| cell.borrow().as_ref().unwrap().state.all_results().to_vec());
+        assert_eq!(results.len(), 2);
+        for (position, combo) in results.iter().enumerate() {
+            let id = format!("{:016x}", combination_id(combo));
+            let expected = payload_indices(result_format(), combo);
+            assert_eq!(reassemble(&id, 1), expected);
+            assert_eq!(reassemble(&position.to_string(), 2), expected);
+            assert!(get_result_meta(&position.to_string()).starts_with(&format!(r#"{{"id":"{}","#, id)));
+        }
+        assert!(get_result_meta("2").contains(r#""code":"unknown_result","result_id":"2""#));
+        destroy_batch_search();
+
+        for unknown in ["0", &id, "not an id", ""] {
+            let json = get_result_meta(unknown);
+            assert!(json.starts_with(r#"{"status":"error","code":"unknown_result","#), "{}", json);
+            assert!(result_indices_chunk(unknown, 0, 10).is_err());
+        }
+    }
+
+    #[test]
+    fn test_max_new_results_per_batch() {
+        // 49 pairs, all found by one scan of the root frame
+        let numbers: Vec<f64> = (101..=199).map(f64::from).collect();
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":300,"max_count":2,"max_new_results_per_batch":20}"#), None);
+        let first = search_batch(1_000_000);
+        assert!(first.contains(r#""finished":false,"#) && first.contains(r#""paused_on_results":true"#), "{}", first);
+        assert_eq!(first.matches(r#"{"id":"#).count(), 20);
+        let second = search_batch(1_000_000);
+        assert_eq!(second.matches(r#"{"id":"#).count(), 20);
+        // The last nine are held, not returned: destroy hands them back
+        let destroyed = destroy_batch_search();
+        assert!(destroyed.contains(r#""total_found":49,"#) && destroyed.contains(r#""finished":false,"#), "{}", destroyed);
+        assert_eq!(destroyed.matches(r#"{"id":"#).count(), 9);
+
+        init_batch_search_v2(&numbers, r#"{"target":300,"max_count":2,"max_new_results_per_batch":20}"#);
+        let counts: Vec<usize> = (0..3).map(|_| search_batch(1_000_000).matches(r#"{"id":"#).count()).collect();
+        assert_eq!(counts, vec![20, 20, 9]);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_results_by_seq() {
+        // (seq, indices) of each result in a payload's `key` list
+        let numbered = |json: &str, key: &str| -> Vec<(u64, Vec<u64>)> {
+            let payload = canonical::parse(json).unwrap();
+            let Some(canonical::Value::Array(results)) = payload.get(key) else { panic!("{}", json) };
+            results.iter()
+                .map(|combo| match (combo.get("seq"), combo.get("indices")) {
+                    (Some(seq), Some(canonical::Value::Array(indices))) => {
+                        (seq.as_u64().unwrap(), indices.iter().map(|i| i.as_u64().unwrap()).collect())
+                    }
+                    _ => panic!("{}", json),
+                })
+                .collect()
+        };
+        let field = |json: &str, key: &str| canonical::parse(json).unwrap().get(key).and_then(canonical::Value::as_u64);
+
+        // Ten pairs make 21, three to a payload; the second payload is lost
+        let numbers: Vec<f64> = (1..=20).map(f64::from).collect();
+        let options = r#"{"target":21,"max_count":2,"max_new_results_per_batch":3}"#;
+        assert_eq!(init_batch_search_v2(&numbers, options), None);
+        let mut received = Vec::new();
+        for batch in 0.. {
+            let json = search_batch(1_000_000);
+            let results = numbered(&json, "new_results");
+            if let Some(first) = field(&json, "first_seq") {
+                assert_eq!(field(&json, "last_seq"), Some(first + results.len() as u64 - 1), "{}", json);
+                assert_eq!(results.first().map(|r| r.0), Some(first), "{}", json);
+            }
+            if batch != 1 {
+                let next = received.len() as u64;
+                if let Some(first) = field(&json, "first_seq").filter(|&first| first > next) {
+                    received.extend(numbered(&get_results_by_seq(next as u32, first as u32 - 1), "results"));
+                }
+                received.extend(results);
+            }
+            if json.contains(r#""finished":true"#) {
+                break;
+            }
+        }
+        let seqs: Vec<u64> = received.iter().map(|r| r.0).collect();
+        assert_eq!(seqs, (0..10).collect::<Vec<u64>>());
+        assert_eq!(received, numbered(&get_results(None), "results"));
+
+        // Numbers past the last result are left out
+        let json = get_results_by_seq(8, 20);
+        assert!(json.starts_with(r#"{"from":8,"to":20,"total":2,"results":[{"id":"#), "{}", json);
+        assert_eq!(numbered(&json, "results"), received[8..]);
+        assert!(get_results_by_seq(12, 20).starts_with(r#"{"from":12,"to":20,"total":0,"results":[]"#));
+        let json = get_results_by_seq(5, 4);
+        assert!(json.contains(r#""code":"invalid_seq_range","from":5,"to":4"#), "{}", json);
+        destroy_batch_search();
+
+        // Streamed results are gone once returned; held ones keep their numbers,
+        // through an export too. 49 pairs, all found by one scan of the root frame
+        let numbers: Vec<f64> = (101..=199).map(f64::from).collect();
+        let options = r#"{"target":300,"max_count":2,"max_new_results_per_batch":20,"stream_results":true}"#;
+        assert_eq!(init_batch_search_v2(&numbers, options), None);
+        search_batch(1_000_000);
+        search_batch(1_000_000);
+        let json = get_results_by_seq(20, 45);
+        assert!(json.contains(r#""code":"seq_unavailable","first_available":40"#), "{}", json);
+        let held = numbered(&get_results_by_seq(40, 41), "results");
+        assert_eq!(held.iter().map(|r| r.0).collect::<Vec<u64>>(), [40, 41]);
+        let bytes = export_batch_state().unwrap();
+        assert!(import_batch_state(&bytes, Some(numbers.clone())).starts_with(r#"{"status":"ok""#));
+        let json = search_batch(1_000_000);
+        assert_eq!(numbered(&json, "new_results")[..2], held[..]);
+        assert!(json.contains(r#""first_seq":40,"last_seq":48,"#), "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_init_batch_search_shard() {
+        let numbers: Vec<f64> = (1..=18).map(|v| f64::from(v * 7 % 23 + 1)).collect();
+        let run = |init: &dyn Fn() -> Option<String>| {
+            init();
+            let mut found = Vec::new();
+            loop {
+                let json = search_batch(500);
+                found.extend(canonical::reported_combinations(&json).unwrap().into_iter().map(|c| c.indices));
+                if json.contains(r#""finished":true"#) {
+                    destroy_batch_search();
+                    return found;
+                }
+            }
+        };
+        let mut expected = run(&|| init_batch_search(&numbers, 40.0, 1, 4, 10_000, None, None));
+        expected.sort();
+        assert!(expected.len() > 50, "{}", expected.len());
+        for mode in [None, Some("first_row"), Some("first_two_rows")] {
+            let mut union: Vec<Vec<u64>> = (0..3)
+                .flat_map(|shard| run(&|| init_batch_search_shard(&numbers, 40.0, 1, 4, 10_000, shard, 3, mode.map(String::from))))
+                .collect();
+            union.sort();
+            assert_eq!(union, expected, "{:?}", mode);
+        }
+
+        assert!(init_batch_search_shard(&numbers, 40.0, 1, 4, 0, 3, 3, None).unwrap()
+            .starts_with(r#"{"status":"error","code":"invalid_shard","shard_index":3,"shard_count":3"#));
+        assert!(init_batch_search_shard(&numbers, 40.0, 1, 4, 0, 0, 3, Some("rows".into())).unwrap()
+            .starts_with(r#"{"status":"error","code":"invalid_shard_mode","found":"rows""#));
+    }
+
+    #[test]
+    fn test_preferred_indices() {
+        let numbers = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":10,"max_count":3,"precheck":false}"#), None);
+        let plain = search_batch(1_000_000);
+        assert!(plain.starts_with(r#"{"new_results":[{"id":"#) && !plain.contains("contains_preferred"), "{}", plain);
+
+        init_batch_search_v2(&numbers, r#"{"target":10,"max_count":3,"precheck":false,"preferred_indices":[8]}"#);
+        let preferred = search_batch(1_000_000);
+        assert!(preferred.contains(r#""indices":[0,8],"values":[1,9],"selection_order":[0,1],"count":2,"seq":0,"contains_preferred":1}"#), "{}", preferred);
+        let first = preferred.find("contains_preferred").unwrap();
+        assert!(preferred[first..].starts_with(r#"contains_preferred":1}"#), "{}", preferred);
+        assert_eq!(preferred.matches(r#""contains_preferred":0"#).count(), plain.matches(r#"{"id":"#).count() - 1);
+        assert_eq!(canonicalize_results(&preferred).matches(r#"{"id":"#).count(), canonicalize_results(&plain).matches(r#"{"id":"#).count());
+        assert_eq!(export_batch_state(), None);
+        destroy_batch_search();
+
+        let json = init_batch_search_v2(&numbers, r#"{"target":10,"algorithm":"mitm","preferred_indices":[8]}"#).unwrap();
+        assert!(json.contains(r#""conflicts_with":"preferred_indices""#), "{}", json);
+    }
+
+    #[test]
+    fn test_include_zeros() {
+        // Four rows can only reach 10 as 4 + 6 and both zeros
+        let numbers = [4.0, 0.0, 6.0, -1.0, 0.0];
+        let options = r#"{"target":10,"min_count":4,"max_count":4"#;
+        assert_eq!(init_batch_search_v2(&numbers, &format!("{}}}", options)), None);
+        let plain = search_batch(1_000_000);
+        assert!(plain.starts_with(r#"{"new_results":[],"#), "{}", plain);
+        destroy_batch_search();
+
+        let zeros = format!(r#"{},"include_zeros":true,"paranoid":true,"quick_phase":true}}"#, options);
+        init_batch_search_v2(&numbers, &zeros);
+        let padded = search_batch(1_000_000);
+        assert!(padded.contains(r#""indices":[0,1,2,4],"values":[4,0,6,0]"#), "{}", padded);
+        assert_eq!(padded.matches(r#"{"id":"#).count(), 1, "{}", padded);
+        let info = get_batch_info();
+        assert!(info.contains(r#""skipped_by_reason":{"non_positive":1}"#), "{}", info);
+        destroy_batch_search();
+
+        let json = init_batch_search_v2(&numbers, r#"{"target":10,"algorithm":"mitm","include_zeros":true}"#).unwrap();
+        assert!(json.contains(r#""conflicts_with":"include_zeros""#), "{}", json);
+    }
+
+    #[test]
+    fn test_max_entries() {
+        // Unusable rows never count against the cap
+        let numbers = [1.0, f64::NAN, 2.0, 3.0, -4.0, 4.0, f64::INFINITY, 5.0];
+        let json = find_one_v2(&numbers, r#"{"target":9,"max_entries":4,"tag":"t"}"#);
+        assert!(json.starts_with(r#"{"tag":"t","status":"error","code":"too_many_entries","max_entries":4,"scanned":8,"#), "{}", json);
+        assert!(find_one_v2(&numbers, r#"{"target":9,"max_entries":5}"#).starts_with(r#"{"status":"found""#));
+
+        let json = init_batch_search_v2(&numbers, r#"{"target":9,"max_entries":3}"#).unwrap();
+        assert!(json.contains(r#""code":"too_many_entries","max_entries":3,"scanned":6"#), "{}", json);
+        assert!(search_batch(1_000).contains("no search initialized"));
+        // Rows past the target aren't usable either
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":3,"max_entries":3}"#), None);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_deterministic_snapshots() {
+        // Duplicate values make every tie-break show in the output
+        let numbers = [5.0, 3.0, 5.0, 2.0, 3.0, 7.0, 2.0, 5.0, 1.0, 3.0];
+        let run = || {
+            let mut out = Vec::new();
+            for algorithm in ["bnb", "mitm"] {
+                let options = format!(r#"{{"target":10,"algorithm":"{}","deterministic":true}}"#, algorithm);
+                out.push(find_one_v2(&numbers, &options));
+                let options = format!(r#"{{"target":10,"max_count":3,"algorithm":"{}","score_results":"prefer_round","deterministic":true}}"#, algorithm);
+                assert_eq!(init_batch_search_v2(&numbers, &options), None);
+                loop {
+                    let json = search_batch(7);
+                    let finished = json.contains(r#""finished":true"#);
+                    out.push(json);
+                    if finished {
+                        break;
+                    }
+                }
+                out.push(get_results(Some("score".to_string())));
+                destroy_batch_search_quiet();
+            }
+            out
+        };
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.iter().all(|json| !json.contains("elapsed_ms")));
+
+        // Locked: a change here changes what a deterministic caller sees
+        assert_eq!(first[0], r#"{"status":"found","id":"510b6f4beff09f88","indices":[0,3,6,8],"values":[5,2,2,1],"selection_order":[3,1,2,0],"count":4,"stats":{"phases":[{"algorithm":"bnb","budget":null,"nodes":6}],"count_window":[2,4],"value_bits":32,"peak_memory_estimate_bytes":248}}"#);
+        let mitm = first.iter().position(|json| json.contains(r#""algorithm":"direct""#)).unwrap();
+        assert_eq!(first[mitm], r#"{"status":"found","id":"c615adcb76ddf8a7","indices":[0,2],"values":[5,5],"selection_order":[0,1],"count":2,"stats":{"phases":[{"algorithm":"direct","budget":null,"nodes":0}],"count_window":[2,4],"shortcut":"pair","peak_memory_estimate_bytes":248}}"#);
+        let seqs = |json: &str| json.split(r#""seq":"#).skip(1)
+            .map(|rest| rest.split('}').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(seqs(&first[mitm - 1]), "0,24,25,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23");
+        assert_eq!(seqs(first.last().unwrap()), "0,3,15,1,2,4,5,6,7,8,9,10,11,12,13,14,16,17,18,19,20,21,22,23,24,25");
+        // Every payload, one per line
+        let locked: Vec<&str> = include_str!("../tests/fixtures/deterministic_snapshots.txt").lines().collect();
+        assert_eq!(first.len(), locked.len());
+        for (i, (json, locked)) in first.iter().zip(locked).enumerate() {
+            assert_eq!(json, locked, "payload {}", i);
+        }
+    }
+
+    #[test]
+    fn test_peak_memory_estimate() {
+        let numbers: Vec<f64> = (1..=20).map(f64::from).collect();
+        let peak = |json: &str| -> u64 {
+            let Some((_, rest)) = json.split_once(r#""peak_memory_estimate_bytes":"#) else { panic!("{}", json) };
+            rest.split([',', '}']).next().unwrap().parse().unwrap()
+        };
+        for options in [r#"{"target":21}"#, r#"{"target":1000}"#, r#"{"target":21,"algorithm":"bnb"}"#] {
+            assert!(peak(&find_one_v2(&numbers, options)) > 0);
+        }
+
+        // Every way a batch search can finish reports its peak
+        assert_eq!(get_current_memory_estimate(), None);
+        let evens: Vec<f64> = numbers.iter().map(|v| v * 2.0).collect();
+        let finishes = [
+            (&numbers, r#"{"target":21,"max_count":3,"precheck":false}"#),
+            (&numbers, r#"{"target":21,"max_count":3,"max_results":5}"#),
+            (&numbers, r#"{"target":21,"max_count":3,"algorithm":"mitm"}"#),
+            (&evens, r#"{"target":41,"precheck":true}"#),
+        ];
+        for (numbers, options) in finishes {
+            assert_eq!(init_batch_search_v2(numbers, options), None);
+            let mut json = search_batch(50);
+            let running = get_current_memory_estimate().unwrap();
+            assert!(!json.contains("peak_memory_estimate_bytes") || json.contains(r#""finished":true"#), "{}", json);
+            while !json.contains(r#""finished":true"#) {
+                json = search_batch(1_000_000);
+            }
+            assert!(peak(&json) > 0 && peak(&json) as f64 >= running, "{}", json);
+            destroy_batch_search_quiet();
+        }
+
+        // The exhaustion proof's bitset counts towards the peak
+        let thirds = [3.0, 30_000.0, 33_000.0, 36_000.0, 39_000.0, 42_000.0];
+        assert_eq!(init_batch_search_v2(&thirds, r#"{"target":100001,"precheck":false}"#), None);
+        assert!(set_batch_sparse_tail(1));
+        let json = search_batch(1_000_000);
+        assert!(json.contains(r#""termination_reason":"proved_exhausted""#), "{}", json);
+        assert!(peak(&json) >= 100_001 / 8, "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_verify_exhaustive() {
+        // Ten pairs make 21; the first page holds four of them
+        let numbers: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(init_batch_search_v2(&numbers, r#"{"target":21,"max_count":2,"max_new_results_per_batch":4}"#), None);
+        let first = search_batch(1_000_000);
+        destroy_batch_search();
+        let shown: Vec<Vec<u64>> = canonical::reported_combinations(&first).unwrap().into_iter().map(|c| c.indices).collect();
+        assert_eq!(shown.len(), 4);
+
+        let check = verify_exhaustive(&numbers, 21.0, 1, 2, &first, None);
+        assert!(check.starts_with(r#"{"status":"mismatch","usable_count":20,"expected_count":10,"reported_count":4,"#), "{}", check);
+        let missing = canonical::parse(&check).unwrap();
+        let Some(canonical::Value::Array(missing)) = missing.get("missing") else { panic!("{}", check) };
+        let missing: Vec<Vec<u64>> = missing.iter()
+            .map(|combo| match combo.get("indices") {
+                Some(canonical::Value::Array(indices)) => indices.iter().map(|i| i.as_u64().unwrap()).collect(),
+                _ => panic!("{}", check),
+            })
+            .collect();
+        let expected: Vec<Vec<u64>> = (0..10).map(|i| vec![i, 19 - i]).filter(|pair| !shown.contains(pair)).collect();
+        assert_eq!(missing, expected);
+        assert!(check.ends_with(r#""extra":[]}"#), "{}", check);
+
+        // The full list verifies; a wrong and a repeated combination come back as extra
+        let all: Vec<String> = (0..10).map(|i| format!(r#"{{"indices":[{},{}]}}"#, i, 19 - i)).collect();
+        let full = format!("[{}]", all.join(","));
+        assert!(verify_exhaustive(&numbers, 21.0, 1, 2, &full, None).starts_with(r#"{"status":"verified","#));
+        let padded = format!(r#"[{},{},{{"indices":[0,1],"values":[1,2]}}]"#, all.join(","), all[3]);
+        let check = verify_exhaustive(&numbers, 21.0, 1, 2, &padded, None);
+        assert!(check.ends_with(concat!(
+            r#""missing":[],"extra":[{"indices":[0,1],"values":[1,2],"reason":"sum_mismatch","sum":3},"#,
+            r#"{"indices":[3,16],"reason":"repeated"}]}"#,
+        )), "{}", check);
+
+        let many: Vec<f64> = (1..=25).map(f64::from).collect();
+        assert_eq!(
+            verify_exhaustive(&many, 30.0, 1, 2, "[]", None),
+            r#"{"status":"not_verifiable","reason":"too_many_rows","usable_count":25,"max_rows":24}"#,
+        );
+        // Rows above the target don't count toward the cap
+        assert!(verify_exhaustive(&many, 3.0, 1, 2, "[]", None).contains(r#""expected_count":2,"#));
+        assert!(verify_exhaustive(&numbers, 21.0, 1, 2, "[{", None).starts_with(r#"{"status":"error","code":"invalid_json","#));
+    }
+
+    #[test]
+    fn test_truncated_results_reassemble() {
+        // Target = total sum: the only solution is every row
+        let numbers: Vec<f64> = (1..=30).rev().map(|v| v as f64).collect();
+        set_max_elements_per_result(8);
+        let json = find_one(&numbers, 465.0, 1, 30, None, None, None);
+        init_batch_search(&numbers, 465.0, 1, 30, 10, None, None);
+        let batch = search_batch(1_000_000);
+        destroy_batch_search();
+        set_max_elements_per_result(0);
+
+        for json in [&json, &batch] {
+            assert!(json.contains(r#""count":30,"sum":465,"truncated":true,"indices":[0,1,2,3,4,5,6,7]"#), "{}", json);
+            assert!(!json.contains("values"), "{}", json);
+        }
+        let id = format!("{:016x}", combination_id(&build_entries(&numbers, 465, 0, None).0));
+        let mut full = Vec::new();
+        loop {
+            let chunk = get_full_result(&id, full.len() as u32, 7).unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            full.extend(chunk);
+        }
+        assert_eq!(full, (0..30).collect::<Vec<u32>>());
+        assert_eq!(get_full_result(&id, 1000, 7), Some(vec![]));
+
+        assert!(release_full_result(&id));
+        assert_eq!(get_full_result(&id, 0, 7), None);
+        assert!(!release_full_result("not hex"));
+
+        // Without a cap the same combination is sent whole
+        assert!(!find_one(&numbers, 465.0, 1, 30, None, None, None).contains("truncated"));
+    }
+
+    #[test]
+    fn test_canonicalize_results_across_entry_points() {
+        // The same solutions found by different entry points compare equal
+        let numbers = [4.0, 1.0, 3.0, 2.0, 5.0];
+        init_batch_search(&numbers, 5.0, 1, 5, 1000, None, None);
+        let batch = search_batch(1_000_000);
+        destroy_batch_search();
+        let handle = create_dataset(&numbers, 0);
+        init_batch_search_on(handle, 5.0, 1, 5, 1000);
+        let batch_on = search_batch(1_000_000);
+        destroy_batch_search();
+        destroy_dataset(handle);
+
+        let results = |json: &str| canonical::parse(&canonicalize_results(json)).unwrap()
+            .get("new_results").cloned().unwrap();
+        assert_eq!(results(&batch), results(&batch_on));
+        let canonical = canonicalize_results(&batch);
+        assert!(canonical.contains(r#""indices":[0,1],"values":[4,1]"#), "{}", canonical);
+        let order: Vec<usize> = [r#""indices":[0,1]"#, r#""indices":[2,3]"#, r#""indices":[4]"#]
+            .iter().map(|needle| canonical.find(needle).unwrap()).collect();
+        assert!(order.windows(2).all(|w| w[0] < w[1]), "{}", canonical);
+
+        let err = canonicalize_results("[1,");
+        assert!(err.starts_with(r#"{"status":"error","code":"invalid_json","offset":3"#), "{}", err);
+    }
+
+    #[test]
+    fn test_get_capabilities() {
+        let json = get_capabilities();
+        let prefix = format!(r#"{{"api_version":{},"crate_version":"{}","features":["#, capabilities::API_VERSION, export::CRATE_VERSION);
+        assert!(json.starts_with(&prefix), "{}", json);
+        for feature in ["scale", "batch_export", "handles", "paranoid", "prefer_small_count"] {
+            assert!(json.contains(&format!(r#""{}""#, feature)), "{}: {}", feature, json);
+        }
+        assert_eq!(json.contains(r#""console_log""#), cfg!(feature = "console_log"));
+        // Errors identify the build the same way
+        let stamp = format!(r#","api_version":{},"crate_version":"{}"}}"#, capabilities::API_VERSION, export::CRATE_VERSION);
+        assert!(get_usage_histogram().ends_with(&stamp));
+    }
+
+    #[test]
+    fn test_every_payload_parses() {
+        let numbers = [4.0, 1.0, 3.0, 2.0, 5.0, f64::NAN];
+        let mut payloads = vec![
+            find_one(&numbers, 5.0, 1, 5, Some(vec![0, 1, 9]), Some(true), None),
+            find_matching_for_rows(&numbers, &[4], 1, 5, None),
+            find_random(&numbers, 5.0, 1, 5, 7, None),
+            get_effective_entries(&numbers, 5.0, 2),
+            inspect_batch_state(b"nope"),
+            canonicalize_results("{"),
+            get_capabilities(),
+        ];
+        init_batch_search(&numbers, 5.0, 1, 5, 1000, Some(vec![0, 1, 2, 3, 4, 9]), None);
+        payloads.push(search_batch(1_000_000));
+        payloads.push(get_usage_histogram());
+        let bytes = export_batch_state().unwrap();
+        payloads.push(inspect_batch_state(&bytes));
+        payloads.push(import_batch_state(&bytes, Some(numbers.to_vec())));
+        payloads.push(destroy_batch_search());
+        init_batch_count(&numbers, 5.0, 1, 5, None);
+        payloads.push(count_batch(1_000_000));
+        destroy_batch_count();
+        init_single_search(&numbers, 5.0, 1, 5, None, None);
+        payloads.push(step_single_search(2));
+        payloads.push(step_single_search(1_000_000));
+        destroy_single_search();
+        payloads.push(estimate_difficulty(&numbers, 5.0, 1, 5, None));
+        let handle = create_dataset(&numbers, 0);
+        payloads.push(get_dataset_summary(handle));
+        payloads.push(find_one_on(handle, 5.0, 1, 5));
+        destroy_dataset(handle);
+
+        for json in payloads {
+            assert!(canonical::parse(&json).is_ok(), "{}", json);
+        }
+    }
+}
+
+/// Boundary values for every exported function. The intended outcome for each:
+/// out-of-range arguments are structured errors (see validate.rs); everything else
+/// gives a well-formed payload, terminal where no work can be done:
+/// - targets that are non-finite or <= 0 scale to 0, which nothing matches
+/// - targets whose units reach 2^64 are a `target_scale_overflow` error, such rows are
+///   skipped as `scale_overflow`; sums are checked, never wrapped
+/// - an empty or inverted count window is not_found / finished with nothing found
+/// - max_results = 0 finishes on the first step with termination_reason "max_results"
+/// - node_budget = 0 does no work and reports the unchanged state
+#[cfg(test)]
+mod boundary_tests {
+    use super::*;
+    use serialize::stamped;
+
+    const TWO_POW_63: f64 = 9_223_372_036_854_775_808.0;
+    const TWO_POW_64: f64 = 18_446_744_073_709_551_616.0;
+    /// 2^64 - 2048, the largest f64 that fits in u64
+    const MAX_SAFE: f64 = 18_446_744_073_709_549_568.0;
+
+    fn status(json: &str) -> &str {
+        json.split('"').nth(3).unwrap_or("")
+    }
+
+    #[test]
+    fn test_find_one_extreme_targets() {
+        let numbers = [1.0, 2.0, 3.0];
+        for target in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, f64::MIN_POSITIVE] {
+            assert_eq!(status(&find_one(&numbers, target, 1, 3, None, None, None)), "not_found", "{}", target);
+        }
+        for target in [TWO_POW_64, f64::MAX] {
+            let json = find_one(&numbers, target, 1, 3, None, None, None);
+            assert_eq!(json, stamped(r#"{"status":"error","code":"target_scale_overflow","scale":0,"max_scale":null}"#));
+        }
+        assert_eq!(status(&find_one(&numbers, MAX_SAFE, 1, 3, None, None, None)), "not_found");
+    }
+
+    #[test]
+    fn test_sums_near_u64_max_do_not_wrap() {
+        // 2^63 + 2^63 + (2^64 - 2048) wraps to exactly 2^64 - 2048
+        let numbers = [TWO_POW_63, TWO_POW_63, MAX_SAFE];
+        let json = find_one(&numbers, MAX_SAFE, 3, 3, None, None, None);
+        assert_eq!(status(&json), "not_found", "{}", json);
+        let json = find_one(&numbers, MAX_SAFE, 1, 3, None, None, None);
+        assert!(json.contains(r#""indices":[2]"#), "{}", json);
+        let json = find_random(&numbers, MAX_SAFE, 3, 3, 0, None);
+        assert_eq!(status(&json), "not_found", "{}", json);
+
+        // Beyond 60 entries (plain B&B) and in the batch DFS
+        let mut many = vec![TWO_POW_63; 70];
+        many.push(MAX_SAFE);
+        let json = find_one(&many, MAX_SAFE, 3, 3, None, None, None);
+        assert_eq!(status(&json), "not_found", "{}", json);
+        init_batch_search(&many, MAX_SAFE, 1, 3, 100, None, None);
+        let json = search_batch(u32::MAX);
+        assert!(json.contains(r#""total_found":1,"#), "{}", json);
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_scale_overflow_rows_and_target() {
+        let max = max_safe_value_for_scale(4);
+        let over = f64::from_bits(max.to_bits() + 1);
+        let json = get_effective_entries(&[max, over, 1.0], max, 4);
+        assert!(json.contains(r#"{"index":1,"skipped_reason":"scale_overflow"}"#), "{}", json);
+        assert!(!json.contains(r#""index":0,"skipped_reason"#), "{}", json);
+
+        assert_eq!(
+            find_one(&[1.0], 5e15, 1, 1, None, None, Some(4)),
+            stamped(r#"{"status":"error","code":"target_scale_overflow","scale":4,"max_scale":3}"#),
+        );
+        assert!(init_batch_search(&[1.0], 5e15, 1, 1, 10, None, Some(4)).unwrap().contains("target_scale_overflow"));
+        assert!(init_batch_count(&[1.0], 5e15, 1, 1, Some(4)).unwrap().contains("target_scale_overflow"));
+        let handle = create_dataset(&[1.0], 4);
+        assert!(find_one_on(handle, 5e15, 1, 1).contains("target_scale_overflow"));
+        assert!(init_batch_search_on(handle, 5e15, 1, 1, 10).is_some());
+        destroy_dataset(handle);
+    }
+
+    #[test]
+    fn test_extreme_count_windows() {
+        let numbers = [1.0, 2.0, 3.0];
+        for (min, max) in [(0, 0), (3, 2), (u32::MAX, u32::MAX), (u32::MAX, 1)] {
+            let json = find_one(&numbers, 3.0, min, max, None, None, None);
+            let expected = if min > max { "error" } else { "not_found" };
+            assert_eq!(status(&json), expected, "{} {}: {}", min, max, json);
+            init_batch_search(&numbers, 3.0, min, max, 10, None, None);
+            let json = search_batch(10);
+            assert!(json.contains(r#""total_found":0,"#) && json.contains(r#""finished":true"#), "{}", json);
+        }
+        let json = find_one(&numbers, 3.0, 0, u32::MAX, None, None, None);
+        assert_eq!(status(&json), "found", "{}", json);
+        // Left out, max_count never falls below min_count
+        let json = find_one_v2(&numbers, r#"{"target":3,"min_count":5}"#);
+        assert!(json.starts_with(r#"{"status":"not_found""#), "{}", json);
+        assert_eq!(status(&find_random(&numbers, 3.0, 0, u32::MAX, u32::MAX, None)), "found");
+        destroy_batch_search();
+    }
+
+    #[test]
+    fn test_batch_extreme_budgets_and_caps() {
+        let numbers = [1.0, 2.0, 3.0];
+        init_batch_search(&numbers, 3.0, 1, 3, 0, None, None);
+        let json = search_batch(10);
+        assert!(json.contains(r#""finished":true"#) && json.contains(r#""termination_reason":"max_results""#), "{}", json);
+        assert!(json.contains(r#""nodes_explored":0,"#), "{}", json);
+
+        init_batch_search(&numbers, 3.0, 1, 3, 10, None, None);
+        assert_eq!(
+            search_batch(0),
+            r#"{"new_results":[],"total_found":0,"nodes_explored":0,"finished":false,"space_fully_explored":false,"progress":0.000000,"count_window":[1,2],"open_branches":3,"results_rate":0.000,"stage":"searching"}"#,
+        );
+        assert!(set_batch_sparse_tail(u32::MAX));
+        assert!(search_batch(u32::MAX).contains(r#""total_found":2,"#));
+        destroy_batch_search();
+
+        init_batch_count(&numbers, 3.0, 1, 3, None);
+        assert!(count_batch(0).contains(r#""found_so_far":0,"nodes_explored":0,"finished":false"#));
+        assert!(count_batch(u32::MAX).contains(r#""found_so_far":2,"#));
+        destroy_batch_count();
+    }
+
+    #[test]
+    fn test_input_len_cap_and_forced_algorithms() {
+        // A forced MITM runs up to MAX_FORCED_MITM_N usable entries and no further
+        let numbers: Vec<f64> = (1..=70).map(f64::from).collect();
+        let json = find_one_v2(&numbers, r#"{"target":100,"algorithm":"mitm"}"#);
+        assert_eq!(json, stamped(r#"{"status":"error","code":"algorithm_not_applicable","algorithm":"mitm","n":70,"max_n":50}"#));
+        let json = find_one_v2(&numbers[..51], r#"{"target":100,"algorithm":"mitm"}"#);
+        assert!(json.contains(r#""code":"algorithm_not_applicable""#), "{}", json);
+        let json = find_one_v2(&numbers[..30], r#"{"target":100,"algorithm":"mitm"}"#);
+        assert!(json.contains(r#""phases":[{"algorithm":"mitm","#), "{}", json);
+        let json = find_one_v2(&numbers, r#"{"target":100,"algorithm":"bnb"}"#);
+        assert!(json.contains(r#""phases":[{"algorithm":"bnb","#), "{}", json);
+
+        let too_long = validate::MAX_INPUT_LEN as u32 + 1;
+        assert!(!begin_dataset_load(too_long, 0));
+        assert!(!append_dataset_chunk(&[1.0]));
+        let handle = create_dataset(&[1.0], 0);
+        assert!(append_dataset_values(handle, &[2.0]));
+        assert!(!append_dataset_values(handle + 1, &[2.0]));
+        destroy_dataset(handle);
+    }
+
+    #[test]
+    fn test_extreme_indices_and_handles() {
+        let numbers = [1.0, 2.0, 3.0];
+        let json = find_one(&numbers, 3.0, 1, 3, Some(vec![u32::MAX, 0, 1]), None, None);
+        assert!(json.contains(r#""skipped":[{"index":4294967295,"reason":"out_of_range"}]"#), "{}", json);
+        assert_eq!(
+            find_matching_for_rows(&numbers, &[u32::MAX], 1, 3, None),
+            stamped(r#"{"status":"error","code":"invalid_target_index","index":4294967295,"reason":"out_of_range"}"#),
+        );
+        for handle in [0, u32::MAX] {
+            assert_eq!(find_one_on(handle, 3.0, 1, 3), stamped(r#"{"error":"unknown dataset handle"}"#));
+            assert_eq!(get_dataset_summary(handle), stamped(r#"{"error":"unknown dataset handle"}"#));
+            assert!(init_batch_search_on(handle, 3.0, 1, 3, 10).is_some());
+            destroy_dataset(handle);
+        }
+    }
+
+    #[test]
+    fn test_empty_inputs() {
+        let json = find_one(&[], 3.0, 1, 3, None, None, None);
+        assert!(json.starts_with(r#"{"status":"not_found","reason":"empty_input","#), "{}", json);
+        assert_eq!(status(&find_random(&[], 3.0, 1, 3, 0, None)), "not_found");
+        assert_eq!(get_effective_entries(&[], 3.0, 0), r#"{"target":3,"scale":0,"entries":[]}"#);
+
+        let handle = create_dataset(&[], 0);
+        assert_ne!(handle, 0);
+        assert_eq!(
+            get_dataset_summary(handle),
+            r#"{"scale":0,"input_len":0,"count":0,"min":null,"max":null,"total":0,"top_duplicates":[]}"#,
+        );
+        destroy_dataset(handle);
+
+        init_batch_search(&[], 3.0, 1, 3, 10, None, None);
+        assert!(search_batch(10).contains(r#""finished":true,"space_fully_explored":true,"progress":1.000000,"count_window":null"#));
+        destroy_batch_search();
+    }
 }
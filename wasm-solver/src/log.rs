@@ -0,0 +1,147 @@
+//! Opt-in logging of the solver's decisions, for debugging in the browser.
+//!
+//! `log!(Level::Info, "...", args)` checks the level set by `set_log_level` before
+//! formatting anything, so a disabled message costs one thread-local read. Lines
+//! go to the browser console on wasm with the `console_log` feature and to stderr
+//! natively. Without that feature a wasm build has every call site compiled out.
+
+use std::cell::{Cell, RefCell};
+
+/// How much to log; each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    /// Algorithm choices, fallbacks, phase changes, caps hit
+    Info = 2,
+    /// Also periodic node-count heartbeats
+    Debug = 3,
+}
+
+impl Level {
+    /// 0 = off, 1 = error, 2 = info, 3 or more = debug.
+    pub fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Level::Off,
+            1 => Level::Error,
+            2 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+/// Where log lines go.
+pub trait Sink {
+    fn write(&mut self, level: Level, message: &str);
+}
+
+/// The console on wasm, stderr natively.
+struct DefaultSink;
+
+impl Sink for DefaultSink {
+    fn write(&mut self, level: Level, message: &str) {
+        #[cfg(all(target_arch = "wasm32", feature = "console_log"))]
+        web_sys::console::log_1(&format!("[targetsum {}] {}", level.as_str(), message).into());
+        #[cfg(not(target_arch = "wasm32"))]
+        eprintln!("[targetsum {}] {}", level.as_str(), message);
+        // Never reached: `enabled` is constant false here
+        #[cfg(all(target_arch = "wasm32", not(feature = "console_log")))]
+        let _ = (level, message);
+    }
+}
+
+thread_local! {
+    static LEVEL: Cell<Level> = const { Cell::new(Level::Off) };
+    static SINK: RefCell<Box<dyn Sink>> = RefCell::new(Box::new(DefaultSink));
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.with(|l| l.set(level));
+}
+
+/// Whether a message at `level` would be written.
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    cfg!(any(not(target_arch = "wasm32"), feature = "console_log")) && level != Level::Off && LEVEL.with(|l| level <= l.get())
+}
+
+/// Replace the sink, returning the previous one.
+#[cfg(test)]
+pub fn set_sink(sink: Box<dyn Sink>) -> Box<dyn Sink> {
+    SINK.with(|s| std::mem::replace(&mut *s.borrow_mut(), sink))
+}
+
+/// Write one message; call through `log!`, which checks `enabled` first.
+pub fn emit(level: Level, message: &str) {
+    SINK.with(|s| s.borrow_mut().write(level, message));
+}
+
+/// `log!(Level::Info, "format {}", args)`: formatted only when the level is on.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::emit($level, &format!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use log;
+
+/// Collects messages, for tests of what gets logged.
+#[cfg(test)]
+pub struct CaptureSink(pub std::rc::Rc<RefCell<Vec<(Level, String)>>>);
+
+#[cfg(test)]
+impl Sink for CaptureSink {
+    fn write(&mut self, level: Level, message: &str) {
+        self.0.borrow_mut().push((level, message.to_string()));
+    }
+}
+
+/// Run `f` at `level` with a capturing sink; returns what it logged. The level
+/// and sink are thread-local, so parallel tests do not see each other's lines.
+#[cfg(test)]
+pub fn capture(level: Level, f: impl FnOnce()) -> Vec<(Level, String)> {
+    let lines = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let previous_sink = set_sink(Box::new(CaptureSink(lines.clone())));
+    let previous_level = LEVEL.with(|l| l.replace(level));
+    f();
+    set_level(previous_level);
+    set_sink(previous_sink);
+    lines.take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_filter_messages() {
+        let lines = capture(Level::Info, || {
+            log!(Level::Error, "broken {}", 1);
+            log!(Level::Info, "chose {}", "mitm");
+            log!(Level::Debug, "heartbeat");
+        });
+        assert_eq!(lines, [(Level::Error, "broken 1".to_string()), (Level::Info, "chose mitm".to_string())]);
+
+        assert!(capture(Level::Off, || log!(Level::Error, "quiet")).is_empty());
+        assert_eq!(capture(Level::Debug, || log!(Level::Debug, "beat")).len(), 1);
+        // Restored afterwards
+        assert!(!enabled(Level::Error));
+    }
+
+    #[test]
+    fn test_level_from_u8() {
+        let levels: Vec<Level> = (0..=4).map(Level::from_u8).collect();
+        assert_eq!(levels, [Level::Off, Level::Error, Level::Info, Level::Debug, Level::Debug]);
+    }
+}
@@ -0,0 +1,550 @@
+//! Cheapest exact match: among the combinations hitting the target, the one
+//! with the smallest total of a caller-supplied cost column (`objective:
+//! "min_cost"`), e.g. days outstanding, to clear the oldest invoices first.
+//!
+//! find_one stops at its first match; here a match only tightens the bound the
+//! rest of the search has to beat. Up to MAX_MITM_N entries, meet-in-the-middle
+//! keeps the cheapest left subset per (sum, count) and always proves its answer.
+//! Past that, B&B over ascending values prunes a branch once its cost so far
+//! plus the least any completion must add reaches the best total found; a node
+//! budget can cut that short, leaving the best match so far unproven.
+
+use crate::log::{Level, log};
+use crate::solver::{
+    NumberEntry, PhaseStats, RowMasks, SolverConfig, SolveError, SolveOutcome, SolverStats,
+    assert_mitm_halves, check_input, mitm_table_bytes, note_memory_fallback, reserve_mitm_table, split_alternating, subset_sum,
+    tighten_count_window,
+};
+use crate::utils::{OutOfMemory, StableHashMap, try_reserve};
+
+/// Default node budget for the B&B, a few seconds of search.
+pub const DEFAULT_MIN_COST_NODES: u64 = 20_000_000;
+
+/// Largest usable entry count meet-in-the-middle runs for.
+const MAX_MITM_N: usize = 40;
+
+/// Smallest costs kept per suffix for the completion bound; deeper completions
+/// count each further row at the largest kept cost.
+const BOUND_DEPTH: usize = 16;
+
+/// Which match find_one returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Objective {
+    /// The first one found (the default)
+    #[default]
+    First,
+    /// The one with the smallest total cost
+    MinCost,
+}
+
+impl Objective {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "first" => Some(Objective::First),
+            "min_cost" => Some(Objective::MinCost),
+            _ => None,
+        }
+    }
+}
+
+/// What a min-cost search can say about its answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CostSummary {
+    /// Total cost of the result's rows; None without a result
+    pub total_cost: Option<f64>,
+    /// Every combination was accounted for, so no cheaper match exists (or,
+    /// without a result, no match at all); false when the node budget ran out
+    pub proven: bool,
+}
+
+/// The cheapest combination of `entries` summing to the target, by `costs`
+/// (indexed by original index). Cancellation drops the best match found so far.
+pub fn solve_min_cost(
+    entries: &[NumberEntry],
+    costs: &[f64],
+    config: &SolverConfig,
+    node_budget: u64,
+) -> (SolveOutcome, CostSummary, SolverStats) {
+    let mut stats = SolverStats::default();
+    let none = CostSummary { total_cost: None, proven: true };
+    if let Err(err) = check_input(entries.len(), config) {
+        return (Err(err), none, stats);
+    }
+    let mut sorted = Vec::new();
+    if let Err(oom) = try_reserve(&mut sorted, entries.len(), config.memory_limit) {
+        return (Err(SolveError::OutOfMemory(oom)), none, stats);
+    }
+    sorted.extend_from_slice(entries);
+    sorted.sort_unstable_by_key(NumberEntry::value_order);
+    let suffix_sum = suffix_sums(&sorted);
+    let n = sorted.len();
+    let prepared_bytes = (sorted.capacity() * size_of::<NumberEntry>() + suffix_sum.capacity() * size_of::<u64>()) as u64;
+    stats.note_memory(prepared_bytes);
+    let Some((min_count, max_count)) = tighten_count_window(
+        &sorted, &suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return (Ok(None), none, stats);
+    };
+    stats.count_window = Some((min_count, max_count));
+    let config = &SolverConfig { min_count, max_count, ..*config };
+    log!(
+        Level::Info, "min_cost: n={}, count window [{}, {}], {}", n, min_count, max_count,
+        if n <= MAX_MITM_N { "mitm" } else { "bnb" },
+    );
+
+    let mitm = if n <= MAX_MITM_N {
+        let (mut enumerated, mut table_bytes) = (0, 0);
+        match mitm_min_cost(&sorted, costs, config, &mut enumerated, &mut table_bytes) {
+            Ok(best) => {
+                stats.phases.push(PhaseStats { algorithm: "mitm_min_cost", budget: None, nodes: enumerated });
+                stats.note_memory(prepared_bytes + table_bytes);
+                Some(best)
+            }
+            Err(oom) => {
+                note_memory_fallback(oom, "bnb_min_cost", &mut stats);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let (best, proven) = if let Some(best) = mitm {
+        match best {
+            Some(best) => (best, true),
+            None => return (Err(SolveError::Cancelled), none, stats),
+        }
+    } else {
+        let mut search = CostBb::new(sorted, suffix_sum, costs, config, node_budget);
+        let walk = search.dfs(0, 0, 0.0);
+        stats.value_bits = Some(u64::BITS);
+        stats.phases.push(PhaseStats {
+            algorithm: "bnb_min_cost",
+            budget: (node_budget != u64::MAX).then_some(node_budget),
+            nodes: search.nodes,
+        });
+        if walk == Walk::BudgetExhausted {
+            log!(Level::Info, "min_cost: bnb stopped at its {}-node budget", node_budget);
+        }
+        match walk {
+            Walk::Cancelled => return (Err(SolveError::Cancelled), none, stats),
+            walk => (search.best_entries(), walk == Walk::Complete),
+        }
+    };
+    match best {
+        Some(found) => {
+            let total_cost = found.iter().map(|e| costs[e.original_index.as_usize()]).sum();
+            (Ok(Some(found)), CostSummary { total_cost: Some(total_cost), proven }, stats)
+        }
+        None => (Ok(None), CostSummary { total_cost: None, proven }, stats),
+    }
+}
+
+fn suffix_sums(sorted: &[NumberEntry]) -> Vec<u64> {
+    let mut suffix_sum = vec![0u64; sorted.len() + 1];
+    for i in (0..sorted.len()).rev() {
+        suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+    }
+    suffix_sum
+}
+
+/// Meet-in-the-middle keeping, per (sum, count) of a left subset, only the
+/// cheapest (every one when row ids or a span can rule pairings out). None when cancelled,
+/// Err when left_map does not fit (see solver::reserve_mitm_table). `table_bytes`
+/// gets left_map's size once it is built.
+fn mitm_min_cost(
+    sorted: &[NumberEntry],
+    costs: &[f64],
+    config: &SolverConfig,
+    enumerated: &mut u64,
+    table_bytes: &mut u64,
+) -> Result<Option<Option<Vec<NumberEntry>>>, OutOfMemory> {
+    let (left, right) = split_alternating(sorted);
+    assert_mitm_halves(left.len(), right.len());
+    let rows = config.row_ids.map(|ids| RowMasks::new(&left, &right, ids));
+    // (smallest, largest) original index in a non-empty subset of a half
+    let mask_bounds = |half: &[NumberEntry], mask: u64| -> (usize, usize) {
+        half.iter().enumerate()
+            .filter(|&(bit, _)| mask & (1u64 << bit) != 0)
+            .map(|(_, e)| e.original_index.as_usize())
+            .fold((usize::MAX, 0), |(lo, hi), i| (lo.min(i), hi.max(i)))
+    };
+    let too_wide = |(lo, hi): (usize, usize)| config.max_index_span.is_some_and(|span| lo <= hi && hi - lo > span);
+    let mask_cost = |half: &[NumberEntry], mask: u64| -> f64 {
+        half.iter().enumerate()
+            .filter(|&(bit, _)| mask & (1u64 << bit) != 0)
+            .map(|(_, e)| costs[e.original_index.as_usize()])
+            .sum()
+    };
+
+    // sum -> Vec<(count, bitmask, cost)>
+    let mut left_map: StableHashMap<u64, Vec<(usize, u64, f64)>> = StableHashMap::default();
+    reserve_mitm_table(&mut left_map, 1u64 << left.len(), config.memory_limit)?;
+    for mask in 0..1u64 << left.len() {
+        if mask & 0xFFFF == 0 && config.controller.should_stop(mask).is_break() {
+            return Ok(None);
+        }
+        *enumerated += 1;
+        let Some((sum, count)) = subset_sum(&left, mask, config.target) else { continue };
+        if count > config.max_count || rows.as_ref().is_some_and(|rows| rows.clash(mask, &rows.left)) {
+            continue;
+        }
+        if too_wide(mask_bounds(&left, mask)) {
+            continue;
+        }
+        let cost = mask_cost(&left, mask);
+        let subsets = left_map.entry(sum).or_default();
+        let kept = if config.path_dependent() { None } else { subsets.iter().position(|s| s.0 == count) };
+        match kept {
+            Some(k) if cost < subsets[k].2 => subsets[k] = (count, mask, cost),
+            Some(_) => {}
+            None => subsets.push((count, mask, cost)),
+        }
+    }
+    *table_bytes = mitm_table_bytes(&left_map, left_map.values().map(Vec::len).sum());
+
+    // (left mask, right mask, total cost)
+    let mut best: Option<(u64, u64, f64)> = None;
+    for rmask in 0..1u64 << right.len() {
+        if rmask & 0xFFFF == 0 && config.controller.should_stop(rmask).is_break() {
+            return Ok(None);
+        }
+        *enumerated += 1;
+        let Some((rsum, rcount)) = subset_sum(&right, rmask, config.target) else { continue };
+        let rbounds = mask_bounds(&right, rmask);
+        if rows.as_ref().is_some_and(|rows| rows.clash(rmask, &rows.right)) || too_wide(rbounds) {
+            continue;
+        }
+        let Some(partners) = left_map.get(&(config.target - rsum)) else { continue };
+        let taken = rows.as_ref().map_or(0, |rows| rows.union(rmask, &rows.cross));
+        let rcost = mask_cost(&right, rmask);
+        for &(lcount, lmask, lcost) in partners {
+            let count = lcount + rcount;
+            if count < config.min_count || count > config.max_count || lmask & taken != 0 {
+                continue;
+            }
+            if config.max_index_span.is_some() {
+                let (lo, hi) = mask_bounds(&left, lmask);
+                if too_wide((lo.min(rbounds.0), hi.max(rbounds.1))) {
+                    continue;
+                }
+            }
+            let total = lcost + rcost;
+            if best.is_none_or(|(_, _, cheapest)| total < cheapest) {
+                best = Some((lmask, rmask, total));
+            }
+        }
+    }
+
+    let pick = |half: &[NumberEntry], mask: u64| -> Vec<NumberEntry> {
+        half.iter().enumerate().filter(|&(bit, _)| mask & (1u64 << bit) != 0).map(|(_, e)| e.clone()).collect()
+    };
+    Ok(Some(best.map(|(lmask, rmask, _)| [pick(&left, lmask), pick(&right, rmask)].concat())))
+}
+
+/// How a B&B walk ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Walk {
+    Complete,
+    BudgetExhausted,
+    Cancelled,
+}
+
+/// Per suffix of the sorted entries, the least cost any r more of its rows add.
+struct CostBounds {
+    /// negative[i]: the sum of the negative costs in sorted[i..]
+    negative: Vec<f64>,
+    /// Row i (BOUND_DEPTH + 1 wide): prefix sums of the smallest max(cost, 0)
+    /// in sorted[i..], as many as it has up to BOUND_DEPTH
+    lowest: Vec<f64>,
+}
+
+impl CostBounds {
+    /// `costs` by sorted position.
+    fn new(costs: &[f64]) -> Self {
+        let (n, width) = (costs.len(), BOUND_DEPTH + 1);
+        let mut negative = vec![0.0; n + 1];
+        let mut lowest = vec![0.0; (n + 1) * width];
+        let mut smallest: Vec<f64> = Vec::with_capacity(BOUND_DEPTH + 1);
+        for i in (0..n).rev() {
+            negative[i] = negative[i + 1] + costs[i].min(0.0);
+            let cost = costs[i].max(0.0);
+            smallest.insert(smallest.partition_point(|&s| s <= cost), cost);
+            smallest.truncate(BOUND_DEPTH);
+            let row = &mut lowest[i * width..(i + 1) * width];
+            for (k, &s) in smallest.iter().enumerate() {
+                row[k + 1] = row[k] + s;
+            }
+        }
+        CostBounds { negative, lowest }
+    }
+
+    /// Least cost of `rows` more rows from sorted[i..], plus any further rows:
+    /// those can only add their negative costs, already in `negative`.
+    fn least(&self, i: usize, rows: usize) -> f64 {
+        let kept = BOUND_DEPTH.min(self.negative.len() - 1 - i);
+        let row = &self.lowest[i * (BOUND_DEPTH + 1)..];
+        let mut bound = self.negative[i] + row[rows.min(kept)];
+        if rows > kept {
+            // Each row past the kept ones costs at least the largest of them
+            bound += (rows - kept) as f64 * (row[kept] - row[kept - 1]);
+        }
+        bound
+    }
+}
+
+/// The min-cost DFS over ascending values, with its best match so far.
+struct CostBb<'a> {
+    config: &'a SolverConfig<'a>,
+    sorted: Vec<NumberEntry>,
+    suffix_sum: Vec<u64>,
+    /// Cost of sorted[i]
+    costs: Vec<f64>,
+    bounds: CostBounds,
+    path: Vec<usize>,
+    /// Sorted positions of the cheapest match so far, and its cost
+    best: Option<(Vec<usize>, f64)>,
+    nodes: u64,
+    limit: u64,
+}
+
+impl<'a> CostBb<'a> {
+    fn new(
+        sorted: Vec<NumberEntry>,
+        suffix_sum: Vec<u64>,
+        costs: &[f64],
+        config: &'a SolverConfig<'a>,
+        limit: u64,
+    ) -> Self {
+        let costs: Vec<f64> = sorted.iter().map(|e| costs[e.original_index.as_usize()]).collect();
+        let bounds = CostBounds::new(&costs);
+        CostBb { config, sorted, suffix_sum, costs, bounds, path: Vec::new(), best: None, nodes: 0, limit }
+    }
+
+    fn best_entries(&self) -> Option<Vec<NumberEntry>> {
+        let (path, _) = self.best.as_ref()?;
+        Some(path.iter().map(|&i| self.sorted[i].clone()).collect())
+    }
+
+    /// Fewest more rows from sorted[start..] that can close `remaining` (their
+    /// largest values first); None when even all of them fall short.
+    fn rows_needed(&self, start: usize, remaining: u64) -> Option<usize> {
+        if remaining == 0 {
+            return Some(0);
+        }
+        if self.suffix_sum[start] < remaining {
+            return None;
+        }
+        // The k largest values of sorted[start..] are sorted[n - k..]
+        let n = self.sorted.len();
+        let (mut lo, mut hi) = (1, n - start);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.suffix_sum[n - mid] >= remaining {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Some(lo)
+    }
+
+    /// Whether a branch with `count` rows summing to `sum` at `cost`, continuing
+    /// from sorted[start..], can still reach the target and beat the best match.
+    fn promising(&self, start: usize, count: usize, sum: u64, cost: f64) -> bool {
+        let config = self.config;
+        let Some(by_value) = self.rows_needed(start, config.target - sum) else { return false };
+        let more = by_value.max(config.min_count.saturating_sub(count));
+        if count + more > config.max_count || more > self.sorted.len() - start {
+            return false;
+        }
+        match &self.best {
+            Some((_, best)) => cost + self.bounds.least(start, more) < *best,
+            None => true,
+        }
+    }
+
+    fn dfs(&mut self, start: usize, sum: u64, cost: f64) -> Walk {
+        let config = self.config;
+        if self.nodes >= self.limit {
+            return Walk::BudgetExhausted;
+        }
+        self.nodes += 1;
+        if self.nodes & 0xFFF == 0 && config.controller.should_stop(self.nodes).is_break() {
+            return Walk::Cancelled;
+        }
+
+        let count = self.path.len();
+        if sum == config.target && count >= config.min_count {
+            if self.best.as_ref().is_none_or(|(_, best)| cost < *best) {
+                self.best = Some((self.path.clone(), cost));
+            }
+            return Walk::Complete;
+        }
+        if count >= config.max_count {
+            return Walk::Complete;
+        }
+
+        let remaining = config.target - sum;
+        let n = self.sorted.len();
+        for i in start..n {
+            let value = self.sorted[i].value;
+            // Sorted ascending: once one value is too large, so are the rest
+            if value > remaining || self.suffix_sum[i] < remaining {
+                break;
+            }
+            let original_index = self.sorted[i].original_index;
+            let chosen = self.path.iter().map(|&p| self.sorted[p].original_index);
+            if config.row_taken(chosen.clone(), original_index) || config.outside_span(chosen, original_index) {
+                continue;
+            }
+            let (sum, cost) = (sum + value, cost + self.costs[i]);
+            if !self.promising(i + 1, count + 1, sum, cost) {
+                continue;
+            }
+            self.path.push(i);
+            let walk = self.dfs(i + 1, sum, cost);
+            self.path.pop();
+            if walk != Walk::Complete {
+                return walk;
+            }
+        }
+        Walk::Complete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{DEFAULT_HYBRID_BB_BUDGET, OriginalIndex, solve_depth_first_with_stats};
+    use crate::testing;
+    use std::sync::atomic::AtomicBool;
+
+    static NOT_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+    fn make_entries(values: &[u64]) -> Vec<NumberEntry> {
+        values.iter().enumerate().map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) }).collect()
+    }
+
+    fn make_config(target: u64, min_count: usize, max_count: usize) -> SolverConfig<'static> {
+        SolverConfig {
+            target,
+            min_count,
+            max_count,
+            controller: &NOT_CANCELLED,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    /// The least total cost over every subset, by enumeration.
+    fn brute_force(values: &[u64], costs: &[f64], config: &SolverConfig) -> Option<f64> {
+        let mut best: Option<f64> = None;
+        for mask in 1u32..1 << values.len() {
+            let rows: Vec<usize> = (0..values.len()).filter(|&i| mask & 1 << i != 0).collect();
+            let sum: u64 = rows.iter().map(|&i| values[i]).sum();
+            if sum != config.target || rows.len() < config.min_count || rows.len() > config.max_count {
+                continue;
+            }
+            let clash = |ids: &[u32]| rows.iter().any(|&i| rows.iter().any(|&j| i < j && ids[i] == ids[j]));
+            if config.row_ids.is_some_and(clash) {
+                continue;
+            }
+            if config.max_index_span.is_some_and(|span| rows[rows.len() - 1] - rows[0] > span) {
+                continue;
+            }
+            let cost = rows.iter().map(|&i| costs[i]).sum();
+            best = Some(best.map_or(cost, |b: f64| b.min(cost)));
+        }
+        best
+    }
+
+    /// Both algorithms on the same instance: (MITM total, B&B total, B&B proven).
+    fn both_algorithms(values: &[u64], costs: &[f64], config: &SolverConfig) -> (Option<f64>, Option<f64>, bool) {
+        let total = |found: Option<Vec<NumberEntry>>| found.map(|f| f.iter().map(|e| costs[e.original_index.as_usize()]).sum());
+        let mut sorted = make_entries(values);
+        sorted.sort_unstable_by_key(|e| e.value);
+        let mitm = mitm_min_cost(&sorted, costs, config, &mut 0, &mut 0).unwrap().unwrap();
+        let mut search = CostBb::new(sorted.clone(), suffix_sums(&sorted), costs, config, u64::MAX);
+        let walk = search.dfs(0, 0, 0.0);
+        (total(mitm), total(search.best_entries()), walk == Walk::Complete)
+    }
+
+    #[test]
+    fn test_first_match_is_not_the_cheapest() {
+        let values = [1, 2, 3, 4, 6];
+        let costs = [5.0, 5.0, 5.0, 5.0, 1.0];
+        let entries = make_entries(&values);
+        let config = make_config(10, 1, 5);
+        // The depth-first answer: 1 + 2 + 3 + 4, at 20
+        let (Ok(Some(first)), _) = solve_depth_first_with_stats(&entries, &config) else { panic!() };
+        assert_eq!(first.iter().map(|e| costs[e.original_index.as_usize()]).sum::<f64>(), 20.0);
+        let (result, summary, stats) = solve_min_cost(&entries, &costs, &config, DEFAULT_MIN_COST_NODES);
+        let Ok(Some(cheapest)) = result else { panic!("{:?}", result) };
+        let mut indices: Vec<usize> = cheapest.iter().map(|e| e.original_index.as_usize()).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, vec![3, 4]);
+        assert_eq!(summary, CostSummary { total_cost: Some(6.0), proven: true });
+        assert_eq!(stats.phases[0].algorithm, "mitm_min_cost");
+        assert_eq!(both_algorithms(&values, &costs, &config), (Some(6.0), Some(6.0), true));
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let mut rng = testing::rng();
+        for round in 0..150 {
+            let n = 4 + rng.below(10) as usize;
+            let values: Vec<u64> = (0..n).map(|_| 1 + rng.below(20)).collect();
+            // Whole costs, some negative, so totals compare exactly
+            let costs: Vec<f64> = (0..n).map(|_| rng.below(40) as f64 - 8.0).collect();
+            let target = values.iter().take(n / 2).sum();
+            let min = 1 + rng.below(3) as usize;
+            let max = min + rng.below(n as u64) as usize;
+            let row_ids: Vec<u32> = (0..n).map(|_| rng.below(n as u64 - 1) as u32).collect();
+            let mut config = make_config(target, min, max);
+            if round % 3 == 0 {
+                config.row_ids = Some(&row_ids);
+            }
+            if round % 4 == 1 {
+                config.max_index_span = Some(rng.below(n as u64) as usize);
+            }
+            let expected = brute_force(&values, &costs, &config);
+            let (mitm, bnb, proven) = both_algorithms(&values, &costs, &config);
+            assert_eq!(mitm, expected, "round {}", round);
+            assert_eq!(bnb, expected, "round {}", round);
+            assert!(proven);
+        }
+    }
+
+    #[test]
+    fn test_node_budget_leaves_answer_unproven() {
+        let values: Vec<u64> = (1..=60).collect();
+        let costs: Vec<f64> = (1..=60).map(|v| 100.0 - v as f64).collect();
+        let entries = make_entries(&values);
+        let config = make_config(200, 1, 60);
+        let (result, summary, stats) = solve_min_cost(&entries, &costs, &config, 500);
+        assert!(matches!(result, Ok(Some(_))), "{:?}", result);
+        assert!(!summary.proven);
+        assert_eq!((stats.phases[0].algorithm, stats.phases[0].budget), ("bnb_min_cost", Some(500)));
+
+        // A match of k rows costs 100k - 200, so any four rows are cheapest,
+        // which the bound proves without walking the rest
+        let (result, summary, stats) = solve_min_cost(&entries, &costs, &config, u64::MAX);
+        let Ok(Some(found)) = result else { panic!() };
+        assert_eq!(found.len(), 4);
+        assert_eq!(summary, CostSummary { total_cost: Some(200.0), proven: true });
+        assert!(stats.phases[0].nodes < 1_000_000, "{}", stats.phases[0].nodes);
+    }
+
+    #[test]
+    fn test_cost_bounds() {
+        let bounds = CostBounds::new(&[3.0, -2.0, 1.0, 5.0]);
+        // sorted[1..] holds -2, 1, 5: the negative cost always counts
+        assert_eq!(bounds.least(1, 0), -2.0);
+        assert_eq!(bounds.least(1, 2), -2.0 + 0.0 + 1.0);
+        assert_eq!(bounds.least(0, 4), -2.0 + 0.0 + 1.0 + 3.0 + 5.0);
+        let many = CostBounds::new(&[1.0; BOUND_DEPTH + 4]);
+        assert_eq!(many.least(0, BOUND_DEPTH + 3), (BOUND_DEPTH + 3) as f64);
+    }
+}
@@ -0,0 +1,334 @@
+//! Every combination by meet-in-the-middle, in resumable steps.
+//!
+//! The sorted entries are dealt alternately into two halves, as for find_one's
+//! MITM; every subset of the left half goes into a map by sum, then every
+//! subset of the right half looks up the left subsets that make up the rest of
+//! the target. Each subset, left or right, is one step, so a batch search can
+//! build the map and walk the right half a node budget at a time, where
+//! solve_mitm_all runs it in one go. The map holds up to 2^left subsets, which
+//! caps the halves at MAX_MITM_ALL_HALF; row ids and index spans are not
+//! supported.
+
+use crate::solver::{
+    AllCombinationsResult, NumberEntry, SolverConfig, mitm_table_bytes, reserve_mitm_table, split_alternating,
+    subset_sum, tighten_count_window,
+};
+use crate::utils::{OutOfMemory, StableHashMap};
+use std::ops::ControlFlow;
+
+/// Most entries in either half: 2^20 left subsets in the map at most.
+pub const MAX_MITM_ALL_HALF: usize = 20;
+
+/// Steps solve_mitm_all takes between asking its controller, as find_one's MITM does.
+const CHECK_INTERVAL: u64 = 1 << 16;
+
+pub struct MitmAll {
+    target: u64,
+    min_count: usize,
+    max_count: usize,
+    /// Bit i of a mask picks half[i]
+    left: Vec<NumberEntry>,
+    right: Vec<NumberEntry>,
+    /// Position of half[i] in the sorted entries the walk was built from
+    left_at: Vec<usize>,
+    right_at: Vec<usize>,
+    /// Position of the first entry equal to the target: a single row match
+    /// past it is left out, as the DFS reports such rows once through the first
+    first_at_target: Option<usize>,
+    /// Next left subset to enter into left_map
+    next_left: u64,
+    /// sum -> left subsets with that sum and at most max_count rows, ascending
+    left_map: StableHashMap<u64, Vec<u64>>,
+    /// Masks in left_map
+    masks_kept: usize,
+    /// Next right subset to match
+    next_right: u64,
+    /// Partners of the right subset next_right already handed out, when a match
+    /// stopped the walk among them
+    next_partner: usize,
+}
+
+impl MitmAll {
+    /// A walk over `sorted` (ascending by value) for combinations of
+    /// [min_count, max_count] rows summing to `target`. Err when the left map
+    /// would not fit `memory_limit` or cannot be allocated.
+    pub fn new(
+        sorted: &[NumberEntry],
+        target: u64,
+        min_count: usize,
+        max_count: usize,
+        memory_limit: Option<u64>,
+    ) -> Result<Self, OutOfMemory> {
+        let (left, right) = split_alternating(sorted);
+        let (left_at, right_at) = split_alternating(&(0..sorted.len()).collect::<Vec<usize>>());
+        assert!(
+            left.len() <= MAX_MITM_ALL_HALF && right.len() <= MAX_MITM_ALL_HALF,
+            "MITM over all combinations entered with {} entries", sorted.len(),
+        );
+        let mut left_map = StableHashMap::default();
+        reserve_mitm_table(&mut left_map, 1u64 << left.len(), memory_limit)?;
+        Ok(MitmAll {
+            target,
+            min_count,
+            max_count,
+            left,
+            right,
+            left_at,
+            right_at,
+            first_at_target: sorted.iter().position(|e| e.value == target),
+            next_left: 0,
+            left_map,
+            masks_kept: 0,
+            next_right: 0,
+            next_partner: 0,
+        })
+    }
+
+    /// Whether `n` sorted entries split into halves this walk can take.
+    pub fn fits(n: usize) -> bool {
+        n <= 2 * MAX_MITM_ALL_HALF
+    }
+
+    /// Take up to `budget` steps, handing each match to `found` as ascending
+    /// positions in the sorted entries. A break from `found` stops the walk
+    /// right after that match; the next call picks up from the one after it.
+    /// Returns the steps taken.
+    pub fn run(&mut self, budget: u64, mut found: impl FnMut(&[usize]) -> ControlFlow<()>) -> u64 {
+        let mut steps = 0;
+        let left_count = 1u64 << self.left.len();
+        while steps < budget && self.next_left < left_count {
+            let mask = self.next_left;
+            self.next_left += 1;
+            steps += 1;
+            if let Some((sum, count)) = subset_sum(&self.left, mask, self.target) {
+                if count <= self.max_count {
+                    self.left_map.entry(sum).or_default().push(mask);
+                    self.masks_kept += 1;
+                }
+            }
+        }
+        if self.next_left < left_count {
+            return steps;
+        }
+
+        let right_count = 1u64 << self.right.len();
+        let mut combo = Vec::with_capacity(self.max_count);
+        while self.next_right < right_count {
+            let rmask = self.next_right;
+            // A right subset is charged once, not again when resumed among its partners
+            if self.next_partner == 0 {
+                if steps == budget {
+                    break;
+                }
+                steps += 1;
+            }
+            let partners = subset_sum(&self.right, rmask, self.target)
+                .and_then(|(rsum, rcount)| Some((self.left_map.get(&(self.target - rsum))?, rcount)));
+            if let Some((partners, rcount)) = partners {
+                while let Some(&lmask) = partners.get(self.next_partner) {
+                    self.next_partner += 1;
+                    let count = lmask.count_ones() as usize + rcount;
+                    if count < self.min_count || count > self.max_count {
+                        continue;
+                    }
+                    combo.clear();
+                    combo.extend(picked(&self.left_at, lmask).chain(picked(&self.right_at, rmask)));
+                    combo.sort_unstable();
+                    if let ([row], Some(first)) = (combo.as_slice(), self.first_at_target) {
+                        if *row != first {
+                            continue;
+                        }
+                    }
+                    if found(&combo).is_break() {
+                        return steps;
+                    }
+                }
+            }
+            self.next_partner = 0;
+            self.next_right += 1;
+        }
+        steps
+    }
+
+    /// Whether some left subsets are still to go into the map.
+    pub fn building(&self) -> bool {
+        self.next_left < 1u64 << self.left.len()
+    }
+
+    /// Share of the left subsets in the map.
+    pub fn build_progress(&self) -> f64 {
+        self.next_left as f64 / (1u64 << self.left.len()) as f64
+    }
+
+    /// Every right subset has been matched.
+    pub fn finished(&self) -> bool {
+        self.next_right == 1u64 << self.right.len()
+    }
+
+    /// Heap bytes of the left map so far (see solver::mitm_table_bytes).
+    pub fn table_bytes(&self) -> u64 {
+        mitm_table_bytes(&self.left_map, self.masks_kept)
+    }
+
+    /// Share of the right half matched; 0 while the left map is being built.
+    pub fn progress(&self) -> f64 {
+        self.next_right as f64 / (1u64 << self.right.len()) as f64
+    }
+}
+
+/// Positions in the sorted entries of the subset `mask` of a half at `half_at`.
+fn picked(half_at: &[usize], mask: u64) -> impl Iterator<Item = usize> + '_ {
+    half_at.iter().enumerate().filter(move |&(bit, _)| mask & (1u64 << bit) != 0).map(|(_, &i)| i)
+}
+
+/// Every combination (up to max_results) by meet-in-the-middle, blocking, as
+/// solve_all_combinations finds them by DFS: each in ascending value order,
+/// `nodes_explored` counting subsets enumerated. Entries that don't fit the
+/// halves (MitmAll::fits), row ids and max_index_span are not supported. Err
+/// when the left map would not fit the config's memory_limit.
+#[allow(dead_code)]
+pub fn solve_mitm_all(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+    max_results: usize,
+) -> Result<AllCombinationsResult, OutOfMemory> {
+    debug_assert!(config.row_ids.is_none() && config.max_index_span.is_none());
+    let mut sorted = entries.to_vec();
+    sorted.sort_unstable_by_key(NumberEntry::value_order);
+    let mut suffix_sum = vec![0u64; sorted.len() + 1];
+    for i in (0..sorted.len()).rev() {
+        suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+    }
+    let mut all = AllCombinationsResult::default();
+    let window = (!sorted.is_empty())
+        .then(|| tighten_count_window(&sorted, &suffix_sum, config.target, config.min_count, config.max_count))
+        .flatten();
+    let Some((min_count, max_count)) = window else { return Ok(all) };
+
+    let mut walk = MitmAll::new(&sorted, config.target, min_count, max_count, config.memory_limit)?;
+    while !walk.finished() && !all.truncated_by_max_results {
+        if config.controller.should_stop(all.nodes_explored).is_break() {
+            all.cancelled = true;
+            break;
+        }
+        all.nodes_explored += walk.run(CHECK_INTERVAL, |combo| {
+            if all.results.len() == max_results {
+                all.truncated_by_max_results = true;
+                return ControlFlow::Break(());
+            }
+            all.results.push(combo.iter().map(|&i| sorted[i].clone()).collect());
+            ControlFlow::Continue(())
+        });
+    }
+    Ok(all)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::SearchController;
+    use crate::solver::{DEFAULT_HYBRID_BB_BUDGET, OriginalIndex, solve_all_combinations};
+    use crate::testing;
+    use std::sync::atomic::AtomicBool;
+
+    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate().map(|(original_index, &value)| NumberEntry { value, original_index: OriginalIndex::new(original_index) }).collect()
+    }
+
+    fn config(target: u64, min_count: usize, max_count: usize, controller: &dyn SearchController) -> SolverConfig<'_> {
+        SolverConfig {
+            target,
+            min_count,
+            max_count,
+            controller,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    fn canonical(results: &[Vec<NumberEntry>]) -> Vec<Vec<usize>> {
+        let mut sets: Vec<Vec<usize>> = results.iter()
+            .map(|combo| {
+                let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                indices
+            })
+            .collect();
+        sets.sort_unstable();
+        sets
+    }
+
+    #[test]
+    fn test_mitm_all_matches_dfs() {
+        let not_cancelled = AtomicBool::new(false);
+        let mut rng = testing::rng();
+        for round in 0..300 {
+            let n = rng.below(17) as usize;
+            // Narrow values, so duplicates and equal-target rows come up
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(12)).collect();
+            let target = 1 + rng.below(40);
+            let max = 1 + rng.below(8) as usize;
+            let min = 1 + rng.below(max as u64) as usize;
+            let entries = make_entries(&nums);
+            let config = config(target, min, max, &not_cancelled);
+            let mitm = solve_mitm_all(&entries, &config, usize::MAX).unwrap();
+            let dfs = solve_all_combinations(&entries, &config, usize::MAX);
+            assert_eq!(canonical(&mitm.results), canonical(&dfs.results), "round {}: {:?} {} [{}, {}]", round, nums, target, min, max);
+            assert!(!mitm.cancelled && !mitm.truncated_by_max_results);
+            assert!(mitm.results.iter().all(|combo| combo.windows(2).all(|w| w[0].value <= w[1].value)));
+        }
+    }
+
+    #[test]
+    fn test_mitm_all_resumes_after_any_budget() {
+        let nums: Vec<u64> = (0..22).map(|i| 1 + i % 9).collect();
+        let entries = make_entries(&nums);
+        let mut sorted = entries.clone();
+        sorted.sort_unstable_by_key(|e| e.value);
+        let mut whole = MitmAll::new(&sorted, 30, 2, 6, None).unwrap();
+        let mut expected = Vec::new();
+        let steps = whole.run(u64::MAX, |combo| {
+            expected.push(combo.to_vec());
+            ControlFlow::Continue(())
+        });
+        assert_eq!(steps, (1 << 11) + (1 << 11));
+        assert!(whole.finished());
+
+        for budget in [1, 7, 2048, 2049] {
+            let mut walk = MitmAll::new(&sorted, 30, 2, 6, None).unwrap();
+            let (mut found, mut steps) = (Vec::new(), 0);
+            let mut calls = 0u64;
+            while !walk.finished() {
+                calls += 1;
+                // A break on every third match stops the walk among partners
+                steps += walk.run(budget, |combo| {
+                    found.push(combo.to_vec());
+                    if found.len() % 3 == 0 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+                });
+                assert!(walk.progress() <= 1.0);
+            }
+            assert_eq!(found, expected, "budget {}", budget);
+            assert_eq!(steps, 1 << 12);
+            assert!(calls >= steps / budget);
+        }
+    }
+
+    #[test]
+    fn test_mitm_all_stops() {
+        let nums: Vec<u64> = (1..=30).collect();
+        let entries = make_entries(&nums);
+        let not_cancelled = AtomicBool::new(false);
+        let capped = solve_mitm_all(&entries, &config(40, 1, 30, &not_cancelled), 25).unwrap();
+        assert_eq!(capped.results.len(), 25);
+        assert!(capped.truncated_by_max_results && !capped.cancelled);
+
+        let cancelled = AtomicBool::new(true);
+        let stopped = solve_mitm_all(&entries, &config(40, 1, 30, &cancelled), usize::MAX).unwrap();
+        assert!(stopped.cancelled && stopped.results.is_empty());
+
+        let limited = SolverConfig { memory_limit: Some(1024), ..config(40, 1, 30, &not_cancelled) };
+        assert!(solve_mitm_all(&entries, &limited, usize::MAX).is_err());
+    }
+}
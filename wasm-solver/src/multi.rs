@@ -0,0 +1,344 @@
+//! Several targets at once, each matched by a combination of its own with no
+//! row used twice: deposits reconciled together, say, where a row one deposit
+//! takes cannot explain another.
+//!
+//! Targets are solved fewest candidates first, as counted by a budgeted batch
+//! count over every row, so the most constrained ones choose before the rows
+//! they need are gone. Each target walks its combinations in batch DFS order
+//! over the rows the targets before it left (exclude_rows takes out the rest);
+//! when a later target has none, the walk backs up and moves the previous one
+//! on to its next combination. Counting and walking share one node budget.
+//! Without a full assignment, the deepest one reached is reported, topped up
+//! with whatever remaining targets still fit beside it, and every target left
+//! over is named with the reason.
+
+use crate::batch::{BatchResult, BatchSearchState};
+use crate::control::SearchController;
+use crate::input::exclude_rows;
+use crate::solver::{NumberEntry, SolveError};
+
+/// Most targets one call takes.
+pub const MAX_TARGETS: usize = 64;
+
+/// Default node budget for a whole call, a few seconds of search.
+pub const DEFAULT_MULTI_TARGET_NODES: u64 = 20_000_000;
+
+/// Nodes a difficulty count may take per target before it is extrapolated.
+const COUNT_NODES: u64 = 200_000;
+
+/// Nodes per search_batch step; cancellation is checked between steps.
+const STEP_NODES: u64 = 4096;
+
+/// Why a target got no combination.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetFailure {
+    /// None exists, even with every row available
+    NoCombination,
+    /// Every combination it has needs a row the other targets hold
+    Conflict,
+    /// The node budget ran out before one was found
+    Budget,
+}
+
+impl TargetFailure {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TargetFailure::NoCombination => "no_combination",
+            TargetFailure::Conflict => "conflict",
+            TargetFailure::Budget => "budget_exhausted",
+        }
+    }
+}
+
+/// What solve_multiple_targets settled on.
+#[derive(Debug, Default)]
+pub struct MultiTargetResult {
+    /// One per target, in the order given; None where unsatisfied
+    pub combinations: Vec<Option<Vec<NumberEntry>>>,
+    /// (target position, reason) for each None in `combinations`, ascending
+    pub failures: Vec<(usize, TargetFailure)>,
+    /// Positions of the targets that have combinations, in the order they were solved
+    pub order: Vec<usize>,
+    pub nodes_explored: u64,
+    /// Combinations given up after a later target found no room
+    pub backtracks: u64,
+}
+
+impl MultiTargetResult {
+    pub fn solved(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// The search stopped short.
+enum Stop {
+    Cancelled,
+    Budget,
+}
+
+struct Walk<'a> {
+    targets: &'a [u64],
+    min_count: usize,
+    max_count: usize,
+    controller: &'a dyn SearchController,
+    node_budget: u64,
+    nodes: u64,
+    backtracks: u64,
+    /// Positions of the targets being assigned, in solving order
+    order: Vec<usize>,
+    /// Combinations chosen so far, by depth in `order`
+    chosen: Vec<Vec<NumberEntry>>,
+    /// The longest `chosen` reached
+    deepest: Vec<Vec<NumberEntry>>,
+}
+
+impl Walk<'_> {
+    fn search(&self, entries: &[NumberEntry], target: u64, max_results: usize) -> BatchSearchState {
+        let mut state = BatchSearchState::new(entries, target, self.min_count, self.max_count, max_results);
+        state.set_stream_results();
+        state
+    }
+
+    /// One search_batch step, charged to the budget.
+    fn step(&mut self, state: &mut BatchSearchState, cap: u64) -> Result<BatchResult, Stop> {
+        let left = self.node_budget.saturating_sub(self.nodes).min(cap);
+        if left == 0 {
+            return Err(Stop::Budget);
+        }
+        let before = state.nodes_explored();
+        let batch = state.search_batch(left.min(STEP_NODES));
+        self.nodes += batch.nodes_explored - before;
+        if self.controller.should_stop(self.nodes).is_break() {
+            return Err(Stop::Cancelled);
+        }
+        Ok(batch)
+    }
+
+    /// Candidate combinations for `target` over every row: exactly when the
+    /// count finishes, else extrapolated from its progress. None when it has none.
+    fn difficulty(&mut self, entries: &[NumberEntry], target: u64) -> Result<Option<f64>, Stop> {
+        let mut state = BatchSearchState::new_counting(entries, target, self.min_count, self.max_count);
+        let start = self.nodes;
+        let mut progress = 0.0;
+        loop {
+            match self.step(&mut state, COUNT_NODES.saturating_sub(self.nodes - start)) {
+                Ok(batch) if batch.finished => return Ok((batch.total_found > 0).then_some(batch.total_found as f64)),
+                Ok(batch) => progress = batch.progress,
+                Err(Stop::Budget) => return Ok(Some(state.found().max(1) as f64 / f64::max(progress, 1e-6))),
+                Err(Stop::Cancelled) => return Err(Stop::Cancelled),
+            }
+        }
+    }
+
+    /// Satisfy order[depth..] from `available`, leaving the combinations in
+    /// `chosen`. False when no assignment exists.
+    fn assign(&mut self, depth: usize, available: &[NumberEntry]) -> Result<bool, Stop> {
+        let Some(&position) = self.order.get(depth) else { return Ok(true) };
+        let mut state = self.search(available, self.targets[position], usize::MAX);
+        loop {
+            let batch = self.step(&mut state, u64::MAX)?;
+            for combination in batch.new_results {
+                let rest = without(available, &combination);
+                self.chosen.push(combination);
+                if self.chosen.len() > self.deepest.len() {
+                    self.deepest = self.chosen.clone();
+                }
+                if self.assign(depth + 1, &rest)? {
+                    return Ok(true);
+                }
+                self.chosen.pop();
+                self.backtracks += 1;
+            }
+            if batch.finished {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// The first combination for `target` among `available`; Ok(None) when
+    /// there is none.
+    fn first(&mut self, available: &[NumberEntry], target: u64) -> Result<Option<Vec<NumberEntry>>, Stop> {
+        let mut state = self.search(available, target, 1);
+        loop {
+            let batch = self.step(&mut state, u64::MAX)?;
+            if let Some(combination) = batch.new_results.into_iter().next() {
+                return Ok(Some(combination));
+            }
+            if batch.finished {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// `available` less the rows `combination` uses.
+fn without(available: &[NumberEntry], combination: &[NumberEntry]) -> Vec<NumberEntry> {
+    let mut rows: Vec<u32> = combination.iter().map(NumberEntry::index_u32).collect();
+    rows.sort_unstable();
+    let mut rest = available.to_vec();
+    exclude_rows(&mut rest, &mut Vec::new(), &rows);
+    rest
+}
+
+/// One combination per target in `targets`, with `min_count..=max_count` rows
+/// each and no row in two of them. Errors with InvalidBounds when min > max
+/// and Cancelled when `controller` stops the search; otherwise the result says
+/// which targets were satisfied and why the others were not.
+pub fn solve_multiple_targets(
+    entries: &[NumberEntry],
+    targets: &[u64],
+    min_count: usize,
+    max_count: usize,
+    controller: &dyn SearchController,
+    node_budget: u64,
+) -> Result<MultiTargetResult, SolveError> {
+    if min_count > max_count {
+        return Err(SolveError::InvalidBounds { min_count, max_count });
+    }
+    let mut walk = Walk {
+        targets,
+        min_count,
+        max_count,
+        controller,
+        node_budget,
+        nodes: 0,
+        backtracks: 0,
+        order: Vec::new(),
+        chosen: Vec::new(),
+        deepest: Vec::new(),
+    };
+    let mut result = MultiTargetResult { combinations: vec![None; targets.len()], ..Default::default() };
+
+    let mut ranked = Vec::with_capacity(targets.len());
+    for (position, &target) in targets.iter().enumerate() {
+        match walk.difficulty(entries, target) {
+            Ok(Some(candidates)) => ranked.push((candidates, position)),
+            Ok(None) => result.failures.push((position, TargetFailure::NoCombination)),
+            Err(Stop::Budget | Stop::Cancelled) => return Err(SolveError::Cancelled),
+        }
+    }
+    ranked.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    walk.order = ranked.into_iter().map(|(_, position)| position).collect();
+
+    let assigned = match walk.assign(0, entries) {
+        Ok(true) => std::mem::take(&mut walk.chosen),
+        Ok(false) | Err(Stop::Budget) => std::mem::take(&mut walk.deepest),
+        Err(Stop::Cancelled) => return Err(SolveError::Cancelled),
+    };
+
+    // Top up a partial assignment with whatever still fits beside it
+    let order = std::mem::take(&mut walk.order);
+    let mut available = entries.to_vec();
+    for (&position, combination) in order.iter().zip(assigned) {
+        available = without(&available, &combination);
+        result.combinations[position] = Some(combination);
+        result.order.push(position);
+    }
+    for &position in &order[result.order.len()..] {
+        match walk.first(&available, targets[position]) {
+            Ok(Some(combination)) => {
+                available = without(&available, &combination);
+                result.combinations[position] = Some(combination);
+                result.order.push(position);
+            }
+            Ok(None) => result.failures.push((position, TargetFailure::Conflict)),
+            Err(Stop::Budget) => result.failures.push((position, TargetFailure::Budget)),
+            Err(Stop::Cancelled) => return Err(SolveError::Cancelled),
+        }
+    }
+    result.failures.sort_by_key(|&(position, _)| position);
+    result.nodes_explored = walk.nodes;
+    result.backtracks = walk.backtracks;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::OriginalIndex;
+    use std::sync::atomic::AtomicBool;
+
+    fn entries(values: &[u64]) -> Vec<NumberEntry> {
+        values.iter().enumerate().map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) }).collect()
+    }
+
+    fn rows(combination: &Option<Vec<NumberEntry>>) -> Vec<usize> {
+        let mut rows: Vec<usize> = combination.iter().flatten().map(|e| e.original_index.as_usize()).collect();
+        rows.sort_unstable();
+        rows
+    }
+
+    fn solve(values: &[u64], targets: &[u64], min: usize, max: usize) -> MultiTargetResult {
+        solve_multiple_targets(&entries(values), targets, min, max, &AtomicBool::new(false), DEFAULT_MULTI_TARGET_NODES).unwrap()
+    }
+
+    #[test]
+    fn test_backtracks_where_greedy_fails() {
+        let values = [1, 2, 3, 4, 6];
+        // Solved alone in DFS order, 5 takes {1, 4} and leaves 10 nothing
+        let greedy = BatchSearchState::new(&entries(&values), 5, 1, 5, 1).search_batch(u64::MAX).new_results.remove(0);
+        assert_eq!(rows(&Some(greedy.clone())), vec![0, 3]);
+        let rest = without(&entries(&values), &greedy);
+        assert!(BatchSearchState::new(&rest, 10, 1, 5, 1).search_batch(u64::MAX).new_results.is_empty());
+
+        let result = solve(&values, &[5, 10], 1, 5);
+        assert!(result.solved());
+        assert_eq!(rows(&result.combinations[0]), vec![1, 2]);
+        assert_eq!(rows(&result.combinations[1]), vec![3, 4]);
+        assert!(result.backtracks > 0);
+    }
+
+    #[test]
+    fn test_combinations_are_disjoint_and_exact() {
+        let values = [3, 5, 7, 8, 10, 12, 15, 4, 6, 9];
+        let targets = [20, 15, 12, 22];
+        let result = solve(&values, &targets, 1, 4);
+        assert!(result.solved());
+        let mut used = Vec::new();
+        for (combination, &target) in result.combinations.iter().zip(&targets) {
+            let combination = combination.as_ref().unwrap();
+            assert_eq!(combination.iter().map(|e| e.value).sum::<u64>(), target);
+            used.extend(combination.iter().map(|e| e.original_index));
+        }
+        let count = used.len();
+        used.sort_unstable();
+        used.dedup();
+        assert_eq!(used.len(), count);
+        assert_eq!(result.order.len(), targets.len());
+    }
+
+    #[test]
+    fn test_partial_failure_names_targets() {
+        // 100 has no combination at all; 7 and 8 both need the 7
+        let result = solve(&[1, 7, 2], &[7, 100, 8], 1, 3);
+        assert!(!result.solved());
+        assert_eq!(result.combinations.iter().filter(|c| c.is_some()).count(), 1);
+        assert_eq!(result.failures.len(), 2);
+        assert!(result.failures.contains(&(1, TargetFailure::NoCombination)));
+        let (other, reason) = result.failures.iter().copied().find(|&(p, _)| p != 1).unwrap();
+        assert_eq!(reason, TargetFailure::Conflict);
+        assert!(result.combinations[other].is_none());
+        assert_eq!(result.order.len(), 1);
+
+        // Too small a budget to count anything is reported as such
+        let tight = solve_multiple_targets(&entries(&(1..40).collect::<Vec<u64>>()), &[300, 301], 1, 39, &AtomicBool::new(false), 10).unwrap();
+        assert!(tight.failures.iter().all(|&(_, reason)| reason == TargetFailure::Budget));
+        assert_eq!(tight.failures.len(), 2);
+    }
+
+    #[test]
+    fn test_bounds_and_cancellation() {
+        let never = AtomicBool::new(false);
+        assert_eq!(
+            solve_multiple_targets(&entries(&[1, 2]), &[3], 3, 2, &never, 100).unwrap_err(),
+            SolveError::InvalidBounds { min_count: 3, max_count: 2 },
+        );
+        let cancelled = AtomicBool::new(true);
+        assert_eq!(
+            solve_multiple_targets(&entries(&[1, 2, 3]), &[3, 3], 1, 3, &cancelled, 1000).unwrap_err(),
+            SolveError::Cancelled,
+        );
+        let none = solve(&[1, 2], &[], 1, 2);
+        assert!(none.solved() && none.combinations.is_empty());
+    }
+}
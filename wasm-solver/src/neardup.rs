@@ -0,0 +1,224 @@
+//! Near-duplicate suppression for batch results.
+//!
+//! With many equal amounts, a wide search reports one combination and then
+//! dozens more that only swap a value for an equal one elsewhere: distinct
+//! indices, the same amounts. With `suppress_near_duplicates: k`, a result
+//! whose multiset of values is within k of a recently emitted one is counted
+//! but not emitted. The distance between two multisets is the number of
+//! positions to change in the longer one to get the other (values swapped,
+//! added or dropped), so 0 means the same amounts and 2 a pair of them
+//! swapped for another pair with the same total.
+//!
+//! Only the last NEAR_DUPLICATE_WINDOW emitted results are compared against,
+//! which keeps the check bounded however many results stream out; an old
+//! near-duplicate can come through again once its twin has left the window.
+//! The window goes into exported state, so a resumed search holds back what
+//! the uninterrupted one would.
+
+use std::collections::VecDeque;
+use std::hash::Hasher;
+
+use crate::export::{ByteReader, ByteWriter, DecodeError};
+use crate::utils::Fnv1a;
+
+/// Emitted results a new one is compared against.
+pub const NEAR_DUPLICATE_WINDOW: usize = 256;
+
+/// What is kept of an emitted result: its values, with a hash for the exact
+/// case and a one-word sketch that rules most pairs out before any merge.
+struct ValueSignature {
+    hash: u64,
+    /// Bit (hash of a value) % 64 set for each distinct value
+    sketch: u64,
+    /// Ascending
+    values: Box<[u64]>,
+}
+
+impl ValueSignature {
+    fn new(values: &[u64]) -> Self {
+        let mut hash = Fnv1a::new();
+        let mut sketch = 0u64;
+        for &value in values {
+            hash.write_u64(value);
+            sketch |= 1 << (value_bit(value) % 64);
+        }
+        ValueSignature { hash: hash.finish(), sketch, values: values.into() }
+    }
+
+    /// Whether `other` is within `max_distance` of this one.
+    fn near(&self, other: &ValueSignature, max_distance: usize) -> bool {
+        if max_distance == 0 {
+            return self.hash == other.hash && self.values == other.values;
+        }
+        // Each sketch bit one side lacks is a distinct value the other misses
+        let (a, b) = (self.sketch, other.sketch);
+        if (a & !b).count_ones() as usize > max_distance || (b & !a).count_ones() as usize > max_distance {
+            return false;
+        }
+        if self.values.len().abs_diff(other.values.len()) > max_distance {
+            return false;
+        }
+        distance(&self.values, &other.values) <= max_distance
+    }
+}
+
+fn value_bit(value: u64) -> u32 {
+    let mut hash = Fnv1a::new();
+    hash.write_u64(value);
+    (hash.finish() >> 58) as u32
+}
+
+/// Positions to change in the longer of two ascending lists to get the other.
+fn distance(a: &[u64], b: &[u64]) -> usize {
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    a.len().max(b.len()) - common
+}
+
+/// The recent emissions of one search and how many results were held back.
+pub struct NearDuplicates {
+    max_distance: usize,
+    recent: VecDeque<ValueSignature>,
+    suppressed: usize,
+}
+
+impl NearDuplicates {
+    pub fn new(max_distance: usize) -> Self {
+        NearDuplicates { max_distance, recent: VecDeque::with_capacity(NEAR_DUPLICATE_WINDOW), suppressed: 0 }
+    }
+
+    /// Whether a result with these values (ascending) should be emitted: true
+    /// remembers it, false counts it as suppressed.
+    pub fn admit(&mut self, values: &[u64]) -> bool {
+        let signature = ValueSignature::new(values);
+        if self.recent.iter().any(|recent| recent.near(&signature, self.max_distance)) {
+            self.suppressed += 1;
+            return false;
+        }
+        if self.recent.len() == NEAR_DUPLICATE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(signature);
+        true
+    }
+
+    pub fn suppressed(&self) -> usize {
+        self.suppressed
+    }
+
+    /// Forget the results admitted so far, e.g. once a new target made them
+    /// invalid; the suppressed count stays.
+    pub fn forget_admitted(&mut self) {
+        self.recent.clear();
+    }
+
+    /// The distance, the count and the window's values; signatures are rebuilt
+    /// from those on read.
+    pub fn write_to(&self, w: &mut ByteWriter) {
+        w.usize(self.max_distance);
+        w.usize(self.suppressed);
+        w.usize(self.recent.len());
+        for recent in &self.recent {
+            w.usize(recent.values.len());
+            for &value in recent.values.iter() {
+                w.u64(value);
+            }
+        }
+    }
+
+    /// Inverse of write_to.
+    pub fn read_from(r: &mut ByteReader) -> Result<Self, DecodeError> {
+        let max_distance = r.usize()?;
+        let suppressed = r.usize()?;
+        let len = r.len(8)?;
+        if len > NEAR_DUPLICATE_WINDOW {
+            return Err(DecodeError::Corrupt);
+        }
+        let mut recent = VecDeque::with_capacity(NEAR_DUPLICATE_WINDOW);
+        for _ in 0..len {
+            let count = r.len(8)?;
+            let values = (0..count).map(|_| r.u64()).collect::<Result<Vec<u64>, _>>()?;
+            if !values.is_sorted() {
+                return Err(DecodeError::Corrupt);
+            }
+            recent.push_back(ValueSignature::new(&values));
+        }
+        Ok(NearDuplicates { max_distance, recent, suppressed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(&[1, 2, 3], &[1, 2, 3]), 0);
+        assert_eq!(distance(&[1, 2, 3], &[1, 2, 4]), 1);
+        assert_eq!(distance(&[1, 2, 3], &[2, 4]), 2);
+        assert_eq!(distance(&[5, 5, 5], &[5, 5]), 1);
+        assert_eq!(distance(&[], &[7, 8]), 2);
+    }
+
+    #[test]
+    fn test_admit_by_distance() {
+        let mut exact = NearDuplicates::new(0);
+        assert!(exact.admit(&[10, 20, 30]));
+        assert!(!exact.admit(&[10, 20, 30]));
+        assert!(exact.admit(&[10, 25, 25]));
+        assert_eq!(exact.suppressed(), 1);
+
+        // Two values swapped for two others: distance 2
+        let mut loose = NearDuplicates::new(2);
+        assert!(loose.admit(&[10, 20, 30]));
+        assert!(!loose.admit(&[10, 15, 35]));
+        assert!(loose.admit(&[5, 15, 40]));
+        assert!(!loose.admit(&[5, 15, 40]));
+        assert_eq!(loose.suppressed(), 2);
+    }
+
+    #[test]
+    fn test_window_forgets_old_results() {
+        let mut dups = NearDuplicates::new(0);
+        assert!(dups.admit(&[1]));
+        for v in 2..=NEAR_DUPLICATE_WINDOW as u64 {
+            assert!(dups.admit(&[v]));
+        }
+        assert!(!dups.admit(&[1]));
+        assert!(dups.admit(&[NEAR_DUPLICATE_WINDOW as u64 + 1]));
+        // [1] has left the window
+        assert!(dups.admit(&[1]));
+    }
+
+    #[test]
+    fn test_round_trip_keeps_window() {
+        let mut dups = NearDuplicates::new(1);
+        assert!(dups.admit(&[1, 2, 3]));
+        assert!(!dups.admit(&[1, 2, 4]));
+        let mut w = ByteWriter::new();
+        dups.write_to(&mut w);
+        let mut restored = NearDuplicates::read_from(&mut ByteReader::new(&w.into_bytes())).unwrap();
+        assert_eq!(restored.suppressed(), 1);
+        assert!(!restored.admit(&[1, 2, 5]));
+        assert!(restored.admit(&[7, 8]));
+
+        let mut w = ByteWriter::new();
+        w.usize(1);
+        w.usize(0);
+        w.usize(1);
+        w.usize(2);
+        w.u64(9);
+        w.u64(3);
+        assert_eq!(NearDuplicates::read_from(&mut ByteReader::new(&w.into_bytes())).err(), Some(DecodeError::Corrupt));
+    }
+}
@@ -0,0 +1,569 @@
+//! Options objects for the `*_v2` entry points: one JSON object instead of a
+//! growing list of positional arguments.
+//!
+//! The v2 calls also define 0 as "unlimited" for `max_count` and `max_results`;
+//! the positional (v1) calls keep using those values literally.
+
+use crate::batch::{DedupOverflow, MAX_COUNT_WINDOWS};
+use crate::canonical::{self, ParseError, Value};
+use crate::mincost::Objective;
+use crate::score::ScoreMode;
+use crate::solver::Algorithm;
+use crate::validate::{DEFAULT_MAX_ENTRIES, MAX_RESULTS_CAP};
+
+/// Why an options object was rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionsError {
+    Json(ParseError),
+    /// A required field is absent
+    Missing(&'static str),
+    /// A field is present with the wrong type
+    WrongType { field: &'static str, expected: &'static str },
+    /// `tag` is longer than MAX_TAG_LEN bytes
+    TagTooLong { max: usize, actual: usize },
+}
+
+impl OptionsError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OptionsError::Json(_) => "invalid_json",
+            OptionsError::Missing(_) => "missing_option",
+            OptionsError::WrongType { .. } => "invalid_option",
+            OptionsError::TagTooLong { .. } => "tag_too_long",
+        }
+    }
+}
+
+/// One count window: `(min, max, max_results)`.
+pub type WindowSpec = (u32, u32, u32);
+
+/// A search as described by an options object, before any limits are resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchOptions {
+    pub target: f64,
+    /// 0 accepted: the empty combination never matches a positive target, so it acts as 1
+    pub min_count: u32,
+    /// 0 = unlimited
+    pub max_count: u32,
+    /// 0 = unlimited; batch searches only
+    pub max_results: u32,
+    pub include_indices: Option<Vec<u32>>,
+    pub scale: Option<u32>,
+    pub check_uniqueness: bool,
+    /// find_one runs the batch search's DFS, so its answer is the batch's first result
+    pub consistent_with_find_one: bool,
+    /// Parallel to the numbers: rows sharing an id are copies of one physical row
+    pub row_ids: Option<Vec<u32>>,
+    /// `[min, max, max_results?]` windows searched in one pass, each with its own cap
+    /// (0 or absent = max_results); batch searches only, replacing min/max_count
+    pub count_windows: Option<Vec<WindowSpec>>,
+    /// Soft limit on batch init's prework; what does not fit runs in the first batch
+    pub init_budget_ms: Option<u32>,
+    /// Batch searches only: report a quick find-one answer before the exhaustive DFS
+    pub quick_phase: bool,
+    /// Score each combination in result payloads (see score.rs)
+    pub score_results: ScoreMode,
+    /// Batch searches only: check feasibility first; None = when n <= PRECHECK_MAX_N
+    /// and neither init_budget_ms nor consistent_with_find_one is set
+    pub precheck: Option<bool>,
+    /// Node budget for that check; None = DEFAULT_PRECHECK_NODES
+    pub precheck_nodes: Option<u32>,
+    /// Re-verify every returned combination against the input (see verify.rs)
+    pub paranoid: bool,
+    /// Batch searches only: return results smallest first, one count at a time
+    pub prefer_small_count: bool,
+    /// find_one only: which match to return; MinCost needs `costs`
+    pub objective: Objective,
+    /// Parallel to the numbers: what each row costs, for `objective: "min_cost"`
+    pub costs: Option<Vec<f64>>,
+    /// Node budget for the min-cost B&B; None = DEFAULT_MIN_COST_NODES
+    pub cost_nodes: Option<u32>,
+    /// find_one: force an algorithm instead of choosing by input size; batch
+    /// searches: Mitm searches by meet-in-the-middle instead of the DFS
+    pub algorithm: Algorithm,
+    /// Batch searches only: drop results once returned, keeping their ids
+    pub stream_results: bool,
+    /// Batch searches only: most results one search_batch returns; 0 = no cap
+    pub max_new_results_per_batch: Option<u32>,
+    /// Batch searches only: what to do once the set of result ids is full
+    pub dedup_overflow: DedupOverflow,
+    /// Echoed verbatim in every payload of the call or search
+    pub tag: Option<String>,
+    /// find_one only: amounts added to the target, tried in order (e.g. a known fee)
+    pub adjustments: Option<Vec<f64>>,
+    /// Batch searches only: hold back results within this many values of a
+    /// recently emitted one
+    pub suppress_near_duplicates: Option<u32>,
+    /// Cap on each large table a search builds, in MiB (see SolverConfig::memory_limit)
+    pub memory_limit_mb: Option<u32>,
+    /// Most a combination's original indices may spread (max - min)
+    pub max_index_span: Option<u32>,
+    /// Batch searches only: fail instead of replacing an active search
+    pub refuse_if_active: bool,
+    /// Refuse a target that is not a whole number of units at `scale` instead of
+    /// rounding it (see input::target_rounding)
+    pub strict_target: bool,
+    /// Refuse rows that lose decimals to `scale` instead of warning about them
+    /// (see input::fractional_inputs)
+    pub strict_inputs: bool,
+    /// Rows reconciled in earlier runs, left out of the search (see
+    /// input::previously_matched_rows)
+    pub previously_matched: Option<Vec<u32>>,
+    /// The same ledger by value, for rows that moved: each value leaves out one row
+    pub previously_matched_values: Option<Vec<f64>>,
+    /// find_one only: search over distinct values (see collapse.rs); None = when
+    /// it pays, past collapse::AUTO_MIN_N rows
+    pub collapse_duplicates: Option<bool>,
+    /// Batch searches only: rows tried first at every level of the DFS (see
+    /// BatchSearchState::set_preferred_rows)
+    pub preferred_indices: Option<Vec<u32>>,
+    /// Batch searches only: keep rows of exactly zero, which only pad a
+    /// combination's count (see input::keep_zeros)
+    pub include_zeros: bool,
+    /// Most usable rows the search takes before failing with `too_many_entries`;
+    /// 0 = DEFAULT_MAX_ENTRIES (see input::classify_rows)
+    pub max_entries: u32,
+    /// Byte-identical payloads for identical calls: no wall-clock fields, and
+    /// nothing that depends on timing (init_budget_ms is rejected)
+    pub deterministic: bool,
+}
+
+/// Most `adjustments` one call may try.
+pub const MAX_ADJUSTMENTS: usize = 16;
+
+/// Longest accepted `tag`, in UTF-8 bytes.
+pub const MAX_TAG_LEN: usize = 256;
+
+/// Largest usable entry count the precheck runs for when not asked explicitly.
+pub const PRECHECK_MAX_N: usize = 60;
+
+impl SearchOptions {
+    /// Parse `{ target, min_count?, max_count?, max_results?, include_indices?,
+    /// scale?, check_uniqueness?, consistent_with_find_one?, row_ids?, count_windows?,
+    /// init_budget_ms?, quick_phase?, score_results?, precheck?, precheck_nodes?,
+    /// paranoid?, prefer_small_count?, objective?, costs?, cost_nodes?, algorithm?,
+    /// stream_results?, max_new_results_per_batch?, dedup_overflow?, tag?, adjustments?, suppress_near_duplicates?,
+    /// memory_limit_mb?, max_index_span?, refuse_if_active?, strict_target?,
+    /// strict_inputs?, previously_matched?, previously_matched_values?, collapse_duplicates?,
+    /// preferred_indices?, include_zeros?, max_entries?, deterministic? }`.
+    /// Unknown fields are ignored. `objective: "min_cost"` and `costs` come together.
+    pub fn parse(json: &str) -> Result<Self, OptionsError> {
+        let value = canonical::parse(json).map_err(OptionsError::Json)?;
+        if !matches!(value, Value::Object(_)) {
+            return Err(OptionsError::WrongType { field: "options", expected: "object" });
+        }
+        let target = match value.get("target") {
+            None => return Err(OptionsError::Missing("target")),
+            Some(v) => v.as_f64().ok_or(OptionsError::WrongType { field: "target", expected: "number" })?,
+        };
+        let consistent_with_find_one = bool_field(&value, "consistent_with_find_one")?;
+        let algorithm = algorithm_field(&value)?;
+        if consistent_with_find_one && algorithm == Algorithm::Mitm {
+            let expected = "\"auto\" or \"bnb\" with consistent_with_find_one";
+            return Err(OptionsError::WrongType { field: "algorithm", expected });
+        }
+        let deterministic = bool_field(&value, "deterministic")?;
+        if deterministic && !matches!(value.get("init_budget_ms"), None | Some(Value::Null)) {
+            return Err(OptionsError::WrongType { field: "init_budget_ms", expected: "absent with deterministic" });
+        }
+        let objective = objective_field(&value)?;
+        let costs = f64_array_field(&value, "costs")?;
+        match (objective, &costs) {
+            (Objective::MinCost, None) => return Err(OptionsError::Missing("costs")),
+            (Objective::First, Some(_)) => return Err(OptionsError::Missing("objective")),
+            _ => {}
+        }
+        Ok(SearchOptions {
+            target,
+            min_count: u32_field(&value, "min_count")?.unwrap_or(1),
+            max_count: u32_field(&value, "max_count")?.unwrap_or(0),
+            max_results: u32_field(&value, "max_results")?.unwrap_or(0),
+            include_indices: u32_array_field(&value, "include_indices")?,
+            scale: u32_field(&value, "scale")?,
+            check_uniqueness: bool_field(&value, "check_uniqueness")?,
+            consistent_with_find_one,
+            row_ids: u32_array_field(&value, "row_ids")?,
+            count_windows: count_windows_field(&value)?,
+            init_budget_ms: u32_field(&value, "init_budget_ms")?,
+            quick_phase: bool_field(&value, "quick_phase")?,
+            score_results: score_field(&value)?,
+            precheck: match value.get("precheck") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(bool_field(&value, "precheck")?),
+            },
+            precheck_nodes: u32_field(&value, "precheck_nodes")?,
+            paranoid: bool_field(&value, "paranoid")?,
+            prefer_small_count: bool_field(&value, "prefer_small_count")?,
+            objective,
+            costs,
+            cost_nodes: u32_field(&value, "cost_nodes")?,
+            algorithm,
+            stream_results: bool_field(&value, "stream_results")?,
+            max_new_results_per_batch: u32_field(&value, "max_new_results_per_batch")?,
+            dedup_overflow: dedup_overflow_field(&value)?,
+            tag: tag_field(&value)?,
+            adjustments: adjustments_field(&value)?,
+            suppress_near_duplicates: u32_field(&value, "suppress_near_duplicates")?,
+            memory_limit_mb: u32_field(&value, "memory_limit_mb")?,
+            max_index_span: u32_field(&value, "max_index_span")?,
+            refuse_if_active: bool_field(&value, "refuse_if_active")?,
+            strict_target: bool_field(&value, "strict_target")?,
+            strict_inputs: bool_field(&value, "strict_inputs")?,
+            previously_matched: u32_array_field(&value, "previously_matched")?,
+            previously_matched_values: f64_array_field(&value, "previously_matched_values")?,
+            collapse_duplicates: match value.get("collapse_duplicates") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(bool_field(&value, "collapse_duplicates")?),
+            },
+            preferred_indices: u32_array_field(&value, "preferred_indices")?,
+            include_zeros: bool_field(&value, "include_zeros")?,
+            max_entries: u32_field(&value, "max_entries")?.unwrap_or(0),
+            deterministic,
+        })
+    }
+
+    /// memory_limit_mb in bytes.
+    pub fn memory_limit(&self) -> Option<u64> {
+        self.memory_limit_mb.map(|mb| u64::from(mb) << 20)
+    }
+
+    /// max_count with 0 resolved to the number of usable entries, or min_count
+    /// when that is more, so leaving it out never makes the window invalid.
+    pub fn resolved_max_count(&self, n: usize) -> u32 {
+        match self.max_count {
+            0 => u32::try_from(n).unwrap_or(u32::MAX).max(self.min_count),
+            max => max,
+        }
+    }
+
+    /// max_results with 0 resolved to the global cap, which bounds memory either way.
+    pub fn resolved_max_results(&self) -> u32 {
+        match self.max_results {
+            0 => MAX_RESULTS_CAP,
+            max => max,
+        }
+    }
+
+    /// max_entries with 0 resolved to the default cap.
+    pub fn resolved_max_entries(&self) -> usize {
+        match self.max_entries {
+            0 => DEFAULT_MAX_ENTRIES as usize,
+            max => max as usize,
+        }
+    }
+
+    /// Whether a batch search over `n` usable entries runs the precheck. Never
+    /// with prefer_small_count, as its witness would come first whatever its size,
+    /// nor with include_zeros, as the find-one solvers take positive values only.
+    pub fn resolved_precheck(&self, n: usize) -> bool {
+        if self.prefer_small_count || self.include_zeros {
+            return false;
+        }
+        let default = n <= PRECHECK_MAX_N && self.init_budget_ms.is_none() && !self.consistent_with_find_one;
+        self.precheck.unwrap_or(default)
+    }
+
+    /// The count windows with each 0 cap resolved to resolved_max_results.
+    pub fn resolved_count_windows(&self) -> Option<Vec<WindowSpec>> {
+        let windows = self.count_windows.as_ref()?;
+        let resolved = windows.iter()
+            .map(|&(min, max, cap)| (min, max, if cap == 0 { self.resolved_max_results() } else { cap }))
+            .collect();
+        Some(resolved)
+    }
+}
+
+fn as_u32(v: &Value) -> Option<u32> {
+    v.as_u64().and_then(|n| u32::try_from(n).ok())
+}
+
+fn u32_field(value: &Value, field: &'static str) -> Result<Option<u32>, OptionsError> {
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => as_u32(v).map(Some).ok_or(OptionsError::WrongType { field, expected: "u32" }),
+    }
+}
+
+fn u32_array_field(value: &Value, field: &'static str) -> Result<Option<Vec<u32>>, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field, expected: "array of u32" };
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(items)) => items.iter().map(as_u32).collect::<Option<Vec<u32>>>().map(Some).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn f64_array_field(value: &Value, field: &'static str) -> Result<Option<Vec<f64>>, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field, expected: "array of finite numbers" };
+    let finite = |v: &Value| v.as_f64().filter(|x| x.is_finite());
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(items)) => items.iter().map(finite).collect::<Option<Vec<f64>>>().map(Some).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn count_windows_field(value: &Value) -> Result<Option<Vec<WindowSpec>>, OptionsError> {
+    let wrong_type = OptionsError::WrongType {
+        field: "count_windows",
+        expected: "1 to 32 arrays of [min, max] or [min, max, max_results] with min <= max",
+    };
+    let window = |v: &Value| match v {
+        Value::Array(bounds) if bounds.len() == 2 || bounds.len() == 3 => {
+            let bounds = bounds.iter().map(as_u32).collect::<Option<Vec<u32>>>()?;
+            let cap = bounds.get(2).copied().unwrap_or(0);
+            (bounds[0] <= bounds[1]).then_some((bounds[0], bounds[1], cap))
+        }
+        _ => None,
+    };
+    match value.get("count_windows") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(items)) if !items.is_empty() && items.len() <= MAX_COUNT_WINDOWS => {
+            items.iter().map(window).collect::<Option<Vec<_>>>().map(Some).ok_or(wrong_type)
+        }
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn score_field(value: &Value) -> Result<ScoreMode, OptionsError> {
+    let wrong_type = OptionsError::WrongType {
+        field: "score_results",
+        expected: "\"prefer_round\", \"prefer_organic\" or \"none\"",
+    };
+    match value.get("score_results") {
+        None | Some(Value::Null) => Ok(ScoreMode::None),
+        Some(Value::Str(s)) => ScoreMode::parse(s).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn objective_field(value: &Value) -> Result<Objective, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field: "objective", expected: "\"first\" or \"min_cost\"" };
+    match value.get("objective") {
+        None | Some(Value::Null) => Ok(Objective::First),
+        Some(Value::Str(s)) => Objective::parse(s).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn algorithm_field(value: &Value) -> Result<Algorithm, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field: "algorithm", expected: "\"auto\", \"mitm\" or \"bnb\"" };
+    match value.get("algorithm") {
+        None | Some(Value::Null) => Ok(Algorithm::Auto),
+        Some(Value::Str(s)) => Algorithm::parse(s).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn dedup_overflow_field(value: &Value) -> Result<DedupOverflow, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field: "dedup_overflow", expected: "\"allow_duplicates\" or \"pause\"" };
+    match value.get("dedup_overflow") {
+        None | Some(Value::Null) => Ok(DedupOverflow::AllowDuplicates),
+        Some(Value::Str(s)) => DedupOverflow::parse(s).ok_or(wrong_type),
+        Some(_) => Err(wrong_type),
+    }
+}
+
+fn adjustments_field(value: &Value) -> Result<Option<Vec<f64>>, OptionsError> {
+    let wrong_type = OptionsError::WrongType { field: "adjustments", expected: "1 to 16 finite numbers" };
+    match f64_array_field(value, "adjustments") {
+        Ok(Some(adjustments)) if adjustments.is_empty() || adjustments.len() > MAX_ADJUSTMENTS => Err(wrong_type),
+        Err(_) => Err(wrong_type),
+        parsed => parsed,
+    }
+}
+
+fn tag_field(value: &Value) -> Result<Option<String>, OptionsError> {
+    match value.get("tag") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Str(s)) if s.len() > MAX_TAG_LEN => Err(OptionsError::TagTooLong { max: MAX_TAG_LEN, actual: s.len() }),
+        Some(Value::Str(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(OptionsError::WrongType { field: "tag", expected: "string" }),
+    }
+}
+
+fn bool_field(value: &Value, field: &'static str) -> Result<bool, OptionsError> {
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(false),
+        Some(v) => v.as_bool().ok_or(OptionsError::WrongType { field, expected: "boolean" }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_defaults() {
+        let options = SearchOptions::parse(r#"{"target":12.5}"#).unwrap();
+        assert_eq!(options, SearchOptions {
+            target: 12.5,
+            min_count: 1,
+            max_count: 0,
+            max_results: 0,
+            include_indices: None,
+            scale: None,
+            check_uniqueness: false,
+            consistent_with_find_one: false,
+            row_ids: None,
+            count_windows: None,
+            init_budget_ms: None,
+            quick_phase: false,
+            score_results: ScoreMode::None,
+            precheck: None,
+            precheck_nodes: None,
+            paranoid: false,
+            prefer_small_count: false,
+            objective: Objective::First,
+            costs: None,
+            cost_nodes: None,
+            algorithm: Algorithm::Auto,
+            stream_results: false,
+            max_new_results_per_batch: None,
+            dedup_overflow: DedupOverflow::AllowDuplicates,
+            tag: None,
+            adjustments: None,
+            suppress_near_duplicates: None,
+            memory_limit_mb: None,
+            max_index_span: None,
+            refuse_if_active: false,
+            strict_target: false,
+            strict_inputs: false,
+            previously_matched: None,
+            previously_matched_values: None,
+            collapse_duplicates: None,
+            preferred_indices: None,
+            include_zeros: false,
+            max_entries: 0,
+            deterministic: false,
+        });
+        assert!(options.resolved_precheck(PRECHECK_MAX_N) && !options.resolved_precheck(PRECHECK_MAX_N + 1));
+        assert_eq!(options.resolved_max_count(7), 7);
+        assert_eq!(options.resolved_max_results(), MAX_RESULTS_CAP);
+        assert_eq!(options.resolved_max_entries(), DEFAULT_MAX_ENTRIES as usize);
+    }
+
+    #[test]
+    fn test_parse_all_fields() {
+        let json = r#"{"target":9,"min_count":2,"max_count":3,"max_results":50,"include_indices":[4,1],
+            "scale":2,"check_uniqueness":true,"consistent_with_find_one":true,"row_ids":[7,7],"quick_phase":true,
+            "score_results":"prefer_organic","precheck":false,"precheck_nodes":100,"paranoid":true,
+            "prefer_small_count":true,"objective":"min_cost","costs":[1.5,-2],"cost_nodes":9,"algorithm":"bnb",
+            "stream_results":true,"max_new_results_per_batch":500,"dedup_overflow":"pause","tag":"panel-2 · Σ","adjustments":[-2.5,0,2.5],
+            "suppress_near_duplicates":1,"memory_limit_mb":64,"max_index_span":30,"refuse_if_active":true,"strict_target":true,
+            "strict_inputs":true,"previously_matched":[3,0],"previously_matched_values":[12.5,12.5],"collapse_duplicates":false,"include_zeros":true,"max_entries":1000,
+            "preferred_indices":[5,2],"deterministic":true,"unknown":"ignored"}"#;
+        let options = SearchOptions::parse(json).unwrap();
+        assert_eq!(options.include_indices, Some(vec![4, 1]));
+        assert_eq!((options.min_count, options.max_count, options.max_results), (2, 3, 50));
+        assert_eq!(options.resolved_max_count(100), 3);
+        assert_eq!(options.resolved_max_results(), 50);
+        assert_eq!(options.scale, Some(2));
+        assert!(options.check_uniqueness);
+        assert!(options.consistent_with_find_one);
+        assert_eq!(options.row_ids, Some(vec![7, 7]));
+        assert!(options.quick_phase);
+        assert_eq!(options.score_results, ScoreMode::PreferOrganic);
+        assert_eq!((options.precheck, options.precheck_nodes), (Some(false), Some(100)));
+        assert!(!options.resolved_precheck(5));
+        assert!(options.paranoid);
+        assert!(options.prefer_small_count);
+        assert_eq!(options.objective, Objective::MinCost);
+        assert_eq!(options.algorithm, Algorithm::Bnb);
+        assert!(options.stream_results);
+        assert_eq!(options.max_new_results_per_batch, Some(500));
+        assert_eq!(options.dedup_overflow, DedupOverflow::Pause);
+        assert_eq!(options.tag.as_deref(), Some("panel-2 · Σ"));
+        assert_eq!(options.adjustments, Some(vec![-2.5, 0.0, 2.5]));
+        assert_eq!(options.suppress_near_duplicates, Some(1));
+        assert_eq!(options.memory_limit(), Some(64 << 20));
+        assert_eq!(options.max_index_span, Some(30));
+        assert!(options.refuse_if_active);
+        assert!(options.strict_target);
+        assert!(options.strict_inputs);
+        assert_eq!(options.previously_matched, Some(vec![3, 0]));
+        assert_eq!(options.previously_matched_values, Some(vec![12.5, 12.5]));
+        assert_eq!(options.collapse_duplicates, Some(false));
+        assert!(options.include_zeros);
+        assert_eq!(options.resolved_max_entries(), 1000);
+        assert_eq!(options.preferred_indices, Some(vec![5, 2]));
+        assert!(options.deterministic);
+        assert_eq!((options.costs.as_deref(), options.cost_nodes), (Some(&[1.5, -2.0][..]), Some(9)));
+        let with_precheck = SearchOptions { precheck: Some(true), ..options };
+        assert!(!with_precheck.resolved_precheck(5));
+        // deterministic refuses init_budget_ms, so it is read on its own
+        assert_eq!(SearchOptions::parse(r#"{"target":9,"init_budget_ms":5}"#).unwrap().init_budget_ms, Some(5));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_options() {
+        assert_eq!(SearchOptions::parse("{").unwrap_err().code(), "invalid_json");
+        assert_eq!(SearchOptions::parse("[]").unwrap_err().code(), "invalid_option");
+        assert_eq!(SearchOptions::parse("{}").unwrap_err(), OptionsError::Missing("target"));
+        assert_eq!(
+            SearchOptions::parse(r#"{"target":1,"max_count":-1}"#).unwrap_err(),
+            OptionsError::WrongType { field: "max_count", expected: "u32" },
+        );
+        assert_eq!(
+            SearchOptions::parse(r#"{"target":1,"max_results":4294967296}"#).unwrap_err(),
+            OptionsError::WrongType { field: "max_results", expected: "u32" },
+        );
+        assert_eq!(
+            SearchOptions::parse(r#"{"target":"5"}"#).unwrap_err(),
+            OptionsError::WrongType { field: "target", expected: "number" },
+        );
+        assert!(SearchOptions::parse(r#"{"target":1,"include_indices":[1.5]}"#).is_err());
+        assert!(SearchOptions::parse(r#"{"target":1,"check_uniqueness":1}"#).is_err());
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"score_results":"roundest"}"#).unwrap_err().code(), "invalid_option");
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"objective":"min_cost"}"#).unwrap_err(), OptionsError::Missing("costs"));
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"costs":[1]}"#).unwrap_err(), OptionsError::Missing("objective"));
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"objective":"max_cost","costs":[1]}"#).unwrap_err().code(), "invalid_option");
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"algorithm":"dp"}"#).unwrap_err().code(), "invalid_option");
+        assert_eq!(
+            SearchOptions::parse(r#"{"target":1,"algorithm":"mitm","consistent_with_find_one":true}"#).unwrap_err().code(),
+            "invalid_option",
+        );
+        assert_eq!(
+            SearchOptions::parse(r#"{"target":1,"deterministic":true,"init_budget_ms":5}"#).unwrap_err(),
+            OptionsError::WrongType { field: "init_budget_ms", expected: "absent with deterministic" },
+        );
+        assert!(SearchOptions::parse(r#"{"target":1,"deterministic":true}"#).unwrap().deterministic);
+        assert_eq!(SearchOptions::parse(r#"{"target":1,"tag":7}"#).unwrap_err().code(), "invalid_option");
+        for costs in [r#"["1"]"#, "[1e999]", "1"] {
+            let json = format!(r#"{{"target":1,"objective":"min_cost","costs":{}}}"#, costs);
+            assert_eq!(SearchOptions::parse(&json).unwrap_err().code(), "invalid_option", "{}", costs);
+        }
+        for adjustments in ["[]", "[1,null]", "2.5", r#"["2.5"]"#, "[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16]"] {
+            let json = format!(r#"{{"target":1,"adjustments":{}}}"#, adjustments);
+            assert_eq!(
+                SearchOptions::parse(&json).unwrap_err(),
+                OptionsError::WrongType { field: "adjustments", expected: "1 to 16 finite numbers" },
+                "{}", adjustments,
+            );
+        }
+        for windows in ["[]", "[[3,2]]", "[[1]]", "[[1,2,3,4]]", "[2,3]", "[[1,-2]]"] {
+            let json = format!(r#"{{"target":1,"count_windows":{}}}"#, windows);
+            assert_eq!(SearchOptions::parse(&json).unwrap_err().code(), "invalid_option", "{}", windows);
+        }
+    }
+
+    #[test]
+    fn test_parse_tag_length() {
+        // The cap counts bytes, so 128 two-byte characters just fit
+        let tag = "é".repeat(MAX_TAG_LEN / 2);
+        let json = format!(r#"{{"target":1,"tag":"{}"}}"#, tag);
+        assert_eq!(SearchOptions::parse(&json).unwrap().tag, Some(tag.clone()));
+        let json = format!(r#"{{"target":1,"tag":"{}x"}}"#, tag);
+        assert_eq!(SearchOptions::parse(&json).unwrap_err(), OptionsError::TagTooLong { max: MAX_TAG_LEN, actual: MAX_TAG_LEN + 1 });
+        let escaped = SearchOptions::parse(r#"{"target":1,"tag":"a\"b\u00e9"}"#).unwrap();
+        assert_eq!(escaped.tag.as_deref(), Some("a\"bé"));
+    }
+
+    #[test]
+    fn test_parse_count_windows() {
+        let options = SearchOptions::parse(r#"{"target":1,"max_results":40,"count_windows":[[2,2],[3,3,5],[4,6]]}"#).unwrap();
+        assert_eq!(options.count_windows, Some(vec![(2, 2, 0), (3, 3, 5), (4, 6, 0)]));
+        assert_eq!(options.resolved_count_windows(), Some(vec![(2, 2, 40), (3, 3, 5), (4, 6, 40)]));
+        assert_eq!(SearchOptions::parse(r#"{"target":1}"#).unwrap().resolved_count_windows(), None);
+    }
+}
@@ -0,0 +1,358 @@
+//! Two columns, debits and credits say, and a group of rows from each with the
+//! same total: offsetting entries between two accounts.
+//!
+//! The column with fewer usable rows is enumerated first: a DFS over its rows,
+//! ascending by value, collects every sum a combination within its count
+//! window reaches (inside the hinted band, when there is one) into a map keyed
+//! by sum, keeping the first combination that reached each. Siblings of equal
+//! value are walked once, as they reach the same sums. Each sum, nearest the
+//! hint first (else smallest first), is then the target of a batch search over
+//! the other column, held to that column's count window and one result; the
+//! first probe that finds one answers. Enumeration takes at most half of the
+//! node budget and each probe at most PROBE_NODES of what is left, so one hard
+//! sum cannot starve the rest; cancellation is checked between steps.
+
+use crate::batch::{BatchEntries, BatchSearchState, SortedView};
+use crate::control::SearchController;
+use crate::solver::{NumberEntry, SolveError};
+use crate::utils::StableHashMap;
+use std::rc::Rc;
+
+/// Default node budget for a whole call, a few seconds of search.
+pub const DEFAULT_PAIR_NODES: u64 = 20_000_000;
+
+/// Most distinct sums the enumeration collects before it stops.
+pub const MAX_PAIR_SUMS: usize = 1 << 16;
+
+/// Nodes one probe may take before it is given up.
+const PROBE_NODES: u64 = 1_000_000;
+
+/// Nodes per search_batch step, and between the enumeration's cancel checks.
+const STEP_NODES: u64 = 4096;
+
+/// The column that was enumerated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Side::A => "a",
+            Side::B => "b",
+        }
+    }
+}
+
+/// A combination of each column and the total they share.
+#[derive(Debug)]
+pub struct MatchingPair {
+    pub sum: u64,
+    pub a: Vec<NumberEntry>,
+    pub b: Vec<NumberEntry>,
+}
+
+/// What solve_matching_pair found.
+#[derive(Debug)]
+pub struct PairSearch {
+    pub pair: Option<MatchingPair>,
+    /// No sum was left out or probe given up before the answer, so a missing
+    /// pair proves there is none
+    pub complete: bool,
+    pub enumerated: Side,
+    /// Distinct sums collected
+    pub sums: usize,
+    /// Sums probed against the other column
+    pub probes: usize,
+    pub nodes_explored: u64,
+}
+
+/// Count window and rows of one column.
+#[derive(Clone, Copy)]
+pub struct Column<'a> {
+    pub entries: &'a [NumberEntry],
+    pub min_count: usize,
+    pub max_count: usize,
+}
+
+/// The search stopped short.
+enum Stop {
+    Cancelled,
+    Budget,
+}
+
+struct Walk<'a> {
+    controller: &'a dyn SearchController,
+    node_budget: u64,
+    nodes: u64,
+}
+
+impl Walk<'_> {
+    /// Charge `steps` nodes; Err once the budget up to `limit` is spent or the
+    /// controller says stop.
+    fn charge(&mut self, steps: u64, limit: u64) -> Result<(), Stop> {
+        self.nodes += steps;
+        if self.controller.should_stop(self.nodes).is_break() {
+            return Err(Stop::Cancelled);
+        }
+        if self.nodes >= limit.min(self.node_budget) {
+            return Err(Stop::Budget);
+        }
+        Ok(())
+    }
+
+    /// Sum -> first combination of `column` reaching it, for the sums in
+    /// [low, high]. Err(Budget) keeps what was collected in `sums`.
+    fn enumerate(
+        &mut self,
+        column: &Column,
+        (low, high): (u64, u64),
+        sums: &mut StableHashMap<u64, Vec<NumberEntry>>,
+    ) -> Result<(), Stop> {
+        let mut sorted = column.entries.to_vec();
+        sorted.sort_unstable_by_key(NumberEntry::value_order);
+        let limit = self.node_budget / 2;
+        let n = sorted.len();
+        // Positions in `sorted` of the rows picked, and their sum
+        let mut path: Vec<usize> = Vec::new();
+        let mut sum = 0u64;
+        let mut next = 0;
+        let mut unchecked = 0;
+        loop {
+            if next < n && path.len() < column.max_count && sum + sorted[next].value <= high {
+                unchecked += 1;
+                if unchecked == STEP_NODES {
+                    self.charge(unchecked, limit)?;
+                    unchecked = 0;
+                }
+                path.push(next);
+                sum += sorted[next].value;
+                next += 1;
+                if path.len() >= column.min_count && sum >= low && !sums.contains_key(&sum) {
+                    if sums.len() == MAX_PAIR_SUMS {
+                        return Err(Stop::Budget);
+                    }
+                    sums.insert(sum, path.iter().map(|&i| sorted[i].clone()).collect());
+                }
+                continue;
+            }
+            // Ascending values: nothing further in this frame fits either
+            let Some(last) = path.pop() else { break };
+            sum -= sorted[last].value;
+            next = last + 1;
+            while next < n && sorted[next].value == sorted[last].value {
+                next += 1;
+            }
+        }
+        self.nodes += unchecked;
+        if self.controller.should_stop(self.nodes).is_break() {
+            return Err(Stop::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// The first combination of the other column summing to `target`, over
+    /// `sorted` (ascending by value): Ok(None) when it has none, Err(Budget) when
+    /// the probe ran out first.
+    fn probe(&mut self, sorted: &Rc<Vec<NumberEntry>>, column: &Column, target: u64) -> Result<Option<Vec<NumberEntry>>, Stop> {
+        let len = sorted.partition_point(|e| e.value <= target);
+        let view = BatchEntries::Sorted(SortedView::new(Rc::clone(sorted), len));
+        let mut state = BatchSearchState::new_budgeted(view, target, column.min_count, column.max_count, 1, None);
+        state.set_stream_results();
+        let limit = self.nodes.saturating_add(PROBE_NODES);
+        loop {
+            let left = limit.min(self.node_budget).saturating_sub(self.nodes);
+            if left == 0 {
+                return Err(Stop::Budget);
+            }
+            let before = state.nodes_explored();
+            let batch = state.search_batch(left.min(STEP_NODES));
+            let spent = batch.nodes_explored - before;
+            if let Some(found) = batch.new_results.into_iter().next() {
+                self.nodes += spent;
+                return Ok(Some(found));
+            }
+            if batch.finished {
+                self.nodes += spent;
+                return Ok(None);
+            }
+            self.charge(spent, limit)?;
+        }
+    }
+}
+
+/// A combination of `a` and one of `b`, each within its count window, with the
+/// same total, in [hint - tolerance, hint + tolerance] when `hint` is given.
+/// Errors with InvalidBounds when a column's min_count > max_count and
+/// Cancelled when `controller` stops the search.
+pub fn solve_matching_pair(
+    a: Column,
+    b: Column,
+    hint: Option<u64>,
+    tolerance: u64,
+    controller: &dyn SearchController,
+    node_budget: u64,
+) -> Result<PairSearch, SolveError> {
+    for column in [&a, &b] {
+        if column.min_count > column.max_count {
+            return Err(SolveError::InvalidBounds { min_count: column.min_count, max_count: column.max_count });
+        }
+    }
+    let (enumerated, small, other) = if b.entries.len() < a.entries.len() { (Side::B, b, a) } else { (Side::A, a, b) };
+    let mut walk = Walk { controller, node_budget, nodes: 0 };
+
+    // The empty combination matches the other's trivially; a sum past the
+    // other column's total matches nothing
+    let other_total = other.entries.iter().fold(0u64, |total, e| total.saturating_add(e.value));
+    let band = match hint {
+        Some(hint) => (hint.saturating_sub(tolerance).max(1), hint.saturating_add(tolerance).min(other_total)),
+        None => (1, other_total),
+    };
+    let mut sums = StableHashMap::default();
+    let mut complete = match walk.enumerate(&small, band, &mut sums) {
+        Ok(()) => true,
+        Err(Stop::Budget) => false,
+        Err(Stop::Cancelled) => return Err(SolveError::Cancelled),
+    };
+
+    let mut order: Vec<u64> = sums.keys().copied().collect();
+    order.sort_unstable_by_key(|&sum| (hint.map_or(0, |hint| sum.abs_diff(hint)), sum));
+    let mut sorted = other.entries.to_vec();
+    sorted.sort_unstable_by_key(NumberEntry::value_order);
+    let sorted = Rc::new(sorted);
+
+    let mut result = PairSearch { pair: None, complete, enumerated, sums: sums.len(), probes: 0, nodes_explored: 0 };
+    for sum in order {
+        if walk.nodes >= walk.node_budget {
+            complete = false;
+            break;
+        }
+        result.probes += 1;
+        match walk.probe(&sorted, &other, sum) {
+            Ok(Some(found)) => {
+                let mine = sums.remove(&sum).unwrap();
+                let (a, b) = if enumerated == Side::A { (mine, found) } else { (found, mine) };
+                result.pair = Some(MatchingPair { sum, a, b });
+                break;
+            }
+            Ok(None) => {}
+            Err(Stop::Budget) => complete = false,
+            Err(Stop::Cancelled) => return Err(SolveError::Cancelled),
+        }
+    }
+    result.complete = complete;
+    result.nodes_explored = walk.nodes;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::OriginalIndex;
+    use std::sync::atomic::AtomicBool;
+
+    fn entries(values: &[u64]) -> Vec<NumberEntry> {
+        values.iter().enumerate().map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) }).collect()
+    }
+
+    fn rows(combination: &[NumberEntry]) -> Vec<usize> {
+        let mut rows: Vec<usize> = combination.iter().map(|e| e.original_index.as_usize()).collect();
+        rows.sort_unstable();
+        rows
+    }
+
+    fn solve(a: &[u64], b: &[u64], counts: (usize, usize), hint: Option<u64>, tolerance: u64) -> PairSearch {
+        let (a, b) = (entries(a), entries(b));
+        let column = |entries| Column { entries, min_count: counts.0, max_count: counts.1 };
+        solve_matching_pair(column(&a), column(&b), hint, tolerance, &AtomicBool::new(false), DEFAULT_PAIR_NODES).unwrap()
+    }
+
+    /// Every (mask of a, mask of b) with equal non-zero sums.
+    fn brute_force(a: &[u64], b: &[u64]) -> Vec<(u64, u64)> {
+        let sum = |values: &[u64], mask: u64| -> u64 {
+            values.iter().enumerate().filter(|&(i, _)| mask & 1 << i != 0).map(|(_, v)| v).sum()
+        };
+        let mut pairs = Vec::new();
+        for ma in 1..1u64 << a.len() {
+            for mb in 1..1u64 << b.len() {
+                if sum(a, ma) == sum(b, mb) {
+                    pairs.push((ma, mb));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_only_cross_pair() {
+        let a = [100, 210, 370];
+        let b = [55, 125, 255, 5];
+        // 100 + 210 = 55 + 255 is the one total both columns reach
+        assert_eq!(brute_force(&a, &b), [(0b011, 0b0101)]);
+        let search = solve(&a, &b, (1, 4), None, 0);
+        let pair = search.pair.unwrap();
+        assert_eq!(pair.sum, 310);
+        assert_eq!(rows(&pair.a), [0, 1]);
+        assert_eq!(rows(&pair.b), [0, 2]);
+        assert_eq!(search.enumerated, Side::A);
+        // 470, 580 and 680 are past b's total, so never collected
+        assert_eq!(search.sums, 4);
+
+        // Either column may be the one enumerated; the answer keeps its sides
+        let swapped = solve(&b, &a, (1, 4), None, 0);
+        assert_eq!(swapped.enumerated, Side::B);
+        let pair = swapped.pair.unwrap();
+        assert_eq!((rows(&pair.a), rows(&pair.b)), (vec![0, 2], vec![0, 1]));
+    }
+
+    #[test]
+    fn test_hint_and_count_windows() {
+        let a = [10, 20, 30];
+        let b = [10, 20, 30, 60];
+        assert_eq!(solve(&a, &b, (1, 3), None, 0).pair.unwrap().sum, 10);
+        assert_eq!(solve(&a, &b, (1, 3), Some(50), 0).pair.unwrap().sum, 50);
+        // Nearest the hint within the band, whichever side of it
+        assert_eq!(solve(&a, &b, (1, 3), Some(57), 5).pair.unwrap().sum, 60);
+        let outside = solve(&a, &b, (1, 3), Some(100), 30);
+        assert!(outside.pair.is_none() && outside.complete);
+
+        let pair = solve(&a, &b, (2, 2), None, 0).pair.unwrap();
+        assert_eq!(pair.sum, 30);
+        assert_eq!((pair.a.len(), pair.b.len()), (2, 2));
+    }
+
+    #[test]
+    fn test_not_found_and_budget() {
+        // Even totals on one side, odd on the other
+        let search = solve(&[2, 4, 8], &[3], (1, 3), None, 0);
+        assert!(search.pair.is_none() && search.complete);
+        // Only 2 is within b's total of 3
+        assert_eq!((search.sums, search.probes), (1, 1));
+
+        let evens: Vec<u64> = (1..=24).map(|v| v * 2).collect();
+        let odds: Vec<u64> = (0..30).map(|v| v * 2 + 1).collect();
+        let column = |entries| Column { entries, min_count: 1, max_count: 1 };
+        let (a, b) = (entries(&evens), entries(&odds));
+        let tight = solve_matching_pair(column(&a), column(&b), None, 0, &AtomicBool::new(false), 10).unwrap();
+        assert!(tight.pair.is_none() && !tight.complete);
+        assert!(tight.nodes_explored <= 10 + STEP_NODES);
+    }
+
+    #[test]
+    fn test_bounds_and_cancellation() {
+        let (a, b) = (entries(&[1, 2]), entries(&[3]));
+        let never = AtomicBool::new(false);
+        let column = |entries, min_count, max_count| Column { entries, min_count, max_count };
+        assert_eq!(
+            solve_matching_pair(column(&a, 1, 2), column(&b, 2, 1), None, 0, &never, 100).unwrap_err(),
+            SolveError::InvalidBounds { min_count: 2, max_count: 1 },
+        );
+        let cancelled = AtomicBool::new(true);
+        assert_eq!(
+            solve_matching_pair(column(&a, 1, 2), column(&b, 1, 1), None, 0, &cancelled, 100).unwrap_err(),
+            SolveError::Cancelled,
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Scores for emitted combinations by how round their amounts look.
+//!
+//! Some users distrust a match made of suspiciously round amounts (1000.00,
+//! 2500.00), others distrust one made of organic amounts. With `score_results`
+//! set, every combination a payload carries gets a score in [0, 1], higher
+//! meaning a better fit for the preference. Scoring happens when a payload is
+//! built and never affects the search.
+//!
+//! Two signals, both in [0, 1]: roundness, the mean count of trailing decimal
+//! zeros of the scaled values (so at scale 2, 1000.00 has five and 12.30 one),
+//! capped at MAX_ROUND_ZEROS; and diversity, the share of distinct values. A
+//! round combination is round and repetitive; an organic one is neither.
+
+/// Trailing zeros past this count as this many, so 10^12 is no rounder than 10^6
+pub const MAX_ROUND_ZEROS: u32 = 6;
+
+/// Which combinations score high.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScoreMode {
+    /// No scores in payloads
+    #[default]
+    None,
+    PreferRound,
+    PreferOrganic,
+}
+
+impl ScoreMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "none" => Some(ScoreMode::None),
+            "prefer_round" => Some(ScoreMode::PreferRound),
+            "prefer_organic" => Some(ScoreMode::PreferOrganic),
+            _ => None,
+        }
+    }
+
+    /// The score of a combination with these scaled values; None when not scoring.
+    pub fn score(self, values: impl IntoIterator<Item = u64>) -> Option<f64> {
+        match self {
+            ScoreMode::None => None,
+            ScoreMode::PreferRound => Some(round_score(values)),
+            ScoreMode::PreferOrganic => Some(1.0 - round_score(values)),
+        }
+    }
+}
+
+fn trailing_zeros(mut value: u64) -> u32 {
+    let mut zeros = 0;
+    while value != 0 && value.is_multiple_of(10) && zeros < MAX_ROUND_ZEROS {
+        value /= 10;
+        zeros += 1;
+    }
+    zeros
+}
+
+/// Mean capped trailing zeros, as a share of MAX_ROUND_ZEROS.
+pub fn roundness(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let zeros: u32 = values.iter().map(|&v| trailing_zeros(v)).sum();
+    f64::from(zeros) / (values.len() as f64 * f64::from(MAX_ROUND_ZEROS))
+}
+
+/// Distinct values as a share of all values; 1 when there are none.
+pub fn diversity(values: &[u64]) -> f64 {
+    if values.is_empty() {
+        return 1.0;
+    }
+    let mut distinct = values.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    distinct.len() as f64 / values.len() as f64
+}
+
+/// Round and repetitive scores 1, organic and all distinct scores 0.
+fn round_score(values: impl IntoIterator<Item = u64>) -> f64 {
+    let values: Vec<u64> = values.into_iter().collect();
+    (roundness(&values) + 1.0 - diversity(&values)) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundness_counts_capped_trailing_zeros() {
+        assert_eq!(roundness(&[1234, 5678]), 0.0);
+        // 1000.00 and 2500.00 in cents: five and four zeros
+        assert_eq!(roundness(&[100_000, 250_000]), 9.0 / 12.0);
+        assert_eq!(roundness(&[1_000_000_000_000]), 1.0);
+        assert_eq!(roundness(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_diversity_is_share_of_distinct_values() {
+        assert_eq!(diversity(&[5, 5, 5, 5]), 0.25);
+        assert_eq!(diversity(&[5, 6, 5, 7]), 0.75);
+        assert_eq!(diversity(&[1, 2, 3]), 1.0);
+    }
+
+    #[test]
+    fn test_modes_rank_opposite_ways() {
+        let round = [100_000u64, 100_000, 250_000];
+        let organic = [123_457u64, 98_213, 4_411];
+        let score = |mode: ScoreMode, values: &[u64]| mode.score(values.iter().copied()).unwrap();
+
+        assert!(score(ScoreMode::PreferRound, &round) > score(ScoreMode::PreferRound, &organic));
+        assert!(score(ScoreMode::PreferOrganic, &organic) > score(ScoreMode::PreferOrganic, &round));
+        assert_eq!(score(ScoreMode::PreferRound, &organic), 0.0);
+        assert_eq!(score(ScoreMode::PreferOrganic, &organic), 1.0);
+        for values in [&round[..], &organic[..]] {
+            let sum = score(ScoreMode::PreferRound, values) + score(ScoreMode::PreferOrganic, values);
+            assert!((sum - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(ScoreMode::None.score(round), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ScoreMode::parse("none"), Some(ScoreMode::None));
+        assert_eq!(ScoreMode::parse("prefer_round"), Some(ScoreMode::PreferRound));
+        assert_eq!(ScoreMode::parse("prefer_organic"), Some(ScoreMode::PreferOrganic));
+        assert_eq!(ScoreMode::parse("roundest"), None);
+    }
+}
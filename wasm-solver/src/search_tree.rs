@@ -0,0 +1,250 @@
+//! The tree a batch search walked in its first nodes, for teaching and for
+//! drawing branch and bound: every row picked, every sibling skipped, and every
+//! prune with the bound values that cut it, as nodes with parent pointers.
+//!
+//! Strictly opt-in and capped at MAX_TREE_NODES; a search without it pays one
+//! None check per decision. Nodes name rows by their index into the search's
+//! sorted entries, so a path is the indices up the parent chain. Parents are
+//! only known along the path being walked, so recording stops, as truncated,
+//! once the walk starts over elsewhere: set_max_count reopening the paths it
+//! cut, or a smallest-count-first search moving on to the next count.
+
+/// Most nodes a tree may hold, root included.
+pub const MAX_TREE_NODES: usize = 10_000;
+
+/// Why a row and every later sibling were not tried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prune {
+    /// The row is above what is left of the target
+    TooLarge { value: u64, remaining: u64 },
+    /// The row and all after it sum to less than what is left
+    SuffixShort { suffix_sum: u64, remaining: u64 },
+    /// Fewer rows from here on than min_count still needs
+    TooFewLeft { left: usize, needed: usize },
+    /// One more row equal to the target, reported through the first
+    DuplicateTarget,
+}
+
+/// Why a row alone was passed over; later siblings may still be tried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Skip {
+    /// Shares a row id with a row on the path
+    RowTaken,
+    /// Too far, by original index, from a row on the path
+    OutsideSpan,
+    /// Completes a solution no open count window wants, or one already returned
+    NotWanted,
+    /// Left to another shard of a split search
+    OtherShard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Root,
+    /// Picked, and the search went deeper
+    Branch,
+    /// Picked, completing a solution
+    Solution,
+    /// Picked, but no deeper: at max_count, or no pick of exactly the rows still
+    /// needed can make up the rest (`at_max_count` false)
+    Cut { at_max_count: bool },
+    Skip(Skip),
+    Prune(Prune),
+    /// The frame's last one or two picks, found by one scan (see LastPicks):
+    /// the nodes it was charged and the solutions it found
+    Scan { steps: u64, found: usize },
+}
+
+impl Decision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Decision::Root => "root",
+            Decision::Branch => "branch",
+            Decision::Solution => "solution",
+            Decision::Cut { .. } => "cut",
+            Decision::Skip(_) => "skip",
+            Decision::Prune(_) => "prune",
+            Decision::Scan { .. } => "scan",
+        }
+    }
+}
+
+impl Skip {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Skip::RowTaken => "row_taken",
+            Skip::OutsideSpan => "outside_span",
+            Skip::NotWanted => "not_wanted",
+            Skip::OtherShard => "other_shard",
+        }
+    }
+}
+
+impl Prune {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Prune::TooLarge { .. } => "too_large",
+            Prune::SuffixShort { .. } => "suffix_short",
+            Prune::TooFewLeft { .. } => "too_few_left",
+            Prune::DuplicateTarget => "duplicate_target",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeNode {
+    /// None for the root only
+    pub parent: Option<u32>,
+    /// Index into the sorted entries of the row decided on; None for the root
+    /// and for a scan
+    pub index: Option<usize>,
+    /// The root is at 0, its children at 1, and so on
+    pub depth: usize,
+    /// Sum of the rows on the path above this one
+    pub path_sum: u64,
+    pub decision: Decision,
+}
+
+pub struct SearchTree {
+    max_nodes: usize,
+    pub nodes: Vec<TreeNode>,
+    /// The cap was reached or the walk started over; nodes after that are missing
+    pub truncated: bool,
+    /// Node ids of the rows on the current path, by depth
+    path_ids: Vec<u32>,
+}
+
+impl SearchTree {
+    /// `max_nodes` is at least 1 (the root) and at most MAX_TREE_NODES.
+    pub fn new(max_nodes: usize) -> Self {
+        debug_assert!((1..=MAX_TREE_NODES).contains(&max_nodes));
+        let root = TreeNode { parent: None, index: None, depth: 0, path_sum: 0, decision: Decision::Root };
+        SearchTree { max_nodes, nodes: vec![root], truncated: false, path_ids: Vec::new() }
+    }
+
+    pub fn max_nodes(&self) -> usize {
+        self.max_nodes
+    }
+
+    /// Record a decision made by the frame `depth` rows deep, whose path sums to
+    /// `path_sum`. A branch becomes the parent of what that frame's child decides.
+    pub fn record(&mut self, depth: usize, index: Option<usize>, path_sum: u64, decision: Decision) {
+        if self.truncated {
+            return;
+        }
+        if self.nodes.len() >= self.max_nodes {
+            self.truncated = true;
+            return;
+        }
+        let parent = if depth == 0 { 0 } else { self.path_ids[depth - 1] };
+        let id = self.nodes.len() as u32;
+        self.nodes.push(TreeNode { parent: Some(parent), index, depth: depth + 1, path_sum, decision });
+        if decision == Decision::Branch {
+            self.path_ids.truncate(depth);
+            self.path_ids.push(id);
+        }
+    }
+
+    /// The walk is leaving the path it was on for one the tree has no parents for.
+    pub fn stop(&mut self) {
+        self.truncated = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::BatchSearchState;
+    use crate::solver::{NumberEntry, OriginalIndex};
+
+    fn entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate().map(|(original_index, &value)| NumberEntry { value, original_index: OriginalIndex::new(original_index) }).collect()
+    }
+
+    /// Every node hangs off an earlier one at the depth below it, the bounds it
+    /// reports are what the sorted values give, and solutions sum to the target.
+    fn assert_consistent(tree: &SearchTree, sorted: &[u64], target: u64, min_count: usize) {
+        assert_eq!(tree.nodes[0].decision, Decision::Root);
+        for (id, node) in tree.nodes.iter().enumerate().skip(1) {
+            let parent = &tree.nodes[node.parent.unwrap() as usize];
+            assert!((node.parent.unwrap() as usize) < id);
+            assert_eq!(parent.decision, if parent.depth == 0 { Decision::Root } else { Decision::Branch });
+            assert_eq!(node.depth, parent.depth + 1);
+            let parent_sum = parent.path_sum + parent.index.map_or(0, |i| sorted[i]);
+            assert_eq!(node.path_sum, parent_sum, "node {}", id);
+            let remaining = target - node.path_sum;
+            let Some(i) = node.index else {
+                assert!(matches!(node.decision, Decision::Scan { .. }));
+                continue;
+            };
+            // A row is only decided on after the rows before it on the path
+            if let Some(above) = parent.index {
+                assert!(i > above);
+            }
+            match node.decision {
+                Decision::Solution => assert_eq!(sorted[i], remaining),
+                Decision::Branch | Decision::Cut { .. } => assert!(sorted[i] <= remaining),
+                Decision::Prune(Prune::TooLarge { value, remaining: r }) => {
+                    assert_eq!((value, r), (sorted[i], remaining));
+                    assert!(value > r);
+                }
+                Decision::Prune(Prune::SuffixShort { suffix_sum, remaining: r }) => {
+                    assert_eq!((suffix_sum, r), (sorted[i..].iter().sum(), remaining));
+                    assert!(suffix_sum < r);
+                }
+                Decision::Prune(Prune::TooFewLeft { left, needed }) => {
+                    assert_eq!((left, needed), (sorted.len() - i, min_count.saturating_sub(node.depth - 1)));
+                    assert!(left < needed);
+                }
+                other => panic!("unexpected {:?} at node {}", other, id),
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_tree_is_consistent() {
+        let nums = [3, 9, 4, 7, 1, 12, 6, 5, 8, 2];
+        let mut sorted = nums.to_vec();
+        sorted.sort_unstable();
+        let mut seen = std::collections::BTreeSet::new();
+        for (target, min_count, max_count) in [(20, 1, 10), (20, 4, 6), (15, 3, 3), (100, 1, 4)] {
+            let mut state = BatchSearchState::new(&entries(&nums), target, min_count, max_count, 1000);
+            assert!(state.set_search_tree(MAX_TREE_NODES));
+            while !state.search_batch(7).finished {}
+            let tree = state.search_tree().unwrap();
+            assert!(!tree.truncated);
+            assert_consistent(tree, &sorted, target, min_count);
+            seen.extend(tree.nodes.iter().map(|node| match node.decision {
+                Decision::Prune(prune) => prune.as_str(),
+                decision => decision.as_str(),
+            }));
+            let solutions = tree.nodes.iter().filter(|node| node.decision == Decision::Solution).count();
+            let scanned: usize = tree.nodes.iter()
+                .map(|node| if let Decision::Scan { found, .. } = node.decision { found } else { 0 })
+                .sum();
+            assert_eq!(solutions + scanned, state.all_results().len(), "{} [{}, {}]", target, min_count, max_count);
+        }
+        let expected = ["branch", "cut", "root", "scan", "solution", "suffix_short", "too_few_left", "too_large"];
+        assert!(expected.iter().all(|d| seen.contains(d)), "{:?}", seen);
+    }
+
+    #[test]
+    fn test_search_tree_is_capped_and_opt_in() {
+        let nums: Vec<u64> = (1..=30).collect();
+        let mut state = BatchSearchState::new(&entries(&nums), 40, 1, 30, 100_000);
+        assert!(!state.set_search_tree(0));
+        assert!(!state.set_search_tree(MAX_TREE_NODES + 1));
+        assert!(state.set_search_tree(50));
+        state.search_batch(1000);
+        let tree = state.search_tree().unwrap();
+        assert_eq!(tree.nodes.len(), 50);
+        assert!(tree.truncated);
+        assert_consistent(tree, &nums, 40, 1);
+
+        // Off unless asked for, and only before the search starts
+        let mut state = BatchSearchState::new(&entries(&nums), 40, 1, 30, 100_000);
+        state.search_batch(10);
+        assert!(state.search_tree().is_none());
+        assert!(!state.set_search_tree(50));
+    }
+}
@@ -0,0 +1,199 @@
+//! Embedded fixtures run by `self_test()`, so a deployment can check that a
+//! fresh build actually solves things without shipping a dataset.
+//!
+//! Every fixture is small enough that the whole run stays in the low
+//! milliseconds; the details are meant to be read in a remote bug report.
+
+use crate::batch::BatchSearchState;
+use crate::solver::{
+    DEFAULT_HYBRID_BB_BUDGET, NumberEntry, OriginalIndex, SolverConfig, SolveError,
+    solve_all_combinations, solve_subset_sum_with_stats,
+};
+use crate::utils::SplitMix64;
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicBool;
+
+/// How one fixture went.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaseOutcome {
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was observed; on failure, also what was expected
+    pub detail: String,
+}
+
+/// A fixture: Ok(what it saw) or Err(what went wrong).
+type Case = fn() -> Result<String, String>;
+
+/// Run every fixture, in a fixed order.
+pub fn run() -> Vec<CaseOutcome> {
+    let cases: [(&'static str, Case); 5] = [
+        ("mitm_find_one", mitm_find_one),
+        ("bnb_find_one", bnb_find_one),
+        ("find_all", find_all),
+        ("batch_cycle", batch_cycle),
+        ("cancellation", cancellation),
+    ];
+    cases.iter()
+        .map(|&(name, case)| {
+            let (passed, detail) = match case() {
+                Ok(detail) => (true, detail),
+                Err(detail) => (false, detail),
+            };
+            CaseOutcome { name, passed, detail }
+        })
+        .collect()
+}
+
+fn entries(values: &[u64]) -> Vec<NumberEntry> {
+    values.iter().enumerate()
+        .map(|(i, &value)| NumberEntry { value, original_index: OriginalIndex::new(i) })
+        .collect()
+}
+
+fn config(target: u64, max_count: usize, cancelled: &AtomicBool) -> SolverConfig<'_> {
+    SolverConfig {
+        target,
+        min_count: 1,
+        max_count,
+        controller: cancelled,
+        hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+        row_ids: None,
+        max_index_span: None,
+        memory_limit: None,
+    }
+}
+
+/// 24 seeded values in 1..=1,000,000; the target is the sum of every third one.
+fn mitm_values() -> (Vec<u64>, u64) {
+    let mut rng = SplitMix64::new(1);
+    let values: Vec<u64> = (0..24).map(|_| 1 + rng.below(1_000_000)).collect();
+    let target = values.iter().step_by(3).sum();
+    (values, target)
+}
+
+/// Find one solution and check it, expecting `algorithm` to have produced it.
+fn find_one(values: &[u64], target: u64, algorithm: &str) -> Result<String, String> {
+    let not_cancelled = AtomicBool::new(false);
+    let (result, stats) = solve_subset_sum_with_stats(&entries(values), &config(target, values.len(), &not_cancelled));
+    let phases: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+    let nodes: u64 = stats.phases.iter().map(|p| p.nodes).sum();
+    let combo = match result {
+        Ok(Some(combo)) => combo,
+        other => return Err(format!("expected a solution for target {}, got {:?} (phases {:?})", target, other, phases)),
+    };
+    let sum: u64 = combo.iter().map(|e| e.value).sum();
+    if sum != target || combo.iter().any(|e| values.get(e.original_index.as_usize()) != Some(&e.value)) {
+        return Err(format!("bad solution {:?}: sums to {}, target {}", combo, sum, target));
+    }
+    if phases.last() != Some(&algorithm) {
+        return Err(format!("expected {} to find it, phases were {:?}", algorithm, phases));
+    }
+    Ok(format!("{} of {} entries via {} in {} nodes", combo.len(), values.len(), algorithm, nodes))
+}
+
+fn mitm_find_one() -> Result<String, String> {
+    let (values, target) = mitm_values();
+    find_one(&values, target, "mitm")
+}
+
+/// 80 entries: past the hybrid range, so B&B alone.
+fn bnb_find_one() -> Result<String, String> {
+    let values: Vec<u64> = (1..=80).map(|v| v * 37 % 101 + 1).collect();
+    find_one(&values, 1234, "bnb")
+}
+
+/// {1..10} has exactly 20 subsets summing to 15.
+const FIND_ALL_EXPECTED: usize = 20;
+
+fn find_all_fixture() -> (Vec<NumberEntry>, BTreeSet<Vec<usize>>) {
+    let entries = entries(&(1..=10).collect::<Vec<u64>>());
+    let not_cancelled = AtomicBool::new(false);
+    let all = solve_all_combinations(&entries, &config(15, 10, &not_cancelled), usize::MAX);
+    (entries, index_sets(&all.results))
+}
+
+fn index_sets(combos: &[Vec<NumberEntry>]) -> BTreeSet<Vec<usize>> {
+    combos.iter()
+        .map(|combo| {
+            let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+            indices.sort_unstable();
+            indices
+        })
+        .collect()
+}
+
+fn find_all() -> Result<String, String> {
+    let (entries, all) = find_all_fixture();
+    let bad = all.iter().find(|combo| combo.iter().map(|&i| entries[i].value).sum::<u64>() != 15);
+    match (all.len(), bad) {
+        (_, Some(combo)) => Err(format!("combination {:?} does not sum to 15", combo)),
+        (FIND_ALL_EXPECTED, None) => Ok(format!("{} combinations of {{1..10}} summing to 15", FIND_ALL_EXPECTED)),
+        (found, None) => Err(format!("expected {} combinations summing to 15, found {}", FIND_ALL_EXPECTED, found)),
+    }
+}
+
+/// The find_all fixture through the resumable batch search, in small uneven
+/// batches, against the recursive result.
+fn batch_cycle() -> Result<String, String> {
+    let (entries, expected) = find_all_fixture();
+    let mut state = BatchSearchState::new(&entries, 15, 1, 10, usize::MAX);
+    let mut collected = Vec::new();
+    let mut batches = 0;
+    for &budget in [1u64, 7, 64].iter().cycle() {
+        let result = state.search_batch(budget);
+        batches += 1;
+        collected.extend(result.new_results);
+        if result.finished {
+            break;
+        }
+        if batches > 10_000 {
+            return Err(format!("not finished after {} batches ({} nodes)", batches, result.nodes_explored));
+        }
+    }
+    let found = index_sets(&collected);
+    if found != expected || collected.len() != expected.len() {
+        return Err(format!(
+            "batch found {} combinations ({} distinct), recursive search {}; first difference {:?}",
+            collected.len(), found.len(), expected.len(), found.symmetric_difference(&expected).next(),
+        ));
+    }
+    Ok(format!("{} combinations in {} batches, {} nodes", collected.len(), batches, state.nodes_explored()))
+}
+
+/// A pre-set cancel flag stops both MITM and B&B rather than running to the end.
+fn cancellation() -> Result<String, String> {
+    let cancelled = AtomicBool::new(true);
+    let (values, target) = mitm_values();
+    // Even values, odd target: B&B has no solution to stop at and must notice the flag
+    let even: Vec<u64> = (1..=80).map(|v| v * 2).collect();
+    for (name, values, target) in [("mitm", &values, target), ("bnb", &even, 801)] {
+        let (result, _) = solve_subset_sum_with_stats(&entries(values), &config(target, values.len(), &cancelled));
+        if !matches!(result, Err(SolveError::Cancelled)) {
+            return Err(format!("{} with the cancel flag set returned {:?}, expected Cancelled", name, result));
+        }
+    }
+    Ok("mitm and bnb both stopped on the cancel flag".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_cases_pass() {
+        let cases = run();
+        assert_eq!(cases.len(), 5);
+        for case in cases {
+            assert!(case.passed, "{}: {}", case.name, case.detail);
+        }
+    }
+
+    #[test]
+    fn test_failure_detail_names_expectation() {
+        let err = find_one(&[2, 4, 6], 5, "mitm").unwrap_err();
+        assert!(err.starts_with("expected a solution for target 5"), "{}", err);
+        let err = find_one(&[1, 2, 3, 4], 9, "bnb").unwrap_err();
+        assert!(err.contains(r#"expected bnb to find it, phases were ["mitm"]"#), "{}", err);
+    }
+}
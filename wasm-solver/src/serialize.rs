@@ -0,0 +1,2364 @@
+//! Every payload handed back to JS, as typed structs.
+//!
+//! Each payload builds a small `Json` tree; the same tree is written out as a
+//! string (what the current API returns) or converted to a JsValue. Field order
+//! is part of the output, so tests below pin exact strings.
+
+use std::fmt::Write;
+use wasm_bindgen::JsValue;
+
+use crate::batch::{BatchResult, MaxCountChange, PathSnapshot, SearchInfo, Stage, TargetChange, TerminationReason, UsageCount};
+use crate::canonical::ParseError;
+use crate::capabilities::{self, API_VERSION};
+use crate::csv::InvalidDelimiter;
+use crate::dataset::DatasetSummary;
+use crate::export::{self, DecodeError, StateHeader};
+use crate::session::Session;
+use crate::mincost::CostSummary;
+use crate::multi::MultiTargetResult;
+use crate::pairs::PairSearch;
+use crate::input::{FractionalInputs, RowSumError, SkipReason, SkippedIndex, TargetNotRepresentable, TargetOverflow, TargetRounding};
+use crate::options::OptionsError;
+use crate::score::ScoreMode;
+use crate::search_tree::{Decision, Prune, SearchTree};
+use crate::selftest::CaseOutcome;
+use crate::utils::OutOfMemory;
+use crate::validate::InvalidInput;
+use crate::verify::{ConsistencyFailure, EXHAUSTIVE_MAX_ROWS, ExhaustiveCheck, ExtraProblem, Inconsistency};
+use crate::solver::{combination_id, NearMiss, NearSums, NumberEntry, OriginalIndex, Prognosis, SolveError, SolveOutcome, SolverStats, TargetAnalysis, Uniqueness, UniquenessCheck};
+
+/// A JSON value. Objects keep insertion order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    U64(u64),
+    U128(u128),
+    /// Written with Rust's shortest round-trip formatting (5.0 -> 5)
+    F64(f64),
+    /// Written with a fixed number of decimals
+    Fixed(f64, usize),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    pub fn str(s: &str) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    pub fn usize(v: usize) -> Json {
+        Json::U64(v as u64)
+    }
+
+    /// A row's index into the raw input, the only kind of index payloads carry
+    pub fn index(i: OriginalIndex) -> Json {
+        Json::U64(i.get().into())
+    }
+
+    pub fn opt_u64(v: Option<u64>) -> Json {
+        v.map_or(Json::Null, Json::U64)
+    }
+
+    /// 64-bit ids and fingerprints go out as 16 hex digits: JS numbers can't hold them
+    pub fn hex(v: u64) -> Json {
+        Json::Str(format!("{:016x}", v))
+    }
+
+    pub fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::U64(v) => write_u64(*v, out),
+            Json::U128(v) => write_u128(*v, out),
+            Json::F64(v) if v.is_finite() => { let _ = write!(out, "{}", v); }
+            Json::Fixed(v, decimals) if v.is_finite() => { let _ = write!(out, "{:.*}", decimals, v); }
+            Json::F64(_) | Json::Fixed(..) => out.push_str("null"),
+            Json::Str(s) => write_string(s, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_escaped(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    /// Rough output size, so a payload is written into one allocation in the
+    /// common case. Numbers count as 10 bytes; growing past it is fine.
+    pub fn estimated_len(&self) -> usize {
+        match self {
+            Json::Null | Json::Bool(_) => 5,
+            Json::U64(_) | Json::F64(_) | Json::Fixed(..) => 10,
+            Json::U128(_) => 20,
+            Json::Str(s) => s.len().min(MAX_STRING_BYTES) + 2,
+            Json::Array(items) => items.iter().map(|item| item.estimated_len() + 1).sum::<usize>() + 2,
+            Json::Object(fields) => {
+                fields.iter().map(|(key, value)| key.len() + 4 + value.estimated_len()).sum::<usize>() + 2
+            }
+        }
+    }
+}
+
+/// Integers are most of a result-dense payload; writing digits directly skips
+/// the formatting machinery.
+fn write_u64(mut v: u64, out: &mut String) {
+    let mut buf = [0u8; 20];
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (v % 10) as u8;
+        v /= 10;
+        if v == 0 {
+            break;
+        }
+    }
+    out.push_str(std::str::from_utf8(&buf[pos..]).unwrap_or_default());
+}
+
+fn write_u128(v: u128, out: &mut String) {
+    match u64::try_from(v) {
+        Ok(v) => write_u64(v, out),
+        Err(_) => { let _ = write!(out, "{}", v); }
+    }
+}
+
+/// Longest string value a payload carries, in UTF-8 bytes before escaping.
+/// Ours are far shorter; this bounds what came from outside, such as the crate
+/// version in a foreign export or a long message quoted back.
+pub const MAX_STRING_BYTES: usize = 4096;
+
+/// What a string cut at MAX_STRING_BYTES ends in.
+pub const TRUNCATION_MARKER: &str = "…[truncated]";
+
+/// A string value: escaped, and cut at a character boundary to end in
+/// TRUNCATION_MARKER within MAX_STRING_BYTES when longer. Rust strings are
+/// valid UTF-8 by construction; JS strings with lone surrogates reach us with
+/// U+FFFD in their place, as wasm-bindgen decodes them.
+fn write_string(s: &str, out: &mut String) {
+    if s.len() <= MAX_STRING_BYTES {
+        return write_escaped(s, out);
+    }
+    let end = (0..=MAX_STRING_BYTES - TRUNCATION_MARKER.len()).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0);
+    write_escaped(&format!("{}{}", &s[..end], TRUNCATION_MARKER), out);
+}
+
+/// Escape `s` as a JSON string: quotes, backslashes and control characters.
+pub fn write_escaped(s: &str, out: &mut String) {
+    out.push('"');
+    // Keys and ids never need escaping
+    if s.bytes().all(|b| b >= 0x20 && b != b'"' && b != b'\\') {
+        out.push_str(s);
+        out.push('"');
+        return;
+    }
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Anything returned to JS.
+pub trait Payload {
+    fn to_tree(&self) -> Json;
+
+    fn to_json(&self) -> String {
+        let tree = self.to_tree();
+        let mut out = String::with_capacity(tree.estimated_len());
+        tree.write(&mut out);
+        out
+    }
+
+    /// Same payload as a JS object rather than a string.
+    #[allow(dead_code)]
+    fn to_js_value(&self) -> JsValue {
+        js_sys::JSON::parse(&self.to_json()).unwrap_or(JsValue::NULL)
+    }
+
+    /// This payload with the caller's `tag` (if any) as its first field.
+    fn tagged(self, tag: Option<&str>) -> Tagged<'_, Self>
+    where
+        Self: Sized,
+    {
+        Tagged { payload: self, tag }
+    }
+}
+
+/// A payload echoing the `tag` of the call or search it belongs to, so a worker
+/// multiplexing several searches can route it. Untagged, it is the payload as is.
+pub struct Tagged<'a, P> {
+    pub payload: P,
+    pub tag: Option<&'a str>,
+}
+
+impl<P: Payload> Payload for Tagged<'_, P> {
+    fn to_tree(&self) -> Json {
+        match (self.payload.to_tree(), self.tag) {
+            (Json::Object(mut fields), Some(tag)) => {
+                fields.insert(0, ("tag", Json::str(tag)));
+                Json::Object(fields)
+            }
+            (tree, _) => tree,
+        }
+    }
+}
+
+/// How combinations are laid out in result payloads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResultFormat {
+    /// Keep selection order instead of sorting by index
+    pub discovery_order: bool,
+    /// Combinations with more elements are sent truncated
+    pub max_elements: usize,
+    /// Append a `score` to every combination (see score.rs)
+    pub score: ScoreMode,
+}
+
+impl Default for ResultFormat {
+    fn default() -> Self {
+        ResultFormat { discovery_order: false, max_elements: usize::MAX, score: ScoreMode::None }
+    }
+}
+
+impl ResultFormat {
+    /// Positions into `entries` in the order they are reported.
+    pub fn report_order(&self, entries: &[NumberEntry]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..entries.len()).collect();
+        if !self.discovery_order {
+            order.sort_by_key(|&i| entries[i].original_index);
+        }
+        order
+    }
+
+    pub fn truncates(&self, entries: &[NumberEntry]) -> bool {
+        entries.len() > self.max_elements
+    }
+}
+
+/// One combination: `{"id","indices","values","count"}`, or when larger than the
+/// format allows, `{"id","count","sum","truncated":true,"indices"}` with only the
+/// first indices; the full list is fetched separately by id. Either form ends
+/// with a `score` when the format scores combinations.
+pub struct CombinationPayload<'a> {
+    /// In the order the solver selected them
+    pub entries: &'a [NumberEntry],
+    pub format: ResultFormat,
+}
+
+impl CombinationPayload<'_> {
+    fn fields(&self) -> Vec<(&'static str, Json)> {
+        let entries = self.entries;
+        let indices = |order: &[usize]| Json::Array(order.iter().map(|&i| Json::index(entries[i].original_index)).collect());
+        let values = |order: &[usize]| Json::Array(order.iter().map(|&i| Json::U64(entries[i].value)).collect());
+
+        let order = self.format.report_order(entries);
+        let id = ("id", Json::hex(combination_id(entries)));
+        let mut fields = if self.format.truncates(entries) {
+            let sum = entries.iter().fold(0u64, |sum, e| sum.saturating_add(e.value));
+            vec![
+                id,
+                ("count", Json::usize(entries.len())),
+                ("sum", Json::U64(sum)),
+                ("truncated", Json::Bool(true)),
+                ("indices", indices(&order[..self.format.max_elements])),
+            ]
+        } else if self.format.discovery_order {
+            vec![
+                id,
+                ("indices", indices(&order)),
+                ("values", values(&order)),
+                ("count", Json::usize(entries.len())),
+            ]
+        } else {
+            // Index order, with the step each element was selected at alongside
+            vec![
+                id,
+                ("indices", indices(&order)),
+                ("values", values(&order)),
+                ("selection_order", Json::Array(order.iter().map(|&i| Json::usize(i)).collect())),
+                ("count", Json::usize(entries.len())),
+            ]
+        };
+        if let Some(score) = self.format.score.score(entries.iter().map(|e| e.value)) {
+            fields.push(("score", Json::Fixed(score, 4)));
+        }
+        fields
+    }
+}
+
+impl Payload for CombinationPayload<'_> {
+    fn to_tree(&self) -> Json {
+        Json::Object(self.fields())
+    }
+}
+
+fn combinations(combos: &[Vec<NumberEntry>], format: ResultFormat) -> Json {
+    Json::Array(combos.iter().map(|entries| CombinationPayload { entries, format }.to_tree()).collect())
+}
+
+/// A batch search result: the combination plus its `seq`.
+fn sequenced(seq: u64, entries: &[NumberEntry], format: ResultFormat) -> Vec<(&'static str, Json)> {
+    let mut fields = CombinationPayload { entries, format }.fields();
+    fields.push(("seq", Json::U64(seq)));
+    fields
+}
+
+fn count_window(window: Option<(usize, usize)>) -> Json {
+    match window {
+        Some((min, max)) => Json::Array(vec![Json::usize(min), Json::usize(max)]),
+        None => Json::Null,
+    }
+}
+
+/// `[{"index","reason"}]`, or None when nothing was skipped (the field is omitted).
+fn skipped(skipped: &[SkippedIndex]) -> Option<Json> {
+    if skipped.is_empty() {
+        return None;
+    }
+    Some(Json::Array(skipped.iter()
+        .map(|s| Json::Object(vec![
+            ("index", Json::U64(s.index as u64)),
+            ("reason", Json::str(s.reason.as_str())),
+        ]))
+        .collect()))
+}
+
+/// The `stats` object of a find payload.
+pub struct StatsPayload<'a> {
+    pub stats: &'a SolverStats,
+    /// The uniqueness check and the budget it ran under
+    pub uniqueness: Option<(&'a UniquenessCheck, u64)>,
+}
+
+impl Payload for StatsPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let phases = self.stats.phases.iter()
+            .map(|p| Json::Object(vec![
+                ("algorithm", Json::str(p.algorithm)),
+                ("budget", Json::opt_u64(p.budget)),
+                ("nodes", Json::U64(p.nodes)),
+            ]))
+            .collect();
+        let mut fields = vec![
+            ("phases", Json::Array(phases)),
+            ("count_window", count_window(self.stats.count_window)),
+        ];
+        if let Some(bits) = self.stats.value_bits {
+            fields.push(("value_bits", Json::U64(bits.into())));
+        }
+        if let Some(shortcut) = self.stats.shortcut {
+            fields.push(("shortcut", Json::str(shortcut.as_str())));
+        }
+        if let Some(bytes) = self.stats.memory_fallback {
+            fields.push(("memory_fallback", Json::U64(bytes)));
+        }
+        if let Some(groups) = self.stats.collapsed_to {
+            fields.push(("collapsed_to", Json::usize(groups)));
+        }
+        if let Some(restarts) = self.stats.restarts {
+            fields.push(("restarts", Json::usize(restarts)));
+        }
+        if self.stats.peak_memory_estimate > 0 {
+            fields.push(("peak_memory_estimate_bytes", Json::U64(self.stats.peak_memory_estimate)));
+        }
+        if let Some((check, budget)) = self.uniqueness {
+            fields.push(("uniqueness_budget", Json::U64(budget)));
+            fields.push(("uniqueness_nodes", Json::U64(check.nodes_explored)));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// find_one and friends: found / not_found / cancelled.
+pub struct FindOnePayload<'a> {
+    pub result: &'a SolveOutcome,
+    pub stats: StatsPayload<'a>,
+    /// Echoed when the caller didn't pass the target directly
+    pub target: Option<u64>,
+    pub skipped: &'a [SkippedIndex],
+    pub format: ResultFormat,
+    /// Values came in as integer units (the `*_u64` calls), never through f64
+    pub exact_integers: bool,
+    /// Rows equal to the target, when the single-element result is one of several
+    pub identical_value_matches: Option<usize>,
+    /// Set by `objective: "min_cost"`
+    pub cost: Option<CostSummary>,
+    /// The `adjustments` entry whose target `target` is, with `adjustments` given
+    pub adjustment: Option<f64>,
+    /// Adjustments not tried, as they left no positive target
+    pub skipped_adjustments: &'a [f64],
+    /// How the searched target was rounded to a whole unit, when it had to be
+    pub target_rounding: Option<TargetRounding>,
+    /// Rows that lost their decimals to the scale, when enough did to warn
+    pub fractional_inputs: Option<FractionalInputs>,
+}
+
+impl Payload for FindOnePayload<'_> {
+    fn to_tree(&self) -> Json {
+        let mut fields = match self.result {
+            Ok(Some(entries)) => {
+                let mut fields = vec![("status", Json::str("found"))];
+                fields.extend(CombinationPayload { entries, format: self.format }.fields());
+                if let Some((check, _)) = self.stats.uniqueness {
+                    fields.push(("unique", match check.uniqueness {
+                        Uniqueness::Unique => Json::Bool(true),
+                        Uniqueness::NotUnique => Json::Bool(false),
+                        Uniqueness::Unknown => Json::str("unknown"),
+                    }));
+                }
+                if let Some(matches) = self.identical_value_matches {
+                    fields.push(("identical_value_matches", Json::usize(matches)));
+                }
+                fields
+            }
+            Ok(None) => {
+                let mut fields = vec![("status", Json::str("not_found"))];
+                if let Some(near) = &self.stats.stats.near_sums {
+                    fields.push(("explain", near_sums(near)));
+                }
+                fields
+            }
+            Err(SolveError::EmptyInput) => vec![("status", Json::str("not_found")), ("reason", Json::str("empty_input"))],
+            Err(SolveError::Cancelled) => vec![("status", Json::str("cancelled"))],
+            // lib.rs answers these with their error payloads instead
+            Err(err @ (SolveError::InvalidBounds { .. } | SolveError::TooLarge { .. } | SolveError::OutOfMemory(_))) => {
+                let error = ErrorPayload::from(*err);
+                let mut fields = vec![("status", Json::str("error")), ("code", Json::str(error.code))];
+                fields.extend(error.details);
+                fields
+            }
+        };
+        if let (Some(cost), false) = (self.cost, matches!(self.result, Err(SolveError::Cancelled))) {
+            if let Some(total) = cost.total_cost {
+                fields.push(("total_cost", Json::F64(total)));
+            }
+            fields.push(("optimal", Json::Bool(cost.proven)));
+        }
+        if let Some(target) = self.target {
+            fields.push(("target", Json::U64(target)));
+        }
+        if let Some(rounding) = self.target_rounding {
+            fields.push(("target_rounded_to", rounded_to(rounding)));
+        }
+        if let Some(fractional) = self.fractional_inputs {
+            fields.push(("warnings", Json::Array(vec![Json::Str(fractional.warning())])));
+        }
+        if let Some(adjustment) = self.adjustment {
+            fields.push(("adjustment", Json::F64(adjustment)));
+        }
+        if !self.skipped_adjustments.is_empty() {
+            fields.push(("skipped_adjustments", Json::Array(self.skipped_adjustments.iter().map(|&a| Json::F64(a)).collect())));
+        }
+        if self.exact_integers {
+            fields.push(("exact_integers", Json::Bool(true)));
+        }
+        fields.push(("stats", self.stats.to_tree()));
+        if let Some(skipped) = skipped(self.skipped) {
+            fields.push(("skipped", skipped));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// `{units, direction}`: the whole unit a target was rounded to, "up" or "down".
+fn rounded_to(rounding: TargetRounding) -> Json {
+    Json::Object(vec![
+        ("units", Json::U64(rounding.units)),
+        ("direction", Json::str(if rounding.up { "up" } else { "down" })),
+    ])
+}
+
+/// find_one's not-found summary: `{nearest_below, nearest_above, radius,
+/// achievable_within, truncated?}`, sums in scaled units, each nearest miss
+/// as `{sum, indices, diff}` or null. From a B&B, only the two misses and
+/// `exhaustive: false`.
+fn near_sums(near: &NearSums) -> Json {
+    let miss = |miss: &Option<NearMiss>| match miss {
+        Some(miss) => Json::Object(vec![
+            ("sum", Json::U128(miss.sum)),
+            ("indices", Json::Array(miss.indices.iter().map(|&i| Json::index(i)).collect())),
+            ("diff", Json::U128(miss.diff)),
+        ]),
+        None => Json::Null,
+    };
+    let mut fields = vec![
+        ("nearest_below", miss(&near.nearest_below)),
+        ("nearest_above", miss(&near.nearest_above)),
+    ];
+    if !near.exhaustive {
+        fields.push(("exhaustive", Json::Bool(false)));
+        return Json::Object(fields);
+    }
+    fields.extend([
+        ("radius", Json::U64(near.radius)),
+        ("achievable_within", Json::Array(near.achievable_within.iter().map(|&sum| Json::U128(sum)).collect())),
+    ]);
+    if near.truncated {
+        fields.push(("truncated", Json::Bool(true)));
+    }
+    Json::Object(fields)
+}
+
+/// One search_batch step.
+pub struct BatchStepPayload<'a> {
+    pub result: &'a BatchResult,
+    /// Only non-empty on the first step
+    pub skipped: &'a [SkippedIndex],
+    /// Most used rows, on the finished step only
+    pub top_usage: Option<&'a [UsageCount]>,
+    pub format: ResultFormat,
+    /// Milliseconds since the search started, reported once it has exhausted the space
+    pub elapsed_ms: Option<u64>,
+    /// See FindOnePayload::exact_integers
+    pub exact_integers: bool,
+    /// See FindOnePayload::target_rounding; on the first step only
+    pub target_rounding: Option<TargetRounding>,
+    /// See FindOnePayload::fractional_inputs; on the first step only
+    pub fractional_inputs: Option<FractionalInputs>,
+    /// Original indices of the search's preferred rows, ascending; each new
+    /// result then counts those it holds as `contains_preferred`
+    pub preferred: &'a [u32],
+}
+
+impl Payload for BatchStepPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let r = self.result;
+        let new_results = r.new_results.iter().zip(r.first_seq..)
+            .map(|(entries, seq)| {
+                let mut fields = sequenced(seq, entries, self.format);
+                if !self.preferred.is_empty() {
+                    let held = entries.iter().filter(|e| self.preferred.binary_search(&e.original_index.get()).is_ok()).count();
+                    fields.push(("contains_preferred", Json::usize(held)));
+                }
+                Json::Object(fields)
+            })
+            .collect();
+        let mut fields = vec![
+            ("new_results", Json::Array(new_results)),
+            ("total_found", Json::usize(r.total_found)),
+            ("nodes_explored", Json::U64(r.nodes_explored)),
+            ("finished", Json::Bool(r.finished)),
+            ("space_fully_explored", Json::Bool(r.space_fully_explored())),
+            ("progress", Json::Fixed(r.progress, 6)),
+            ("count_window", count_window(r.count_window)),
+            ("open_branches", Json::U64(r.open_branches)),
+            ("results_rate", Json::Fixed(r.results_rate, 3)),
+        ];
+        if let Some(count) = (r.new_results.len() as u64).checked_sub(1) {
+            fields.push(("first_seq", Json::U64(r.first_seq)));
+            fields.push(("last_seq", Json::U64(r.first_seq + count)));
+        }
+        fields.extend(stage_fields(&r.stages, r.stage_progress));
+        if self.exact_integers {
+            fields.push(("exact_integers", Json::Bool(true)));
+        }
+        if let Some(phase) = r.phase {
+            fields.push(("phase", Json::str(phase.as_str())));
+        }
+        if let Some(next) = r.next_phase {
+            fields.push(("next_phase", Json::str(next.as_str())));
+        }
+        if let Some(count) = r.current_count {
+            fields.push(("current_count", Json::usize(count)));
+        }
+        if let Some(bytes) = r.retained_bytes {
+            fields.push(("retained_bytes", Json::usize(bytes)));
+        }
+        if let Some(suppressed) = r.suppressed_near_duplicates {
+            fields.push(("suppressed_near_duplicates", Json::usize(suppressed)));
+        }
+        if let Some(region) = r.current_region {
+            fields.push(("current_region", Json::Object(vec![
+                ("min_index", Json::index(region.min_index)),
+                ("max_index", Json::index(region.max_index)),
+                ("partial_sum", Json::U64(region.partial_sum)),
+                ("depth", Json::usize(region.depth)),
+            ])));
+        }
+        if r.likely_long_running {
+            fields.push(("likely_long_running", Json::Bool(true)));
+        }
+        if r.paused_on_results {
+            fields.push(("paused_on_results", Json::Bool(true)));
+        }
+        if let Some(matches) = r.identical_value_matches {
+            fields.push(("identical_value_matches", Json::usize(matches)));
+        }
+        if !r.per_window_found.is_empty() {
+            let windows = r.window_matches.iter()
+                .map(|&mask| Json::Array((0..u32::BITS).filter(|w| mask & 1 << w != 0).map(|w| Json::U64(w.into())).collect()))
+                .collect();
+            fields.push(("window_matches", Json::Array(windows)));
+            fields.push(("per_window_found", Json::Array(r.per_window_found.iter().map(|&n| Json::usize(n)).collect())));
+        }
+        if let Some(prework) = &r.prework {
+            fields.push(("prework_pending", Json::Bool(true)));
+            fields.push(("prework", Json::Object(vec![
+                ("deferred", Json::Array(prework.deferred.iter().map(|&step| Json::str(step)).collect())),
+                ("init_ms", Json::Fixed(prework.init_ms, 1)),
+                ("deferred_ms", Json::Fixed(prework.deferred_ms, 1)),
+            ])));
+        }
+        if let Some(precheck) = &r.precheck {
+            fields.push(("precheck", Json::Object(vec![
+                ("outcome", Json::str(precheck.outcome)),
+                ("node_budget", Json::U64(precheck.node_budget)),
+                ("nodes", Json::U64(precheck.nodes)),
+            ])));
+        }
+        if let Some(reason) = r.termination_reason {
+            fields.push(("termination_reason", Json::str(reason.as_str())));
+            fields.push(("space_exhausted", Json::Bool(reason.space_exhausted())));
+            if !reason.space_exhausted() {
+                fields.push(("more_may_exist", Json::Bool(true)));
+            } else if let Some(ms) = self.elapsed_ms {
+                fields.push(("elapsed_ms", Json::U64(ms)));
+            }
+        }
+        if let Some(bytes) = r.peak_memory_estimate {
+            fields.push(("peak_memory_estimate_bytes", Json::U64(bytes)));
+        }
+        if let Some(rounding) = self.target_rounding {
+            fields.push(("target_rounded_to", rounded_to(rounding)));
+        }
+        if let Some(fractional) = self.fractional_inputs {
+            fields.push(("warnings", Json::Array(vec![Json::Str(fractional.warning())])));
+        }
+        if let Some(skipped) = skipped(self.skipped) {
+            fields.push(("skipped", skipped));
+        }
+        if let Some(usage) = self.top_usage {
+            fields.push(("top_usage", UsageHistogramPayload(usage).to_tree()));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// `stage`, with `stage_progress` while building tables, and `stages` when the
+/// step went through more than the one it ended in (see BatchResult::stages).
+fn stage_fields(stages: &[Stage], stage_progress: Option<f64>) -> Vec<(&'static str, Json)> {
+    let stage = stages.last().copied().unwrap_or(Stage::Searching);
+    let mut fields = vec![("stage", Json::str(stage.as_str()))];
+    if let Some(share) = stage_progress {
+        fields.push(("stage_progress", Json::Fixed(share, 6)));
+    }
+    if stages.len() > 1 {
+        fields.push(("stages", Json::Array(stages.iter().map(|stage| Json::str(stage.as_str())).collect())));
+    }
+    fields
+}
+
+/// One step_single_search step.
+pub struct SingleStepPayload<'a> {
+    /// Set once a solution is found
+    pub found: Option<&'a [NumberEntry]>,
+    pub finished: bool,
+    pub nodes_explored: u64,
+    pub progress: f64,
+    /// The stage the search is in; see BatchResult::stages
+    pub stage: Stage,
+    /// Where the search is, while it runs with diagnostics on
+    pub path: Option<PathSnapshot>,
+    pub format: ResultFormat,
+}
+
+impl Payload for SingleStepPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let mut fields = match (self.found, self.finished) {
+            (Some(entries), _) => {
+                let mut fields = vec![("status", Json::str("found"))];
+                fields.extend(CombinationPayload { entries, format: self.format }.fields());
+                fields
+            }
+            (None, true) => vec![("status", Json::str("not_found"))],
+            (None, false) => vec![("status", Json::str("running"))],
+        };
+        fields.push(("nodes_explored", Json::U64(self.nodes_explored)));
+        fields.push(("progress", Json::Fixed(self.progress, 6)));
+        fields.push(("stage", Json::str(self.stage.as_str())));
+        if let Some(path) = &self.path {
+            // Unstable: the shape may change between releases
+            fields.push(("diagnostics", Json::Object(vec![
+                ("unstable", Json::Bool(true)),
+                ("path", Json::Array(path.indices.iter().map(|&i| Json::index(i)).collect())),
+                ("path_len", Json::usize(path.len)),
+                ("partial_sum", Json::U64(path.sum)),
+            ])));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// get_usage_histogram: `[{"index","count","value"}]`.
+pub struct UsageHistogramPayload<'a>(pub &'a [UsageCount]);
+
+impl Payload for UsageHistogramPayload<'_> {
+    fn to_tree(&self) -> Json {
+        Json::Array(self.0.iter()
+            .map(|u| Json::Object(vec![
+                ("index", Json::index(u.index)),
+                ("count", Json::usize(u.count)),
+                ("value", Json::U64(u.value)),
+            ]))
+            .collect())
+    }
+}
+
+/// One count_batch step.
+pub struct CountStepPayload<'a>(pub &'a BatchResult);
+
+impl Payload for CountStepPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![
+            ("found_so_far", Json::usize(self.0.total_found)),
+            ("nodes_explored", Json::U64(self.0.nodes_explored)),
+            ("finished", Json::Bool(self.0.finished)),
+            ("progress", Json::Fixed(self.0.progress, 6)),
+        ];
+        fields.extend(stage_fields(&self.0.stages, self.0.stage_progress));
+        if let Some(reason) = self.0.termination_reason {
+            fields.push(("termination_reason", Json::str(reason.as_str())));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// `{"status":"error","code":...}` plus code-specific details.
+#[derive(Debug, PartialEq)]
+pub struct ErrorPayload {
+    pub code: &'static str,
+    pub details: Vec<(&'static str, Json)>,
+}
+
+impl ErrorPayload {
+    pub fn new(code: &'static str) -> Self {
+        ErrorPayload { code, details: Vec::new() }
+    }
+
+    pub fn with(mut self, key: &'static str, value: Json) -> Self {
+        self.details.push((key, value));
+        self
+    }
+}
+
+impl Payload for ErrorPayload {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![("status", Json::str("error")), ("code", Json::str(self.code))];
+        fields.extend(self.details.iter().cloned());
+        fields.extend(build_stamp());
+        Json::Object(fields)
+    }
+}
+
+impl From<RowSumError> for ErrorPayload {
+    fn from(err: RowSumError) -> Self {
+        match err {
+            RowSumError::Empty => ErrorPayload::new("empty_target_rows"),
+            RowSumError::InvalidIndex { index, reason } => ErrorPayload::new("invalid_target_index")
+                .with("index", Json::U64(index as u64))
+                .with("reason", Json::str(reason.as_str())),
+            RowSumError::Overflow => ErrorPayload::new("target_overflow"),
+        }
+    }
+}
+
+impl From<DecodeError> for ErrorPayload {
+    fn from(err: DecodeError) -> Self {
+        let payload = ErrorPayload::new(err.code());
+        match err {
+            DecodeError::UnsupportedFormat(version) => payload.with("format_version", Json::U64(version as u64)),
+            _ => payload,
+        }
+    }
+}
+
+impl From<OutOfMemory> for ErrorPayload {
+    fn from(oom: OutOfMemory) -> Self {
+        ErrorPayload::new("out_of_memory").with("bytes", Json::U64(oom.bytes))
+    }
+}
+
+impl From<SolveError> for ErrorPayload {
+    fn from(err: SolveError) -> Self {
+        match err {
+            SolveError::InvalidBounds { min_count, max_count } => ErrorPayload::new("invalid_bounds")
+                .with("min_count", Json::usize(min_count))
+                .with("max_count", Json::usize(max_count)),
+            SolveError::EmptyInput => ErrorPayload::new("empty_input"),
+            SolveError::TooLarge { algorithm, n, max_n } => ErrorPayload::new("algorithm_not_applicable")
+                .with("algorithm", Json::str(algorithm))
+                .with("n", Json::usize(n))
+                .with("max_n", Json::usize(max_n)),
+            SolveError::Cancelled => ErrorPayload::new("cancelled"),
+            SolveError::OutOfMemory(oom) => ErrorPayload::from(oom),
+        }
+    }
+}
+
+impl From<InvalidInput> for ErrorPayload {
+    fn from(err: InvalidInput) -> Self {
+        let payload = ErrorPayload::new(err.code());
+        match err {
+            InvalidInput::LengthMismatch { field, expected, actual } => payload
+                .with("field", Json::str(field))
+                .with("expected", Json::usize(expected))
+                .with("actual", Json::usize(actual)),
+            InvalidInput::OutOfRange { field, max, actual } => payload
+                .with("field", Json::str(field))
+                .with("max", Json::U64(max))
+                .with("actual", Json::U64(actual)),
+            InvalidInput::TooManyEntries { max, scanned } => payload
+                .with("max_entries", Json::usize(max))
+                .with("scanned", Json::usize(scanned)),
+        }
+    }
+}
+
+impl From<InvalidDelimiter> for ErrorPayload {
+    fn from(InvalidDelimiter(delimiter): InvalidDelimiter) -> Self {
+        ErrorPayload::new("invalid_delimiter").with("delimiter", Json::Str(delimiter.to_string()))
+    }
+}
+
+impl From<ConsistencyFailure> for ErrorPayload {
+    fn from(failure: ConsistencyFailure) -> Self {
+        let mut payload = ErrorPayload::new("internal_consistency_error");
+        for (key, value) in inconsistency_fields(&failure.inconsistency) {
+            payload = payload.with(key, value);
+        }
+        let combo = &failure.combination;
+        payload
+            .with("indices", Json::Array(combo.iter().map(|e| Json::index(e.original_index)).collect()))
+            .with("values", Json::Array(combo.iter().map(|e| Json::U64(e.value)).collect()))
+            .with("target", Json::U64(failure.target))
+            .with("count_window", count_window(Some((failure.min_count, failure.max_count))))
+    }
+}
+
+/// The failed check's reason, with what it found.
+fn inconsistency_fields(inconsistency: &Inconsistency) -> Vec<(&'static str, Json)> {
+    let mut fields = vec![("reason", Json::str(inconsistency.reason()))];
+    match *inconsistency {
+        Inconsistency::OutOfRange { index } | Inconsistency::DuplicateIndex { index } => {
+            fields.push(("index", Json::usize(index)));
+        }
+        Inconsistency::ValueMismatch { index, recorded, rescaled } => fields.extend([
+            ("index", Json::usize(index)),
+            ("recorded", Json::U64(recorded)),
+            ("rescaled", rescaled.map_or(Json::Null, Json::U64)),
+        ]),
+        Inconsistency::SumMismatch { sum } => fields.push(("sum", Json::U128(sum))),
+        Inconsistency::CountOutOfBounds { count } => fields.push(("count", Json::usize(count))),
+    }
+    fields
+}
+
+impl From<TargetOverflow> for ErrorPayload {
+    fn from(err: TargetOverflow) -> Self {
+        ErrorPayload::new("target_scale_overflow")
+            .with("scale", Json::U64(err.scale as u64))
+            .with("max_scale", err.max_scale.map_or(Json::Null, |s| Json::U64(s as u64)))
+    }
+}
+
+impl From<TargetNotRepresentable> for ErrorPayload {
+    fn from(err: TargetNotRepresentable) -> Self {
+        ErrorPayload::new("target_not_representable")
+            .with("target", Json::F64(err.target))
+            .with("scale", Json::U64(err.scale as u64))
+            .with("nearest", rounded_to(err.nearest))
+    }
+}
+
+impl From<FractionalInputs> for ErrorPayload {
+    fn from(found: FractionalInputs) -> Self {
+        ErrorPayload::new("fractional_inputs_truncated")
+            .with("percent", Json::U64(found.percent()))
+            .with("fractional", Json::usize(found.fractional))
+            .with("considered", Json::usize(found.considered))
+            .with("suggested_scale", found.suggested_scale.map_or(Json::Null, |scale| Json::U64(scale.into())))
+    }
+}
+
+impl From<ParseError> for ErrorPayload {
+    fn from(err: ParseError) -> Self {
+        ErrorPayload::new("invalid_json")
+            .with("offset", Json::usize(err.offset))
+            .with("message", Json::str(err.message))
+    }
+}
+
+impl From<OptionsError> for ErrorPayload {
+    fn from(err: OptionsError) -> Self {
+        match err {
+            OptionsError::Json(err) => err.into(),
+            OptionsError::Missing(field) => ErrorPayload::new(err.code()).with("field", Json::str(field)),
+            OptionsError::WrongType { field, expected } => ErrorPayload::new(err.code())
+                .with("field", Json::str(field))
+                .with("expected", Json::str(expected)),
+            OptionsError::TagTooLong { max, actual } => ErrorPayload::new(err.code())
+                .with("max", Json::usize(max))
+                .with("actual", Json::usize(actual)),
+        }
+    }
+}
+
+/// The older `{"error":"..."}` shape, kept for the calls that always returned it.
+pub struct LegacyErrorPayload(pub &'static str);
+
+impl Payload for LegacyErrorPayload {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![("error", Json::str(self.0))];
+        fields.extend(build_stamp());
+        Json::Object(fields)
+    }
+}
+
+/// Appended to every error, so a pasted error names the build it came from.
+fn build_stamp() -> [(&'static str, Json); 2] {
+    [("api_version", Json::U64(API_VERSION.into())), ("crate_version", Json::str(export::CRATE_VERSION))]
+}
+
+/// A test's expected error JSON with the build stamp appended.
+#[cfg(test)]
+pub fn stamped(json: &str) -> String {
+    let stamp = format!(r#","api_version":{},"crate_version":"{}"}}"#, API_VERSION, export::CRATE_VERSION);
+    format!("{}{}", json.strip_suffix('}').expect("a JSON object"), stamp)
+}
+
+/// get_capabilities: `{"api_version","crate_version","features"}`.
+pub struct CapabilitiesPayload;
+
+impl Payload for CapabilitiesPayload {
+    fn to_tree(&self) -> Json {
+        let features = capabilities::features().into_iter().map(Json::str).collect();
+        Json::Object(vec![
+            ("api_version", Json::U64(API_VERSION.into())),
+            ("crate_version", Json::str(export::CRATE_VERSION)),
+            ("features", Json::Array(features)),
+        ])
+    }
+}
+
+/// import_batch_state success.
+pub struct ImportedPayload {
+    pub total_found: usize,
+}
+
+impl Payload for ImportedPayload {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![("status", Json::str("ok")), ("total_found", Json::usize(self.total_found))])
+    }
+}
+
+/// set_batch_max_count success.
+pub struct MaxCountPayload(pub MaxCountChange);
+
+impl Payload for MaxCountPayload {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![("status", Json::str("ok"))];
+        match self.0 {
+            MaxCountChange::Unchanged => fields.push(("change", Json::str("unchanged"))),
+            MaxCountChange::Resumed { reopened } => {
+                fields.push(("change", Json::str("resumed")));
+                fields.push(("reopened", Json::usize(reopened)));
+            }
+            MaxCountChange::Restarted { frontier_overflowed } => {
+                fields.push(("change", Json::str("restarted")));
+                if frontier_overflowed {
+                    fields.push(("warning", Json::str("frontier_overflow")));
+                }
+            }
+        }
+        Json::Object(fields)
+    }
+}
+
+/// adjust_target: the search going on at a lower target, in scaled units.
+pub struct TargetChangePayload {
+    pub change: TargetChange,
+    pub target: u64,
+}
+
+impl Payload for TargetChangePayload {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![("status", Json::str("ok"))];
+        match self.change {
+            TargetChange::Unchanged | TargetChange::Refused => fields.push(("change", Json::str("unchanged"))),
+            TargetChange::Lowered { invalidated } => {
+                fields.push(("change", Json::str("lowered")));
+                fields.push(("invalidated_count", Json::usize(invalidated)));
+            }
+        }
+        fields.push(("target", Json::U64(self.target)));
+        Json::Object(fields)
+    }
+}
+
+/// verify_exhaustive: a reported result set against every combination of the
+/// input, in scaled units.
+pub enum ExhaustiveCheckPayload<'a> {
+    Checked { check: &'a ExhaustiveCheck, usable_count: usize },
+    NotVerifiable { usable_count: usize },
+    Cancelled,
+}
+
+impl Payload for ExhaustiveCheckPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let (check, usable_count) = match *self {
+            ExhaustiveCheckPayload::Checked { check, usable_count } => (check, usable_count),
+            ExhaustiveCheckPayload::NotVerifiable { usable_count } => return Json::Object(vec![
+                ("status", Json::str("not_verifiable")),
+                ("reason", Json::str("too_many_rows")),
+                ("usable_count", Json::usize(usable_count)),
+                ("max_rows", Json::usize(EXHAUSTIVE_MAX_ROWS)),
+            ]),
+            ExhaustiveCheckPayload::Cancelled => return Json::Object(vec![("status", Json::str("cancelled"))]),
+        };
+        let missing = check.missing.iter()
+            .map(|combo| Json::Object(vec![
+                ("indices", Json::Array(combo.iter().map(|e| Json::index(e.original_index)).collect())),
+                ("values", Json::Array(combo.iter().map(|e| Json::U64(e.value)).collect())),
+                ("count", Json::usize(combo.len())),
+            ]))
+            .collect();
+        let extra = check.extra.iter()
+            .map(|extra| {
+                let mut fields = vec![("indices", Json::Array(extra.indices.iter().map(|&i| Json::U64(i)).collect()))];
+                if let Some(values) = &extra.values {
+                    fields.push(("values", Json::Array(values.iter().map(|&v| Json::U64(v)).collect())));
+                }
+                match &extra.problem {
+                    ExtraProblem::Invalid(inconsistency) => fields.extend(inconsistency_fields(inconsistency)),
+                    ExtraProblem::Repeated => fields.push(("reason", Json::str("repeated"))),
+                    ExtraProblem::Unexpected => fields.push(("reason", Json::str("not_enumerated"))),
+                }
+                Json::Object(fields)
+            })
+            .collect();
+        Json::Object(vec![
+            ("status", Json::str(if check.complete() { "verified" } else { "mismatch" })),
+            ("usable_count", Json::usize(usable_count)),
+            ("expected_count", Json::usize(check.expected_count)),
+            ("reported_count", Json::usize(check.reported_count)),
+            ("missing", Json::Array(missing)),
+            ("extra", Json::Array(extra)),
+        ])
+    }
+}
+
+/// get_results: every collected result of the active batch search, in the
+/// order asked for.
+pub struct ResultsPayload<'a> {
+    pub order_by: &'static str,
+    /// Each with its sequence number
+    pub results: &'a [(u64, &'a [NumberEntry])],
+    pub format: ResultFormat,
+}
+
+impl Payload for ResultsPayload<'_> {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![
+            ("order_by", Json::str(self.order_by)),
+            ("total", Json::usize(self.results.len())),
+            ("results", sequenced_results(self.results, self.format)),
+        ])
+    }
+}
+
+fn sequenced_results(results: &[(u64, &[NumberEntry])], format: ResultFormat) -> Json {
+    Json::Array(results.iter().map(|&(seq, entries)| Json::Object(sequenced(seq, entries, format))).collect())
+}
+
+/// get_results_by_seq: the results numbered `from..=to` still held.
+pub struct SeqRangePayload<'a> {
+    pub from: u64,
+    pub to: u64,
+    pub results: &'a [(u64, &'a [NumberEntry])],
+    pub format: ResultFormat,
+}
+
+impl Payload for SeqRangePayload<'_> {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![
+            ("from", Json::U64(self.from)),
+            ("to", Json::U64(self.to)),
+            ("total", Json::usize(self.results.len())),
+            ("results", sequenced_results(self.results, self.format)),
+        ])
+    }
+}
+
+/// get_batch_info: what the active batch search was built from and how it runs.
+pub struct BatchInfoPayload<'a> {
+    pub info: &'a SearchInfo,
+    /// Rows left out at init by reason; None when init kept no counts
+    pub skip_counts: Option<&'a [(SkipReason, usize)]>,
+    pub finished: bool,
+    pub termination_reason: Option<TerminationReason>,
+}
+
+impl Payload for BatchInfoPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let info = self.info;
+        let mut fields = vec![("usable_count", Json::usize(info.usable_count))];
+        if let Some(counts) = self.skip_counts {
+            fields.push(("skipped_count", Json::usize(counts.iter().map(|&(_, n)| n).sum())));
+            let by_reason = counts.iter().map(|&(reason, n)| (reason.as_str(), Json::usize(n))).collect();
+            fields.push(("skipped_by_reason", Json::Object(by_reason)));
+        }
+        fields.extend([
+            ("total_value", Json::U128(info.total_value)),
+            ("min_value", info.min_value.map_or(Json::Null, Json::U64)),
+            ("max_value", info.max_value.map_or(Json::Null, Json::U64)),
+            ("strategy", Json::str(info.strategy)),
+            ("precheck", Json::Bool(info.precheck)),
+            ("count_window", count_window(info.count_window)),
+            ("finished", Json::Bool(self.finished)),
+        ]);
+        if let Some(reason) = self.termination_reason {
+            fields.push(("termination_reason", Json::str(reason.as_str())));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// destroy_batch_search: where the search stood, plus collected results that no
+/// search_batch payload has returned yet (at most a page; the rest are counted).
+pub struct DestroyedPayload<'a> {
+    pub total_found: usize,
+    pub nodes_explored: u64,
+    pub finished: bool,
+    pub termination_reason: Option<TerminationReason>,
+    pub undelivered: &'a [Vec<NumberEntry>],
+    /// Undelivered results beyond the page
+    pub undelivered_omitted: usize,
+    pub format: ResultFormat,
+}
+
+impl Payload for DestroyedPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let mut fields = vec![
+            ("total_found", Json::usize(self.total_found)),
+            ("nodes_explored", Json::U64(self.nodes_explored)),
+            ("finished", Json::Bool(self.finished)),
+        ];
+        if let Some(reason) = self.termination_reason {
+            fields.push(("termination_reason", Json::str(reason.as_str())));
+        }
+        fields.push(("undelivered_results", combinations(self.undelivered, self.format)));
+        if self.undelivered_omitted > 0 {
+            fields.push(("undelivered_omitted", Json::usize(self.undelivered_omitted)));
+        }
+        Json::Object(fields)
+    }
+}
+
+/// The batch search an init found active: replaced, or left alone when refused.
+pub struct ActiveSearchPayload {
+    pub tag: Option<String>,
+    pub total_found: usize,
+    pub nodes_explored: u64,
+    pub finished: bool,
+    /// Collected results no search_batch payload has returned yet
+    pub undelivered: usize,
+}
+
+impl Payload for ActiveSearchPayload {
+    fn to_tree(&self) -> Json {
+        let mut fields = Vec::new();
+        if let Some(tag) = &self.tag {
+            fields.push(("tag", Json::str(tag)));
+        }
+        fields.extend([
+            ("total_found", Json::usize(self.total_found)),
+            ("nodes_explored", Json::U64(self.nodes_explored)),
+            ("finished", Json::Bool(self.finished)),
+            ("undelivered", Json::usize(self.undelivered)),
+        ]);
+        Json::Object(fields)
+    }
+}
+
+/// init_batch_search*: the new search is running in place of an active one.
+pub struct ReplacedPayload(pub ActiveSearchPayload);
+
+impl Payload for ReplacedPayload {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![
+            ("status", Json::str("ok")),
+            ("replaced_active", Json::Bool(true)),
+            ("replaced", self.0.to_tree()),
+        ])
+    }
+}
+
+/// inspect_batch_state: the header of an exported state.
+pub struct StateHeaderPayload<'a>(pub &'a StateHeader);
+
+impl Payload for StateHeaderPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let h = self.0;
+        let o = &h.options;
+        let include = o.include_indices.as_ref().map_or(Json::Null, |indices| {
+            Json::Array(indices.iter().map(|&i| Json::U64(i as u64)).collect())
+        });
+        Json::Object(vec![
+            ("format_version", Json::U64(h.format_version as u64)),
+            ("crate_version", Json::str(&h.crate_version)),
+            ("compatible", Json::Bool(export::version_compatible(&h.crate_version))),
+            ("fingerprint", Json::hex(h.fingerprint)),
+            ("n", Json::U64(h.n)),
+            ("results_so_far", Json::U64(h.results_so_far)),
+            ("options", Json::Object(vec![
+                ("target", Json::U64(o.target)),
+                ("scale", Json::U64(o.scale as u64)),
+                ("min_count", Json::U64(o.min_count as u64)),
+                ("max_count", Json::U64(o.max_count as u64)),
+                ("max_results", Json::U64(o.max_results as u64)),
+                ("include_indices", include),
+            ])),
+        ])
+    }
+}
+
+/// import_session.
+pub struct SessionImportedPayload {
+    pub total_found: usize,
+    /// Started over from the recorded options rather than resumed
+    pub replayed: bool,
+}
+
+impl Payload for SessionImportedPayload {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![
+            ("status", Json::str("ok")),
+            ("total_found", Json::usize(self.total_found)),
+            ("replayed", Json::Bool(self.replayed)),
+        ])
+    }
+}
+
+/// describe_session.
+pub struct SessionPayload<'a>(pub &'a Session);
+
+impl Payload for SessionPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let session = self.0;
+        let Json::Object(header) = StateHeaderPayload(&session.header).to_tree() else { unreachable!() };
+        let mut fields = vec![
+            ("session_version", Json::U64(session.session_version as u64)),
+            ("compressed", Json::Bool(session.compressed)),
+            ("data", Json::str(session.data.as_str())),
+        ];
+        fields.extend(header);
+        let stats = &session.stats;
+        fields.push(("stats", Json::Object(vec![
+            ("nodes_explored", Json::U64(stats.nodes_explored)),
+            ("finished", Json::Bool(stats.finished)),
+            ("termination_reason", stats.termination_reason.as_deref().map_or(Json::Null, Json::str)),
+            ("elapsed_ms", Json::Fixed(stats.elapsed_ms, 1)),
+        ])));
+        let results = session.results.iter()
+            .map(|rows| Json::Array(rows.iter().map(|&row| Json::U64(row as u64)).collect()))
+            .collect();
+        fields.push(("results", Json::Array(results)));
+        Json::Object(fields)
+    }
+}
+
+/// get_dataset_summary.
+pub struct DatasetSummaryPayload<'a> {
+    pub scale: u32,
+    pub summary: &'a DatasetSummary,
+}
+
+impl Payload for DatasetSummaryPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let s = self.summary;
+        let dups = s.top_duplicates.iter()
+            .map(|&(value, count)| Json::Object(vec![("value", Json::U64(value)), ("count", Json::usize(count))]))
+            .collect();
+        Json::Object(vec![
+            ("scale", Json::U64(self.scale as u64)),
+            ("input_len", Json::usize(s.input_len)),
+            ("count", Json::usize(s.count)),
+            ("min", Json::opt_u64(s.min)),
+            ("max", Json::opt_u64(s.max)),
+            ("total", Json::U128(s.total)),
+            ("top_duplicates", Json::Array(dups)),
+        ])
+    }
+}
+
+/// estimate_difficulty.
+pub struct PrognosisPayload<'a> {
+    pub prognosis: &'a Prognosis,
+    /// Rows dropped before the solver, for any reason
+    pub skipped: usize,
+}
+
+impl Payload for PrognosisPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let p = self.prognosis;
+        let count_window = match p.count_window {
+            Some((min, max)) => Json::Array(vec![Json::usize(min), Json::usize(max)]),
+            None => Json::Null,
+        };
+        Json::Object(vec![
+            ("n", Json::usize(p.n)),
+            ("skipped", Json::usize(self.skipped)),
+            ("algorithms", Json::Array(p.algorithms.iter().map(|a| Json::str(a)).collect())),
+            ("count_window", count_window),
+            ("infeasible", p.infeasible.map_or(Json::Null, |i| Json::str(i.as_str()))),
+            ("worst_case_nodes", Json::F64(p.worst_case_nodes.round())),
+            ("difficulty", Json::str(p.difficulty.as_str())),
+            ("mitm_memory_bytes", Json::opt_u64(p.mitm_memory_bytes)),
+            ("mitm_memory_over_budget", Json::Bool(p.mitm_over_budget())),
+        ])
+    }
+}
+
+/// analyze_target: the usable total, the target's share of it, and the rows
+/// every solution needs or none can use.
+pub struct TargetAnalysisPayload<'a> {
+    pub analysis: &'a TargetAnalysis,
+    pub usable_count: usize,
+    pub target: u64,
+}
+
+impl Payload for TargetAnalysisPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let a = self.analysis;
+        let fraction = match a.total_value {
+            0 => Json::Null,
+            total => Json::Fixed(self.target as f64 / total as f64, 6),
+        };
+        let indices = |rows: &[OriginalIndex]| Json::Array(rows.iter().map(|&i| Json::index(i)).collect());
+        Json::Object(vec![
+            ("usable_count", Json::usize(self.usable_count)),
+            ("total_value", Json::U128(a.total_value)),
+            ("target", Json::U64(self.target)),
+            ("target_fraction", fraction),
+            ("count_window", count_window(a.count_window)),
+            ("mandatory", indices(&a.mandatory)),
+            ("irrelevant", indices(&a.irrelevant)),
+        ])
+    }
+}
+
+/// get_result_meta: which result, how many rows and their sum.
+pub struct ResultMetaPayload {
+    pub id: u64,
+    pub count: usize,
+    pub sum: u64,
+}
+
+impl Payload for ResultMetaPayload {
+    fn to_tree(&self) -> Json {
+        Json::Object(vec![
+            ("id", Json::hex(self.id)),
+            ("count", Json::usize(self.count)),
+            ("sum", Json::U64(self.sum)),
+        ])
+    }
+}
+
+/// solve_multiple_targets: one entry per target, in the order given, holding its
+/// combination or the reason it has none.
+pub struct MultiTargetPayload<'a> {
+    pub result: &'a Result<MultiTargetResult, SolveError>,
+    pub targets: &'a [u64],
+    pub format: ResultFormat,
+}
+
+impl Payload for MultiTargetPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let result = match self.result {
+            Ok(result) => result,
+            Err(SolveError::Cancelled) => return Json::Object(vec![("status", Json::str("cancelled"))]),
+            Err(err) => return ErrorPayload::from(*err).to_tree(),
+        };
+        let targets = self.targets.iter().zip(&result.combinations).enumerate()
+            .map(|(position, (&target, combination))| {
+                let mut fields = vec![("target", Json::U64(target))];
+                match combination {
+                    Some(entries) => fields.extend(CombinationPayload { entries, format: self.format }.fields()),
+                    None => {
+                        let reason = result.failures.iter().find(|&&(p, _)| p == position).map(|&(_, reason)| reason);
+                        fields.push(("unsatisfied", reason.map_or(Json::Null, |reason| Json::str(reason.as_str()))));
+                    }
+                }
+                Json::Object(fields)
+            })
+            .collect();
+        Json::Object(vec![
+            ("status", Json::str(if result.solved() { "solved" } else { "partial" })),
+            ("targets", Json::Array(targets)),
+            ("order", Json::Array(result.order.iter().map(|&p| Json::usize(p)).collect())),
+            ("stats", Json::Object(vec![
+                ("nodes_explored", Json::U64(result.nodes_explored)),
+                ("backtracks", Json::U64(result.backtracks)),
+            ])),
+        ])
+    }
+}
+
+/// find_matching_pair: both combinations and their common sum, or why there
+/// are none.
+pub struct MatchingPairPayload<'a> {
+    pub result: &'a Result<PairSearch, SolveError>,
+    pub format: ResultFormat,
+}
+
+impl Payload for MatchingPairPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let search = match self.result {
+            Ok(search) => search,
+            Err(SolveError::Cancelled) => return Json::Object(vec![("status", Json::str("cancelled"))]),
+            Err(err) => return ErrorPayload::from(*err).to_tree(),
+        };
+        let mut fields = Vec::new();
+        match &search.pair {
+            Some(pair) => {
+                fields.push(("status", Json::str("found")));
+                fields.push(("sum", Json::U64(pair.sum)));
+                fields.push(("a", Json::Object(CombinationPayload { entries: &pair.a, format: self.format }.fields())));
+                fields.push(("b", Json::Object(CombinationPayload { entries: &pair.b, format: self.format }.fields())));
+            }
+            None => {
+                fields.push(("status", Json::str("not_found")));
+                if !search.complete {
+                    fields.push(("more_may_exist", Json::Bool(true)));
+                }
+            }
+        }
+        fields.push(("stats", Json::Object(vec![
+            ("enumerated", Json::str(search.enumerated.as_str())),
+            ("sums", Json::usize(search.sums)),
+            ("probes", Json::usize(search.probes)),
+            ("nodes_explored", Json::U64(search.nodes_explored)),
+        ])));
+        Json::Object(fields)
+    }
+}
+
+/// get_search_tree: the recorded nodes, each naming its row by index into the
+/// search's sorted entries, with the row's original index and value alongside.
+pub struct SearchTreePayload<'a> {
+    pub tree: &'a SearchTree,
+    pub sorted: &'a [NumberEntry],
+    pub target: u64,
+}
+
+impl Payload for SearchTreePayload<'_> {
+    fn to_tree(&self) -> Json {
+        let nodes = self.tree.nodes.iter().enumerate()
+            .map(|(id, node)| {
+                let mut fields = vec![
+                    ("id", Json::usize(id)),
+                    ("parent", node.parent.map_or(Json::Null, |p| Json::U64(p.into()))),
+                    ("depth", Json::usize(node.depth)),
+                    ("path_sum", Json::U64(node.path_sum)),
+                ];
+                if let Some(i) = node.index {
+                    fields.push(("index", Json::usize(i)));
+                    fields.push(("row", Json::index(self.sorted[i].original_index)));
+                    fields.push(("value", Json::U64(self.sorted[i].value)));
+                }
+                fields.push(("decision", Json::str(node.decision.as_str())));
+                match node.decision {
+                    Decision::Root | Decision::Branch | Decision::Solution => {}
+                    Decision::Cut { at_max_count } => fields.push(("at_max_count", Json::Bool(at_max_count))),
+                    Decision::Skip(skip) => fields.push(("reason", Json::str(skip.as_str()))),
+                    Decision::Prune(prune) => {
+                        fields.push(("reason", Json::str(prune.as_str())));
+                        match prune {
+                            Prune::TooLarge { remaining, .. } => fields.push(("remaining", Json::U64(remaining))),
+                            Prune::SuffixShort { suffix_sum, remaining } => {
+                                fields.push(("suffix_sum", Json::U64(suffix_sum)));
+                                fields.push(("remaining", Json::U64(remaining)));
+                            }
+                            Prune::TooFewLeft { left, needed } => {
+                                fields.push(("left", Json::usize(left)));
+                                fields.push(("needed", Json::usize(needed)));
+                            }
+                            Prune::DuplicateTarget => {}
+                        }
+                    }
+                    Decision::Scan { steps, found } => {
+                        fields.push(("steps", Json::U64(steps)));
+                        fields.push(("found", Json::usize(found)));
+                    }
+                }
+                Json::Object(fields)
+            })
+            .collect();
+        Json::Object(vec![
+            ("target", Json::U64(self.target)),
+            ("max_nodes", Json::usize(self.tree.max_nodes())),
+            ("truncated", Json::Bool(self.tree.truncated)),
+            ("nodes", Json::Array(nodes)),
+        ])
+    }
+}
+
+/// self_test: `{"passed","cases":[{"name","passed","detail"}],"version"}`.
+pub struct SelfTestPayload<'a>(pub &'a [CaseOutcome]);
+
+impl Payload for SelfTestPayload<'_> {
+    fn to_tree(&self) -> Json {
+        let cases = self.0.iter()
+            .map(|case| Json::Object(vec![
+                ("name", Json::str(case.name)),
+                ("passed", Json::Bool(case.passed)),
+                ("detail", Json::str(&case.detail)),
+            ]))
+            .collect();
+        Json::Object(vec![
+            ("passed", Json::Bool(self.0.iter().all(|case| case.passed))),
+            ("cases", Json::Array(cases)),
+            ("version", Json::str(export::CRATE_VERSION)),
+        ])
+    }
+}
+
+/// get_effective_entries: each input row with its scaled value or skip reason.
+pub struct EffectiveEntriesPayload {
+    pub target: u64,
+    pub scale: u32,
+    /// (input value, scaled value or why it was skipped), in input order
+    pub rows: Vec<(f64, Result<u64, SkipReason>)>,
+}
+
+impl Payload for EffectiveEntriesPayload {
+    fn to_tree(&self) -> Json {
+        let rows = self.rows.iter()
+            .enumerate()
+            .map(|(index, &(value, classified))| match classified {
+                Ok(scaled) => Json::Object(vec![
+                    ("index", Json::usize(index)),
+                    ("value", Json::F64(value)),
+                    ("scaled", Json::U64(scaled)),
+                ]),
+                Err(reason) => Json::Object(vec![
+                    ("index", Json::usize(index)),
+                    ("skipped_reason", Json::str(reason.as_str())),
+                ]),
+            })
+            .collect();
+        Json::Object(vec![
+            ("target", Json::U64(self.target)),
+            ("scale", Json::U64(self.scale as u64)),
+            ("entries", Json::Array(rows)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{BatchOptions, CarriedOptions};
+    use crate::batch::{CurrentRegion, SearchPhase};
+    use crate::canonical;
+    use crate::solver::{PhaseStats, Shortcut};
+
+    fn entry(value: u64, original_index: usize) -> NumberEntry {
+        NumberEntry { value, original_index: OriginalIndex::new(original_index) }
+    }
+
+    fn step(result: &BatchResult) -> BatchStepPayload<'_> {
+        BatchStepPayload {
+            result,
+            skipped: &[],
+            top_usage: None,
+            format: ResultFormat::default(),
+            elapsed_ms: None,
+            exact_integers: false,
+            target_rounding: None,
+            fractional_inputs: None,
+            preferred: &[],
+        }
+    }
+
+    fn batch_result(new_results: Vec<Vec<NumberEntry>>, finished: bool, progress: f64) -> BatchResult {
+        BatchResult {
+            total_found: new_results.len(),
+            new_results,
+            first_seq: 0,
+            nodes_explored: 42,
+            finished,
+            progress,
+            count_window: Some((1, 3)),
+            termination_reason: finished.then_some(TerminationReason::Exhausted),
+            paused_on_results: false,
+            open_branches: if finished { 0 } else { 12 },
+            likely_long_running: false,
+            window_matches: vec![],
+            per_window_found: vec![],
+            prework: None,
+            precheck: None,
+            phase: None,
+            next_phase: None,
+            identical_value_matches: None,
+            current_count: None,
+            current_region: None,
+            results_rate: 0.0,
+            retained_bytes: None,
+            peak_memory_estimate: None,
+            suppressed_near_duplicates: None,
+            stages: vec![if finished { Stage::Finalizing } else { Stage::Searching }],
+            stage_progress: None,
+        }
+    }
+
+    #[test]
+    fn test_json_scalars_and_escaping() {
+        let cases = [
+            (Json::Null, "null"),
+            (Json::Bool(true), "true"),
+            (Json::U64(u64::MAX), "18446744073709551615"),
+            (Json::U128(u128::from(u64::MAX) * 3), "55340232221128654845"),
+            (Json::F64(5.0), "5"),
+            (Json::F64(0.1), "0.1"),
+            (Json::F64(f64::NAN), "null"),
+            (Json::Fixed(1.0 / 3.0, 6), "0.333333"),
+            (Json::Fixed(f64::INFINITY, 6), "null"),
+            (Json::str("a\"b\\c\nd"), r#""a\"b\\c\u000ad""#),
+            (Json::hex(0xab), r#""00000000000000ab""#),
+            (Json::Array(vec![]), "[]"),
+            (Json::Object(vec![]), "{}"),
+        ];
+        for (json, expected) in cases {
+            let mut out = String::new();
+            json.write(&mut out);
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_strings_are_escaped_and_bounded() {
+        let round_trip = |s: &str| {
+            let mut out = String::new();
+            Json::str(s).write(&mut out);
+            match canonical::parse(&out) {
+                Ok(canonical::Value::Str(parsed)) => (out, parsed),
+                other => panic!("{:?} from {}", other, out),
+            }
+        };
+        // A JS string with a lone surrogate arrives as wasm-bindgen decodes it
+        let lone_surrogate = String::from_utf16_lossy(&[0x61, 0xD800, 0x62]);
+        assert_eq!(lone_surrogate, "a\u{FFFD}b");
+        for s in ["say \"hi\"", "C:\\tmp\\", "two\nlines\r\t\u{0}\u{1f}", "panel 🚀 Σ", "", lone_surrogate.as_str()] {
+            let (out, parsed) = round_trip(s);
+            assert_eq!(parsed, s, "{}", out);
+        }
+        assert_eq!(round_trip("🚀\u{7}").0, "\"🚀\\u0007\"");
+
+        // At the cap as it is; past it cut to fit, marker included
+        let full = "x".repeat(MAX_STRING_BYTES);
+        assert_eq!(round_trip(&full).1, full);
+        let (_, cut) = round_trip(&format!("{}y", full));
+        assert!(cut.len() <= MAX_STRING_BYTES && cut.ends_with(TRUNCATION_MARKER), "{}", cut.len());
+        assert_eq!(cut.len(), MAX_STRING_BYTES);
+        // Never inside a character: the emoji that would straddle the cut goes
+        let keep = MAX_STRING_BYTES - TRUNCATION_MARKER.len();
+        let (_, cut) = round_trip(&format!("{}🚀{}", "x".repeat(keep - 2), full));
+        assert!(cut == format!("{}{}", "x".repeat(keep - 2), TRUNCATION_MARKER), "{}", cut.len());
+        // Quotes past the cut are dropped, those before it still escaped
+        let (out, cut) = round_trip(&"\"".repeat(MAX_STRING_BYTES + 1));
+        assert!(cut.starts_with("\"\"") && out.len() < 2 * MAX_STRING_BYTES + 20);
+
+        // As a field of an error payload, tags included
+        let json = ErrorPayload::new("invalid_json").with("message", Json::str("bad \\ \"input\"")).tagged(Some("a\"b\nc")).to_json();
+        assert!(canonical::parse(&json).is_ok(), "{}", json);
+        assert!(json.starts_with(r#"{"tag":"a\"b\u000ac","status":"error""#), "{}", json);
+    }
+
+    /// The std-formatting writer this module used before write_u64, kept to pin
+    /// the output byte for byte.
+    fn write_reference(json: &Json, out: &mut String) {
+        match json {
+            Json::U64(v) => { let _ = write!(out, "{}", v); }
+            Json::U128(v) => { let _ = write!(out, "{}", v); }
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_reference(item, out);
+                }
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_escaped(key, out);
+                    out.push(':');
+                    write_reference(value, out);
+                }
+                out.push('}');
+            }
+            other => other.write(out),
+        }
+    }
+
+    /// 10k combinations of 8 elements, values spread over the whole u64 range.
+    fn dense_batch() -> BatchResult {
+        let combos = (0..10_000u64)
+            .map(|c| (0..8).map(|i| entry(c.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (i * 8), (c * 8 + i) as usize)).collect())
+            .collect();
+        batch_result(combos, false, 0.25)
+    }
+
+    #[test]
+    fn test_writer_matches_reference() {
+        let mut numbers: Vec<Json> = [0, 1, 9, 10, 99, 100, 12_345, u32::MAX as u64, 1 << 53, u64::MAX - 1, u64::MAX]
+            .into_iter()
+            .map(Json::U64)
+            .collect();
+        numbers.extend([0, u64::MAX as u128, u64::MAX as u128 + 1, u128::MAX].map(Json::U128));
+        let trees = [
+            Json::Array(numbers),
+            step(&dense_batch()).to_tree(),
+        ];
+        for tree in trees {
+            let mut expected = String::new();
+            write_reference(&tree, &mut expected);
+            let mut out = String::new();
+            tree.write(&mut out);
+            assert_eq!(out, expected);
+        }
+    }
+
+    /// Run with `cargo test --release bench_json_emission -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_json_emission() {
+        use std::time::Instant;
+
+        let result = dense_batch();
+        let payload = step(&result);
+        let tree = payload.to_tree();
+        let runs = 20;
+        let time = |write: &dyn Fn(&mut String)| {
+            let start = Instant::now();
+            for _ in 0..runs {
+                let mut out = String::new();
+                write(&mut out);
+                std::hint::black_box(out);
+            }
+            start.elapsed() / runs
+        };
+        let reference = time(&|out| write_reference(&tree, out));
+        let current = time(&|out| {
+            out.reserve(tree.estimated_len());
+            tree.write(out);
+        });
+        let building = time(&|out| *out = payload.to_json());
+        println!("10k x 8 payload: write {:?} (reference {:?}), to_json {:?}", current, reference, building);
+    }
+
+    #[test]
+    fn test_combination_payload() {
+        assert_eq!(
+            CombinationPayload { entries: &[entry(9, 0)], format: ResultFormat::default() }.to_json(),
+            r#"{"id":"a8c7f832281a39c5","indices":[0],"values":[9],"selection_order":[0],"count":1}"#,
+        );
+        let json = CombinationPayload { entries: &[entry(u64::MAX, 3), entry(1, 7)], format: ResultFormat::default() }.to_json();
+        assert!(
+            json.ends_with(r#""indices":[3,7],"values":[18446744073709551615,1],"selection_order":[0,1],"count":2}"#),
+            "{}", json,
+        );
+    }
+
+    #[test]
+    fn test_combination_payload_orderings() {
+        // Selected smallest value first, as the DFS does
+        let selected = [entry(2, 5), entry(3, 1), entry(7, 4)];
+        let by_index = CombinationPayload { entries: &selected, format: ResultFormat::default() }.to_json();
+        let discovery = ResultFormat { discovery_order: true, ..Default::default() };
+        let by_discovery = CombinationPayload { entries: &selected, format: discovery }.to_json();
+        assert!(by_index.ends_with(r#""indices":[1,4,5],"values":[3,7,2],"selection_order":[1,2,0],"count":3}"#), "{}", by_index);
+        assert!(by_discovery.ends_with(r#""indices":[5,1,4],"values":[2,3,7],"count":3}"#), "{}", by_discovery);
+        // Same combination either way
+        assert_eq!(by_index[..24], by_discovery[..24]);
+    }
+
+    #[test]
+    fn test_truncated_combination() {
+        let selected = [entry(2, 5), entry(3, 1), entry(7, 4)];
+        let capped = ResultFormat { max_elements: 2, ..Default::default() };
+        let json = CombinationPayload { entries: &selected, format: capped }.to_json();
+        assert!(json.ends_with(r#","count":3,"sum":12,"truncated":true,"indices":[1,4]}"#), "{}", json);
+        let json = CombinationPayload { entries: &selected, format: ResultFormat { discovery_order: true, ..capped } }.to_json();
+        assert!(json.ends_with(r#""indices":[5,1]}"#), "{}", json);
+        // At the cap exactly, nothing is truncated
+        let json = CombinationPayload { entries: &selected[..2], format: capped }.to_json();
+        assert!(!json.contains("truncated"), "{}", json);
+    }
+
+    #[test]
+    fn test_scored_combination() {
+        let selected = [entry(2500, 5), entry(1000, 1), entry(1000, 4)];
+        let round = ResultFormat { score: ScoreMode::PreferRound, ..Default::default() };
+        let json = CombinationPayload { entries: &selected, format: round }.to_json();
+        assert!(json.ends_with(r#""selection_order":[1,2,0],"count":3,"score":0.3889}"#), "{}", json);
+        let organic = ResultFormat { score: ScoreMode::PreferOrganic, max_elements: 2, ..Default::default() };
+        let json = CombinationPayload { entries: &selected, format: organic }.to_json();
+        assert!(json.ends_with(r#""truncated":true,"indices":[1,4],"score":0.6111}"#), "{}", json);
+    }
+
+    #[test]
+    fn test_zero_length_combination() {
+        let json = CombinationPayload { entries: &[], format: ResultFormat::default() }.to_json();
+        assert!(json.ends_with(r#""indices":[],"values":[],"selection_order":[],"count":0}"#), "{}", json);
+    }
+
+    #[test]
+    fn test_find_one_payload_found() {
+        let stats = SolverStats {
+            phases: vec![PhaseStats { algorithm: "mitm", budget: None, nodes: 8 }],
+            count_window: Some((1, 2)),
+            value_bits: None,
+            shortcut: Some(Shortcut::None),
+            near_sums: None,
+            memory_fallback: None,
+            collapsed_to: None,
+            restarts: None,
+            peak_memory_estimate: 4096,
+        };
+        let result = Ok(Some(vec![entry(9, 0)]));
+        let check = UniquenessCheck { uniqueness: Uniqueness::Unknown, nodes_explored: 100 };
+        let payload = FindOnePayload {
+            result: &result,
+            stats: StatsPayload { stats: &stats, uniqueness: Some((&check, 100)) },
+            target: Some(9),
+            skipped: &[SkippedIndex { index: 4, reason: SkipReason::OutOfRange }],
+            format: ResultFormat::default(),
+            exact_integers: false,
+            identical_value_matches: None,
+            cost: None,
+            adjustment: None,
+            skipped_adjustments: &[],
+            target_rounding: None,
+            fractional_inputs: None,
+        };
+        assert_eq!(
+            payload.to_json(),
+            concat!(
+                r#"{"status":"found","id":"a8c7f832281a39c5","indices":[0],"values":[9],"selection_order":[0],"count":1,"#,
+                r#""unique":"unknown","target":9,"stats":{"phases":[{"algorithm":"mitm","budget":null,"nodes":8}],"#,
+                r#""count_window":[1,2],"shortcut":"none","peak_memory_estimate_bytes":4096,"#,
+                r#""uniqueness_budget":100,"uniqueness_nodes":100},"#,
+                r#""skipped":[{"index":4,"reason":"out_of_range"}]}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_find_one_payload_not_found_and_cancelled() {
+        let stats = SolverStats::default();
+        let stats_payload = || StatsPayload { stats: &stats, uniqueness: None };
+        let not_found = FindOnePayload {
+            result: &Ok(None),
+            stats: stats_payload(),
+            target: None,
+            skipped: &[],
+            format: ResultFormat::default(),
+            exact_integers: false,
+            identical_value_matches: None,
+            cost: None,
+            adjustment: None,
+            skipped_adjustments: &[],
+            target_rounding: None,
+            fractional_inputs: None,
+        };
+        assert_eq!(not_found.to_json(), r#"{"status":"not_found","stats":{"phases":[],"count_window":null}}"#);
+        let exact = FindOnePayload { exact_integers: true, ..not_found };
+        assert_eq!(exact.to_json(), r#"{"status":"not_found","exact_integers":true,"stats":{"phases":[],"count_window":null}}"#);
+        let cancelled = FindOnePayload { result: &Err(SolveError::Cancelled), stats: stats_payload(), ..not_found };
+        assert_eq!(cancelled.to_json(), r#"{"status":"cancelled","stats":{"phases":[],"count_window":null}}"#);
+        let empty = FindOnePayload { result: &Err(SolveError::EmptyInput), stats: stats_payload(), ..not_found };
+        assert_eq!(empty.to_json(), r#"{"status":"not_found","reason":"empty_input","stats":{"phases":[],"count_window":null}}"#);
+        let unproven = FindOnePayload {
+            stats: stats_payload(),
+            cost: Some(CostSummary { total_cost: None, proven: false }),
+            ..not_found
+        };
+        assert_eq!(unproven.to_json(), r#"{"status":"not_found","optimal":false,"stats":{"phases":[],"count_window":null}}"#);
+        let cheapest = Ok(Some(vec![entry(9, 0)]));
+        let summary = CostSummary { total_cost: Some(-2.5), proven: true };
+        let found = FindOnePayload { result: &cheapest, stats: stats_payload(), cost: Some(summary), ..not_found };
+        assert!(found.to_json().contains(r#""count":1,"total_cost":-2.5,"optimal":true,"stats""#), "{}", found.to_json());
+        let cancelled = FindOnePayload { result: &Err(SolveError::Cancelled), stats: stats_payload(), ..found };
+        assert!(!cancelled.to_json().contains("optimal"));
+        let near = SolverStats {
+            near_sums: Some(NearSums {
+                nearest_below: Some(NearMiss { sum: 3995, indices: vec![OriginalIndex::new(0), OriginalIndex::new(2)], diff: 5 }),
+                nearest_above: None,
+                radius: 40,
+                achievable_within: vec![3995],
+                truncated: true,
+                exhaustive: true,
+            }),
+            ..SolverStats::default()
+        };
+        let explained = FindOnePayload { stats: StatsPayload { stats: &near, uniqueness: None }, ..not_found };
+        assert_eq!(
+            explained.to_json(),
+            concat!(
+                r#"{"status":"not_found","explain":{"nearest_below":{"sum":3995,"indices":[0,2],"diff":5},"nearest_above":null,"radius":40,"#,
+                r#""achievable_within":[3995],"truncated":true},"stats":{"phases":[],"count_window":null}}"#,
+            ),
+        );
+        // A B&B's misses are only the ones its walk met
+        let walked = SolverStats {
+            near_sums: Some(NearSums {
+                nearest_above: Some(NearMiss { sum: 4002, indices: vec![OriginalIndex::new(1)], diff: 2 }),
+                ..NearSums::default()
+            }),
+            ..SolverStats::default()
+        };
+        let explained = FindOnePayload { stats: StatsPayload { stats: &walked, uniqueness: None }, ..not_found };
+        assert_eq!(
+            explained.to_json(),
+            concat!(
+                r#"{"status":"not_found","explain":{"nearest_below":null,"nearest_above":{"sum":4002,"indices":[1],"diff":2},"#,
+                r#""exhaustive":false},"stats":{"phases":[],"count_window":null}}"#,
+            ),
+        );
+        let adjusted = FindOnePayload {
+            stats: stats_payload(),
+            target: Some(1250),
+            adjustment: Some(2.5),
+            skipped_adjustments: &[-12.5, -10.0],
+            ..not_found
+        };
+        assert_eq!(
+            adjusted.to_json(),
+            r#"{"status":"not_found","target":1250,"adjustment":2.5,"skipped_adjustments":[-12.5,-10],"stats":{"phases":[],"count_window":null}}"#,
+        );
+        let rounded = FindOnePayload {
+            stats: stats_payload(),
+            target_rounding: Some(TargetRounding { units: 10001, up: true }),
+            ..not_found
+        };
+        assert_eq!(
+            rounded.to_json(),
+            r#"{"status":"not_found","target_rounded_to":{"units":10001,"direction":"up"},"stats":{"phases":[],"count_window":null}}"#,
+        );
+        let truncated = FindOnePayload {
+            stats: stats_payload(),
+            fractional_inputs: Some(FractionalInputs { fractional: 5, considered: 6, suggested_scale: Some(2) }),
+            ..not_found
+        };
+        assert_eq!(
+            truncated.to_json(),
+            concat!(
+                r#"{"status":"not_found","warnings":["fractional_inputs_truncated: 83% of values had decimals; consider scale=2"],"#,
+                r#""stats":{"phases":[],"count_window":null}}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_single_step_payload() {
+        let running = SingleStepPayload {
+            found: None,
+            finished: false,
+            nodes_explored: 42,
+            progress: 0.5,
+            stage: Stage::Searching,
+            path: Some(PathSnapshot { indices: vec![OriginalIndex::new(3), OriginalIndex::new(1)], len: 5, sum: 17 }),
+            format: ResultFormat::default(),
+        };
+        assert_eq!(
+            running.to_json(),
+            concat!(
+                r#"{"status":"running","nodes_explored":42,"progress":0.500000,"stage":"searching","#,
+                r#""diagnostics":{"unstable":true,"path":[3,1],"path_len":5,"partial_sum":17}}"#,
+            ),
+        );
+        let found = [entry(9, 0)];
+        let done = SingleStepPayload { found: Some(&found), finished: true, progress: 1.0, stage: Stage::Finalizing, path: None, ..running };
+        assert_eq!(
+            done.to_json(),
+            concat!(
+                r#"{"status":"found","id":"a8c7f832281a39c5","indices":[0],"values":[9],"selection_order":[0],"count":1,"#,
+                r#""nodes_explored":42,"progress":1.000000,"stage":"finalizing"}"#,
+            ),
+        );
+        let not_found = SingleStepPayload { found: None, ..done };
+        assert_eq!(not_found.to_json(), r#"{"status":"not_found","nodes_explored":42,"progress":1.000000,"stage":"finalizing"}"#);
+    }
+
+    #[test]
+    fn test_self_test_payload() {
+        let cases = [
+            CaseOutcome { name: "find_all", passed: true, detail: "20 combinations".to_string() },
+            CaseOutcome { name: "cancellation", passed: false, detail: "mitm returned \"NotFound\"".to_string() },
+        ];
+        assert_eq!(
+            SelfTestPayload(&cases).to_json(),
+            format!(
+                concat!(
+                    r#"{{"passed":false,"cases":[{{"name":"find_all","passed":true,"detail":"20 combinations"}},"#,
+                    r#"{{"name":"cancellation","passed":false,"detail":"mitm returned \"NotFound\""}}],"version":"{}"}}"#,
+                ),
+                export::CRATE_VERSION,
+            ),
+        );
+        assert!(SelfTestPayload(&cases[..1]).to_json().starts_with(r#"{"passed":true,"#));
+    }
+
+    #[test]
+    fn test_prognosis_payload() {
+        use crate::solver::{Difficulty, Infeasibility};
+
+        let prognosis = Prognosis {
+            n: 45,
+            algorithms: vec!["bnb", "mitm"],
+            count_window: Some((2, 9)),
+            infeasible: None,
+            worst_case_nodes: 17_582_912.4,
+            difficulty: Difficulty::Seconds,
+            mitm_memory_bytes: Some(48 << 22),
+        };
+        assert_eq!(
+            PrognosisPayload { prognosis: &prognosis, skipped: 3 }.to_json(),
+            concat!(
+                r#"{"n":45,"skipped":3,"algorithms":["bnb","mitm"],"count_window":[2,9],"infeasible":null,"#,
+                r#""worst_case_nodes":17582912,"difficulty":"seconds","mitm_memory_bytes":201326592,"#,
+                r#""mitm_memory_over_budget":false}"#,
+            ),
+        );
+        let infeasible = Prognosis {
+            n: 0,
+            algorithms: vec![],
+            count_window: None,
+            infeasible: Some(Infeasibility::NoEntries),
+            worst_case_nodes: 0.0,
+            difficulty: Difficulty::Instant,
+            mitm_memory_bytes: None,
+        };
+        assert_eq!(
+            PrognosisPayload { prognosis: &infeasible, skipped: 0 }.to_json(),
+            concat!(
+                r#"{"n":0,"skipped":0,"algorithms":[],"count_window":null,"infeasible":"no_entries","#,
+                r#""worst_case_nodes":0,"difficulty":"instant","mitm_memory_bytes":null,"mitm_memory_over_budget":false}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_destroyed_payload() {
+        let running = DestroyedPayload {
+            total_found: 3,
+            nodes_explored: 42,
+            finished: false,
+            termination_reason: None,
+            undelivered: &[],
+            undelivered_omitted: 0,
+            format: ResultFormat::default(),
+        };
+        assert_eq!(
+            running.to_json(),
+            r#"{"total_found":3,"nodes_explored":42,"finished":false,"undelivered_results":[]}"#,
+        );
+        let pending = [vec![entry(9, 0)]];
+        let finished = DestroyedPayload {
+            finished: true,
+            termination_reason: Some(TerminationReason::MaxResults),
+            undelivered: &pending,
+            undelivered_omitted: 2,
+            ..running
+        };
+        assert_eq!(
+            finished.to_json(),
+            concat!(
+                r#"{"total_found":3,"nodes_explored":42,"finished":true,"termination_reason":"max_results","#,
+                r#""undelivered_results":[{"id":"a8c7f832281a39c5","indices":[0],"values":[9],"selection_order":[0],"count":1}],"#,
+                r#""undelivered_omitted":2}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_batch_step_payload() {
+        let empty = batch_result(vec![], true, 1.0);
+        assert_eq!(
+            step(&empty).to_json(),
+            concat!(
+                r#"{"new_results":[],"total_found":0,"nodes_explored":42,"finished":true,"space_fully_explored":true,"progress":1.000000,"#,
+                r#""count_window":[1,3],"open_branches":0,"results_rate":0.000,"stage":"finalizing","termination_reason":"exhausted","space_exhausted":true}"#,
+            ),
+        );
+        let usage = [UsageCount { index: OriginalIndex::new(4), value: 7, count: 2 }];
+        let json = BatchStepPayload { top_usage: Some(&usage), ..step(&empty) }.to_json();
+        assert!(json.ends_with(r#""space_exhausted":true,"top_usage":[{"index":4,"count":2,"value":7}]}"#), "{}", json);
+
+        let some = batch_result(vec![vec![entry(9, 0)]], false, 0.25);
+        let skipped = [SkippedIndex { index: 2, reason: SkipReason::OutOfRange }];
+        assert_eq!(
+            BatchStepPayload { skipped: &skipped, ..step(&some) }.to_json(),
+            concat!(
+                r#"{"new_results":[{"id":"a8c7f832281a39c5","indices":[0],"values":[9],"selection_order":[0],"count":1,"seq":0}],"#,
+                r#""total_found":1,"nodes_explored":42,"finished":false,"space_fully_explored":false,"progress":0.250000,"count_window":[1,3],"#,
+                r#""open_branches":12,"results_rate":0.000,"first_seq":0,"last_seq":0,"stage":"searching","skipped":[{"index":2,"reason":"out_of_range"}]}"#,
+            ),
+        );
+        let stuck = BatchResult { likely_long_running: true, ..batch_result(vec![], false, 0.5) };
+        let json = step(&stuck).to_json();
+        assert!(json.ends_with(r#""count_window":[1,3],"open_branches":12,"results_rate":0.000,"stage":"searching","likely_long_running":true}"#), "{}", json);
+        let region = CurrentRegion { min_index: OriginalIndex::new(4812), max_index: OriginalIndex::new(9001), partial_sum: 77, depth: 3 };
+        let sparse = BatchResult { current_region: Some(region), results_rate: 1.0 / 3.0, ..batch_result(vec![], false, 0.5) };
+        let json = step(&sparse).to_json();
+        assert!(json.ends_with(concat!(
+            r#""open_branches":12,"results_rate":0.333,"stage":"searching","#,
+            r#""current_region":{"min_index":4812,"max_index":9001,"partial_sum":77,"depth":3}}"#,
+        )), "{}", json);
+
+        // Exhausted: the claim comes with its cost; truncated: more may exist
+        let json = BatchStepPayload { elapsed_ms: Some(17), ..step(&empty) }.to_json();
+        assert!(json.ends_with(r#""termination_reason":"exhausted","space_exhausted":true,"elapsed_ms":17}"#), "{}", json);
+        let capped = BatchResult { termination_reason: Some(TerminationReason::MaxResults), ..batch_result(vec![], true, 1.0) };
+        let json = BatchStepPayload { elapsed_ms: Some(17), ..step(&capped) }.to_json();
+        assert!(json.ends_with(r#""termination_reason":"max_results","space_exhausted":false,"more_may_exist":true}"#), "{}", json);
+
+        let windowed = BatchResult {
+            window_matches: vec![0b101],
+            per_window_found: vec![1, 0, 1],
+            first_seq: 41,
+            ..batch_result(vec![vec![entry(9, 0)]], false, 0.25)
+        };
+        let json = step(&windowed).to_json();
+        assert!(json.contains(r#""count":1,"seq":41}]"#), "{}", json);
+        assert!(json.ends_with(r#""results_rate":0.000,"first_seq":41,"last_seq":41,"stage":"searching","window_matches":[[0,2]],"per_window_found":[1,0,1]}"#), "{}", json);
+
+        let json = BatchStepPayload { exact_integers: true, ..step(&stuck) }.to_json();
+        assert!(json.ends_with(r#""open_branches":12,"results_rate":0.000,"stage":"searching","exact_integers":true,"likely_long_running":true}"#), "{}", json);
+
+        let quick = BatchResult {
+            phase: Some(SearchPhase::Quick),
+            next_phase: Some(SearchPhase::Exhaustive),
+            ..batch_result(vec![vec![entry(9, 0)]], false, 1.0)
+        };
+        let json = step(&quick).to_json();
+        assert!(json.ends_with(r#""first_seq":0,"last_seq":0,"stage":"searching","phase":"quick","next_phase":"exhaustive"}"#), "{}", json);
+
+        let deferred = BatchResult {
+            prework: Some(crate::batch::PreworkReport { deferred: vec!["sort", "suffix_sums", "count_window"], init_ms: 0.04, deferred_ms: 38.26 }),
+            ..batch_result(vec![], false, 0.5)
+        };
+        let json = step(&deferred).to_json();
+        assert!(json.ends_with(concat!(
+            r#""open_branches":12,"results_rate":0.000,"stage":"searching","prework_pending":true,"#,
+            r#""prework":{"deferred":["sort","suffix_sums","count_window"],"init_ms":0.0,"deferred_ms":38.3}}"#,
+        )), "{}", json);
+
+        let building = BatchResult {
+            stages: vec![Stage::Preparing, Stage::Prechecking, Stage::BuildingTables],
+            stage_progress: Some(0.375),
+            ..batch_result(vec![], false, 0.0)
+        };
+        let json = step(&building).to_json();
+        assert!(json.ends_with(concat!(
+            r#""results_rate":0.000,"stage":"building_tables","stage_progress":0.375000,"#,
+            r#""stages":["preparing","prechecking","building_tables"]}"#,
+        )), "{}", json);
+
+        let infeasible = BatchResult {
+            termination_reason: Some(TerminationReason::ProvedInfeasible),
+            precheck: Some(crate::batch::PrecheckReport { outcome: "infeasible", node_budget: 200_000, nodes: 64 }),
+            ..batch_result(vec![], true, 1.0)
+        };
+        let json = step(&infeasible).to_json();
+        assert!(json.ends_with(concat!(
+            r#""open_branches":0,"results_rate":0.000,"stage":"finalizing","precheck":{"outcome":"infeasible","node_budget":200000,"nodes":64},"#,
+            r#""termination_reason":"proved_infeasible","space_exhausted":true}"#,
+        )), "{}", json);
+    }
+
+    #[test]
+    fn test_count_step_payload() {
+        assert_eq!(
+            CountStepPayload(&batch_result(vec![], false, 0.5)).to_json(),
+            r#"{"found_so_far":0,"nodes_explored":42,"finished":false,"progress":0.500000,"stage":"searching"}"#,
+        );
+    }
+
+    #[test]
+    fn test_error_payloads() {
+        assert_eq!(
+            ErrorPayload::from(RowSumError::Empty).to_json(),
+            stamped(r#"{"status":"error","code":"empty_target_rows"}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(RowSumError::InvalidIndex { index: 5, reason: SkipReason::NotFinite }).to_json(),
+            stamped(r#"{"status":"error","code":"invalid_target_index","index":5,"reason":"not_finite"}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(DecodeError::UnsupportedFormat(9)).to_json(),
+            stamped(r#"{"status":"error","code":"unsupported_format_version","format_version":9}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(InvalidInput::LengthMismatch { field: "weights", expected: 3, actual: 2 }).to_json(),
+            stamped(r#"{"status":"error","code":"length_mismatch","field":"weights","expected":3,"actual":2}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(InvalidInput::OutOfRange { field: "scale", max: 9, actual: 12 }).to_json(),
+            stamped(r#"{"status":"error","code":"scale_out_of_range","field":"scale","max":9,"actual":12}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(InvalidInput::TooManyEntries { max: 4, scanned: 7 }).to_json(),
+            stamped(r#"{"status":"error","code":"too_many_entries","max_entries":4,"scanned":7}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(OptionsError::Json(ParseError { offset: 3, message: "expected a key" })).to_json(),
+            stamped(r#"{"status":"error","code":"invalid_json","offset":3,"message":"expected a key"}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(OptionsError::Missing("target")).to_json(),
+            stamped(r#"{"status":"error","code":"missing_option","field":"target"}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(OptionsError::WrongType { field: "max_count", expected: "u32" }).to_json(),
+            stamped(r#"{"status":"error","code":"invalid_option","field":"max_count","expected":"u32"}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(OptionsError::TagTooLong { max: 256, actual: 300 }).to_json(),
+            stamped(r#"{"status":"error","code":"tag_too_long","max":256,"actual":300}"#),
+        );
+        assert_eq!(
+            ErrorPayload::from(TargetOverflow { scale: 4, max_scale: Some(3) }).to_json(),
+            stamped(r#"{"status":"error","code":"target_scale_overflow","scale":4,"max_scale":3}"#),
+        );
+        let solve_errors = [
+            (
+                SolveError::InvalidBounds { min_count: 3, max_count: 2 },
+                r#"{"status":"error","code":"invalid_bounds","min_count":3,"max_count":2}"#,
+            ),
+            (SolveError::EmptyInput, r#"{"status":"error","code":"empty_input"}"#),
+            (
+                SolveError::TooLarge { algorithm: "mitm", n: 70, max_n: 50 },
+                r#"{"status":"error","code":"algorithm_not_applicable","algorithm":"mitm","n":70,"max_n":50}"#,
+            ),
+            (SolveError::Cancelled, r#"{"status":"error","code":"cancelled"}"#),
+            (SolveError::OutOfMemory(OutOfMemory { bytes: 96 }), r#"{"status":"error","code":"out_of_memory","bytes":96}"#),
+        ];
+        for (err, json) in solve_errors {
+            assert_eq!(ErrorPayload::from(err).to_json(), stamped(json));
+        }
+        assert_eq!(
+            LegacyErrorPayload("no search initialized").to_json(),
+            stamped(r#"{"error":"no search initialized"}"#),
+        );
+        assert_eq!(ImportedPayload { total_found: 3 }.to_json(), r#"{"status":"ok","total_found":3}"#);
+        let changes = [
+            (MaxCountChange::Unchanged, r#"{"status":"ok","change":"unchanged"}"#),
+            (MaxCountChange::Resumed { reopened: 12 }, r#"{"status":"ok","change":"resumed","reopened":12}"#),
+            (MaxCountChange::Restarted { frontier_overflowed: false }, r#"{"status":"ok","change":"restarted"}"#),
+            (
+                MaxCountChange::Restarted { frontier_overflowed: true },
+                r#"{"status":"ok","change":"restarted","warning":"frontier_overflow"}"#,
+            ),
+        ];
+        for (change, json) in changes {
+            assert_eq!(MaxCountPayload(change).to_json(), json);
+        }
+    }
+
+    #[test]
+    fn test_tagged_payloads() {
+        assert_eq!(ImportedPayload { total_found: 2 }.tagged(None).to_json(), r#"{"status":"ok","total_found":2}"#);
+        assert_eq!(
+            ImportedPayload { total_found: 2 }.tagged(Some("panel \"α\" 🚀")).to_json(),
+            r#"{"tag":"panel \"α\" 🚀","status":"ok","total_found":2}"#,
+        );
+        assert_eq!(
+            ErrorPayload::new("not_scored").tagged(Some("")).to_json(),
+            stamped(r#"{"tag":"","status":"error","code":"not_scored"}"#),
+        );
+    }
+
+    #[test]
+    fn test_state_header_payload() {
+        let header = StateHeader {
+            format_version: 1,
+            crate_version: "0.0.0-\"x\"".to_string(),
+            options: BatchOptions {
+                target: u64::MAX,
+                scale: 2,
+                min_count: 1,
+                max_count: 4,
+                max_results: 10,
+                include_indices: None,
+            },
+            fingerprint: 1,
+            n: 0,
+            results_so_far: 0,
+            carried: CarriedOptions::default(),
+        };
+        assert_eq!(
+            StateHeaderPayload(&header).to_json(),
+            concat!(
+                r#"{"format_version":1,"crate_version":"0.0.0-\"x\"","compatible":false,"#,
+                r#""fingerprint":"0000000000000001","n":0,"results_so_far":0,"options":{"target":18446744073709551615,"#,
+                r#""scale":2,"min_count":1,"max_count":4,"max_results":10,"include_indices":null}}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_dataset_and_effective_entries_payloads() {
+        let summary = DatasetSummary {
+            input_len: 3,
+            count: 0,
+            min: None,
+            max: None,
+            total: 0,
+            top_duplicates: vec![],
+        };
+        assert_eq!(
+            DatasetSummaryPayload { scale: 0, summary: &summary }.to_json(),
+            r#"{"scale":0,"input_len":3,"count":0,"min":null,"max":null,"total":0,"top_duplicates":[]}"#,
+        );
+
+        let payload = EffectiveEntriesPayload {
+            target: 1000,
+            scale: 2,
+            rows: vec![(1.5, Ok(150)), (-1.0, Err(SkipReason::NonPositive))],
+        };
+        assert_eq!(
+            payload.to_json(),
+            r#"{"target":1000,"scale":2,"entries":[{"index":0,"value":1.5,"scaled":150},{"index":1,"skipped_reason":"non_positive"}]}"#,
+        );
+    }
+}
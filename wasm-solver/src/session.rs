@@ -0,0 +1,270 @@
+//! Support bundles: the active batch search's options, stats and results in one
+//! blob a user can attach to a ticket, to be described or replayed locally.
+//!
+//! Layout (little-endian):
+//!   magic "TSSN" | session_version u16 | compressed u8 | body
+//!
+//! The body is raw DEFLATE when compressed:
+//!   state format_version u16 | state header | stats | result rows | data
+//!
+//! Without the user's consent the body holds no values: the input is only known
+//! by its fingerprint and results by their rows. With it, data is the exported
+//! batch state, which carries the entries and every result.
+
+use crate::deflate;
+use crate::export::{ByteReader, ByteWriter, DecodeError, StateHeader};
+
+const MAGIC: &[u8; 4] = b"TSSN";
+pub const SESSION_VERSION: u16 = 1;
+/// Results listed by rows; an included state still holds all of them
+pub const MAX_SESSION_RESULTS: usize = 10_000;
+/// export_session's default cap on the body, before compression
+pub const DEFAULT_MAX_SESSION_BYTES: usize = 16 << 20;
+/// Largest body an import will inflate, so a small blob can't claim gigabytes
+const MAX_INFLATED_BYTES: usize = 256 << 20;
+
+/// What a session says about the input values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionData {
+    /// Exported without consent; only the fingerprint identifies the input
+    NotConsented,
+    /// Consented, but the state did not fit the size cap
+    OmittedForSize,
+    /// The exported batch state, as export_batch_state returns it
+    State(Vec<u8>),
+}
+
+impl SessionData {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionData::NotConsented => "not_consented",
+            SessionData::OmittedForSize => "omitted_for_size",
+            SessionData::State(_) => "included",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionStats {
+    pub nodes_explored: u64,
+    pub finished: bool,
+    pub termination_reason: Option<String>,
+    /// Since the search was started or imported
+    pub elapsed_ms: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub session_version: u16,
+    pub compressed: bool,
+    pub header: StateHeader,
+    pub stats: SessionStats,
+    /// Original rows of each collected result, in discovery order; at most
+    /// MAX_SESSION_RESULTS, fewer when trimmed to fit
+    pub results: Vec<Vec<u32>>,
+    pub data: SessionData,
+}
+
+impl Session {
+    fn write_body(&self, w: &mut ByteWriter) {
+        w.u16(self.header.format_version);
+        self.header.write(w);
+        w.u64(self.stats.nodes_explored);
+        w.u8(self.stats.finished as u8);
+        match &self.stats.termination_reason {
+            None => w.u8(0),
+            Some(reason) => {
+                w.u8(1);
+                w.str(reason);
+            }
+        }
+        w.u64(self.stats.elapsed_ms.to_bits());
+        w.usize(self.results.len());
+        for rows in &self.results {
+            w.usize(rows.len());
+            for &row in rows {
+                w.u32(row);
+            }
+        }
+        match &self.data {
+            SessionData::NotConsented => w.u8(0),
+            SessionData::OmittedForSize => w.u8(1),
+            SessionData::State(state) => {
+                w.u8(2);
+                w.usize(state.len());
+                for &b in state {
+                    w.u8(b);
+                }
+            }
+        }
+    }
+
+    fn read_body(r: &mut ByteReader, session_version: u16, compressed: bool) -> Result<Session, DecodeError> {
+        let format_version = r.u16()?;
+        let header = StateHeader::read(r, format_version)?;
+        let stats = SessionStats {
+            nodes_explored: r.u64()?,
+            finished: r.bool()?,
+            termination_reason: if r.bool()? { Some(r.str()?) } else { None },
+            elapsed_ms: f64::from_bits(r.u64()?),
+        };
+        let len = r.len(8)?;
+        let results = (0..len)
+            .map(|_| {
+                let rows = r.len(4)?;
+                (0..rows).map(|_| r.u32()).collect::<Result<Vec<u32>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let data = match r.u8()? {
+            0 => SessionData::NotConsented,
+            1 => SessionData::OmittedForSize,
+            2 => {
+                let len = r.len(1)?;
+                SessionData::State((0..len).map(|_| r.u8()).collect::<Result<Vec<u8>, _>>()?)
+            }
+            _ => return Err(DecodeError::Corrupt),
+        };
+        Ok(Session { session_version, compressed, header, stats, results, data })
+    }
+
+    /// Bring the body within `max_bytes`: drop the state first, then results
+    /// from the end.
+    pub fn fit(&mut self, max_bytes: usize) {
+        let mut w = ByteWriter::new();
+        self.write_body(&mut w);
+        let mut len = w.into_bytes().len();
+        if len > max_bytes {
+            if let SessionData::State(state) = &self.data {
+                len -= 8 + state.len();
+                self.data = SessionData::OmittedForSize;
+            }
+        }
+        while len > max_bytes {
+            let Some(rows) = self.results.pop() else { break };
+            len -= 8 + 4 * rows.len();
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = ByteWriter::new();
+        self.write_body(&mut body);
+        let mut body = body.into_bytes();
+        if self.compressed {
+            body = deflate::deflate(&body);
+        }
+        let mut w = ByteWriter::new();
+        for &b in MAGIC {
+            w.u8(b);
+        }
+        w.u16(SESSION_VERSION);
+        w.u8(self.compressed as u8);
+        let mut bytes = w.into_bytes();
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Session, DecodeError> {
+        if bytes.get(..4) != Some(MAGIC.as_slice()) {
+            return Err(DecodeError::BadMagic);
+        }
+        let mut r = ByteReader::new(&bytes[4..]);
+        let session_version = r.u16()?;
+        if session_version != SESSION_VERSION {
+            return Err(DecodeError::UnsupportedFormat(session_version));
+        }
+        let compressed = r.bool()?;
+        let rest = &bytes[7..];
+        let inflated;
+        let body = if compressed {
+            inflated = deflate::inflate(rest, MAX_INFLATED_BYTES)?;
+            &inflated[..]
+        } else {
+            rest
+        };
+        let mut r = ByteReader::new(body);
+        let session = Session::read_body(&mut r, session_version, compressed)?;
+        if !r.is_empty() {
+            return Err(DecodeError::Corrupt);
+        }
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{BatchOptions, CarriedOptions, CRATE_VERSION, FORMAT_VERSION};
+
+    fn session(data: SessionData, compressed: bool) -> Session {
+        Session {
+            session_version: SESSION_VERSION,
+            compressed,
+            header: StateHeader {
+                format_version: FORMAT_VERSION,
+                crate_version: CRATE_VERSION.to_string(),
+                options: BatchOptions {
+                    target: 500,
+                    scale: 2,
+                    min_count: 1,
+                    max_count: 4,
+                    max_results: 0,
+                    include_indices: None,
+                },
+                fingerprint: 0x1234,
+                n: 40,
+                results_so_far: 300,
+                carried: CarriedOptions::default(),
+            },
+            stats: SessionStats {
+                nodes_explored: 98_765,
+                finished: true,
+                termination_reason: Some("exhausted".to_string()),
+                elapsed_ms: 12.5,
+            },
+            results: (0..300u32).map(|i| vec![i % 40, (i * 7) % 40, 39]).collect(),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_with_and_without_data() {
+        for compressed in [false, true] {
+            for data in [SessionData::NotConsented, SessionData::OmittedForSize, SessionData::State(vec![7; 5000])] {
+                let original = session(data, compressed);
+                let bytes = original.encode();
+                assert_eq!(Session::decode(&bytes).unwrap(), original);
+            }
+        }
+        let plain = session(SessionData::State(vec![7; 5000]), false).encode();
+        let packed = session(SessionData::State(vec![7; 5000]), true).encode();
+        assert!(packed.len() < plain.len() / 4);
+    }
+
+    #[test]
+    fn test_fit_drops_state_then_results() {
+        let mut s = session(SessionData::State(vec![0; 5000]), false);
+        s.fit(1 << 20);
+        assert!(matches!(s.data, SessionData::State(_)));
+        s.fit(4000);
+        assert_eq!(s.data, SessionData::OmittedForSize);
+        assert!(s.results.len() < 300 && !s.results.is_empty());
+        let mut w = ByteWriter::new();
+        s.write_body(&mut w);
+        assert!(w.into_bytes().len() <= 4000);
+        // Earlier results are the ones kept
+        assert_eq!(s.results[1], vec![1, 7, 39]);
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_input() {
+        let bytes = session(SessionData::NotConsented, true).encode();
+        assert_eq!(Session::decode(b"TSBS\x01\x00"), Err(DecodeError::BadMagic));
+        assert_eq!(Session::decode(&bytes[..bytes.len() - 3]).unwrap_err(), DecodeError::Truncated);
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert_eq!(Session::decode(&future), Err(DecodeError::UnsupportedFormat(99)));
+        let mut extra = session(SessionData::NotConsented, false).encode();
+        extra.push(0);
+        assert_eq!(Session::decode(&extra), Err(DecodeError::Corrupt));
+    }
+}
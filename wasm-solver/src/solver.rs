@@ -1,520 +1,3743 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::hash::Hasher;
+use std::ops::{ControlFlow, Range};
+use crate::batch::BatchSearchState;
+use crate::control::SearchController;
+use crate::log::{Level, log};
+use crate::utils::{Fnv1a, OutOfMemory, SplitMix64, StableHashMap, try_reserve};
+
+/// A row's position in the caller's raw input, before include lists, skipped
+/// rows, exclusions and sorting: what every payload reports. Positions the
+/// batch DFS keeps in its sorted array are SortedIndex, so the two cannot be
+/// mixed up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OriginalIndex(u32);
+
+impl OriginalIndex {
+    /// Inputs are capped at MAX_INPUT_LEN rows (and imported states checked
+    /// against it), so every position in one fits.
+    pub fn new(index: usize) -> Self {
+        OriginalIndex(u32::try_from(index).expect("row indices are below MAX_INPUT_LEN"))
+    }
+
+    /// For a u32 payload
+    pub fn get(self) -> u32 {
+        self.0
+    }
+
+    /// For arrays parallel to the raw input (row ids, costs, the numbers themselves)
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A row's position in a search's sorted input, as the batch DFS keeps it on
+/// its path, frames and cut paths. Not a usize, so it cannot index `numbers`,
+/// row ids or costs by mistake; `get()` where it is counted from or compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortedIndex(usize);
+
+impl SortedIndex {
+    pub fn new(position: usize) -> Self {
+        SortedIndex(position)
+    }
+
+    pub fn get(self) -> usize {
+        self.0
+    }
+
+    /// The position after this one, where a frame below it starts.
+    pub fn next(self) -> usize {
+        self.0 + 1
+    }
+}
 
 /// A number with its original position in the CSV input.
 #[derive(Clone, Debug)]
 pub struct NumberEntry {
     pub value: u64,
-    pub original_index: usize,
+    pub original_index: OriginalIndex,
+}
+
+impl NumberEntry {
+    /// original_index for a u32 payload.
+    pub fn index_u32(&self) -> u32 {
+        self.original_index.get()
+    }
+
+    /// Sort key: by value, equal values by original index. A total order, so
+    /// every sort of the same rows, stable or not, on any std, agrees.
+    pub fn value_order(&self) -> (u64, OriginalIndex) {
+        (self.value, self.original_index)
+    }
+}
+
+/// Stable 64-bit id for a combination: FNV-1a over its sorted original indices.
+///
+/// Independent of element order and of the algorithm that found it, so the same
+/// index set always maps to the same id. At 64 bits, collisions only become
+/// plausible around billions of distinct combinations, far beyond max_results.
+pub fn combination_id(entries: &[NumberEntry]) -> u64 {
+    let mut indices: Vec<u64> = entries.iter().map(|e| u64::from(e.index_u32())).collect();
+    indices.sort_unstable();
+
+    let mut hash = Fnv1a::new();
+    for idx in indices {
+        hash.write_u64(idx);
+    }
+    hash.finish()
+}
+
+/// Put a result set in canonical order: each combination by original index,
+/// then combinations lexicographically by their index lists. Two searches that
+/// found the same combinations compare equal afterwards, whatever their order.
+/// Used by tests; JS canonicalizes payloads through `canonicalize_results` in lib.rs.
+#[allow(dead_code)]
+pub fn canonicalize_results(results: &mut [Vec<NumberEntry>]) {
+    for combo in results.iter_mut() {
+        combo.sort_by_key(|e| e.original_index);
+    }
+    results.sort_by(|a, b| {
+        a.iter().map(|e| e.original_index).cmp(b.iter().map(|e| e.original_index))
+    });
 }
 
 pub struct SolverConfig<'a> {
     pub target: u64,
     pub min_count: usize,
     pub max_count: usize,
-    pub cancelled: &'a AtomicBool,
+    /// Asked at every checkpoint whether to stop (see control.rs)
+    pub controller: &'a dyn SearchController,
+    /// Node budget for the first B&B attempt when 40 < n <= 60, before falling
+    /// back, and for each attempt before the unlimited one past 60 (see restart_bnb).
+    pub hybrid_bb_budget: u64,
+    /// Caller-assigned row ids by original index: rows sharing an id are copies of
+    /// one physical row, and a combination uses at most one of them.
+    pub row_ids: Option<&'a [u32]>,
+    /// Most a combination's original indices may spread: max - min stays within
+    /// it, for rows that only belong together when close in the input.
+    pub max_index_span: Option<usize>,
+    /// Most bytes any one large table (the sorted copy of the input, a MITM
+    /// half's sums) may take; None leaves it to the allocator. Either way an
+    /// allocation that fails is reported instead of aborting.
+    pub memory_limit: Option<u64>,
 }
 
-pub enum SolverResult {
-    Found(Vec<NumberEntry>),
-    NotFound,
-    Cancelled,
-}
+impl SolverConfig<'_> {
+    /// Whether `candidate` has the row id of one of the `chosen` original indices.
+    pub fn row_taken(&self, mut chosen: impl Iterator<Item = OriginalIndex>, candidate: OriginalIndex) -> bool {
+        self.row_ids.is_some_and(|ids| chosen.any(|i| ids[i.as_usize()] == ids[candidate.as_usize()]))
+    }
 
-/// Preprocessed data: sorted entries plus suffix sums for pruning.
-/// No filtering here — input is already filtered by the caller (lib.rs).
-struct PreparedData {
-    /// Entries sorted by value ascending, with original indices preserved
-    sorted: Vec<NumberEntry>,
-    /// suffix_sum[i] = sum of sorted[i..].value
-    suffix_sum: Vec<u64>,
+    /// Whether `candidate` would spread the `chosen` original indices past
+    /// max_index_span.
+    pub fn outside_span(&self, chosen: impl Iterator<Item = OriginalIndex>, candidate: OriginalIndex) -> bool {
+        let Some(span) = self.max_index_span else { return false };
+        let (lo, hi) = chosen.fold((candidate, candidate), |(lo, hi), i| (lo.min(i), hi.max(i)));
+        hi.as_usize() - lo.as_usize() > span
+    }
+
+    /// Whether which rows may join a combination depends on the rows already in
+    /// it (row ids, an index span), which the shortcuts and memos built on
+    /// values alone cannot see.
+    pub fn path_dependent(&self) -> bool {
+        self.row_ids.is_some() || self.max_index_span.is_some()
+    }
 }
 
-impl PreparedData {
-    fn new(entries: &[NumberEntry]) -> Self {
-        let mut sorted: Vec<NumberEntry> = entries.to_vec();
-        sorted.sort_unstable_by_key(|e| e.value);
+/// Default first-attempt budget for the hybrid strategy (~a second of B&B).
+pub const DEFAULT_HYBRID_BB_BUDGET: u64 = 5_000_000;
 
-        let n = sorted.len();
-        let mut suffix_sum = vec![0u64; n + 1];
-        for i in (0..n).rev() {
-            suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
-        }
+/// One algorithm run inside solve_subset_sum, in the order they ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhaseStats {
+    /// "mitm", "bnb", "bnb_desc" (B&B restarted over descending values),
+    /// "mitm_sample" (find_random), "direct" (see `run_direct` and `run_shortcut`),
+    /// "mitm_min_cost" / "bnb_min_cost" (see mincost.rs) or "collapsed" (see collapse.rs)
+    pub algorithm: &'static str,
+    /// Node budget the phase ran under; None when unlimited
+    pub budget: Option<u64>,
+    /// B&B nodes visited, or subsets enumerated for MITM
+    pub nodes: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SolverStats {
+    pub phases: Vec<PhaseStats>,
+    /// [min_count, max_count] after intersecting with the data-derived bounds;
+    /// None when no count can work (the search was skipped)
+    pub count_window: Option<(usize, usize)>,
+    /// Width B&B ran its values in: 32 when every value and the target fit u32,
+    /// else 64; None when no B&B ran
+    pub value_bits: Option<u32>,
+    /// What the single/pair pre-pass settled; None when it did not run
+    pub shortcut: Option<Shortcut>,
+    /// Set when MITM over at most NEAR_SUMS_MAX_N entries proved there is no
+    /// match, or a find-one's B&B did (then not `exhaustive`)
+    pub near_sums: Option<NearSums>,
+    /// Bytes a MITM table would have needed when it did not fit and a B&B ran
+    /// in its place
+    pub memory_fallback: Option<u64>,
+    /// Distinct values the rows were collapsed into (see collapse.rs)
+    pub collapsed_to: Option<usize>,
+    /// Budgeted B&B attempts that stalled before the one that answered, when
+    /// any did (see restart_bnb)
+    pub restarts: Option<usize>,
+    /// Most bytes the search's big tables held at once, counted where they are
+    /// allocated: the prepared input plus a MITM table, near_sums' lists or a
+    /// reordered copy beside it. 0 when nothing was prepared
+    pub peak_memory_estimate: u64,
+}
 
-        PreparedData { sorted, suffix_sum }
+impl SolverStats {
+    /// Raise peak_memory_estimate to `bytes` held at once.
+    pub fn note_memory(&mut self, bytes: u64) {
+        self.peak_memory_estimate = self.peak_memory_estimate.max(bytes);
     }
 }
 
-/// Find ONE valid subset summing to target with count in [min_count, max_count].
-///
-/// Strategy:
-/// - n <= 40: meet-in-the-middle (handles any target size, exhaustive for small n)
-/// - n > 40: branch-and-bound DFS with aggressive pruning
-pub fn solve_subset_sum(entries: &[NumberEntry], config: &SolverConfig) -> SolverResult {
-    let data = PreparedData::new(entries);
-    let n = data.sorted.len();
+/// The achievable sums closest to a target no combination reaches, within the
+/// count window: a rounding difference shows up as a sum a few units off.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NearSums {
+    /// Largest achievable sum below the target
+    pub nearest_below: Option<NearMiss>,
+    /// Smallest achievable sum above it: 0.02 over points at a duplicated row
+    pub nearest_above: Option<NearMiss>,
+    /// Half-width of the band `achievable_within` covers: 1% of the target, at least 1
+    pub radius: u64,
+    /// Distinct achievable sums within `radius` of the target, ascending; the
+    /// MAX_NEAR_SUMS nearest when there are more
+    pub achievable_within: Vec<u128>,
+    /// More sums lay in the band than `achievable_within` holds
+    pub truncated: bool,
+    /// Every achievable sum was looked at (MITM). A B&B walk only offers the
+    /// misses it met (see MissTracker): a nearer one may exist, and radius
+    /// and achievable_within are left empty
+    pub exhaustive: bool,
+}
 
-    if n == 0 {
-        return SolverResult::NotFound;
-    }
+/// One combination reaching a sum next to the target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NearMiss {
+    pub sum: u128,
+    /// Rows reaching it, ascending; the lowest-valued set of the first count
+    /// pair that does
+    pub indices: Vec<OriginalIndex>,
+    /// How far it lies from the target
+    pub diff: u128,
+}
 
-    // Quick feasibility checks
-    if data.suffix_sum[0] < config.target {
-        return SolverResult::NotFound;
-    }
-    if config.min_count > n || config.max_count < 1 {
-        return SolverResult::NotFound;
-    }
-    if config.min_count > 0 {
-        let min_sum: u64 = data.sorted.iter().take(config.min_count).map(|e| e.value).sum();
-        if min_sum > config.target {
-            return SolverResult::NotFound;
-        }
-    }
+/// Largest input the not-found summary is computed for: its per-count sum
+/// lists hold 2^20 entries a half at most.
+pub const NEAR_SUMS_MAX_N: usize = 40;
 
-    if n <= 40 && config.max_count >= config.min_count {
-        if let Some(result) = meet_in_the_middle(&data, config) {
-            return SolverResult::Found(result);
-        }
-        if config.cancelled.load(Ordering::Relaxed) {
-            return SolverResult::Cancelled;
-        }
-        return SolverResult::NotFound;
-    }
+/// Most sums `achievable_within` lists.
+pub const MAX_NEAR_SUMS: usize = 32;
 
-    branch_and_bound_first(&data, config)
+/// Outcome of find_one's cheap pre-pass for one- and two-entry matches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shortcut {
+    /// Checked, no hit: the search ran
+    None,
+    SingleElement,
+    Pair,
 }
 
-/// Find ALL valid combinations (up to max_results).
-/// Used by tests; the WASM API uses BatchSearchState for streaming instead.
-#[allow(dead_code)]
-pub fn solve_all_combinations(
-    entries: &[NumberEntry],
-    config: &SolverConfig,
-    max_results: usize,
-) -> Vec<Vec<NumberEntry>> {
-    let data = PreparedData::new(entries);
-    let n = data.sorted.len();
+impl Shortcut {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shortcut::None => "none",
+            Shortcut::SingleElement => "single_element",
+            Shortcut::Pair => "pair",
+        }
+    }
+}
 
-    if n == 0 || data.suffix_sum[0] < config.target || config.min_count > n {
-        return Vec::new();
+/// Cardinality bounds implied by the data alone, for entries sorted ascending.
+///
+/// Any solution needs at least as many elements as the fewest largest values that
+/// reach `target` (which implies the ceil(target / largest) bound), and at most as
+/// many as the most smallest values that still fit under it. None when even all
+/// entries together fall short of `target`.
+pub fn count_bounds(sorted: &[NumberEntry], suffix_sum: &[u64], target: u64) -> Option<(usize, usize)> {
+    let n = sorted.len();
+    if suffix_sum[0] < target {
+        return None;
     }
-    if config.min_count > 0 {
-        let min_sum: u64 = data.sorted.iter().take(config.min_count).map(|e| e.value).sum();
-        if min_sum > config.target {
-            return Vec::new();
+
+    // suffix_sum[n - k] is the sum of the k largest values
+    let lo = (0..=n).find(|&k| suffix_sum[n - k] >= target)?;
+
+    let mut hi = 0;
+    let mut prefix = 0u64;
+    for e in sorted {
+        prefix = prefix.saturating_add(e.value);
+        if prefix > target {
+            break;
         }
+        hi += 1;
     }
 
-    let mut results = Vec::new();
-    let mut path = Vec::new();
-    let mut check_counter = 0u64;
-    branch_and_bound_all(
-        &data, config, 0, 0, &mut path, &mut results, max_results, &mut check_counter,
-    );
-    results
+    Some((lo, hi))
 }
 
-// ---------------------------------------------------------------------------
-// Meet-in-the-middle: split into two halves, enumerate all subsets per half,
-// find complementary pairs via hash map.
-// Time: O(2^(n/2)), Space: O(2^(n/2)). Works for n up to ~40.
-// ---------------------------------------------------------------------------
-
-fn meet_in_the_middle(data: &PreparedData, config: &SolverConfig) -> Option<Vec<NumberEntry>> {
-    let n = data.sorted.len();
-    let mid = n / 2;
-    let left = &data.sorted[..mid];
-    let right = &data.sorted[mid..];
+/// Intersect the caller's [min_count, max_count] with count_bounds.
+/// None when the intersection is empty, i.e. no solution can exist.
+pub fn tighten_count_window(
+    sorted: &[NumberEntry],
+    suffix_sum: &[u64],
+    target: u64,
+    min_count: usize,
+    max_count: usize,
+) -> Option<(usize, usize)> {
+    let (lo, hi) = count_bounds(sorted, suffix_sum, target)?;
+    let min = min_count.max(lo);
+    let max = max_count.min(hi);
+    (min <= max).then_some((min, max))
+}
 
-    let left_len = left.len();
-    let right_len = right.len();
-    let left_count = 1u64 << left_len;
+/// Whether exactly `k` more picks from `start` on can sum to `budget`, for values
+/// sorted either way with `suffix_sum` over them: the k smallest must fit under
+/// it and the k largest reach it, both read off the suffix sums in O(1). Always
+/// true once the suffix sums saturate, as their differences are then unknown.
+pub(crate) fn exact_picks_reach<V: LaneValue>(suffix_sum: &[V], start: usize, k: usize, budget: V, ascending: bool) -> bool {
+    let n = suffix_sum.len() - 1;
+    if n - start < k {
+        return false;
+    }
+    if suffix_sum[start] == V::MAX {
+        return true;
+    }
+    let head = suffix_sum[start] - suffix_sum[start + k];
+    let tail = suffix_sum[n - k];
+    let (smallest, largest) = if ascending { (head, tail) } else { (tail, head) };
+    smallest <= budget && budget <= largest
+}
 
-    // sum -> Vec<(count, bitmask)>
-    let mut left_map: HashMap<u64, Vec<(usize, u64)>> = HashMap::with_capacity(left_count as usize);
+/// The last one or two picks of a DFS branch over values sorted ascending, found
+/// by search instead of a recursion per row: a two-pointer scan for the pairs
+/// that sum to the budget, and a binary search for single rows equal to it.
+/// Hands out the completions in the order the recursion reaches them (pairs by
+/// first then second row, then singles, which are larger than any first row of
+/// a pair) at O(rows that fit) for the lot instead of O(rows that fit^2).
+/// `steps` counts the pointer moves and completions, the nodes to charge for it.
+pub(crate) struct LastPicks<V> {
+    budget: V,
+    start: usize,
+    /// Rows from `start` up to here fit under the budget
+    fit_end: usize,
+    /// The two pointers; pairs remain while lo < hi
+    lo: usize,
+    hi: usize,
+    /// Second rows still to hand out for `first`
+    partners: Range<usize>,
+    first: usize,
+    /// Singles are yet to be looked up
+    singles_pending: bool,
+    singles: Range<usize>,
+    pub steps: u64,
+}
 
-    for mask in 0..left_count {
-        if mask & 0xFFFF == 0 && config.cancelled.load(Ordering::Relaxed) {
-            return None;
-        }
-        let mut sum = 0u64;
-        let mut count = 0usize;
-        let mut overflow = false;
-        for bit in 0..left_len {
-            if mask & (1u64 << bit) != 0 {
-                sum += left[bit].value;
-                count += 1;
-                if sum > config.target {
-                    overflow = true;
-                    break;
-                }
-            }
-        }
-        if overflow { continue; }
-        if count <= config.max_count {
-            left_map.entry(sum).or_default().push((count, mask));
+impl<V: LaneValue> LastPicks<V> {
+    /// Over rows [start, end), whose ascending values `value` reads; `pair` and
+    /// `single` say whether two picks, or one, may complete the branch.
+    pub(crate) fn new(value: impl Fn(usize) -> V, start: usize, end: usize, budget: V, pair: bool, single: bool) -> Self {
+        let fit_end = partition_point(start, end, |i| value(i) <= budget);
+        LastPicks {
+            budget,
+            start,
+            fit_end,
+            lo: start,
+            hi: if pair { fit_end.saturating_sub(1) } else { start },
+            partners: 0..0,
+            first: start,
+            singles_pending: single,
+            singles: 0..0,
+            steps: 0,
         }
     }
 
-    let right_count = 1u64 << right_len;
-
-    for rmask in 0..right_count {
-        if rmask & 0xFFFF == 0 && config.cancelled.load(Ordering::Relaxed) {
-            return None;
-        }
-        let mut rsum = 0u64;
-        let mut rcount = 0usize;
-        let mut overflow = false;
-        for bit in 0..right_len {
-            if rmask & (1u64 << bit) != 0 {
-                rsum += right[bit].value;
-                rcount += 1;
-                if rsum > config.target {
-                    overflow = true;
-                    break;
-                }
+    /// The next completion: (first row, second row) for a pair, (row, None) for a single.
+    pub(crate) fn next(&mut self, value: impl Fn(usize) -> V) -> Option<(usize, Option<usize>)> {
+        loop {
+            if let Some(j) = self.partners.next() {
+                self.steps += 1;
+                return Some((self.first, Some(j)));
             }
-        }
-        if overflow { continue; }
-
-        let needed = config.target - rsum;
-        if let Some(left_entries) = left_map.get(&needed) {
-            for &(lcount, lmask) in left_entries {
-                let total_count = lcount + rcount;
-                if total_count >= config.min_count && total_count <= config.max_count {
-                    let mut result = Vec::with_capacity(total_count);
-                    for bit in 0..left_len {
-                        if lmask & (1u64 << bit) != 0 {
-                            result.push(left[bit].clone());
-                        }
-                    }
-                    for bit in 0..right_len {
-                        if rmask & (1u64 << bit) != 0 {
-                            result.push(right[bit].clone());
-                        }
-                    }
-                    result.sort_unstable_by_key(|e| e.original_index);
-                    return Some(result);
+            if self.lo < self.hi {
+                self.steps += 1;
+                let (low, high) = (value(self.lo), value(self.hi));
+                let wanted = self.budget - high;
+                if low > wanted {
+                    self.hi -= 1;
+                } else if low < wanted {
+                    self.lo += 1;
+                } else {
+                    // Every row between lo and hi equal to high pairs with lo; none
+                    // past hi can, as a smaller first row already overshot with it
+                    let (first, hi) = (self.lo, self.hi);
+                    self.partners = partition_point(first + 1, hi, |j| value(j) < high)..hi + 1;
+                    self.first = first;
+                    self.lo += 1;
                 }
+                continue;
+            }
+            if std::mem::take(&mut self.singles_pending) {
+                let budget = self.budget;
+                self.singles = partition_point(self.start, self.fit_end, |i| value(i) < budget)..self.fit_end;
             }
+            let single = self.singles.next()?;
+            self.steps += 1;
+            return Some((single, None));
         }
     }
+}
 
-    None
+/// First index in [lo, hi) where `below` turns false, for a `below` that is true
+/// up to some point and false after it.
+fn partition_point(mut lo: usize, mut hi: usize, below: impl Fn(usize) -> bool) -> usize {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if below(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
 }
 
-// ---------------------------------------------------------------------------
-// Branch-and-bound DFS with aggressive pruning.
-// ---------------------------------------------------------------------------
+/// What a find-one ends with: a combination, in the order its entries were
+/// selected (payloads sort them by index), None when the search proved there is
+/// none, or why it gave no answer.
+pub type SolveOutcome = Result<Option<Vec<NumberEntry>>, SolveError>;
 
-fn branch_and_bound_first(data: &PreparedData, config: &SolverConfig) -> SolverResult {
-    let mut path: Vec<usize> = Vec::with_capacity(config.max_count);
-    let mut check_counter = 0u64;
+/// Why a find-one ended without proving whether a combination exists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolveError {
+    /// min_count above max_count, so no combination could ever fit
+    InvalidBounds { min_count: usize, max_count: usize },
+    /// No usable entries to search
+    EmptyInput,
+    /// More usable entries than `algorithm` runs for
+    TooLarge { algorithm: &'static str, n: usize, max_n: usize },
+    Cancelled,
+    /// A table the search needed could not be allocated, and no lower-memory
+    /// algorithm could take over
+    OutOfMemory(OutOfMemory),
+}
 
-    match bb_dfs_first(data, config, 0, 0, 0, &mut path, &mut check_counter) {
-        BbResult::Found => {
-            let entries: Vec<NumberEntry> = path.iter()
-                .map(|&i| data.sorted[i].clone())
-                .collect();
-            SolverResult::Found(entries)
-        }
-        BbResult::Cancelled => SolverResult::Cancelled,
-        BbResult::NotFound => SolverResult::NotFound,
+impl From<OutOfMemory> for SolveError {
+    fn from(oom: OutOfMemory) -> Self {
+        SolveError::OutOfMemory(oom)
     }
 }
 
-enum BbResult {
-    Found,
-    NotFound,
-    Cancelled,
+/// What every find-one checks before searching: a window some count fits, and
+/// at least one entry.
+pub fn check_input(n: usize, config: &SolverConfig) -> Result<(), SolveError> {
+    if config.min_count > config.max_count {
+        return Err(SolveError::InvalidBounds { min_count: config.min_count, max_count: config.max_count });
+    }
+    if n == 0 {
+        return Err(SolveError::EmptyInput);
+    }
+    Ok(())
 }
 
-fn bb_dfs_first(
-    data: &PreparedData,
-    config: &SolverConfig,
-    start: usize,
-    current_sum: u64,
-    current_count: usize,
-    path: &mut Vec<usize>,
-    check_counter: &mut u64,
-) -> BbResult {
-    // Check cancellation every 4096 nodes (amortized cost of atomic load)
-    *check_counter = check_counter.wrapping_add(1);
-    if *check_counter & 0xFFF == 0 && config.cancelled.load(Ordering::Relaxed) {
-        return BbResult::Cancelled;
+/// The order a PreparedData holds its entries in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Order {
+    Ascending,
+    /// B&B must skip, not break, on oversized values
+    Descending,
+    /// B&B keeps only the prunes that hold in any order: skipping oversized
+    /// values, and the suffix-sum and rows-left breaks
+    Shuffled,
+}
+
+/// Preprocessed data: sorted entries plus suffix sums for pruning.
+/// No filtering here — input is already filtered by the caller (lib.rs).
+struct PreparedData {
+    /// Entries sorted by value ascending (unless `order` says otherwise), with
+    /// original indices preserved
+    sorted: Vec<NumberEntry>,
+    /// suffix_sum[i] = sum of sorted[i..].value
+    suffix_sum: Vec<u64>,
+    order: Order,
+    /// Every value fits u32, so B&B may run narrow lanes when the target does too
+    narrow_values: bool,
+}
+
+impl PreparedData {
+    /// Fails when the sorted copy or its suffix sums do not fit `memory_limit`
+    /// (see SolverConfig) or cannot be allocated.
+    fn new(entries: &[NumberEntry], memory_limit: Option<u64>) -> Result<Self, OutOfMemory> {
+        let mut sorted = Vec::new();
+        try_reserve(&mut sorted, entries.len(), memory_limit)?;
+        sorted.extend_from_slice(entries);
+        sorted.sort_unstable_by_key(NumberEntry::value_order);
+        Self::from_ordered(sorted, Order::Ascending, memory_limit)
     }
 
-    if current_sum == config.target && current_count >= config.min_count {
-        return BbResult::Found;
+    /// Same entries in descending value order, for B&B restart diversification.
+    /// Only called on ascending data.
+    fn reversed(&self, memory_limit: Option<u64>) -> Result<Self, OutOfMemory> {
+        debug_assert_eq!(self.order, Order::Ascending);
+        let mut sorted = Vec::new();
+        try_reserve(&mut sorted, self.sorted.len(), memory_limit)?;
+        sorted.extend(self.sorted.iter().rev().cloned());
+        Self::from_ordered(sorted, Order::Descending, memory_limit)
     }
 
-    if current_count >= config.max_count {
-        return BbResult::NotFound;
+    /// Same entries in an order drawn from `seed` (Fisher-Yates), for restarts
+    /// that neither value order gets out of.
+    fn shuffled(&self, seed: u64, memory_limit: Option<u64>) -> Result<Self, OutOfMemory> {
+        let mut sorted = Vec::new();
+        try_reserve(&mut sorted, self.sorted.len(), memory_limit)?;
+        sorted.extend_from_slice(&self.sorted);
+        let mut rng = SplitMix64::new(seed);
+        for i in (1..sorted.len()).rev() {
+            sorted.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        Self::from_ordered(sorted, Order::Shuffled, memory_limit)
     }
 
-    let n = data.sorted.len();
-    let remaining_needed = config.min_count.saturating_sub(current_count);
+    fn from_ordered(sorted: Vec<NumberEntry>, order: Order, memory_limit: Option<u64>) -> Result<Self, OutOfMemory> {
+        let n = sorted.len();
+        let mut suffix_sum = Vec::new();
+        try_reserve(&mut suffix_sum, n + 1, memory_limit)?;
+        suffix_sum.resize(n + 1, 0u64);
+        for i in (0..n).rev() {
+            suffix_sum[i] = suffix_sum[i + 1].saturating_add(sorted[i].value);
+        }
 
-    if n - start < remaining_needed {
-        return BbResult::NotFound;
+        let narrow_values = sorted.iter().all(|e| u32::try_from(e.value).is_ok());
+        Ok(PreparedData { sorted, suffix_sum, order, narrow_values })
     }
 
-    let remaining_budget = config.target - current_sum;
+    /// Heap bytes of the sorted copy and its suffix sums
+    fn bytes(&self) -> u64 {
+        (self.sorted.capacity() * size_of::<NumberEntry>() + self.suffix_sum.capacity() * size_of::<u64>()) as u64
+    }
+}
 
-    for i in start..n {
-        let value = data.sorted[i].value;
+/// A width B&B can run its values in. Suffix sums saturate; that is exact
+/// enough, as they are only compared against residuals, which never exceed the
+/// target and so always fit.
+pub(crate) trait LaneValue: Copy + Ord + std::ops::Add<Output = Self> + std::ops::Sub<Output = Self> {
+    const BITS: u32;
+    const ZERO: Self;
+    const MAX: Self;
+    /// Only called on values already known to fit
+    fn from_u64(value: u64) -> Self;
+    fn to_u64(self) -> u64;
+    fn saturating_add(self, other: Self) -> Self;
+}
 
-        // Since sorted ascending, once one element exceeds budget, all after do too
-        if value > remaining_budget {
-            break;
+macro_rules! lane_value {
+    ($ty:ty) => {
+        impl LaneValue for $ty {
+            const BITS: u32 = <$ty>::BITS;
+            const ZERO: Self = 0;
+            const MAX: Self = <$ty>::MAX;
+            fn from_u64(value: u64) -> Self {
+                value as $ty
+            }
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+            fn saturating_add(self, other: Self) -> Self {
+                <$ty>::saturating_add(self, other)
+            }
         }
+    };
+}
 
-        // If sum of all remaining elements can't reach target, prune
-        if data.suffix_sum[i] < remaining_budget {
-            break;
-        }
+lane_value!(u32);
+lane_value!(u64);
 
-        // Not enough elements left to meet min_count
-        if (n - i) < remaining_needed {
-            break;
+/// What the B&B hot loop reads, as dense value and suffix-sum arrays in the
+/// chosen width: 8 bytes per entry for u32 against 24 with NumberEntry and
+/// u64 sums. Entries stay in `data` for the row-id check and the result.
+struct BbInput<'a, V> {
+    data: &'a PreparedData,
+    config: &'a SolverConfig<'a>,
+    values: Vec<V>,
+    suffix_sum: Vec<V>,
+    target: V,
+}
+
+impl<'a, V: LaneValue> BbInput<'a, V> {
+    fn new(data: &'a PreparedData, config: &'a SolverConfig<'a>) -> Self {
+        let values: Vec<V> = data.sorted.iter().map(|e| V::from_u64(e.value)).collect();
+        let mut suffix_sum = vec![V::ZERO; values.len() + 1];
+        for i in (0..values.len()).rev() {
+            suffix_sum[i] = suffix_sum[i + 1].saturating_add(values[i]);
         }
+        BbInput { data, config, values, suffix_sum, target: V::from_u64(config.target) }
+    }
+}
 
-        path.push(i);
-        let result = bb_dfs_first(
-            data, config, i + 1,
-            current_sum + value,
-            current_count + 1,
-            path, check_counter,
-        );
+/// Which find-one algorithm runs: find_one_v2's `algorithm` option.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    /// Chosen by input size (see solve_subset_sum)
+    #[default]
+    Auto,
+    /// Meet-in-the-middle, up to MAX_FORCED_MITM_N usable entries
+    Mitm,
+    /// One unlimited B&B over ascending values (see solve_depth_first_with_stats)
+    Bnb,
+}
 
-        match result {
-            BbResult::Found => return BbResult::Found,
-            BbResult::Cancelled => return BbResult::Cancelled,
-            BbResult::NotFound => { path.pop(); }
+impl Algorithm {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(Algorithm::Auto),
+            "mitm" => Some(Algorithm::Mitm),
+            "bnb" => Some(Algorithm::Bnb),
+            _ => None,
         }
     }
 
-    BbResult::NotFound
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Algorithm::Auto => "auto",
+            Algorithm::Mitm => "mitm",
+            Algorithm::Bnb => "bnb",
+        }
+    }
 }
 
+/// Most usable entries a forced MITM runs for: the hybrid strategy's own limit,
+/// where the tables reach about a gigabyte.
+pub const MAX_FORCED_MITM_N: usize = 50;
+
+/// Find ONE valid subset summing to target with count in [min_count, max_count].
+///
+/// Strategy:
+/// - n <= 40: meet-in-the-middle (handles any target size, exhaustive for small n)
+/// - 40 < n <= 60: B&B under `hybrid_bb_budget`; if that stalls, full MITM for
+///   n <= 50, otherwise an unlimited B&B restart over descending values
+/// - n > 60: branch-and-bound DFS with aggressive pruning, restarted over other
+///   orderings when it stalls (see restart_bnb)
 #[allow(dead_code)]
-fn branch_and_bound_all(
-    data: &PreparedData,
+pub fn solve_subset_sum(entries: &[NumberEntry], config: &SolverConfig) -> SolveOutcome {
+    solve_subset_sum_with_stats(entries, config).0
+}
+
+/// solve_subset_sum, also reporting which algorithm phases ran.
+pub fn solve_subset_sum_with_stats(
+    entries: &[NumberEntry],
     config: &SolverConfig,
-    start: usize,
-    current_sum: u64,
-    path: &mut Vec<usize>,
-    results: &mut Vec<Vec<NumberEntry>>,
-    max_results: usize,
-    check_counter: &mut u64,
-) {
-    let current_count = path.len();
+) -> (SolveOutcome, SolverStats) {
+    solve_fresh(entries, config, Algorithm::Auto)
+}
 
-    // Periodic cancellation check (every 4096 nodes)
-    *check_counter = check_counter.wrapping_add(1);
-    if *check_counter & 0xFFF == 0 && config.cancelled.load(Ordering::Relaxed) {
-        return;
+/// Prepare `entries` and run `algorithm` over them once.
+fn solve_fresh(entries: &[NumberEntry], config: &SolverConfig, algorithm: Algorithm) -> (SolveOutcome, SolverStats) {
+    let mut stats = SolverStats::default();
+    let result = match PreparedData::new(entries, config.memory_limit) {
+        Ok(data) => solve_prepared(&data, config, algorithm, None, &mut stats),
+        Err(oom) => Err(SolveError::OutOfMemory(oom)),
+    };
+    (result, stats)
+}
+
+/// One input prepared once for a series of find-ones with different count
+/// windows or targets, e.g. tightening max_count until the match is small
+/// enough to review. Every solve returns exactly what solve_subset_sum_with_stats
+/// (or the forced algorithm's solve) would for the same entries and config; later
+/// ones skip the sort and, in unlimited ascending B&B runs, the subtrees earlier
+/// ones proved empty (see DeadEnds). The one exception is restart_bnb's first
+/// attempt, which skips them too: where that lets it finish in its budget and a
+/// fresh solve restarts, the session answers with the ascending walk's match.
+pub struct FindSession {
+    data: PreparedData,
+    dead_ends: RefCell<DeadEnds>,
+}
+
+impl FindSession {
+    /// Fails when the prepared input does not fit `memory_limit` (see SolverConfig).
+    pub fn new(entries: &[NumberEntry], memory_limit: Option<u64>) -> Result<Self, OutOfMemory> {
+        Ok(FindSession { data: PreparedData::new(entries, memory_limit)?, dead_ends: RefCell::new(DeadEnds::default()) })
     }
 
-    // Found a valid solution
-    if current_sum == config.target && current_count >= config.min_count {
-        let entries: Vec<NumberEntry> = path.iter()
-            .map(|&i| data.sorted[i].clone())
-            .collect();
-        results.push(entries);
-        if results.len() >= max_results {
-            return;
-        }
-        // All values are positive, so adding more elements would exceed target.
-        return;
+    pub fn solve(&self, config: &SolverConfig, algorithm: Algorithm) -> (SolveOutcome, SolverStats) {
+        let mut stats = SolverStats::default();
+        let mut dead_ends = self.dead_ends.borrow_mut();
+        let result = solve_prepared(&self.data, config, algorithm, Some(&mut dead_ends), &mut stats);
+        (result, stats)
+    }
+
+    /// The session's entries, sorted by value
+    pub fn entries(&self) -> &[NumberEntry] {
+        &self.data.sorted
     }
 
-    if current_count >= config.max_count || results.len() >= max_results {
-        return;
+    /// Subtrees remembered so far
+    #[cfg(test)]
+    pub fn dead_ends(&self) -> usize {
+        self.dead_ends.borrow().map.len()
     }
+}
+
+/// Find ONE subset with a single unlimited B&B over ascending values, whatever n.
+/// This is the same DFS order BatchSearchState walks, so the result equals the
+/// batch search's first result for the same input; solve_subset_sum's MITM may
+/// pick a different solution.
+pub fn solve_depth_first_with_stats(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+) -> (SolveOutcome, SolverStats) {
+    solve_fresh(entries, config, Algorithm::Bnb)
+}
+
+/// Find ONE subset with meet-in-the-middle alone, for up to MAX_FORCED_MITM_N
+/// entries; more are TooLarge.
+pub fn solve_mitm_with_stats(entries: &[NumberEntry], config: &SolverConfig) -> (SolveOutcome, SolverStats) {
+    solve_fresh(entries, config, Algorithm::Mitm)
+}
 
+/// solve_depth_first_with_stats with B&B held to u64 values, whatever they fit;
+/// the u32 lanes must find exactly the same combination.
+#[cfg(any(test, feature = "fuzzing"))]
+pub fn solve_depth_first_wide(entries: &[NumberEntry], config: &SolverConfig) -> (SolveOutcome, SolverStats) {
+    let mut data = PreparedData::new(entries, None).expect("out of memory preparing the input");
+    data.narrow_values = false;
+    let mut stats = SolverStats::default();
+    let result = solve_prepared(&data, config, Algorithm::Bnb, None, &mut stats);
+    (result, stats)
+}
+
+/// `dead_ends` is consulted and extended by the unlimited ascending B&B runs
+/// and restart_bnb's first attempt only; other budgeted ones leave it alone so a
+/// session's fallbacks match a fresh solve's.
+fn solve_prepared(
+    data: &PreparedData,
+    config: &SolverConfig,
+    algorithm: Algorithm,
+    dead_ends: Option<&mut DeadEnds>,
+    stats: &mut SolverStats,
+) -> SolveOutcome {
     let n = data.sorted.len();
-    let remaining_needed = config.min_count.saturating_sub(current_count);
+    stats.note_memory(data.bytes());
 
-    if n - start < remaining_needed {
-        return;
+    if algorithm == Algorithm::Mitm && n > MAX_FORCED_MITM_N {
+        return Err(SolveError::TooLarge { algorithm: algorithm.as_str(), n, max_n: MAX_FORCED_MITM_N });
     }
+    check_input(n, config)?;
 
-    let remaining_budget = config.target - current_sum;
-
-    for i in start..n {
-        let value = data.sorted[i].value;
+    let requested = (config.min_count, config.max_count);
+    // Quick feasibility: tighten the count window to what the data allows
+    let Some((min_count, max_count)) = tighten_count_window(
+        &data.sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return Ok(None);
+    };
+    stats.count_window = Some((min_count, max_count));
+    let config = &SolverConfig { min_count, max_count, ..*config };
+    log!(
+        Level::Info, "find_one: n={}, count window [{}, {}], {}", n, min_count, max_count,
+        match n {
+            _ if algorithm == Algorithm::Bnb => "depth-first bnb",
+            _ if algorithm == Algorithm::Mitm => "forced mitm",
+            0..=40 => "mitm",
+            41..=60 => "hybrid bnb",
+            _ => "bnb",
+        },
+    );
 
-        if value > remaining_budget {
-            break;
-        }
-        if data.suffix_sum[i] < remaining_budget {
-            break;
+    // The depth-first mode promises the batch search's first result, which
+    // need not be a one- or two-entry match even when one exists
+    if algorithm != Algorithm::Bnb {
+        if let Some(result) = run_shortcut(data, config, stats) {
+            return result;
+        }
+    }
+    match algorithm {
+        Algorithm::Auto => {}
+        Algorithm::Mitm => return run_mitm(data, config, Some(requested), stats),
+        Algorithm::Bnb => return unlimited_bnb(data, config, dead_ends, stats),
+    }
+    if let Some(result) = run_direct(data, config, stats) {
+        return result;
+    }
+    if n <= 40 {
+        return match run_mitm(data, config, Some(requested), stats) {
+            Err(SolveError::OutOfMemory(oom)) => {
+                note_memory_fallback(oom, "bnb", stats);
+                unlimited_bnb(data, config, dead_ends, stats)
+            }
+            result => result,
+        };
+    }
+
+    if n <= 60 {
+        match branch_and_bound_first(data, config, config.hybrid_bb_budget, None, true, stats) {
+            BbOutcome::Done(result) => return result,
+            BbOutcome::BudgetExhausted => {}
+        }
+        log!(
+            Level::Info, "find_one: bnb stalled at its {}-node budget, falling back to {}",
+            config.hybrid_bb_budget, if n <= 50 { "mitm" } else { "bnb_desc" },
+        );
+        if n <= 50 {
+            match run_mitm(data, config, Some(requested), stats) {
+                Err(SolveError::OutOfMemory(oom)) => note_memory_fallback(oom, "bnb_desc", stats),
+                result => return result,
+            }
+        }
+        return match data.reversed(config.memory_limit) {
+            Ok(reversed) => {
+                stats.note_memory(data.bytes() + reversed.bytes());
+                unlimited_bnb(&reversed, config, None, stats)
+            }
+            Err(oom) => Err(SolveError::OutOfMemory(oom)),
+        };
+    }
+
+    restart_bnb(data, config, dead_ends, stats)
+}
+
+/// Seeds of the shuffled orderings restart_bnb tries after the descending one.
+const RESTART_SEEDS: [u64; 2] = [0x9e37_79b9, 0x7f4a_7c15];
+
+/// B&B for n > 60, where the ascending order alone can sink a whole run into
+/// one dead region: ascending values under `hybrid_bb_budget`, then the same
+/// budget over descending values and over each RESTART_SEEDS shuffle, and only
+/// then the unlimited ascending walk. Any attempt that ends answers, a proven
+/// miss (with the nearest misses it met) included, since each walks the whole
+/// pruned tree of its order. The
+/// ascending attempts share `dead_ends` (see FindSession).
+fn restart_bnb(
+    data: &PreparedData,
+    config: &SolverConfig,
+    mut dead_ends: Option<&mut DeadEnds>,
+    stats: &mut SolverStats,
+) -> SolveOutcome {
+    let budget = config.hybrid_bb_budget;
+    if let BbOutcome::Done(result) = branch_and_bound_first(data, config, budget, dead_ends.as_deref_mut(), true, stats) {
+        return result;
+    }
+    let reorderings = [None].into_iter().chain(RESTART_SEEDS.map(Some));
+    for (restarts, seed) in (1..).zip(reorderings) {
+        log!(
+            Level::Info, "find_one: bnb stalled at its {}-node budget, restarting over {}",
+            budget, if seed.is_some() { "shuffled values" } else { "descending values" },
+        );
+        let reordered = match seed {
+            None => data.reversed(config.memory_limit)?,
+            Some(seed) => data.shuffled(seed, config.memory_limit)?,
+        };
+        stats.restarts = Some(restarts);
+        stats.note_memory(data.bytes() + reordered.bytes());
+        if let BbOutcome::Done(result) = branch_and_bound_first(&reordered, config, budget, None, true, stats) {
+            return result;
+        }
+    }
+    log!(Level::Info, "find_one: every restart stalled, running bnb unlimited");
+    // Both value orders and every shuffle
+    stats.restarts = Some(2 + RESTART_SEEDS.len());
+    unlimited_bnb(data, config, dead_ends, stats)
+}
+
+fn unlimited_bnb(
+    data: &PreparedData,
+    config: &SolverConfig,
+    dead_ends: Option<&mut DeadEnds>,
+    stats: &mut SolverStats,
+) -> SolveOutcome {
+    match branch_and_bound_first(data, config, u64::MAX, dead_ends, true, stats) {
+        BbOutcome::Done(result) => result,
+        BbOutcome::BudgetExhausted => unreachable!("unlimited B&B cannot exhaust its budget"),
+    }
+}
+
+/// Record that MITM's table did not fit and `instead` runs in its place.
+pub fn note_memory_fallback(oom: OutOfMemory, instead: &str, stats: &mut SolverStats) {
+    log!(Level::Info, "mitm: {} bytes do not fit, falling back to {}", oom.bytes, instead);
+    stats.memory_fallback = Some(oom.bytes);
+}
+
+/// Answers that need no search: a value equal to the target when single-element
+/// combinations are allowed (the first such in sorted order, so every algorithm
+/// picks the same representative of identical rows), or, when every value is the
+/// same v, the first target / v entries. Catches degenerate inputs, such as
+/// thousands of rows each equal to the target, before MITM builds its tables.
+fn direct_solution(data: &PreparedData, config: &SolverConfig) -> Option<Vec<NumberEntry>> {
+    let sorted = &data.sorted;
+    if let Some(single) = single_match(sorted, config) {
+        return Some(single);
+    }
+    let v = sorted.first()?.value;
+    // Row ids or a span could forbid any k of the copies; leave that to the search
+    if v == 0 || sorted.last()?.value != v || config.path_dependent() || !config.target.is_multiple_of(v) {
+        return None;
+    }
+    let k = usize::try_from(config.target / v).ok()?;
+    (config.min_count..=config.max_count.min(sorted.len())).contains(&k).then(|| sorted[..k].to_vec())
+}
+
+/// The first entry equal to the target in sorted order, when the window allows one.
+fn single_match(sorted: &[NumberEntry], config: &SolverConfig) -> Option<Vec<NumberEntry>> {
+    if config.min_count > 1 || config.max_count < 1 {
+        return None;
+    }
+    let first = sorted.partition_point(|e| e.value < config.target);
+    sorted.get(first).filter(|e| e.value == config.target).map(|e| vec![e.clone()])
+}
+
+/// Two entries summing to the target, when the window allows two: of all such
+/// pairs, the one with the lexicographically smallest original indices, as
+/// meet-in-the-middle would pick. Two pointers over runs of equal values, so
+/// O(n). None with row ids or a span, whose clashes are left to the search.
+fn pair_match(sorted: &[NumberEntry], config: &SolverConfig) -> Option<Vec<NumberEntry>> {
+    if config.min_count > 2 || config.max_count < 2 || config.path_dependent() {
+        return None;
+    }
+    // Positions in `sorted` of the smallest original indices in sorted[range]
+    let smallest_two = |range: std::ops::Range<usize>| -> (usize, Option<usize>) {
+        let mut best = (range.start, None::<usize>);
+        for p in range.start + 1..range.end {
+            let index = sorted[p].original_index;
+            if index < sorted[best.0].original_index {
+                best = (p, Some(best.0));
+            } else if best.1.is_none_or(|q| index < sorted[q].original_index) {
+                best.1 = Some(p);
+            }
+        }
+        best
+    };
+    let (mut lo, mut hi) = (0, sorted.len());
+    let mut best: Option<(usize, usize)> = None;
+    while lo < hi {
+        let (v, w) = (sorted[lo].value, sorted[hi - 1].value);
+        let lo_end = lo + sorted[lo..hi].partition_point(|e| e.value == v);
+        let hi_start = lo + sorted[lo..hi].partition_point(|e| e.value < w);
+        match v.saturating_add(w).cmp(&config.target) {
+            std::cmp::Ordering::Less => lo = lo_end,
+            std::cmp::Ordering::Greater => hi = hi_start,
+            std::cmp::Ordering::Equal => {
+                let pair = if v == w {
+                    let (first, second) = smallest_two(lo..lo_end);
+                    second.map(|second| (first, second))
+                } else {
+                    Some((smallest_two(lo..lo_end).0, smallest_two(hi_start..hi).0))
+                };
+                let key = |(a, b): (usize, usize)| {
+                    let (a, b) = (sorted[a].original_index, sorted[b].original_index);
+                    (a.min(b), a.max(b))
+                };
+                if let Some(pair) = pair.filter(|&pair| best.is_none_or(|best| key(pair) < key(best))) {
+                    best = Some(pair);
+                }
+                (lo, hi) = (lo_end, hi_start);
+            }
+        }
+    }
+    let (a, b) = best?;
+    let (a, b) = if sorted[a].original_index < sorted[b].original_index { (a, b) } else { (b, a) };
+    Some(vec![sorted[a].clone(), sorted[b].clone()])
+}
+
+/// The pre-pass ahead of every find_one algorithm but the depth-first one: a
+/// single entry equal to the target, then a pair summing to it, each only when
+/// the (tightened) count window allows that many. Spares MITM building its
+/// tables, and B&B its descent, for answers a lookup finds. Records the outcome
+/// in `stats.shortcut`, and a hit as a "direct" phase.
+fn run_shortcut(data: &PreparedData, config: &SolverConfig, stats: &mut SolverStats) -> Option<SolveOutcome> {
+    let (shortcut, found) = if let Some(found) = single_match(&data.sorted, config) {
+        (Shortcut::SingleElement, found)
+    } else if let Some(found) = pair_match(&data.sorted, config) {
+        (Shortcut::Pair, found)
+    } else {
+        stats.shortcut = Some(Shortcut::None);
+        return None;
+    };
+    stats.shortcut = Some(shortcut);
+    stats.phases.push(PhaseStats { algorithm: "direct", budget: None, nodes: 0 });
+    log!(Level::Info, "find_one: {} shortcut, no search", shortcut.as_str());
+    Some(Ok(Some(found)))
+}
+
+/// direct_solution as a phase; None when it does not apply.
+fn run_direct(data: &PreparedData, config: &SolverConfig, stats: &mut SolverStats) -> Option<SolveOutcome> {
+    let found = direct_solution(data, config)?;
+    stats.phases.push(PhaseStats { algorithm: "direct", budget: None, nodes: 0 });
+    log!(Level::Info, "find_one: answered directly with {} entries, no search", found.len());
+    Some(Ok(Some(found)))
+}
+
+/// How many entries equal the target: the single-element solutions a search
+/// reports once, through the first of them in sorted order.
+pub fn identical_target_matches(entries: &[NumberEntry], target: u64) -> usize {
+    entries.iter().filter(|e| e.value == target).count()
+}
+
+/// `near_window` is the count window the caller asked for, before tightening:
+/// given, a proven not-found over at most NEAR_SUMS_MAX_N entries records the
+/// achievable sums near the target within it.
+fn run_mitm(
+    data: &PreparedData,
+    config: &SolverConfig,
+    near_window: Option<(usize, usize)>,
+    stats: &mut SolverStats,
+) -> SolveOutcome {
+    let (mut nodes, mut table_bytes) = (0u64, 0u64);
+    let found = match meet_in_the_middle(data, config, &mut nodes, &mut table_bytes) {
+        Ok(found) => found,
+        Err(oom) => return Err(SolveError::OutOfMemory(oom)),
+    };
+    stats.phases.push(PhaseStats { algorithm: "mitm", budget: None, nodes });
+    stats.note_memory(data.bytes() + table_bytes);
+
+    if let Some(result) = found {
+        return Ok(Some(result));
+    }
+    if config.controller.should_stop(nodes).is_break() {
+        return Err(SolveError::Cancelled);
+    }
+    // Sums of clashing or far-apart rows aren't achievable; without either every one is
+    if let Some((min_count, max_count)) = near_window.filter(|_| data.sorted.len() <= NEAR_SUMS_MAX_N && !config.path_dependent()) {
+        stats.near_sums = near_sums(&data.sorted, config.target, min_count.max(1), max_count, config.memory_limit);
+        if stats.near_sums.is_some() {
+            stats.note_memory(data.bytes() + near_sums_bytes(data.sorted.len()));
+        }
+    }
+    Ok(None)
+}
+
+/// Bytes near_sums' per-count sum lists take over `n` entries, at most.
+fn near_sums_bytes(n: usize) -> u64 {
+    ((1u64 << (n / 2)) + (1u64 << n.div_ceil(2))) * size_of::<(u128, u64)>() as u64
+}
+
+/// The achievable sums around `target` (see NearSums), by merging the
+/// two MITM halves' sums count by count: for each pair of counts the window
+/// allows, the left sums ascend while three pointers into the right sums (the
+/// band's start and end, and the first sum reaching the target) only descend.
+/// The sums on either side of that first pointer are the nearest misses; each
+/// list keeps one subset per sum, so their rows come along.
+/// None when those lists would not fit `memory_limit`: the summary is extra.
+fn near_sums(sorted: &[NumberEntry], target: u64, min_count: usize, max_count: usize, memory_limit: Option<u64>) -> Option<NearSums> {
+    OutOfMemory::check(near_sums_bytes(sorted.len()), memory_limit).ok()?;
+    let (left, right) = split_alternating(sorted);
+    // Distinct subset sums of a half, ascending, by subset size, each with
+    // the lowest mask reaching it
+    let by_count = |half: &[NumberEntry]| -> Vec<Vec<(u128, u64)>> {
+        let mut sums = vec![Vec::new(); half.len() + 1];
+        for mask in 0..1u64 << half.len() {
+            let sum = half.iter().enumerate().filter(|&(i, _)| mask & 1 << i != 0).map(|(_, e)| e.value as u128).sum();
+            sums[mask.count_ones() as usize].push((sum, mask));
+        }
+        for list in &mut sums {
+            list.sort_unstable();
+            list.dedup_by_key(|&mut (sum, _)| sum);
+        }
+        sums
+    };
+    let (left_sums, right_sums) = (by_count(&left), by_count(&right));
+    let radius = (target / 100).max(1);
+    let target = target as u128;
+    let (low, high) = (target.saturating_sub(radius as u128), target + radius as u128);
+    let mut near = NearSums { radius, exhaustive: true, ..NearSums::default() };
+    let mut within = std::collections::BTreeSet::new();
+    // (sum, left mask, right mask) of the nearest misses so far
+    let (mut below, mut above) = (None::<(u128, u64, u64)>, None::<(u128, u64, u64)>);
+
+    for (lcount, ls) in left_sums.iter().enumerate() {
+        for (rcount, rs) in right_sums.iter().enumerate() {
+            if !(min_count..=max_count).contains(&(lcount + rcount)) {
+                continue;
+            }
+            let (mut band_start, mut band_end, mut reaching) = (rs.len(), rs.len(), rs.len());
+            for &(l, lmask) in ls {
+                while band_start > 0 && l + rs[band_start - 1].0 >= low {
+                    band_start -= 1;
+                }
+                while band_end > 0 && l + rs[band_end - 1].0 > high {
+                    band_end -= 1;
+                }
+                while reaching > 0 && l + rs[reaching - 1].0 >= target {
+                    reaching -= 1;
+                }
+                if let Some(&(r, rmask)) = reaching.checked_sub(1).map(|i| &rs[i]) {
+                    if below.is_none_or(|(sum, _, _)| l + r > sum) {
+                        below = Some((l + r, lmask, rmask));
+                    }
+                }
+                // Sums reaching the target are above it: none equals it
+                if let Some(&(r, rmask)) = rs.get(reaching) {
+                    if above.is_none_or(|(sum, _, _)| l + r < sum) {
+                        above = Some((l + r, lmask, rmask));
+                    }
+                }
+                for &(r, _) in rs.get(band_start..band_end).unwrap_or_default() {
+                    within.insert(l + r);
+                    if within.len() > MAX_NEAR_SUMS {
+                        // Drop whichever end lies farther from the target
+                        let (first, last) = (within.first().copied(), within.last().copied());
+                        if let (Some(first), Some(last)) = (first, last) {
+                            within.remove(&if target.abs_diff(first) > last.abs_diff(target) { first } else { last });
+                        }
+                        near.truncated = true;
+                    }
+                }
+            }
+        }
+    }
+    let miss = |(sum, lmask, rmask): (u128, u64, u64)| {
+        let mut indices: Vec<OriginalIndex> = [(&left, lmask), (&right, rmask)].into_iter()
+            .flat_map(|(half, mask)| half.iter().enumerate().filter(move |&(i, _)| mask & 1 << i != 0))
+            .map(|(_, e)| e.original_index)
+            .collect();
+        indices.sort_unstable();
+        NearMiss { sum, indices, diff: sum.abs_diff(target) }
+    };
+    near.nearest_below = below.map(miss);
+    near.nearest_above = above.map(miss);
+    near.achievable_within = within.into_iter().collect();
+    Some(near)
+}
+
+/// Default node cap for a batch search's quick phase (see `solve_quick`).
+pub const DEFAULT_QUICK_PHASE_NODES: u64 = 1_000_000;
+
+/// A fast, possibly incomplete find-one: full MITM for n <= 40 (bounded by n,
+/// not by the cap), otherwise B&B over descending values, which reaches large
+/// targets in few nodes, stopped after `node_cap` nodes. Runs with the count
+/// window as given. None when nothing turned up within the cap.
+pub fn solve_quick(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+    node_cap: u64,
+) -> (Option<Vec<NumberEntry>>, SolverStats) {
+    let mut stats = SolverStats::default();
+    // Out of memory here just means no quick answer: the DFS follows anyway
+    let Ok(data) = PreparedData::new(entries, config.memory_limit) else {
+        return (None, stats);
+    };
+    let result = match data.sorted.len() {
+        0 => Err(SolveError::EmptyInput),
+        n => match run_direct(&data, config, &mut stats) {
+            Some(result) => result,
+            None if n <= 40 => run_mitm(&data, config, None, &mut stats),
+            None => match data.reversed(config.memory_limit) {
+                Ok(reversed) => match branch_and_bound_first(&reversed, config, node_cap, None, false, &mut stats) {
+                    BbOutcome::Done(result) => result,
+                    BbOutcome::BudgetExhausted => Ok(None),
+                },
+                Err(oom) => Err(SolveError::OutOfMemory(oom)),
+            },
+        },
+    };
+    let found = match result {
+        Ok(Some(combo)) => Some(combo),
+        Ok(None) => None,
+        Err(SolveError::InvalidBounds { .. } | SolveError::EmptyInput | SolveError::TooLarge { .. }) => None,
+        Err(SolveError::Cancelled | SolveError::OutOfMemory(_)) => None,
+    };
+    (found, stats)
+}
+
+/// Default node budget for the feasibility check ahead of a batch search.
+pub const DEFAULT_PRECHECK_NODES: u64 = 200_000;
+
+/// What a budgeted feasibility check settled.
+#[derive(Clone, Debug)]
+pub enum Precheck {
+    /// A solution, in selection order
+    Found(Vec<NumberEntry>),
+    /// Proved that no combination in the count window reaches the target
+    Infeasible,
+    /// The budget ran out first
+    Unknown,
+}
+
+impl Precheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precheck::Found(_) => "found",
+            Precheck::Infeasible => "infeasible",
+            Precheck::Unknown => "unknown",
+        }
+    }
+}
+
+/// Is there any solution at all? Decided exactly, unlike solve_quick: full MITM
+/// for n <= 40 (bounded by n, not by the budget), otherwise ascending B&B, which
+/// proves infeasibility by walking its whole pruned tree, stopped after
+/// `node_budget` nodes.
+pub fn precheck(entries: &[NumberEntry], config: &SolverConfig, node_budget: u64) -> (Precheck, SolverStats) {
+    let mut stats = SolverStats::default();
+    let Ok(data) = PreparedData::new(entries, config.memory_limit) else {
+        return (Precheck::Unknown, stats);
+    };
+    let Some((min_count, max_count)) = tighten_count_window(
+        &data.sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return (Precheck::Infeasible, stats);
+    };
+    stats.count_window = Some((min_count, max_count));
+    let config = &SolverConfig { min_count, max_count, ..*config };
+    let mitm = match run_direct(&data, config, &mut stats) {
+        Some(result) => Some(result),
+        None if data.sorted.len() <= 40 => Some(run_mitm(&data, config, None, &mut stats)),
+        None => None,
+    };
+    let result = match mitm {
+        Some(Err(SolveError::OutOfMemory(oom))) => {
+            note_memory_fallback(oom, "bnb", &mut stats);
+            None
+        }
+        result => result,
+    };
+    let result = result.unwrap_or_else(|| match branch_and_bound_first(&data, config, node_budget, None, false, &mut stats) {
+        BbOutcome::Done(result) => result,
+        BbOutcome::BudgetExhausted => Err(SolveError::Cancelled),
+    });
+    let outcome = match result {
+        Ok(Some(combo)) => Precheck::Found(combo),
+        Ok(None) | Err(SolveError::InvalidBounds { .. } | SolveError::EmptyInput) => Precheck::Infeasible,
+        Err(SolveError::TooLarge { .. } | SolveError::Cancelled | SolveError::OutOfMemory(_)) => Precheck::Unknown,
+    };
+    (outcome, stats)
+}
+
+/// What solve_all_combinations found, and whether it walked the whole space.
+#[derive(Debug, Default)]
+pub struct AllCombinationsResult {
+    pub results: Vec<Vec<NumberEntry>>,
+    /// The controller stopped the search before it was done
+    pub cancelled: bool,
+    /// The search stopped at max_results; more combinations may exist
+    pub truncated_by_max_results: bool,
+    /// DFS nodes, counted as BatchSearchState counts them, so the two can be compared
+    pub nodes_explored: u64,
+}
+
+/// Find ALL valid combinations (up to max_results).
+/// Used by verify_exhaustive and tests; the WASM API uses BatchSearchState for
+/// streaming instead.
+pub fn solve_all_combinations(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+    max_results: usize,
+) -> AllCombinationsResult {
+    let data = PreparedData::new(entries, None).expect("out of memory preparing the input");
+
+    if data.sorted.is_empty() {
+        return AllCombinationsResult::default();
+    }
+    let Some((min_count, max_count)) = tighten_count_window(
+        &data.sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return AllCombinationsResult::default();
+    };
+    let config = &SolverConfig { min_count, max_count, ..*config };
+
+    let mut all = AllCombinationsResult::default();
+    let mut path = Vec::new();
+    if let ControlFlow::Break(stop) = branch_and_bound_all(&data, config, 0, 0, &mut path, max_results, &mut all) {
+        match stop {
+            AllStop::Cancelled => all.cancelled = true,
+            AllStop::MaxResults => all.truncated_by_max_results = true,
+        }
+    }
+    all
+}
+
+/// Whether a found solution is the only one, as far as the budget allowed us to look.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Uniqueness {
+    Unique,
+    NotUnique,
+    /// The node budget ran out before a second solution was found or ruled out.
+    Unknown,
+}
+
+pub struct UniquenessCheck {
+    pub uniqueness: Uniqueness,
+    pub nodes_explored: u64,
+}
+
+/// Search for a second solution with a different index set than `known`.
+///
+/// Runs an exhaustive DFS capped at two results and `node_budget` nodes. Two results
+/// always include one that differs from `known`; exhausting the space with fewer
+/// proves `known` is the only one.
+pub fn check_uniqueness(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+    known: &[NumberEntry],
+    node_budget: u64,
+) -> UniquenessCheck {
+    let mut known_indices: Vec<OriginalIndex> = known.iter().map(|e| e.original_index).collect();
+    known_indices.sort_unstable();
+
+    let mut state = BatchSearchState::new(
+        entries, config.target, config.min_count, config.max_count, 2,
+    );
+    if let Some(ids) = config.row_ids {
+        state.set_row_ids(ids);
+    }
+    if let Some(span) = config.max_index_span {
+        state.set_max_index_span(span);
+    }
+    let batch = state.search_batch(node_budget);
+
+    let has_other = state.all_results().iter().any(|combo| {
+        let mut indices: Vec<OriginalIndex> = combo.iter().map(|e| e.original_index).collect();
+        indices.sort_unstable();
+        indices != known_indices
+    });
+
+    let uniqueness = if has_other {
+        Uniqueness::NotUnique
+    } else if batch.finished {
+        Uniqueness::Unique
+    } else {
+        Uniqueness::Unknown
+    };
+
+    UniquenessCheck { uniqueness, nodes_explored: batch.nodes_explored }
+}
+
+// ---------------------------------------------------------------------------
+// Meet-in-the-middle: split into two halves, enumerate all subsets per half,
+// find complementary pairs via hash map.
+// Time: O(2^(n/2)), Space: O(2^(n/2)). Works for n up to ~40.
+// ---------------------------------------------------------------------------
+
+/// Largest half MITM enumerates: 2^62 subsets is already far past any budget,
+/// and the masks are u64.
+pub const MAX_MITM_HALF: usize = 62;
+
+/// Guard at every MITM entry: the strategy choice keeps halves small, and no
+/// option may route a larger input here.
+pub fn assert_mitm_halves(left: usize, right: usize) {
+    assert!(
+        left <= MAX_MITM_HALF && right <= MAX_MITM_HALF,
+        "MITM entered with halves of {} and {} entries (max {})", left, right, MAX_MITM_HALF,
+    );
+}
+
+fn meet_in_the_middle(
+    data: &PreparedData,
+    config: &SolverConfig,
+    enumerated: &mut u64,
+    table_bytes: &mut u64,
+) -> Result<Option<Vec<NumberEntry>>, OutOfMemory> {
+    let (left, right) = split_alternating(&data.sorted);
+    mitm_halves(&left, &right, config, enumerated, table_bytes)
+}
+
+/// Deal sorted entries alternately into two halves (even positions left, odd
+/// right; the left gets n / 2), so both span the whole value range. Splitting at
+/// n / 2 instead puts every small value on the left, whose many tiny sums fill
+/// left_map without ever completing a large target.
+pub fn split_alternating<T: Clone>(sorted: &[T]) -> (Vec<T>, Vec<T>) {
+    let n = sorted.len();
+    let (mut left, mut right) = (Vec::with_capacity(n / 2), Vec::with_capacity(n.div_ceil(2)));
+    for (i, e) in sorted.iter().enumerate() {
+        // Odd n: the last entry goes right too, keeping left_map at 2^(n / 2)
+        if i % 2 == 1 || (n % 2 == 1 && i == n - 1) {
+            right.push(e.clone());
+        } else {
+            left.push(e.clone());
+        }
+    }
+    (left, right)
+}
+
+/// Per entry of both halves, its bit in a combination's tie-break key: ranked
+/// by original index, the smallest index taking the highest bit. Between two
+/// combinations of the same size, the one with the lexicographically smaller
+/// index set (the one holding the smallest index of those they don't share)
+/// then has the larger key.
+fn tie_break_bits(left: &[NumberEntry], right: &[NumberEntry], by_index: &[OriginalIndex]) -> (Vec<u128>, Vec<u128>) {
+    let bit = |e: &NumberEntry| 1u128 << (127 - by_index.binary_search(&e.original_index).unwrap());
+    (left.iter().map(bit).collect(), right.iter().map(bit).collect())
+}
+
+/// The original indices of both halves, ascending: tie-break bit 127 - r
+/// stands for by_index[r].
+fn index_ranks(left: &[NumberEntry], right: &[NumberEntry]) -> Vec<OriginalIndex> {
+    let mut by_index: Vec<OriginalIndex> = left.iter().chain(right).map(|e| e.original_index).collect();
+    by_index.sort_unstable();
+    by_index
+}
+
+/// max - min original index of the non-empty combination with tie-break `key`:
+/// its highest bit is its smallest index, its lowest bit its largest.
+fn key_span(key: u128, by_index: &[OriginalIndex]) -> usize {
+    by_index[127 - key.trailing_zeros() as usize].as_usize() - by_index[key.leading_zeros() as usize].as_usize()
+}
+
+/// The tie-break key of the subset `mask` of a half.
+fn tie_break_key(bits: &[u128], mask: u64) -> u128 {
+    bits.iter().enumerate().filter(|&(bit, _)| mask & (1u64 << bit) != 0).fold(0, |key, (_, &b)| key | b)
+}
+
+/// Meet-in-the-middle over two given halves; bit positions in the masks are
+/// positions within `left` and `right`.
+///
+/// Returns the canonical match, not the first one met: the fewest entries, then
+/// the lexicographically smallest set of original indices. Which match that is
+/// depends only on the entries and the config, never on how the halves are
+/// split or enumerated, so answers stay put when those internals change. This
+/// walks every right subset even after a match; each one still stops at its
+/// first compatible left partner, as left_map lists them best first.
+///
+/// Err, before any enumeration, when left_map would not fit the config's
+/// memory_limit or cannot be allocated. `table_bytes` gets left_map's size
+/// once it is built (see mitm_table_bytes).
+fn mitm_halves(
+    left: &[NumberEntry],
+    right: &[NumberEntry],
+    config: &SolverConfig,
+    enumerated: &mut u64,
+    table_bytes: &mut u64,
+) -> Result<Option<Vec<NumberEntry>>, OutOfMemory> {
+    let left_len = left.len();
+    let right_len = right.len();
+    assert_mitm_halves(left_len, right_len);
+    let left_count = 1u64 << left_len;
+    let rows = config.row_ids.map(|ids| RowMasks::new(left, right, ids));
+    let by_index = index_ranks(left, right);
+    let (left_bits, right_bits) = tie_break_bits(left, right, &by_index);
+    let too_wide = |key: u128| key != 0 && config.max_index_span.is_some_and(|span| key_span(key, &by_index) > span);
+
+    // sum -> tie-break keys of the left subsets with that sum; a key's popcount
+    // is the subset's size
+    let mut left_map: StableHashMap<u64, Vec<u128>> = StableHashMap::default();
+    reserve_mitm_table(&mut left_map, left_count, config.memory_limit)?;
+    *enumerated += left_count;
+
+    for mask in 0..left_count {
+        if mask & 0xFFFF == 0 && config.controller.should_stop(mask).is_break() {
+            return Ok(None);
+        }
+        let Some((sum, count)) = subset_sum(left, mask, config.target) else { continue };
+        if rows.as_ref().is_some_and(|rows| rows.clash(mask, &rows.left)) {
+            continue;
+        }
+        let key = tie_break_key(&left_bits, mask);
+        if count <= config.max_count && !too_wide(key) {
+            left_map.entry(sum).or_default().push(key);
+        }
+    }
+    let mut keys_kept = 0;
+    for keys in left_map.values_mut() {
+        keys.sort_unstable_by(|a, b| a.count_ones().cmp(&b.count_ones()).then(b.cmp(a)));
+        keys_kept += keys.len();
+    }
+    *table_bytes = mitm_table_bytes(&left_map, keys_kept);
+
+    // (size, key) of the best match so far
+    let mut best: Option<(usize, u128)> = None;
+    let right_count = 1u64 << right_len;
+
+    for rmask in 0..right_count {
+        if rmask & 0xFFFF == 0 && config.controller.should_stop(rmask).is_break() {
+            return Ok(None);
+        }
+        *enumerated += 1;
+        let Some((rsum, rcount)) = subset_sum(right, rmask, config.target) else { continue };
+        let rkey = tie_break_key(&right_bits, rmask);
+        if rows.as_ref().is_some_and(|rows| rows.clash(rmask, &rows.right)) || too_wide(rkey) {
+            continue;
+        }
+        let Some(left_keys) = left_map.get(&(config.target - rsum)) else { continue };
+        // Left rows sharing a row id with this right subset
+        let taken = rows.as_ref().map_or(0, |rows| tie_break_key(&left_bits, rows.union(rmask, &rows.cross)));
+        let partner = left_keys.iter().find(|&&lkey| {
+            let total_count = lkey.count_ones() as usize + rcount;
+            total_count >= config.min_count && total_count <= config.max_count && lkey & taken == 0 && !too_wide(lkey | rkey)
+        });
+        if let Some(&lkey) = partner {
+            let candidate = (lkey.count_ones() as usize + rcount, lkey | rkey);
+            if best.is_none_or(|(count, key)| candidate.0 < count || (candidate.0 == count && candidate.1 > key)) {
+                best = Some(candidate);
+            }
+        }
+    }
+
+    let Some((_, key)) = best else { return Ok(None) };
+    let chosen = |half: &[NumberEntry], bits: &[u128]| -> Vec<NumberEntry> {
+        half.iter().zip(bits).filter(|&(_, &b)| key & b != 0).map(|(e, _)| e.clone()).collect()
+    };
+    let mut result = chosen(left, &left_bits);
+    result.extend(chosen(right, &right_bits));
+    Ok(Some(result))
+}
+
+/// Room in a MITM left-half map for `subsets` entries, at the
+/// MITM_BYTES_PER_SUBSET the prognosis assumes for each.
+pub fn reserve_mitm_table<V>(map: &mut StableHashMap<u64, V>, subsets: u64, memory_limit: Option<u64>) -> Result<(), OutOfMemory> {
+    let bytes = subsets.saturating_mul(MITM_BYTES_PER_SUBSET);
+    OutOfMemory::check(bytes, memory_limit)?;
+    let subsets = usize::try_from(subsets).map_err(|_| OutOfMemory { bytes })?;
+    map.try_reserve(subsets).map_err(|_| OutOfMemory { bytes })
+}
+
+/// Heap bytes of a MITM left map: its buckets, each the size of an entry, plus
+/// the `values` held in the entries' lists.
+pub fn mitm_table_bytes<T>(map: &StableHashMap<u64, Vec<T>>, values: usize) -> u64 {
+    (map.capacity() * size_of::<(u64, Vec<T>)>() + values * size_of::<T>()) as u64
+}
+
+/// Per entry of a MITM half, the bit masks of entries sharing its row id.
+pub struct RowMasks {
+    /// Within the left half
+    pub left: Vec<u64>,
+    /// Within the right half
+    pub right: Vec<u64>,
+    /// Per right entry, in the left half
+    pub cross: Vec<u64>,
+}
+
+impl RowMasks {
+    pub fn new(left: &[NumberEntry], right: &[NumberEntry], ids: &[u32]) -> Self {
+        // Bit j of masks[i]: within[j] is another entry with of[i]'s row id
+        let masks = |of: &[NumberEntry], within: &[NumberEntry]| -> Vec<u64> {
+            of.iter()
+                .map(|e| {
+                    within.iter().enumerate()
+                        .filter(|(_, w)| w.original_index != e.original_index && ids[w.original_index.as_usize()] == ids[e.original_index.as_usize()])
+                        .fold(0, |mask, (j, _)| mask | 1 << j)
+                })
+                .collect()
+        };
+        RowMasks { left: masks(left, left), right: masks(right, right), cross: masks(right, left) }
+    }
+
+    /// OR of `masks` over the bits set in `mask`.
+    pub fn union(&self, mut mask: u64, masks: &[u64]) -> u64 {
+        let mut union = 0;
+        while mask != 0 {
+            union |= masks[mask.trailing_zeros() as usize];
+            mask &= mask - 1;
+        }
+        union
+    }
+
+    /// Whether the subset `mask` holds two entries with one row id.
+    pub fn clash(&self, mask: u64, masks: &[u64]) -> bool {
+        self.union(mask, masks) & mask != 0
+    }
+}
+
+/// Largest entry count find_random accepts: uniform sampling needs the full MITM
+/// enumeration, which is only affordable up to here.
+pub const MAX_RANDOM_N: usize = 40;
+
+/// Pick one solution uniformly at random among ALL solutions, or None. Past
+/// MAX_RANDOM_N entries this is TooLarge.
+///
+/// Splits at n / 2 (unlike meet_in_the_middle, so seeded draws stay as they
+/// were) and keeps the left half as a list sorted
+/// by (sum, count), so the left partners of any right subset form one contiguous
+/// range. Each (right subset, left partner) pair is a distinct solution: a single
+/// draw over the total number of pairs, located with a second pass over the right
+/// half, picks a solution with exactly uniform probability.
+pub fn sample_uniform_with_stats(
+    entries: &[NumberEntry],
+    config: &SolverConfig,
+    rng: &mut SplitMix64,
+) -> (SolveOutcome, SolverStats) {
+    // The uniform draw counts every pair of halves as a solution
+    debug_assert!(!config.path_dependent(), "sampling doesn't support row ids or a span");
+    let mut stats = SolverStats::default();
+    let n = entries.len();
+    if n > MAX_RANDOM_N {
+        return (Err(SolveError::TooLarge { algorithm: "mitm_sample", n, max_n: MAX_RANDOM_N }), stats);
+    }
+    if let Err(err) = check_input(n, config) {
+        return (Err(err), stats);
+    }
+    let data = match PreparedData::new(entries, config.memory_limit) {
+        Ok(data) => data,
+        Err(oom) => return (Err(SolveError::OutOfMemory(oom)), stats),
+    };
+    let Some((min_count, max_count)) = tighten_count_window(
+        &data.sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return (Ok(None), stats);
+    };
+    stats.count_window = Some((min_count, max_count));
+    let config = &SolverConfig { min_count, max_count, ..*config };
+
+    let (mut nodes, mut table_bytes) = (0u64, 0u64);
+    let result = mitm_sample(&data, config, rng, &mut nodes, &mut table_bytes);
+    stats.phases.push(PhaseStats { algorithm: "mitm_sample", budget: None, nodes });
+    stats.note_memory(data.bytes() + table_bytes);
+    (result, stats)
+}
+
+// ---------------------------------------------------------------------------
+// Prognosis: what a search would face, without running one.
+// ---------------------------------------------------------------------------
+
+/// Rough DFS / enumeration speed the difficulty classes assume.
+const NODES_PER_SECOND: f64 = 10_000_000.0;
+
+/// Memory meet_in_the_middle may use before the prognosis flags it.
+pub const MITM_MEMORY_BUDGET: u64 = 1 << 30;
+
+/// Approximate bytes per left-half subset in meet_in_the_middle's hash map.
+const MITM_BYTES_PER_SUBSET: u64 = 48;
+
+/// Why a search is known to find nothing before it starts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Infeasibility {
+    /// No usable entries
+    NoEntries,
+    /// count_bounds leaves no count in [min_count, max_count]
+    CountWindow,
+    /// Every value is a multiple of some g > 1 that doesn't divide the target
+    Gcd,
+}
+
+impl Infeasibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Infeasibility::NoEntries => "no_entries",
+            Infeasibility::CountWindow => "count_window",
+            Infeasibility::Gcd => "gcd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difficulty {
+    Instant,
+    Seconds,
+    Minutes,
+    Intractable,
+}
+
+impl Difficulty {
+    fn from_nodes(nodes: f64) -> Self {
+        let seconds = nodes / NODES_PER_SECOND;
+        if seconds < 0.1 {
+            Difficulty::Instant
+        } else if seconds < 60.0 {
+            Difficulty::Seconds
+        } else if seconds < 3600.0 {
+            Difficulty::Minutes
+        } else {
+            Difficulty::Intractable
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Instant => "instant",
+            Difficulty::Seconds => "seconds",
+            Difficulty::Minutes => "minutes",
+            Difficulty::Intractable => "intractable",
+        }
+    }
+}
+
+/// What solve_subset_sum would face for an input, from the same preprocessing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prognosis {
+    /// Usable entries
+    pub n: usize,
+    /// Phases solve_subset_sum may run, in order (later ones only if earlier ones stall)
+    pub algorithms: Vec<&'static str>,
+    /// [min_count, max_count] after count_bounds
+    pub count_window: Option<(usize, usize)>,
+    pub infeasible: Option<Infeasibility>,
+    /// Worst case: MITM enumerations, or for B&B the number of subsets in the
+    /// count window. A B&B that finds a solution usually stops far sooner.
+    pub worst_case_nodes: f64,
+    pub difficulty: Difficulty,
+    /// Estimated peak of meet_in_the_middle's table, when MITM may run
+    pub mitm_memory_bytes: Option<u64>,
+}
+
+impl Prognosis {
+    pub fn mitm_over_budget(&self) -> bool {
+        self.mitm_memory_bytes.is_some_and(|bytes| bytes > MITM_MEMORY_BUDGET)
+    }
+}
+
+/// Size up a search without running it.
+pub fn prognosis(entries: &[NumberEntry], config: &SolverConfig) -> Prognosis {
+    let data = PreparedData::new(entries, None).expect("out of memory preparing the input");
+    let n = data.sorted.len();
+    let algorithms = match n {
+        0 => vec![],
+        1..=40 => vec!["mitm"],
+        41..=50 => vec!["bnb", "mitm"],
+        51..=60 => vec!["bnb", "bnb_desc"],
+        _ => vec!["bnb"],
+    };
+    let mitm_memory_bytes = algorithms.contains(&"mitm").then(|| MITM_BYTES_PER_SUBSET << (n / 2));
+    let infeasible_prognosis = |count_window, infeasible| Prognosis {
+        n,
+        algorithms: algorithms.clone(),
+        count_window,
+        infeasible: Some(infeasible),
+        worst_case_nodes: 0.0,
+        difficulty: Difficulty::Instant,
+        mitm_memory_bytes,
+    };
+
+    if n == 0 {
+        return infeasible_prognosis(None, Infeasibility::NoEntries);
+    }
+    let Some((min_count, max_count)) = tighten_count_window(
+        &data.sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ) else {
+        return infeasible_prognosis(None, Infeasibility::CountWindow);
+    };
+    let count_window = Some((min_count, max_count));
+    let gcd = data.sorted.iter().fold(0, |g, e| gcd(g, e.value));
+    if gcd > 1 && !config.target.is_multiple_of(gcd) {
+        return infeasible_prognosis(count_window, Infeasibility::Gcd);
+    }
+
+    let mitm_nodes = 2f64.powi((n / 2) as i32) + 2f64.powi((n - n / 2) as i32);
+    let dfs_nodes = subsets_in_window(n, min_count, max_count);
+    let worst_case_nodes = match n {
+        1..=40 => mitm_nodes,
+        41..=50 => dfs_nodes.min(config.hybrid_bb_budget as f64 + mitm_nodes),
+        _ => dfs_nodes,
+    };
+    Prognosis {
+        n,
+        algorithms,
+        count_window,
+        infeasible: None,
+        worst_case_nodes,
+        difficulty: Difficulty::from_nodes(worst_case_nodes),
+        mitm_memory_bytes,
+    }
+}
+
+/// Which rows a target leaves a choice about, from bounds alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TargetAnalysis {
+    /// Sum of the usable entries
+    pub total_value: u128,
+    /// [min_count, max_count] after count_bounds; None when no count can work
+    pub count_window: Option<(usize, usize)>,
+    /// Original indices every solution must use: without the row, even the
+    /// largest others allowed fall short of the target. Ascending
+    pub mandatory: Vec<OriginalIndex>,
+    /// Original indices no solution can use: with the row in, the rest of the
+    /// target lies outside what the smallest and largest others allowed can
+    /// make. Ascending; other rows may be unusable too without showing here
+    pub irrelevant: Vec<OriginalIndex>,
+}
+
+/// Mandatory and irrelevant rows for a target, by comparing it with the sums
+/// of the largest and smallest other rows the count window allows: O(n log n),
+/// and exact in what it reports, though not every irrelevant row shows up.
+pub fn analyze_target(entries: &[NumberEntry], config: &SolverConfig) -> TargetAnalysis {
+    let data = PreparedData::new(entries, None).expect("out of memory preparing the input");
+    let sorted = &data.sorted;
+    let n = sorted.len();
+    let total_value = sorted.iter().map(|e| u128::from(e.value)).sum();
+    let Some((min_count, max_count)) = tighten_count_window(
+        sorted, &data.suffix_sum, config.target, config.min_count, config.max_count,
+    ).filter(|_| n > 0) else {
+        return TargetAnalysis { total_value, ..TargetAnalysis::default() };
+    };
+
+    // prefix[k]: the k smallest values; the k largest are prefix[n] - prefix[n - k]
+    let mut prefix = vec![0u128; n + 1];
+    for (i, e) in sorted.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + u128::from(e.value);
+    }
+    // The k smallest or largest rows other than the one at sorted position p
+    let smallest_others = |p: usize, k: usize| if p < k { prefix[k + 1] - u128::from(sorted[p].value) } else { prefix[k] };
+    let largest_others = |p: usize, k: usize| {
+        if p >= n - k { prefix[n] - prefix[n - k - 1] - u128::from(sorted[p].value) } else { prefix[n] - prefix[n - k] }
+    };
+
+    let target = u128::from(config.target);
+    let (mut mandatory, mut irrelevant) = (Vec::new(), Vec::new());
+    for (p, e) in sorted.iter().enumerate() {
+        let others = n - 1;
+        if largest_others(p, max_count.min(others)) < target {
+            mandatory.push(e.original_index);
+        }
+        // A row past the target can't be in any solution
+        let Some(rest) = target.checked_sub(u128::from(e.value)) else {
+            irrelevant.push(e.original_index);
+            continue;
+        };
+        let (fewest, most) = (min_count.saturating_sub(1), (max_count - 1).min(others));
+        if smallest_others(p, fewest) > rest || largest_others(p, most) < rest {
+            irrelevant.push(e.original_index);
+        }
+    }
+    mandatory.sort_unstable();
+    irrelevant.sort_unstable();
+    TargetAnalysis { total_value, count_window: Some((min_count, max_count)), mandatory, irrelevant }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Sum of C(n, k) for k in [min, max], as a float (it overflows integers quickly).
+fn subsets_in_window(n: usize, min: usize, max: usize) -> f64 {
+    let mut binomial = 1.0; // C(n, 0)
+    let mut total = 0.0;
+    for k in 0..=max.min(n) {
+        if k >= min {
+            total += binomial;
+        }
+        binomial = binomial * (n - k) as f64 / (k + 1) as f64;
+    }
+    total
+}
+
+/// (sum, count) of the subset `mask` of `half`, or None once the sum passes `target`.
+/// Checked, so values near u64::MAX can't wrap around into a false match.
+pub fn subset_sum(half: &[NumberEntry], mask: u64, target: u64) -> Option<(u64, usize)> {
+    let mut sum = 0u64;
+    let mut count = 0usize;
+    for (bit, e) in half.iter().enumerate() {
+        if mask & (1u64 << bit) != 0 {
+            sum = sum.checked_add(e.value).filter(|&s| s <= target)?;
+            count += 1;
+        }
+    }
+    Some((sum, count))
+}
+
+fn mitm_sample(
+    data: &PreparedData,
+    config: &SolverConfig,
+    rng: &mut SplitMix64,
+    enumerated: &mut u64,
+    table_bytes: &mut u64,
+) -> SolveOutcome {
+    let mid = data.sorted.len() / 2;
+    let left = &data.sorted[..mid];
+    let right = &data.sorted[mid..];
+    assert_mitm_halves(left.len(), right.len());
+    let left_count = 1u64 << left.len();
+    let right_count = 1u64 << right.len();
+
+    // (sum, count, mask), sorted so equal (sum, count) keys are adjacent
+    let mut left_list: Vec<(u64, usize, u64)> = Vec::new();
+    if let Err(oom) = try_reserve(&mut left_list, left_count as usize, config.memory_limit) {
+        return Err(SolveError::OutOfMemory(oom));
+    }
+    *table_bytes = (left_list.capacity() * size_of::<(u64, usize, u64)>()) as u64;
+    *enumerated += left_count;
+    for mask in 0..left_count {
+        if mask & 0xFFFF == 0 && config.controller.should_stop(mask).is_break() {
+            return Err(SolveError::Cancelled);
+        }
+        if let Some((sum, count)) = subset_sum(left, mask, config.target) {
+            if count <= config.max_count {
+                left_list.push((sum, count, mask));
+            }
+        }
+    }
+    left_list.sort_unstable();
+
+    // Index range of left partners completing the right subset `rmask`
+    let partners = |rmask: u64| -> Option<std::ops::Range<usize>> {
+        let (rsum, rcount) = subset_sum(right, rmask, config.target)?;
+        if rcount > config.max_count {
+            return None;
+        }
+        let needed = config.target - rsum;
+        // The empty combination is never a solution
+        let lo = config.min_count.saturating_sub(rcount).max(usize::from(rcount == 0));
+        let hi = config.max_count - rcount;
+        let start = left_list.partition_point(|&(s, c, _)| (s, c) < (needed, lo));
+        let end = left_list.partition_point(|&(s, c, _)| (s, c) <= (needed, hi));
+        (start < end).then_some(start..end)
+    };
+
+    let mut total = 0u64;
+    for rmask in 0..right_count {
+        if rmask & 0xFFFF == 0 && config.controller.should_stop(rmask).is_break() {
+            return Err(SolveError::Cancelled);
+        }
+        if let Some(range) = partners(rmask) {
+            total += range.len() as u64;
+        }
+    }
+    *enumerated += right_count;
+    if total == 0 {
+        return Ok(None);
+    }
+
+    let mut pick = rng.below(total);
+    for rmask in 0..right_count {
+        *enumerated += 1;
+        let Some(range) = partners(rmask) else { continue };
+        let len = range.len() as u64;
+        if pick >= len {
+            pick -= len;
+            continue;
+        }
+        let lmask = left_list[range.start + pick as usize].2;
+        let result: Vec<NumberEntry> = left.iter().enumerate()
+            .filter(|&(bit, _)| lmask & (1u64 << bit) != 0)
+            .chain(right.iter().enumerate().filter(|&(bit, _)| rmask & (1u64 << bit) != 0))
+            .map(|(_, e)| e.clone())
+            .collect();
+        return Ok(Some(result));
+    }
+    unreachable!("pick is below the total number of pairs")
+}
+
+// ---------------------------------------------------------------------------
+// Branch-and-bound DFS with aggressive pruning.
+// ---------------------------------------------------------------------------
+
+enum BbOutcome {
+    Done(SolveOutcome),
+    BudgetExhausted,
+}
+
+/// Subtrees of the ascending B&B that were walked to the end without a match,
+/// so a later run can skip them. A node's subtree depends only on where it
+/// starts in `sorted`, the sum still missing, and how many more rows it must
+/// (`need`) and may (`allow`) take, so an entry stays true for any target and
+/// count window over the same data, and covers every node with the same start
+/// and remainder that must take at least as many rows and may take no more.
+/// Row ids and spans make the subtree depend on the path too; B&B keeps no
+/// entries then.
+#[derive(Default)]
+pub struct DeadEnds {
+    /// (start, remainder) -> (need, allow) of the widest window proved empty
+    map: StableHashMap<(usize, u64), (usize, usize)>,
+}
+
+/// Entries kept at most; at about 40 bytes each, 40MB. Later dead ends go unrecorded.
+const MAX_DEAD_ENDS: usize = 1 << 20;
+
+/// Smaller subtrees are cheaper to walk again than to look up.
+const DEAD_END_MIN_NODES: u64 = 64;
+
+impl DeadEnds {
+    fn covers(&self, start: usize, remainder: u64, need: usize, allow: usize) -> bool {
+        self.map.get(&(start, remainder)).is_some_and(|&(n, a)| need >= n && allow <= a)
+    }
+
+    fn record(&mut self, start: usize, remainder: u64, need: usize, allow: usize) {
+        if let Some(window) = self.map.get_mut(&(start, remainder)) {
+            if need <= window.0 && allow >= window.1 {
+                *window = (need, allow);
+            }
+        } else if self.map.len() < MAX_DEAD_ENDS {
+            self.map.insert((start, remainder), (need, allow));
+        }
+    }
+}
+
+/// With `near_misses`, a run that proves there is no match records the nearest
+/// misses it met in `stats.near_sums` (see MissTracker); not with row ids or a
+/// span, whose sums depend on which rows meet.
+fn branch_and_bound_first(
+    data: &PreparedData,
+    config: &SolverConfig,
+    node_budget: u64,
+    dead_ends: Option<&mut DeadEnds>,
+    near_misses: bool,
+    stats: &mut SolverStats,
+) -> BbOutcome {
+    let mut path: Vec<usize> = Vec::with_capacity(config.max_count.min(data.sorted.len()));
+    let dead_ends = dead_ends.filter(|_| data.order == Order::Ascending && !config.path_dependent());
+    let misses = (near_misses && !config.path_dependent()).then(|| MissTracker::new(data, config.target));
+    let mut counter = NodeCounter { nodes: 0, limit: node_budget, dead_ends, misses };
+
+    let result = if data.narrow_values && u32::try_from(config.target).is_ok() {
+        bb_first_in::<u32>(data, config, &mut path, &mut counter, stats)
+    } else {
+        bb_first_in::<u64>(data, config, &mut path, &mut counter, stats)
+    };
+    stats.phases.push(PhaseStats {
+        algorithm: match data.order {
+            Order::Ascending => "bnb",
+            Order::Descending => "bnb_desc",
+            Order::Shuffled => "bnb_shuffled",
+        },
+        budget: (node_budget != u64::MAX).then_some(node_budget),
+        nodes: counter.nodes,
+    });
+
+    match result {
+        BbResult::Found => {
+            let entries: Vec<NumberEntry> = path.iter()
+                .map(|&i| data.sorted[i].clone())
+                .collect();
+            BbOutcome::Done(Ok(Some(entries)))
+        }
+        BbResult::Cancelled => BbOutcome::Done(Err(SolveError::Cancelled)),
+        BbResult::NotFound => {
+            // Nothing met is no summary, not a proof that nothing is near
+            if let Some(misses) = counter.misses.filter(|misses| misses.below.is_some() || misses.above.is_some()) {
+                stats.near_sums = Some(misses.near_sums(data));
+            }
+            BbOutcome::Done(Ok(None))
+        }
+        BbResult::BudgetExhausted => BbOutcome::BudgetExhausted,
+    }
+}
+
+/// One B&B run in lanes of width V.
+fn bb_first_in<V: LaneValue>(
+    data: &PreparedData,
+    config: &SolverConfig,
+    path: &mut Vec<usize>,
+    counter: &mut NodeCounter<'_>,
+    stats: &mut SolverStats,
+) -> BbResult {
+    stats.value_bits = Some(V::BITS);
+    let input = BbInput::<V>::new(data, config);
+    bb_dfs_first(&input, 0, V::ZERO, 0, path, counter)
+}
+
+enum BbResult {
+    Found,
+    NotFound,
+    Cancelled,
+    BudgetExhausted,
+}
+
+/// Nodes visited so far and the budget they may not exceed, plus the subtrees
+/// known to be empty and the nearest misses when the run keeps them.
+struct NodeCounter<'a> {
+    nodes: u64,
+    limit: u64,
+    dead_ends: Option<&'a mut DeadEnds>,
+    misses: Option<MissTracker>,
+}
+
+/// The sums nearest the target a B&B walk meets on either side, for a not-found
+/// explain past NEAR_SUMS_MAX_N entries: each node's own sum below it, the first
+/// row too large for the budget as one step past it (not walked into), the
+/// nearest picks each prune reads off where it cuts, and the last one or two
+/// picks around the budget. Over ascending values that is the nearest on each
+/// side; the restarts' other orders skip oversized rows without cutting and
+/// can miss nearer ones. Once both sides are as near as the values allow, the
+/// scans stop looking, so a settled walk pays only a comparison per node.
+struct MissTracker {
+    target: u128,
+    /// (sum, sorted positions) of the nearest so far on each side
+    below: Option<(u128, Vec<usize>)>,
+    above: Option<(u128, Vec<usize>)>,
+    /// The nearest any sum can lie below and above: every sum is a multiple
+    /// of the values' greatest common divisor
+    closest: (u128, u128),
+}
+
+impl MissTracker {
+    fn new(data: &PreparedData, target: u64) -> Self {
+        let step = data.sorted.iter().fold(0, |g, e| gcd(g, e.value)).max(1) as u128;
+        let target = target as u128;
+        let closest = match target % step {
+            0 => (step, step),
+            off => (off, step - off),
+        };
+        MissTracker { target, below: None, above: None, closest }
+    }
+
+    /// Both sides as near as any sum can be: the scans need not look.
+    fn settled(&self) -> bool {
+        let diff = |best: &Option<(u128, Vec<usize>)>| best.as_ref().map(|(sum, _)| sum.abs_diff(self.target));
+        (diff(&self.below), diff(&self.above)) == (Some(self.closest.0), Some(self.closest.1))
+    }
+
+    /// Offer the rows of `path` and then `rest`, which sum to `sum`.
+    fn offer(&mut self, sum: u128, path: &[usize], rest: impl IntoIterator<Item = usize>) {
+        let best = match sum.cmp(&self.target) {
+            std::cmp::Ordering::Less => &mut self.below,
+            std::cmp::Ordering::Greater => &mut self.above,
+            std::cmp::Ordering::Equal => return,
+        };
+        if best.as_ref().is_none_or(|(seen, _)| sum.abs_diff(self.target) < seen.abs_diff(self.target)) {
+            *best = Some((sum, path.iter().copied().chain(rest).collect()));
+        }
+    }
+
+    /// What LastPicks found no match among: the singles on either side of the
+    /// budget and, for a pair, every pair a two-pointer walk meets, which
+    /// includes the nearest on each side. Pairs reaching past the first two
+    /// rows over the budget overshoot by more than one reaching only the first.
+    fn offer_last_picks<V: LaneValue>(&mut self, input: &BbInput<V>, start: usize, base: V, path: &[usize], pair: bool, single: bool) {
+        if self.settled() {
+            return;
+        }
+        let values = &input.values;
+        let budget = input.target - base;
+        let wide = |i: usize| values[i].to_u64() as u128;
+        let base = base.to_u64() as u128;
+        let fit_end = partition_point(start, values.len(), |i| values[i] <= budget);
+        if single {
+            for i in [fit_end.checked_sub(1).filter(|&i| i >= start), Some(fit_end)].into_iter().flatten() {
+                if i < values.len() {
+                    self.offer(base + wide(i), path, [i]);
+                }
+            }
+        }
+        if pair {
+            // The nearest pair on each side, offered once the walk is done
+            let budget = budget.to_u64() as u128;
+            let (mut short, mut over) = (None::<(u128, usize, usize)>, None::<(u128, usize, usize)>);
+            let (mut lo, mut hi) = (start, (fit_end + 2).min(values.len()).saturating_sub(1));
+            while lo < hi {
+                let sum = wide(lo) + wide(hi);
+                if sum < budget {
+                    if short.is_none_or(|(best, _, _)| sum > best) {
+                        short = Some((sum, lo, hi));
+                    }
+                    lo += 1;
+                } else {
+                    if over.is_none_or(|(best, _, _)| sum < best) {
+                        over = Some((sum, lo, hi));
+                    }
+                    hi -= 1;
+                }
+            }
+            for (sum, lo, hi) in [short, over].into_iter().flatten() {
+                self.offer(base + sum, path, [lo, hi]);
+            }
+        }
+    }
+
+    /// What the sum-of-the-rest prune cut at row `i`: every row from there on,
+    /// or when more are left than `allowed`, the largest that many when
+    /// ascending. Only as many as the count window allows.
+    fn offer_rest<V: LaneValue>(&mut self, input: &BbInput<V>, i: usize, base: V, path: &[usize], allowed: usize, ascending: bool) {
+        let n = input.values.len();
+        let rows = match n - i {
+            left if left <= allowed => i..n,
+            _ if ascending => n - allowed..n,
+            _ => return,
+        };
+        if path.len() + rows.len() >= input.config.min_count {
+            let sum = input.suffix_sum[rows.start] - input.suffix_sum[n];
+            self.offer((base + sum).to_u64() as u128, path, rows);
+        }
+    }
+
+    /// What the pinned-count prune cut: the `k` smallest rows from `start` on,
+    /// nearest above when even they overshoot, and the `k` largest, nearest
+    /// below when even they fall short.
+    fn offer_exact_picks<V: LaneValue>(&mut self, input: &BbInput<V>, start: usize, base: V, path: &[usize], k: usize, ascending: bool) {
+        let n = input.values.len();
+        let (head, tail) = (start..start + k, n - k..n);
+        let (smallest, largest) = if ascending { (head, tail) } else { (tail, head) };
+        for rows in [smallest, largest] {
+            let sum = rows.clone().map(|i| input.values[i].to_u64() as u128).sum::<u128>();
+            self.offer(base.to_u64() as u128 + sum, path, rows);
+        }
+    }
+
+    fn near_sums(self, data: &PreparedData) -> NearSums {
+        let target = self.target;
+        let miss = |(sum, positions): (u128, Vec<usize>)| {
+            let mut indices: Vec<OriginalIndex> = positions.iter().map(|&i| data.sorted[i].original_index).collect();
+            indices.sort_unstable();
+            NearMiss { sum, indices, diff: sum.abs_diff(target) }
+        };
+        NearSums { nearest_below: self.below.map(miss), nearest_above: self.above.map(miss), ..NearSums::default() }
+    }
+}
+
+fn bb_dfs_first<V: LaneValue>(
+    input: &BbInput<V>,
+    start: usize,
+    current_sum: V,
+    current_count: usize,
+    path: &mut Vec<usize>,
+    counter: &mut NodeCounter<'_>,
+) -> BbResult {
+    let (data, config) = (input.data, input.config);
+    if counter.nodes >= counter.limit {
+        return BbResult::BudgetExhausted;
+    }
+    counter.nodes += 1;
+
+    // Check cancellation every 4096 nodes (amortized cost of atomic load)
+    if counter.nodes & 0xFFF == 0 {
+        if config.controller.should_stop(counter.nodes).is_break() {
+            return BbResult::Cancelled;
+        }
+        if counter.nodes & 0xF_FFFF == 0 {
+            log!(Level::Debug, "bnb: {} nodes, depth {}", counter.nodes, current_count);
+        }
+    }
+
+    if current_sum == input.target && current_count >= config.min_count {
+        return BbResult::Found;
+    }
+    if current_count >= config.min_count && current_count > 0 {
+        if let Some(misses) = &mut counter.misses {
+            misses.offer(current_sum.to_u64() as u128, path, []);
+        }
+    }
+
+    if current_count >= config.max_count {
+        return BbResult::NotFound;
+    }
+
+    let n = input.values.len();
+    let remaining_needed = config.min_count.saturating_sub(current_count);
+
+    if n - start < remaining_needed {
+        return BbResult::NotFound;
+    }
+
+    let remaining_budget = input.target - current_sum;
+    let allowed = config.max_count - current_count;
+    if config.min_count == config.max_count
+        && data.order != Order::Shuffled
+        && !exact_picks_reach(&input.suffix_sum, start, allowed, remaining_budget, data.order == Order::Ascending)
+    {
+        if let Some(misses) = &mut counter.misses {
+            misses.offer_exact_picks(input, start, current_sum, path, allowed, data.order == Order::Ascending);
+        }
+        return BbResult::NotFound;
+    }
+    if counter.dead_ends.as_deref().is_some_and(|memo| {
+        memo.covers(start, remaining_budget.to_u64(), remaining_needed, allowed)
+    }) {
+        return BbResult::NotFound;
+    }
+    let nodes_before = counter.nodes;
+
+    // One or two picks left: scan for them instead of recursing per row. Row ids
+    // and spans depend on the whole path, so those searches keep recursing
+    if allowed <= 2 && data.order == Order::Ascending && !config.path_dependent() {
+        let value = |i: usize| input.values[i];
+        let mut picks = LastPicks::new(value, start, n, remaining_budget, allowed == 2, remaining_needed <= 1);
+        let found = picks.next(value);
+        counter.nodes += picks.steps;
+        if let Some((i, j)) = found {
+            path.push(i);
+            path.extend(j);
+            return BbResult::Found;
+        }
+        if let Some(misses) = &mut counter.misses {
+            misses.offer_last_picks(input, start, current_sum, path, allowed == 2, remaining_needed <= 1);
+        }
+        if nodes_before >> 12 != counter.nodes >> 12 && config.controller.should_stop(counter.nodes).is_break() {
+            return BbResult::Cancelled;
+        }
+        if picks.steps >= DEAD_END_MIN_NODES {
+            if let Some(memo) = counter.dead_ends.as_deref_mut() {
+                memo.record(start, remaining_budget.to_u64(), remaining_needed, allowed);
+            }
+        }
+        return BbResult::NotFound;
+    }
+
+    for i in start..n {
+        let value = input.values[i];
+
+        // Sorted ascending: once one element exceeds budget, all after do too.
+        // Other orders can only skip it. Either way it is one step past the
+        // target, and the nearest overshoot from here when ascending
+        if value > remaining_budget {
+            if current_count + 1 >= config.min_count {
+                if let Some(misses) = &mut counter.misses {
+                    misses.offer(current_sum.to_u64() as u128 + value.to_u64() as u128, path, [i]);
+                }
+            }
+            if data.order == Order::Ascending {
+                break;
+            }
+            continue;
+        }
+
+        // If sum of all remaining elements can't reach target, prune; the
+        // most of them the count allows are then the nearest below from here
+        if input.suffix_sum[i] < remaining_budget {
+            if let Some(misses) = &mut counter.misses {
+                misses.offer_rest(input, i, current_sum, path, allowed, data.order == Order::Ascending);
+            }
+            break;
+        }
+
+        // Not enough elements left to meet min_count
+        if (n - i) < remaining_needed {
+            break;
+        }
+
+        let chosen = path.iter().map(|&p| data.sorted[p].original_index);
+        if config.row_taken(chosen.clone(), data.sorted[i].original_index)
+            || config.outside_span(chosen, data.sorted[i].original_index)
+        {
+            continue;
+        }
+
+        path.push(i);
+        let result = bb_dfs_first(
+            input, i + 1,
+            current_sum + value,
+            current_count + 1,
+            path, counter,
+        );
+
+        match result {
+            BbResult::Found => return BbResult::Found,
+            BbResult::Cancelled => return BbResult::Cancelled,
+            BbResult::BudgetExhausted => return BbResult::BudgetExhausted,
+            BbResult::NotFound => { path.pop(); }
+        }
+    }
+
+    if counter.nodes - nodes_before >= DEAD_END_MIN_NODES {
+        if let Some(memo) = counter.dead_ends.as_deref_mut() {
+            memo.record(start, remaining_budget.to_u64(), remaining_needed, allowed);
+        }
+    }
+    BbResult::NotFound
+}
+
+/// Why branch_and_bound_all stopped before the end of its tree.
+enum AllStop {
+    Cancelled,
+    MaxResults,
+}
+
+/// Add `steps` nodes to the count, asking the controller whenever it crosses a
+/// multiple of 4096.
+fn charge_nodes(config: &SolverConfig, nodes: &mut u64, steps: u64) -> ControlFlow<AllStop> {
+    let before = *nodes;
+    *nodes += steps;
+    if before >> 12 != *nodes >> 12 && config.controller.should_stop(*nodes).is_break() {
+        return ControlFlow::Break(AllStop::Cancelled);
+    }
+    ControlFlow::Continue(())
+}
+
+/// Walk one frame: every child of `path` from `start` on, recording solutions
+/// as they are met. Nodes are charged as in BatchSearchState::search_batch: one
+/// per child walked into (or cut there), and one for the last look at a frame.
+#[allow(dead_code)]
+fn branch_and_bound_all(
+    data: &PreparedData,
+    config: &SolverConfig,
+    start: usize,
+    current_sum: u64,
+    path: &mut Vec<usize>,
+    max_results: usize,
+    all: &mut AllCombinationsResult,
+) -> ControlFlow<AllStop> {
+    let current_count = path.len();
+    let n = data.sorted.len();
+    let remaining_needed = config.min_count.saturating_sub(current_count);
+    let remaining_budget = config.target - current_sum;
+
+    // One or two picks left: every completion from one scan (see bb_dfs_first)
+    let allowed = config.max_count - current_count;
+    if allowed <= 2 && data.order == Order::Ascending && !config.path_dependent() {
+        let value = |i: usize| data.sorted[i].value;
+        let mut picks = LastPicks::new(value, start, n, remaining_budget, allowed == 2, remaining_needed <= 1);
+        let mut capped = false;
+        while let Some((i, j)) = picks.next(value) {
+            // Entries equal to the target sort last; only the first is reported,
+            // when alone (after zeros the sum is no sign of an empty path)
+            if j.is_none() && current_count == 0 && i > 0 && data.sorted[i - 1].value == remaining_budget {
+                break;
+            }
+            let entries = path.iter().chain([i].iter()).chain(j.iter()).map(|&k| data.sorted[k].clone()).collect();
+            all.results.push(entries);
+            capped = all.results.len() >= max_results;
+            if capped {
+                break;
+            }
+        }
+        charge_nodes(config, &mut all.nodes_explored, 1 + picks.steps)?;
+        return if capped { ControlFlow::Break(AllStop::MaxResults) } else { ControlFlow::Continue(()) };
+    }
+
+    for i in start..n {
+        let value = data.sorted[i].value;
+
+        if value > remaining_budget {
+            break;
+        }
+        if data.suffix_sum[i] < remaining_budget {
+            break;
         }
         if (n - i) < remaining_needed {
             break;
         }
-        if results.len() >= max_results {
-            return;
+        // Entries equal to the target sort last; only the first is reported, when
+        // alone: after zeros each copy pads a result of its own
+        if current_count == 0 && value == config.target && i > 0 && data.sorted[i - 1].value == value {
+            break;
+        }
+        let chosen = path.iter().map(|&p| data.sorted[p].original_index);
+        if config.row_taken(chosen.clone(), data.sorted[i].original_index)
+            || config.outside_span(chosen, data.sorted[i].original_index)
+        {
+            continue;
+        }
+
+        // A solution, and nothing below it makes another: the order is
+        // ascending, so zeros sort first and every row after this one adds to
+        // the sum
+        if value == remaining_budget && current_count + 1 >= config.min_count {
+            let entries = path.iter().chain([i].iter()).map(|&k| data.sorted[k].clone()).collect();
+            all.results.push(entries);
+            if all.results.len() >= max_results {
+                return ControlFlow::Break(AllStop::MaxResults);
+            }
+            continue;
+        }
+
+        charge_nodes(config, &mut all.nodes_explored, 1)?;
+        // Past max_count, or a pinned count no pick of the rest can reach: cut
+        let picks_left = config.max_count - current_count - 1;
+        if picks_left > 0
+            && (config.min_count != config.max_count
+                || exact_picks_reach(&data.suffix_sum, i + 1, picks_left, remaining_budget - value, data.order == Order::Ascending))
+        {
+            path.push(i);
+            let walked = branch_and_bound_all(data, config, i + 1, current_sum + value, path, max_results, all);
+            path.pop();
+            walked?;
+        }
+    }
+    charge_nodes(config, &mut all.nodes_explored, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, brute_force, index_sets, subsets};
+    use std::sync::atomic::AtomicBool;
+
+    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
+        nums.iter().enumerate()
+            .map(|(i, &v)| NumberEntry { value: v, original_index: OriginalIndex::new(i) })
+            .collect()
+    }
+
+    fn make_config(target: u64, min: usize, max: usize) -> SolverConfig<'static> {
+        static FALSE: AtomicBool = AtomicBool::new(false);
+        SolverConfig {
+            target,
+            min_count: min,
+            max_count: max,
+            controller: &FALSE,
+            hybrid_bb_budget: DEFAULT_HYBRID_BB_BUDGET,
+            row_ids: None,
+            max_index_span: None,
+            memory_limit: None,
+        }
+    }
+
+    #[test]
+    fn test_simple_case() {
+        let nums = vec![1, 2, 3, 4, 5];
+        let entries = make_entries(&nums);
+        let config = make_config(9, 2, 3);
+        match solve_subset_sum(&entries, &config) {
+            Ok(Some(result)) => {
+                let sum: u64 = result.iter().map(|e| e.value).sum();
+                assert_eq!(sum, 9);
+                assert!(result.len() >= 2 && result.len() <= 3);
+                // Verify original indices are correct
+                for e in &result {
+                    assert_eq!(e.value, nums[e.original_index.as_usize()]);
+                }
+            }
+            _ => panic!("Should have found a solution"),
+        }
+    }
+
+    #[test]
+    fn test_no_solution() {
+        let nums = vec![10, 20, 30];
+        let entries = make_entries(&nums);
+        let config = make_config(5, 1, 3);
+        match solve_subset_sum(&entries, &config) {
+            Ok(None) => {}
+            _ => panic!("Should not have found a solution"),
+        }
+    }
+
+    #[test]
+    fn test_exact_single() {
+        let nums = vec![100, 200, 300];
+        let entries = make_entries(&nums);
+        let config = make_config(200, 1, 1);
+        match solve_subset_sum(&entries, &config) {
+            Ok(Some(result)) => {
+                assert_eq!(result.len(), 1);
+                assert_eq!(result[0].value, 200);
+                assert_eq!(result[0].original_index.as_usize(), 1);
+            }
+            _ => panic!("Should have found 200"),
+        }
+    }
+
+    #[test]
+    fn test_all_combinations() {
+        let nums = vec![1, 2, 3, 4, 5];
+        let entries = make_entries(&nums);
+        let config = make_config(5, 1, 5);
+        let results = solve_all_combinations(&entries, &config, 100).results;
+        // Valid combos: [5], [1,4], [2,3]
+        assert!(results.len() >= 3);
+        for combo in &results {
+            let sum: u64 = combo.iter().map(|e| e.value).sum();
+            assert_eq!(sum, 5);
+        }
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        let nums = vec![
+            1_000_000_000_000u64,
+            500_000_000_000,
+            250_000_000_000,
+            125_000_000_000,
+            375_000_000_000,
+        ];
+        let entries = make_entries(&nums);
+        let config = make_config(875_000_000_000, 2, 4);
+        match solve_subset_sum(&entries, &config) {
+            Ok(Some(result)) => {
+                let sum: u64 = result.iter().map(|e| e.value).sum();
+                assert_eq!(sum, 875_000_000_000);
+                for e in &result {
+                    assert_eq!(e.value, nums[e.original_index.as_usize()]);
+                }
+            }
+            _ => panic!("Should have found a solution"),
+        }
+    }
+
+    #[test]
+    fn test_meet_in_the_middle_threshold() {
+        let nums: Vec<u64> = (1..=30).collect();
+        let entries = make_entries(&nums);
+        let config = make_config(100, 3, 10);
+        match solve_subset_sum(&entries, &config) {
+            Ok(Some(result)) => {
+                let sum: u64 = result.iter().map(|e| e.value).sum();
+                assert_eq!(sum, 100);
+                for e in &result {
+                    assert_eq!(e.value, nums[e.original_index.as_usize()]);
+                }
+            }
+            _ => panic!("Should have found a solution"),
+        }
+    }
+
+    #[test]
+    fn test_original_indices_preserved_with_gaps() {
+        // Simulate CSV with some invalid rows filtered by lib.rs
+        // Original CSV: [0, 5, 0, 3, 7, 0, 2] -> after filtering: entries at indices 1,3,4,6
+        let entries = vec![
+            NumberEntry { value: 5, original_index: OriginalIndex::new(1) },
+            NumberEntry { value: 3, original_index: OriginalIndex::new(3) },
+            NumberEntry { value: 7, original_index: OriginalIndex::new(4) },
+            NumberEntry { value: 2, original_index: OriginalIndex::new(6) },
+        ];
+        let config = make_config(10, 2, 4);
+        match solve_subset_sum(&entries, &config) {
+            Ok(Some(result)) => {
+                let sum: u64 = result.iter().map(|e| e.value).sum();
+                assert_eq!(sum, 10);
+                // Verify original indices are from the original set {1,3,4,6}
+                for e in &result {
+                    assert!(
+                        [1, 3, 4, 6].contains(&e.original_index.as_usize()),
+                        "Unexpected original_index: {:?}",
+                        e.original_index
+                    );
+                }
+            }
+            _ => panic!("Should have found a solution"),
+        }
+    }
+
+    fn found(entries: &[NumberEntry], config: &SolverConfig) -> Vec<NumberEntry> {
+        match solve_subset_sum(entries, config) {
+            Ok(Some(result)) => result,
+            _ => panic!("Should have found a solution"),
+        }
+    }
+
+    #[test]
+    fn test_uniqueness_single_solution() {
+        // Powers of two: every subset sum is distinct
+        let entries = make_entries(&[1, 2, 4, 8]);
+        let config = make_config(5, 1, 4);
+        let known = found(&entries, &config);
+        let check = check_uniqueness(&entries, &config, &known, 10_000);
+        assert_eq!(check.uniqueness, Uniqueness::Unique);
+        assert!(check.nodes_explored > 0);
+    }
+
+    #[test]
+    fn test_uniqueness_two_solutions() {
+        // [1,4] and [2,3]
+        let entries = make_entries(&[1, 2, 3, 4]);
+        let config = make_config(5, 2, 2);
+        let known = found(&entries, &config);
+        let check = check_uniqueness(&entries, &config, &known, 10_000);
+        assert_eq!(check.uniqueness, Uniqueness::NotUnique);
+    }
+
+    #[test]
+    fn test_uniqueness_budget_expired() {
+        let nums: Vec<u64> = (1..=50).collect();
+        let entries = make_entries(&nums);
+        let config = make_config(50, 1, 5);
+        let known = found(&entries, &config);
+        let check = check_uniqueness(&entries, &config, &known, 1);
+        assert_eq!(check.uniqueness, Uniqueness::Unknown);
+        assert_eq!(check.nodes_explored, 1);
+    }
+
+    #[test]
+    fn test_combination_id_ignores_order() {
+        let a = vec![
+            NumberEntry { value: 3, original_index: OriginalIndex::new(7) },
+            NumberEntry { value: 5, original_index: OriginalIndex::new(2) },
+        ];
+        let b = vec![a[1].clone(), a[0].clone()];
+        assert_eq!(combination_id(&a), combination_id(&b));
+    }
+
+    #[test]
+    fn test_combination_id_stable_across_runs() {
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6]);
+        let config = make_config(7, 1, 6);
+        let first: Vec<u64> = solve_all_combinations(&entries, &config, 100).results
+            .iter().map(|c| combination_id(c)).collect();
+        let second: Vec<u64> = solve_all_combinations(&entries, &config, 100).results
+            .iter().map(|c| combination_id(c)).collect();
+        assert_eq!(first, second);
+
+        // Pinned so a hashing change can't slip through unnoticed
+        assert_eq!(combination_id(&make_entries(&[9])), 0xa8c7_f832_281a_39c5);
+    }
+
+    #[test]
+    fn test_combination_id_distinct_combinations() {
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6]);
+        let config = make_config(7, 1, 6);
+        let mut ids: Vec<u64> = solve_all_combinations(&entries, &config, 100).results
+            .iter().map(|c| combination_id(c)).collect();
+        let total = ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), total);
+        assert!(total > 1);
+    }
+
+    #[test]
+    fn test_canonicalize_results_ignores_order() {
+        let entries = make_entries(&[1, 2, 3, 4, 5, 6]);
+        let indices = |results: &[Vec<NumberEntry>]| -> Vec<Vec<usize>> {
+            results.iter().map(|c| c.iter().map(|e| e.original_index.as_usize()).collect()).collect()
+        };
+        let mut forward = solve_all_combinations(&entries, &make_config(7, 1, 6), 100).results;
+        let mut shuffled: Vec<Vec<NumberEntry>> = forward.iter().rev()
+            .map(|c| c.iter().rev().cloned().collect())
+            .collect();
+        canonicalize_results(&mut forward);
+        canonicalize_results(&mut shuffled);
+        assert_eq!(indices(&forward), indices(&shuffled));
+        // 7 = 1+6 = 2+5 = 3+4 = 1+2+4, as 0-based indices
+        assert_eq!(indices(&forward), vec![vec![0, 1, 3], vec![0, 5], vec![1, 4], vec![2, 3]]);
+    }
+
+    fn assert_valid(result: &[NumberEntry], nums: &[u64], config: &SolverConfig) {
+        let sum: u64 = result.iter().map(|e| e.value).sum();
+        assert_eq!(sum, config.target);
+        assert!(result.len() >= config.min_count && result.len() <= config.max_count);
+        for e in result {
+            assert_eq!(e.value, nums[e.original_index.as_usize()]);
+        }
+    }
+
+    #[test]
+    fn test_row_ids_forbid_two_copies() {
+        // Only 5 + 5 reaches 10, and both 5s are one physical row
+        let entries = make_entries(&[5, 5, 3]);
+        let ids = [0, 0, 1];
+        let config = SolverConfig { row_ids: Some(&ids), ..make_config(10, 1, 3) };
+        assert!(matches!(solve_subset_sum(&entries, &config), Ok(None)));
+        assert!(matches!(solve_depth_first_with_stats(&entries, &config).0, Ok(None)));
+        assert!(solve_all_combinations(&entries, &config, usize::MAX).results.is_empty());
+        let mut state = BatchSearchState::new(&entries, 10, 1, 3, usize::MAX);
+        state.set_row_ids(&ids);
+        assert_eq!(state.search_batch(1000).total_found, 0);
+        // Distinct ids: the pair is fine
+        let config = SolverConfig { row_ids: Some(&[0, 1, 2]), ..make_config(10, 1, 3) };
+        assert!(matches!(solve_subset_sum(&entries, &config), Ok(Some(_))));
+    }
+
+    #[test]
+    fn test_row_ids_match_brute_force() {
+        let mut rng = testing::rng();
+        for _ in 0..200 {
+            let n = 1 + rng.below(12) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(9)).collect();
+            let ids: Vec<u32> = (0..n).map(|_| rng.below(n as u64) as u32).collect();
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let entries = make_entries(&nums);
+            let config = SolverConfig { row_ids: Some(&ids), ..make_config(target, 1, n) };
+
+            let valid = |indices: &[usize]| {
+                let mut seen: Vec<u32> = indices.iter().map(|&i| ids[i]).collect();
+                seen.sort_unstable();
+                seen.dedup();
+                seen.len() == indices.len() && indices.iter().map(|&i| nums[i]).sum::<u64>() == target
+            };
+            // Rows equal to the target are reported once between them
+            let copies = nums.iter().filter(|&&v| v == target).count();
+            let expected = subsets(&nums, 1, n).filter(|rows| valid(rows)).count() - copies.saturating_sub(1);
+
+            let all = solve_all_combinations(&entries, &config, usize::MAX).results;
+            assert_eq!(all.len(), expected);
+            let mut state = BatchSearchState::new(&entries, target, 1, n, usize::MAX);
+            state.set_row_ids(&ids);
+            while !state.search_batch(7).finished {}
+            assert_eq!(state.found(), expected);
+            for combo in all.iter().chain(state.all_results()) {
+                assert!(valid(&combo.iter().map(|e| e.original_index.as_usize()).collect::<Vec<_>>()));
+            }
+            for result in [solve_subset_sum(&entries, &config), solve_depth_first_with_stats(&entries, &config).0] {
+                match result {
+                    Ok(Some(found)) => {
+                        assert!(valid(&found.iter().map(|e| e.original_index.as_usize()).collect::<Vec<_>>()));
+                    }
+                    _ => assert_eq!(expected, 0, "{:?} ids {:?} target {}", nums, ids, target),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_span_keeps_rows_close() {
+        // 10 + 20 (rows 0 and 9) is the canonical match; 12 + 18 (rows 10 and
+        // 11) is the only one within 5 rows
+        let nums = [10, 100, 100, 100, 100, 100, 100, 100, 100, 20, 12, 18];
+        let entries = make_entries(&nums);
+        let indices = |result: SolveOutcome| match result {
+            Ok(Some(found)) => {
+                let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                indices
+            }
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(indices(solve_subset_sum(&entries, &make_config(30, 1, 4))), vec![0, 9]);
+        let config = SolverConfig { max_index_span: Some(5), ..make_config(30, 1, 4) };
+        assert_eq!(indices(solve_subset_sum(&entries, &config)), vec![10, 11]);
+        assert_eq!(indices(solve_mitm_with_stats(&entries, &config).0), vec![10, 11]);
+        assert_eq!(indices(solve_depth_first_with_stats(&entries, &config).0), vec![10, 11]);
+        let mut state = BatchSearchState::new(&entries, 30, 1, 4, usize::MAX);
+        state.set_max_index_span(5);
+        assert_eq!(state.search_batch(10_000).total_found, 1);
+        assert_eq!(state.all_results()[0].iter().map(|e| e.original_index.as_usize()).collect::<Vec<_>>(), vec![10, 11]);
+        // Nothing within 0 rows: a single row never reaches 30
+        let config = SolverConfig { max_index_span: Some(0), ..make_config(30, 1, 4) };
+        assert!(matches!(solve_subset_sum(&entries, &config), Ok(None)));
+        assert!(matches!(solve_depth_first_with_stats(&entries, &config).0, Ok(None)));
+    }
+
+    #[test]
+    fn test_exact_count_prunes_by_extreme_sums() {
+        // Exactly three of 1..=60 summing to 150: below a first pick of 31 even
+        // 59 + 60 fall short, which the count window alone never noticed
+        let entries = make_entries(&(1..=60).collect::<Vec<u64>>());
+        let config = make_config(150, 3, 3);
+        let (result, stats) = solve_depth_first_with_stats(&entries, &config);
+        assert!(matches!(result, Ok(Some(_))));
+        // 30,359 nodes before
+        assert!(stats.phases[0].nodes < 1_000, "{:?}", stats.phases);
+
+        let mut state = BatchSearchState::new(&entries, 150, 3, 3, usize::MAX);
+        let result = state.search_batch(u64::MAX);
+        assert!(result.finished);
+        assert_eq!(result.total_found, 75);
+        // 35,203 nodes before
+        assert!(result.nodes_explored < 5_000, "{}", result.nodes_explored);
+        assert_eq!(solve_all_combinations(&entries, &config, usize::MAX).results.len(), 75);
+    }
+
+    #[test]
+    fn test_exact_count_matches_brute_force() {
+        let mut rng = testing::rng();
+        for _ in 0..300 {
+            let n = 1 + rng.below(12) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(20)).collect();
+            let k = 1 + rng.below(n as u64) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let entries = make_entries(&nums);
+            let config = make_config(target, k, k);
+
+            let exact = brute_force(&nums, target, k, k);
+            let up_to_next = brute_force(&nums, target, k, k + 1);
+            // Rows equal to the target are one result between them
+            let copies = if k == 1 { nums.iter().filter(|&&v| v == target).count().saturating_sub(1) } else { 0 };
+
+            let found = |result: SolveOutcome| matches!(result, Ok(Some(_)));
+            assert_eq!(found(solve_depth_first_with_stats(&entries, &config).0), !exact.is_empty(), "{:?} k {} target {}", nums, k, target);
+            assert_eq!(found(solve_subset_sum(&entries, &config)), !exact.is_empty());
+            assert_eq!(solve_all_combinations(&entries, &config, usize::MAX).results.len(), exact.len() - copies);
+
+            // Raising max_count walks what the pinned count cut, exactly once
+            let mut state = BatchSearchState::new(&entries, target, k, k, usize::MAX);
+            while !state.search_batch(5).finished {}
+            assert_eq!(state.found(), exact.len() - copies);
+            state.set_max_count(k + 1);
+            while !state.search_batch(5).finished {}
+            let mut seen = index_sets(state.all_results());
+            let before = seen.len();
+            seen.dedup();
+            assert_eq!(seen.len(), before, "{:?} k {} target {}", nums, k, target);
+            assert!(seen.iter().all(|indices| up_to_next.contains(indices)));
+            assert_eq!(seen.len(), up_to_next.len() - copies, "{:?} k {} target {}", nums, k, target);
+        }
+    }
+
+    #[test]
+    fn test_analyze_target() {
+        let entries = make_entries(&[2, 100, 5, 3]);
+        let rows = |indices: &[usize]| indices.iter().map(|&i| OriginalIndex::new(i)).collect::<Vec<_>>();
+        let analysis = analyze_target(&entries, &make_config(105, 1, 4));
+        assert_eq!(analysis.total_value, 110);
+        assert_eq!((analysis.mandatory, analysis.irrelevant), (rows(&[1]), rows(&[])));
+        // At most two rows: 100 + 5 is the only way, so 2 and 3 have no part in it
+        let analysis = analyze_target(&entries, &make_config(105, 1, 2));
+        assert_eq!(analysis.count_window, Some((2, 2)));
+        assert_eq!((analysis.mandatory, analysis.irrelevant), (rows(&[1, 2]), rows(&[0, 3])));
+        // 100 is past the target; 2 + 5 + 3 is the only way left, but the bounds
+        // still count on 100 as a large other, so they don't show it
+        let analysis = analyze_target(&entries, &make_config(10, 3, 4));
+        assert_eq!((analysis.mandatory, analysis.irrelevant), (rows(&[]), rows(&[1])));
+        assert_eq!(analyze_target(&entries, &make_config(500, 1, 4)), TargetAnalysis { total_value: 110, ..TargetAnalysis::default() });
+        assert_eq!(analyze_target(&[], &make_config(5, 1, 4)), TargetAnalysis::default());
+    }
+
+    #[test]
+    fn test_analyze_target_is_sound() {
+        let mut rng = testing::rng();
+        for _ in 0..500 {
+            let n = 1 + rng.below(10) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(30)).collect();
+            let max = 1 + rng.below(n as u64) as usize;
+            let min = 1 + rng.below(max as u64) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let analysis = analyze_target(&make_entries(&nums), &make_config(target, min, max));
+            for rows in brute_force(&nums, target, min, max) {
+                assert!(analysis.mandatory.iter().all(|i| rows.contains(&i.as_usize())), "{:?} [{}, {}] {}: {:?}", nums, min, max, target, rows);
+                assert!(analysis.irrelevant.iter().all(|i| !rows.contains(&i.as_usize())), "{:?} [{}, {}] {}: {:?}", nums, min, max, target, rows);
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_picks_match_recursion() {
+        let mut rng = testing::rng();
+        for _ in 0..2000 {
+            let n = rng.below(14) as usize;
+            let mut values: Vec<u64> = (0..n).map(|_| 1 + rng.below(12)).collect();
+            values.sort_unstable();
+            let start = rng.below(n as u64 + 1) as usize;
+            let budget = 1 + rng.below(24);
+            let (pair, single) = (rng.below(2) == 0, rng.below(2) == 0);
+
+            // What a recursion one or two rows deep reaches, in its order
+            let mut expected = Vec::new();
+            for i in start..n {
+                if single && values[i] == budget {
+                    expected.push((i, None));
+                }
+                for j in (i + 1..n).filter(|_| pair) {
+                    if values[i] + values[j] == budget {
+                        expected.push((i, Some(j)));
+                    }
+                }
+            }
+            let value = |i: usize| values[i];
+            let mut picks = LastPicks::new(value, start, n, budget, pair, single);
+            let found: Vec<(usize, Option<usize>)> = std::iter::from_fn(|| picks.next(value)).collect();
+            assert_eq!(found, expected, "{:?} from {} budget {} pair {} single {}", values, start, budget, pair, single);
+            assert!(picks.steps <= (n - start + found.len()) as u64);
         }
+    }
 
-        path.push(i);
-        branch_and_bound_all(
-            data, config, i + 1,
-            current_sum + value,
-            path, results, max_results, check_counter,
-        );
-        path.pop();
+    #[test]
+    fn test_all_combinations_reports_why_it_stopped() {
+        let entries = make_entries(&(1..=10).collect::<Vec<u64>>());
+        let all = solve_all_combinations(&entries, &make_config(15, 1, 10), usize::MAX);
+        assert_eq!((all.results.len(), all.cancelled, all.truncated_by_max_results), (20, false, false));
+        let capped = solve_all_combinations(&entries, &make_config(15, 1, 10), 3);
+        assert_eq!((capped.results.len(), capped.cancelled, capped.truncated_by_max_results), (3, false, true));
+        assert!(capped.nodes_explored < all.nodes_explored);
+
+        // Odd against even values: nothing to find, so only a stop ends it early,
+        // and the whole DFS unwinds from the first checkpoint past the limit
+        let entries: Vec<NumberEntry> = (1..=60).map(|i| NumberEntry { value: 2 * i, original_index: OriginalIndex::new(i as usize - 1) }).collect();
+        let limit = crate::control::NodeLimit(10_000);
+        let config = SolverConfig { controller: &limit, ..make_config(1001, 1, 60) };
+        let stopped = solve_all_combinations(&entries, &config, usize::MAX);
+        assert!(stopped.cancelled && !stopped.truncated_by_max_results && stopped.results.is_empty());
+        assert!((10_000..10_000 + 4096 + 60).contains(&stopped.nodes_explored), "{}", stopped.nodes_explored);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::atomic::AtomicBool;
+    #[test]
+    fn test_all_combinations_count_nodes_as_the_batch_does() {
+        let mut rng = testing::rng();
+        for _ in 0..300 {
+            let n = 1 + rng.below(14) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(12)).collect();
+            let min = 1 + rng.below(3) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let entries = make_entries(&nums);
 
-    fn make_entries(nums: &[u64]) -> Vec<NumberEntry> {
-        nums.iter().enumerate()
-            .map(|(i, &v)| NumberEntry { value: v, original_index: i })
-            .collect()
+            let all = solve_all_combinations(&entries, &make_config(target, min, n), usize::MAX);
+            let mut state = BatchSearchState::new(&entries, target, min, n, usize::MAX);
+            // In one batch, so no scan is cut short at a batch's end
+            assert!(state.search_batch(u64::MAX).finished);
+            assert_eq!(all.results.len(), state.found(), "{:?} [{}, {}] target {}", nums, min, n, target);
+            assert_eq!(all.nodes_explored, state.nodes_explored(), "{:?} [{}, {}] target {}", nums, min, n, target);
+        }
     }
 
-    fn make_config(target: u64, min: usize, max: usize) -> SolverConfig<'static> {
-        static FALSE: AtomicBool = AtomicBool::new(false);
-        SolverConfig {
-            target,
-            min_count: min,
-            max_count: max,
-            cancelled: &FALSE,
+    #[test]
+    fn test_last_picks_searches_match_brute_force() {
+        let mut rng = testing::rng();
+        for _ in 0..300 {
+            let n = 1 + rng.below(12) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(10)).collect();
+            let max = 1 + rng.below(4) as usize;
+            let min = 1 + rng.below(max as u64) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let entries = make_entries(&nums);
+            let config = make_config(target, min, max);
+
+            let mut expected = brute_force(&nums, target, min, max);
+            // Rows equal to the target are one result between them: the first
+            let copies: Vec<usize> = (0..n).filter(|&i| nums[i] == target).skip(1).collect();
+            expected.retain(|indices| !(indices.len() == 1 && copies.contains(&indices[0])));
+
+            let all = solve_all_combinations(&entries, &config, usize::MAX).results;
+            assert_eq!(index_sets(&all), expected, "{:?} [{}, {}] target {}", nums, min, max, target);
+            let mut state = BatchSearchState::new(&entries, target, min, max, usize::MAX);
+            while !state.search_batch(3).finished {}
+            assert_eq!(index_sets(state.all_results()), expected, "{:?} [{}, {}] target {}", nums, min, max, target);
+
+            // B&B answers with the batch search's first result
+            match solve_depth_first_with_stats(&entries, &config).0 {
+                Ok(Some(found)) => assert_eq!(index_sets(&[found]), index_sets(&state.all_results()[..1])),
+                _ => assert!(expected.is_empty()),
+            }
         }
     }
 
+    /// B&B over 300 entries with up to four picks and no match (even values,
+    /// odd target), against the same search recursing to the last row: a span
+    /// no combination exceeds makes it path-dependent, which turns the scans off.
+    /// Run with `cargo test --release bench_last_picks -- --ignored --nocapture`.
     #[test]
-    fn test_simple_case() {
-        let nums = vec![1, 2, 3, 4, 5];
+    #[ignore]
+    fn bench_last_picks() {
+        let mut rng = testing::rng();
+        let nums: Vec<u64> = (0..300).map(|_| 2 * (1 + rng.below(500_000))).collect();
         let entries = make_entries(&nums);
-        let config = make_config(9, 2, 3);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
-                let sum: u64 = result.iter().map(|e| e.value).sum();
-                assert_eq!(sum, 9);
-                assert!(result.len() >= 2 && result.len() <= 3);
-                // Verify original indices are correct
-                for e in &result {
-                    assert_eq!(e.value, nums[e.original_index]);
+        for span in [None, Some(usize::MAX)] {
+            let config = SolverConfig { max_index_span: span, ..make_config(1_500_001, 1, 4) };
+            let start = std::time::Instant::now();
+            let (result, stats) = solve_depth_first_with_stats(&entries, &config);
+            assert!(matches!(result, Ok(None)));
+            let scans = if span.is_none() { "scans" } else { "recursion" };
+            println!("{:>9}: {:?} for {} nodes", scans, start.elapsed(), stats.phases[0].nodes);
+        }
+    }
+
+    #[test]
+    fn test_index_span_matches_brute_force() {
+        let mut rng = testing::rng();
+        for _ in 0..300 {
+            let n = 1 + rng.below(14) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(9)).collect();
+            let span = rng.below(n as u64) as usize;
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let entries = make_entries(&nums);
+            let config = SolverConfig { max_index_span: Some(span), ..make_config(target, 1, n) };
+
+            // Index sets as ascending vectors
+            let valid = |indices: &[usize]| {
+                indices[indices.len() - 1] - indices[0] <= span && indices.iter().map(|&i| nums[i]).sum::<u64>() == target
+            };
+            let mut matches: Vec<Vec<usize>> = subsets(&nums, 1, n).filter(|indices| valid(indices)).collect();
+            let copies = nums.iter().filter(|&&v| v == target).count();
+            let mut state = BatchSearchState::new(&entries, target, 1, n, usize::MAX);
+            state.set_max_index_span(span);
+            while !state.search_batch(7).finished {}
+            assert_eq!(state.found(), matches.len() - copies.saturating_sub(1));
+            for combo in state.all_results() {
+                let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                assert!(valid(&indices));
+            }
+            // MITM's canonical pick: fewest rows, then the smallest index set
+            matches.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+            let (mitm, _) = solve_mitm_with_stats(&entries, &config);
+            let (bnb, _) = solve_depth_first_with_stats(&entries, &config);
+            match (mitm, bnb) {
+                (Ok(Some(mitm)), Ok(Some(bnb))) => {
+                    let mut indices: Vec<usize> = mitm.iter().map(|e| e.original_index.as_usize()).collect();
+                    indices.sort_unstable();
+                    assert_eq!(indices, matches[0], "{:?} span {} target {}", nums, span, target);
+                    let mut indices: Vec<usize> = bnb.iter().map(|e| e.original_index.as_usize()).collect();
+                    indices.sort_unstable();
+                    assert!(valid(&indices));
                 }
+                (Ok(None), Ok(None)) => assert!(matches.is_empty()),
+                other => panic!("{:?}", other),
             }
-            _ => panic!("Should have found a solution"),
         }
     }
 
     #[test]
-    fn test_no_solution() {
-        let nums = vec![10, 20, 30];
+    fn test_find_session_matches_fresh_solves() {
+        let mut rng = testing::rng();
+        // Multiples of 3 against targets that are not: whole trees proved empty
+        let nums: Vec<u64> = (0..70).map(|_| 3 * (1 + rng.below(100))).collect();
         let entries = make_entries(&nums);
-        let config = make_config(5, 1, 3);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::NotFound => {}
-            _ => panic!("Should not have found a solution"),
+        let session = FindSession::new(&entries, None).unwrap();
+        let outcome = |result: &SolveOutcome| match result {
+            Ok(Some(found)) => {
+                let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                Some(indices)
+            }
+            Ok(None) => None,
+            Err(err) => panic!("no answer: {:?}", err),
+        };
+        let bnb_nodes = |stats: &SolverStats| stats.phases.iter().map(|p| p.nodes).sum::<u64>();
+        for (target, min, max) in [
+            (1000, 1, 4), (1000, 1, 3), (1000, 2, 4), (1000, 4, 4), (999, 1, 4), (999, 5, 5),
+            (1000, 1, 5), (1000, 1, 4), (998, 2, 3), (999, 2, 4),
+        ] {
+            let config = make_config(target, min, max);
+            let (fresh, fresh_stats) = solve_subset_sum_with_stats(&entries, &config);
+            let (result, stats) = session.solve(&config, Algorithm::Auto);
+            assert_eq!(outcome(&result), outcome(&fresh), "target {} window [{}, {}]", target, min, max);
+            assert!(bnb_nodes(&stats) <= bnb_nodes(&fresh_stats));
         }
+        assert!(session.dead_ends() > 0);
+
+        // A window inside one already proved empty costs a single node
+        let (result, stats) = session.solve(&make_config(1000, 1, 4), Algorithm::Auto);
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(bnb_nodes(&stats), 1);
     }
 
     #[test]
-    fn test_exact_single() {
-        let nums = vec![100, 200, 300];
-        let entries = make_entries(&nums);
-        let config = make_config(200, 1, 1);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
-                assert_eq!(result.len(), 1);
-                assert_eq!(result[0].value, 200);
-                assert_eq!(result[0].original_index, 1);
+    fn test_forced_mitm() {
+        // Past 40 entries, so the hybrid strategy would run B&B first
+        let nums: Vec<u64> = (0..44).map(|i| 1_000 + i * i).collect();
+        let target = nums[3] + nums[17] + nums[43];
+        let (result, stats) = solve_mitm_with_stats(&make_entries(&nums), &make_config(target, 3, 3));
+        let Ok(Some(found)) = result else { panic!("{:?}", result) };
+        assert_eq!(found.iter().map(|e| e.value).sum::<u64>(), target);
+        let algorithms: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+        assert_eq!(algorithms, vec!["mitm"]);
+        assert_eq!(Algorithm::parse("mitm").map(Algorithm::as_str), Some("mitm"));
+    }
+
+    #[test]
+    fn test_mitm_peak_memory_estimate() {
+        // Two more entries, one per half, double the left map
+        let peak = |n: u64| {
+            let nums: Vec<u64> = (0..n).map(|i| 1_000 + i * i).collect();
+            let target = nums[0] + nums[1] + nums[2];
+            let (result, stats) = solve_mitm_with_stats(&make_entries(&nums), &make_config(target, 3, 3));
+            assert!(matches!(result, Ok(Some(_))), "{:?}", result);
+            stats.peak_memory_estimate as f64
+        };
+        for n in [24, 28, 32] {
+            let ratio = peak(n + 2) / peak(n);
+            assert!((1.8..2.2).contains(&ratio), "n = {}: {}", n, ratio);
+        }
+        // At least the table itself: a bucket and a key per left subset
+        assert!(peak(32) >= ((1 << 16) * (size_of::<(u64, Vec<u128>)>() + size_of::<u128>())) as f64);
+
+        let (_, stats) = solve_depth_first_with_stats(&make_entries(&[1, 2, 3]), &make_config(3, 1, 2));
+        assert_eq!(stats.peak_memory_estimate, (3 * size_of::<NumberEntry>() + 4 * size_of::<u64>()) as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "MITM entered with halves of 63")]
+    fn test_mitm_refuses_oversized_halves() {
+        let half: Vec<NumberEntry> = make_entries(&[1; MAX_MITM_HALF + 1]);
+        mitm_halves(&half, &half[..1], &make_config(2, 1, 2), &mut 0, &mut 0).unwrap();
+    }
+
+    #[test]
+    fn test_mitm_alternating_split_matches_brute_force() {
+        let mut rng = testing::rng();
+        for round in 0..300 {
+            let n = 1 + rng.below(14) as usize;
+            // Every other round is skewed: mostly tiny values plus a few huge ones
+            let nums: Vec<u64> = (0..n)
+                .map(|_| if round % 2 == 1 && rng.below(4) == 0 { 1000 + rng.below(9000) } else { 1 + rng.below(20) })
+                .collect();
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let (min, max) = (1 + rng.below(n as u64) as usize, n);
+            let min = min.min(max);
+            // The canonical match: fewest entries, then smallest index set
+            let expected = brute_force(&nums, target, min, max)
+                .into_iter()
+                .min_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+            let data = PreparedData::new(&make_entries(&nums), None).unwrap();
+            let config = make_config(target, min, max);
+            let found = meet_in_the_middle(&data, &config, &mut 0, &mut 0).unwrap();
+            if let Some(found) = &found {
+                assert_eq!(found.iter().map(|e| e.value).sum::<u64>(), target);
+                for e in found {
+                    assert_eq!(e.value, nums[e.original_index.as_usize()]);
+                }
             }
-            _ => panic!("Should have found 200"),
+            let indices = |found: Option<Vec<NumberEntry>>| found.map(|found| {
+                let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                indices
+            });
+            assert_eq!(indices(found), expected, "{:?} target {} count {}..={}", nums, target, min, max);
+
+            // However the halves are cut or ordered
+            let (left, right) = data.sorted.split_at(n / 2);
+            assert_eq!(indices(mitm_halves(right, left, &config, &mut 0, &mut 0).unwrap()), expected);
+            let (left, right) = split_alternating(&data.reversed(None).unwrap().sorted);
+            assert_eq!(indices(mitm_halves(&left, &right, &config, &mut 0, &mut 0).unwrap()), expected);
         }
     }
 
     #[test]
-    fn test_all_combinations() {
-        let nums = vec![1, 2, 3, 4, 5];
-        let entries = make_entries(&nums);
-        let config = make_config(5, 1, 5);
-        let results = solve_all_combinations(&entries, &config, 100);
-        // Valid combos: [5], [1,4], [2,3]
-        assert!(results.len() >= 3);
-        for combo in &results {
-            let sum: u64 = combo.iter().map(|e| e.value).sum();
-            assert_eq!(sum, 5);
+    fn test_mitm_tie_break_fixtures() {
+        // Answers users have seen; a change here changes results between versions
+        type Fixture = (&'static [u64], u64, usize, usize, &'static [usize]);
+        let fixtures: [Fixture; 6] = [
+            (&[5, 5, 5, 5, 10, 10], 20, 1, 6, &[4, 5]),
+            (&[5, 5, 5, 5, 10, 10], 20, 3, 6, &[0, 1, 4]),
+            (&[1, 1, 1, 1, 1, 1, 1, 1], 3, 1, 8, &[0, 1, 2]),
+            (&[7, 3, 4, 6, 1, 9, 2, 8, 5], 15, 1, 9, &[0, 7]),
+            (&[7, 3, 4, 6, 1, 9, 2, 8, 5], 15, 3, 3, &[0, 1, 8]),
+            (&[12, 30, 18, 6, 24, 30, 6, 12], 60, 1, 8, &[1, 5]),
+        ];
+        for (nums, target, min, max, expected) in fixtures {
+            let (result, _) = solve_mitm_with_stats(&make_entries(nums), &make_config(target, min, max));
+            let Ok(Some(found)) = result else { panic!("{:?}: {:?}", nums, result) };
+            let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+            indices.sort_unstable();
+            assert_eq!(indices, expected, "{:?} target {}", nums, target);
         }
+
+        // Row ids rule out the first choice, not the rule
+        let entries = make_entries(&[5, 5, 5, 5, 10, 10]);
+        let ids = [0, 1, 2, 3, 4, 4];
+        let config = SolverConfig { row_ids: Some(&ids), ..make_config(20, 1, 6) };
+        let Ok(Some(found)) = solve_mitm_with_stats(&entries, &config).0 else { panic!() };
+        let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, vec![0, 1, 4]);
     }
 
     #[test]
-    fn test_large_numbers() {
-        let nums = vec![
-            1_000_000_000_000u64,
-            500_000_000_000,
-            250_000_000_000,
-            125_000_000_000,
-            375_000_000_000,
+    fn test_split_alternating_halves() {
+        let sorted = make_entries(&[1, 2, 3, 4, 5]);
+        let (left, right) = split_alternating(&sorted);
+        assert_eq!(left.iter().map(|e| e.value).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(right.iter().map(|e| e.value).collect::<Vec<_>>(), vec![2, 4, 5]);
+        let (left, right) = split_alternating(&sorted[..4]);
+        assert_eq!((left.len(), right.len()), (2, 2));
+    }
+
+    /// Skewed input (many small values, a few large ones): left_map size and time
+    /// for the contiguous split at n / 2 against the alternating one.
+    /// Run with `cargo test --release bench_mitm_split -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_mitm_split() {
+        let mut rng = testing::rng();
+        let mut nums: Vec<u64> = (0..30).map(|_| 1 + rng.below(50)).collect();
+        nums.extend((0..10).map(|_| 100_000 + rng.below(100_000)));
+        let target = nums.iter().sum::<u64>() / 4;
+        let data = PreparedData::new(&make_entries(&nums), None).unwrap();
+        let config = make_config(target, 1, nums.len());
+        let n = data.sorted.len();
+        let contiguous = (data.sorted[..n / 2].to_vec(), data.sorted[n / 2..].to_vec());
+        for (name, (left, right)) in [("contiguous", contiguous), ("alternating", split_alternating(&data.sorted))] {
+            let sums: Vec<u64> = (0..1u64 << left.len())
+                .filter_map(|mask| subset_sum(&left, mask, config.target))
+                .map(|(sum, _)| sum)
+                .collect();
+            let keys = sums.iter().collect::<std::collections::HashSet<_>>().len();
+            let start = std::time::Instant::now();
+            let found = mitm_halves(&left, &right, &config, &mut 0, &mut 0).unwrap();
+            println!(
+                "{name:>12}: {} left_map entries under {keys} sums, {:?}, found {}",
+                sums.len(), start.elapsed(), found.is_some(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_bnb_value_width() {
+        let cases: [(&[u64], u64, Option<u32>); 4] = [
+            (&[3, 5, 7, 11], 18, Some(32)),
+            (&[3, 5, 7, u32::MAX as u64 - 3], u32::MAX as u64, Some(32)),
+            // One value past u32 widens the run, even though it can never be taken
+            (&[3, 5, 7, 1 << 32], 15, Some(64)),
+            (&[1 << 31, 1 << 31, 1], (1 << 32) + 1, Some(64)),
         ];
+        for (nums, target, bits) in cases {
+            let (result, stats) = solve_depth_first_with_stats(&make_entries(nums), &make_config(target, 1, nums.len()));
+            assert!(matches!(result, Ok(Some(_))), "{:?}", nums);
+            assert_eq!(stats.value_bits, bits, "{:?}", nums);
+        }
+        // MITM alone never reports a width
+        let (_, stats) = solve_subset_sum_with_stats(&make_entries(&[3, 5, 7]), &make_config(12, 1, 3));
+        assert_eq!(stats.value_bits, None);
+    }
+
+    /// u32 against u64 B&B lanes over 500k entries, on a search with no solution
+    /// (even values, odd target) held to a fixed node budget.
+    /// Run with `cargo test --release bench_bnb_value_width -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_bnb_value_width() {
+        let mut rng = testing::rng();
+        let nums: Vec<u64> = (0..500_000).map(|_| 2 * (1 + rng.below(1_000_000))).collect();
         let entries = make_entries(&nums);
-        let config = make_config(875_000_000_000, 2, 4);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
-                let sum: u64 = result.iter().map(|e| e.value).sum();
-                assert_eq!(sum, 875_000_000_000);
-                for e in &result {
-                    assert_eq!(e.value, nums[e.original_index]);
-                }
-            }
-            _ => panic!("Should have found a solution"),
+        let config = make_config(10_000_001, 1, 12);
+        for narrow in [true, false] {
+            let mut data = PreparedData::new(&entries, None).unwrap();
+            data.narrow_values = narrow;
+            let mut stats = SolverStats::default();
+            let start = std::time::Instant::now();
+            let outcome = branch_and_bound_first(&data, &config, 50_000_000, None, false, &mut stats);
+            println!(
+                "{:>2} bits: {:?} for {} nodes, budget exhausted {}",
+                stats.value_bits.unwrap(), start.elapsed(), stats.phases[0].nodes,
+                matches!(outcome, BbOutcome::BudgetExhausted),
+            );
         }
     }
 
     #[test]
-    fn test_meet_in_the_middle_threshold() {
-        let nums: Vec<u64> = (1..=30).collect();
+    fn test_prognosis_classification() {
+        // Small: MITM over a handful of entries
+        let small = make_entries(&[3, 5, 7, 11, 13, 17]);
+        let p = prognosis(&small, &make_config(20, 1, 6));
+        assert_eq!((p.n, p.algorithms.clone(), p.count_window), (6, vec!["mitm"], Some((2, 3))));
+        assert_eq!((p.infeasible, p.difficulty), (None, Difficulty::Instant));
+        assert!(!p.mitm_over_budget());
+
+        // Medium: the hybrid range, bounded by the B&B budget plus a full MITM
+        let medium: Vec<u64> = (1..=45).map(|v| v * 3 + 1).collect();
+        let p = prognosis(&make_entries(&medium), &make_config(500, 1, 45));
+        assert_eq!(p.algorithms, vec!["bnb", "mitm"]);
+        assert_eq!(p.difficulty, Difficulty::Seconds);
+        assert_eq!(p.mitm_memory_bytes, Some(48 << 22));
+
+        // Absurd: 200 entries, any count
+        let absurd: Vec<u64> = (1..=200).map(|v| v * 7 + 1).collect();
+        let p = prognosis(&make_entries(&absurd), &make_config(50_000, 1, 200));
+        assert_eq!(p.algorithms, vec!["bnb"]);
+        assert_eq!(p.difficulty, Difficulty::Intractable);
+        assert_eq!(p.mitm_memory_bytes, None);
+
+        // MITM tables for 50 entries don't fit the budget
+        let fifty: Vec<u64> = (1..=50).collect();
+        assert!(prognosis(&make_entries(&fifty), &make_config(100, 1, 50)).mitm_over_budget());
+    }
+
+    #[test]
+    fn test_prognosis_proves_infeasibility() {
+        let evens = make_entries(&[4, 6, 10, 22]);
+        let p = prognosis(&evens, &make_config(21, 1, 4));
+        assert_eq!((p.infeasible, p.worst_case_nodes), (Some(Infeasibility::Gcd), 0.0));
+        assert_eq!(prognosis(&evens, &make_config(20, 1, 4)).infeasible, None);
+        // The two largest sum to 32, short of 50
+        assert_eq!(prognosis(&evens, &make_config(50, 1, 2)).infeasible, Some(Infeasibility::CountWindow));
+        assert_eq!(prognosis(&[], &make_config(5, 1, 4)).infeasible, Some(Infeasibility::NoEntries));
+    }
+
+    #[test]
+    fn test_subsets_in_window() {
+        assert_eq!(subsets_in_window(5, 0, 5), 32.0);
+        assert_eq!(subsets_in_window(5, 2, 3), 20.0);
+        assert_eq!(subsets_in_window(4, 5, 9), 0.0);
+    }
+
+    #[test]
+    fn test_hybrid_falls_back_to_mitm() {
+        let nums: Vec<u64> = (1..=42).map(|i| i * 7).collect();
         let entries = make_entries(&nums);
-        let config = make_config(100, 3, 10);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
-                let sum: u64 = result.iter().map(|e| e.value).sum();
-                assert_eq!(sum, 100);
-                for e in &result {
-                    assert_eq!(e.value, nums[e.original_index]);
-                }
+        let mut config = make_config(7 + 14 + 21, 3, 3);
+        config.hybrid_bb_budget = 1;
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        match result {
+            Ok(Some(result)) => assert_valid(&result, &nums, &config),
+            _ => panic!("MITM fallback should have found a solution"),
+        }
+        let algorithms: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+        assert_eq!(algorithms, vec!["bnb", "mitm"]);
+        assert_eq!(stats.phases[0].budget, Some(1));
+        assert_eq!(stats.phases[0].nodes, 1);
+        assert_eq!(stats.phases[1].budget, None);
+    }
+
+    #[test]
+    fn test_hybrid_restarts_descending_above_fifty() {
+        let nums: Vec<u64> = (1..=55).collect();
+        let entries = make_entries(&nums);
+        let mut config = make_config(150, 3, 4);
+        config.hybrid_bb_budget = 1;
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        match result {
+            Ok(Some(result)) => assert_valid(&result, &nums, &config),
+            _ => panic!("Descending restart should have found a solution"),
+        }
+        let algorithms: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+        assert_eq!(algorithms, vec!["bnb", "bnb_desc"]);
+    }
+
+    #[test]
+    fn test_restarts_past_sixty() {
+        // Ascending B&B wanders the small values for millions of nodes; four of
+        // the large ones, tried first in descending order, take a few dozen
+        let mut nums: Vec<u64> = (1..=60).collect();
+        nums.extend(1000..1010);
+        let entries = make_entries(&nums);
+        let mut config = make_config(1000 + 1001 + 1002 + 1003, 1, 70);
+        config.hybrid_bb_budget = 100;
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        let Ok(Some(found)) = result else { panic!("{:?}", result) };
+        assert_valid(&found, &nums, &config);
+        let algorithms: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+        assert_eq!(algorithms, ["bnb", "bnb_desc"]);
+        assert_eq!(stats.phases[0].nodes, 100);
+        assert!(stats.phases[1].nodes < 100);
+        assert_eq!(stats.restarts, Some(1));
+
+        // Too small a budget for any ordering: the unlimited walk answers
+        config.hybrid_bb_budget = 5;
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        let Ok(Some(found)) = result else { panic!("{:?}", result) };
+        assert_valid(&found, &nums, &config);
+        let algorithms: Vec<&str> = stats.phases.iter().map(|p| p.algorithm).collect();
+        assert_eq!(algorithms, ["bnb", "bnb_desc", "bnb_shuffled", "bnb_shuffled", "bnb"]);
+        assert_eq!(stats.phases[4].budget, None);
+        assert_eq!(stats.restarts, Some(4));
+
+        // An answer within the first budget is the plain ascending one
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &make_config(60, 1, 70));
+        assert!(matches!(result, Ok(Some(_))));
+        assert_eq!(stats.restarts, None);
+    }
+
+    #[test]
+    fn test_shuffled_bnb_matches_ascending() {
+        let mut rng = testing::rng();
+        for round in 0..300 {
+            let n = 1 + rng.below(14) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(30)).collect();
+            let target = 1 + rng.below(nums.iter().sum::<u64>() + 5);
+            let min_count = rng.below(4) as usize;
+            // Pinned counts exercise the prune a shuffle must do without
+            let max_count = if round % 3 == 0 { min_count.max(1) } else { min_count + rng.below(n as u64) as usize };
+            let config = make_config(target, min_count, max_count);
+            let data = PreparedData::new(&make_entries(&nums), None).unwrap();
+            let outcome = |data: &PreparedData| match branch_and_bound_first(data, &config, u64::MAX, None, false, &mut SolverStats::default()) {
+                BbOutcome::Done(result) => result.unwrap(),
+                BbOutcome::BudgetExhausted => unreachable!(),
+            };
+            let ascending = outcome(&data);
+            let shuffled = outcome(&data.shuffled(round, None).unwrap());
+            assert_eq!(ascending.is_some(), shuffled.is_some(), "{:?} target {} [{}, {}]", nums, target, min_count, max_count);
+            if let Some(found) = shuffled {
+                assert_valid(&found, &nums, &config);
             }
-            _ => panic!("Should have found a solution"),
         }
     }
 
     #[test]
-    fn test_original_indices_preserved_with_gaps() {
-        // Simulate CSV with some invalid rows filtered by lib.rs
-        // Original CSV: [0, 5, 0, 3, 7, 0, 2] -> after filtering: entries at indices 1,3,4,6
-        let entries = vec![
-            NumberEntry { value: 5, original_index: 1 },
-            NumberEntry { value: 3, original_index: 3 },
-            NumberEntry { value: 7, original_index: 4 },
-            NumberEntry { value: 2, original_index: 6 },
-        ];
-        let config = make_config(10, 2, 4);
-        match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
-                let sum: u64 = result.iter().map(|e| e.value).sum();
-                assert_eq!(sum, 10);
-                // Verify original indices are from the original set {1,3,4,6}
-                for e in &result {
-                    assert!(
-                        [1, 3, 4, 6].contains(&e.original_index),
-                        "Unexpected original_index: {}",
-                        e.original_index
-                    );
+    fn test_hybrid_logs_choice_and_fallback() {
+        use crate::log::capture;
+        let entries = make_entries(&(1..=55).collect::<Vec<u64>>());
+        let mut config = make_config(150, 3, 4);
+        config.hybrid_bb_budget = 1;
+        let lines = capture(Level::Info, || {
+            let _ = solve_subset_sum_with_stats(&entries, &config);
+        });
+        let messages: Vec<&str> = lines.iter().map(|(_, m)| m.as_str()).collect();
+        assert_eq!(messages, [
+            "find_one: n=55, count window [3, 4], hybrid bnb",
+            "find_one: bnb stalled at its 1-node budget, falling back to bnb_desc",
+        ]);
+        // Off by default
+        assert!(capture(Level::Off, || { let _ = solve_subset_sum_with_stats(&entries, &config); }).is_empty());
+    }
+
+    #[test]
+    fn test_shortcut_pairs_match_brute_force() {
+        let mut rng = testing::rng();
+        for round in 0..400 {
+            let n = 2 + rng.below(30) as usize;
+            // Few distinct values, so pairs and runs of equal values abound
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(12)).collect();
+            let target = 2 + rng.below(24);
+            let entries = make_entries(&nums);
+            let expected = (0..n)
+                .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+                .find(|&(a, b)| nums[a] + nums[b] == target);
+            let data = PreparedData::new(&entries, None).unwrap();
+            let found = pair_match(&data.sorted, &make_config(target, 2, 2));
+            let indices = found.as_ref().map(|found| {
+                assert!(found.iter().all(|e| nums[e.original_index.as_usize()] == e.value));
+                (found[0].original_index.as_usize(), found[1].original_index.as_usize())
+            });
+            assert_eq!(indices, expected, "round {}: {:?} target {}", round, nums, target);
+        }
+    }
+
+    #[test]
+    fn test_near_sums_match_brute_force() {
+        let mut rng = testing::rng();
+        let mut summarized = 0;
+        for _ in 0..800 {
+            let n = 1 + rng.below(12) as usize;
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(60)).collect();
+            let target = 1 + rng.below(400);
+            let min = rng.below(4) as usize;
+            let max = min + rng.below(n as u64 + 1) as usize;
+            let (result, stats) = solve_subset_sum_with_stats(&make_entries(&nums), &make_config(target, min, max));
+            let Some(near) = stats.near_sums else { continue };
+            assert!(matches!(result, Ok(None)));
+            let sums: Vec<u128> = subsets(&nums, min, max)
+                .map(|rows| rows.iter().map(|&i| nums[i] as u128).sum())
+                .collect();
+            let t = target as u128;
+            let mut within: Vec<u128> = sums.iter().copied().filter(|&s| s.abs_diff(t) <= near.radius as u128).collect();
+            within.sort_unstable();
+            within.dedup();
+            // Each miss's rows reach its sum inside the window
+            for miss in [&near.nearest_below, &near.nearest_above].into_iter().flatten() {
+                let rows = miss.indices.iter().map(|i| nums[i.as_usize()] as u128);
+                assert_eq!((rows.clone().sum::<u128>(), miss.diff), (miss.sum, miss.sum.abs_diff(t)));
+                assert!((min.max(1)..=max).contains(&miss.indices.len()) && miss.indices.is_sorted());
+            }
+            let sum_of = |miss: &Option<NearMiss>| miss.as_ref().map(|m| m.sum);
+            assert_eq!(
+                (sum_of(&near.nearest_below), sum_of(&near.nearest_above)),
+                (sums.iter().copied().filter(|&s| s < t).max(), sums.iter().copied().filter(|&s| s > t).min()),
+                "{:?} target {} window [{}, {}]", nums, target, min, max,
+            );
+            assert_eq!((near.radius, near.achievable_within, near.truncated), ((target / 100).max(1), within, false));
+            summarized += 1;
+        }
+        assert!(summarized > 50, "{}", summarized);
+    }
+
+    #[test]
+    fn test_memory_limit_falls_back_or_fails() {
+        let mut rng = testing::rng();
+        let nums: Vec<u64> = (0..30).map(|_| 1 + rng.below(1000)).collect();
+        let entries = make_entries(&nums);
+        let target = nums[1] + nums[7] + nums[12] + nums[20];
+        let ids = |result: &SolveOutcome| match result {
+            Ok(Some(found)) => {
+                let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+                indices.sort_unstable();
+                indices
+            }
+            other => panic!("{:?}", other),
+        };
+        let (unlimited, stats) = solve_subset_sum_with_stats(&entries, &make_config(target, 1, 30));
+        assert_eq!((stats.phases[0].algorithm, stats.memory_fallback), ("mitm", None));
+        let table = MITM_BYTES_PER_SUBSET << 15;
+
+        // Room for the table: the same MITM run
+        let roomy = SolverConfig { memory_limit: Some(table), ..make_config(target, 1, 30) };
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &roomy);
+        assert_eq!((ids(&result), stats.memory_fallback), (ids(&unlimited), None));
+
+        // Room for the input but not the table: B&B takes over and still finds one
+        let tight = SolverConfig { memory_limit: Some(table - 1), ..make_config(target, 1, 30) };
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &tight);
+        assert!(matches!(result, Ok(Some(ref found)) if found.iter().map(|e| e.value).sum::<u64>() == target));
+        assert_eq!(stats.memory_fallback, Some(table));
+        assert_eq!(stats.phases.iter().map(|p| p.algorithm).collect::<Vec<_>>(), ["bnb"]);
+        assert!(matches!(precheck(&entries, &tight, 1_000_000).0, Precheck::Found(_)));
+        // Forced MITM has nothing to hand over to
+        let (result, _) = solve_mitm_with_stats(&entries, &tight);
+        assert!(matches!(result, Err(SolveError::OutOfMemory(OutOfMemory { bytes })) if bytes == table));
+
+        // Not even room for the sorted input
+        let starved = SolverConfig { memory_limit: Some(100), ..make_config(target, 1, 30) };
+        let (result, _) = solve_subset_sum_with_stats(&entries, &starved);
+        assert_eq!(
+            match result { Err(SolveError::OutOfMemory(oom)) => oom.bytes, other => panic!("{:?}", other) },
+            30 * size_of::<NumberEntry>() as u64,
+        );
+        assert!(FindSession::new(&entries, Some(100)).is_err());
+        assert!(solve_quick(&entries, &starved, 1_000).0.is_none());
+        assert!(matches!(precheck(&entries, &starved, 1_000).0, Precheck::Unknown));
+    }
+
+    #[test]
+    fn test_bnb_near_misses_match_brute_force() {
+        let mut rng = testing::rng();
+        let mut summarized = 0;
+        for round in 0..600 {
+            let n = 1 + rng.below(14) as usize;
+            let spread = [10, 60, 1000][round % 3];
+            let nums: Vec<u64> = (0..n).map(|_| 1 + rng.below(spread)).collect();
+            let target = 1 + rng.below(nums.iter().sum::<u64>());
+            let max = 1 + rng.below(n as u64) as usize;
+            let min = 1 + rng.below(max as u64) as usize;
+            let entries = make_entries(&nums);
+            let data = PreparedData::new(&entries, None).unwrap();
+            let Some((min, max)) = tighten_count_window(&data.sorted, &data.suffix_sum, target, min, max) else { continue };
+            let config = make_config(target, min, max);
+            let t = target as u128;
+            let sums: Vec<u128> = subsets(&nums, min, max).map(|rows| rows.iter().map(|&i| nums[i] as u128).sum()).collect();
+            let below = sums.iter().copied().filter(|&s| s < t).max();
+            let above = sums.iter().copied().filter(|&s| s > t).min();
+
+            let orders = [data.reversed(None).unwrap(), data.shuffled(rng.next_u64(), None).unwrap(), data];
+            for (k, data) in orders.iter().enumerate() {
+                let mut stats = SolverStats::default();
+                let BbOutcome::Done(Ok(None)) = branch_and_bound_first(data, &config, u64::MAX, None, true, &mut stats) else { continue };
+                // Nothing met, no summary
+                let near = stats.near_sums.unwrap_or_default();
+                assert!(!near.exhaustive && near.achievable_within.is_empty());
+                let sum_of = |miss: &Option<NearMiss>| miss.as_ref().map(|m| m.sum);
+                for miss in [&near.nearest_below, &near.nearest_above].into_iter().flatten() {
+                    let rows = miss.indices.iter().map(|i| nums[i.as_usize()] as u128);
+                    assert_eq!((rows.sum::<u128>(), miss.diff), (miss.sum, miss.sum.abs_diff(t)));
+                    assert!((min..=max).contains(&miss.indices.len()) && miss.indices.is_sorted());
+                }
+                let (met_below, met_above) = (sum_of(&near.nearest_below), sum_of(&near.nearest_above));
+                assert!(met_below.is_none_or(|sum| sum < t && Some(sum) <= below));
+                assert!(met_above.is_none_or(|sum| sum > t && Some(sum) >= above));
+                // Ascending, every miss is met or cut where the nearest is read off
+                if k == 2 {
+                    assert_eq!((met_below, met_above), (below, above), "{:?} [{}, {}] {}", nums, min, max, target);
+                    summarized += 1;
                 }
             }
-            _ => panic!("Should have found a solution"),
         }
+        assert!(summarized > 100, "{}", summarized);
+        // Precheck and quick runs keep none
+        let (_, stats) = solve_quick(&make_entries(&[20; 70]), &make_config(30, 1, 3), 1_000);
+        assert!(stats.near_sums.is_none());
+    }
+
+    #[test]
+    fn test_near_sums_fixtures() {
+        let near = |nums: &[u64], target: u64, min: usize, max: usize| {
+            let (result, stats) = solve_subset_sum_with_stats(&make_entries(nums), &make_config(target, min, max));
+            assert!(matches!(result, Ok(None)));
+            stats.near_sums
+        };
+        let miss = |sum: u128, indices: &[usize], diff: u128| {
+            Some(NearMiss { sum, indices: indices.iter().map(|&i| OriginalIndex::new(i)).collect(), diff })
+        };
+        let sums = |near: &NearSums| (near.nearest_below.as_ref().map(|m| m.sum), near.nearest_above.as_ref().map(|m| m.sum));
+        // Cents: 39.95 and 50.00 reachable, 40.00 not
+        let expected = NearSums {
+            nearest_below: miss(3995, &[0, 2], 5),
+            nearest_above: miss(5000, &[1, 2], 1000),
+            radius: 40,
+            achievable_within: vec![3995],
+            truncated: false,
+            exhaustive: true,
+        };
+        assert_eq!(near(&[1005, 2010, 2990], 4000, 1, 3), Some(expected));
+        // Within the window the caller asked for, not the one tightened to pairs
+        // at most (any three sum past 62): 20 + 21 + 22 is the nearest above
+        let triple = near(&[20, 21, 22, 60], 62, 1, 4).unwrap();
+        assert_eq!(sums(&triple), (Some(60), Some(63)));
+        assert_eq!(triple.nearest_above, miss(63, &[0, 1, 2], 1));
+        assert_eq!(triple.achievable_within, [63]);
+        let pairs = near(&[20, 21, 22, 60], 62, 1, 2).unwrap();
+        assert_eq!(sums(&pairs), (Some(60), Some(80)));
+
+        // 6.00 + 4.02 overshoots 10.00 by two cents, while the nearest sum
+        // below is 50 cents short: the rows that are over are the ones to look at
+        let over = near(&[600, 402, 350], 1000, 1, 3).unwrap();
+        assert_eq!((over.nearest_below, over.nearest_above), (miss(950, &[0, 2], 50), miss(1002, &[0, 1], 2)));
+        // 4.99 + 5.02 is a cent over; the best below is 1.80 short
+        let far_below = near(&[700, 499, 502, 120], 1000, 1, 4).unwrap();
+        assert_eq!((far_below.nearest_below, far_below.nearest_above), (miss(820, &[0, 3], 180), miss(1001, &[1, 2], 1)));
+
+        // Even values, odd target: 35 even sums lie in the band, the 32 nearest are kept
+        let evens: Vec<u64> = (0..20).map(|i| 200 + 2 * i).collect();
+        let crowded = near(&evens, 3501, 1, 20).unwrap();
+        assert_eq!((sums(&crowded), crowded.radius), ((Some(3500), Some(3502)), 35));
+        assert_eq!(crowded.achievable_within, (3470..=3532).step_by(2).collect::<Vec<u128>>());
+        assert!(crowded.truncated);
+
+        // Row ids can make sums unachievable, so none is reported
+        let ids = [0, 0, 1];
+        let config = SolverConfig { row_ids: Some(&ids), ..make_config(4000, 1, 3) };
+        assert!(solve_subset_sum_with_stats(&make_entries(&[1005, 2010, 2990]), &config).1.near_sums.is_none());
+    }
+
+    #[test]
+    fn test_shortcut_outcomes() {
+        let entries = make_entries(&[9, 4, 7, 15, 2, 8, 1, 30]);
+        let run = |config: &SolverConfig| {
+            let (result, stats) = solve_subset_sum_with_stats(&entries, config);
+            let Ok(Some(found)) = result else { panic!("{:?}", result) };
+            let mut indices: Vec<usize> = found.iter().map(|e| e.original_index.as_usize()).collect();
+            indices.sort_unstable();
+            (indices, stats.shortcut, stats.phases.iter().map(|p| p.algorithm).collect::<Vec<_>>())
+        };
+        assert_eq!(run(&make_config(15, 1, 4)), (vec![3], Some(Shortcut::SingleElement), vec!["direct"]));
+        // 9 + 2 at rows 0 and 4 comes before 4 + 7 at rows 1 and 2
+        assert_eq!(run(&make_config(11, 1, 4)), (vec![0, 4], Some(Shortcut::Pair), vec!["direct"]));
+        assert_eq!(run(&make_config(15, 2, 4)), (vec![2, 5], Some(Shortcut::Pair), vec!["direct"]));
+        // min_count past two: the search runs, pairs or not
+        let (indices, shortcut, phases) = run(&make_config(11, 3, 4));
+        assert!(indices.len() >= 3);
+        assert_eq!((shortcut, phases), (Some(Shortcut::None), vec!["mitm"]));
+
+        // The depth-first mode keeps to the batch search's first result
+        let (_, stats) = solve_depth_first_with_stats(&entries, &make_config(15, 1, 4));
+        assert_eq!(stats.shortcut, None);
+        // Row ids leave pairs to the search: 9 + 2 share a row
+        let ids = [0, 1, 2, 3, 0, 5, 6, 7];
+        let config = SolverConfig { row_ids: Some(&ids), ..make_config(11, 1, 4) };
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        assert_eq!(stats.shortcut, Some(Shortcut::None));
+        let Ok(Some(found)) = result else { panic!() };
+        assert!(!found.iter().any(|e| e.original_index.as_usize() == 0) || !found.iter().any(|e| e.original_index.as_usize() == 4));
+    }
+
+    #[test]
+    fn test_hybrid_skips_fallback_when_bnb_succeeds() {
+        let nums: Vec<u64> = (1..=45).collect();
+        let entries = make_entries(&nums);
+        let config = make_config(46, 3, 3);
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        assert!(matches!(result, Ok(Some(_))));
+        assert_eq!(stats.phases.len(), 1);
+        assert_eq!(stats.phases[0].algorithm, "bnb");
+        assert_eq!(stats.phases[0].budget, Some(DEFAULT_HYBRID_BB_BUDGET));
+    }
+
+    #[test]
+    fn test_rows_equal_to_target() {
+        let entries = make_entries(&[700; 10_000]);
+        for config in [make_config(700, 1, 10_000), make_config(700, 0, 3)] {
+            let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+            let Ok(Some(found)) = result else { panic!("no solution") };
+            assert_eq!(found.len(), 1);
+            assert_eq!(stats.phases, [PhaseStats { algorithm: "direct", budget: None, nodes: 0 }]);
+            // One result stands for all 10,000 rows
+            let all = solve_all_combinations(&entries, &config, usize::MAX).results;
+            assert_eq!(all.len(), 1);
+            assert_eq!(all[0][0].original_index, found[0].original_index);
+        }
+        assert_eq!(identical_target_matches(&entries, 700), 10_000);
+
+        // Mixed in with other solutions, the copies still count once
+        let entries = make_entries(&[3, 10, 7, 10, 10]);
+        let all = solve_all_combinations(&entries, &make_config(10, 1, 5), usize::MAX).results;
+        assert_eq!(all.len(), 2);
+        assert!(matches!(solve_subset_sum(&entries, &make_config(10, 1, 5)), Ok(Some(f)) if f.len() == 1));
+        // Not when single elements are out of the window
+        assert!(matches!(solve_subset_sum(&entries, &make_config(10, 2, 5)), Ok(Some(f)) if f.len() == 2));
+    }
+
+    #[test]
+    fn test_equal_values_answered_directly() {
+        let entries = make_entries(&[5; 40]);
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &make_config(50, 1, 40));
+        assert!(matches!(result, Ok(Some(f)) if f.len() == 10));
+        assert_eq!(stats.phases[0].algorithm, "direct");
+        // Outside the count window, or not a multiple: a real (and failing) search
+        for config in [make_config(50, 1, 9), make_config(52, 1, 40)] {
+            let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+            assert!(matches!(result, Ok(None)));
+            assert_ne!(stats.phases.first().map(|p| p.algorithm), Some("direct"));
+        }
+    }
+
+    fn bounds_of(nums: &[u64], target: u64) -> Option<(usize, usize)> {
+        let data = PreparedData::new(&make_entries(nums), None).unwrap();
+        count_bounds(&data.sorted, &data.suffix_sum, target)
+    }
+
+    #[test]
+    fn test_count_bounds_fixtures() {
+        // Largest 90k toward 1M: at least 12 elements. 1..=1000 sums to 500,500, leaving
+        // room for five of the 90k values before 1M is exceeded.
+        let mut nums: Vec<u64> = (1..=1000).collect();
+        nums.extend(std::iter::repeat_n(90_000, 10));
+        let (lo, hi) = bounds_of(&nums, 1_000_000).unwrap();
+        assert!(lo >= 12);
+        assert_eq!(hi, 1005);
+
+        // 10 + 9 = 19 < 20, 10 + 9 + 8 = 27 >= 20; 1 + 2 + 3 + 4 + 5 = 15 <= 20 < 21
+        assert_eq!(bounds_of(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 20), Some((3, 5)));
+        // Exactly the total: every element is needed
+        assert_eq!(bounds_of(&[3, 4, 5], 12), Some((3, 3)));
+        // Unreachable
+        assert_eq!(bounds_of(&[3, 4, 5], 13), None);
+    }
+
+    #[test]
+    fn test_tightened_window_reported_and_empty_window_short_circuits() {
+        let nums: Vec<u64> = (1..=10).collect();
+        let entries = make_entries(&nums);
+
+        let config = make_config(20, 1, 10);
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        assert!(matches!(result, Ok(Some(_))));
+        assert_eq!(stats.count_window, Some((3, 5)));
+
+        // Pairs can't reach 20 (10 + 9 = 19): nothing runs at all
+        let config = make_config(20, 1, 2);
+        let (result, stats) = solve_subset_sum_with_stats(&entries, &config);
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(stats.count_window, None);
+        assert!(stats.phases.is_empty());
+    }
+
+    #[test]
+    fn test_tightening_does_not_change_solutions() {
+        let nums: Vec<u64> = vec![3, 5, 7, 8, 11, 13, 14, 19, 22, 25];
+        let entries = make_entries(&nums);
+        let config = make_config(40, 1, 10);
+        let all = solve_all_combinations(&entries, &config, 10_000).results;
+
+        assert_eq!(all.len(), brute_force(&nums, 40, 1, nums.len()).len());
     }
 
     #[test]
@@ -524,11 +3747,81 @@ mod tests {
         let entries = make_entries(&nums);
         let config = make_config(50, 1, 5);
         match solve_subset_sum(&entries, &config) {
-            SolverResult::Found(result) => {
+            Ok(Some(result)) => {
                 let sum: u64 = result.iter().map(|e| e.value).sum();
                 assert_eq!(sum, 50);
             }
             _ => panic!("Should have found a solution"),
         }
     }
+
+    fn sample(entries: &[NumberEntry], config: &SolverConfig, seed: u64) -> Option<Vec<usize>> {
+        match sample_uniform_with_stats(entries, config, &mut SplitMix64::new(seed)).0 {
+            Ok(Some(found)) => Some(found.iter().map(|e| e.original_index.as_usize()).collect()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_sample_uniform_is_uniform() {
+        // Solutions for 5: [4] (value 5), [0,3] (1+4), [1,2] (2+3)
+        let entries = make_entries(&[1, 2, 3, 4, 5]);
+        let config = make_config(5, 1, 5);
+        let mut counts: std::collections::HashMap<Vec<usize>, u32> = std::collections::HashMap::new();
+        for seed in 0..3000 {
+            *counts.entry(sample(&entries, &config, seed).unwrap()).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        let chi_square: f64 = counts.values()
+            .map(|&observed| (observed as f64 - 1000.0).powi(2) / 1000.0)
+            .sum();
+        // 2 degrees of freedom: P(chi^2 > 20) is about 5e-5
+        assert!(chi_square < 20.0, "{:?}", counts);
+    }
+
+    #[test]
+    fn test_sample_uniform_same_seed_same_pick() {
+        let entries = make_entries(&(1..=30).collect::<Vec<u64>>());
+        let config = make_config(60, 3, 6);
+        for seed in [0, 1, 42, u64::MAX] {
+            let pick = sample(&entries, &config, seed).unwrap();
+            assert_eq!(sample(&entries, &config, seed), Some(pick.clone()));
+            let sum: u64 = pick.iter().map(|&i| entries[i].value).sum();
+            assert_eq!(sum, 60);
+            assert!((3..=6).contains(&pick.len()));
+        }
+    }
+
+    #[test]
+    fn test_sample_uniform_respects_count_window() {
+        let entries = make_entries(&[1, 2, 3, 4, 5]);
+        for seed in 0..50 {
+            assert_eq!(sample(&entries, &make_config(5, 1, 1), seed), Some(vec![4]));
+            assert_eq!(sample(&entries, &make_config(5, 2, 2), seed).unwrap().len(), 2);
+        }
+        assert_eq!(sample(&entries, &make_config(100, 1, 5), 7), None);
+        assert_eq!(sample(&[], &make_config(5, 1, 5), 7), None);
+    }
+
+    #[test]
+    fn test_solve_errors() {
+        let entries = make_entries(&(1..=60).collect::<Vec<u64>>());
+        let draw = |entries: &[NumberEntry], config| sample_uniform_with_stats(entries, &config, &mut SplitMix64::new(1)).0;
+        let bounds = SolveError::InvalidBounds { min_count: 3, max_count: 2 };
+        assert_eq!(solve_subset_sum(&entries, &make_config(10, 3, 2)).unwrap_err(), bounds);
+        assert_eq!(solve_depth_first_with_stats(&entries, &make_config(10, 3, 2)).0.unwrap_err(), bounds);
+        assert_eq!(draw(&entries[..10], make_config(10, 3, 2)).unwrap_err(), bounds);
+        // An empty window is checked before an empty input
+        assert_eq!(solve_subset_sum(&[], &make_config(10, 3, 2)).unwrap_err(), bounds);
+        assert_eq!(solve_subset_sum(&[], &make_config(10, 1, 2)).unwrap_err(), SolveError::EmptyInput);
+        assert_eq!(draw(&[], make_config(10, 1, 2)).unwrap_err(), SolveError::EmptyInput);
+        // Rows that cannot reach the target are a proven miss, not an error
+        assert!(matches!(solve_subset_sum(&entries[..3], &make_config(100, 1, 3)), Ok(None)));
+
+        let too_large = |algorithm, n, max_n| SolveError::TooLarge { algorithm, n, max_n };
+        let mitm = |n| solve_mitm_with_stats(&entries[..n], &make_config(10, 1, 5)).0;
+        assert_eq!(mitm(51).unwrap_err(), too_large("mitm", 51, MAX_FORCED_MITM_N));
+        assert!(matches!(mitm(50), Ok(Some(_))));
+        assert_eq!(draw(&entries[..41], make_config(10, 1, 5)).unwrap_err(), too_large("mitm_sample", 41, MAX_RANDOM_N));
+    }
 }
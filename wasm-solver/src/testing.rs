@@ -0,0 +1,60 @@
+//! What the unit tests share: one seeded generator and a brute-force oracle
+//! the searches are checked against.
+
+use crate::solver::NumberEntry;
+use crate::utils::SplitMix64;
+
+/// Seed of every randomized test, so a failure reproduces as it was seen.
+pub const SEED: u64 = 0x5eed;
+
+/// A generator started from SEED.
+pub fn rng() -> SplitMix64 {
+    SplitMix64::new(SEED)
+}
+
+/// Every non-empty set of rows of `values` with `min_count..=max_count` of
+/// them, as ascending row indices, by walking all 2^n masks: n stays small.
+pub fn subsets(values: &[u64], min_count: usize, max_count: usize) -> impl Iterator<Item = Vec<usize>> {
+    let n = values.len();
+    assert!(n < 32, "brute force over {} rows", n);
+    (1u32..1 << n)
+        .filter(move |mask| (min_count..=max_count).contains(&(mask.count_ones() as usize)))
+        .map(move |mask| (0..n).filter(|&i| mask & 1 << i != 0).collect())
+}
+
+/// The oracle: every subset in the count window summing to `target`, ascending
+/// as index_sets lists them.
+pub fn brute_force(values: &[u64], target: u64, min_count: usize, max_count: usize) -> Vec<Vec<usize>> {
+    let mut sets: Vec<Vec<usize>> = subsets(values, min_count, max_count)
+        .filter(|rows| rows.iter().map(|&i| values[i]).sum::<u64>() == target)
+        .collect();
+    sets.sort();
+    sets
+}
+
+/// The rows of each combination, ascending, and the combinations in order:
+/// what two searches are compared by whatever order they found things in.
+pub fn index_sets(results: &[Vec<NumberEntry>]) -> Vec<Vec<usize>> {
+    let mut sets: Vec<Vec<usize>> = results.iter()
+        .map(|combo| {
+            let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+            indices.sort_unstable();
+            indices
+        })
+        .collect();
+    sets.sort();
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brute_force() {
+        // 5 = 5 = 1 + 4 = 2 + 3
+        assert_eq!(brute_force(&[1, 2, 3, 4, 5], 5, 1, 5), [vec![0, 3], vec![1, 2], vec![4]]);
+        assert_eq!(brute_force(&[1, 2, 3, 4, 5], 5, 2, 2), [vec![0, 3], vec![1, 2]]);
+        assert_eq!(subsets(&[7, 7, 7], 0, 3).count(), 7);
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then
@@ -8,3 +11,112 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// HashMap hashing with Fnv1a instead of std's per-process random keys, so its
+/// iteration order is the same on every run and every toolchain.
+pub type StableHashMap<K, V> = HashMap<K, V, BuildHasherDefault<Fnv1a>>;
+
+/// HashSet counterpart of StableHashMap.
+pub type StableHashSet<T> = HashSet<T, BuildHasherDefault<Fnv1a>>;
+
+/// Wall-clock milliseconds, for elapsed times only. `Date.now()` in wasm, where
+/// std has no clock.
+pub fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    return js_sys::Date::now();
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+}
+
+/// 64-bit FNV-1a. Unlike std's hashers its output is fixed forever, so it is safe
+/// for ids and fingerprints that outlive a single run.
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// Little-endian on every target, where std's default is native-endian
+    fn write_u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// SplitMix64: tiny, fast and fully determined by its seed, so seeded picks
+/// reproduce across runs and platforms. Not for anything security-related.
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in 0..bound (bound > 0), without modulo bias.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Reject the lowest (2^64 mod bound) values so every residue is equally likely
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold {
+                return x % bound;
+            }
+        }
+    }
+}
+
+/// A large allocation that did not happen: its size passed the caller's memory
+/// limit, or the allocator refused it (wasm memory could not grow).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutOfMemory {
+    /// Bytes asked for
+    pub bytes: u64,
+}
+
+impl OutOfMemory {
+    /// Err when `bytes` is over `limit`; None allows anything.
+    pub fn check(bytes: u64, limit: Option<u64>) -> Result<(), OutOfMemory> {
+        match limit {
+            Some(limit) if bytes > limit => Err(OutOfMemory { bytes }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Make room for `additional` more elements in `vec`, failing instead of
+/// aborting when the whole vector would take more than `limit` bytes or the
+/// allocator says no.
+pub fn try_reserve<T>(vec: &mut Vec<T>, additional: usize, limit: Option<u64>) -> Result<(), OutOfMemory> {
+    let bytes = (vec.len() as u64).saturating_add(additional as u64).saturating_mul(size_of::<T>() as u64);
+    OutOfMemory::check(bytes, limit)?;
+    vec.try_reserve_exact(additional).map_err(|_| OutOfMemory { bytes })
+}
@@ -0,0 +1,133 @@
+//! Argument checks shared by every entry point, run before any processing so a
+//! bad call fails with a specific error instead of a panic or misaligned data.
+
+/// Largest accepted `scale`; 10^9 units per whole still leaves plenty of u64 headroom.
+pub const MAX_SCALE: u32 = 9;
+
+/// Largest accepted `max_results`. Results are held in memory until destroyed.
+pub const MAX_RESULTS_CAP: u32 = 10_000_000;
+
+/// Most rows one call (or dataset) may have, 16M. Every row index then fits the
+/// u32 of a Uint32Array and the exact integers of a JS number, so no payload
+/// ever has to truncate one.
+pub const MAX_INPUT_LEN: usize = 1 << 24;
+
+/// Most usable rows a search takes unless told otherwise (`max_entries`), 4M.
+/// Sorting and searching more would take seconds before the first result, so
+/// such an input fails fast with `too_many_entries` instead.
+pub const DEFAULT_MAX_ENTRIES: u32 = 1 << 22;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidInput {
+    /// An array meant to run parallel to `numbers` has a different length
+    LengthMismatch { field: &'static str, expected: usize, actual: usize },
+    /// A numeric argument above its limit
+    OutOfRange { field: &'static str, max: u64, actual: u64 },
+    /// More than `max` usable rows; the sweep stopped after `scanned` rows
+    TooManyEntries { max: usize, scanned: usize },
+}
+
+impl InvalidInput {
+    pub fn code(self) -> &'static str {
+        match self {
+            InvalidInput::LengthMismatch { .. } => "length_mismatch",
+            InvalidInput::OutOfRange { field: "scale", .. } => "scale_out_of_range",
+            InvalidInput::OutOfRange { field: "max_results", .. } => "max_results_out_of_range",
+            InvalidInput::OutOfRange { field: "numbers", .. } => "too_many_numbers",
+            InvalidInput::OutOfRange { .. } => "out_of_range",
+            InvalidInput::TooManyEntries { .. } => "too_many_entries",
+        }
+    }
+}
+
+/// Arguments of one call, as far as validation is concerned.
+#[derive(Default)]
+pub struct CallArgs<'a> {
+    pub numbers_len: usize,
+    pub scale: Option<u32>,
+    pub max_results: Option<u32>,
+    /// (field name, length) of each array that must match `numbers` one-to-one
+    pub parallel: &'a [(&'static str, usize)],
+}
+
+/// Check the input size, then lengths, then ranges; the first problem found is returned.
+pub fn validate(args: &CallArgs) -> Result<(), InvalidInput> {
+    if args.numbers_len > MAX_INPUT_LEN {
+        return Err(InvalidInput::OutOfRange {
+            field: "numbers",
+            max: MAX_INPUT_LEN as u64,
+            actual: args.numbers_len as u64,
+        });
+    }
+    for &(field, actual) in args.parallel {
+        if actual != args.numbers_len {
+            return Err(InvalidInput::LengthMismatch { field, expected: args.numbers_len, actual });
+        }
+    }
+    check_max("scale", args.scale, MAX_SCALE)?;
+    check_max("max_results", args.max_results, MAX_RESULTS_CAP)?;
+    Ok(())
+}
+
+fn check_max(field: &'static str, value: Option<u32>, max: u32) -> Result<(), InvalidInput> {
+    match value {
+        Some(actual) if actual > max => Err(InvalidInput::OutOfRange {
+            field,
+            max: max as u64,
+            actual: actual as u64,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_accepts_limits() {
+        let args = CallArgs {
+            numbers_len: 3,
+            scale: Some(MAX_SCALE),
+            max_results: Some(MAX_RESULTS_CAP),
+            parallel: &[("weights", 3), ("groups", 3)],
+        };
+        assert_eq!(validate(&args), Ok(()));
+        assert_eq!(validate(&CallArgs::default()), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_input_len() {
+        assert_eq!(validate(&CallArgs { numbers_len: MAX_INPUT_LEN, ..Default::default() }), Ok(()));
+        assert!(u32::try_from(MAX_INPUT_LEN - 1).is_ok());
+        // Checked before the parallel arrays, which would mismatch too
+        let args = CallArgs { numbers_len: MAX_INPUT_LEN + 1, parallel: &[("weights", 3)], ..Default::default() };
+        let err = validate(&args).unwrap_err();
+        assert_eq!(err, InvalidInput::OutOfRange { field: "numbers", max: 1 << 24, actual: (1 << 24) + 1 });
+        assert_eq!(err.code(), "too_many_numbers");
+    }
+
+    #[test]
+    fn test_validate_length_mismatch() {
+        let args = CallArgs { numbers_len: 3, parallel: &[("weights", 3), ("groups", 2)], ..Default::default() };
+        let err = validate(&args).unwrap_err();
+        assert_eq!(err, InvalidInput::LengthMismatch { field: "groups", expected: 3, actual: 2 });
+        assert_eq!(err.code(), "length_mismatch");
+    }
+
+    #[test]
+    fn test_validate_ranges() {
+        let err = validate(&CallArgs { scale: Some(10), ..Default::default() }).unwrap_err();
+        assert_eq!(err, InvalidInput::OutOfRange { field: "scale", max: 9, actual: 10 });
+        assert_eq!(err.code(), "scale_out_of_range");
+
+        let err = validate(&CallArgs { max_results: Some(MAX_RESULTS_CAP + 1), ..Default::default() }).unwrap_err();
+        assert_eq!(err.code(), "max_results_out_of_range");
+    }
+
+    #[test]
+    fn test_validate_reports_lengths_before_ranges() {
+        let args = CallArgs { numbers_len: 1, scale: Some(99), parallel: &[("weights", 0)], ..Default::default() };
+        assert_eq!(validate(&args).unwrap_err().code(), "length_mismatch");
+    }
+}
@@ -0,0 +1,302 @@
+//! Re-verification of combinations against the caller's original input, for
+//! searches run with `paranoid: true`.
+//!
+//! The solvers only ever see a prepared copy of the input: filtered, scaled and
+//! sorted, with indices carried along. A bug anywhere in that pipeline could
+//! emit a combination that does not add up in the caller's data. With the
+//! option set, every combination is checked against the original rows on its
+//! way out: indices distinct and in range, each row re-scaling to the value the
+//! solver recorded, the sum equal to the target and the count within bounds.
+//!
+//! The same checks explain what is wrong with the extra combinations
+//! verify_exhaustive finds in a reported result list.
+
+use crate::canonical::ReportedCombination;
+use crate::input::scale_value;
+use crate::solver::{NumberEntry, OriginalIndex};
+
+#[cfg(test)]
+use std::cell::Cell;
+
+/// What every combination of one search must satisfy.
+#[derive(Clone, Debug)]
+pub struct Reference {
+    /// A copy of the rows as the caller passed them
+    pub numbers: Vec<f64>,
+    pub scale: u32,
+    pub target: u64,
+    pub min_count: usize,
+    pub max_count: usize,
+}
+
+/// The first check a combination failed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inconsistency {
+    OutOfRange { index: usize },
+    DuplicateIndex { index: usize },
+    /// The row re-scales to something else (None: not to a value at all)
+    ValueMismatch { index: usize, recorded: u64, rescaled: Option<u64> },
+    SumMismatch { sum: u128 },
+    CountOutOfBounds { count: usize },
+}
+
+impl Inconsistency {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Inconsistency::OutOfRange { .. } => "index_out_of_range",
+            Inconsistency::DuplicateIndex { .. } => "duplicate_index",
+            Inconsistency::ValueMismatch { .. } => "value_mismatch",
+            Inconsistency::SumMismatch { .. } => "sum_mismatch",
+            Inconsistency::CountOutOfBounds { .. } => "count_out_of_bounds",
+        }
+    }
+}
+
+/// A combination that failed verification, as the solver emitted it.
+#[derive(Clone, Debug)]
+pub struct ConsistencyFailure {
+    pub inconsistency: Inconsistency,
+    pub combination: Vec<NumberEntry>,
+    pub target: u64,
+    pub min_count: usize,
+    pub max_count: usize,
+}
+
+impl Reference {
+    pub fn check(&self, combo: &[NumberEntry]) -> Result<(), Inconsistency> {
+        let count = combo.len();
+        if count < self.min_count.max(1) || count > self.max_count {
+            return Err(Inconsistency::CountOutOfBounds { count });
+        }
+        let mut indices: Vec<usize> = combo.iter().map(|e| e.original_index.as_usize()).collect();
+        indices.sort_unstable();
+        if let Some(&index) = indices.iter().find(|&&i| i >= self.numbers.len()) {
+            return Err(Inconsistency::OutOfRange { index });
+        }
+        if let Some(pair) = indices.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(Inconsistency::DuplicateIndex { index: pair[0] });
+        }
+        for e in combo {
+            // A row of exactly 0 is kept as 0 when a search includes zeros
+            let rescaled = match self.numbers[e.original_index.as_usize()] {
+                0.0 => Some(0),
+                n => scale_value(n, self.scale).ok(),
+            };
+            if rescaled != Some(e.value) {
+                return Err(Inconsistency::ValueMismatch { index: e.original_index.as_usize(), recorded: e.value, rescaled });
+            }
+        }
+        let sum: u128 = combo.iter().map(|e| u128::from(e.value)).sum();
+        if sum != u128::from(self.target) {
+            return Err(Inconsistency::SumMismatch { sum });
+        }
+        Ok(())
+    }
+
+    /// Check combinations about to be returned, stopping at the first failure.
+    pub fn check_all<'a>(
+        &self,
+        combos: impl IntoIterator<Item = &'a mut Vec<NumberEntry>>,
+    ) -> Result<(), ConsistencyFailure> {
+        for combo in combos {
+            #[cfg(test)]
+            if let Some(tamper) = TAMPER.with(Cell::take) {
+                tamper(combo);
+            }
+            if let Err(inconsistency) = self.check(combo) {
+                return Err(ConsistencyFailure {
+                    inconsistency,
+                    combination: combo.clone(),
+                    target: self.target,
+                    min_count: self.min_count,
+                    max_count: self.max_count,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Most usable rows verify_exhaustive enumerates: 2^24 subsets at worst.
+pub const EXHAUSTIVE_MAX_ROWS: usize = 24;
+
+/// A reported result set compared with every combination that exists.
+#[derive(Clone, Debug, Default)]
+pub struct ExhaustiveCheck {
+    pub expected_count: usize,
+    pub reported_count: usize,
+    /// Combinations that exist but weren't reported, sorted by original index
+    pub missing: Vec<Vec<NumberEntry>>,
+    /// Reported combinations that aren't among them, with what is wrong
+    pub extra: Vec<Extra>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extra {
+    pub indices: Vec<u64>,
+    pub values: Option<Vec<u64>>,
+    pub problem: ExtraProblem,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExtraProblem {
+    /// Not a valid combination at all
+    Invalid(Inconsistency),
+    /// The same index set was already reported
+    Repeated,
+    /// Passes every check yet the enumeration didn't produce it
+    Unexpected,
+}
+
+impl ExhaustiveCheck {
+    pub fn complete(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl Reference {
+    /// Compare `reported` (canonical order) with `expected`, every combination
+    /// of the input. Reported values stand in for the solver's recorded ones;
+    /// without them, each row's re-scaled value is used.
+    pub fn compare_exhaustive(&self, mut expected: Vec<Vec<NumberEntry>>, reported: Vec<ReportedCombination>) -> ExhaustiveCheck {
+        for combo in &mut expected {
+            combo.sort_by_key(|e| e.original_index);
+        }
+        expected.sort_by(|a, b| a.iter().map(|e| e.original_index).cmp(b.iter().map(|e| e.original_index)));
+        let key = |combo: &[NumberEntry]| -> Vec<u64> { combo.iter().map(|e| e.original_index.as_usize() as u64).collect() };
+
+        let mut check = ExhaustiveCheck { expected_count: expected.len(), reported_count: reported.len(), ..Default::default() };
+        let mut matched = vec![false; expected.len()];
+        let mut previous: Option<Vec<u64>> = None;
+        for combo in reported {
+            let problem = if previous.as_ref() == Some(&combo.indices) {
+                Some(ExtraProblem::Repeated)
+            } else if let Ok(at) = expected.binary_search_by(|e| key(e).cmp(&combo.indices)) {
+                matched[at] = true;
+                None
+            } else {
+                Some(self.check_reported(&combo).err().map_or(ExtraProblem::Unexpected, ExtraProblem::Invalid))
+            };
+            previous = Some(combo.indices.clone());
+            if let Some(problem) = problem {
+                check.extra.push(Extra { indices: combo.indices, values: combo.values, problem });
+            }
+        }
+        check.missing = expected.into_iter().zip(matched).filter(|&(_, found)| !found).map(|(combo, _)| combo).collect();
+        check
+    }
+
+    /// check() for a combination read back from a payload, whose indices may
+    /// be anything.
+    fn check_reported(&self, combo: &ReportedCombination) -> Result<(), Inconsistency> {
+        if let Some(&index) = combo.indices.iter().find(|&&i| i >= self.numbers.len() as u64) {
+            return Err(Inconsistency::OutOfRange { index: usize::try_from(index).unwrap_or(usize::MAX) });
+        }
+        let entries: Vec<NumberEntry> = combo.indices.iter().enumerate()
+            .map(|(i, &index)| {
+                let index = index as usize;
+                let value = match &combo.values {
+                    Some(values) => values[i],
+                    None => scale_value(self.numbers[index], self.scale).unwrap_or(0),
+                };
+                NumberEntry { value, original_index: OriginalIndex::new(index) }
+            })
+            .collect();
+        self.check(&entries)
+    }
+}
+
+/// A test's stand-in for a solver bug
+#[cfg(test)]
+type Tamper = fn(&mut Vec<NumberEntry>);
+
+#[cfg(test)]
+thread_local! {
+    static TAMPER: Cell<Option<Tamper>> = const { Cell::new(None) };
+}
+
+/// Corrupt the next combination check_all sees, as a solver bug would.
+#[cfg(test)]
+pub fn tamper_with_next(tamper: Tamper) {
+    TAMPER.with(|cell| cell.set(Some(tamper)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: u64, original_index: usize) -> NumberEntry {
+        NumberEntry { value, original_index: OriginalIndex::new(original_index) }
+    }
+
+    fn reference() -> Reference {
+        Reference {
+            numbers: vec![1.25, 2.5, 3.75, -1.0],
+            scale: 2,
+            target: 500,
+            min_count: 1,
+            max_count: 2,
+        }
+    }
+
+    #[test]
+    fn test_check_accepts_valid_combination() {
+        assert_eq!(reference().check(&[entry(375, 2), entry(125, 0)]), Ok(()));
+        let whole = Reference { numbers: vec![7.9, 3.0], scale: 0, target: 10, ..reference() };
+        assert_eq!(whole.check(&[entry(7, 0), entry(3, 1)]), Ok(()));
+    }
+
+    #[test]
+    fn test_check_names_each_failure() {
+        let reference = reference();
+        let cases = [
+            (vec![], Inconsistency::CountOutOfBounds { count: 0 }),
+            (vec![entry(125, 0), entry(125, 0), entry(250, 1)], Inconsistency::CountOutOfBounds { count: 3 }),
+            (vec![entry(500, 9)], Inconsistency::OutOfRange { index: 9 }),
+            (vec![entry(250, 1), entry(250, 1)], Inconsistency::DuplicateIndex { index: 1 }),
+            (vec![entry(375, 1), entry(125, 0)], Inconsistency::ValueMismatch { index: 1, recorded: 375, rescaled: Some(250) }),
+            (vec![entry(500, 3)], Inconsistency::ValueMismatch { index: 3, recorded: 500, rescaled: None }),
+            (vec![entry(250, 1), entry(125, 0)], Inconsistency::SumMismatch { sum: 375 }),
+        ];
+        for (combo, expected) in cases {
+            assert_eq!(reference.check(&combo), Err(expected));
+        }
+    }
+
+    #[test]
+    fn test_compare_exhaustive() {
+        let reference = reference();
+        let expected = || vec![vec![entry(375, 2), entry(125, 0)]];
+        let reported = |indices: Vec<u64>, values: Option<Vec<u64>>| ReportedCombination { indices, values };
+
+        let check = reference.compare_exhaustive(expected(), vec![reported(vec![0, 1], None), reported(vec![7], None)]);
+        assert_eq!((check.expected_count, check.reported_count), (1, 2));
+        assert_eq!(check.missing.len(), 1);
+        assert_eq!(check.missing[0].iter().map(|e| e.value).collect::<Vec<_>>(), [125, 375]);
+        let problems: Vec<ExtraProblem> = check.extra.into_iter().map(|extra| extra.problem).collect();
+        assert_eq!(problems, [
+            ExtraProblem::Invalid(Inconsistency::SumMismatch { sum: 375 }),
+            ExtraProblem::Invalid(Inconsistency::OutOfRange { index: 7 }),
+        ]);
+
+        let matching = reported(vec![0, 2], Some(vec![125, 375]));
+        assert!(reference.compare_exhaustive(expected(), vec![matching.clone()]).complete());
+        let check = reference.compare_exhaustive(expected(), vec![matching.clone(), matching.clone()]);
+        assert!(check.missing.is_empty());
+        assert_eq!(check.extra[0].problem, ExtraProblem::Repeated);
+        // A valid combination the enumeration lacks is still flagged
+        let check = reference.compare_exhaustive(Vec::new(), vec![matching]);
+        assert_eq!(check.extra[0].problem, ExtraProblem::Unexpected);
+    }
+
+    #[test]
+    fn test_tamper_hook_fires_once() {
+        let reference = reference();
+        let mut combos = vec![vec![entry(375, 2), entry(125, 0)], vec![entry(375, 2), entry(125, 0)]];
+        tamper_with_next(|combo| combo[0].value += 1);
+        let failure = reference.check_all(&mut combos).unwrap_err();
+        assert_eq!(failure.inconsistency.reason(), "value_mismatch");
+        assert_eq!(failure.combination[0].value, 376);
+        assert!(reference.check_all(&mut combos[1..]).is_ok());
+    }
+}
@@ -4,6 +4,9 @@
 
 extern crate wasm_bindgen_test;
 use wasm_bindgen_test::*;
+use wasm_solver::{
+    SolverInstance, find_one, get_full_result, get_result_indices_chunk, get_result_meta, search_batch, set_max_elements_per_result,
+};
 
 wasm_bindgen_test_configure!(run_in_browser);
 
@@ -11,3 +14,45 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn result_indices_reassemble_from_chunks() {
+    // Every row is needed: one 50,000-row combination, sent truncated
+    let numbers = vec![1.0; 50_000];
+    set_max_elements_per_result(1000);
+    let json = find_one(&numbers, 50_000.0, 1, 50_000, None, None, None);
+    set_max_elements_per_result(0);
+    let start = json.find(r#""id":""#).unwrap() + 6;
+    let id = &json[start..start + 16];
+    assert!(get_result_meta(id).ends_with(r#","count":50000,"sum":50000}"#));
+
+    let mut indices = Vec::new();
+    loop {
+        let chunk = get_result_indices_chunk(id, indices.len() as u32, 4096).unwrap();
+        if chunk.is_empty() {
+            break;
+        }
+        indices.extend(chunk);
+    }
+    assert_eq!(indices, get_full_result(id, 0, 50_000).unwrap());
+    assert_eq!(indices, (0..50_000).collect::<Vec<u32>>());
+    assert!(get_result_indices_chunk(id, 50_001, 1).is_err());
+    assert!(get_result_indices_chunk("ffffffffffffffff", 0, 1).is_err());
+}
+
+#[wasm_bindgen_test]
+fn solver_instances_search_side_by_side() {
+    let numbers: Vec<f64> = (1..=14).map(f64::from).collect();
+    let (a, b) = (SolverInstance::new(), SolverInstance::new());
+    assert_eq!(a.init_batch(&numbers, r#"{"target":20}"#), None);
+    assert_eq!(b.init_batch(&numbers, r#"{"target":33,"max_count":4}"#), None);
+    let (mut a_json, mut b_json) = (String::new(), String::new());
+    while !a_json.contains(r#""finished":true"#) || !b_json.contains(r#""finished":true"#) {
+        a_json = a.search_batch(50);
+        b.cancel();
+        b_json = b.search_batch(50);
+    }
+    assert!(!a.destroy().contains("no search initialized"));
+    assert!(search_batch(10).contains("no search initialized"));
+    assert!(!b.destroy().contains("no search initialized"));
+}
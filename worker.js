@@ -10,7 +10,7 @@ import init, {
   find_one,
   init_batch_search,
   search_batch,
-  destroy_batch_search,
+  destroy_batch_search_quiet,
   init_panic_hook,
 } from './wasm-solver/pkg/wasm_solver.js';
 
@@ -70,7 +70,7 @@ self.onmessage = function(e) {
 
           if (batch.error) {
             self.postMessage({ type: 'error', message: batch.error });
-            destroy_batch_search();
+            destroy_batch_search_quiet();
             return;
           }
 
@@ -100,9 +100,12 @@ self.onmessage = function(e) {
               combinations: allCombinations,
               total: allCombinations.length,
               elapsed_ms: elapsed,
+              // False when max_results cut the search short; more may exist
+              space_exhausted: batch.space_exhausted,
+              more_may_exist: !!batch.more_may_exist,
             };
             self.postMessage({ type: 'result', data: finalResult });
-            destroy_batch_search();
+            destroy_batch_search_quiet();
           } else {
             // Yield to the event loop so cancel messages can be processed,
             // then continue with the next batch.
@@ -110,7 +113,7 @@ self.onmessage = function(e) {
           }
         } catch (err) {
           self.postMessage({ type: 'error', message: 'Batch error: ' + err.message });
-          destroy_batch_search();
+          destroy_batch_search_quiet();
         }
       }
 